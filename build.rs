@@ -0,0 +1,15 @@
+use std::process::Command;
+
+/// 从 git 仓库构建时记录 git describe，供 --version --verbose 和会话环境信息使用
+fn main() {
+  println!("cargo:rerun-if-changed=.git/HEAD");
+  println!("cargo:rerun-if-changed=.git/index");
+  if let Ok(output) = Command::new("git")
+    .args(["describe", "--always", "--dirty", "--tags"])
+    .output()
+    && output.status.success()
+  {
+    let describe = String::from_utf8_lossy(&output.stdout);
+    println!("cargo:rustc-env=DEEPCLI_GIT_DESCRIBE={}", describe.trim());
+  }
+}
@@ -0,0 +1,37 @@
+#!/bin/sh
+# deepcli 插件示例：按编号查询工单。
+#
+# 在 ~/.config/deepcli/plugins.yaml 中启用：
+#
+#   plugins:
+#     - path: /path/to/ticket.sh
+#       timeout: 10        # 秒，默认 30
+#       max_output: 8192   # 字节，默认 16384
+#
+# 以 --manifest 运行时输出清单；被调用时从 stdin 读入参数 JSON，stdout 即为结果。
+# 这里返回固定的数据，实际使用时换成对工单系统的查询。
+
+if [ "$1" = "--manifest" ]; then
+  cat <<'JSON'
+{
+  "name": "lookup_ticket",
+  "description": "Look up a ticket in the internal ticket system by its id, e.g. OPS-42.",
+  "parameters": {
+    "type": "object",
+    "properties": {
+      "id": {"type": "string", "description": "Ticket id"}
+    },
+    "required": ["id"]
+  }
+}
+JSON
+  exit 0
+fi
+
+args=$(cat)
+id=$(printf '%s' "$args" | sed -n 's/.*"id"[[:space:]]*:[[:space:]]*"\([^"]*\)".*/\1/p')
+if [ -z "$id" ]; then
+  echo "missing ticket id" >&2
+  exit 2
+fi
+printf '{"id": "%s", "status": "open", "title": "Login page returns 500 after deploy"}\n' "$id"
@@ -1,11 +1,18 @@
+use crate::attachment::ReadLimits;
+use crate::ratelimit::{Headers, Limiter, parse_reset};
+use crate::retry::{self, RetryPolicy};
+use crate::sse::SseParser;
+use crate::truncate::TruncateMode;
 use anyhow::{Context, Result};
-use base64::Engine;
 use futures_util::Stream;
 use futures_util::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::path::Path;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Serialize)]
 pub struct ApiRequest {
@@ -15,19 +22,142 @@ pub struct ApiRequest {
   pub max_tokens: Option<u32>,
   pub stream: bool,
   pub response_format: Option<ResponseFormat>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub reasoning_effort: Option<ReasoningEffort>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub seed: Option<u64>,
+  /// 终端用户标识（OpenAI 兼容的 user 字段），供服务方审计
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub user: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub tools: Option<Vec<ToolSpec>>,
+  /// 流式请求要求服务方在最后一个增量中附带用量
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub stream_options: Option<StreamOptions>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct ResponseFormat {
-  #[serde(rename = "type")]
-  pub format_type: String,
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StreamOptions {
+  pub include_usage: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+  Low,
+  Medium,
+  High,
+}
+
+impl std::str::FromStr for ReasoningEffort {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "low" => Ok(ReasoningEffort::Low),
+      "medium" => Ok(ReasoningEffort::Medium),
+      "high" => Ok(ReasoningEffort::High),
+      _ => Err("Reasoning effort must be low, medium or high".to_string()),
+    }
+  }
+}
+
+/// 请求的 `response_format`：`{"type": "json_object"}` 或带 schema 的 `{"type": "json_schema", …}`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+  JsonObject,
+  JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JsonSchemaFormat {
+  pub name: String,
+  pub schema: serde_json::Value,
+  pub strict: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum Message {
-  Simple { role: String, content: String },
-  MultiModal { role: String, content: Vec<Content> },
+  Simple {
+    role: String,
+    content: String,
+    /// 说话人（OpenAI 的 name 字段），用于多人对话记录
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+  },
+  MultiModal {
+    role: String,
+    content: Vec<Content>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+  },
+  /// 工具调用的往返：带 tool_calls 的 assistant 消息，或 role 为 tool 的调用结果。
+  /// 只出现在同一轮的后续请求中，不进入历史
+  Tool {
+    role: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+  },
+}
+
+/// 模型发起的一次函数调用
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+  pub id: String,
+  #[serde(rename = "type")]
+  pub kind: String,
+  pub function: FunctionCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionCall {
+  pub name: String,
+  /// 参数的 JSON 文本，模型生成，不保证合法
+  pub arguments: String,
+}
+
+/// 请求 tools 数组中的一个函数
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ToolSpec {
+  #[serde(rename = "type")]
+  pub kind: &'static str,
+  pub function: FunctionSpec,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FunctionSpec {
+  pub name: String,
+  pub description: String,
+  /// 参数的 JSON Schema
+  pub parameters: serde_json::Value,
+}
+
+/// OpenAI 对 name 的限制：1 到 64 个字母、数字、_ 或 -
+pub const MAX_NAME_CHARS: usize = 64;
+
+pub fn valid_name(name: &str) -> bool {
+  (1..=MAX_NAME_CHARS).contains(&name.len())
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+impl Message {
+  /// 显示用的角色，带说话人时为 `user(alice)`
+  pub fn speaker(&self) -> String {
+    match self {
+      Message::Simple { role, name, .. } | Message::MultiModal { role, name, .. } => match name {
+        Some(name) => format!("{}({})", role, name),
+        None => role.clone(),
+      },
+      Message::Tool { role, .. } => role.clone(),
+    }
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -56,9 +186,48 @@ pub struct ImageUrl {
   pub url: String,
 }
 
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+  model: &'a str,
+  input: &'a [String],
+  #[serde(skip_serializing_if = "Option::is_none")]
+  user: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+  data: Vec<EmbeddingData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+  index: usize,
+  embedding: Vec<f32>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiResponse {
   pub choices: Vec<Choice>,
+  /// 服务方报告的用量；有的兼容接口不返回
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub usage: Option<Usage>,
+}
+
+/// 响应中的 usage 对象，缺少的字段按 0 处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Usage {
+  pub prompt_tokens: usize,
+  pub completion_tokens: usize,
+  pub total_tokens: usize,
+}
+
+impl std::ops::AddAssign for Usage {
+  fn add_assign(&mut self, other: Self) {
+    self.prompt_tokens += other.prompt_tokens;
+    self.completion_tokens += other.completion_tokens;
+    self.total_tokens += other.total_tokens;
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -66,19 +235,1023 @@ pub struct Choice {
   pub message: Message,
 }
 
+impl ApiResponse {
+  /// 第一个候选回答的文本内容
+  pub fn text(&self) -> String {
+    match self.choices.first().map(|c| &c.message) {
+      Some(Message::Simple { content, .. } | Message::Tool { content, .. }) => content.clone(),
+      Some(Message::MultiModal { content, .. }) => content
+        .iter()
+        .filter_map(|c| match c {
+          Content::Text(t) => Some(t.text.as_str()),
+          Content::Image(_) => None,
+        })
+        .collect::<Vec<_>>()
+        .join(""),
+      None => String::new(),
+    }
+  }
+}
+
+/// 流式响应中的一个增量：推理过程（reasoning_content）与可见回答分开
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StreamChunk {
+  pub content: String,
+  pub reasoning: String,
+  pub finish_reason: Option<String>,
+  /// 函数调用的片段，按 index 拼接成完整的调用
+  pub tool_calls: Vec<ToolCallDelta>,
+  /// 只在最后一个增量中出现（通常 choices 为空）
+  pub usage: Option<Usage>,
+}
+
+/// 流式响应中一次函数调用的片段：id 和函数名只在第一个片段中出现，参数分多段到达
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolCallDelta {
+  pub index: usize,
+  pub id: Option<String>,
+  pub name: Option<String>,
+  pub arguments: String,
+}
+
+/// 把流式片段拼接成完整的函数调用
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+  calls: Vec<ToolCall>,
+}
+
+impl ToolCallAccumulator {
+  pub fn push(&mut self, delta: &ToolCallDelta) {
+    while self.calls.len() <= delta.index {
+      self.calls.push(ToolCall {
+        id: String::new(),
+        kind: "function".to_string(),
+        function: FunctionCall {
+          name: String::new(),
+          arguments: String::new(),
+        },
+      });
+    }
+    let call = &mut self.calls[delta.index];
+    if let Some(id) = &delta.id {
+      call.id.clone_from(id);
+    }
+    if let Some(name) = &delta.name {
+      call.function.name.push_str(name);
+    }
+    call.function.arguments.push_str(&delta.arguments);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.calls.is_empty()
+  }
+
+  pub fn finish(self) -> Vec<ToolCall> {
+    self.calls
+  }
+}
+
+fn tool_call_deltas(choice: &serde_json::Value) -> Vec<ToolCallDelta> {
+  let Some(calls) = ["delta", "message"]
+    .iter()
+    .find_map(|k| choice.get(k)?.get("tool_calls")?.as_array())
+  else {
+    return vec![];
+  };
+  let text = |v: &serde_json::Value, key: &str| v.get(key)?.as_str().map(str::to_string);
+  calls
+    .iter()
+    .enumerate()
+    .map(|(i, call)| {
+      let function = call.get("function");
+      ToolCallDelta {
+        index: call
+          .get("index")
+          .and_then(|v| v.as_u64())
+          .map_or(i, |n| n as usize),
+        id: text(call, "id"),
+        name: function.and_then(|f| text(f, "name")),
+        arguments: function
+          .and_then(|f| text(f, "arguments"))
+          .unwrap_or_default(),
+      }
+    })
+    .collect()
+}
+
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Result<StreamChunk>> + Send>>;
+
+/// 解析一个 SSE 事件的 data；无法解析、没有内容也没有 finish_reason 的返回 None
+#[cfg(test)]
+pub fn parse_stream_data(data: &str) -> Option<StreamChunk> {
+  chunk_of(&serde_json::from_str(data).ok()?)
+}
+
+/// 没有内容也没有 finish_reason 的增量（例如只有 role）返回 None
+fn chunk_of(json: &serde_json::Value) -> Option<StreamChunk> {
+  // DashScope 在流中用错误事件报告审核拦截，换成 OpenAI 风格的 finish_reason
+  if is_refusal(json) {
+    return Some(StreamChunk {
+      finish_reason: Some(CONTENT_FILTER.to_string()),
+      ..Default::default()
+    });
+  }
+  let usage = json
+    .get("usage")
+    .and_then(|u| serde_json::from_value::<Option<Usage>>(u.clone()).ok())
+    .flatten();
+  let Some(choice) = json.get("choices").and_then(|c| c.get(0)) else {
+    return usage.map(|usage| StreamChunk {
+      usage: Some(usage),
+      ..Default::default()
+    });
+  };
+  let text_of = |key: &str| {
+    // deepseek 可能直接有 message.content，兼容 OpenAI 风格的 delta
+    ["delta", "message"]
+      .iter()
+      .find_map(|k| choice.get(k)?.get(key)?.as_str())
+      .unwrap_or_default()
+      .to_string()
+  };
+  let chunk = StreamChunk {
+    content: text_of("content"),
+    reasoning: text_of("reasoning_content"),
+    finish_reason: choice
+      .get("finish_reason")
+      .and_then(|v| v.as_str())
+      .map(|s| s.to_string()),
+    tool_calls: tool_call_deltas(choice),
+    usage,
+  };
+  if chunk == StreamChunk::default() {
+    return None;
+  }
+  Some(chunk)
+}
+
+/// 服务商的内容审核拦截了问题或回答
+pub const CONTENT_FILTER: &str = "content_filter";
+
+/// DashScope 审核拦截时错误体中的 code
+const REFUSAL_CODES: &[&str] = &["data_inspection_failed", "DataInspectionFailed"];
+
+/// 错误体是否是 DashScope 的审核拦截：`{"error": {"code": ...}}` 或顶层的 `code`
+fn is_refusal(json: &serde_json::Value) -> bool {
+  let code = json
+    .get("error")
+    .and_then(|e| e.get("code"))
+    .or_else(|| json.get("code"))
+    .and_then(|c| c.as_str());
+  code.is_some_and(|c| REFUSAL_CODES.contains(&c))
+}
+
+/// JSON 模式追加的要求
+pub const JSON_INSTRUCTION: &str = "You must output your response in a valid JSON format.";
+
+/// 流式响应默认的空闲提示间隔
+pub const DEFAULT_STREAM_IDLE: Duration = Duration::from_secs(30);
+
+/// --connect-timeout 的默认值
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// --timeout 的默认值；r1 思考时可能很久没有输出，留足余量
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// 请求的超时设置（--connect-timeout、--timeout）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+  /// 建立连接（含 TLS 握手）的上限
+  pub connect: Duration,
+  /// 等待数据的上限：等待响应头、读取响应体，以及流式响应两块数据之间的空闲时长。
+  /// 不限制总时长，持续输出的长回答不会被中途切断
+  pub read: Duration,
+}
+
+impl Default for Timeouts {
+  fn default() -> Self {
+    Self {
+      connect: DEFAULT_CONNECT_TIMEOUT,
+      read: DEFAULT_TIMEOUT,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+  Connect,
+  /// 已经连上，等待响应头或非流式的响应体
+  Response,
+  /// 流式响应中途没有数据
+  Stream,
+}
+
+/// 超过了某个超时设置，错误信息说明是哪一个和上限是多少
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeoutError {
+  pub kind: TimeoutKind,
+  pub limit: Duration,
+}
+
+impl std::fmt::Display for TimeoutError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let secs = self.limit.as_secs_f64();
+    match self.kind {
+      TimeoutKind::Connect => write!(
+        f,
+        "Connect timeout: no connection within {}s (--connect-timeout {}s)",
+        secs, secs
+      ),
+      TimeoutKind::Response => write!(
+        f,
+        "Read timeout: no response within {}s (--timeout {}s)",
+        secs, secs
+      ),
+      TimeoutKind::Stream => write!(
+        f,
+        "Read timeout: the stream sent no data for {}s (--timeout {}s)",
+        secs, secs
+      ),
+    }
+  }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// 还没有收到任何内容时连接就被关闭：通常是代理在模型思考期间按空闲超时断开。
+/// 没有丢失内容，可以从头重试
+#[derive(Debug)]
+pub struct IdleDisconnect {
+  pub idle: Duration,
+}
+
+impl std::fmt::Display for IdleDisconnect {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "Connection closed after {}s without any content (idle disconnect). \
+       A proxy may be buffering or idle-closing the stream",
+      self.idle.as_secs()
+    )
+  }
+}
+
+impl std::error::Error for IdleDisconnect {}
+
+/// 请求失败的原因：非 2xx 响应按状态码和错误体分类，连不上服务方时为 Network。
+/// 调用方可以从 anyhow::Error 中 downcast 出来按变体处理
+#[derive(Debug)]
+pub enum ApiError {
+  /// 401/403：密钥无效或没有权限
+  Unauthorized {
+    status: reqwest::StatusCode,
+    body: String,
+  },
+  /// 429，`retry_after` 为响应头 Retry-After
+  RateLimited {
+    retry_after: Option<Duration>,
+    body: String,
+  },
+  /// 输入加上历史超出了模型的上下文长度
+  ContextLengthExceeded {
+    status: reqwest::StatusCode,
+    body: String,
+  },
+  /// 400/404/422：请求本身有问题（参数、模型名），重试没有用。
+  /// `message` 为错误体中的说明，错误体不是 JSON 时为整个错误体
+  InvalidRequest {
+    status: reqwest::StatusCode,
+    message: String,
+    body: String,
+  },
+  /// 服务商的内容审核拦截了请求
+  ContentFilter {
+    status: reqwest::StatusCode,
+    body: String,
+  },
+  /// 5xx
+  Server {
+    status: reqwest::StatusCode,
+    body: String,
+  },
+  /// 其他非 2xx 响应
+  Other {
+    status: reqwest::StatusCode,
+    body: String,
+  },
+  /// 连不上服务方，或者发送请求时出错
+  Network(reqwest::Error),
+}
+
+/// 错误体中的字段：OpenAI 形式在 `error` 之下，DashScope 原生接口在顶层
+fn error_field(body: &str, name: &str) -> Option<String> {
+  let json: serde_json::Value = serde_json::from_str(body).ok()?;
+  let value = json.get("error").unwrap_or(&json).get(name)?;
+  value.as_str().map(str::to_string)
+}
+
+/// 是否是超出上下文长度的错误：DeepSeek 的 code 只是 invalid_request_error，
+/// DashScope 是 invalid_parameter_error，只能看说明
+fn exceeds_context(status: u16, body: &str) -> bool {
+  if !matches!(status, 400 | 413 | 422) {
+    return false;
+  }
+  if error_field(body, "code").as_deref() == Some("context_length_exceeded") {
+    return true;
+  }
+  let message = error_field(body, "message")
+    .unwrap_or_default()
+    .to_ascii_lowercase();
+  [
+    "maximum context length",
+    "context_length",
+    "range of input length",
+  ]
+  .iter()
+  .any(|marker| message.contains(marker))
+}
+
+impl ApiError {
+  /// 按状态码和错误体分类非 2xx 响应
+  pub fn from_response(
+    status: reqwest::StatusCode,
+    body: String,
+    retry_after: Option<Duration>,
+  ) -> Self {
+    if serde_json::from_str(&body).is_ok_and(|json| is_refusal(&json)) {
+      return Self::ContentFilter { status, body };
+    }
+    if exceeds_context(status.as_u16(), &body) {
+      return Self::ContextLengthExceeded { status, body };
+    }
+    match status.as_u16() {
+      401 | 403 => Self::Unauthorized { status, body },
+      400 | 404 | 422 => Self::InvalidRequest {
+        status,
+        message: error_field(&body, "message").unwrap_or_else(|| body.clone()),
+        body,
+      },
+      429 => Self::RateLimited { retry_after, body },
+      500..=599 => Self::Server { status, body },
+      _ => Self::Other { status, body },
+    }
+  }
+
+  /// 响应的状态码；Network 没有响应
+  pub fn status(&self) -> Option<reqwest::StatusCode> {
+    match self {
+      Self::Unauthorized { status, .. }
+      | Self::ContextLengthExceeded { status, .. }
+      | Self::InvalidRequest { status, .. }
+      | Self::ContentFilter { status, .. }
+      | Self::Server { status, .. }
+      | Self::Other { status, .. } => Some(*status),
+      Self::RateLimited { .. } => Some(reqwest::StatusCode::TOO_MANY_REQUESTS),
+      Self::Network(_) => None,
+    }
+  }
+
+  /// 响应体；Network 没有响应
+  pub fn body(&self) -> Option<&str> {
+    match self {
+      Self::Unauthorized { body, .. }
+      | Self::RateLimited { body, .. }
+      | Self::ContextLengthExceeded { body, .. }
+      | Self::InvalidRequest { body, .. }
+      | Self::ContentFilter { body, .. }
+      | Self::Server { body, .. }
+      | Self::Other { body, .. } => Some(body),
+      Self::Network(_) => None,
+    }
+  }
+
+  /// 显示在错误之后的处理建议；响应给出了 Retry-After 时附上等待时长
+  pub fn hint(&self) -> Option<String> {
+    let hint = match self {
+      Self::Unauthorized { .. } => "请检查 API 密钥是否正确、是否有权限使用该模型",
+      Self::InvalidRequest { .. } => "请求被拒绝，重试不会成功：请检查模型名和参数",
+      Self::RateLimited {
+        retry_after: Some(wait),
+        ..
+      } => {
+        return Some(format!(
+          "已达到速率限制，请稍后重试（服务方要求等待 {}s）",
+          wait.as_secs_f64()
+        ));
+      }
+      Self::RateLimited { .. } => "已达到速率限制，请稍后重试",
+      Self::ContextLengthExceeded { .. } => {
+        "超出了模型的上下文长度，重试不会成功：请用 \\c 清空对话历史，或缩短输入"
+      }
+      Self::Server { .. } => "服务端出错，可以稍后重试",
+      Self::ContentFilter { .. } => "内容被服务商的审核拦截，原样重发不会成功，请换个说法",
+      Self::Network(e) if e.is_connect() && !e.is_timeout() => {
+        "无法连接到服务方，请检查网络、代理设置和 --base-url"
+      }
+      Self::Network(_) | Self::Other { .. } => return None,
+    };
+    Some(hint.to_string())
+  }
+
+  /// 成功的响应原样返回，否则读取响应体作为错误
+  async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+    if response.status().is_success() {
+      return Ok(response);
+    }
+    let status = response.status();
+    let retry_after = response
+      .headers()
+      .get(reqwest::header::RETRY_AFTER)
+      .and_then(|v| v.to_str().ok())
+      .and_then(parse_reset);
+    let body = response
+      .text()
+      .await
+      .unwrap_or_else(|_| "Unknown error".into());
+    Err(Self::from_response(status, body, retry_after).into())
+  }
+}
+
+impl std::fmt::Display for ApiError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if let Self::Network(e) = self {
+      return write!(f, "{}", e);
+    }
+    write!(
+      f,
+      "API Error {}: {}",
+      self.status().unwrap_or_default(),
+      self.body().unwrap_or_default()
+    )
+  }
+}
+
+impl std::error::Error for ApiError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      // reqwest 的错误已经作为说明显示，从它的原因开始
+      Self::Network(e) => e.source(),
+      _ => None,
+    }
+  }
+}
+
+/// 请求失败后显示的处理建议
+pub fn hint(error: &anyhow::Error) -> Option<String> {
+  error
+    .chain()
+    .find_map(|e| e.downcast_ref::<ApiError>())
+    .and_then(ApiError::hint)
+}
+
+struct SseState<S> {
+  bytes: Pin<Box<S>>,
+  parser: SseParser,
+  /// 已经切分出来、还没有解码的事件
+  events: VecDeque<String>,
+  finished: bool,
+  /// 连接已经关闭，值为关闭前的空闲时长；剩下的事件处理完后结束
+  closed: Option<Duration>,
+  /// 是否已经产生过内容或推理增量，用来区分空闲断开与回答中途断开
+  saw_content: bool,
+  /// 最近一个带 finish_reason 的增量中的原因；[DONE] 本身不带原因
+  finish_reason: Option<String>,
+  idle_warn: Option<Duration>,
+  /// 超过这么久没有数据时以 TimeoutError 结束
+  idle_limit: Option<Duration>,
+  last_data: tokio::time::Instant,
+}
+
+impl<S, B, E> SseState<S>
+where
+  S: Stream<Item = std::result::Result<B, E>>,
+  B: AsRef<[u8]>,
+  E: std::error::Error + Send + Sync + 'static,
+{
+  /// 从已切分的事件中取出一个增量；无法解析的事件提示后跳过
+  fn take_buffered(&mut self) -> Option<StreamChunk> {
+    while let Some(data) = self.events.pop_front() {
+      if data == "[DONE]" {
+        self.finished = true;
+        return None;
+      }
+      let json = match serde_json::from_str(&data) {
+        Ok(json) => json,
+        Err(e) => {
+          eprintln!(
+            "[警告] 跳过无法解析的流式数据（{}）: {}",
+            e,
+            data.chars().take(80).collect::<String>()
+          );
+          continue;
+        }
+      };
+      if let Some(chunk) = chunk_of(&json) {
+        self.saw_content |=
+          !chunk.content.is_empty() || !chunk.reasoning.is_empty() || !chunk.tool_calls.is_empty();
+        if chunk.finish_reason.is_some() {
+          self.finish_reason = chunk.finish_reason.clone();
+        }
+        return Some(chunk);
+      }
+    }
+    None
+  }
+
+  /// 等待下一块数据；超过 idle_warn 没有数据时提示但继续等待，超过 idle_limit 时放弃
+  async fn next_bytes(
+    &mut self,
+  ) -> std::result::Result<Option<std::result::Result<B, E>>, TimeoutError> {
+    loop {
+      let remaining = self
+        .idle_limit
+        .map(|limit| limit.saturating_sub(self.last_data.elapsed()));
+      let Some(wait) = [self.idle_warn, remaining].into_iter().flatten().min() else {
+        return Ok(self.bytes.next().await);
+      };
+      match tokio::time::timeout(wait, self.bytes.next()).await {
+        Ok(item) => return Ok(item),
+        Err(_) => match self.idle_limit {
+          Some(limit) if self.last_data.elapsed() >= limit => {
+            return Err(TimeoutError {
+              kind: TimeoutKind::Stream,
+              limit,
+            });
+          }
+          _ => eprintln!(
+            "[信息] 已有 {}s 没有收到数据，模型可能仍在思考",
+            self.last_data.elapsed().as_secs()
+          ),
+        },
+      }
+    }
+  }
+
+  async fn next_chunk(&mut self) -> Option<Result<StreamChunk>> {
+    loop {
+      if let Some(chunk) = self.take_buffered() {
+        return Some(Ok(chunk));
+      }
+      if self.finished {
+        return None;
+      }
+      // 没有 [DONE] 就关闭了连接
+      if let Some(idle) = self.closed {
+        self.finished = true;
+        if self.finish_reason.is_some() {
+          return None;
+        }
+        if !self.saw_content {
+          return Some(Err(IdleDisconnect { idle }.into()));
+        }
+        return Some(Err(anyhow::anyhow!(
+          "Stream closed before the answer finished"
+        )));
+      }
+      let item = match self.next_bytes().await {
+        Ok(item) => item,
+        Err(timeout) => {
+          self.finished = true;
+          return Some(Err(timeout.into()));
+        }
+      };
+      let idle = self.last_data.elapsed();
+      match item {
+        Some(Ok(bytes)) => {
+          if !bytes.as_ref().is_empty() {
+            self.last_data = tokio::time::Instant::now();
+          }
+          self.events.extend(self.parser.push(bytes.as_ref()));
+        }
+        Some(Err(e)) => {
+          self.finished = true;
+          if !self.saw_content {
+            return Some(Err(
+              anyhow::Error::new(IdleDisconnect { idle }).context(e.to_string()),
+            ));
+          }
+          return Some(Err(anyhow::anyhow!(e)));
+        }
+        // 最后一个事件可能没有换行结尾
+        None => {
+          self.events.extend(self.parser.finish());
+          self.closed = Some(idle);
+        }
+      }
+    }
+  }
+}
+
+/// 把 SSE 字节流解码为增量。`idle_warn` 为空闲提示间隔，`idle_limit` 为空闲上限（--timeout）；
+/// 连接在没有 [DONE] 的情况下关闭时，尚无内容的报告为 IdleDisconnect
+pub fn decode_sse<S, B, E>(
+  bytes: S,
+  idle_warn: Option<Duration>,
+  idle_limit: Option<Duration>,
+) -> ChunkStream
+where
+  S: Stream<Item = std::result::Result<B, E>> + Send + 'static,
+  B: AsRef<[u8]> + Send + 'static,
+  E: std::error::Error + Send + Sync + 'static,
+{
+  let state = SseState {
+    bytes: Box::pin(bytes),
+    parser: SseParser::default(),
+    events: VecDeque::new(),
+    finished: false,
+    closed: None,
+    saw_content: false,
+    finish_reason: None,
+    idle_warn,
+    idle_limit,
+    last_data: tokio::time::Instant::now(),
+  };
+  Box::pin(futures_util::stream::unfold(
+    state,
+    |mut state| async move {
+      let chunk = state.next_chunk().await?;
+      Some((chunk, state))
+    },
+  ))
+}
+
+/// --base-url、DEEPCLI_BASE_URL 或配置文件给出的接口地址。可以带上 /chat/completions，
+/// 请求时会重新拼接；必须是带主机名的 http(s) 地址，不能有查询参数
+pub fn normalize_base_url(input: &str) -> Result<String, String> {
+  let example = "e.g. https://api.deepseek.com or http://localhost:8000/v1";
+  let trimmed = input.trim().trim_end_matches('/');
+  let trimmed = trimmed
+    .strip_suffix("/chat/completions")
+    .unwrap_or(trimmed)
+    .trim_end_matches('/');
+  let url = reqwest::Url::parse(trimmed)
+    .map_err(|e| format!("{:?} is not a valid URL ({}); {}", input, e, example))?;
+  if !matches!(url.scheme(), "http" | "https") {
+    return Err(format!(
+      "{:?} must start with http:// or https://; {}",
+      input, example
+    ));
+  }
+  if url.host_str().is_none_or(str::is_empty) {
+    return Err(format!("{:?} has no host; {}", input, example));
+  }
+  if url.query().is_some() || url.fragment().is_some() {
+    return Err(format!(
+      "{:?} must not contain a query string or fragment",
+      input
+    ));
+  }
+  Ok(trimmed.to_string())
+}
+
+/// 对话接口的抽象，上层流程可以用脚本化的 mock 测试。
+/// 非流式请求要求 Send，以便放到后台任务中执行。
+pub trait ChatBackend {
+  fn complete(
+    &self,
+    model: &str,
+    messages: Vec<Message>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+  ) -> impl std::future::Future<Output = Result<String>> + Send;
+
+  async fn stream(
+    &self,
+    model: &str,
+    messages: Vec<Message>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+  ) -> Result<ChunkStream>;
+}
+
+impl ChatBackend for ApiClient {
+  async fn complete(
+    &self,
+    model: &str,
+    messages: Vec<Message>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+  ) -> Result<String> {
+    let response = self
+      .call_api_with_history(model, messages, temperature, max_tokens, false)
+      .await?;
+    Ok(response.text())
+  }
+
+  async fn stream(
+    &self,
+    model: &str,
+    messages: Vec<Message>,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+  ) -> Result<ChunkStream> {
+    let mut request =
+      self.build_request_with_history(model, messages, temperature, max_tokens, false);
+    request.tools = (!self.tools.is_empty()).then(|| self.tools.clone());
+    self.send_stream(request).await
+  }
+}
+
+#[derive(Clone)]
 pub struct ApiClient {
   client: Client,
   api_key: String,
+  base_url: String,
+  reasoning_effort: Option<ReasoningEffort>,
+  attachment_truncation: Option<(usize, TruncateMode)>,
+  read_limits: ReadLimits,
+  table_budget: Option<usize>,
+  seed: Option<u64>,
+  stream_idle: Option<Duration>,
+  timeouts: Timeouts,
+  user: Option<String>,
+  /// 一次性请求的 system 消息（--system、--system-file、人设等）
+  system_prompt: String,
+  /// --no-system：一次性请求不带 system 消息
+  no_system: bool,
+  /// 对话流式请求中提供给模型的函数
+  tools: Vec<ToolSpec>,
+  /// --json-schema：JSON 模式下的 json_schema 响应格式
+  json_schema: Option<JsonSchemaFormat>,
+  /// 服务方不支持 json_schema 时改用 json_object，schema 写进 system 消息
+  schema_in_prompt: bool,
+  /// 最近一次响应的响应头，克隆出的客户端共享
+  last_headers: Arc<Mutex<Option<Headers>>>,
+  limiter: Arc<Limiter>,
+  /// 对话请求遇到暂时性失败时的重试
+  retry: RetryPolicy,
+  /// 每个 HTTP 请求记一个 span，克隆出的客户端共享
+  #[cfg(feature = "otlp")]
+  tracer: Option<Arc<crate::otlp::Exporter>>,
 }
 
 impl ApiClient {
   pub fn new(api_key: String) -> Self {
     Self {
-      client: Client::new(),
+      client: http_client(Timeouts::default()),
       api_key,
+      base_url: crate::profile::DASHSCOPE.base_url.to_string(),
+      reasoning_effort: None,
+      attachment_truncation: None,
+      read_limits: ReadLimits::default(),
+      table_budget: Some(crate::tabular::DEFAULT_BUDGET),
+      seed: None,
+      stream_idle: Some(DEFAULT_STREAM_IDLE),
+      timeouts: Timeouts::default(),
+      user: None,
+      system_prompt: crate::prompt::DEFAULT_SYSTEM_PROMPT.to_string(),
+      no_system: false,
+      tools: Vec::new(),
+      json_schema: None,
+      schema_in_prompt: false,
+      last_headers: Arc::default(),
+      limiter: Arc::default(),
+      retry: RetryPolicy::default(),
+      #[cfg(feature = "otlp")]
+      tracer: None,
     }
   }
 
+  /// --retries：连接错误、超时和 500/502/503 时按退避策略重试
+  pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+    self.retry = retry;
+    self
+  }
+
+  /// --json-schema：JSON 模式的请求使用 json_schema 响应格式
+  pub fn with_json_schema(mut self, schema: Option<JsonSchemaFormat>) -> Self {
+    self.json_schema = schema;
+    self
+  }
+
+  /// 服务方拒绝 json_schema 后调用：改用 json_object，schema 写进 JSON 要求
+  pub fn with_schema_in_prompt(mut self) -> Self {
+    self.schema_in_prompt = true;
+    self
+  }
+
+  /// 是否以 json_schema 响应格式发送 schema
+  pub fn sends_json_schema(&self) -> bool {
+    self.json_schema.is_some() && !self.schema_in_prompt
+  }
+
+  fn response_format(&self, json_mode: bool) -> Option<ResponseFormat> {
+    match (&self.json_schema, json_mode) {
+      (_, false) => None,
+      (Some(schema), true) if !self.schema_in_prompt => Some(ResponseFormat::JsonSchema {
+        json_schema: schema.clone(),
+      }),
+      _ => Some(ResponseFormat::JsonObject),
+    }
+  }
+
+  /// JSON 模式追加到 system 消息的要求；schema 不能作为响应格式发送时附在后面
+  pub fn json_instruction(&self) -> String {
+    match (&self.json_schema, self.schema_in_prompt) {
+      (Some(schema), true) => format!(
+        "{}\nYour JSON output must conform to this JSON Schema:\n{}",
+        JSON_INSTRUCTION, schema.schema
+      ),
+      _ => JSON_INSTRUCTION.to_string(),
+    }
+  }
+
+  /// --connect-timeout 和 --timeout；连接超时需要重新创建 reqwest 客户端
+  pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+    self.client = http_client(timeouts);
+    self.timeouts = timeouts;
+    self
+  }
+
+  /// 流式响应超过这么久没有数据时提示一次；None 关闭提示
+  pub fn with_stream_idle(mut self, idle: Option<Duration>) -> Self {
+    self.stream_idle = idle;
+    self
+  }
+
+  /// 固定采样种子，供需要可复现结果的场景（如 test-prompts）使用
+  pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+    self.seed = seed;
+    self
+  }
+
+  #[cfg(feature = "otlp")]
+  pub fn with_tracer(mut self, tracer: Option<Arc<crate::otlp::Exporter>>) -> Self {
+    self.tracer = tracer;
+    self
+  }
+
+  /// 每个请求都带上的终端用户标识
+  pub fn with_user(mut self, user: Option<String>) -> Self {
+    self.user = user;
+    self
+  }
+
+  /// 一次性请求（call_api、call_api_with_file）使用的 system 消息。带历史的请求由调用方组装
+  pub fn with_system_prompt(mut self, prompt: &str) -> Self {
+    self.system_prompt = prompt.to_string();
+    self
+  }
+
+  /// 一次性请求（call_api、call_api_with_file）不发送默认的 system 消息，
+  /// JSON 模式的要求附加到用户消息。带历史的请求由调用方组装
+  pub fn with_no_system(mut self, no_system: bool) -> Self {
+    self.no_system = no_system;
+    self
+  }
+
+  /// 通过 ChatBackend::stream 发出的对话请求带上这些函数。摘要等内部请求不带
+  pub fn with_tools(mut self, tools: Vec<ToolSpec>) -> Self {
+    self.tools = tools;
+    self
+  }
+
+  /// 文本附件超过 `budget` tokens 时按 `mode` 截断
+  pub fn with_attachment_truncation(mut self, budget: usize, mode: TruncateMode) -> Self {
+    self.attachment_truncation = Some((budget, mode));
+    self
+  }
+
+  pub fn with_read_limits(mut self, limits: ReadLimits) -> Self {
+    self.read_limits = limits;
+    self
+  }
+
+  /// 表格文件概要的 token 预算；None 时完整附加
+  pub fn with_table_budget(mut self, budget: Option<usize>) -> Self {
+    self.table_budget = budget;
+    self
+  }
+
+  /// 仅对支持该参数的模型生效，其余模型请求中不出现该字段
+  pub fn with_reasoning_effort(mut self, effort: Option<ReasoningEffort>) -> Self {
+    self.reasoning_effort = effort;
+    self
+  }
+
+  fn reasoning_effort_for(&self, model: &str) -> Option<ReasoningEffort> {
+    self.reasoning_effort.filter(|_| {
+      crate::models::registry().supports(model, crate::models::Capability::ReasoningEffort)
+    })
+  }
+
+  /// 地址末尾的 / 会被去掉；用户输入的地址先经过 normalize_base_url
+  pub fn with_base_url(mut self, base_url: &str) -> Self {
+    self.base_url = base_url.trim_end_matches('/').to_string();
+    self
+  }
+
+  pub fn base_url(&self) -> &str {
+    &self.base_url
+  }
+
+  /// 最近一次响应（流式或非流式）的响应头
+  pub fn last_headers(&self) -> Option<Headers> {
+    self.last_headers.lock().unwrap().clone()
+  }
+
+  /// 按限流器的要求等待后发送，并记录响应头
+  async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    if let Some(delay) = self.limiter.delay(Instant::now()) {
+      match delay >= Duration::from_secs(1) {
+        true => {
+          crate::widget::countdown(delay, |left| {
+            format!(
+              "[信息] 接近速率限制，{}s 后发送请求",
+              left.as_secs_f64().ceil()
+            )
+          })
+          .await
+        }
+        false => tokio::time::sleep(delay).await,
+      }
+    }
+    let request = request.build()?;
+    #[cfg(feature = "otlp")]
+    let (url, started) = (request.url().to_string(), std::time::SystemTime::now());
+    // 响应头的等待按 --timeout 限制；总时长不限，流式响应体由 decode_sse 按空闲时长限制
+    let response =
+      match tokio::time::timeout(self.timeouts.read, self.client.execute(request)).await {
+        Ok(response) => response.map_err(|e| self.timeout_error(e)),
+        Err(_) => Err(
+          TimeoutError {
+            kind: TimeoutKind::Response,
+            limit: self.timeouts.read,
+          }
+          .into(),
+        ),
+      };
+    #[cfg(feature = "otlp")]
+    if let Some(tracer) = &self.tracer {
+      let status = response.as_ref().ok().map(|r| r.status().as_u16());
+      let error = response.as_ref().err().map(|e| e.to_string());
+      tracer.record_http(&url, started, status, error);
+    }
+    let response = response?;
+    let headers = Headers::capture(response.status().as_u16(), response.headers());
+    self.limiter.observe(&headers.limits(), Instant::now());
+    *self.last_headers.lock().unwrap() = Some(headers);
+    Ok(response)
+  }
+
+  /// 连接超时换成说明上限的 TimeoutError，其他错误为 ApiError::Network
+  fn timeout_error(&self, error: reqwest::Error) -> anyhow::Error {
+    match error.is_connect() && error.is_timeout() {
+      true => TimeoutError {
+        kind: TimeoutKind::Connect,
+        limit: self.timeouts.connect,
+      }
+      .into(),
+      false => ApiError::Network(error).into(),
+    }
+  }
+
+  /// 发送并检查状态码，暂时性失败时按重试策略重新发送。
+  /// 返回时还没有读取响应体，所以流式请求不会在输出中途重试
+  async fn send_checked(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let retries = self.retry.retries;
+    let mut attempt = 0;
+    loop {
+      let Some(this) = request.try_clone() else {
+        // 请求体无法复制时只发送一次
+        let response = self.send(request).await.context("API request failed")?;
+        return ApiError::check(response).await;
+      };
+      let reason = match self.send(this).await {
+        Ok(response)
+          if attempt < retries && retry::retryable_status(response.status().as_u16()) =>
+        {
+          format!("HTTP {}", response.status())
+        }
+        Ok(response) => return ApiError::check(response).await,
+        Err(e)
+          if attempt < retries
+            && (e.is::<TimeoutError>()
+              || matches!(
+                e.downcast_ref::<ApiError>(),
+                Some(ApiError::Network(e)) if retry::retryable_error(e)
+              )) =>
+        {
+          e.to_string()
+        }
+        Err(e) => return Err(e).context("API request failed"),
+      };
+      let delay = self.retry.delay(attempt, retry::jitter());
+      attempt += 1;
+      eprintln!(
+        "[信息] 请求失败（{}），{:.1}s 后重试（{}/{}）",
+        reason,
+        delay.as_secs_f64(),
+        attempt,
+        retries
+      );
+      tokio::time::sleep(delay).await;
+    }
+  }
+
+  fn chat_completions_url(&self) -> String {
+    format!("{}/chat/completions", self.base_url)
+  }
+
   pub async fn call_api(
     &self,
     model: &str,
@@ -104,7 +1277,21 @@ impl ApiClient {
     self.send_request(request).await
   }
 
-  pub async fn call_api_with_file(
+  pub async fn call_api_with_file(
+    &self,
+    model: &str,
+    query: &str,
+    file_path: &Path,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    json_mode: bool,
+  ) -> Result<ApiResponse> {
+    let request =
+      self.build_request_with_file(model, query, file_path, temperature, max_tokens, json_mode)?;
+    self.send_request(request).await
+  }
+
+  pub async fn call_api_with_file_stream(
     &self,
     model: &str,
     query: &str,
@@ -112,10 +1299,10 @@ impl ApiClient {
     temperature: Option<f32>,
     max_tokens: Option<u32>,
     json_mode: bool,
-  ) -> Result<ApiResponse> {
+  ) -> Result<ChunkStream> {
     let request =
       self.build_request_with_file(model, query, file_path, temperature, max_tokens, json_mode)?;
-    self.send_request(request).await
+    self.send_stream(request).await
   }
 
   pub async fn call_api_with_history_stream(
@@ -125,110 +1312,128 @@ impl ApiClient {
     temperature: Option<f32>,
     max_tokens: Option<u32>,
     json_mode: bool,
-  ) -> Result<Pin<Box<dyn Stream<Item = Result<(String, Option<String>)>> + Send>>> {
-    use futures_util::stream;
+  ) -> Result<ChunkStream> {
+    let request =
+      self.build_request_with_history(model, messages, temperature, max_tokens, json_mode);
+    self.send_stream(request).await
+  }
+
+  async fn send_stream(&self, mut request: ApiRequest) -> Result<ChunkStream> {
     use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
-    use serde_json::Value;
 
-    let mut request =
-      self.build_request_with_history(model, messages, temperature, max_tokens, json_mode);
     request.stream = true;
+    request.stream_options = Some(StreamOptions {
+      include_usage: true,
+    });
+
+    let resp = self
+      .send_checked(
+        self
+          .client
+          .post(self.chat_completions_url())
+          .header(CONTENT_TYPE, "application/json")
+          .header(AUTHORIZATION, format!("Bearer {}", self.api_key))
+          .json(&request),
+      )
+      .await?;
+
+    Ok(decode_sse(
+      resp.bytes_stream(),
+      self.stream_idle,
+      Some(self.timeouts.read),
+    ))
+  }
 
-    let client = &self.client;
-    let api_key = &self.api_key;
-    let resp = client
-      .post("https://dashscope.aliyuncs.com/compatible-mode/v1/chat/completions")
-      .header(CONTENT_TYPE, "application/json")
-      .header(AUTHORIZATION, format!("Bearer {}", api_key))
-      .json(&request)
-      .send()
+  /// 发送一个很小的流式请求，返回每个原始数据块相对请求开始的到达时间
+  pub async fn probe_stream(&self, model: &str, timeout: Duration) -> Result<Vec<Duration>> {
+    let mut request = self.build_request(
+      model,
+      "Count from 1 to 20, separated by spaces.",
+      Some(0.0),
+      Some(64),
+      false,
+    );
+    request.stream = true;
+    let started = Instant::now();
+    let response = self
+      .send(
+        self
+          .client
+          .post(self.chat_completions_url())
+          .header("Content-Type", "application/json")
+          .header("Authorization", format!("Bearer {}", self.api_key))
+          .timeout(timeout)
+          .json(&request),
+      )
       .await
       .context("API request failed")?;
+    let response = ApiError::check(response).await?;
+    let mut arrivals = vec![];
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+      chunk.context("Stream interrupted")?;
+      arrivals.push(started.elapsed());
+    }
+    Ok(arrivals)
+  }
 
-    let stream = resp.bytes_stream();
-    let buffer = Vec::new();
-    let finished = false;
+  /// 用只要 1 个 token 的请求检查接口形态，不重试、不检查状态码，交给 compat::classify 判断
+  pub async fn probe_shape(&self, model: &str) -> Result<crate::compat::Response> {
+    let request = self.build_request(model, "hi", None, Some(1), false);
+    let response = self
+      .send(
+        self
+          .client
+          .post(self.chat_completions_url())
+          .header("Content-Type", "application/json")
+          .header("Authorization", format!("Bearer {}", self.api_key))
+          .timeout(crate::compat::PROBE_TIMEOUT)
+          .json(&request),
+      )
+      .await
+      .context("API request failed")?;
+    let status = response.status().as_u16();
+    let content_type = response
+      .headers()
+      .get(reqwest::header::CONTENT_TYPE)
+      .and_then(|v| v.to_str().ok())
+      .unwrap_or_default()
+      .to_string();
+    let body = response.text().await.unwrap_or_default();
+    Ok(crate::compat::Response {
+      status,
+      content_type,
+      body: body.chars().take(crate::compat::BODY_LIMIT).collect(),
+    })
+  }
 
-    let s = stream::unfold(
-      (stream, buffer, finished),
-      |(mut stream, mut buffer, mut finished)| async move {
-        if finished {
-          return None;
-        }
-        while let Some(item) = stream.next().await {
-          match item {
-            Ok(chunk) => {
-              buffer.extend_from_slice(&chunk);
-              // 尝试按行分割
-              while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
-                let line = buffer.drain(..=pos).collect::<Vec<u8>>();
-                let line_str = String::from_utf8_lossy(&line).trim().to_string();
-                if line_str.is_empty() {
-                  continue;
-                }
-                if let Some(data) = line_str.strip_prefix("data: ") {
-                  if data == "[DONE]" {
-                    finished = true;
-                    return Some((
-                      Ok((String::new(), Some("length".to_string()))),
-                      (stream, buffer, finished),
-                    ));
-                  }
-                  // 解析json
-                  if let Ok(json) = serde_json::from_str::<Value>(data) {
-                    // 兼容OpenAI风格
-                    if let Some(choices) = json.get("choices") {
-                      if let Some(choice) = choices.get(0) {
-                        let finish_reason = choice
-                          .get("finish_reason")
-                          .and_then(|v| v.as_str())
-                          .map(|s| s.to_string());
-                        if let Some(delta) = choice.get("delta") {
-                          if let Some(content) = delta.get("content") {
-                            if let Some(s) = content.as_str() {
-                              return Some((
-                                Ok((s.to_string(), finish_reason)),
-                                (stream, buffer, finished),
-                              ));
-                            }
-                          }
-                        }
-                        // deepseek 可能直接有 message.content
-                        if let Some(message) = choice.get("message") {
-                          if let Some(content) = message.get("content") {
-                            if let Some(s) = content.as_str() {
-                              return Some((
-                                Ok((s.to_string(), finish_reason)),
-                                (stream, buffer, finished),
-                              ));
-                            }
-                          }
-                        }
-                        // 如果有 finish_reason 但没有内容，也要传递
-                        if finish_reason.is_some() {
-                          return Some((
-                            Ok((String::new(), finish_reason)),
-                            (stream, buffer, finished),
-                          ));
-                        }
-                      }
-                    }
-                  }
-                }
-              }
-            }
-            Err(e) => {
-              return Some((
-                Err::<(String, Option<String>), _>(anyhow::anyhow!(e)),
-                (stream, buffer, true),
-              ));
-            }
-          }
-        }
-        None
-      },
-    );
-    Ok(Box::pin(s))
+  /// 一次性请求的消息：system 消息（JSON 模式时要求输出 JSON）加上 `user`
+  fn one_shot_messages(&self, user: Message, json_mode: bool) -> Vec<Message> {
+    let system = |content: String| Message::Simple {
+      role: "system".to_string(),
+      content,
+      name: None,
+    };
+    let content = match json_mode {
+      true => format!("{} {}", self.system_prompt, self.json_instruction()),
+      false => self.system_prompt.clone(),
+    };
+    if !self.no_system {
+      return vec![system(content), user];
+    }
+    // --no-system：默认提示不发送，自定义的提示和 JSON 要求并入用户消息
+    let content = match (
+      self.system_prompt == crate::prompt::DEFAULT_SYSTEM_PROMPT,
+      json_mode,
+    ) {
+      (true, false) => return vec![user],
+      (true, true) => self.json_instruction(),
+      (false, _) => content,
+    };
+    let mut messages = vec![system(content), user];
+    crate::prompt::fold_system(&mut messages);
+    crate::prompt::warn_folded();
+    messages
   }
 
   fn build_request(
@@ -239,36 +1444,23 @@ impl ApiClient {
     max_tokens: Option<u32>,
     json_mode: bool,
   ) -> ApiRequest {
-    // Update system message when in JSON mode
-    let system_message = if json_mode {
-      "You are a helpful assistant. You must output your response in a valid JSON format."
-        .to_string()
-    } else {
-      "You are a helpful assistant.".to_string()
+    let user = Message::Simple {
+      role: "user".to_string(),
+      content: query.to_string(),
+      name: None,
     };
-
     ApiRequest {
       model: model.to_string(),
-      messages: vec![
-        Message::Simple {
-          role: "system".to_string(),
-          content: system_message,
-        },
-        Message::Simple {
-          role: "user".to_string(),
-          content: query.to_string(),
-        },
-      ],
+      messages: self.one_shot_messages(user, json_mode),
       temperature,
       max_tokens,
       stream: false,
-      response_format: if json_mode {
-        Some(ResponseFormat {
-          format_type: "json_object".to_string(),
-        })
-      } else {
-        None
-      },
+      response_format: self.response_format(json_mode),
+      reasoning_effort: self.reasoning_effort_for(model),
+      seed: self.seed,
+      user: self.user.clone(),
+      tools: None,
+      stream_options: None,
     }
   }
 
@@ -286,13 +1478,12 @@ impl ApiClient {
       temperature,
       max_tokens,
       stream: false,
-      response_format: if json_mode {
-        Some(ResponseFormat {
-          format_type: "json_object".to_string(),
-        })
-      } else {
-        None
-      },
+      response_format: self.response_format(json_mode),
+      reasoning_effort: self.reasoning_effort_for(model),
+      seed: self.seed,
+      user: self.user.clone(),
+      tools: None,
+      stream_options: None,
     }
   }
 
@@ -330,83 +1521,106 @@ impl ApiClient {
       })]
     };
 
-    let system_message = if json_mode {
-      "You are a helpful assistant. You must output your response in a valid JSON format."
-        .to_string()
-    } else {
-      "You are a helpful assistant.".to_string()
+    let user = Message::MultiModal {
+      role: "user".to_string(),
+      content,
+      name: None,
     };
-
     Ok(ApiRequest {
       model: model.to_string(),
-      messages: vec![
-        Message::Simple {
-          role: "system".to_string(),
-          content: system_message,
-        },
-        Message::MultiModal {
-          role: "user".to_string(),
-          content,
-        },
-      ],
+      messages: self.one_shot_messages(user, json_mode),
       temperature,
       max_tokens,
       stream: true,
-      response_format: if json_mode {
-        Some(ResponseFormat {
-          format_type: "json_object".to_string(),
-        })
-      } else {
-        None
-      },
+      response_format: self.response_format(json_mode),
+      reasoning_effort: self.reasoning_effort_for(model),
+      seed: self.seed,
+      user: self.user.clone(),
+      tools: None,
+      stream_options: None,
     })
   }
 
   fn read_file_content(&self, file_path: &Path) -> Result<String> {
-    let mime_type = mime_guess::from_path(file_path)
-      .first_or_octet_stream()
-      .to_string();
+    let bytes = crate::attachment::read(file_path, self.read_limits)?;
+    if let Some(summary) = self
+      .table_budget
+      .and_then(|budget| crate::tabular::summarize(file_path, &bytes, budget))
+    {
+      return Ok(summary);
+    }
+    let (_, data) = crate::attachment::encode(file_path, bytes, self.attachment_truncation)?;
+    Ok(data)
+  }
 
-    if mime_type.starts_with("image/") {
-      // 读取图像文件并转换为base64
-      let image_data =
-        std::fs::read(file_path).context(format!("Failed to read image file: {:?}", file_path))?;
-      Ok(base64::engine::general_purpose::STANDARD.encode(image_data))
-    } else {
-      // 读取文本文件
-      let content = std::fs::read_to_string(file_path)
-        .context(format!("Failed to read file: {:?}", file_path))?;
-      Ok(content)
+  /// 调用 /embeddings，返回与 `inputs` 顺序一致的向量
+  pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+    let request = EmbeddingRequest {
+      model,
+      input: inputs,
+      user: self.user.as_deref(),
+    };
+    let response = self
+      .send(
+        self
+          .client
+          .post(format!("{}/embeddings", self.base_url))
+          .header("Content-Type", "application/json")
+          .header("Authorization", format!("Bearer {}", self.api_key))
+          .json(&request),
+      )
+      .await
+      .context("Embedding request failed")?;
+    let response = ApiError::check(response).await?;
+    let mut response: EmbeddingResponse = response
+      .json()
+      .await
+      .context("Failed to parse embedding response")?;
+    if response.data.len() != inputs.len() {
+      anyhow::bail!(
+        "Expected {} embeddings, got {}",
+        inputs.len(),
+        response.data.len()
+      );
     }
+    response.data.sort_by_key(|d| d.index);
+    Ok(response.data.into_iter().map(|d| d.embedding).collect())
   }
 
   async fn send_request(&self, request: ApiRequest) -> Result<ApiResponse> {
     let response = self
-      .client
-      .post("https://dashscope.aliyuncs.com/compatible-mode/v1/chat/completions")
-      .header("Content-Type", "application/json")
-      .header("Authorization", format!("Bearer {}", self.api_key))
-      .json(&request)
-      .send()
-      .await
-      .context("API request failed")?;
+      .send_checked(
+        self
+          .client
+          .post(self.chat_completions_url())
+          .header("Content-Type", "application/json")
+          .header("Authorization", format!("Bearer {}", self.api_key))
+          .json(&request),
+      )
+      .await?;
 
-    if !response.status().is_success() {
-      let status = response.status();
-      let error_text = response
-        .text()
-        .await
-        .unwrap_or_else(|_| "Unknown error".into());
-      anyhow::bail!("API Error {}: {}", status, error_text);
+    let limit = self.timeouts.read;
+    match tokio::time::timeout(limit, response.json()).await {
+      Ok(parsed) => parsed.context("Failed to parse API response"),
+      Err(_) => Err(
+        TimeoutError {
+          kind: TimeoutKind::Response,
+          limit,
+        }
+        .into(),
+      ),
     }
-
-    response
-      .json()
-      .await
-      .context("Failed to parse API response")
   }
 }
 
+/// 带连接超时的 reqwest 客户端
+fn http_client(timeouts: Timeouts) -> Client {
+  Client::builder()
+    .connect_timeout(timeouts.connect)
+    .build()
+    .expect("the TLS backend initializes")
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -415,6 +1629,52 @@ mod tests {
   fn test_api_client_creation() {
     let client = ApiClient::new("test_key".to_string());
     assert_eq!(client.api_key, "test_key");
+    assert_eq!(
+      client.chat_completions_url(),
+      "https://dashscope.aliyuncs.com/compatible-mode/v1/chat/completions"
+    );
+
+    let client = ApiClient::new("test_key".to_string()).with_base_url("https://api.deepseek.com/");
+    assert_eq!(
+      client.chat_completions_url(),
+      "https://api.deepseek.com/chat/completions"
+    );
+  }
+
+  #[test]
+  fn test_normalize_base_url() {
+    let ok = [
+      ("https://api.deepseek.com", "https://api.deepseek.com"),
+      ("https://api.deepseek.com/", "https://api.deepseek.com"),
+      ("http://localhost:8000/v1", "http://localhost:8000/v1"),
+      (
+        " https://gateway.example.com/openai/v1/chat/completions ",
+        "https://gateway.example.com/openai/v1",
+      ),
+      (
+        "http://127.0.0.1:8000/v1/chat/completions/",
+        "http://127.0.0.1:8000/v1",
+      ),
+    ];
+    for (input, expected) in ok {
+      let url = normalize_base_url(input).unwrap();
+      assert_eq!(url, expected);
+      let client = ApiClient::new("k".to_string()).with_base_url(&url);
+      assert_eq!(
+        client.chat_completions_url(),
+        format!("{}/chat/completions", expected)
+      );
+    }
+    for input in [
+      "",
+      "api.deepseek.com",
+      "ftp://example.com",
+      "https://",
+      "https://example.com/v1?key=1",
+    ] {
+      let err = normalize_base_url(input).unwrap_err();
+      assert!(err.contains(&format!("{:?}", input)), "{}", err);
+    }
   }
 
   #[test]
@@ -426,19 +1686,499 @@ mod tests {
     assert_eq!(request.temperature, Some(1.0));
     assert_eq!(request.max_tokens, Some(100));
     assert!(!request.stream);
-    assert!(request.response_format.is_some());
-    assert_eq!(request.response_format.unwrap().format_type, "json_object");
+    assert_eq!(request.response_format, Some(ResponseFormat::JsonObject));
     assert_eq!(request.messages.len(), 2);
   }
 
+  #[test]
+  fn test_response_format_serialization() {
+    assert_eq!(
+      serde_json::to_value(ResponseFormat::JsonObject).unwrap(),
+      serde_json::json!({"type": "json_object"})
+    );
+    let schema = JsonSchemaFormat {
+      name: "report".to_string(),
+      schema: serde_json::json!({"type": "object", "required": ["title"]}),
+      strict: true,
+    };
+    let format = ResponseFormat::JsonSchema {
+      json_schema: schema.clone(),
+    };
+    assert_eq!(
+      serde_json::to_value(&format).unwrap(),
+      serde_json::json!({
+        "type": "json_schema",
+        "json_schema": {
+          "name": "report",
+          "schema": {"type": "object", "required": ["title"]},
+          "strict": true
+        }
+      })
+    );
+
+    // 发送 schema 时 JSON 要求不变；被拒绝后改用 json_object，schema 写进 system 消息
+    let client = ApiClient::new("test_key".to_string()).with_json_schema(Some(schema));
+    let request = client.build_request("deepseek-chat", "q", None, None, true);
+    assert_eq!(request.response_format, Some(format));
+    assert!(client.sends_json_schema());
+    assert_eq!(client.json_instruction(), JSON_INSTRUCTION);
+    let client = client.with_schema_in_prompt();
+    let request = client.build_request("deepseek-chat", "q", None, None, true);
+    assert_eq!(request.response_format, Some(ResponseFormat::JsonObject));
+    let payload = serde_json::to_string(&request.messages[0]).unwrap();
+    assert!(
+      payload.contains(r#"{\"required\":[\"title\"],\"type\":\"object\"}"#),
+      "{}",
+      payload
+    );
+    assert!(!client.sends_json_schema());
+    assert_eq!(
+      client
+        .build_request("deepseek-chat", "q", None, None, false)
+        .response_format,
+      None
+    );
+  }
+
+  #[test]
+  fn test_reasoning_effort_serialization() {
+    let client =
+      ApiClient::new("test_key".to_string()).with_reasoning_effort(Some(ReasoningEffort::High));
+
+    let request = client.build_request("o3-mini", "test", None, None, false);
+    let json = serde_json::to_value(&request).unwrap();
+    assert_eq!(json["reasoning_effort"], "high");
+
+    for model in ["deepseek-r1", "deepseek-reasoner", "deepseek-chat"] {
+      let request = client.build_request_with_history(model, vec![], None, None, false);
+      let json = serde_json::to_value(&request).unwrap();
+      assert!(json.get("reasoning_effort").is_none(), "{}", model);
+    }
+
+    let client = ApiClient::new("test_key".to_string());
+    let request = client.build_request("o3-mini", "test", None, None, false);
+    let json = serde_json::to_value(&request).unwrap();
+    assert!(json.get("reasoning_effort").is_none());
+  }
+
+  #[test]
+  fn test_user_serialized_only_when_configured() {
+    let client = ApiClient::new("test_key".to_string());
+    let requests = [
+      client.build_request("deepseek-chat", "test", None, None, false),
+      client.build_request_with_history("deepseek-chat", vec![], None, None, false),
+    ];
+    for request in &requests {
+      let json = serde_json::to_value(request).unwrap();
+      assert!(json.get("user").is_none());
+    }
+
+    let client = client.with_user(Some("alice@corp".to_string()));
+    let requests = [
+      client.build_request("deepseek-chat", "test", None, None, false),
+      client.build_request_with_history("deepseek-chat", vec![], None, None, true),
+    ];
+    for request in &requests {
+      let json = serde_json::to_value(request).unwrap();
+      assert_eq!(json["user"], "alice@corp");
+    }
+  }
+
+  #[test]
+  fn test_message_name_serialized_only_when_present() {
+    let text: Message = serde_json::from_str(r#"{"role":"user","content":"hi"}"#).unwrap();
+    assert_eq!(
+      serde_json::to_string(&text).unwrap(),
+      r#"{"role":"user","content":"hi"}"#
+    );
+    let named: Message =
+      serde_json::from_str(r#"{"role":"user","content":"hi","name":"alice"}"#).unwrap();
+    assert_eq!(named.speaker(), "user(alice)");
+    let image: Message = serde_json::from_str(
+      r#"{"role":"user","content":[{"type":"text","text":"看图"}],"name":"bob"}"#,
+    )
+    .unwrap();
+    assert!(matches!(&image, Message::MultiModal { name: Some(n), .. } if n == "bob"));
+
+    let client = ApiClient::new("test_key".to_string());
+    let request = client.build_request_with_history(
+      "deepseek-chat",
+      vec![text, named, image],
+      None,
+      None,
+      false,
+    );
+    let json = serde_json::to_value(&request).unwrap();
+    assert!(json["messages"][0].get("name").is_none());
+    assert_eq!(json["messages"][1]["name"], "alice");
+    assert_eq!(json["messages"][2]["name"], "bob");
+
+    assert!(valid_name("alice_2-b"));
+    assert!(!valid_name(""));
+    assert!(!valid_name("bob smith"));
+    assert!(!valid_name(&"a".repeat(MAX_NAME_CHARS + 1)));
+  }
+
+  #[test]
+  fn test_notes_and_reasoning_never_reach_requests() {
+    let history: Vec<Message> = [("user", "q1"), ("assistant", "a1"), ("user", "q2")]
+      .iter()
+      .map(|(role, content)| Message::Simple {
+        role: role.to_string(),
+        content: content.to_string(),
+        name: None,
+      })
+      .collect();
+    let mut notes = crate::notes::Notes::default();
+    notes.add(1, "NOTE-SECRET 这个回答错了");
+    let mut reasoning = crate::reasoning::Reasoning::default();
+    reasoning.record(1, "THOUGHT-SECRET 先算一下");
+    let markdown = crate::export::markdown(&history, &notes, &reasoning);
+    assert!(markdown.contains("NOTE-SECRET") && markdown.contains("THOUGHT-SECRET"));
+
+    let client = ApiClient::new("test_key".to_string());
+    let payloads = [
+      serde_json::to_string(&client.build_request_with_history(
+        "deepseek-chat",
+        history.clone(),
+        None,
+        None,
+        false,
+      ))
+      .unwrap(),
+      serde_json::to_string(&client.build_request_with_history(
+        "deepseek-chat",
+        crate::summary::summary_messages(&history),
+        None,
+        None,
+        false,
+      ))
+      .unwrap(),
+    ];
+    for payload in payloads {
+      assert!(payload.contains("q1"));
+      assert!(!payload.contains("NOTE-SECRET"), "{}", payload);
+      assert!(!payload.contains("这个回答错了"));
+      assert!(!payload.contains("THOUGHT-SECRET"), "{}", payload);
+      assert!(!payload.contains("reasoning"), "{}", payload);
+    }
+
+    // 导入的历史即使带着 reasoning_content，重新发送时也不会带上
+    let imported: Message = serde_json::from_str(
+      r#"{"role":"assistant","content":"a1","reasoning_content":"THOUGHT-SECRET"}"#,
+    )
+    .unwrap();
+    let payload = serde_json::to_string(&client.build_request_with_history(
+      "deepseek-reasoner",
+      vec![imported],
+      None,
+      None,
+      false,
+    ))
+    .unwrap();
+    assert!(!payload.contains("reasoning"), "{}", payload);
+  }
+
+  #[test]
+  fn test_file_request_sends_images_as_data_urls_and_inlines_code() {
+    let client = ApiClient::new("test_key".to_string());
+    let dir = crate::checkpoint::temp_dir("file-request");
+    let image = dir.join("screenshot.png");
+    std::fs::write(&image, [0x89, b'P', b'N', b'G', 0x0d, 0x0a]).unwrap();
+    let request = client
+      .build_request_with_file("qwen-vl-max", "这是什么？", &image, None, None, false)
+      .unwrap();
+    let user = &serde_json::to_value(&request).unwrap()["messages"][1];
+    assert_eq!(user["content"][0]["text"], "这是什么？");
+    assert_eq!(user["content"][1]["type"], "image_url");
+    assert_eq!(
+      user["content"][1]["image_url"]["url"],
+      "data:image/png;base64,iVBORw0K"
+    );
+
+    let code = dir.join("lib.rs");
+    std::fs::write(&code, "fn answer() -> u32 { 42 }\n").unwrap();
+    let request = client
+      .build_request_with_file("deepseek-chat", "review", &code, None, None, false)
+      .unwrap();
+    let user = &serde_json::to_value(&request).unwrap()["messages"][1];
+    assert_eq!(user["content"].as_array().unwrap().len(), 1);
+    let text = user["content"][0]["text"].as_str().unwrap();
+    assert!(text.starts_with("review\n\n文件内容:\n"), "{}", text);
+    assert!(text.contains("fn answer() -> u32 { 42 }"), "{}", text);
+
+    assert!(
+      client
+        .build_request_with_file(
+          "deepseek-chat",
+          "q",
+          &dir.join("missing.rs"),
+          None,
+          None,
+          false
+        )
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_no_system_in_any_request_path() {
+    use crate::prompt::{AnswerLength, DEFAULT_SYSTEM_PROMPT, SystemPrompt};
+    let systems = |request: &ApiRequest| {
+      serde_json::to_value(request).unwrap()["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|m| m["role"] == "system")
+        .count()
+    };
+    let client = ApiClient::new("test_key".to_string()).with_no_system(true);
+    let dir = crate::checkpoint::temp_dir("no-system");
+    let path = dir.join("notes.txt");
+    std::fs::write(&path, "hello").unwrap();
+    for json_mode in [false, true] {
+      let one_shot = client.build_request("deepseek-chat", "q", None, None, json_mode);
+      assert_eq!(systems(&one_shot), 0);
+      let file = client
+        .build_request_with_file("deepseek-chat", "q", &path, None, None, json_mode)
+        .unwrap();
+      assert_eq!(systems(&file), 0);
+      // JSON 模式的要求附加到用户消息
+      let payload = serde_json::to_string(&file).unwrap();
+      assert_eq!(payload.contains(JSON_INSTRUCTION), json_mode);
+    }
+    let history = vec![Message::Simple {
+      role: "user".to_string(),
+      content: "q".to_string(),
+      name: None,
+    }];
+    let prompt = SystemPrompt::new(DEFAULT_SYSTEM_PROMPT, AnswerLength::Brief).with_no_system(true);
+    let request = client.build_request_with_history(
+      "deepseek-chat",
+      prompt.messages(&history),
+      None,
+      None,
+      false,
+    );
+    assert_eq!(systems(&request), 0);
+    // 摘要请求保留自己的 system 消息
+    let summary = client.build_request_with_history(
+      "deepseek-chat",
+      crate::summary::summary_messages(&history),
+      None,
+      None,
+      false,
+    );
+    assert_eq!(systems(&summary), 1);
+    // 默认仍然发送
+    let client = ApiClient::new("test_key".to_string());
+    assert_eq!(
+      systems(&client.build_request("deepseek-chat", "q", None, None, false)),
+      1
+    );
+    std::fs::remove_dir_all(dir).ok();
+  }
+
+  #[test]
+  fn test_custom_system_prompt_in_one_shot_requests() {
+    let first =
+      |request: &ApiRequest| serde_json::to_value(request).unwrap()["messages"][0].clone();
+    let client = ApiClient::new("test_key".to_string()).with_system_prompt("You review Rust code.");
+    let request = client.build_request("deepseek-chat", "q", None, None, false);
+    assert_eq!(first(&request)["role"], "system");
+    assert_eq!(first(&request)["content"], "You review Rust code.");
+    // JSON 模式的要求仍然附加在后面
+    let request = client.build_request("deepseek-chat", "q", None, None, true);
+    assert_eq!(
+      first(&request)["content"],
+      format!("You review Rust code. {}", JSON_INSTRUCTION)
+    );
+    // --no-system 时并入用户消息
+    let request =
+      client
+        .with_no_system(true)
+        .build_request("deepseek-chat", "q", None, None, false);
+    assert_eq!(first(&request)["role"], "user");
+    assert!(
+      first(&request)["content"]
+        .as_str()
+        .unwrap()
+        .starts_with("You review Rust code."),
+      "{}",
+      first(&request)
+    );
+  }
+
+  #[test]
+  fn test_text_attachment_truncation() {
+    let path = std::env::temp_dir().join(format!("deepcli-test-{}.log", std::process::id()));
+    let log = (0..2000)
+      .map(|i| format!("2026-10-14 10:00:00 INFO line {}", i))
+      .collect::<Vec<_>>()
+      .join("\n");
+    std::fs::write(&path, &log).unwrap();
+
+    let client = ApiClient::new("test_key".to_string());
+    assert_eq!(client.read_file_content(&path).unwrap(), log);
+
+    let client = client.with_attachment_truncation(500, TruncateMode::Auto);
+    let content = client.read_file_content(&path).unwrap();
+    assert!(content.contains("[... 省略"));
+    assert!(content.ends_with("INFO line 1999"));
+    std::fs::remove_file(path).unwrap();
+  }
+
+  #[test]
+  fn test_parse_stream_data() {
+    let chunk = parse_stream_data(
+      r#"{"choices":[{"delta":{"content":null,"reasoning_content":"思考"},"finish_reason":null}]}"#,
+    )
+    .unwrap();
+    assert_eq!(chunk.content, "");
+    assert_eq!(chunk.reasoning, "思考");
+
+    let chunk =
+      parse_stream_data(r#"{"choices":[{"delta":{"content":"答"},"finish_reason":"length"}]}"#)
+        .unwrap();
+    assert_eq!(chunk.content, "答");
+    assert_eq!(chunk.finish_reason.as_deref(), Some("length"));
+
+    let chunk = parse_stream_data(r#"{"choices":[{"message":{"content":"完整"}}]}"#).unwrap();
+    assert_eq!(chunk.content, "完整");
+
+    // 空增量（例如只有 role）和无法解析的行都跳过
+    assert!(parse_stream_data(r#"{"choices":[{"delta":{"role":"assistant"}}]}"#).is_none());
+    assert!(parse_stream_data("not json").is_none());
+  }
+
+  #[test]
+  fn test_usage_is_optional() {
+    let response: ApiResponse = serde_json::from_str(
+      r#"{"choices":[{"message":{"role":"assistant","content":"好"}}],
+          "usage":{"prompt_tokens":9,"completion_tokens":1,"total_tokens":10}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+      response.usage,
+      Some(Usage {
+        prompt_tokens: 9,
+        completion_tokens: 1,
+        total_tokens: 10,
+      })
+    );
+    let response: ApiResponse =
+      serde_json::from_str(r#"{"choices":[{"message":{"role":"assistant","content":"好"}}]}"#)
+        .unwrap();
+    assert_eq!(response.usage, None);
+    // 缺少的字段按 0 处理
+    let response: ApiResponse =
+      serde_json::from_str(r#"{"choices":[],"usage":{"total_tokens":3}}"#).unwrap();
+    assert_eq!(response.usage.unwrap().total_tokens, 3);
+
+    // 流式响应的用量在最后一个 choices 为空的增量中；其余增量的 usage 为 null
+    let chunk = parse_stream_data(
+      r#"{"choices":[],"usage":{"prompt_tokens":9,"completion_tokens":1,"total_tokens":10}}"#,
+    )
+    .unwrap();
+    assert_eq!(chunk.usage.map(|u| u.prompt_tokens), Some(9));
+    let chunk =
+      parse_stream_data(r#"{"choices":[{"delta":{"content":"答"}}],"usage":null}"#).unwrap();
+    assert_eq!(chunk.usage, None);
+    assert!(parse_stream_data(r#"{"choices":[],"usage":null}"#).is_none());
+  }
+
+  #[test]
+  fn test_content_filter_refusals() {
+    // OpenAI 风格：finish_reason 原样保留
+    let chunk = parse_stream_data(
+      r#"{"choices":[{"delta":{"content":""},"finish_reason":"content_filter"}]}"#,
+    )
+    .unwrap();
+    assert_eq!(chunk.finish_reason.as_deref(), Some(CONTENT_FILTER));
+    // DashScope：流中的错误事件
+    let body = r#"{"error":{"code":"data_inspection_failed","message":"Output data may contain inappropriate content.","type":"data_inspection_failed"}}"#;
+    let chunk = parse_stream_data(body).unwrap();
+    assert_eq!(chunk.finish_reason.as_deref(), Some(CONTENT_FILTER));
+    assert_eq!(chunk.content, "");
+    let error = |status: u16, body: &str| {
+      ApiError::from_response(
+        reqwest::StatusCode::from_u16(status).unwrap(),
+        body.to_string(),
+        None,
+      )
+    };
+    // DashScope：请求被拦截时的 400 错误体，原生接口的 code 在顶层
+    assert!(matches!(error(400, body), ApiError::ContentFilter { .. }));
+    assert!(matches!(
+      error(400, r#"{"code":"DataInspectionFailed","message":"..."}"#),
+      ApiError::ContentFilter { .. }
+    ));
+    assert!(matches!(
+      error(400, r#"{"error":{"code":"invalid_parameter_error"}}"#),
+      ApiError::InvalidRequest { .. }
+    ));
+    assert!(parse_stream_data(r#"{"error":{"code":"internal_error"}}"#).is_none());
+  }
+
+  #[test]
+  fn test_tool_call_deltas_accumulate() {
+    let lines = [
+      r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"lookup","arguments":""}}]}}]}"#,
+      r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"id\":"}}]}}]}"#,
+      r#"{"choices":[{"delta":{"tool_calls":[{"index":1,"id":"call_2","function":{"name":"other","arguments":"{}"}}]}}]}"#,
+      r#"{"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"A-1\"}"}}]}}]}"#,
+    ];
+    let mut calls = ToolCallAccumulator::default();
+    for line in lines {
+      for delta in &parse_stream_data(line).unwrap().tool_calls {
+        calls.push(delta);
+      }
+    }
+    let calls = calls.finish();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0].id, "call_1");
+    assert_eq!(calls[0].function.name, "lookup");
+    assert_eq!(calls[0].function.arguments, r#"{"id":"A-1"}"#);
+    assert_eq!(calls[1].function.name, "other");
+
+    // 工具往返消息按接口格式序列化
+    let message = Message::Tool {
+      role: "assistant".to_string(),
+      content: String::new(),
+      tool_calls: calls,
+      tool_call_id: None,
+    };
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(json["tool_calls"][1]["type"], "function");
+    assert!(json.get("tool_call_id").is_none());
+
+    // 只有流式对话请求带上 tools
+    let client = ApiClient::new("test_key".to_string()).with_tools(vec![ToolSpec {
+      kind: "function",
+      function: FunctionSpec {
+        name: "lookup".to_string(),
+        description: "Look up".to_string(),
+        parameters: serde_json::json!({"type": "object"}),
+      },
+    }]);
+    let request = client.build_request_with_history("deepseek-chat", vec![], None, None, false);
+    assert!(
+      serde_json::to_value(&request)
+        .unwrap()
+        .get("tools")
+        .is_none()
+    );
+  }
+
   #[test]
   fn test_message_creation() {
     let message = Message::Simple {
       role: "user".to_string(),
       content: "Hello".to_string(),
+      name: None,
     };
     match message {
-      Message::Simple { role, content } => {
+      Message::Simple { role, content, .. } => {
         assert_eq!(role, "user");
         assert_eq!(content, "Hello");
       }
@@ -466,4 +2206,512 @@ mod tests {
       panic!("Expected simple message");
     }
   }
+
+  async fn collect(stream: ChunkStream) -> Vec<Result<StreamChunk>> {
+    stream.collect().await
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_idle_close_before_content_is_idle_disconnect() {
+    use crate::mock::scripted_transport;
+    // r1 思考 70s 没有任何有效字节，随后代理关闭连接
+    let events = vec![(Duration::from_secs(70), String::new())];
+    let items = collect(decode_sse(
+      scripted_transport(events),
+      Some(DEFAULT_STREAM_IDLE),
+      None,
+    ))
+    .await;
+    assert_eq!(items.len(), 1);
+    let err = items[0].as_ref().unwrap_err();
+    let idle = err.downcast_ref::<IdleDisconnect>().unwrap();
+    assert_eq!(idle.idle, Duration::from_secs(70));
+    assert!(err.to_string().contains("proxy"));
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_stream_idle_limit_is_not_a_total_limit() {
+    use crate::mock::{scripted_transport, sse_content};
+    let limit = Some(Duration::from_secs(60));
+    // 持续输出的长回答超过上限很多也不会被切断
+    let mut events: Vec<_> = (0..10)
+      .map(|_| (Duration::from_secs(40), sse_content("字")))
+      .collect();
+    events.push((Duration::ZERO, "data: [DONE]\n\n".to_string()));
+    let items = collect(decode_sse(scripted_transport(events), None, limit)).await;
+    assert_eq!(items.len(), 10);
+    assert!(items.iter().all(Result::is_ok));
+
+    // 中途停住超过上限时报告是哪个超时
+    let events = vec![
+      (Duration::ZERO, sse_content("Hel")),
+      (Duration::from_secs(90), sse_content("lo")),
+    ];
+    let items = collect(decode_sse(scripted_transport(events), None, limit)).await;
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap().content, "Hel");
+    let err = items[1].as_ref().unwrap_err();
+    assert_eq!(
+      err.downcast_ref::<TimeoutError>(),
+      Some(&TimeoutError {
+        kind: TimeoutKind::Stream,
+        limit: Duration::from_secs(60)
+      })
+    );
+    assert_eq!(
+      err.to_string(),
+      "Read timeout: the stream sent no data for 60s (--timeout 60s)"
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_close_mid_content_is_not_idle_disconnect() {
+    use crate::mock::{scripted_transport, sse_content};
+    // 一个分片里有两行，半行留到下一个分片
+    let events = vec![
+      (
+        Duration::ZERO,
+        format!("{}{}", sse_content("Hel"), sse_content("lo")),
+      ),
+      (
+        Duration::from_secs(90),
+        "data: {\"choices\":[{\"delta\":".to_string(),
+      ),
+    ];
+    let items = collect(decode_sse(scripted_transport(events), None, None)).await;
+    let contents: Vec<_> = items
+      .iter()
+      .filter_map(|i| i.as_ref().ok())
+      .map(|c| c.content.as_str())
+      .collect();
+    assert_eq!(contents, ["Hel", "lo"]);
+    let err = items.last().unwrap().as_ref().unwrap_err();
+    assert!(!err.is::<IdleDisconnect>());
+    assert!(err.to_string().contains("before the answer finished"));
+  }
+
+  #[tokio::test]
+  async fn test_finish_reason_without_done_ends_cleanly() {
+    use crate::mock::{scripted_transport, sse_content};
+    let finish = "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n";
+    let events = vec![
+      (Duration::ZERO, sse_content("ok")),
+      (Duration::ZERO, finish.to_string()),
+    ];
+    let items = collect(decode_sse(scripted_transport(events), None, None)).await;
+    assert!(items.iter().all(|i| i.is_ok()));
+    assert_eq!(
+      items[1].as_ref().unwrap().finish_reason.as_deref(),
+      Some("stop")
+    );
+  }
+
+  /// 依次解码 SSE 行，返回每个增量的 finish_reason
+  async fn finish_reasons(lines: &[&str]) -> Vec<Option<String>> {
+    use crate::mock::scripted_transport;
+    let events = lines
+      .iter()
+      .map(|line| (Duration::ZERO, format!("{}\n\n", line)))
+      .collect();
+    collect(decode_sse(scripted_transport(events), None, None))
+      .await
+      .into_iter()
+      .map(|item| item.unwrap().finish_reason)
+      .collect()
+  }
+
+  #[tokio::test]
+  async fn test_done_carries_no_finish_reason() {
+    let stop = [
+      r#"data: {"choices":[{"delta":{"content":"答"},"finish_reason":null}]}"#,
+      r#"data: {"choices":[{"delta":{},"finish_reason":"stop"}]}"#,
+      "data: [DONE]",
+    ];
+    assert_eq!(
+      finish_reasons(&stop).await,
+      [None, Some("stop".to_string())]
+    );
+
+    let length = [
+      r#"data: {"choices":[{"delta":{"content":"很长"},"finish_reason":null}]}"#,
+      r#"data: {"choices":[{"delta":{"content":"的"},"finish_reason":"length"}]}"#,
+      "data: [DONE]",
+    ];
+    assert_eq!(
+      finish_reasons(&length).await,
+      [None, Some("length".to_string())]
+    );
+
+    // [DONE] 之后的内容被忽略；只有 [DONE] 时没有任何增量
+    let after = [
+      r#"data: {"choices":[{"delta":{},"finish_reason":"stop"}]}"#,
+      "data: [DONE]",
+      r#"data: {"choices":[{"delta":{"content":"多余"},"finish_reason":"length"}]}"#,
+    ];
+    assert_eq!(finish_reasons(&after).await, [Some("stop".to_string())]);
+    assert!(finish_reasons(&["data: [DONE]"]).await.is_empty());
+  }
+
+  /// 只应答一次的 HTTP 服务，返回 base_url
+  async fn serve_once(headers: &'static str, body: &'static str) -> String {
+    serve_status("200 OK", headers, body).await
+  }
+
+  async fn serve_status(status: &'static str, headers: &'static str, body: &'static str) -> String {
+    serve_sequence(vec![(status, headers, body)]).await.0
+  }
+
+  /// 按顺序应答每个连接（状态行, 额外响应头, 响应体），返回 base_url 和已应答的请求数
+  async fn serve_sequence(
+    responses: Vec<(&'static str, &'static str, &'static str)>,
+  ) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let served = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = served.clone();
+    tokio::spawn(async move {
+      for (status, headers, body) in responses {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        // 读到请求头结束和声明长度的请求体为止
+        let mut request = vec![];
+        let mut buf = [0; 4096];
+        loop {
+          let n = socket.read(&mut buf).await.unwrap();
+          request.extend_from_slice(&buf[..n]);
+          let text = String::from_utf8_lossy(&request);
+          if let Some(end) = text.find("\r\n\r\n") {
+            let length = text
+              .lines()
+              .find_map(|l| {
+                l.to_ascii_lowercase()
+                  .strip_prefix("content-length:")
+                  .map(|v| v.trim().parse::<usize>().unwrap())
+              })
+              .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+              break;
+            }
+          }
+          if n == 0 {
+            break;
+          }
+        }
+        let response = format!(
+          "HTTP/1.1 {}\r\ncontent-type: application/json\r\n{}content-length: {}\r\nconnection: close\r\n\r\n{}",
+          status,
+          headers,
+          body.len(),
+          body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+      }
+    });
+    (format!("http://{}", addr), served)
+  }
+
+  #[tokio::test]
+  async fn test_unanswered_request_times_out() {
+    // 接受连接后一直不应答，模拟挂起的服务方
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      let mut held = vec![];
+      while let Ok((socket, _)) = listener.accept().await {
+        held.push(socket);
+      }
+    });
+    let client = ApiClient::new("test_key".to_string())
+      .with_base_url(&format!("http://{}", addr))
+      .with_timeouts(Timeouts {
+        connect: Duration::from_secs(5),
+        read: Duration::from_millis(200),
+      });
+    let err = client
+      .call_api("deepseek-chat", "hi", None, None, false)
+      .await
+      .unwrap_err();
+    assert_eq!(
+      err.downcast_ref::<TimeoutError>().map(|t| t.kind),
+      Some(TimeoutKind::Response)
+    );
+    assert!(
+      format!("{:#}", err).contains("no response within 0.2s (--timeout 0.2s)"),
+      "{:#}",
+      err
+    );
+    // 流式请求等待响应头时同样受限
+    let stream = client
+      .call_api_with_history_stream("deepseek-chat", vec![], None, None, false)
+      .await;
+    assert!(matches!(stream, Err(e) if e.is::<TimeoutError>()));
+  }
+
+  fn fast_retry(retries: u32) -> RetryPolicy {
+    RetryPolicy {
+      retries,
+      base: Duration::from_millis(1),
+      max: Duration::from_millis(5),
+    }
+  }
+
+  #[tokio::test]
+  async fn test_transient_failures_are_retried() {
+    let ok = r#"{"choices":[{"message":{"role":"assistant","content":"hi"}}]}"#;
+    let (base_url, served) = serve_sequence(vec![
+      ("503 Service Unavailable", "", "busy"),
+      ("502 Bad Gateway", "", "bad gateway"),
+      ("200 OK", "", ok),
+    ])
+    .await;
+    let client = ApiClient::new("test_key".to_string())
+      .with_base_url(&base_url)
+      .with_retry(fast_retry(2));
+    let response = client
+      .call_api("deepseek-chat", "hi", None, None, false)
+      .await
+      .unwrap();
+    assert_eq!(
+      serde_json::to_value(&response.choices[0].message).unwrap()["content"],
+      "hi"
+    );
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+    // 次数用完后返回最后一次的错误
+    let (base_url, served) = serve_sequence(vec![
+      ("500 Internal Server Error", "", "boom"),
+      ("500 Internal Server Error", "", "boom again"),
+    ])
+    .await;
+    let client = client.with_base_url(&base_url).with_retry(fast_retry(1));
+    let err = client
+      .call_api("deepseek-chat", "hi", None, None, false)
+      .await
+      .unwrap_err();
+    assert_eq!(
+      err.downcast_ref::<ApiError>().unwrap().body(),
+      Some("boom again")
+    );
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+  }
+
+  #[tokio::test]
+  async fn test_client_errors_are_not_retried() {
+    let (base_url, served) = serve_sequence(vec![
+      ("401 Unauthorized", "", "invalid api key"),
+      ("200 OK", "", "{}"),
+    ])
+    .await;
+    let client = ApiClient::new("test_key".to_string())
+      .with_base_url(&base_url)
+      .with_retry(fast_retry(3));
+    let err = client
+      .call_api_with_history_stream("deepseek-chat", vec![], None, None, false)
+      .await
+      .err()
+      .unwrap();
+    let api = err.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(api.body(), Some("invalid api key"));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn test_stream_retries_before_the_body() {
+    let body = "data: {\"choices\":[{\"delta\":{\"content\":\"答\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n";
+    let (base_url, served) = serve_sequence(vec![
+      ("503 Service Unavailable", "", "busy"),
+      ("500 Internal Server Error", "", "boom"),
+      ("200 OK", "", body),
+    ])
+    .await;
+    let client = ApiClient::new("test_key".to_string())
+      .with_base_url(&base_url)
+      .with_retry(fast_retry(2));
+    let stream = client
+      .call_api_with_history_stream("deepseek-chat", vec![], None, None, false)
+      .await
+      .unwrap();
+    let chunks: Vec<_> = collect(stream)
+      .await
+      .into_iter()
+      .map(Result::unwrap)
+      .collect();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].content, "答");
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+  }
+
+  #[tokio::test]
+  async fn test_response_headers_feed_limiter() {
+    let base_url = serve_once(
+      "x-ratelimit-remaining-requests: 0\r\nx-ratelimit-reset-requests: 20s\r\nx-ratelimit-remaining-tokens: 9000\r\nauthorization: Bearer test_key\r\n",
+      r#"{"choices":[{"message":{"role":"assistant","content":"hi"}}]}"#,
+    )
+    .await;
+    let client = ApiClient::new("test_key".to_string()).with_base_url(&base_url);
+    assert!(client.last_headers().is_none());
+    let reply = client
+      .complete("deepseek-chat", vec![], None, None)
+      .await
+      .unwrap();
+    assert_eq!(reply, "hi");
+
+    // 克隆的客户端共享最近的响应头；代理回显的 Authorization 不保存
+    let headers = client.clone().last_headers().unwrap();
+    assert_eq!(headers.status, 200);
+    assert_eq!(headers.get("x-ratelimit-remaining-tokens"), Some("9000"));
+    let rendered = headers.render();
+    assert!(rendered.starts_with("HTTP 200\n"));
+    assert!(rendered.contains("x-ratelimit-remaining-requests: 0"));
+    assert!(!rendered.to_lowercase().contains("authorization"));
+    assert!(!rendered.contains("test_key"));
+    // 额度用完，下一个请求要等到重置之后
+    let delay = client.limiter.delay(Instant::now()).unwrap();
+    assert!(delay > Duration::from_secs(19) && delay <= Duration::from_secs(20));
+  }
+
+  #[tokio::test]
+  async fn test_error_status_is_classified() {
+    let body = r#"{"error":{"message":"Authentication Fails","type":"authentication_error"}}"#;
+    let base_url = serve_status("401 Unauthorized", "", body).await;
+    let client = ApiClient::new("bad_key".to_string()).with_base_url(&base_url);
+    let err = client
+      .stream("deepseek-chat", vec![], None, None)
+      .await
+      .err()
+      .unwrap();
+    let api_error = err.downcast_ref::<ApiError>().unwrap();
+    assert!(matches!(api_error, ApiError::Unauthorized { .. }));
+    assert_eq!(
+      err.to_string(),
+      format!("API Error 401 Unauthorized: {}", body)
+    );
+    let error = |status| {
+      ApiError::from_response(
+        reqwest::StatusCode::from_u16(status).unwrap(),
+        String::new(),
+        None,
+      )
+    };
+    assert!(matches!(
+      error(422),
+      ApiError::InvalidRequest { message, .. } if message.is_empty()
+    ));
+    assert!(matches!(error(429), ApiError::RateLimited { .. }));
+    assert_eq!(error(429).status().unwrap().as_u16(), 429);
+    assert!(matches!(error(503), ApiError::Server { .. }));
+    assert!(matches!(error(418), ApiError::Other { .. }));
+    assert_eq!(error(418).hint(), None);
+  }
+
+  /// 测试数据中的错误响应：状态行、响应头、空行、响应体
+  fn error_fixture(text: &str) -> ApiError {
+    let (head, body) = text.split_once("\n\n").unwrap();
+    let mut lines = head.lines();
+    let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+    ApiError::from_response(
+      reqwest::StatusCode::from_u16(status.parse().unwrap()).unwrap(),
+      body.to_string(),
+      lines
+        .find_map(|l| l.strip_prefix("retry-after: "))
+        .and_then(parse_reset),
+    )
+  }
+
+  #[test]
+  fn test_captured_error_bodies_are_classified() {
+    let context = |e: &ApiError| matches!(e, ApiError::ContextLengthExceeded { .. });
+    type Check = fn(&ApiError) -> bool;
+    let cases: [(&str, Check); 7] = [
+      (
+        include_str!("testdata/errors/deepseek_context.http"),
+        context,
+      ),
+      (
+        include_str!("testdata/errors/dashscope_context.http"),
+        context,
+      ),
+      (include_str!("testdata/errors/openai_context.http"), context),
+      (include_str!("testdata/errors/rate_limited.http"), |e| {
+        matches!(e, ApiError::RateLimited { .. })
+      }),
+      (include_str!("testdata/errors/unauthorized.http"), |e| {
+        matches!(e, ApiError::Unauthorized { .. })
+      }),
+      (
+        include_str!("testdata/errors/model_not_exist.http"),
+        |e| matches!(e, ApiError::InvalidRequest { message, .. } if message == "Model Not Exist"),
+      ),
+      (include_str!("testdata/errors/gateway_busy.http"), |e| {
+        matches!(e, ApiError::Server { .. })
+      }),
+    ];
+    for (text, expected) in cases {
+      assert!(expected(&error_fixture(text)), "{}", text);
+    }
+
+    let error = error_fixture(include_str!("testdata/errors/openai_context.http"));
+    assert_eq!(
+      error_field(error.body().unwrap(), "code").as_deref(),
+      Some("context_length_exceeded")
+    );
+    assert!(error.hint().unwrap().contains("\\c"));
+    let error = error_fixture(include_str!("testdata/errors/rate_limited.http"));
+    assert!(matches!(
+      error,
+      ApiError::RateLimited {
+        retry_after: Some(_),
+        ..
+      }
+    ));
+    assert_eq!(
+      error.hint().as_deref(),
+      Some("已达到速率限制，请稍后重试（服务方要求等待 2s）")
+    );
+    // DashScope 原生接口的字段在顶层；不是 JSON 的响应体没有说明
+    let native = r#"{"code":"Throttling","message":"Requests rate limit exceeded"}"#;
+    assert_eq!(error_field(native, "code").as_deref(), Some("Throttling"));
+    let busy = error_fixture(include_str!("testdata/errors/gateway_busy.http"));
+    assert_eq!(error_field(busy.body().unwrap(), "message"), None);
+  }
+
+  #[tokio::test]
+  async fn test_hints_for_rate_limits_and_unreachable_servers() {
+    let body = r#"{"error":{"message":"Rate Limit Reached","type":"rate_limit_error"}}"#;
+    let base_url = serve_status("429 Too Many Requests", "retry-after: 1.5\r\n", body).await;
+    let client = ApiClient::new("test_key".to_string())
+      .with_base_url(&base_url)
+      .with_retry(fast_retry(0));
+    let err = client
+      .complete("deepseek-chat", vec![], None, None)
+      .await
+      .unwrap_err();
+    let api_error = err.downcast_ref::<ApiError>().unwrap();
+    assert!(matches!(
+      api_error,
+      ApiError::RateLimited {
+        retry_after: Some(wait),
+        ..
+      } if *wait == Duration::from_millis(1500)
+    ));
+    assert!(hint(&err).unwrap().contains("1.5s"));
+
+    // 端口上没有服务在监听
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let client = client.with_base_url(&format!("http://{}", addr));
+    let err = client
+      .complete("deepseek-chat", vec![], None, None)
+      .await
+      .unwrap_err();
+    assert!(
+      matches!(err.downcast_ref::<ApiError>(), Some(ApiError::Network(_))),
+      "{:#}",
+      err
+    );
+    assert!(hint(&err).unwrap().contains("--base-url"), "{:#}", err);
+    assert_eq!(hint(&anyhow::anyhow!("other")), None);
+  }
 }
@@ -0,0 +1,873 @@
+use crate::api::{Content, ImageContent, ImageUrl, Message, TextContent};
+use crate::cite;
+use crate::tabular;
+use crate::tokens;
+use crate::truncate::{self, TruncateMode};
+use anyhow::{Context, Result};
+use base64::Engine;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+/// 历史中的附件引用，请求发出前由 AttachmentStore::expand 展开
+static REFERENCE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"\[\[attachment:([0-9a-f]{64})\]\]").unwrap());
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+  pub name: String,
+  /// 附加时的绝对路径，用于引用的超链接
+  pub path: PathBuf,
+  pub mime: String,
+  /// 文本文件为（可能已截断的）内容，图片为 base64
+  pub data: String,
+  /// 文本内容带行号
+  pub numbered: bool,
+  /// 表格文件，内容是概要而不是全文
+  pub summarized: bool,
+  /// 附加顺序，同名文件取最近的一个
+  seq: usize,
+}
+
+impl Attachment {
+  pub fn is_image(&self) -> bool {
+    self.mime.starts_with("image/")
+  }
+
+  /// 图片的原始字节；不是图片时返回 None
+  pub fn image_bytes(&self) -> Option<Vec<u8>> {
+    match self.is_image() {
+      true => base64::engine::general_purpose::STANDARD
+        .decode(&self.data)
+        .ok(),
+      false => None,
+    }
+  }
+}
+
+/// 附件文件的默认大小上限
+pub const DEFAULT_MAX_FILE_BYTES: u64 = 16 * 1024 * 1024;
+/// 只检查开头这么多字节来判断是否为文本
+const SNIFF_BYTES: usize = 8 * 1024;
+/// 开头部分无效 UTF-8 超过这个比例时视为二进制
+const INVALID_UTF8_PERCENT: usize = 5;
+
+/// 读取附件时的限制
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+  pub max_bytes: u64,
+  /// 检测为二进制时仍按文本读取（无效字节替换为 U+FFFD）
+  pub force_text: bool,
+}
+
+impl Default for ReadLimits {
+  fn default() -> Self {
+    Self {
+      max_bytes: DEFAULT_MAX_FILE_BYTES,
+      force_text: false,
+    }
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sniffed {
+  Text,
+  Utf16 {
+    big_endian: bool,
+  },
+  /// 判断依据
+  Binary(String),
+}
+
+/// 不看扩展名，根据开头的内容判断编码
+pub fn sniff(head: &[u8]) -> Sniffed {
+  match head {
+    [0xFF, 0xFE, ..] => return Sniffed::Utf16 { big_endian: false },
+    [0xFE, 0xFF, ..] => return Sniffed::Utf16 { big_endian: true },
+    _ => {}
+  }
+  if let Some(offset) = head.iter().position(|&b| b == 0) {
+    // 没有 BOM 的 UTF-16：ASCII 字符的高字节为 0，集中在奇数或偶数位置
+    let zeros_at = |parity: usize| {
+      head
+        .iter()
+        .skip(parity)
+        .step_by(2)
+        .filter(|&&b| b == 0)
+        .count()
+    };
+    let half = head.len() / 2;
+    if half > 0 && zeros_at(1) * 10 >= half * 9 && zeros_at(0) == 0 {
+      return Sniffed::Utf16 { big_endian: false };
+    }
+    if half > 0 && zeros_at(0) * 10 >= half * 9 && zeros_at(1) == 0 {
+      return Sniffed::Utf16 { big_endian: true };
+    }
+    return Sniffed::Binary(format!("NUL byte at offset {}", offset));
+  }
+  let mut invalid = 0;
+  let mut rest = head;
+  while let Err(e) = std::str::from_utf8(rest) {
+    // 末尾被截断的多字节字符不算无效
+    let Some(len) = e.error_len() else {
+      break;
+    };
+    invalid += len;
+    rest = &rest[e.valid_up_to() + len..];
+  }
+  if invalid * 100 > head.len() * INVALID_UTF8_PERCENT {
+    return Sniffed::Binary(format!(
+      "{}% of the first {} bytes is not valid UTF-8",
+      invalid * 100 / head.len(),
+      head.len()
+    ));
+  }
+  Sniffed::Text
+}
+
+fn decode_utf16(bytes: &[u8], big_endian: bool) -> String {
+  let units = bytes.chunks_exact(2).map(|pair| {
+    let pair = [pair[0], pair[1]];
+    if big_endian {
+      u16::from_be_bytes(pair)
+    } else {
+      u16::from_le_bytes(pair)
+    }
+  });
+  let text: String = char::decode_utf16(units)
+    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    .collect();
+  text
+    .strip_prefix('\u{feff}')
+    .map(str::to_string)
+    .unwrap_or(text)
+}
+
+/// 读取附件：先按文件元数据检查大小，读取时也不超过上限；
+/// 非图片文件按内容检测编码，UTF-16 转成 UTF-8，二进制文件报错
+pub fn read(path: &Path, limits: ReadLimits) -> Result<Vec<u8>> {
+  let file = crate::sandbox::open(path).context(format!("Failed to read file: {:?}", path))?;
+  let too_large = |size: u64| {
+    anyhow::anyhow!(
+      "{:?} is {} bytes, over the {} byte limit (--max-file-size)",
+      path,
+      size,
+      limits.max_bytes
+    )
+  };
+  let size = file.metadata()?.len();
+  if size > limits.max_bytes {
+    return Err(too_large(size));
+  }
+  // 元数据可能不准（管道、正在增长的日志），读取时再限制一次
+  let mut bytes = vec![];
+  file
+    .take(limits.max_bytes + 1)
+    .read_to_end(&mut bytes)
+    .context(format!("Failed to read file: {:?}", path))?;
+  if bytes.len() as u64 > limits.max_bytes {
+    return Err(too_large(bytes.len() as u64));
+  }
+  let is_image = mime_guess::from_path(path)
+    .first()
+    .is_some_and(|m| m.type_() == mime_guess::mime::IMAGE);
+  if is_image {
+    return Ok(bytes);
+  }
+  match sniff(&bytes[..bytes.len().min(SNIFF_BYTES)]) {
+    Sniffed::Text => match String::from_utf8(bytes) {
+      Ok(text) => Ok(text.into_bytes()),
+      Err(e) => Ok(
+        String::from_utf8_lossy(e.as_bytes())
+          .into_owned()
+          .into_bytes(),
+      ),
+    },
+    Sniffed::Utf16 { big_endian } => Ok(decode_utf16(&bytes, big_endian).into_bytes()),
+    Sniffed::Binary(_) if limits.force_text => {
+      Ok(String::from_utf8_lossy(&bytes).into_owned().into_bytes())
+    }
+    Sniffed::Binary(reason) => anyhow::bail!(
+      "{:?} looks like a binary file ({}); use --force-text to send it anyway",
+      path,
+      reason
+    ),
+  }
+}
+
+/// 读取并编码附件：图片转 base64，文本按配置截断
+pub fn encode(
+  path: &Path,
+  bytes: Vec<u8>,
+  truncation: Option<(usize, TruncateMode)>,
+) -> Result<(String, String)> {
+  let mime = mime_guess::from_path(path)
+    .first_or_octet_stream()
+    .to_string();
+  if mime.starts_with("image/") {
+    return Ok((
+      mime,
+      base64::engine::general_purpose::STANDARD.encode(bytes),
+    ));
+  }
+  let text = String::from_utf8(bytes).context(format!("File is not valid UTF-8: {:?}", path))?;
+  let text = match truncation {
+    Some((budget, mode)) => truncate::truncate(&text, budget, mode),
+    None => text,
+  };
+  Ok((mime, text))
+}
+
+/// `\attach --sticky` 附加的文件：每个请求都带上，直到 `\detach`
+#[derive(Debug)]
+struct Sticky {
+  path: PathBuf,
+  hash: String,
+  modified: Option<SystemTime>,
+}
+
+/// 持续附加的文件放在这条 system 消息里，紧跟在 system prompt 之后
+pub const STICKY_HEADER: &str = "以下文件在本会话中持续附加，回答时可以参考：";
+
+/// 按内容哈希保存附件。同一内容只编码一次，历史里只保存引用，
+/// 构造请求时只在最后一次引用处放入完整内容。
+pub struct AttachmentStore {
+  items: HashMap<String, Attachment>,
+  truncation: Option<(usize, TruncateMode)>,
+  limits: ReadLimits,
+  sticky: Vec<Sticky>,
+  /// 表格文件概要的 token 预算；None 时按普通文本完整附加
+  table_budget: Option<usize>,
+}
+
+impl Default for AttachmentStore {
+  fn default() -> Self {
+    Self::new(None)
+  }
+}
+
+impl AttachmentStore {
+  pub fn new(truncation: Option<(usize, TruncateMode)>) -> Self {
+    Self {
+      items: HashMap::new(),
+      truncation,
+      limits: ReadLimits::default(),
+      sticky: vec![],
+      table_budget: Some(tabular::DEFAULT_BUDGET),
+    }
+  }
+
+  pub fn with_table_budget(mut self, budget: Option<usize>) -> Self {
+    self.table_budget = budget;
+    self
+  }
+
+  pub fn with_read_limits(mut self, limits: ReadLimits) -> Self {
+    self.limits = limits;
+    self
+  }
+
+  /// 返回附件哈希，以及内容是否与之前附加过的文件相同
+  pub fn attach(&mut self, path: &Path) -> Result<(String, bool)> {
+    self.attach_named(path, None)
+  }
+
+  /// `name` 为请求中显示的文件名，默认取路径的最后一段；附加目录时用相对路径区分同名文件
+  pub fn attach_named(&mut self, path: &Path, name: Option<String>) -> Result<(String, bool)> {
+    let bytes = read(path, self.limits)?;
+    let hash = format!("{:x}", Sha256::digest(&bytes));
+    if self.items.contains_key(&hash) {
+      return Ok((hash, true));
+    }
+    // 先加行号再截断，保留下来的行号与原文件一致
+    let is_image = mime_guess::from_path(path)
+      .first()
+      .is_some_and(|m| m.type_() == mime_guess::mime::IMAGE);
+    // 表格文件只发送概要，不加行号也不截断
+    let summary = self
+      .table_budget
+      .filter(|_| !is_image)
+      .and_then(|budget| tabular::summarize(path, &bytes, budget));
+    let numbered = !is_image && summary.is_none() && cite::should_number(path, bytes.len());
+    let bytes = match numbered {
+      true => {
+        let text =
+          String::from_utf8(bytes).context(format!("File is not valid UTF-8: {:?}", path))?;
+        cite::number_lines(&text).into_bytes()
+      }
+      false => bytes,
+    };
+    let summarized = summary.is_some();
+    let (mime, data) = match summary {
+      Some(summary) => (
+        mime_guess::from_path(path)
+          .first_or_octet_stream()
+          .to_string(),
+        summary,
+      ),
+      None => encode(path, bytes, self.truncation)?,
+    };
+    let name = name.unwrap_or_else(|| {
+      path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+    });
+    let attachment = Attachment {
+      name,
+      path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+      mime,
+      data,
+      numbered,
+      summarized,
+      seq: self.items.len(),
+    };
+    self.items.insert(hash.clone(), attachment);
+    Ok((hash, false))
+  }
+
+  /// 持续附加文件，再次附加同一路径时更新内容
+  pub fn attach_sticky(&mut self, path: &Path) -> Result<String> {
+    let (hash, _) = self.attach(path)?;
+    if self.items[&hash].is_image() {
+      anyhow::bail!("Images cannot be attached with --sticky: {:?}", path);
+    }
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+    self.sticky.retain(|s| s.path != path);
+    self.sticky.push(Sticky {
+      path,
+      hash: hash.clone(),
+      modified,
+    });
+    Ok(hash)
+  }
+
+  /// 按路径或路径后缀取消持续附加，返回被取消的文件
+  pub fn detach(&mut self, file: &str) -> Option<PathBuf> {
+    let file = Path::new(file);
+    let absolute = std::path::absolute(file).ok();
+    let i = self
+      .sticky
+      .iter()
+      .rposition(|s| Some(&s.path) == absolute.as_ref() || s.path.ends_with(file))?;
+    Some(self.sticky.remove(i).path)
+  }
+
+  /// 取消全部持续附加，返回取消的文件数
+  pub fn clear_sticky(&mut self) -> usize {
+    std::mem::take(&mut self.sticky).len()
+  }
+
+  /// 持续附加的文件及其内容的 token 估算
+  pub fn sticky(&self) -> Vec<(&Attachment, usize)> {
+    self
+      .sticky
+      .iter()
+      .filter_map(|s| self.items.get(&s.hash))
+      .map(|a| (a, tokens::count(&a.data)))
+      .collect()
+  }
+
+  /// 持续附加的文件在磁盘上修改过时重新读取，返回给用户的提示
+  pub fn refresh_sticky(&mut self) -> Vec<String> {
+    let mut notices = vec![];
+    for i in 0..self.sticky.len() {
+      let path = self.sticky[i].path.clone();
+      let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+      if modified == self.sticky[i].modified {
+        continue;
+      }
+      self.sticky[i].modified = modified;
+      match (modified, self.attach(&path)) {
+        (Some(_), Ok((hash, _))) => {
+          self.sticky[i].hash = hash;
+          notices.push(format!("[信息] {} 已修改，已重新读取", path.display()));
+        }
+        (_, Err(e)) => notices.push(format!(
+          "[警告] 无法重新读取 {}: {:#}，继续使用之前的内容",
+          path.display(),
+          e
+        )),
+        (None, Ok(_)) => {}
+      }
+    }
+    notices
+  }
+
+  /// 把持续附加的文件作为一条 system 消息插在开头的 system prompt 之后
+  fn with_sticky(&self, messages: &[Message]) -> Vec<Message> {
+    let mut messages = messages.to_vec();
+    if self.sticky.is_empty() {
+      return messages;
+    }
+    let references: Vec<_> = self
+      .sticky
+      .iter()
+      .map(|s| Self::reference(&s.hash))
+      .collect();
+    let at = match messages.first() {
+      Some(Message::Simple { role, .. }) if role == "system" => 1,
+      _ => 0,
+    };
+    messages.insert(
+      at,
+      Message::Simple {
+        role: "system".to_string(),
+        content: format!("{}\n\n{}", STICKY_HEADER, references.join("\n\n")),
+        name: None,
+      },
+    );
+    messages
+  }
+
+  /// 按回答中引用的文件名查找附件：路径后缀或文件名匹配，多个时取最近附加的
+  pub fn find(&self, file: &str) -> Option<&Attachment> {
+    let file = Path::new(file);
+    self
+      .items
+      .values()
+      .filter(|a| !a.is_image() && a.path.ends_with(file))
+      .max_by_key(|a| a.seq)
+  }
+
+  /// 是否有带行号的附件，决定 system prompt 是否要求引用行号
+  pub fn has_numbered(&self) -> bool {
+    self.items.values().any(|a| a.numbered)
+  }
+
+  pub fn get(&self, hash: &str) -> Option<&Attachment> {
+    self.items.get(hash)
+  }
+
+  /// 文本中引用的图片附件，按出现顺序，重复的只算一次
+  pub fn images_in(&self, text: &str) -> Vec<&Attachment> {
+    let mut images: Vec<&Attachment> = vec![];
+    for m in REFERENCE.captures_iter(text) {
+      if let Some(item) = self.items.get(m.get(1).unwrap().as_str())
+        && item.is_image()
+        && !images.iter().any(|i| std::ptr::eq(*i, item))
+      {
+        images.push(item);
+      }
+    }
+    images
+  }
+
+  pub fn reference(hash: &str) -> String {
+    format!("[[attachment:{}]]", hash)
+  }
+
+  /// 展开消息中的附件引用：每个附件只在最后一次出现处放入完整内容，
+  /// 更早的引用替换为指向下方的占位说明
+  pub fn expand(&self, messages: &[Message]) -> Vec<Message> {
+    let messages = &self.with_sticky(messages);
+    let mut last: HashMap<&str, (usize, usize)> = HashMap::new();
+    for (i, message) in messages.iter().enumerate() {
+      if let Message::Simple { content, .. } = message {
+        for (j, m) in REFERENCE.captures_iter(content).enumerate() {
+          last.insert(m.get(1).unwrap().as_str(), (i, j));
+        }
+      }
+    }
+    if last.is_empty() {
+      return messages.to_vec();
+    }
+    messages
+      .iter()
+      .enumerate()
+      .map(|(i, message)| match message {
+        Message::Simple {
+          role,
+          content,
+          name,
+        } if REFERENCE.is_match(content) => self.expand_one(role, content, name, |hash, j| {
+          last.get(hash) == Some(&(i, j))
+        }),
+        other => other.clone(),
+      })
+      .collect()
+  }
+
+  fn expand_one(
+    &self,
+    role: &str,
+    content: &str,
+    name: &Option<String>,
+    is_last: impl Fn(&str, usize) -> bool,
+  ) -> Message {
+    let mut images = vec![];
+    let mut j = 0;
+    let text = REFERENCE.replace_all(content, |caps: &regex::Captures| {
+      let hash = &caps[1];
+      let last = is_last(hash, j);
+      j += 1;
+      let Some(item) = self.get(hash) else {
+        return format!("[附件 {} 已不可用]", &hash[..8]);
+      };
+      if !last {
+        return format!("[同一文件 {}，内容见下方]", item.name);
+      }
+      if item.is_image() {
+        images.push(Content::Image(ImageContent {
+          content_type: "image_url".to_string(),
+          image_url: ImageUrl {
+            url: format!("data:{};base64,{}", item.mime, item.data),
+          },
+        }));
+        return format!("[图片 {}]", item.name);
+      }
+      format!("文件 {} 内容:\n{}", item.name, item.data)
+    });
+    if images.is_empty() {
+      return Message::Simple {
+        role: role.to_string(),
+        content: text.into_owned(),
+        name: name.clone(),
+      };
+    }
+    let mut parts = vec![Content::Text(TextContent {
+      content_type: "text".to_string(),
+      text: text.into_owned(),
+    })];
+    parts.extend(images);
+    Message::MultiModal {
+      role: role.to_string(),
+      content: parts,
+      name: name.clone(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use crate::estimate_messages_tokens;
+  use std::fs;
+
+  fn user(content: String) -> Message {
+    Message::Simple {
+      role: "user".to_string(),
+      content,
+      name: None,
+    }
+  }
+
+  fn text_of(message: &Message) -> &str {
+    match message {
+      Message::Simple { content, .. } | Message::Tool { content, .. } => content,
+      Message::MultiModal { content, .. } => match &content[0] {
+        Content::Text(t) => &t.text,
+        Content::Image(_) => "",
+      },
+    }
+  }
+
+  #[test]
+  fn test_read_sniffs_content_not_extension() {
+    let dir = temp_dir("attachment-sniff");
+    let limits = ReadLimits::default();
+
+    let fake = dir.join("notes.txt");
+    fs::write(&fake, b"ELF\x7f\x00\x01\x02 binary").unwrap();
+    let err = read(&fake, limits).unwrap_err().to_string();
+    assert!(err.contains("NUL byte at offset 4"), "{}", err);
+    assert!(err.contains("--force-text"));
+    let forced = read(
+      &fake,
+      ReadLimits {
+        force_text: true,
+        ..limits
+      },
+    )
+    .unwrap();
+    assert!(String::from_utf8(forced).unwrap().ends_with(" binary"));
+
+    // 带 BOM 和不带 BOM 的 UTF-16 都转成 UTF-8
+    let text = "日志 line 1\nline 2";
+    let le: Vec<u8> = [0xFF, 0xFE]
+      .into_iter()
+      .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+      .collect();
+    let utf16 = dir.join("windows.log");
+    fs::write(&utf16, &le).unwrap();
+    assert_eq!(read(&utf16, limits).unwrap(), text.as_bytes());
+    let be: Vec<u8> = "plain ascii"
+      .encode_utf16()
+      .flat_map(u16::to_be_bytes)
+      .collect();
+    fs::write(&utf16, &be).unwrap();
+    assert_eq!(read(&utf16, limits).unwrap(), b"plain ascii");
+
+    let latin1 = dir.join("latin1.txt");
+    fs::write(&latin1, b"caf\xe9 ".repeat(10)).unwrap();
+    assert!(
+      read(&latin1, limits)
+        .unwrap_err()
+        .to_string()
+        .contains("not valid UTF-8")
+    );
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_read_rejects_oversized_files() {
+    let dir = temp_dir("attachment-size");
+    let path = dir.join("big.log");
+    fs::write(&path, "x".repeat(2048)).unwrap();
+    let limits = ReadLimits {
+      max_bytes: 1024,
+      force_text: false,
+    };
+    let err = read(&path, limits).unwrap_err().to_string();
+    assert!(
+      err.contains("2048 bytes, over the 1024 byte limit"),
+      "{}",
+      err
+    );
+    assert!(
+      AttachmentStore::default()
+        .with_read_limits(limits)
+        .attach(&path)
+        .is_err()
+    );
+    assert_eq!(read(&path, ReadLimits::default()).unwrap().len(), 2048);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_same_file_attached_three_times() {
+    let dir = temp_dir("attach-dedup");
+    let path = dir.join("config.yaml");
+    let body = "key: value\n".repeat(2000);
+    fs::write(&path, &body).unwrap();
+
+    let mut store = AttachmentStore::default();
+    let mut history = vec![];
+    for (i, question) in ["这是什么配置？", "有哪些键？", "怎么改？"]
+      .iter()
+      .enumerate()
+    {
+      let (hash, reused) = store.attach(&path).unwrap();
+      assert_eq!(reused, i > 0);
+      history.push(user(format!(
+        "{}\n\n{}",
+        question,
+        AttachmentStore::reference(&hash)
+      )));
+      history.push(Message::Simple {
+        role: "assistant".to_string(),
+        content: "好的".to_string(),
+        name: None,
+      });
+    }
+    assert_eq!(store.items.len(), 1);
+
+    let expanded = store.expand(&history);
+    assert_eq!(expanded.len(), history.len());
+    for earlier in [&expanded[0], &expanded[2]] {
+      assert!(text_of(earlier).ends_with("[同一文件 config.yaml，内容见下方]"));
+    }
+    assert!(
+      text_of(&expanded[4]).starts_with("怎么改？\n\n文件 config.yaml 内容:\n   1 | key: value")
+    );
+    assert_eq!(text_of(&expanded[4]).matches("key: value").count(), 2000);
+
+    // token 数只计入一份文件内容
+    let once = crate::tokens::count(&cite::number_lines(&body));
+    let total = estimate_messages_tokens(&expanded);
+    assert!(total > once && total < once + 100, "{} vs {}", total, once);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_changed_content_is_a_new_attachment() {
+    let dir = temp_dir("attach-changed");
+    let path = dir.join("notes.txt");
+    let mut store = AttachmentStore::default();
+    fs::write(&path, "v1").unwrap();
+    let (first, _) = store.attach(&path).unwrap();
+    fs::write(&path, "v2").unwrap();
+    let (second, reused) = store.attach(&path).unwrap();
+    assert_ne!(first, second);
+    assert!(!reused);
+
+    let history = vec![
+      user(AttachmentStore::reference(&first)),
+      user(AttachmentStore::reference(&second)),
+    ];
+    let expanded = store.expand(&history);
+    assert_eq!(text_of(&expanded[0]), "文件 notes.txt 内容:\n   1 | v1\n");
+    assert_eq!(text_of(&expanded[1]), "文件 notes.txt 内容:\n   1 | v2\n");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_code_attachment_is_numbered_and_findable() {
+    let dir = temp_dir("attach-numbered");
+    fs::create_dir_all(dir.join("src")).unwrap();
+    let path = dir.join("src").join("api.rs");
+    fs::write(&path, "use std::io;\nfn main() {}\n").unwrap();
+    let long = dir.join("book.txt");
+    fs::write(&long, "word ".repeat(cite::NUMBER_MAX_BYTES)).unwrap();
+
+    let mut store = AttachmentStore::default();
+    assert!(!store.has_numbered());
+    store.attach(&long).unwrap();
+    assert!(!store.has_numbered());
+    let (hash, _) = store.attach(&path).unwrap();
+    let item = store.get(&hash).unwrap();
+    assert!(item.numbered);
+    assert_eq!(item.data, "   1 | use std::io;\n   2 | fn main() {}\n");
+    assert!(store.has_numbered());
+
+    assert_eq!(store.find("src/api.rs").unwrap().path, path);
+    assert_eq!(store.find("api.rs").unwrap().path, path);
+    assert!(store.find("other/api.rs").is_none());
+    assert!(store.find("book.txt").is_some_and(|a| !a.numbered));
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_csv_attachment_is_summarized() {
+    let dir = temp_dir("attach-csv");
+    let path = dir.join("sales.csv");
+    let rows: String = (1..=500).map(|i| format!("{},{}\n", i, i * 2)).collect();
+    fs::write(&path, format!("id,total\n{}", rows)).unwrap();
+
+    let mut store = AttachmentStore::default();
+    let (hash, _) = store.attach(&path).unwrap();
+    let item = store.get(&hash).unwrap();
+    assert!(item.summarized && !item.numbered);
+    assert!(item.data.starts_with("[表格 sales.csv：500 行 × 2 列"));
+    assert!(!item.data.contains("| 250 |"));
+
+    let mut full = AttachmentStore::default().with_table_budget(None);
+    let (hash, _) = full.attach(&path).unwrap();
+    let item = full.get(&hash).unwrap();
+    assert!(!item.summarized);
+    assert!(item.data.contains("250,500"));
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_image_and_unknown_references() {
+    let dir = temp_dir("attach-image");
+    let path = dir.join("shot.png");
+    fs::write(&path, [0x89, b'P', b'N', b'G']).unwrap();
+    let mut store = AttachmentStore::default();
+    let (hash, _) = store.attach(&path).unwrap();
+
+    let history = vec![user(format!(
+      "看图 {} 和 {}",
+      AttachmentStore::reference(&hash),
+      AttachmentStore::reference(&"0".repeat(64))
+    ))];
+    let expanded = store.expand(&history);
+    let Message::MultiModal { content, .. } = &expanded[0] else {
+      panic!("expected a multimodal message");
+    };
+    assert_eq!(content.len(), 2);
+    assert_eq!(
+      text_of(&expanded[0]),
+      "看图 [图片 shot.png] 和 [附件 00000000 已不可用]"
+    );
+    assert!(
+      matches!(&content[1], Content::Image(img) if img.image_url.url.starts_with("data:image/png;base64,"))
+    );
+    let images = store.images_in(&format!("{0} {0}", AttachmentStore::reference(&hash)));
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].image_bytes().unwrap(), [0x89, b'P', b'N', b'G']);
+    // 不可用的引用不算
+    assert_eq!(store.images_in(text_of(&history[0])).len(), 1);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_sticky_file_is_in_every_request_once() {
+    let dir = temp_dir("attach-sticky");
+    let path = dir.join("schema.sql");
+    fs::write(&path, "CREATE TABLE users (id INT);\n").unwrap();
+    let mut store = AttachmentStore::default();
+    let hash = store.attach_sticky(&path).unwrap();
+    let system = Message::Simple {
+      role: "system".to_string(),
+      content: "You are a helpful assistant.".to_string(),
+      name: None,
+    };
+    let count = |messages: &[Message]| {
+      messages
+        .iter()
+        .map(|m| text_of(m).matches("CREATE TABLE users").count())
+        .sum::<usize>()
+    };
+
+    let mut history = vec![system, user("第一个问题".to_string())];
+    for question in ["第二个问题", "第三个问题"] {
+      let expanded = store.expand(&history);
+      assert_eq!(count(&expanded), 1);
+      // 紧跟在 system prompt 之后
+      assert!(text_of(&expanded[1]).starts_with(STICKY_HEADER));
+      history.push(user(question.to_string()));
+    }
+    // 同一文件又用 \file 附加时，请求中仍然只有一份
+    history.push(user(AttachmentStore::reference(&hash)));
+    let expanded = store.expand(&history);
+    assert_eq!(count(&expanded), 1);
+    assert!(text_of(&expanded[1]).ends_with("[同一文件 schema.sql，内容见下方]"));
+
+    assert_eq!(store.sticky().len(), 1);
+    assert!(store.detach("other.sql").is_none());
+
+    assert_eq!(store.detach("schema.sql"), Some(path.clone()));
+    assert!(store.sticky().is_empty());
+    // \c all 一并取消
+    store.attach_sticky(&path).unwrap();
+    assert_eq!(store.clear_sticky(), 1);
+    assert!(store.sticky().is_empty());
+    let expanded = store.expand(&history[..4]);
+    assert_eq!(count(&expanded), 0);
+    assert_eq!(expanded.len(), 4);
+
+    let image = dir.join("shot.png");
+    fs::write(&image, [0x89, b'P', b'N', b'G']).unwrap();
+    assert!(store.attach_sticky(&image).is_err());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_sticky_file_is_refreshed_when_modified() {
+    let dir = temp_dir("attach-sticky-refresh");
+    let path = dir.join("notes.txt");
+    fs::write(&path, "v1").unwrap();
+    let mut store = AttachmentStore::default();
+    store.attach_sticky(&path).unwrap();
+    assert!(store.refresh_sticky().is_empty());
+
+    fs::write(&path, "v2").unwrap();
+    let later = SystemTime::now() + std::time::Duration::from_secs(5);
+    fs::File::options()
+      .write(true)
+      .open(&path)
+      .unwrap()
+      .set_modified(later)
+      .unwrap();
+    let notices = store.refresh_sticky();
+    assert_eq!(notices.len(), 1);
+    assert!(notices[0].contains("已重新读取"));
+    let expanded = store.expand(&[user("?".to_string())]);
+    assert!(text_of(&expanded[0]).ends_with("1 | v2\n"));
+    assert!(store.refresh_sticky().is_empty());
+
+    fs::remove_file(&path).unwrap();
+    assert!(store.refresh_sticky()[0].contains("继续使用之前的内容"));
+    assert!(store.refresh_sticky().is_empty());
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,187 @@
+use crate::models;
+use crate::stats::{format_count, format_duration};
+use crate::tokens;
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+use std::time::Duration;
+
+/// 实际回答长度相对假设值的浮动范围，用于给出费用区间
+const OUTPUT_RANGE: (f64, f64) = (0.5, 1.5);
+/// 每个请求的固定开销（连接、排队、首字延迟）
+const REQUEST_OVERHEAD_SECS: f64 = 2.0;
+/// 估算耗时时假设的输出速度
+const OUTPUT_TOKENS_PER_SECOND: f64 = 30.0;
+
+/// 读取 JSONL：每行是一个字符串、`{"prompt": ...}` 或 `{"messages": [...]}`，空行跳过
+pub fn parse_prompts(text: &str) -> Result<Vec<String>> {
+  let mut prompts = vec![];
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    let value: Value =
+      serde_json::from_str(line).with_context(|| format!("Line {}: not valid JSON", i + 1))?;
+    let prompt = match &value {
+      Value::String(s) => Some(s.clone()),
+      Value::Object(_) => match (value.get("prompt"), value.get("messages")) {
+        (Some(Value::String(s)), _) => Some(s.clone()),
+        (_, Some(Value::Array(messages))) => Some(
+          messages
+            .iter()
+            .filter_map(|m| m.get("content").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        ),
+        _ => None,
+      },
+      _ => None,
+    };
+    match prompt {
+      Some(prompt) => prompts.push(prompt),
+      None => bail!(
+        "Line {}: expected a string, {{\"prompt\": ...}} or {{\"messages\": [...]}}",
+        i + 1
+      ),
+    }
+  }
+  Ok(prompts)
+}
+
+/// 批量请求的并发与速率限制
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+  pub concurrency: usize,
+  /// 每分钟请求数上限
+  pub rpm: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+  pub prompts: usize,
+  pub input_tokens: usize,
+  /// 按假设的回答长度计算
+  pub output_tokens: usize,
+  /// 人民币，回答长度按 OUTPUT_RANGE 浮动；模型没有价格信息时为 None
+  pub cost: Option<(f64, f64)>,
+  pub wall_time: Duration,
+}
+
+/// 本地估算，不调用 API。每个请求都带上 system prompt
+pub fn estimate(
+  prompts: &[String],
+  system_prompt: &str,
+  output_tokens: usize,
+  model: &str,
+  limits: Limits,
+) -> Estimate {
+  let n = prompts.len();
+  let system = tokens::count(system_prompt);
+  let input_tokens: usize = prompts.iter().map(|p| tokens::count(p) + system).sum();
+  let total_output = output_tokens * n;
+  let cost = models::registry().pricing(model).map(|price| {
+    let input = input_tokens as f64 / 1e6 * price.input_per_million;
+    let output = total_output as f64 / 1e6 * price.output_per_million;
+    (
+      input + output * OUTPUT_RANGE.0,
+      input + output * OUTPUT_RANGE.1,
+    )
+  });
+  let per_request = REQUEST_OVERHEAD_SECS + output_tokens as f64 / OUTPUT_TOKENS_PER_SECOND;
+  let rounds = n.div_ceil(limits.concurrency.max(1));
+  let by_concurrency = rounds as f64 * per_request;
+  let by_rate = limits
+    .rpm
+    .map_or(0.0, |rpm| n as f64 / rpm.max(1) as f64 * 60.0);
+  Estimate {
+    prompts: n,
+    input_tokens,
+    output_tokens: total_output,
+    cost,
+    wall_time: Duration::from_secs_f64(by_concurrency.max(by_rate)),
+  }
+}
+
+impl Estimate {
+  pub fn render(&self) -> String {
+    let cost = match self.cost {
+      Some((low, high)) => format!("¥{:.4} ~ ¥{:.4}", low, high),
+      None => "未知（该模型没有价格信息）".to_string(),
+    };
+    [
+      format!("请求: {} 条", self.prompts),
+      format!(
+        "Tokens（估算）: 输入 {} / 输出约 {}",
+        format_count(self.input_tokens),
+        format_count(self.output_tokens)
+      ),
+      format!("预估费用: {}", cost),
+      format!("预计耗时: {}", format_duration(self.wall_time)),
+    ]
+    .join("\n")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FIXTURE: &str = r#"
+"translate: hello world"
+{"prompt": "summarize this paragraph"}
+
+{"messages": [{"role": "system", "content": "be brief"}, {"role": "user", "content": "hi"}]}
+"#;
+
+  #[test]
+  fn test_parse_prompts() {
+    let prompts = parse_prompts(FIXTURE).unwrap();
+    assert_eq!(
+      prompts,
+      [
+        "translate: hello world",
+        "summarize this paragraph",
+        "be brief\nhi"
+      ]
+    );
+    let err = parse_prompts("\"ok\"\n{\"text\": 1}").unwrap_err();
+    assert!(err.to_string().starts_with("Line 2"));
+    assert!(parse_prompts("not json").is_err());
+  }
+
+  #[test]
+  fn test_estimate_arithmetic() {
+    let prompts = parse_prompts(FIXTURE).unwrap();
+    // tokens::count 的估算：translate 9 个字母算 2，冒号和标点各 1，换行 1
+    // 5（translate: hello world）、5（summarize this paragraph）、4（be brief\nhi）；system 3
+    let limits = Limits {
+      concurrency: 2,
+      rpm: None,
+    };
+    let estimate = estimate(&prompts, "You are.", 300, "deepseek-chat", limits);
+    assert_eq!(estimate.prompts, 3);
+    assert_eq!(estimate.input_tokens, 5 + 5 + 4 + 3 * 3);
+    assert_eq!(estimate.output_tokens, 900);
+    // 输入 23 × ¥2/M，输出 900 × ¥8/M 的 0.5 到 1.5 倍
+    let (low, high) = estimate.cost.unwrap();
+    assert!((low - (23.0 * 2.0 + 450.0 * 8.0) / 1e6).abs() < 1e-12);
+    assert!((high - (23.0 * 2.0 + 1350.0 * 8.0) / 1e6).abs() < 1e-12);
+    // 3 条、并发 2 → 两轮，每轮 2s + 300/30s
+    assert_eq!(estimate.wall_time, Duration::from_secs(24));
+    assert!(estimate.render().ends_with("预计耗时: 24.0s"));
+  }
+
+  #[test]
+  fn test_rate_limit_and_unknown_pricing() {
+    let prompts = vec!["x".to_string(); 600];
+    let limits = Limits {
+      concurrency: 100,
+      rpm: Some(60),
+    };
+    let estimate = estimate(&prompts, "", 30, "local-model", limits);
+    // 并发足够时受每分钟 60 次限制：600 条要 10 分钟
+    assert_eq!(estimate.wall_time, Duration::from_secs(600));
+    assert_eq!(estimate.cost, None);
+    assert!(estimate.render().contains("未知"));
+  }
+}
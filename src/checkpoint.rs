@@ -0,0 +1,213 @@
+use crate::api::Message;
+use crate::lock::FileLock;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 每完成 N 轮对话，把完整历史写入 sessions/auto/<日期>-<序号>.json 的只读快照，
+/// 并只保留最近的 K 个。快照内容与 \save 的格式相同，可直接 \load。
+pub struct Checkpointer {
+  dir: PathBuf,
+  every: usize,
+  keep: usize,
+  turns: usize,
+  /// 防止两个实例写同一个快照目录
+  _lock: Option<FileLock>,
+}
+
+impl Checkpointer {
+  /// `every` 为 0 时不写快照
+  pub fn new(dir: PathBuf, every: usize, keep: usize) -> Self {
+    Self {
+      dir,
+      every,
+      keep,
+      turns: 0,
+      _lock: None,
+    }
+  }
+
+  /// 持有目录锁直到 Checkpointer 被丢弃
+  pub fn with_lock(mut self, lock: FileLock) -> Self {
+    self._lock = Some(lock);
+    self
+  }
+
+  pub fn dir(&self) -> &Path {
+    &self.dir
+  }
+
+  /// 记录一轮完成的对话，到达间隔时写快照并清理旧快照，返回新快照路径
+  pub fn record_turn(&mut self, history: &[Message]) -> Result<Option<PathBuf>> {
+    self.turns += 1;
+    if self.every == 0 || !self.turns.is_multiple_of(self.every) {
+      return Ok(None);
+    }
+    let path = write_snapshot(&self.dir, history)?;
+    prune(&self.dir, self.keep)?;
+    Ok(Some(path))
+  }
+}
+
+fn counter_of(path: &Path) -> Option<u64> {
+  let stem = path.file_stem()?.to_str()?;
+  if path.extension().and_then(|e| e.to_str()) != Some("json") {
+    return None;
+  }
+  let (date, counter) = stem.rsplit_once('-')?;
+  if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+    return None;
+  }
+  counter.parse().ok()
+}
+
+/// 按从旧到新的顺序列出快照（按序号排序，与日期无关）
+pub fn list_checkpoints(dir: &Path) -> Result<Vec<PathBuf>> {
+  let entries = match fs::read_dir(dir) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+    Err(e) => return Err(e).context(format!("Failed to read checkpoint dir: {:?}", dir)),
+  };
+  let mut found: Vec<(u64, PathBuf)> = entries
+    .filter_map(|e| e.ok())
+    .map(|e| e.path())
+    .filter_map(|p| counter_of(&p).map(|c| (c, p)))
+    .collect();
+  found.sort();
+  Ok(found.into_iter().map(|(_, p)| p).collect())
+}
+
+/// 原子写入：先写临时文件再 rename，避免中途退出留下半个快照
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+  if let Some(parent) = path.parent() {
+    crate::paths::ensure(parent)?;
+  }
+  let tmp = path.with_extension("json.tmp");
+  fs::write(&tmp, contents).context(format!("Failed to write file: {:?}", tmp))?;
+  fs::rename(&tmp, path).context(format!("Failed to rename {:?} to {:?}", tmp, path))?;
+  Ok(())
+}
+
+pub fn write_snapshot(dir: &Path, history: &[Message]) -> Result<PathBuf> {
+  let next = list_checkpoints(dir)?
+    .last()
+    .and_then(|p| counter_of(p))
+    .map_or(1, |c| c + 1);
+  let name = format!("{}-{:04}.json", chrono::Local::now().format("%Y%m%d"), next);
+  let path = dir.join(name);
+  let json = serde_json::to_vec_pretty(history).context("Failed to serialize history")?;
+  write_atomic(&path, &json)?;
+  Ok(path)
+}
+
+/// 删除最旧的快照，只保留最近 `keep` 个，返回删除数量
+pub fn prune(dir: &Path, keep: usize) -> Result<usize> {
+  let all = list_checkpoints(dir)?;
+  let excess = all.len().saturating_sub(keep);
+  for path in &all[..excess] {
+    fs::remove_file(path).context(format!("Failed to remove checkpoint: {:?}", path))?;
+  }
+  Ok(excess)
+}
+
+#[cfg(test)]
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+  use std::time::{SystemTime, UNIX_EPOCH};
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_nanos();
+  let dir = std::env::temp_dir().join(format!(
+    "deepcli-test-{}-{}-{}",
+    name,
+    std::process::id(),
+    nanos
+  ));
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn history(n: usize) -> Vec<Message> {
+    (0..n)
+      .map(|i| Message::Simple {
+        role: "user".to_string(),
+        content: format!("message {}", i),
+        name: None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_every_n_counter() {
+    let dir = temp_dir("every-n");
+    let mut cp = Checkpointer::new(dir.clone(), 3, 10);
+    let mut written = vec![];
+    for turn in 1..=7 {
+      if let Some(path) = cp.record_turn(&history(turn)).unwrap() {
+        written.push((turn, path));
+      }
+    }
+    assert_eq!(written.iter().map(|(t, _)| *t).collect::<Vec<_>>(), [3, 6]);
+    assert_eq!(list_checkpoints(&dir).unwrap().len(), 2);
+
+    // 快照保存的是写入时的完整历史
+    let saved: Vec<Message> = serde_json::from_slice(&fs::read(&written[1].1).unwrap()).unwrap();
+    assert_eq!(saved.len(), 6);
+
+    let mut disabled = Checkpointer::new(dir.clone(), 0, 10);
+    for _ in 0..5 {
+      assert!(disabled.record_turn(&history(1)).unwrap().is_none());
+    }
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_prune_oldest_first() {
+    let dir = temp_dir("prune");
+    let mut paths = vec![];
+    for i in 0..5 {
+      paths.push(write_snapshot(&dir, &history(i)).unwrap());
+    }
+    // 混入无关文件，不应被列出或删除
+    fs::write(dir.join("notes.txt"), "x").unwrap();
+    fs::write(dir.join("manual.json"), "[]").unwrap();
+
+    assert_eq!(list_checkpoints(&dir).unwrap(), paths);
+    assert_eq!(prune(&dir, 2).unwrap(), 3);
+    assert_eq!(list_checkpoints(&dir).unwrap(), paths[3..].to_vec());
+    assert_eq!(prune(&dir, 2).unwrap(), 0);
+    assert!(dir.join("notes.txt").exists());
+    assert!(dir.join("manual.json").exists());
+
+    // 删除后序号继续递增，不会覆盖已有快照
+    let next = write_snapshot(&dir, &history(1)).unwrap();
+    assert_eq!(counter_of(&next), Some(6));
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_atomic_write_leaves_no_temp_file() {
+    let dir = temp_dir("atomic");
+    let path = dir.join("nested").join("20260101-0001.json");
+    write_atomic(&path, b"[]").unwrap();
+    write_atomic(&path, b"[1]").unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"[1]");
+    let names: Vec<_> = fs::read_dir(path.parent().unwrap())
+      .unwrap()
+      .map(|e| e.unwrap().file_name())
+      .collect();
+    assert_eq!(names.len(), 1);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_missing_dir_lists_nothing() {
+    let dir = std::env::temp_dir().join("deepcli-test-does-not-exist");
+    assert!(list_checkpoints(&dir).unwrap().is_empty());
+    assert_eq!(prune(&dir, 1).unwrap(), 0);
+  }
+}
@@ -0,0 +1,226 @@
+use crossterm::style::Stylize;
+use regex::Regex;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// 非代码文本超过这个字节数时不加行号，避免为长文档白白增加 token
+pub const NUMBER_MAX_BYTES: usize = 16 * 1024;
+
+/// \goto 显示的上下文行数
+pub const GOTO_CONTEXT: usize = 3;
+
+/// 有编号附件时追加到 system prompt
+pub const CITE_INSTRUCTION: &str = "Attached files are shown with line numbers. When referring to code, cite it as file:line, e.g. src/main.rs:42.";
+
+const CODE_EXTENSIONS: &[&str] = &[
+  "rs", "c", "h", "cc", "cpp", "hpp", "go", "py", "js", "jsx", "ts", "tsx", "java", "kt", "swift",
+  "rb", "php", "cs", "scala", "sh", "bash", "zsh", "lua", "sql", "toml", "yaml", "yml", "json",
+  "html", "css", "vue", "proto",
+];
+
+/// `file:line` 或 `file:start-end`，文件名必须带扩展名
+static CITATION: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(
+    r"(?:^|[^A-Za-z0-9_./-])((?:[A-Za-z0-9_.-]+/)*[A-Za-z0-9_-][A-Za-z0-9_.-]*\.[A-Za-z][A-Za-z0-9]*):(\d+)(?:-(\d+))?",
+  )
+  .unwrap()
+});
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Citation {
+  pub file: String,
+  pub line: usize,
+  pub end: Option<usize>,
+  /// 引用文本在原字符串中的字节范围
+  pub span: std::ops::Range<usize>,
+}
+
+pub fn is_code(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|e| e.to_str())
+    .is_some_and(|e| CODE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// 代码文件总是加行号；其他文本只在不超过 NUMBER_MAX_BYTES 时加
+pub fn should_number(path: &Path, len: usize) -> bool {
+  is_code(path) || len <= NUMBER_MAX_BYTES
+}
+
+/// 每行前加右对齐的行号：`  42 | fn main() {`
+pub fn number_lines(text: &str) -> String {
+  let width = text.lines().count().to_string().len().max(4);
+  text
+    .lines()
+    .enumerate()
+    .map(|(i, line)| format!("{:>width$} | {}\n", i + 1, line, width = width))
+    .collect()
+}
+
+/// 从加过行号的内容中取出第 `line` 行及前后 `context` 行；截断掉的行不存在
+pub fn lookup(numbered: &str, line: usize, context: usize) -> Vec<(usize, &str)> {
+  let range = line.saturating_sub(context)..=line + context;
+  numbered
+    .lines()
+    .filter_map(|l| {
+      let (n, rest) = l.split_once(" | ")?;
+      let n = n.trim().parse().ok()?;
+      range.contains(&n).then_some((n, rest))
+    })
+    .collect()
+}
+
+pub fn find_citations(text: &str) -> Vec<Citation> {
+  CITATION
+    .captures_iter(text)
+    .filter_map(|caps| {
+      let file = caps.get(1)?;
+      let whole = caps.get(0)?;
+      let end = whole.end();
+      Some(Citation {
+        file: file.as_str().to_string(),
+        line: caps[2].parse().ok()?,
+        end: caps.get(3).and_then(|m| m.as_str().parse().ok()),
+        span: file.start()..end,
+      })
+    })
+    .collect()
+}
+
+/// 解析 \goto 的参数 `file:line`
+pub fn parse_location(arg: &str) -> Option<(&str, usize)> {
+  let (file, line) = arg.trim().rsplit_once(':')?;
+  if file.is_empty() {
+    return None;
+  }
+  Some((file, line.parse().ok()?))
+}
+
+/// 高亮回答中的引用；`resolve` 能找到本地文件时加 OSC-8 超链接
+pub fn highlight(text: &str, resolve: impl Fn(&str) -> Option<String>) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut last = 0;
+  for citation in find_citations(text) {
+    let label = &text[citation.span.clone()];
+    out.push_str(&text[last..citation.span.start]);
+    let styled = label.yellow().underlined().to_string();
+    match resolve(&citation.file) {
+      Some(path) => out.push_str(&format!(
+        "\x1b]8;;file://{}\x1b\\{}\x1b]8;;\x1b\\",
+        path, styled
+      )),
+      None => out.push_str(&styled),
+    }
+    last = citation.span.end;
+  }
+  out.push_str(&text[last..]);
+  out
+}
+
+/// 流式输出时按空白切分，保证引用不会被拆在两个分片之间
+#[derive(Default)]
+pub struct StreamHighlighter {
+  pending: String,
+}
+
+impl StreamHighlighter {
+  /// 返回可以立即输出的部分
+  pub fn push(&mut self, chunk: &str, resolve: impl Fn(&str) -> Option<String>) -> String {
+    self.pending.push_str(chunk);
+    let Some(cut) = self.pending.rfind(char::is_whitespace) else {
+      return String::new();
+    };
+    let rest = self.pending.split_off(cut);
+    let ready = std::mem::replace(&mut self.pending, rest);
+    highlight(&ready, resolve)
+  }
+
+  pub fn finish(&mut self, resolve: impl Fn(&str) -> Option<String>) -> String {
+    highlight(&std::mem::take(&mut self.pending), resolve)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_number_lines() {
+    let numbered = number_lines("fn main() {\n  run();\n}\n");
+    assert_eq!(numbered, "   1 | fn main() {\n   2 |   run();\n   3 | }\n");
+    let long = number_lines(&"x\n".repeat(12345));
+    assert!(long.starts_with("    1 | x\n"));
+    assert!(long.ends_with("12345 | x\n"));
+  }
+
+  #[test]
+  fn test_should_number() {
+    assert!(should_number(Path::new("src/api.rs"), 1 << 20));
+    assert!(should_number(Path::new("notes.txt"), 1000));
+    assert!(!should_number(Path::new("notes.txt"), NUMBER_MAX_BYTES + 1));
+    assert!(!should_number(Path::new("README"), NUMBER_MAX_BYTES + 1));
+  }
+
+  #[test]
+  fn test_find_citations() {
+    let text = "见 src/api.rs:131 和 `main.rs:7-9`，版本 1.2:3 与 http://x.io:8080 无关。";
+    let found = find_citations(text);
+    assert_eq!(found.len(), 2, "{:?}", found);
+    assert_eq!(found[0].file, "src/api.rs");
+    assert_eq!(found[0].line, 131);
+    assert_eq!(&text[found[0].span.clone()], "src/api.rs:131");
+    assert_eq!(found[1].file, "main.rs");
+    assert_eq!((found[1].line, found[1].end), (7, Some(9)));
+    assert!(find_citations("at 12:30 today").is_empty());
+    // 中文紧贴引用时也能识别
+    assert_eq!(find_citations("看api.rs:3行")[0].file, "api.rs");
+  }
+
+  #[test]
+  fn test_highlight_links_resolved_files() {
+    let resolve = |f: &str| (f == "api.rs").then(|| "/tmp/p/api.rs".to_string());
+    let out = highlight("看 api.rs:3 与 lib.rs:4", resolve);
+    assert!(out.contains("\x1b]8;;file:///tmp/p/api.rs\x1b\\"));
+    assert_eq!(out.matches("\x1b]8;;").count(), 2);
+    assert!(out.contains("lib.rs:4"));
+    assert_eq!(highlight("没有引用", resolve), "没有引用");
+  }
+
+  #[test]
+  fn test_stream_highlighter_keeps_citation_whole() {
+    let mut h = StreamHighlighter::default();
+    let none = |_: &str| None;
+    let mut out = h.push("see src/ap", none);
+    out.push_str(&h.push("i.rs:13", none));
+    out.push_str(&h.push("1 now", none));
+    out.push_str(&h.finish(none));
+    assert_eq!(out, highlight("see src/api.rs:131 now", none));
+    assert!(out.contains(&"src/api.rs:131".yellow().underlined().to_string()));
+  }
+
+  #[test]
+  fn test_lookup_and_parse_location() {
+    let numbered = number_lines(
+      &(1..=20)
+        .map(|i| format!("line {}\n", i))
+        .collect::<String>(),
+    );
+    let around = lookup(&numbered, 10, 2);
+    assert_eq!(
+      around,
+      [
+        (8, "line 8"),
+        (9, "line 9"),
+        (10, "line 10"),
+        (11, "line 11"),
+        (12, "line 12")
+      ]
+    );
+    assert_eq!(lookup(&numbered, 1, 3).len(), 4);
+    assert!(lookup(&numbered, 40, 3).is_empty());
+
+    assert_eq!(parse_location("src/api.rs:131"), Some(("src/api.rs", 131)));
+    assert_eq!(parse_location("api.rs"), None);
+    assert_eq!(parse_location(":3"), None);
+  }
+}
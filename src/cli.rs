@@ -1,16 +1,92 @@
+use crate::api::ReasoningEffort;
+use crate::lock::LockConflict;
+use crate::profile::Profile;
+use crate::truncate::TruncateMode;
 use clap::{Arg, ArgAction, Command, builder::ValueParser};
 
 pub fn build_cli() -> Command {
   Command::new("deepcli")
     .about("DeepSeek command-line interface")
+    .disable_version_flag(true)
+    .arg(
+      Arg::new("version")
+        .long("version")
+        .short('V')
+        .help("Print version (with --verbose: build and effective settings)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("show_config")
+        .long("show-config")
+        .help("Print the effective configuration and where each value came from, then exit (with --json: as JSON)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("list_models")
+        .long("list-models")
+        .help("Print the model registry (built-in models plus models.yaml) and exit")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("verbose")
+        .long("verbose")
+        .help("Print extra diagnostics; with a one-shot query, token usage on stderr; with --version, the full environment block")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("user_id")
+        .long("user-id")
+        .value_name("ID")
+        .help("End-user identifier sent as the request's `user` field (for provider-side audit)"),
+    )
+    .arg(
+      Arg::new("send_user_id")
+        .long("send-user-id")
+        .help("Send $USER as the end-user identifier when --user-id is not given")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("config")
+        .long("config")
+        .value_name("FILE")
+        .help("Config file with default model, temperature, max_tokens, system prompt and API key (default: config.toml in the config directory, ~/.config/deepcli on Linux)")
+        .value_parser(clap::value_parser!(std::path::PathBuf)),
+    )
+    .arg(
+      Arg::new("data_dir")
+        .long("data-dir")
+        .value_name("DIR")
+        .help("Directory for saved sessions and other data (overrides data_dir and XDG_DATA_HOME)")
+        .value_parser(clap::value_parser!(std::path::PathBuf)),
+    )
+    .arg(
+      Arg::new("cache_dir")
+        .long("cache-dir")
+        .value_name("DIR")
+        .help("Directory for the knowledge-base index and other caches (overrides cache_dir and XDG_CACHE_HOME)")
+        .value_parser(clap::value_parser!(std::path::PathBuf)),
+    )
+    .arg(
+      Arg::new("no_dotenv")
+        .long("no-dotenv")
+        .help("Do not read .env from the current directory or its parents")
+        .action(ArgAction::SetTrue),
+    )
     .arg(
       Arg::new("model")
         .long("model")
         .short('m')
         .value_name("MODEL")
-        .help("Model to use: r1 (deepseek-reasoner) or chat (deepseek-chat)")
+        .help("Model to use: r1 (deepseek-reasoner), chat (deepseek-chat), any name or alias from models.yaml, or another model name passed as-is")
         .default_value("r1"),
     )
+    .arg(
+      Arg::new("profile")
+        .long("profile")
+        .value_name("PROFILE")
+        .help("Provider profile: dashscope or deepseek (auto-selected from API key env vars)")
+        .value_parser(["dashscope", "deepseek"]),
+    )
     .arg(
       Arg::new("temperature")
         .long("temperature")
@@ -37,12 +113,144 @@ pub fn build_cli() -> Command {
         .help("Maximum number of tokens to generate")
         .value_parser(clap::value_parser!(u32)),
     )
+    .arg(
+      Arg::new("system")
+        .long("system")
+        .value_name("TEXT")
+        .help("System prompt to use instead of the default (overrides --persona, DEEPCLI_SYSTEM_PROMPT and the config file)")
+        .conflicts_with("system_file"),
+    )
+    .arg(
+      Arg::new("system_file")
+        .long("system-file")
+        .value_name("PATH")
+        .help("Read the system prompt from a file")
+        .value_parser(clap::value_parser!(std::path::PathBuf)),
+    )
+    .arg(
+      Arg::new("persona")
+        .long("persona")
+        .value_name("FILE")
+        .help("Markdown persona file used as the system prompt (front-matter may set model/temperature)")
+        .value_parser(clap::value_parser!(std::path::PathBuf)),
+    )
+    .arg(
+      Arg::new("no_system")
+        .long("no-system")
+        .help("Send no system message; persona, contract and length instructions are prepended to the first user message")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("accessible")
+        .long("accessible")
+        .help("Screen-reader-friendly output: no colors, in-place updates or box drawing (also DEEPCLI_ACCESSIBLE=1 or TERM=dumb)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("contract")
+        .long("contract")
+        .value_name("RULE")
+        .help("Output rule sent with every request, e.g. \"no emoji\" (repeatable; edit with \\contract)")
+        .action(ArgAction::Append),
+    )
+    .arg(
+      Arg::new("import")
+        .long("import")
+        .value_name("FILE")
+        .help("Start from a conversation file (deepcli session, OpenAI messages, ShareGPT or ChatML)")
+        .value_parser(clap::value_parser!(std::path::PathBuf)),
+    )
+    .arg(
+      Arg::new("reasoning_effort")
+        .long("reasoning-effort")
+        .value_name("EFFORT")
+        .help("Reasoning effort for models that support it: low, medium or high")
+        .value_parser(clap::value_parser!(ReasoningEffort)),
+    )
+    .arg(
+      Arg::new("truncate")
+        .long("truncate")
+        .value_name("MODE")
+        .help("How to shorten oversized text input: auto, head or log (keep start and end)")
+        .value_parser(clap::value_parser!(TruncateMode))
+        .default_value("auto"),
+    )
+    .arg(
+      Arg::new("max_words")
+        .long("max-words")
+        .value_name("N")
+        .help("Cut the visible answer off shortly after N words (CJK characters count one each)")
+        .value_parser(clap::value_parser!(usize)),
+    )
+    .arg(
+      Arg::new("stop_regex")
+        .long("stop-regex")
+        .value_name("PATTERN")
+        .help("Stop the answer where it first matches PATTERN and drop the match; ^ and $ match at line boundaries (repeatable)")
+        .value_parser(ValueParser::new(crate::stop::validate))
+        .action(ArgAction::Append),
+    )
+    .arg(
+      Arg::new("brief")
+        .long("brief")
+        .help("Ask for a short answer (at most 3 sentences)")
+        .action(ArgAction::SetTrue)
+        .conflicts_with_all(["normal", "detailed"]),
+    )
+    .arg(
+      Arg::new("normal")
+        .long("normal")
+        .help("Use the default answer length")
+        .action(ArgAction::SetTrue)
+        .conflicts_with("detailed"),
+    )
+    .arg(
+      Arg::new("detailed")
+        .long("detailed")
+        .help("Ask for a thorough answer with examples")
+        .action(ArgAction::SetTrue),
+    )
     .arg(
       Arg::new("json")
         .long("json")
         .help("Output response as formatted JSON")
         .action(clap::ArgAction::SetTrue),
     )
+    .arg(
+      Arg::new("json_schema")
+        .long("json-schema")
+        .value_name("FILE")
+        .help("Ask for JSON conforming to the JSON Schema in FILE and check the answer against it (implies --json)")
+        .value_parser(clap::value_parser!(std::path::PathBuf))
+        .conflicts_with_all(["interactive", "resume"]),
+    )
+    .arg(
+      Arg::new("no_stream")
+        .long("no-stream")
+        .help("Print a one-shot answer all at once instead of streaming it (the default when stdout is not a terminal)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("quiet")
+        .long("quiet")
+        .short('q')
+        .help("Suppress informational output such as the session summary on exit")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("strict")
+        .long("strict")
+        .help("Refuse session files with fields this version does not know instead of ignoring them")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("isolated")
+        .long("isolated")
+        .help(
+          "Only contact the configured API endpoint; disable clipboard, plugins, telemetry and warm-up",
+        )
+        .action(ArgAction::SetTrue),
+    )
     .arg(
       Arg::new("interactive")
         .long("interactive")
@@ -50,15 +258,386 @@ pub fn build_cli() -> Command {
         .help("启动交互式聊天模式")
         .action(ArgAction::SetTrue),
     )
+    .arg(
+      Arg::new("turn_timeout")
+        .long("turn-timeout")
+        .value_name("DURATION")
+        .help("Stop a turn (including retries and auto-continues) after this long, e.g. 180s or 3m")
+        .value_parser(ValueParser::new(parse_duration)),
+    )
+    .arg(
+      Arg::new("timeout")
+        .long("timeout")
+        .value_name("SECS")
+        .help("Give up when the API sends nothing for this long: while waiting for the response, or between streamed chunks (not a limit on the whole answer)")
+        .value_parser(ValueParser::new(parse_duration))
+        .default_value("300"),
+    )
+    .arg(
+      Arg::new("connect_timeout")
+        .long("connect-timeout")
+        .value_name("SECS")
+        .help("Give up when connecting to the API takes longer than this")
+        .value_parser(ValueParser::new(parse_duration))
+        .default_value("10"),
+    )
+    .arg(
+      Arg::new("stream_idle")
+        .long("stream-idle")
+        .value_name("DURATION")
+        .help("Note when a streamed response has been silent this long")
+        .value_parser(ValueParser::new(parse_duration))
+        .default_value("30s"),
+    )
+    .arg(
+      Arg::new("base_url")
+        .long("base-url")
+        .value_name("URL")
+        .help("OpenAI-compatible API endpoint instead of the profile's, e.g. http://localhost:8000/v1 (also DEEPCLI_BASE_URL)")
+        .value_parser(ValueParser::new(crate::api::normalize_base_url)),
+    )
+    .arg(
+      Arg::new("no_compat_check")
+        .long("no-compat-check")
+        .help("Do not check that a custom --base-url is an OpenAI-compatible chat endpoint before the first request")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("retries")
+        .long("retries")
+        .value_name("N")
+        .help("Retry chat requests up to N times on connection errors, timeouts and HTTP 500/502/503")
+        .value_parser(clap::value_parser!(u32))
+        .default_value("2"),
+    )
+    .arg(
+      Arg::new("render")
+        .long("render")
+        .value_name("MODE")
+        .help("Show answers as rendered Markdown or raw text: markdown or raw (default: markdown when stdout is a terminal)")
+        .value_parser(clap::value_parser!(crate::markdown::RenderMode)),
+    )
+    .arg(
+      Arg::new("hide_reasoning")
+        .long("hide-reasoning")
+        .help("Don't show the reasoning of deepseek-r1 while it streams (use \\reasoning on|off in interactive mode)")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("no_math_render")
+        .long("no-math-render")
+        .help("Show LaTeX math in answers as-is instead of rendering it to Unicode")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("no_dedupe")
+        .long("no-dedupe")
+        .help("Do not warn when a question repeats an earlier one in the session")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("flush_interval_ms")
+        .long("flush-interval-ms")
+        .value_name("MS")
+        .help("Coalesce streamed output for this long before repainting (default: 10 locally, 100 over SSH)")
+        .value_parser(clap::value_parser!(u64)),
+    )
+    .arg(
+      Arg::new("max_file_size")
+        .long("max-file-size")
+        .value_name("SIZE")
+        .help("Refuse attachments larger than this, e.g. 512K or 16M")
+        .value_parser(ValueParser::new(parse_size))
+        .default_value("16M"),
+    )
+    .arg(
+      Arg::new("exclude")
+        .long("exclude")
+        .value_name("GLOB")
+        .help("Skip matching paths when attaching a directory, gitignore syntax, applied after .gitignore (repeatable)")
+        .value_parser(ValueParser::new(crate::walk::validate))
+        .action(ArgAction::Append),
+    )
+    .arg(
+      Arg::new("include")
+        .long("include")
+        .value_name("GLOB")
+        .help("Attach matching paths even if excluded earlier; the last matching --exclude/--include wins (repeatable)")
+        .value_parser(ValueParser::new(crate::walk::validate))
+        .action(ArgAction::Append),
+    )
+    .arg(
+      Arg::new("restrict_to")
+        .long("restrict-to")
+        .value_name("DIR")
+        .help("Refuse to read any file that resolves (after symlinks) outside DIR")
+        .value_parser(clap::value_parser!(std::path::PathBuf)),
+    )
+    .arg(
+      Arg::new("table_budget")
+        .long("table-budget")
+        .value_name("TOKENS")
+        .help("Token budget for the summary sent instead of a CSV/TSV attachment")
+        .value_parser(clap::value_parser!(usize))
+        .default_value("2000"),
+    )
+    .arg(
+      Arg::new("full_table")
+        .long("full-table")
+        .help("Attach CSV/TSV files in full instead of a summary")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("force_text")
+        .long("force-text")
+        .help("Send files that look binary as text anyway")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("checkpoint_every")
+        .long("checkpoint-every")
+        .value_name("N")
+        .help("Write an immutable session snapshot every N turns (0 disables)")
+        .value_parser(clap::value_parser!(usize))
+        .default_value("10"),
+    )
+    .arg(
+      Arg::new("checkpoint_keep")
+        .long("checkpoint-keep")
+        .value_name("K")
+        .help("Number of automatic snapshots to keep")
+        .value_parser(clap::value_parser!(usize))
+        .default_value("20"),
+    )
+    .arg(
+      Arg::new("lock_conflict")
+        .long("lock-conflict")
+        .value_name("MODE")
+        .help("When another instance holds the snapshot directory: suffix (use auto-2, ...) or read-only")
+        .value_parser(clap::value_parser!(LockConflict))
+        .default_value("suffix"),
+    )
+    .arg(
+      Arg::new("auto_route")
+        .long("auto-route")
+        .help("Send obviously simple follow-ups (\"shorter\", \"translate that\") to the cheaper route_model; start a message with @r1 to force a model")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("verify")
+        .long("verify")
+        .help("After each answer in interactive mode, ask the cheaper route_model to review it and print its 复核意见 below (not kept in history); short answers are skipped")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("keep_refusals")
+        .long("keep-refusals")
+        .help("Keep turns blocked by the provider's content filter in history, marked [内容审核拦截]; by default they are undone with their question")
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("resume")
+        .long("resume")
+        .help("Start interactive mode with the answer that was streaming when deepcli last exited unexpectedly")
+        .conflicts_with_all(["import", "query"])
+        .action(ArgAction::SetTrue),
+    )
+    .arg(
+      Arg::new("file")
+        .long("file")
+        .short('f')
+        .value_name("PATH")
+        .help("Send a file (text or image) with the query; use \\file in interactive mode")
+        .value_parser(clap::value_parser!(std::path::PathBuf))
+        .conflicts_with("interactive"),
+    )
+    .arg(
+      Arg::new("output")
+        .long("output")
+        .short('o')
+        .value_name("PATH")
+        .help("Also write the complete answer to PATH once it has arrived; refuses to replace an existing file")
+        .value_parser(clap::value_parser!(std::path::PathBuf))
+        .conflicts_with_all(["interactive", "resume"]),
+    )
+    .arg(
+      Arg::new("output_append")
+        .long("output-append")
+        .help("Append the answer to the --output file instead of creating it")
+        .action(ArgAction::SetTrue)
+        .requires("output"),
+    )
+    .arg(
+      Arg::new("force")
+        .long("force")
+        .help("Overwrite an existing --output file")
+        .action(ArgAction::SetTrue)
+        .requires("output")
+        .conflicts_with("output_append"),
+    )
     .arg(
       Arg::new("query")
         .help("Query to send to the model (在交互模式下可选)")
         .required(false)
         .index(1),
     )
+    .subcommand(
+      Command::new("status")
+        .about("Probe the configured provider: reachability, latency and streaming"),
+    )
+    .subcommand(
+      Command::new("test-prompts")
+        .about("Replay conversation cases from a YAML file and check the answers")
+        .arg(
+          Arg::new("cases")
+            .help("YAML file with the test cases")
+            .required(true)
+            .value_parser(clap::value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+          Arg::new("update_snapshots")
+            .long("update-snapshots")
+            .help("Rewrite the stored transcripts instead of comparing against them")
+            .action(clap::ArgAction::SetTrue),
+        ),
+    )
+    .subcommand(
+      Command::new("run")
+        .about("Run a command and ask the model to explain its output if it fails")
+        .arg(
+          Arg::new("always")
+            .long("always")
+            .help("Analyze the output even if the command succeeds")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+          Arg::new("no_confirm")
+            .long("no-run-analysis-confirm")
+            .help("Send the output without asking first")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+          Arg::new("command")
+            .help("Command to run, after --")
+            .required(true)
+            .num_args(1..)
+            .trailing_var_arg(true)
+            .allow_hyphen_values(true),
+        ),
+    )
+    .subcommand(
+      Command::new("ask")
+        .about("Answer a question grounded in a local directory of notes")
+        .arg(
+          Arg::new("kb")
+            .long("kb")
+            .value_name("DIR")
+            .help("Directory of Markdown/text notes to retrieve from")
+            .required(true)
+            .value_parser(clap::value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+          Arg::new("kb_rebuild")
+            .long("kb-rebuild")
+            .help("Re-embed every note instead of refreshing changed files")
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+          Arg::new("kb_top_k")
+            .long("kb-top-k")
+            .value_name("K")
+            .help("Number of chunks sent as context")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("5"),
+        )
+        .arg(
+          Arg::new("question")
+            .help("Question to answer")
+            .required(true)
+            .num_args(1..),
+        ),
+    )
+    .subcommand(
+      Command::new("batch")
+        .about("Estimate tokens, cost and wall time for a JSONL file of prompts")
+        .arg(
+          Arg::new("input")
+            .long("input")
+            .value_name("FILE")
+            .help("JSONL file: one string, {\"prompt\": ...} or {\"messages\": [...]} per line")
+            .required(true)
+            .value_parser(clap::value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+          Arg::new("estimate")
+            .long("estimate")
+            .help("Only estimate locally; no API calls are made")
+            .required(true)
+            .action(ArgAction::SetTrue),
+        )
+        .arg(
+          Arg::new("assume_output_tokens")
+            .long("assume-output-tokens")
+            .value_name("N")
+            .help("Assumed answer length per prompt")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("500"),
+        )
+        .arg(
+          Arg::new("concurrency")
+            .long("concurrency")
+            .value_name("N")
+            .help("Requests in flight at once")
+            .value_parser(clap::value_parser!(usize))
+            .default_value("4"),
+        )
+        .arg(
+          Arg::new("rpm")
+            .long("rpm")
+            .value_name("N")
+            .help("Provider limit in requests per minute")
+            .value_parser(clap::value_parser!(u32)),
+        ),
+    )
+    .subcommand(
+      Command::new("export")
+        .about("Export a saved session as a self-contained HTML page")
+        .arg(
+          Arg::new("html")
+            .long("html")
+            .value_name("FILE")
+            .help("HTML file to write")
+            .required(true)
+            .value_parser(clap::value_parser!(std::path::PathBuf)),
+        )
+        .arg(
+          Arg::new("session")
+            .help("Name of the saved session")
+            .required(true),
+        ),
+    )
+    .subcommand(
+      Command::new("sessions")
+        .about("Manage saved sessions")
+        .subcommand_required(true)
+        .subcommand(
+          Command::new("gc")
+            .about("Prune automatic snapshots, keeping the most recent ones")
+            .arg(
+              Arg::new("keep")
+                .long("keep")
+                .value_name("K")
+                .help("Number of snapshots to keep (defaults to --checkpoint-keep)")
+                .value_parser(clap::value_parser!(usize)),
+            ),
+        )
+        .subcommand(
+          Command::new("migrate")
+            .about("Upgrade saved session files to the current format, keeping .bak copies"),
+        ),
+    )
 }
 
-#[allow(dead_code)]
 pub fn validate_temperature(temp: f32) -> Result<f32, String> {
   if (0.0..=2.0).contains(&temp) {
     Ok(temp)
@@ -67,17 +646,113 @@ pub fn validate_temperature(temp: f32) -> Result<f32, String> {
   }
 }
 
-pub fn map_model(model: &str) -> Result<String, String> {
-  match model {
-    "r1" => Ok("deepseek-r1".to_string()),
-    "chat" => Ok("deepseek-chat".to_string()),
-    _ => Err("Invalid model. Use 'r1' or 'chat'.".to_string()),
+/// 解析 `500ms`、`180s`、`3m`、`1h` 形式的时长，不带单位时按秒计
+pub fn parse_duration(s: &str) -> Result<std::time::Duration, String> {
+  let s = s.trim();
+  let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  let (number, unit) = s.split_at(split);
+  let n: u64 = number
+    .parse()
+    .map_err(|_| format!("Invalid duration '{}'", s))?;
+  let millis = match unit {
+    "ms" => n,
+    "" | "s" => n * 1000,
+    "m" => n * 60_000,
+    "h" => n * 3_600_000,
+    _ => {
+      return Err(format!(
+        "Invalid duration unit in '{}': use ms, s, m or h",
+        s
+      ));
+    }
+  };
+  if millis == 0 {
+    return Err("Duration must be greater than zero".to_string());
+  }
+  Ok(std::time::Duration::from_millis(millis))
+}
+
+/// 解析 `4096`、`512K`、`16M`、`1G` 形式的字节数
+pub fn parse_size(s: &str) -> Result<u64, String> {
+  let s = s.trim();
+  let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+  let (number, unit) = s.split_at(split);
+  let n: u64 = number
+    .parse()
+    .map_err(|_| format!("Invalid size '{}'", s))?;
+  let shift = match unit.to_ascii_uppercase().as_str() {
+    "" | "B" => 0,
+    "K" | "KB" => 10,
+    "M" | "MB" => 20,
+    "G" | "GB" => 30,
+    _ => return Err(format!("Invalid size unit in '{}': use K, M or G", s)),
+  };
+  Ok(n << shift)
+}
+
+/// 没有子命令时的运行方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode<'a> {
+  /// 交互式对话，可以带上第一条消息
+  Interactive(Option<&'a str>),
+  /// 只发送一个问题，打印回答后退出；stdin 是管道时它的内容接在问题后面
+  OneShot(Option<&'a str>),
+}
+
+/// -i 总是进入交互模式；否则有问题时只问一次，
+/// 没有问题时在终端中进入交互模式（--file 和 --output 除外），stdin 是管道时把输入作为问题
+pub fn run_mode(matches: &clap::ArgMatches, stdin_is_terminal: bool) -> RunMode<'_> {
+  let query = matches.get_one::<String>("query").map(String::as_str);
+  let file = ["file", "output", "json_schema"]
+    .iter()
+    .any(|id| matches.contains_id(id));
+  // --resume 总是进入交互模式
+  let interactive = matches.get_flag("interactive") || matches.get_flag("resume");
+  match (interactive, query) {
+    (true, query) => RunMode::Interactive(query),
+    (false, Some(query)) => RunMode::OneShot(Some(query)),
+    (false, None) if stdin_is_terminal && !file => RunMode::Interactive(None),
+    (false, None) => RunMode::OneShot(None),
+  }
+}
+
+/// 单次提问的问题：命令行中的问题在前，空一行后接 stdin 管道输入的内容，
+/// 例如 `cat error.log | deepcli "解释这段报错"`；只有一边时就用那一边
+pub fn combine_stdin(query: Option<&str>, piped: Option<&str>) -> String {
+  let query = query.map(str::trim).filter(|q| !q.is_empty());
+  let piped = piped.map(str::trim_end).filter(|p| !p.trim().is_empty());
+  match (query, piped) {
+    (Some(query), Some(piped)) => format!("{}\n\n{}", query, piped),
+    (Some(text), None) | (None, Some(text)) => text.to_string(),
+    (None, None) => String::new(),
   }
 }
 
+/// 按模型表把 -m 的输入（名称或别名）解析为 `profile` 使用的模型名
+/// `--exclude` 和 `--include` 按在命令行中出现的顺序排列
+pub fn attach_rules(matches: &clap::ArgMatches) -> Vec<(crate::walk::Kind, String)> {
+  use crate::walk::Kind;
+  let mut rules: Vec<_> = [("exclude", Kind::Exclude), ("include", Kind::Include)]
+    .into_iter()
+    .flat_map(|(id, kind)| {
+      let indices = matches.indices_of(id).into_iter().flatten();
+      let values = matches.get_many::<String>(id).into_iter().flatten();
+      indices.zip(values).map(move |(i, v)| (i, kind, v.clone()))
+    })
+    .collect();
+  rules.sort_by_key(|(i, _, _)| *i);
+  rules.into_iter().map(|(_, kind, v)| (kind, v)).collect()
+}
+
+/// 别名和模型名按模型表解析，模型表里没有的名称原样返回
+pub fn map_model(model: &str, profile: &Profile) -> Result<String, String> {
+  crate::models::registry().resolve(model, profile.name)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::profile::{DASHSCOPE, DEEPSEEK};
 
   #[test]
   fn test_cli_building() {
@@ -113,6 +788,247 @@ mod tests {
     // Test interactive flag
     let matches = build_cli().get_matches_from(vec!["deepcli", "-i"]);
     assert!(matches.get_flag("interactive"));
+
+    // Test length presets
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--brief", "hi"]);
+    assert!(matches.get_flag("brief"));
+    assert!(!matches.get_flag("detailed"));
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--detailed", "hi"]);
+    assert!(matches.get_flag("detailed"));
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--brief", "--detailed", "hi"])
+        .is_err()
+    );
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--normal", "--brief", "hi"])
+        .is_err()
+    );
+
+    // Test quiet flag
+    let matches = build_cli().get_matches_from(vec!["deepcli", "-q", "-i"]);
+    assert!(matches.get_flag("quiet"));
+
+    // Test isolated flag
+    let matches = build_cli().get_matches_from(vec!["deepcli", "hi"]);
+    assert!(!matches.get_flag("isolated"));
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--isolated", "-i"]);
+    assert!(matches.get_flag("isolated"));
+
+    // Test persona file
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--persona", "sre.md", "-i"]);
+    assert_eq!(
+      matches.get_one::<std::path::PathBuf>("persona").unwrap(),
+      &std::path::PathBuf::from("sre.md")
+    );
+
+    // Test reasoning effort
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--reasoning-effort", "low", "hi"]);
+    assert_eq!(
+      matches.get_one::<ReasoningEffort>("reasoning_effort"),
+      Some(&ReasoningEffort::Low)
+    );
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--reasoning-effort", "max", "hi"])
+        .is_err()
+    );
+
+    // Test truncate mode
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--truncate=log", "hi"]);
+    assert_eq!(
+      matches.get_one::<TruncateMode>("truncate"),
+      Some(&TruncateMode::Log)
+    );
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--truncate=tail", "hi"])
+        .is_err()
+    );
+
+    // Test checkpoint options
+    let matches = build_cli().get_matches_from(vec!["deepcli", "hi"]);
+    assert_eq!(matches.get_one::<usize>("checkpoint_every").unwrap(), &10);
+    assert_eq!(matches.get_one::<usize>("checkpoint_keep").unwrap(), &20);
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--checkpoint-every", "0", "-i"]);
+    assert_eq!(matches.get_one::<usize>("checkpoint_every").unwrap(), &0);
+    assert_eq!(
+      matches.get_one::<LockConflict>("lock_conflict").unwrap(),
+      &LockConflict::Suffix
+    );
+    let matches =
+      build_cli().get_matches_from(vec!["deepcli", "--lock-conflict", "read-only", "-i"]);
+    assert_eq!(
+      matches.get_one::<LockConflict>("lock_conflict").unwrap(),
+      &LockConflict::ReadOnly
+    );
+
+    // Test sessions subcommand
+    let matches = build_cli().get_matches_from(vec!["deepcli", "sessions", "gc", "--keep", "3"]);
+    let (name, sub) = matches.subcommand().unwrap();
+    assert_eq!(name, "sessions");
+    let (name, gc) = sub.subcommand().unwrap();
+    assert_eq!(name, "gc");
+    assert_eq!(gc.get_one::<usize>("keep").unwrap(), &3);
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--strict", "sessions", "migrate"]);
+    assert!(matches.get_flag("strict"));
+    let (_, sub) = matches.subcommand().unwrap();
+    assert_eq!(sub.subcommand_name(), Some("migrate"));
+
+    // Test version flags
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--version", "--verbose"]);
+    assert!(matches.get_flag("version"));
+    assert!(matches.get_flag("verbose"));
+    assert!(!matches.get_flag("no_dotenv"));
+    assert_eq!(matches.get_one::<String>("user_id"), None);
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--user-id", "alice", "hi"]);
+    assert_eq!(matches.get_one::<String>("user_id").unwrap(), "alice");
+    assert_eq!(matches.get_one::<usize>("max_words"), None);
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--max-words", "50", "hi"]);
+    assert_eq!(matches.get_one::<usize>("max_words"), Some(&50));
+    let matches = build_cli().get_matches_from(vec![
+      "deepcli",
+      "--stop-regex",
+      "^## References",
+      "--stop-regex",
+      "(?i)^sources:",
+      "hi",
+    ]);
+    let patterns: Vec<&String> = matches.get_many("stop_regex").unwrap().collect();
+    assert_eq!(patterns, ["^## References", "(?i)^sources:"]);
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--stop-regex", "(", "hi"])
+        .is_err()
+    );
+
+    let rules = build_cli().get_matches_from(vec![
+      "deepcli",
+      "--exclude",
+      "target/**",
+      "--include",
+      "target/doc/**",
+      "--exclude",
+      "*.html",
+    ]);
+    use crate::walk::Kind;
+    assert_eq!(
+      attach_rules(&rules),
+      [
+        (Kind::Exclude, "target/**".to_string()),
+        (Kind::Include, "target/doc/**".to_string()),
+        (Kind::Exclude, "*.html".to_string()),
+      ]
+    );
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--exclude", "/", "hi"])
+        .is_err()
+    );
+    assert_eq!(
+      matches.get_one::<std::time::Duration>("stream_idle"),
+      Some(&std::time::Duration::from_secs(30))
+    );
+
+    assert_eq!(matches.get_one::<u64>("flush_interval_ms"), None);
+    assert!(!matches.get_flag("no_math_render"));
+    assert!(!matches.get_flag("no_dedupe"));
+
+    // Test attachment limits
+    assert_eq!(matches.get_one::<u64>("max_file_size"), Some(&(16 << 20)));
+    assert!(!matches.get_flag("force_text"));
+    assert_eq!(matches.get_one::<usize>("table_budget"), Some(&2000));
+    assert!(!matches.get_flag("full_table"));
+    assert_eq!(parse_size("512K"), Ok(512 * 1024));
+    assert_eq!(parse_size("4096"), Ok(4096));
+    assert!(parse_size("2T").is_err());
+
+    // Test request timeouts
+    assert_eq!(
+      matches.get_one::<std::time::Duration>("timeout"),
+      Some(&crate::api::DEFAULT_TIMEOUT)
+    );
+    assert_eq!(
+      matches.get_one::<std::time::Duration>("connect_timeout"),
+      Some(&crate::api::DEFAULT_CONNECT_TIMEOUT)
+    );
+    let matches = build_cli().get_matches_from(vec![
+      "deepcli",
+      "--timeout",
+      "90",
+      "--connect-timeout",
+      "3s",
+    ]);
+    assert_eq!(
+      matches.get_one::<std::time::Duration>("timeout"),
+      Some(&std::time::Duration::from_secs(90))
+    );
+    assert_eq!(
+      matches.get_one::<std::time::Duration>("connect_timeout"),
+      Some(&std::time::Duration::from_secs(3))
+    );
+
+    // Test turn timeout
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--turn-timeout", "3m"]);
+    assert_eq!(
+      matches.get_one::<std::time::Duration>("turn_timeout"),
+      Some(&std::time::Duration::from_secs(180))
+    );
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--turn-timeout", "soon"])
+        .is_err()
+    );
+
+    // Test test-prompts subcommand
+    let matches = build_cli().get_matches_from(vec![
+      "deepcli",
+      "test-prompts",
+      "cases.yaml",
+      "--update-snapshots",
+    ]);
+    let (name, sub) = matches.subcommand().unwrap();
+    assert_eq!(name, "test-prompts");
+    assert_eq!(
+      sub.get_one::<std::path::PathBuf>("cases").unwrap(),
+      std::path::Path::new("cases.yaml")
+    );
+    assert!(sub.get_flag("update_snapshots"));
+
+    // Test run subcommand
+    let matches = build_cli().get_matches_from(vec![
+      "deepcli", "run", "--always", "--", "cargo", "-q", "test",
+    ]);
+    let (name, sub) = matches.subcommand().unwrap();
+    assert_eq!(name, "run");
+    assert!(sub.get_flag("always"));
+    assert!(!sub.get_flag("no_confirm"));
+    let command: Vec<_> = sub.get_many::<String>("command").unwrap().collect();
+    assert_eq!(command, ["cargo", "-q", "test"]);
+
+    // Test ask subcommand
+    let matches =
+      build_cli().get_matches_from(vec!["deepcli", "ask", "--kb", "notes", "cache", "TTL?"]);
+    let (name, sub) = matches.subcommand().unwrap();
+    assert_eq!(name, "ask");
+    assert_eq!(sub.get_one::<usize>("kb_top_k"), Some(&5));
+    assert!(!sub.get_flag("kb_rebuild"));
+    let question: Vec<_> = sub.get_many::<String>("question").unwrap().collect();
+    assert_eq!(question, ["cache", "TTL?"]);
+
+    // Test status subcommand
+    let matches = build_cli().get_matches_from(vec!["deepcli", "status"]);
+    assert_eq!(matches.subcommand_name(), Some("status"));
+
+    // Test profile selection
+    let matches = build_cli().get_matches_from(vec!["deepcli", "--profile", "deepseek", "hi"]);
+    assert_eq!(matches.get_one::<String>("profile").unwrap(), "deepseek");
+    assert!(
+      build_cli()
+        .try_get_matches_from(vec!["deepcli", "--profile", "nope", "hi"])
+        .is_err()
+    );
   }
 
   #[test]
@@ -129,9 +1045,35 @@ mod tests {
 
   #[test]
   fn test_model_mapping() {
-    assert_eq!(map_model("r1").unwrap(), "deepseek-r1");
-    assert_eq!(map_model("chat").unwrap(), "deepseek-chat");
-    assert!(map_model("invalid").is_err());
+    assert_eq!(map_model("r1", &DASHSCOPE).unwrap(), "deepseek-r1");
+    assert_eq!(map_model("chat", &DASHSCOPE).unwrap(), "deepseek-chat");
+    assert_eq!(map_model("qwen-vl-max", &DASHSCOPE).unwrap(), "qwen-vl-max");
+
+    assert_eq!(map_model("r1", &DEEPSEEK).unwrap(), "deepseek-reasoner");
+    assert_eq!(map_model("chat", &DEEPSEEK).unwrap(), "deepseek-chat");
+    // 模型表里没有的名称原样传给服务方；表里的模型换了服务方仍然报错
+    assert_eq!(
+      map_model("deepseek-chat-0324", &DEEPSEEK).unwrap(),
+      "deepseek-chat-0324"
+    );
+    assert!(map_model("qwen-max", &DEEPSEEK).is_err());
+    assert!(
+      build_cli()
+        .get_matches_from(["deepcli", "--list-models"])
+        .get_flag("list_models")
+    );
+  }
+
+  #[test]
+  fn test_parse_duration() {
+    use std::time::Duration;
+    assert_eq!(parse_duration("180s"), Ok(Duration::from_secs(180)));
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+    assert!(parse_duration("0s").is_err());
+    assert!(parse_duration("3d").is_err());
+    assert!(parse_duration("s").is_err());
   }
 
   #[test]
@@ -150,4 +1092,228 @@ mod tests {
     let result = build_cli().try_get_matches_from(vec!["deepcli", "-i"]);
     assert!(result.is_ok());
   }
+
+  #[test]
+  fn test_run_mode() {
+    let cases: [(&[&str], bool, RunMode); 6] = [
+      (
+        &["deepcli", "-m", "chat", "-t", "0.7", "hi"],
+        true,
+        RunMode::OneShot(Some("hi")),
+      ),
+      (
+        &["deepcli", "--json", "give me a list"],
+        false,
+        RunMode::OneShot(Some("give me a list")),
+      ),
+      (&["deepcli"], true, RunMode::Interactive(None)),
+      (&["deepcli"], false, RunMode::OneShot(None)),
+      (&["deepcli", "-i"], false, RunMode::Interactive(None)),
+      (
+        &["deepcli", "-i", "-m", "chat", "hello"],
+        true,
+        RunMode::Interactive(Some("hello")),
+      ),
+    ];
+    for (args, terminal, expected) in cases {
+      let matches = build_cli().get_matches_from(args);
+      assert_eq!(run_mode(&matches, terminal), expected, "{:?}", args);
+    }
+    let matches = build_cli().get_matches_from(["deepcli", "--json", "give me a list"]);
+    assert!(matches.get_flag("json"));
+  }
+
+  #[test]
+  fn test_combine_stdin() {
+    let log = "Traceback (most recent call last):\n  File \"a.py\", line 1\n\n";
+    assert_eq!(
+      combine_stdin(Some(" explain this stack trace "), Some(log)),
+      "explain this stack trace\n\nTraceback (most recent call last):\n  File \"a.py\", line 1"
+    );
+    // 只有管道输入时它就是问题，缩进保留
+    assert_eq!(combine_stdin(None, Some("  x = 1\n")), "  x = 1");
+    // 管道中没有内容（例如 < /dev/null）时只用命令行的问题
+    assert_eq!(combine_stdin(Some("hi"), Some("\n \n")), "hi");
+    assert_eq!(combine_stdin(None, Some("")), "");
+    assert_eq!(combine_stdin(Some("  "), None), "");
+  }
+
+  #[test]
+  fn test_file_flag() {
+    let matches = build_cli().get_matches_from(["deepcli", "-f", "src/main.rs", "review this"]);
+    assert_eq!(
+      matches.get_one::<std::path::PathBuf>("file").unwrap(),
+      std::path::Path::new("src/main.rs")
+    );
+    assert_eq!(
+      run_mode(&matches, true),
+      RunMode::OneShot(Some("review this"))
+    );
+    // 只有文件时问题从 stdin 读取，不进入交互模式
+    let matches = build_cli().get_matches_from(["deepcli", "--file", "shot.png"]);
+    assert_eq!(run_mode(&matches, true), RunMode::OneShot(None));
+    assert!(
+      build_cli()
+        .try_get_matches_from(["deepcli", "-i", "-f", "a.rs"])
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_output_flags() {
+    let matches =
+      build_cli().get_matches_from(["deepcli", "-o", "out.md", "--force", "写一份文档"]);
+    assert_eq!(
+      matches.get_one::<std::path::PathBuf>("output").unwrap(),
+      std::path::Path::new("out.md")
+    );
+    assert!(matches.get_flag("force"));
+    // 与 --file 相同，没有问题时从 stdin 读取
+    let matches = build_cli().get_matches_from(["deepcli", "--output", "out.md"]);
+    assert_eq!(run_mode(&matches, true), RunMode::OneShot(None));
+    for args in [
+      &["deepcli", "-i", "-o", "out.md"][..],
+      &["deepcli", "--output-append", "hi"],
+      &[
+        "deepcli",
+        "-o",
+        "out.md",
+        "--output-append",
+        "--force",
+        "hi",
+      ],
+    ] {
+      assert!(
+        build_cli().try_get_matches_from(args).is_err(),
+        "{:?}",
+        args
+      );
+    }
+  }
+
+  #[test]
+  fn test_json_schema_flag() {
+    let matches = build_cli().get_matches_from(["deepcli", "--json-schema", "report.json"]);
+    assert_eq!(
+      matches
+        .get_one::<std::path::PathBuf>("json_schema")
+        .unwrap(),
+      std::path::Path::new("report.json")
+    );
+    // 只有单次提问检查回答
+    assert_eq!(run_mode(&matches, true), RunMode::OneShot(None));
+    assert!(
+      build_cli()
+        .try_get_matches_from(["deepcli", "-i", "--json-schema", "report.json"])
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_show_config_flag() {
+    let matches = build_cli().get_matches_from(["deepcli", "--show-config", "--json"]);
+    assert!(matches.get_flag("show_config"));
+    assert!(matches.get_flag("json"));
+    assert!(
+      !build_cli()
+        .get_matches_from(["deepcli"])
+        .get_flag("show_config")
+    );
+  }
+
+  #[test]
+  fn test_config_flag() {
+    let matches = build_cli().get_matches_from(["deepcli", "--config", "/tmp/deepcli.toml"]);
+    assert_eq!(
+      matches.get_one::<std::path::PathBuf>("config").unwrap(),
+      std::path::Path::new("/tmp/deepcli.toml")
+    );
+    assert!(
+      build_cli()
+        .get_matches_from(["deepcli"])
+        .get_one::<std::path::PathBuf>("config")
+        .is_none()
+    );
+  }
+
+  #[test]
+  fn test_base_url_flag() {
+    let matches = build_cli().get_matches_from([
+      "deepcli",
+      "--base-url",
+      "http://localhost:8000/v1/chat/completions",
+      "hi",
+    ]);
+    assert_eq!(
+      matches.get_one::<String>("base_url").unwrap(),
+      "http://localhost:8000/v1"
+    );
+    let err = build_cli()
+      .try_get_matches_from(["deepcli", "--base-url", "localhost:8000", "hi"])
+      .unwrap_err();
+    assert!(err.to_string().contains("http://"), "{}", err);
+  }
+
+  #[test]
+  fn test_retries_flag() {
+    let matches = build_cli().get_matches_from(["deepcli", "hi"]);
+    assert_eq!(matches.get_one::<u32>("retries"), Some(&2));
+    let matches = build_cli().get_matches_from(["deepcli", "--retries", "0", "hi"]);
+    assert_eq!(matches.get_one::<u32>("retries"), Some(&0));
+    assert!(
+      build_cli()
+        .try_get_matches_from(["deepcli", "--retries", "-1", "hi"])
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_no_stream_flag() {
+    let matches = build_cli().get_matches_from(["deepcli", "--no-stream", "--json", "hi"]);
+    assert!(matches.get_flag("no_stream"));
+    assert_eq!(run_mode(&matches, true), RunMode::OneShot(Some("hi")));
+    assert!(
+      !build_cli()
+        .get_matches_from(["deepcli", "hi"])
+        .get_flag("no_stream")
+    );
+  }
+
+  #[test]
+  fn test_resume_flag() {
+    let matches = build_cli().get_matches_from(["deepcli", "--resume"]);
+    assert!(matches.get_flag("resume"));
+    assert_eq!(run_mode(&matches, false), RunMode::Interactive(None));
+    assert!(
+      build_cli()
+        .try_get_matches_from(["deepcli", "--resume", "hi"])
+        .is_err()
+    );
+    assert!(
+      build_cli()
+        .try_get_matches_from(["deepcli", "--resume", "--import", "a.json"])
+        .is_err()
+    );
+  }
+
+  #[test]
+  fn test_system_flags() {
+    let matches = build_cli().get_matches_from(["deepcli", "--system", "You translate."]);
+    assert_eq!(
+      matches.get_one::<String>("system").unwrap(),
+      "You translate."
+    );
+    let matches = build_cli().get_matches_from(["deepcli", "--system-file", "review.md"]);
+    assert_eq!(
+      matches
+        .get_one::<std::path::PathBuf>("system_file")
+        .unwrap(),
+      std::path::Path::new("review.md")
+    );
+    assert!(
+      build_cli()
+        .try_get_matches_from(["deepcli", "--system", "x", "--system-file", "y"])
+        .is_err()
+    );
+  }
 }
@@ -0,0 +1,163 @@
+//! 通过 OSC 52 序列把回答写入剪贴板：序列由终端处理，SSH 会话中同样有效。
+//! tmux 和 screen 要用 DCS 透传才会转给外层终端；screen 限制每段字符串的长度，需要分段发送
+
+use base64::Engine;
+
+/// 超过这个字节数不复制：许多终端会静默丢弃过长的序列
+pub const MAX_BYTES: usize = 100 * 1024;
+/// screen 的 DCS 字符串最多 768 字节，每段留出余量
+const SCREEN_CHUNK: usize = 512;
+
+/// 序列怎样到达真正的终端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terminal {
+  Direct,
+  /// 包在 tmux 的 DCS 透传中（需要 allow-passthrough）
+  Tmux,
+  /// 分段包在 screen 的 DCS 中
+  Screen,
+}
+
+impl Terminal {
+  /// 按环境变量判断；已知不支持 OSC 52 的终端返回原因
+  pub fn detect(env: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+    let term = env("TERM").unwrap_or_default();
+    if matches!(term.as_str(), "dumb" | "linux") {
+      return Err(format!("终端 TERM={} 不支持 OSC 52", term));
+    }
+    if env("TMUX").is_some() {
+      return Ok(Self::Tmux);
+    }
+    if term.starts_with("screen") {
+      return Ok(Self::Screen);
+    }
+    if env("TERM_PROGRAM").is_some_and(|p| p == "Apple_Terminal") {
+      return Err("macOS 终端不支持 OSC 52".to_string());
+    }
+    if env("VTE_VERSION").is_some() {
+      return Err("基于 VTE 的终端（GNOME 终端等）不支持 OSC 52".to_string());
+    }
+    Ok(Self::Direct)
+  }
+}
+
+/// 能否复制：隔离模式、stdout 不是终端或终端不支持时返回原因
+pub fn available(
+  isolation: &crate::isolation::Isolation,
+  stdout_is_terminal: bool,
+  env: impl Fn(&str) -> Option<String>,
+) -> Result<Terminal, String> {
+  isolation
+    .check(crate::isolation::Feature::Clipboard)
+    .map_err(|e| e.to_string())?;
+  match stdout_is_terminal {
+    true => Terminal::detect(env),
+    false => Err("stdout is not a terminal".to_string()),
+  }
+}
+
+/// 把 `text` 写入系统剪贴板的序列；超过 MAX_BYTES 时返回说明
+pub fn sequence(text: &str, terminal: Terminal) -> Result<String, String> {
+  if text.len() > MAX_BYTES {
+    return Err(format!(
+      "回答有 {} 字节，超过 OSC 52 的上限 {} 字节，没有复制",
+      text.len(),
+      MAX_BYTES
+    ));
+  }
+  let data = base64::engine::general_purpose::STANDARD.encode(text);
+  let osc = format!("\x1b]52;c;{}\x07", data);
+  Ok(match terminal {
+    Terminal::Direct => osc,
+    // 透传内容中的 ESC 要写两次；以 BEL 结束的序列只有开头一个
+    Terminal::Tmux => format!("\x1bPtmux;\x1b{}\x1b\\", osc),
+    Terminal::Screen => osc
+      .as_bytes()
+      .chunks(SCREEN_CHUNK)
+      .map(|chunk| format!("\x1bP{}\x1b\\", String::from_utf8_lossy(chunk)))
+      .collect(),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  #[test]
+  fn test_sequences() {
+    // "你好" 的 base64 为 5L2g5aW9
+    assert_eq!(
+      sequence("你好", Terminal::Direct).unwrap(),
+      "\x1b]52;c;5L2g5aW9\x07"
+    );
+    assert_eq!(
+      sequence("你好", Terminal::Tmux).unwrap(),
+      "\x1bPtmux;\x1b\x1b]52;c;5L2g5aW9\x07\x1b\\"
+    );
+    assert_eq!(
+      sequence("你好", Terminal::Screen).unwrap(),
+      "\x1bP\x1b]52;c;5L2g5aW9\x07\x1b\\"
+    );
+
+    // 较长的内容在 screen 中分段，拼起来是完整的序列
+    let text = "x".repeat(1000);
+    let chunked = sequence(&text, Terminal::Screen).unwrap();
+    let parts: Vec<&str> = chunked.split("\x1b\\").filter(|p| !p.is_empty()).collect();
+    let data = base64::engine::general_purpose::STANDARD.encode(&text);
+    let osc = format!("\x1b]52;c;{}\x07", data);
+    assert_eq!(parts.len(), osc.len().div_ceil(SCREEN_CHUNK));
+    assert!(parts.iter().all(|p| p.starts_with("\x1bP")));
+    assert!(
+      parts[..parts.len() - 1]
+        .iter()
+        .all(|p| p.len() == 2 + SCREEN_CHUNK)
+    );
+    let joined: String = parts.iter().map(|p| &p[2..]).collect();
+    assert_eq!(joined, osc);
+
+    assert!(sequence(&"x".repeat(MAX_BYTES), Terminal::Direct).is_ok());
+    let error = sequence(&"x".repeat(MAX_BYTES + 1), Terminal::Direct).unwrap_err();
+    assert!(error.contains("超过 OSC 52 的上限"), "{}", error);
+  }
+
+  #[test]
+  fn test_detect_terminal() {
+    // None 表示不支持
+    type Case = (&'static [(&'static str, &'static str)], Option<Terminal>);
+    let cases: &[Case] = &[
+      (&[("TERM", "xterm-256color")], Some(Terminal::Direct)),
+      (&[], Some(Terminal::Direct)),
+      (
+        &[
+          ("TERM", "screen-256color"),
+          ("TMUX", "/tmp/tmux-1000/default"),
+        ],
+        Some(Terminal::Tmux),
+      ),
+      (&[("TERM", "screen")], Some(Terminal::Screen)),
+      (&[("TERM", "dumb")], None),
+      (&[("TERM", "linux")], None),
+      (&[("TERM_PROGRAM", "Apple_Terminal")], None),
+      (&[("TERM", "xterm-256color"), ("VTE_VERSION", "7600")], None),
+    ];
+    for (vars, expected) in cases {
+      let env: HashMap<&str, &str> = vars.iter().copied().collect();
+      let detected = Terminal::detect(|key| env.get(key).map(|v| v.to_string()));
+      assert_eq!(detected.ok(), *expected, "{:?}", vars);
+    }
+  }
+
+  #[test]
+  fn test_isolated_mode_has_no_clipboard() {
+    use crate::isolation::Isolation;
+    let env = |_: &str| None;
+    let error = available(&Isolation::new(true), true, env).unwrap_err();
+    assert!(error.contains("--isolated"), "{}", error);
+    assert_eq!(
+      available(&Isolation::new(false), true, env),
+      Ok(Terminal::Direct)
+    );
+    assert!(available(&Isolation::new(false), false, env).is_err());
+  }
+}
@@ -0,0 +1,237 @@
+//! 自定义接口地址的形态检查：`--base-url` 指向的不是 OpenAI 兼容的对话接口时（只写了主机名、
+//! 网页界面地址、DashScope 原生接口），之后的错误只是看不懂的 JSON 解析失败。
+//! 会话第一次使用非内置地址前发一个最小请求，按响应判断；需要补 `/v1` 时自动改正并记在缓存中
+
+use crate::checkpoint::write_atomic;
+use crate::profile::BUILTIN_PROFILES;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// 缓存目录中记住的地址改正
+pub const CACHE_FILE: &str = "compat.json";
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+/// 只保留响应体的开头用于判断
+pub const BODY_LIMIT: usize = 4096;
+const DASHSCOPE_COMPATIBLE: &str = "https://dashscope.aliyuncs.com/compatible-mode/v1";
+
+/// 检查请求收到的响应
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+  pub status: u16,
+  pub content_type: String,
+  pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+  /// 返回了 OpenAI 形式的回答或错误（例如模型不存在、密钥无效）
+  Compatible,
+  /// `/chat/completions` 不存在，地址可能少了 `/v1`
+  NeedsV1,
+  /// DashScope 原生接口，而不是 compatible-mode
+  NativeDashscope,
+  /// 网页登录界面
+  LoginPage,
+  Unknown(String),
+}
+
+/// 内置配置的地址不需要检查
+pub fn is_builtin(base_url: &str) -> bool {
+  BUILTIN_PROFILES.iter().any(|p| p.base_url == base_url)
+}
+
+/// 补上 `/v1` 的地址；已经以 `/v1` 结尾时返回 None
+pub fn with_v1(base_url: &str) -> Option<String> {
+  let base_url = base_url.trim_end_matches('/');
+  (!base_url.ends_with("/v1")).then(|| format!("{}/v1", base_url))
+}
+
+/// 按状态码、Content-Type 和响应体的结构判断接口形态
+pub fn classify(base_url: &str, response: &Response) -> Shape {
+  if base_url.contains("dashscope") && base_url.contains("/api/v1") {
+    return Shape::NativeDashscope;
+  }
+  let content_type = response.content_type.to_ascii_lowercase();
+  if let Ok(Value::Object(body)) = serde_json::from_str::<Value>(&response.body) {
+    if body.get("choices").is_some_and(Value::is_array)
+      || body.get("error").is_some_and(Value::is_object)
+    {
+      return Shape::Compatible;
+    }
+    // 原生接口的响应：{"code": …, "message": …, "request_id": …} 或 {"output": …, "request_id": …}
+    if body.contains_key("request_id") && (body.contains_key("code") || body.contains_key("output"))
+    {
+      return Shape::NativeDashscope;
+    }
+  }
+  let body = response.body.to_lowercase();
+  let html = content_type.contains("text/html") || body.trim_start().starts_with('<');
+  if html
+    && [
+      "type=\"password\"",
+      "type='password'",
+      "login",
+      "sign in",
+      "登录",
+    ]
+    .iter()
+    .any(|marker| body.contains(marker))
+  {
+    return Shape::LoginPage;
+  }
+  if response.status == 404 && with_v1(base_url).is_some() {
+    return Shape::NeedsV1;
+  }
+  let content_type = match content_type.split(';').next().unwrap_or("").trim() {
+    "" => "no content type".to_string(),
+    kind => kind.to_string(),
+  };
+  Shape::Unknown(format!("HTTP {}, {}", response.status, content_type))
+}
+
+/// 无法继续时的错误说明
+pub fn explain(shape: &Shape, base_url: &str) -> String {
+  let skip = "use --no-compat-check to skip this check";
+  match shape {
+    Shape::NativeDashscope => format!(
+      "{} looks like the native DashScope API; deepcli needs the OpenAI-compatible endpoint, use --base-url {} or --profile dashscope ({})",
+      base_url, DASHSCOPE_COMPATIBLE, skip
+    ),
+    Shape::LoginPage => format!(
+      "{} returned an HTML login page instead of an API response; point --base-url at the API endpoint rather than the web UI ({})",
+      base_url, skip
+    ),
+    Shape::NeedsV1 => format!(
+      "{}/chat/completions does not exist (HTTP 404), also with /v1 appended; check --base-url ({})",
+      base_url, skip
+    ),
+    Shape::Compatible | Shape::Unknown(_) => format!("{} is not a chat endpoint", base_url),
+  }
+}
+
+/// 输入的地址到改正后地址的对应
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cache {
+  #[serde(default)]
+  corrections: BTreeMap<String, String>,
+}
+
+impl Cache {
+  /// 文件不存在或无法解析时从空缓存开始
+  pub fn load(path: &Path) -> Self {
+    std::fs::read(path)
+      .ok()
+      .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+      .unwrap_or_default()
+  }
+
+  pub fn save(&self, path: &Path) -> Result<()> {
+    let json = serde_json::to_vec_pretty(self).context("Failed to serialize compat cache")?;
+    write_atomic(path, &json)
+  }
+
+  pub fn correction(&self, base_url: &str) -> Option<&str> {
+    self.corrections.get(base_url).map(String::as_str)
+  }
+
+  pub fn remember(&mut self, base_url: &str, corrected: &str) {
+    self
+      .corrections
+      .insert(base_url.to_string(), corrected.to_string());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+
+  /// 测试数据中的响应：状态行、响应头、空行、响应体
+  fn fixture(text: &str) -> Response {
+    let (head, body) = text.split_once("\n\n").unwrap();
+    let mut lines = head.lines();
+    let status = lines.next().unwrap().split(' ').nth(1).unwrap();
+    let content_type = lines
+      .find_map(|l| l.strip_prefix("content-type: "))
+      .unwrap_or_default();
+    Response {
+      status: status.parse().unwrap(),
+      content_type: content_type.to_string(),
+      body: body.to_string(),
+    }
+  }
+
+  #[test]
+  fn test_classify_fixtures() {
+    let host = "http://gateway.internal:8000";
+    let cases = [
+      (
+        include_str!("testdata/compat/compatible.http"),
+        Shape::Compatible,
+      ),
+      (
+        include_str!("testdata/compat/openai_error.http"),
+        Shape::Compatible,
+      ),
+      (
+        include_str!("testdata/compat/bare_host.http"),
+        Shape::NeedsV1,
+      ),
+      (
+        include_str!("testdata/compat/dashscope_native.http"),
+        Shape::NativeDashscope,
+      ),
+      (
+        include_str!("testdata/compat/login_page.http"),
+        Shape::LoginPage,
+      ),
+      (
+        include_str!("testdata/compat/unknown.http"),
+        Shape::Unknown("HTTP 502, text/plain".to_string()),
+      ),
+    ];
+    for (text, expected) in cases {
+      assert_eq!(classify(host, &fixture(text)), expected, "{}", text);
+    }
+    // 已经以 /v1 结尾时 404 不再建议补 /v1
+    let not_found = fixture(include_str!("testdata/compat/bare_host.http"));
+    assert_eq!(
+      classify("http://gateway.internal:8000/v1", &not_found),
+      Shape::Unknown("HTTP 404, application/json".to_string())
+    );
+    // 原生接口的路径不用等响应就能认出
+    let ok = fixture(include_str!("testdata/compat/compatible.http"));
+    assert_eq!(
+      classify("https://dashscope.aliyuncs.com/api/v1", &ok),
+      Shape::NativeDashscope
+    );
+    assert!(explain(&Shape::LoginPage, host).contains("--no-compat-check"));
+  }
+
+  #[test]
+  fn test_builtin_urls_and_remembered_corrections() {
+    assert!(is_builtin("https://api.deepseek.com"));
+    assert!(!is_builtin("http://localhost:8000"));
+    assert_eq!(
+      with_v1("http://localhost:8000/").as_deref(),
+      Some("http://localhost:8000/v1")
+    );
+    assert_eq!(with_v1("http://localhost:8000/v1"), None);
+
+    let dir = temp_dir("compat-cache");
+    let path = dir.join(CACHE_FILE);
+    let mut cache = Cache::load(&path);
+    assert_eq!(cache.correction("http://localhost:8000"), None);
+    cache.remember("http://localhost:8000", "http://localhost:8000/v1");
+    cache.save(&path).unwrap();
+    assert_eq!(
+      Cache::load(&path).correction("http://localhost:8000"),
+      Some("http://localhost:8000/v1")
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
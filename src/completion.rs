@@ -0,0 +1,251 @@
+use crate::session::SessionStore;
+use std::fs;
+use std::path::Path;
+
+/// REPL 支持的反斜杠命令，供补全和帮助使用
+pub const COMMANDS: &[&str] = &[
+  "\\q",
+  "\\c",
+  "\\undo",
+  "\\h",
+  "\\drop",
+  "\\stats",
+  "\\tokens",
+  "\\headers",
+  "\\config",
+  "\\checkpoints",
+  "\\sessions",
+  "\\rename",
+  "\\describe",
+  "\\delete",
+  "\\reload",
+  "\\brief",
+  "\\render",
+  "\\reasoning",
+  "\\route",
+  "\\file",
+  "\\goto",
+  "\\import",
+  "\\save",
+  "\\load",
+  "\\note",
+  "\\notes",
+  "\\export",
+  "\\amend",
+  "\\setvar",
+  "\\unsetvar",
+  "\\vars",
+  "\\as",
+  "\\attach",
+  "\\detach",
+  "\\attachments",
+  "\\contract",
+  "\\copy",
+  "\\verify",
+  "\\keep-verification",
+  "\\set",
+];
+
+/// 参数为文件路径的命令
+const PATH_COMMANDS: &[&str] = &["\\file", "\\attach", "\\detach", "\\import", "\\export"];
+
+/// 参数为会话名的命令
+const SESSION_COMMANDS: &[&str] = &["\\rename", "\\describe", "\\delete"];
+
+/// 参数可以是会话名也可以是文件路径的命令
+const SESSION_OR_PATH_COMMANDS: &[&str] = &["\\save", "\\load"];
+
+/// 交互模式的补全来源：命令、会话名、文件路径与设置项
+pub struct Completer {
+  store: Option<SessionStore>,
+  settings: Vec<String>,
+}
+
+impl Completer {
+  pub fn new(store: Option<SessionStore>, settings: Vec<String>) -> Self {
+    Self { store, settings }
+  }
+
+  /// 返回被替换部分的起始字节位置和候选列表
+  pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+    let line = &line[..pos];
+    let Some((command, arg)) = line.split_once(' ') else {
+      if line.starts_with('\\') {
+        return (0, complete_command(line));
+      }
+      return (pos, vec![]);
+    };
+    // 只补全第一个参数
+    if arg.contains(' ') {
+      return (pos, vec![]);
+    }
+    let start = command.len() + 1;
+    let candidates = if PATH_COMMANDS.contains(&command) {
+      complete_path(arg)
+    } else if SESSION_COMMANDS.contains(&command) {
+      self.complete_session(arg)
+    } else if SESSION_OR_PATH_COMMANDS.contains(&command) {
+      let mut candidates = self.complete_session(arg);
+      candidates.extend(complete_path(arg));
+      candidates
+    } else if command == "\\set" {
+      filter_prefix(&self.settings, arg)
+    } else if ["\\brief", "\\render", "\\reasoning", "\\route"].contains(&command) {
+      filter_prefix(&["on".to_string(), "off".to_string()], arg)
+    } else {
+      vec![]
+    };
+    (start, candidates)
+  }
+
+  fn complete_session(&self, prefix: &str) -> Vec<String> {
+    let Some(store) = &self.store else {
+      return vec![];
+    };
+    let names: Vec<String> = store
+      .list()
+      .unwrap_or_default()
+      .into_iter()
+      .map(|(name, _)| name)
+      .collect();
+    filter_prefix(&names, prefix)
+  }
+}
+
+fn filter_prefix(items: &[String], prefix: &str) -> Vec<String> {
+  let mut matches: Vec<String> = items
+    .iter()
+    .filter(|item| item.starts_with(prefix))
+    .cloned()
+    .collect();
+  matches.sort();
+  matches.dedup();
+  matches
+}
+
+pub fn complete_command(prefix: &str) -> Vec<String> {
+  let all: Vec<String> = COMMANDS.iter().map(|c| c.to_string()).collect();
+  filter_prefix(&all, prefix)
+}
+
+/// 目录候选以 `/` 结尾，隐藏文件只有在前缀以 `.` 开头时才列出
+pub fn complete_path(prefix: &str) -> Vec<String> {
+  let (dir, file_prefix) = match prefix.rfind('/') {
+    Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+    None => ("", prefix),
+  };
+  let read_from = if dir.is_empty() {
+    Path::new(".")
+  } else {
+    Path::new(dir)
+  };
+  let Ok(entries) = fs::read_dir(read_from) else {
+    return vec![];
+  };
+  let mut candidates: Vec<String> = entries
+    .filter_map(|e| e.ok())
+    .filter_map(|e| {
+      let name = e.file_name().to_str()?.to_string();
+      if !name.starts_with(file_prefix) || (name.starts_with('.') && !file_prefix.starts_with('.'))
+      {
+        return None;
+      }
+      let suffix = if e.path().is_dir() { "/" } else { "" };
+      Some(format!("{}{}{}", dir, name, suffix))
+    })
+    .collect();
+  candidates.sort();
+  candidates
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::api::Message;
+  use crate::checkpoint::temp_dir;
+
+  fn completer(store: Option<SessionStore>) -> Completer {
+    Completer::new(
+      store,
+      vec!["temperature".to_string(), "max_tokens".to_string()],
+    )
+  }
+
+  #[test]
+  fn test_command_completion() {
+    let c = completer(None);
+    let (start, all) = c.complete("\\", 1);
+    assert_eq!(start, 0);
+    assert!(all.contains(&"\\q".to_string()));
+    assert!(all.contains(&"\\sessions".to_string()));
+
+    let (_, matches) = c.complete("\\re", 3);
+    assert_eq!(matches, ["\\reasoning", "\\reload", "\\rename", "\\render"]);
+    assert_eq!(c.complete("hello", 5), (5, vec![]));
+  }
+
+  #[test]
+  fn test_session_name_completion() {
+    let dir = temp_dir("complete-sessions");
+    let store = SessionStore::new(dir.clone());
+    let messages: Vec<Message> = vec![];
+    for name in ["work", "weekly", "misc"] {
+      store.save(name, &messages, "deepseek-chat", None).unwrap();
+    }
+    let c = completer(Some(SessionStore::new(dir.clone())));
+    let (start, matches) = c.complete("\\delete w", 9);
+    assert_eq!(start, 8);
+    assert_eq!(matches, ["weekly", "work"]);
+    // \load 的参数也可以是文件
+    assert_eq!(c.complete("\\load w", 7).1, ["weekly", "work"]);
+    fs::create_dir(dir.join("out")).unwrap();
+    fs::write(dir.join("out/week.json"), "[]").unwrap();
+    let line = format!("\\load {}/out/w", dir.display());
+    assert_eq!(
+      c.complete(&line, line.len()).1,
+      [format!("{}/out/week.json", dir.display())]
+    );
+    let (_, matches) = c.complete("\\rename m", 9);
+    assert_eq!(matches, ["misc"]);
+    // 第二个参数不补全
+    assert!(c.complete("\\rename misc w", 14).1.is_empty());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_path_completion() {
+    let dir = temp_dir("complete-paths");
+    fs::create_dir(dir.join("src")).unwrap();
+    fs::write(dir.join("src/api.rs"), "").unwrap();
+    fs::write(dir.join("src/main.rs"), "").unwrap();
+    fs::write(dir.join(".hidden"), "").unwrap();
+    let base = format!("{}/", dir.display());
+
+    assert_eq!(complete_path(&base), [format!("{}src/", base)]);
+    assert_eq!(
+      complete_path(&format!("{}src/m", base)),
+      [format!("{}src/main.rs", base)]
+    );
+    assert_eq!(
+      complete_path(&format!("{}.h", base)),
+      [format!("{}.hidden", base)]
+    );
+
+    let c = completer(None);
+    let line = format!("\\file {}src/a", base);
+    let (start, matches) = c.complete(&line, line.len());
+    assert_eq!(start, 6);
+    assert_eq!(matches, [format!("{}src/api.rs", base)]);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_setting_completion() {
+    let c = completer(None);
+    assert_eq!(c.complete("\\set t", 6).1, ["temperature"]);
+    assert_eq!(c.complete("\\brief o", 8).1, ["off", "on"]);
+    assert_eq!(c.complete("\\render of", 10).1, ["off"]);
+    assert_eq!(c.complete("\\rou", 4).1, ["\\route"]);
+    assert_eq!(c.complete("\\route o", 8).1, ["off", "on"]);
+  }
+}
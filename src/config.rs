@@ -0,0 +1,1669 @@
+//! 生效的配置以及每一项来自哪里，用于 --show-config 和 REPL 中的 \config；
+//! 以及配置文件的读取和各层的合并
+
+use crate::api::ReasoningEffort;
+use crate::provenance::{REDACTED, is_secret_key};
+use crate::truncate::TruncateMode;
+use crate::{persona, profile, prompt, widget};
+use clap::parser::ValueSource;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// 一个设置项的值由哪一层决定
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+  /// 内置默认值
+  Default,
+  /// 命令行参数，如 "--model"
+  Flag(&'static str),
+  /// 真实环境变量
+  Env(String),
+  /// .env 文件中的变量
+  DotEnv(String),
+  /// 配置文件，值为路径
+  File(String),
+  /// 人设文件的 front-matter，值为人设名
+  Persona(String),
+  /// 回答长度预设：brief、detailed
+  Preset(&'static str),
+  /// 模型表中的默认输出上限
+  Model(String),
+  /// 内置的服务提供方配置
+  Profile(&'static str),
+  /// 启动时选择的模板
+  Starter(String),
+  /// 会话中的命令，如 "\\brief on"
+  Runtime(String),
+}
+
+impl std::fmt::Display for Source {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Source::Default => write!(f, "default"),
+      Source::Flag(flag) => write!(f, "from {}", flag),
+      Source::Env(key) => write!(f, "from env {}", key),
+      Source::DotEnv(key) => write!(f, "from .env {}", key),
+      Source::File(path) => write!(f, "from {}", path),
+      Source::Persona(name) => write!(f, "from persona '{}'", name),
+      Source::Preset(name) => write!(f, "from preset '{}'", name),
+      Source::Model(model) => write!(f, "output limit of '{}'", model),
+      Source::Profile(name) => write!(f, "from profile '{}'", name),
+      Source::Starter(title) => write!(f, "from starter '{}'", title),
+      Source::Runtime(command) => write!(f, "set by {}", command),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Str(String),
+  Int(u64),
+  Float(f64),
+  Bool(bool),
+  /// 字符串数组
+  List(Vec<String>),
+  /// 未设置，由服务端决定
+  Unset,
+}
+
+impl From<&str> for Value {
+  fn from(value: &str) -> Self {
+    Value::Str(value.to_string())
+  }
+}
+
+impl From<String> for Value {
+  fn from(value: String) -> Self {
+    Value::Str(value)
+  }
+}
+
+impl From<u32> for Value {
+  fn from(value: u32) -> Self {
+    Value::Int(value.into())
+  }
+}
+
+impl From<usize> for Value {
+  fn from(value: usize) -> Self {
+    Value::Int(value as u64)
+  }
+}
+
+impl From<f32> for Value {
+  /// 按显示的十进制转换，0.7 不会变成 0.699999988
+  fn from(value: f32) -> Self {
+    Value::Float(value.to_string().parse().unwrap_or(value.into()))
+  }
+}
+
+impl From<bool> for Value {
+  fn from(value: bool) -> Self {
+    Value::Bool(value)
+  }
+}
+
+impl From<Vec<String>> for Value {
+  fn from(value: Vec<String>) -> Self {
+    Value::List(value)
+  }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+  fn from(value: Option<T>) -> Self {
+    value.map_or(Value::Unset, Into::into)
+  }
+}
+
+impl Value {
+  fn toml(&self) -> String {
+    match self {
+      Value::Str(s) => serde_json::to_string(s).unwrap(),
+      Value::Int(n) => n.to_string(),
+      Value::Float(x) => format!("{:?}", x),
+      Value::Bool(b) => b.to_string(),
+      Value::List(items) => format!(
+        "[{}]",
+        items
+          .iter()
+          .map(|s| serde_json::to_string(s).unwrap())
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+      Value::Unset => String::new(),
+    }
+  }
+
+  fn json(&self) -> serde_json::Value {
+    match self {
+      Value::Str(s) => json!(s),
+      Value::Int(n) => json!(n),
+      Value::Float(x) => json!(x),
+      Value::Bool(b) => json!(b),
+      Value::List(items) => json!(items),
+      Value::Unset => serde_json::Value::Null,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+  pub key: &'static str,
+  pub value: Value,
+  pub source: Source,
+}
+
+impl Entry {
+  /// 密钥类的项只显示来源
+  fn shown(&self) -> Value {
+    match (&self.value, is_secret_key(self.key)) {
+      (Value::Unset, _) | (_, false) => self.value.clone(),
+      (_, true) => Value::Str(REDACTED.to_string()),
+    }
+  }
+}
+
+/// 按启动时解析的顺序保存的设置项，会话中的改动覆盖原来那一项
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+  entries: Vec<Entry>,
+}
+
+impl Config {
+  pub fn set(&mut self, key: &'static str, value: impl Into<Value>, source: Source) {
+    let entry = Entry {
+      key,
+      value: value.into(),
+      source,
+    };
+    match self.entries.iter_mut().find(|e| e.key == key) {
+      Some(existing) => *existing = entry,
+      None => self.entries.push(entry),
+    }
+  }
+
+  /// 带来源注释的 TOML；未设置的项整行注释掉
+  pub fn to_toml(&self) -> String {
+    let lines: Vec<_> = self
+      .entries
+      .iter()
+      .map(|e| match e.shown() {
+        Value::Unset => format!("# {} =", e.key),
+        value => format!("{} = {}", e.key, value.toml()),
+      })
+      .collect();
+    let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    lines
+      .iter()
+      .zip(&self.entries)
+      .map(|(line, e)| format!("{:width$}  # {}", line, e.source, width = width))
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  /// `{"key": {"value": ..., "source": "..."}}`，未设置的值为 null
+  pub fn to_json(&self) -> serde_json::Value {
+    let map = self
+      .entries
+      .iter()
+      .map(|e| {
+        let entry = json!({"value": e.shown().json(), "source": e.source.to_string()});
+        (e.key.to_string(), entry)
+      })
+      .collect::<serde_json::Map<_, _>>();
+    serde_json::Value::Object(map)
+  }
+}
+
+/// 配置文件的默认位置（paths::config_dir() 下）
+pub const FILE_NAME: &str = "config.toml";
+
+/// 可以用环境变量（或 .env）设置的默认值
+pub const ENV_MODEL: &str = "DEEPCLI_MODEL";
+pub const ENV_TEMPERATURE: &str = "DEEPCLI_TEMPERATURE";
+pub const ENV_MAX_TOKENS: &str = "DEEPCLI_MAX_TOKENS";
+pub const ENV_SYSTEM_PROMPT: &str = "DEEPCLI_SYSTEM_PROMPT";
+pub const ENV_BASE_URL: &str = "DEEPCLI_BASE_URL";
+
+/// 配置文件中的默认值。格式是 TOML 的一个子集：每行一个 `key = value`，
+/// 值为字符串、数字、布尔值或单行的字符串数组，`#` 之后是注释；表只支持 `[attach]`，
+/// 也可以写成 `attach.exclude = …`。--show-config 的输出也可以直接使用
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileConfig {
+  pub path: PathBuf,
+  pub profile: Option<String>,
+  pub model: Option<String>,
+  pub temperature: Option<f32>,
+  pub max_tokens: Option<u32>,
+  pub system_prompt: Option<String>,
+  pub api_key: Option<String>,
+  /// 代替提供方默认地址的接口地址
+  pub base_url: Option<String>,
+  /// OTLP 收集器地址，需要 otlp feature
+  pub otlp_endpoint: Option<String>,
+  /// --auto-route 把简单追问交给的模型
+  pub route_model: Option<String>,
+  /// --auto-route 的阈值，0 到 1，越高越少路由
+  pub route_threshold: Option<f32>,
+  /// 被内容审核拦截的一轮保留在历史中
+  pub keep_refusals: Option<bool>,
+  /// 附加图片后显示缩略图的方式；None 为 auto，按终端判断
+  pub inline_images: Option<crate::thumbnail::Protocol>,
+  /// 缩略图最多占的行数
+  pub image_rows: Option<u16>,
+  /// 会话等数据的目录，相对路径相对于配置文件所在目录
+  pub data_dir: Option<PathBuf>,
+  /// 缓存目录，相对路径同上
+  pub cache_dir: Option<PathBuf>,
+  /// 每轮回答完成后用 OSC 52 把原文复制到剪贴板
+  pub copy_on_complete: Option<bool>,
+  /// --verify 和 \verify 的复核提示词，必须包含 `{answer}`
+  pub verify_prompt: Option<String>,
+  /// 交互模式启动时为 deepseek-reasoner 在后台发一个预热请求
+  pub warm_start: Option<bool>,
+  /// `[attach] exclude`：附加目录时排除的路径，gitignore 语法
+  pub attach_exclude: Option<Vec<String>>,
+  /// 默认的输出约定，给出 --contract 时不使用
+  pub output_contract: Option<Vec<String>>,
+  /// 与 --isolated 相同，任一处开启即为隔离模式
+  pub isolated: Option<bool>,
+  /// 每隔多少轮写一次自动快照，给出 --checkpoint-every 时不使用
+  pub checkpoint_every: Option<usize>,
+  /// 快照目录被占用时的处理方式，给出 --lock-conflict 时不使用
+  pub lock_conflict: Option<crate::lock::LockConflict>,
+  /// 与 --send-user-id 相同
+  pub send_user_id: Option<bool>,
+  /// 无障碍输出，环境变量 DEEPCLI_ACCESSIBLE 优先
+  pub accessible: Option<bool>,
+  /// 只允许读取的目录，相对路径相对于配置文件所在目录；--restrict-to 和环境变量优先
+  pub restrict_to: Option<PathBuf>,
+}
+
+/// 温度的取值范围与 --temperature 相同
+fn parse_temperature(s: &str) -> Result<f32, String> {
+  let temp = s.trim().parse::<f32>().map_err(|e| e.to_string())?;
+  crate::cli::validate_temperature(temp)
+}
+
+impl FileConfig {
+  /// `explicit` 为 --config 指定的文件，必须存在；默认位置没有文件时返回空配置。
+  /// 无法使用的行和键不会中止启动，作为警告返回
+  pub fn load(explicit: Option<&Path>) -> anyhow::Result<(Self, Vec<String>)> {
+    use anyhow::Context;
+    let path = match explicit {
+      Some(path) => path.to_path_buf(),
+      None => match crate::paths::config_dir() {
+        Some(dir) => dir.join(FILE_NAME),
+        None => return Ok((Self::default(), vec![])),
+      },
+    };
+    let text = match std::fs::read_to_string(&path) {
+      Ok(text) => text,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound && explicit.is_none() => {
+        return Ok((Self::default(), vec![]));
+      }
+      Err(e) => return Err(e).context(format!("Failed to read config file {:?}", path)),
+    };
+    Ok(Self::parse(&path, &text))
+  }
+
+  pub fn parse(path: &Path, text: &str) -> (Self, Vec<String>) {
+    let mut config = Self {
+      path: path.to_path_buf(),
+      ..Default::default()
+    };
+    let mut warnings = vec![];
+    // 当前所在的表，顶层为 None
+    let mut table = None;
+    for (n, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+      let mut warn = |message: String| {
+        warnings.push(format!("{}:{}: {}", path.display(), n + 1, message));
+      };
+      if line == "[attach]" {
+        table = Some("attach");
+        continue;
+      }
+      let Some((key, value)) = line.split_once('=') else {
+        // 不认识的表头之后的键按顶层处理
+        table = None;
+        warn(format!("expected `key = value`, got {:?}", line));
+        continue;
+      };
+      let dotted;
+      let key = match table {
+        Some(table) => {
+          dotted = format!("{}.{}", table, key.trim());
+          dotted.as_str()
+        }
+        None => key.trim(),
+      };
+      let value = match parse_value(value) {
+        Ok(value) => value,
+        Err(e) => {
+          warn(format!("invalid value for '{}': {}", key, e));
+          continue;
+        }
+      };
+      let result = match (key, &value) {
+        ("profile", Value::Str(name)) => match profile::find_profile(name) {
+          Some(_) => {
+            config.profile = Some(name.clone());
+            Ok(())
+          }
+          None => Err(format!("unknown profile '{}'", name)),
+        },
+        ("model", Value::Str(model)) => {
+          config.model = Some(model.clone());
+          Ok(())
+        }
+        ("temperature", Value::Float(_) | Value::Int(_)) => {
+          let text = value_text(&value);
+          parse_temperature(&text)
+            .map(|t| config.temperature = Some(t))
+            .map_err(|e| format!("{}, got {}", e, text))
+        }
+        ("max_tokens", Value::Int(n)) => match u32::try_from(*n) {
+          Ok(n) if n > 0 => {
+            config.max_tokens = Some(n);
+            Ok(())
+          }
+          _ => Err(format!("must be between 1 and {}, got {}", u32::MAX, n)),
+        },
+        ("system_prompt", Value::Str(prompt)) => {
+          config.system_prompt = Some(prompt.clone());
+          Ok(())
+        }
+        ("api_key", Value::Str(key)) if !key.trim().is_empty() => {
+          config.api_key = Some(key.clone());
+          Ok(())
+        }
+        ("base_url", Value::Str(url)) => crate::api::normalize_base_url(url).map(|url| {
+          config.base_url = Some(url);
+        }),
+        ("base_url", _) => Err("expected a URL string".to_string()),
+        ("otlp_endpoint", Value::Str(url)) if url.starts_with("http") => {
+          config.otlp_endpoint = Some(url.clone());
+          Ok(())
+        }
+        ("otlp_endpoint", _) => Err("expected an http:// or https:// URL".to_string()),
+        ("route_model", Value::Str(model)) if !model.trim().is_empty() => {
+          config.route_model = Some(model.clone());
+          Ok(())
+        }
+        ("route_threshold", Value::Float(_) | Value::Int(_)) => {
+          let text = value_text(&value);
+          match text.parse::<f32>() {
+            Ok(t) if (0.0..=1.0).contains(&t) => {
+              config.route_threshold = Some(t);
+              Ok(())
+            }
+            _ => Err(format!("must be between 0 and 1, got {}", text)),
+          }
+        }
+        ("keep_refusals", Value::Bool(keep)) => {
+          config.keep_refusals = Some(*keep);
+          Ok(())
+        }
+        ("keep_refusals", _) => Err("expected true or false".to_string()),
+        ("copy_on_complete", Value::Bool(copy)) => {
+          config.copy_on_complete = Some(*copy);
+          Ok(())
+        }
+        ("copy_on_complete", _) => Err("expected true or false".to_string()),
+        ("warm_start", Value::Bool(warm)) => {
+          config.warm_start = Some(*warm);
+          Ok(())
+        }
+        ("warm_start", _) => Err("expected true or false".to_string()),
+        ("verify_prompt", Value::Str(template)) => crate::verify::validate(template).map(|()| {
+          config.verify_prompt = Some(template.clone());
+        }),
+        ("verify_prompt", _) => Err("expected a template string containing {answer}".to_string()),
+        ("attach.exclude", Value::List(patterns)) => patterns
+          .iter()
+          .try_for_each(|p| crate::walk::validate(p).map(|_| ()))
+          .map(|_| config.attach_exclude = Some(patterns.clone())),
+        ("attach.exclude", _) => Err("expected an array of strings".to_string()),
+        ("output_contract", Value::List(rules)) => {
+          config.output_contract = Some(rules.clone());
+          Ok(())
+        }
+        ("output_contract", _) => Err("expected an array of strings".to_string()),
+        ("isolated", Value::Bool(isolated)) => {
+          config.isolated = Some(*isolated);
+          Ok(())
+        }
+        ("isolated", _) => Err("expected true or false".to_string()),
+        ("send_user_id", Value::Bool(send)) => {
+          config.send_user_id = Some(*send);
+          Ok(())
+        }
+        ("send_user_id", _) => Err("expected true or false".to_string()),
+        ("accessible", Value::Bool(accessible)) => {
+          config.accessible = Some(*accessible);
+          Ok(())
+        }
+        ("accessible", _) => Err("expected true or false".to_string()),
+        ("checkpoint_every", Value::Int(n)) => {
+          config.checkpoint_every = Some(*n as usize);
+          Ok(())
+        }
+        ("checkpoint_every", _) => Err("expected a number".to_string()),
+        ("lock_conflict", Value::Str(mode)) => mode.parse().map(|mode| {
+          config.lock_conflict = Some(mode);
+        }),
+        ("lock_conflict", _) => Err("expected suffix or read-only".to_string()),
+        ("inline_images", Value::Str(value)) => {
+          crate::thumbnail::Protocol::parse(value).map(|p| config.inline_images = p)
+        }
+        ("inline_images", _) => Err("expected auto, kitty, iterm2 or plain".to_string()),
+        ("image_rows", Value::Int(n)) => match u16::try_from(*n) {
+          Ok(n @ 1..=50) => {
+            config.image_rows = Some(n);
+            Ok(())
+          }
+          _ => Err(format!("must be between 1 and 50, got {}", n)),
+        },
+        ("image_rows", _) => Err("expected a number".to_string()),
+        ("data_dir" | "cache_dir" | "restrict_to", Value::Str(dir)) if !dir.trim().is_empty() => {
+          let dir = path.parent().unwrap_or(Path::new("")).join(dir);
+          match key {
+            "data_dir" => config.data_dir = Some(dir),
+            "cache_dir" => config.cache_dir = Some(dir),
+            _ => config.restrict_to = Some(dir),
+          }
+          Ok(())
+        }
+        (
+          "profile" | "model" | "system_prompt" | "api_key" | "route_model" | "data_dir"
+          | "cache_dir" | "restrict_to",
+          _,
+        ) => Err("expected a non-empty string".to_string()),
+        ("temperature" | "max_tokens" | "route_threshold", _) => {
+          Err("expected a number".to_string())
+        }
+        _ => Err("unknown key, ignored".to_string()),
+      };
+      if let Err(e) = result {
+        warn(format!("'{}': {}", key, e));
+      }
+    }
+    (config, warnings)
+  }
+
+  pub fn source(&self) -> Source {
+    Source::File(self.path.display().to_string())
+  }
+}
+
+/// 有默认值的参数：命令行给出时优先，其次是配置文件中的值，最后是参数的默认值
+pub fn flag_or_file<T: Clone + Send + Sync + 'static>(
+  matches: &clap::ArgMatches,
+  id: &str,
+  file: Option<T>,
+) -> T {
+  match (matches.value_source(id), file) {
+    (Some(ValueSource::CommandLine), _) | (_, None) => matches.get_one::<T>(id).unwrap().clone(),
+    (_, Some(value)) => value,
+  }
+}
+
+/// 数字的原文，整数写法的温度也按小数解析
+fn value_text(value: &Value) -> String {
+  match value {
+    Value::Int(n) => n.to_string(),
+    Value::Float(x) => x.to_string(),
+    other => other.toml(),
+  }
+}
+
+/// `=` 右侧的值：字符串（"…" 或 '…'）、整数、小数、布尔值或字符串数组，后面可以跟注释
+fn parse_value(text: &str) -> Result<Value, String> {
+  let text = text.trim();
+  let (value, rest) = match text.strip_prefix('[') {
+    Some(body) => parse_list(body)?,
+    None => parse_scalar(text)?,
+  };
+  let rest = rest.trim();
+  if !rest.is_empty() && !rest.starts_with('#') {
+    return Err(format!("unexpected {:?} after the value", rest));
+  }
+  Ok(value)
+}
+
+/// `[` 之后的单行字符串数组，允许末尾的逗号；返回数组和 `]` 之后的内容
+fn parse_list(mut body: &str) -> Result<(Value, &str), String> {
+  let mut items = vec![];
+  loop {
+    body = body.trim_start();
+    if let Some(rest) = body.strip_prefix(']') {
+      return Ok((Value::List(items), rest));
+    }
+    if body.is_empty() || body.starts_with('#') {
+      return Err("unterminated array (arrays must fit on one line)".to_string());
+    }
+    let (item, rest) = match body.starts_with(['"', '\'']) {
+      true => parse_scalar(body)?,
+      false => return Err("arrays may only contain strings".to_string()),
+    };
+    let Value::Str(item) = item else {
+      unreachable!()
+    };
+    items.push(item);
+    let rest = rest.trim_start();
+    body = match rest.strip_prefix(',') {
+      Some(rest) => rest,
+      None if rest.starts_with(']') => rest,
+      None if rest.is_empty() || rest.starts_with('#') => rest,
+      None => return Err(format!("expected ',' or ']' in array, got {:?}", rest)),
+    };
+  }
+}
+
+/// 一个字符串、数字或布尔值，返回它和之后的内容
+fn parse_scalar(text: &str) -> Result<(Value, &str), String> {
+  let (value, rest) = if let Some(body) = text.strip_prefix('"') {
+    if body.starts_with("\"\"") {
+      return Err("multi-line strings are not supported".to_string());
+    }
+    // 找到第一个没有被转义的引号
+    let mut escaped = false;
+    let end = body
+      .char_indices()
+      .find(|&(_, c)| {
+        let found = c == '"' && !escaped;
+        escaped = c == '\\' && !escaped;
+        found
+      })
+      .map(|(i, _)| i)
+      .ok_or("unterminated string")?;
+    let quoted = &text[..end + 2];
+    let value: String = serde_json::from_str(quoted).map_err(|e| e.to_string())?;
+    (Value::Str(value), &body[end + 1..])
+  } else if let Some(body) = text.strip_prefix('\'') {
+    let end = body.find('\'').ok_or("unterminated string")?;
+    (Value::Str(body[..end].to_string()), &body[end + 1..])
+  } else {
+    let (token, rest) = text.split_at(text.find('#').unwrap_or(text.len()));
+    let token = token.trim();
+    let value = match token {
+      "true" => Value::Bool(true),
+      "false" => Value::Bool(false),
+      _ if token.contains(['.', 'e', 'E']) => Value::Float(
+        token
+          .parse()
+          .map_err(|_| format!("not a value: {}", token))?,
+      ),
+      _ => Value::Int(
+        token
+          .replace('_', "")
+          .parse()
+          .map_err(|_| format!("not a value: {}", token))?,
+      ),
+    };
+    (value, rest)
+  };
+  Ok((value, rest))
+}
+
+/// 取第一个有值的层
+pub fn pick<T>(layers: impl IntoIterator<Item = (Option<T>, Source)>) -> Option<(T, Source)> {
+  layers
+    .into_iter()
+    .find_map(|(value, source)| value.map(|v| (v, source)))
+}
+
+/// 启动时逐层解析的设置：命令行 > 人设 > 环境变量（或 .env）> 配置文件 > 默认值
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layered {
+  /// 映射到提供方的模型名之前的输入
+  pub model: (String, Source),
+  pub temperature: (Option<f32>, Source),
+  /// 显式要求的输出上限；`None` 时由回答长度和模型决定
+  pub max_tokens: (Option<u32>, Source),
+  pub system_prompt: (String, Source),
+  /// 已规范化的接口地址；`None` 时使用提供方的默认地址
+  pub base_url: (Option<String>, Source),
+  /// 环境变量中无法使用的值
+  pub warnings: Vec<String>,
+}
+
+/// 没有值的层来源记为默认值
+fn unzip<T>(layer: Option<(T, Source)>) -> (Option<T>, Source) {
+  match layer {
+    Some((value, source)) => (Some(value), source),
+    None => (None, Source::Default),
+  }
+}
+
+/// 环境变量这一层，无法解析的值记一条警告后跳过
+fn env_layer<T>(
+  env: &impl Fn(&str) -> Option<(String, Source)>,
+  key: &str,
+  parse: impl Fn(&str) -> Result<T, String>,
+  warnings: &mut Vec<String>,
+) -> (Option<T>, Source) {
+  let layer = env(key).and_then(|(value, source)| match parse(&value) {
+    Ok(parsed) => Some((parsed, source)),
+    Err(e) => {
+      warnings.push(format!("{}={:?} ignored: {}", key, value, e));
+      None
+    }
+  });
+  unzip(layer)
+}
+
+/// --system 或 --system-file 给出的 system prompt
+pub fn system_flag(matches: &clap::ArgMatches) -> anyhow::Result<Option<(String, Source)>> {
+  if let Some(text) = matches.get_one::<String>("system") {
+    return Ok(Some((text.clone(), Source::Flag("--system"))));
+  }
+  let Some(path) = matches.get_one::<PathBuf>("system_file") else {
+    return Ok(None);
+  };
+  let text = crate::sandbox::read_to_string(path)?;
+  let text = text.trim();
+  if text.is_empty() {
+    anyhow::bail!("--system-file {:?} is empty", path);
+  }
+  Ok(Some((text.to_string(), Source::Flag("--system-file"))))
+}
+
+/// `system` 为 system_flag 的结果，`env` 返回环境变量（或 .env）的值及其来源
+pub fn layered(
+  matches: &clap::ArgMatches,
+  persona: Option<&persona::Persona>,
+  system: Option<(String, Source)>,
+  env: impl Fn(&str) -> Option<(String, Source)>,
+  file: &FileConfig,
+) -> Layered {
+  let mut warnings = vec![];
+  let env_model = env_layer(&env, ENV_MODEL, |v| Ok(v.to_string()), &mut warnings);
+  let env_temperature = env_layer(&env, ENV_TEMPERATURE, parse_temperature, &mut warnings);
+  let env_max_tokens = env_layer(
+    &env,
+    ENV_MAX_TOKENS,
+    |v| v.trim().parse::<u32>().map_err(|e| e.to_string()),
+    &mut warnings,
+  );
+  let env_system = env_layer(
+    &env,
+    ENV_SYSTEM_PROMPT,
+    |v| Ok(v.to_string()),
+    &mut warnings,
+  );
+  let env_base_url = env_layer(
+    &env,
+    ENV_BASE_URL,
+    crate::api::normalize_base_url,
+    &mut warnings,
+  );
+  // 有默认值的 --model 只有在命令行给出时才算一层
+  let model_flag = matches
+    .get_one::<String>("model")
+    .filter(|_| matches.value_source("model") == Some(ValueSource::CommandLine));
+  let persona_source = || persona.map_or(Source::Default, |p| Source::Persona(p.name()));
+  let model = pick([
+    (model_flag.cloned(), Source::Flag("--model")),
+    (persona.and_then(|p| p.model.clone()), persona_source()),
+    env_model,
+    (file.model.clone(), file.source()),
+  ])
+  .unwrap_or_else(|| {
+    let default = matches.get_one::<String>("model").unwrap().clone();
+    (default, Source::Default)
+  });
+  let temperature = pick([
+    (
+      matches.get_one::<f32>("temperature").copied(),
+      Source::Flag("--temperature"),
+    ),
+    (persona.and_then(|p| p.temperature), persona_source()),
+    env_temperature,
+    (file.temperature, file.source()),
+  ]);
+  let max_tokens = pick([
+    (
+      matches.get_one::<u32>("max_tokens").copied(),
+      Source::Flag("--max_tokens"),
+    ),
+    env_max_tokens,
+    (file.max_tokens, file.source()),
+  ]);
+  let system_prompt = pick([
+    unzip(system),
+    (persona.map(|p| p.prompt.clone()), persona_source()),
+    env_system,
+    (file.system_prompt.clone(), file.source()),
+  ])
+  .unwrap_or_else(|| (prompt::DEFAULT_SYSTEM_PROMPT.to_string(), Source::Default));
+  // --base-url 在解析参数时已经规范化
+  let base_url = pick([
+    (
+      matches.get_one::<String>("base_url").cloned(),
+      Source::Flag("--base-url"),
+    ),
+    env_base_url,
+    (file.base_url.clone(), file.source()),
+  ]);
+  Layered {
+    model,
+    temperature: unzip(temperature),
+    max_tokens: unzip(max_tokens),
+    system_prompt,
+    base_url: unzip(base_url),
+    warnings,
+  }
+}
+
+/// 启动时的解析结果
+pub struct Startup<'a> {
+  pub matches: &'a clap::ArgMatches,
+  pub resolution: &'a profile::Resolution,
+  pub file: &'a FileConfig,
+  pub layered: &'a Layered,
+  /// 映射后的模型名
+  pub model: &'a str,
+  /// 模型表中的默认输出上限
+  pub model_default: u32,
+  pub max_tokens: u32,
+  pub length: prompt::AnswerLength,
+  pub restrict_to: Option<&'a Path>,
+  pub restrict_to_source: Option<Source>,
+  pub ui: widget::UiMode,
+  pub ui_trigger: widget::Trigger,
+  pub accessible_source: Option<Source>,
+  pub dirs: &'a crate::paths::Dirs,
+  pub render: crate::markdown::RenderMode,
+}
+
+/// 较长的 system prompt 只显示开头
+fn preview(text: &str) -> String {
+  let line = text.lines().next().unwrap_or_default();
+  match line.chars().count() > 40 || text.contains('\n') {
+    true => format!("{}…", line.chars().take(40).collect::<String>()),
+    false => line.to_string(),
+  }
+}
+
+/// 启动时生效的配置，每一项的来源与解析时用的是同一层
+pub fn startup(c: Startup) -> Config {
+  let matches = c.matches;
+  let layered = c.layered;
+  // 有默认值的参数：命令行给出的才算来自参数
+  let arg = |id: &str, flag| match matches.value_source(id) {
+    Some(ValueSource::CommandLine) => Source::Flag(flag),
+    _ => Source::Default,
+  };
+  let resolved = &c.resolution.resolved;
+  let key_source = match c.resolution.source {
+    profile::KeySource::Env => Source::Env(resolved.profile.api_key_env.to_string()),
+    profile::KeySource::DotEnv => Source::DotEnv(resolved.profile.api_key_env.to_string()),
+    profile::KeySource::ConfigFile => c.file.source(),
+  };
+  let profile_source = match (c.resolution.reason, matches.get_one::<String>("profile")) {
+    (profile::Reason::Requested, Some(_)) => Source::Flag("--profile"),
+    (profile::Reason::Requested, None) => c.file.source(),
+    _ => key_source.clone(),
+  };
+  let mut config = Config::default();
+  config.set("profile", resolved.profile.name, profile_source);
+  match &layered.base_url {
+    (Some(url), source) => config.set("base_url", url.as_str(), source.clone()),
+    (None, _) => config.set(
+      "base_url",
+      resolved.profile.base_url,
+      Source::Profile(resolved.profile.name),
+    ),
+  }
+  config.set("api_key", resolved.api_key.as_str(), key_source);
+  config.set("model", c.model, layered.model.1.clone());
+  config.set(
+    "temperature",
+    layered.temperature.0,
+    layered.temperature.1.clone(),
+  );
+  config.set(
+    "max_tokens",
+    c.max_tokens,
+    c.length.max_tokens_source(
+      layered.max_tokens.0.map(|_| &layered.max_tokens.1),
+      c.model_default,
+      c.model,
+    ),
+  );
+  let length_source = match c.length {
+    prompt::AnswerLength::Brief => Source::Flag("--brief"),
+    prompt::AnswerLength::Detailed => Source::Flag("--detailed"),
+    prompt::AnswerLength::Normal => arg("normal", "--normal"),
+  };
+  config.set("length", c.length.name(), length_source);
+  let (prompt, prompt_source) = &layered.system_prompt;
+  match (matches.get_flag("no_system"), prompt_source) {
+    (true, _) => config.set("system", "none", Source::Flag("--no-system")),
+    (false, Source::Persona(name)) => config.set("system", name.as_str(), prompt_source.clone()),
+    (false, Source::Default) => config.set("system", "default", Source::Default),
+    (false, _) => config.set("system", preview(prompt), prompt_source.clone()),
+  }
+  let contract = match (
+    matches.get_many::<String>("contract"),
+    &c.file.output_contract,
+  ) {
+    (Some(rules), _) => (rules.len(), Source::Flag("--contract")),
+    (None, Some(rules)) => (rules.len(), c.file.source()),
+    (None, None) => (0, Source::Default),
+  };
+  config.set("contract", contract.0, contract.1);
+  config.set(
+    "reasoning_effort",
+    matches
+      .get_one::<ReasoningEffort>("reasoning_effort")
+      .map(|e| format!("{:?}", e).to_lowercase()),
+    arg("reasoning_effort", "--reasoning-effort"),
+  );
+  config.set(
+    "truncate",
+    format!("{:?}", matches.get_one::<TruncateMode>("truncate").unwrap()).to_lowercase(),
+    arg("truncate", "--truncate"),
+  );
+  let restrict_to = c.restrict_to.map(|root| root.display().to_string());
+  let restrict_to_source = match matches.get_one::<PathBuf>("restrict_to") {
+    Some(_) => Source::Flag("--restrict-to"),
+    None => c.restrict_to_source.unwrap_or(Source::Default),
+  };
+  config.set("restrict_to", restrict_to, restrict_to_source);
+  let accessible_source = match c.ui_trigger {
+    widget::Trigger::Flag => Source::Flag("--accessible"),
+    widget::Trigger::Setting => c.accessible_source.unwrap_or(Source::Default),
+    widget::Trigger::Term => Source::Env("TERM".to_string()),
+    widget::Trigger::Default => Source::Default,
+  };
+  config.set("accessible", c.ui.is_accessible(), accessible_source);
+  config.set("render", c.render.name(), arg("render", "--render"));
+  let isolated = match (matches.get_flag("isolated"), c.file.isolated) {
+    (true, _) => (true, arg("isolated", "--isolated")),
+    (false, Some(isolated)) => (isolated, c.file.source()),
+    (false, None) => (false, Source::Default),
+  };
+  config.set("isolated", isolated.0, isolated.1);
+  let flag_or_file_source = |id: &str, flag, set: bool| match matches.value_source(id) {
+    Some(ValueSource::CommandLine) => Source::Flag(flag),
+    _ if set => c.file.source(),
+    _ => Source::Default,
+  };
+  config.set(
+    "checkpoint_every",
+    flag_or_file(matches, "checkpoint_every", c.file.checkpoint_every),
+    flag_or_file_source(
+      "checkpoint_every",
+      "--checkpoint-every",
+      c.file.checkpoint_every.is_some(),
+    ),
+  );
+  config.set(
+    "lock_conflict",
+    flag_or_file(matches, "lock_conflict", c.file.lock_conflict).name(),
+    flag_or_file_source(
+      "lock_conflict",
+      "--lock-conflict",
+      c.file.lock_conflict.is_some(),
+    ),
+  );
+  config.set(
+    "checkpoint_keep",
+    *matches.get_one::<usize>("checkpoint_keep").unwrap(),
+    arg("checkpoint_keep", "--checkpoint-keep"),
+  );
+  config.set(
+    "turn_timeout",
+    matches
+      .get_one::<Duration>("turn_timeout")
+      .map(|t| format!("{}s", t.as_secs_f64())),
+    arg("turn_timeout", "--turn-timeout"),
+  );
+  for (key, flag) in [
+    ("timeout", "--timeout"),
+    ("connect_timeout", "--connect-timeout"),
+  ] {
+    config.set(
+      key,
+      format!(
+        "{}s",
+        matches.get_one::<Duration>(key).unwrap().as_secs_f64()
+      ),
+      arg(key, flag),
+    );
+  }
+  config.set(
+    "stream_idle",
+    format!(
+      "{}s",
+      matches
+        .get_one::<Duration>("stream_idle")
+        .unwrap()
+        .as_secs_f64()
+    ),
+    arg("stream_idle", "--stream-idle"),
+  );
+  let otlp_source = match c.file.otlp_endpoint {
+    Some(_) => c.file.source(),
+    None => Source::Default,
+  };
+  config.set("otlp_endpoint", c.file.otlp_endpoint.clone(), otlp_source);
+  let file_or_default = |set: bool| match set {
+    true => c.file.source(),
+    false => Source::Default,
+  };
+  config.set(
+    "auto_route",
+    matches.get_flag("auto_route"),
+    arg("auto_route", "--auto-route"),
+  );
+  config.set(
+    "route_model",
+    c.file
+      .route_model
+      .clone()
+      .unwrap_or_else(|| crate::route::DEFAULT_CHEAP_MODEL.to_string()),
+    file_or_default(c.file.route_model.is_some()),
+  );
+  config.set(
+    "route_threshold",
+    c.file
+      .route_threshold
+      .unwrap_or(crate::route::DEFAULT_THRESHOLD),
+    file_or_default(c.file.route_threshold.is_some()),
+  );
+  let keep_refusals = match (matches.get_flag("keep_refusals"), c.file.keep_refusals) {
+    (true, _) => (true, arg("keep_refusals", "--keep-refusals")),
+    (false, Some(keep)) => (keep, c.file.source()),
+    (false, None) => (false, Source::Default),
+  };
+  config.set("keep_refusals", keep_refusals.0, keep_refusals.1);
+  config.set(
+    "copy_on_complete",
+    c.file.copy_on_complete.unwrap_or(false),
+    file_or_default(c.file.copy_on_complete.is_some()),
+  );
+  config.set(
+    "warm_start",
+    c.file.warm_start.unwrap_or(false),
+    file_or_default(c.file.warm_start.is_some()),
+  );
+  config.set(
+    "show_reasoning",
+    !matches.get_flag("hide_reasoning"),
+    arg("hide_reasoning", "--hide-reasoning"),
+  );
+  config.set(
+    "verify",
+    matches.get_flag("verify"),
+    arg("verify", "--verify"),
+  );
+  config.set(
+    "verify_prompt",
+    c.file
+      .verify_prompt
+      .clone()
+      .unwrap_or_else(|| crate::verify::DEFAULT_PROMPT.to_string()),
+    file_or_default(c.file.verify_prompt.is_some()),
+  );
+  config.set(
+    "attach.exclude",
+    c.file.attach_exclude.clone().unwrap_or_default(),
+    file_or_default(c.file.attach_exclude.is_some()),
+  );
+  config.set(
+    "inline_images",
+    c.file.inline_images.map_or("auto", |p| p.name()),
+    file_or_default(c.file.inline_images.is_some()),
+  );
+  config.set(
+    "image_rows",
+    c.file.image_rows.unwrap_or(crate::thumbnail::DEFAULT_ROWS) as u32,
+    file_or_default(c.file.image_rows.is_some()),
+  );
+  for (key, location) in [
+    ("config_dir", &c.dirs.config),
+    ("data_dir", &c.dirs.data),
+    ("cache_dir", &c.dirs.cache),
+  ] {
+    let path = location.as_ref().map(|l| l.path.display().to_string());
+    let source = location
+      .as_ref()
+      .map_or(Source::Default, |l| l.source.clone());
+    config.set(key, path, source);
+  }
+  config
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_later_layers_override_in_place() {
+    let mut config = Config::default();
+    config.set("model", "deepseek-r1", Source::Default);
+    config.set("max_tokens", 8192u32, Source::Model("deepseek-r1".into()));
+    config.set("max_tokens", 1024u32, Source::Preset("brief"));
+    config.set(
+      "max_tokens",
+      4096u32,
+      Source::Runtime("\\brief off".to_string()),
+    );
+    let keys: Vec<_> = config.entries.iter().map(|e| e.key).collect();
+    assert_eq!(keys, ["model", "max_tokens"]);
+    let entry = &config.entries[1];
+    assert_eq!(entry.value, Value::Int(4096));
+    assert_eq!(entry.source.to_string(), "set by \\brief off");
+  }
+
+  #[test]
+  fn test_toml_is_annotated_and_redacted() {
+    let mut config = Config::default();
+    config.set(
+      "model",
+      "deepseek-chat",
+      Source::Persona("code-review".into()),
+    );
+    config.set("temperature", 0.7f32, Source::Flag("--temperature"));
+    config.set(
+      "api_key",
+      "sk-live-123",
+      Source::Env("DEEPSEEK_API_KEY".into()),
+    );
+    config.set("reasoning_effort", None::<String>, Source::Default);
+    config.set("isolated", false, Source::Default);
+    let toml = config.to_toml();
+    let lines: Vec<_> = toml.lines().collect();
+    assert!(
+      lines[0].starts_with("model = \"deepseek-chat\""),
+      "{}",
+      toml
+    );
+    assert!(
+      lines[0].ends_with("  # from persona 'code-review'"),
+      "{}",
+      toml
+    );
+    assert!(lines[1].starts_with("temperature = 0.7 "), "{}", toml);
+    assert!(lines[2].starts_with("api_key = \"[redacted]\""), "{}", toml);
+    assert!(
+      lines[2].ends_with("# from env DEEPSEEK_API_KEY"),
+      "{}",
+      toml
+    );
+    assert!(lines[3].starts_with("# reasoning_effort ="), "{}", toml);
+    assert!(lines[4].starts_with("isolated = false"), "{}", toml);
+    assert!(!toml.contains("sk-live-123"));
+    // 注释对齐
+    let column: Vec<_> = lines.iter().map(|l| l.find("  # ").unwrap()).collect();
+    assert!(column.iter().all(|&c| c == column[0]), "{}", toml);
+  }
+
+  #[test]
+  fn test_json_keeps_values_typed() {
+    let mut config = Config::default();
+    config.set("max_tokens", 1024u32, Source::Preset("brief"));
+    config.set("temperature", None::<f32>, Source::Default);
+    config.set(
+      "api_key",
+      "sk-live-123",
+      Source::DotEnv("DASHSCOPE_API_KEY".into()),
+    );
+    let json = config.to_json();
+    assert_eq!(json["max_tokens"]["value"], 1024);
+    assert_eq!(json["max_tokens"]["source"], "from preset 'brief'");
+    assert!(json["temperature"]["value"].is_null());
+    assert_eq!(json["api_key"]["value"], REDACTED);
+    assert_eq!(json["api_key"]["source"], "from .env DASHSCOPE_API_KEY");
+  }
+
+  fn deepseek_env(key: &str) -> Option<String> {
+    (key == "DEEPSEEK_API_KEY").then(|| "sk-test-456".to_string())
+  }
+
+  /// 测试用的环境变量：都算作真实环境变量
+  fn env_of(vars: &[(&'static str, &'static str)]) -> impl Fn(&str) -> Option<(String, Source)> {
+    let vars = vars.to_vec();
+    move |key: &str| {
+      let (_, value) = vars.iter().find(|(k, _)| *k == key)?;
+      Some((value.to_string(), Source::Env(key.to_string())))
+    }
+  }
+
+  fn layered_with(
+    args: &[&str],
+    persona: Option<&persona::Persona>,
+    env: &[(&'static str, &'static str)],
+    file: &FileConfig,
+  ) -> Layered {
+    let matches = crate::cli::build_cli().get_matches_from(args);
+    let system = system_flag(&matches).unwrap();
+    layered(&matches, persona, system, env_of(env), file)
+  }
+
+  /// 按 main 的顺序解析参数、人设、环境变量和配置文件，模型默认输出上限为 8192
+  fn startup_with(
+    args: &[&str],
+    persona: Option<&persona::Persona>,
+    env: &[(&'static str, &'static str)],
+    file: &FileConfig,
+    resolution: &profile::Resolution,
+  ) -> Config {
+    let matches = crate::cli::build_cli().get_matches_from(args);
+    let system = system_flag(&matches).unwrap();
+    let layered = layered(&matches, persona, system, env_of(env), file);
+    let length =
+      prompt::AnswerLength::from_flags(matches.get_flag("brief"), matches.get_flag("detailed"));
+    startup(Startup {
+      matches: &matches,
+      resolution,
+      file,
+      layered: &layered,
+      model: &layered.model.0,
+      model_default: 8192,
+      max_tokens: length.max_tokens(layered.max_tokens.0, 8192),
+      length,
+      restrict_to: None,
+      restrict_to_source: None,
+      ui: widget::UiMode::Standard,
+      ui_trigger: widget::Trigger::Default,
+      accessible_source: None,
+      dirs: &crate::paths::Dirs::resolve(crate::paths::Platform::Unix, |_| None, |_| false),
+      render: crate::markdown::RenderMode::Raw,
+    })
+  }
+
+  fn source(config: &Config, key: &str) -> String {
+    let entry = config.entries.iter().find(|e| e.key == key).unwrap();
+    entry.source.to_string()
+  }
+
+  #[test]
+  fn test_startup_layers_flag_over_persona_over_default() {
+    let resolution = profile::resolve_explained(None, deepseek_env, |_| None).unwrap();
+    let mut persona = persona::parse("---\nmodel: chat\ntemperature: 0.3\n---\nReview.").unwrap();
+    persona.path = PathBuf::from("personas/code-review.md");
+
+    let config = startup_with(
+      &["deepcli", "--temperature", "1.0"],
+      Some(&persona),
+      &[],
+      &FileConfig::default(),
+      &resolution,
+    );
+    let toml = config.to_toml();
+    assert!(
+      toml.contains("model = \"chat\"") && source(&config, "model") == "from persona 'code-review'",
+      "{}",
+      toml
+    );
+    assert_eq!(source(&config, "temperature"), "from --temperature");
+    assert_eq!(source(&config, "system"), "from persona 'code-review'");
+    assert_eq!(source(&config, "max_tokens"), "output limit of 'chat'");
+
+    let config = startup_with(
+      &["deepcli", "-m", "r1"],
+      Some(&persona),
+      &[],
+      &FileConfig::default(),
+      &resolution,
+    );
+    assert_eq!(source(&config, "model"), "from --model");
+    assert_eq!(source(&config, "temperature"), "from persona 'code-review'");
+
+    let config = startup_with(&["deepcli"], None, &[], &FileConfig::default(), &resolution);
+    assert_eq!(source(&config, "model"), "default");
+    assert!(config.to_toml().contains("# temperature ="));
+    assert_eq!(source(&config, "truncate"), "default");
+  }
+
+  #[test]
+  fn test_startup_brief_preset_and_key_sources() {
+    let resolution = profile::resolve_explained(None, |_| None, deepseek_env).unwrap();
+    let config = startup_with(
+      &["deepcli", "--brief"],
+      None,
+      &[],
+      &FileConfig::default(),
+      &resolution,
+    );
+    assert_eq!(source(&config, "length"), "from --brief");
+    assert_eq!(source(&config, "max_tokens"), "from preset 'brief'");
+    assert_eq!(config.to_json()["max_tokens"]["value"], 1024);
+    // 显式的 --max_tokens 优先于预设
+    let config = startup_with(
+      &["deepcli", "--brief", "-l", "300"],
+      None,
+      &[],
+      &FileConfig::default(),
+      &resolution,
+    );
+    assert_eq!(source(&config, "max_tokens"), "from --max_tokens");
+    // 密钥只显示来源
+    assert_eq!(source(&config, "profile"), "from .env DEEPSEEK_API_KEY");
+    assert_eq!(source(&config, "base_url"), "from profile 'deepseek'");
+    let toml = config.to_toml();
+    assert!(!toml.contains("sk-test-456"), "{}", toml);
+    assert!(toml.contains("api_key = \"[redacted]\""), "{}", toml);
+
+    let resolution = profile::resolve_explained(Some("deepseek"), |_| None, deepseek_env).unwrap();
+    let config = startup_with(
+      &["deepcli", "--profile", "deepseek", "--truncate", "head"],
+      None,
+      &[],
+      &FileConfig::default(),
+      &resolution,
+    );
+    assert_eq!(source(&config, "profile"), "from --profile");
+    assert_eq!(source(&config, "api_key"), "from .env DEEPSEEK_API_KEY");
+    assert_eq!(source(&config, "truncate"), "from --truncate");
+  }
+
+  fn parse_file(text: &str) -> (FileConfig, Vec<String>) {
+    FileConfig::parse(Path::new("config.toml"), text)
+  }
+
+  #[test]
+  fn test_flag_beats_file_beats_default() {
+    let matches = |args: &[&str]| crate::cli::build_cli().get_matches_from(args);
+    let defaults = matches(&["deepcli", "-i"]);
+    assert_eq!(
+      flag_or_file::<usize>(&defaults, "checkpoint_every", None),
+      10
+    );
+    assert_eq!(flag_or_file(&defaults, "checkpoint_every", Some(3usize)), 3);
+    // 与默认值相同的命令行参数也优先于配置文件
+    let explicit = matches(&["deepcli", "--checkpoint-every", "10", "-i"]);
+    assert_eq!(
+      flag_or_file(&explicit, "checkpoint_every", Some(3usize)),
+      10
+    );
+  }
+
+  #[test]
+  fn test_file_parses_the_toml_subset() {
+    let text = r#"
+# 默认值
+profile = "deepseek"
+model = 'chat'              # 字面字符串
+temperature = 1
+max_tokens = 4_096
+system_prompt = "你是一名\"严谨\"的助手。\n回答要简短。"
+api_key = "sk-file-789"
+otlp_endpoint = "http://localhost:4318"
+route_model = "qwen-turbo"
+route_threshold = 0.8
+keep_refusals = true
+copy_on_complete = true
+warm_start = true
+isolated = true
+send_user_id = true
+accessible = false
+checkpoint_every = 5
+lock_conflict = "read-only"
+restrict_to = "/srv/project"
+verify_prompt = "检查这个回答：{answer}"
+inline_images = "iterm2"
+image_rows = 6
+data_dir = "/srv/deepcli"
+
+output_contract = ["不要用 emoji", "代码注释用中文"]
+
+[attach]
+exclude = ["target/**", '*.lock', ]  # 数组可以有末尾的逗号
+"#;
+    let (config, warnings) = parse_file(text);
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert_eq!(config.profile.as_deref(), Some("deepseek"));
+    assert_eq!(config.model.as_deref(), Some("chat"));
+    assert_eq!(config.temperature, Some(1.0));
+    assert_eq!(config.max_tokens, Some(4096));
+    assert_eq!(
+      config.system_prompt.as_deref(),
+      Some("你是一名\"严谨\"的助手。\n回答要简短。")
+    );
+    assert_eq!(config.api_key.as_deref(), Some("sk-file-789"));
+    assert_eq!(
+      config.otlp_endpoint.as_deref(),
+      Some("http://localhost:4318")
+    );
+    assert_eq!(config.route_model.as_deref(), Some("qwen-turbo"));
+    assert_eq!(config.route_threshold, Some(0.8));
+    assert_eq!(config.keep_refusals, Some(true));
+    assert_eq!(config.copy_on_complete, Some(true));
+    assert_eq!(config.warm_start, Some(true));
+    assert_eq!(config.isolated, Some(true));
+    assert_eq!(config.send_user_id, Some(true));
+    assert_eq!(config.accessible, Some(false));
+    assert_eq!(config.checkpoint_every, Some(5));
+    assert_eq!(
+      config.lock_conflict,
+      Some(crate::lock::LockConflict::ReadOnly)
+    );
+    assert_eq!(config.restrict_to, Some(PathBuf::from("/srv/project")));
+    assert_eq!(
+      config.verify_prompt.as_deref(),
+      Some("检查这个回答：{answer}")
+    );
+    assert_eq!(
+      config.inline_images,
+      Some(crate::thumbnail::Protocol::Iterm2)
+    );
+    assert_eq!(config.image_rows, Some(6));
+    assert_eq!(
+      config.output_contract,
+      Some(vec![
+        "不要用 emoji".to_string(),
+        "代码注释用中文".to_string()
+      ])
+    );
+    assert_eq!(config.source().to_string(), "from config.toml");
+    assert_eq!(config.data_dir, Some(PathBuf::from("/srv/deepcli")));
+    assert_eq!(
+      config.attach_exclude,
+      Some(vec!["target/**".to_string(), "*.lock".to_string()])
+    );
+    assert_eq!(
+      Value::from(config.attach_exclude.unwrap()).toml(),
+      r#"["target/**", "*.lock"]"#
+    );
+    let (config, warnings) = parse_file("attach.exclude = [\"a\", 1]\nattach.exclude = [\"b\"\n");
+    assert_eq!(config.attach_exclude, None);
+    assert!(
+      warnings[0].contains("only contain strings"),
+      "{:?}",
+      warnings
+    );
+    assert!(warnings[1].contains("unterminated array"), "{:?}", warnings);
+    // 相对路径相对于配置文件所在目录
+    let (config, _) =
+      FileConfig::parse(Path::new("/etc/deepcli/config.toml"), "cache_dir = 'cache'");
+    assert_eq!(config.cache_dir, Some(PathBuf::from("/etc/deepcli/cache")));
+  }
+
+  #[test]
+  fn test_file_warnings_name_the_key() {
+    let text = "model = \"chat\"\ntemprature = 0.5\ntemperature = 3.5\nmax_tokens = 0\nmodel = 3\n[server]\nprofile = \"openai\"\nsystem_prompt = \"\"\"x\n";
+    let (config, warnings) = parse_file(text);
+    // 有问题的行被跳过，其余照常生效
+    assert_eq!(config.model.as_deref(), Some("chat"));
+    assert_eq!(config.temperature, None);
+    assert_eq!(config.max_tokens, None);
+    assert_eq!(config.profile, None);
+    assert_eq!(warnings.len(), 7, "{:#?}", warnings);
+    let expect = [
+      ("config.toml:2:", "'temprature'"),
+      ("config.toml:3:", "'temperature'"),
+      ("config.toml:4:", "'max_tokens'"),
+      ("config.toml:5:", "'model'"),
+      ("config.toml:6:", "[server]"),
+      ("config.toml:7:", "'openai'"),
+      ("config.toml:8:", "'system_prompt'"),
+    ];
+    for (warning, (line, key)) in warnings.iter().zip(expect) {
+      assert!(
+        warning.starts_with(line) && warning.contains(key),
+        "{}",
+        warning
+      );
+    }
+    assert!(
+      warnings[1].contains("between 0.0 and 2.0"),
+      "{}",
+      warnings[1]
+    );
+    let (config, warnings) = parse_file("route_threshold = 1.5\n");
+    assert_eq!(config.route_threshold, None);
+    assert!(warnings[0].contains("between 0 and 1"), "{:?}", warnings);
+    let (config, warnings) = parse_file("verify_prompt = \"检查 {question}\"\n");
+    assert_eq!(config.verify_prompt, None);
+    assert!(warnings[0].contains("{answer}"), "{:?}", warnings);
+  }
+
+  #[test]
+  fn test_file_load() {
+    let dir = crate::checkpoint::temp_dir("config-file");
+    let path = dir.join("deepcli.toml");
+    // --config 指定的文件必须存在
+    assert!(FileConfig::load(Some(&path)).is_err());
+    std::fs::write(&path, "model = \"chat\"\n").unwrap();
+    let (config, warnings) = FileConfig::load(Some(&path)).unwrap();
+    assert_eq!((config.model.as_deref(), warnings.len()), (Some("chat"), 0));
+    assert_eq!(config.path, path);
+    std::fs::remove_dir_all(dir).ok();
+  }
+
+  #[test]
+  fn test_flag_overrides_env_and_file() {
+    let file = FileConfig {
+      model: Some("chat".into()),
+      temperature: Some(0.2),
+      max_tokens: Some(2000),
+      ..Default::default()
+    };
+    let env = [
+      (ENV_MODEL, "chat"),
+      (ENV_TEMPERATURE, "0.5"),
+      (ENV_MAX_TOKENS, "3000"),
+    ];
+    let layered = layered_with(
+      &["deepcli", "-m", "r1", "-t", "1.0", "-l", "500"],
+      None,
+      &env,
+      &file,
+    );
+    assert_eq!(layered.model, ("r1".into(), Source::Flag("--model")));
+    assert_eq!(
+      layered.temperature,
+      (Some(1.0), Source::Flag("--temperature"))
+    );
+    assert_eq!(
+      layered.max_tokens,
+      (Some(500), Source::Flag("--max_tokens"))
+    );
+  }
+
+  #[test]
+  fn test_env_overrides_file() {
+    let file = FileConfig {
+      path: PathBuf::from("config.toml"),
+      model: Some("r1".into()),
+      temperature: Some(0.2),
+      max_tokens: Some(2000),
+      system_prompt: Some("from file".into()),
+      ..Default::default()
+    };
+    let env = [
+      (ENV_MODEL, "chat"),
+      (ENV_TEMPERATURE, "0.5"),
+      (ENV_MAX_TOKENS, "3000"),
+      (ENV_SYSTEM_PROMPT, "from env"),
+    ];
+    let layered = layered_with(&["deepcli"], None, &env, &file);
+    let from_env = |key: &str| Source::Env(key.to_string());
+    assert_eq!(layered.model, ("chat".into(), from_env(ENV_MODEL)));
+    assert_eq!(layered.temperature, (Some(0.5), from_env(ENV_TEMPERATURE)));
+    assert_eq!(layered.max_tokens, (Some(3000), from_env(ENV_MAX_TOKENS)));
+    assert_eq!(
+      layered.system_prompt,
+      ("from env".into(), from_env(ENV_SYSTEM_PROMPT))
+    );
+    // 无法解析的环境变量跳过，落到配置文件
+    let layered = layered_with(&["deepcli"], None, &[(ENV_TEMPERATURE, "hot")], &file);
+    assert_eq!(layered.temperature, (Some(0.2), file.source()));
+    assert_eq!(layered.warnings.len(), 1);
+    assert!(
+      layered.warnings[0].contains(ENV_TEMPERATURE),
+      "{:?}",
+      layered.warnings
+    );
+  }
+
+  #[test]
+  fn test_file_overrides_default() {
+    let (file, _) = parse_file(
+      "model = \"chat\"\ntemperature = 0.2\nmax_tokens = 2000\nsystem_prompt = \"简短回答\"\n",
+    );
+    let layered = layered_with(&["deepcli"], None, &[], &file);
+    assert_eq!(layered.model, ("chat".into(), file.source()));
+    assert_eq!(layered.temperature, (Some(0.2), file.source()));
+    assert_eq!(layered.max_tokens, (Some(2000), file.source()));
+    assert_eq!(layered.system_prompt, ("简短回答".into(), file.source()));
+
+    let resolution = profile::resolve_explained(None, deepseek_env, |_| None).unwrap();
+    let config = startup_with(&["deepcli", "--brief"], None, &[], &file, &resolution);
+    assert_eq!(source(&config, "max_tokens"), "from config.toml");
+    assert_eq!(source(&config, "system"), "from config.toml");
+    assert!(config.to_toml().contains("system = \"简短回答\""));
+  }
+
+  #[test]
+  fn test_defaults_when_no_layer_is_set() {
+    let layered = layered_with(&["deepcli"], None, &[], &FileConfig::default());
+    assert_eq!(layered.model, ("r1".into(), Source::Default));
+    assert_eq!(layered.temperature, (None, Source::Default));
+    assert_eq!(layered.max_tokens, (None, Source::Default));
+    assert_eq!(
+      layered.system_prompt,
+      (prompt::DEFAULT_SYSTEM_PROMPT.to_string(), Source::Default)
+    );
+    assert!(layered.warnings.is_empty());
+  }
+
+  #[test]
+  fn test_persona_sits_between_flag_and_env() {
+    let mut persona = persona::parse("---\nmodel: chat\ntemperature: 0.3\n---\nReview.").unwrap();
+    persona.path = PathBuf::from("personas/code-review.md");
+    let env = [(ENV_MODEL, "r1"), (ENV_TEMPERATURE, "0.5")];
+    let layered = layered_with(
+      &["deepcli", "-t", "1.0"],
+      Some(&persona),
+      &env,
+      &FileConfig::default(),
+    );
+    let from_persona = Source::Persona("code-review".into());
+    assert_eq!(layered.model, ("chat".into(), from_persona.clone()));
+    assert_eq!(
+      layered.temperature,
+      (Some(1.0), Source::Flag("--temperature"))
+    );
+    assert_eq!(layered.system_prompt, ("Review.".into(), from_persona));
+  }
+
+  #[test]
+  fn test_system_flag_overrides_every_layer() {
+    let persona = persona::parse("Review.").unwrap();
+    let file = FileConfig {
+      system_prompt: Some("from file".into()),
+      ..Default::default()
+    };
+    let env = [(ENV_SYSTEM_PROMPT, "from env")];
+    let layered = layered_with(
+      &["deepcli", "--system", "You translate."],
+      Some(&persona),
+      &env,
+      &file,
+    );
+    assert_eq!(
+      layered.system_prompt,
+      ("You translate.".into(), Source::Flag("--system"))
+    );
+
+    let dir = crate::checkpoint::temp_dir("system-file");
+    let path = dir.join("review.md");
+    std::fs::write(&path, "\nYou review Rust code.\n").unwrap();
+    let path = path.to_str().unwrap();
+    let layered = layered_with(&["deepcli", "--system-file", path], None, &env, &file);
+    assert_eq!(
+      layered.system_prompt,
+      (
+        "You review Rust code.".into(),
+        Source::Flag("--system-file")
+      )
+    );
+    std::fs::write(dir.join("empty.md"), " \n").unwrap();
+    let matches = crate::cli::build_cli().get_matches_from([
+      "deepcli",
+      "--system-file",
+      dir.join("empty.md").to_str().unwrap(),
+    ]);
+    assert!(system_flag(&matches).is_err());
+    std::fs::remove_dir_all(dir).ok();
+  }
+
+  #[test]
+  fn test_file_profile_and_key_sources() {
+    let (file, _) = parse_file("profile = \"deepseek\"\napi_key = \"sk-file-789\"\n");
+    let resolution = profile::resolve_with_file_key(
+      file.profile.as_deref(),
+      |_| None,
+      |_| None,
+      file.api_key.as_deref(),
+    )
+    .unwrap();
+    let config = startup_with(&["deepcli"], None, &[], &file, &resolution);
+    assert_eq!(source(&config, "profile"), "from config.toml");
+    assert_eq!(source(&config, "api_key"), "from config.toml");
+    assert!(!config.to_toml().contains("sk-file-789"));
+  }
+
+  #[test]
+  fn test_base_url_layers() {
+    let (file, warnings) = parse_file("base_url = \"https://gateway.example.com/v1/\"\n");
+    assert!(warnings.is_empty(), "{:?}", warnings);
+    assert_eq!(
+      file.base_url.as_deref(),
+      Some("https://gateway.example.com/v1")
+    );
+    let layered = layered_with(&["deepcli"], None, &[], &file);
+    assert_eq!(
+      layered.base_url,
+      (Some("https://gateway.example.com/v1".into()), file.source())
+    );
+    // 环境变量覆盖配置文件，路径可以带上 /chat/completions
+    let env = [(ENV_BASE_URL, "http://localhost:8000/v1/chat/completions")];
+    let layered = layered_with(&["deepcli"], None, &env, &file);
+    assert_eq!(
+      layered.base_url,
+      (
+        Some("http://localhost:8000/v1".into()),
+        Source::Env(ENV_BASE_URL.into())
+      )
+    );
+    let args = ["deepcli", "--base-url", "https://api.deepseek.com"];
+    let layered = layered_with(&args, None, &env, &file);
+    assert_eq!(layered.base_url.1, Source::Flag("--base-url"));
+    // 无法使用的环境变量记一条警告，回到下一层
+    let layered = layered_with(
+      &["deepcli"],
+      None,
+      &[(ENV_BASE_URL, "localhost:8000")],
+      &file,
+    );
+    assert_eq!(layered.base_url.1, file.source());
+    assert!(layered.warnings[0].contains(ENV_BASE_URL));
+    // 都没有时用提供方的地址
+    let resolution =
+      profile::resolve_with_file_key(Some("deepseek"), |_| None, |_| None, Some("k")).unwrap();
+    let config = startup_with(&["deepcli"], None, &[], &FileConfig::default(), &resolution);
+    assert_eq!(source(&config, "base_url"), "from profile 'deepseek'");
+    let config = startup_with(
+      &["deepcli"],
+      None,
+      &env,
+      &FileConfig::default(),
+      &resolution,
+    );
+    assert_eq!(source(&config, "base_url"), "from env DEEPCLI_BASE_URL");
+    let (_, warnings) = parse_file("base_url = \"api.example.com\"\n");
+    assert!(warnings[0].contains("'base_url'"), "{:?}", warnings);
+  }
+}
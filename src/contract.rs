@@ -0,0 +1,306 @@
+/// 输出约定消息的开头
+pub const HEADER: &str = "输出约定（每次回答都必须遵守）：";
+
+/// 常见的客套开场白，后面紧跟标点时在输出中去掉
+const GREETINGS: &[&str] = &[
+  "certainly",
+  "sure",
+  "of course",
+  "absolutely",
+  "great question",
+  "good question",
+  "当然",
+  "好的",
+  "没问题",
+  "好问题",
+  "很好的问题",
+];
+
+/// 本会话的输出约定：每个请求都以一条单独的 system 消息带上，不属于历史，
+/// 因此不会被摘要掉，也不受切换模型影响
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Contract {
+  rules: Vec<String>,
+  /// 会话中改动过。改动过的约定放在请求末尾，不再属于可缓存的前缀
+  edited: bool,
+}
+
+/// `\contract` 的子命令
+#[derive(Debug, PartialEq)]
+pub enum Command<'a> {
+  Show,
+  Add(&'a str),
+  Remove(usize),
+  Clear,
+}
+
+/// 解析 `\contract` 之后的参数
+pub fn parse_command(arg: &str) -> Result<Command<'_>, String> {
+  let usage = "用法: \\contract [add <规则> | rm <序号> | clear]";
+  let arg = arg.trim();
+  let (sub, rest) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+  let rest = rest.trim();
+  match sub {
+    "" => Ok(Command::Show),
+    "add" if !rest.is_empty() => Ok(Command::Add(rest)),
+    "rm" => rest
+      .parse()
+      .ok()
+      .filter(|&n| n > 0)
+      .map(Command::Remove)
+      .ok_or_else(|| usage.to_string()),
+    "clear" if rest.is_empty() => Ok(Command::Clear),
+    _ => Err(usage.to_string()),
+  }
+}
+
+impl Contract {
+  /// 会话开始时的约定（命令行或会话文件），属于稳定前缀
+  pub fn new(rules: Vec<String>) -> Self {
+    let rules = rules
+      .into_iter()
+      .map(|r| r.trim().to_string())
+      .filter(|r| !r.is_empty())
+      .collect();
+    Self {
+      rules,
+      edited: false,
+    }
+  }
+
+  pub fn rules(&self) -> &[String] {
+    &self.rules
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.rules.is_empty()
+  }
+
+  pub fn edited(&self) -> bool {
+    self.edited
+  }
+
+  pub fn add(&mut self, rule: &str) {
+    self.rules.push(rule.trim().to_string());
+    self.edited = true;
+  }
+
+  /// 删除第 `n` 条（从 1 开始），返回被删除的规则
+  pub fn remove(&mut self, n: usize) -> Option<String> {
+    let rule = (n >= 1 && n <= self.rules.len()).then(|| self.rules.remove(n - 1))?;
+    self.edited = true;
+    Some(rule)
+  }
+
+  pub fn clear(&mut self) {
+    self.edited |= !self.rules.is_empty();
+    self.rules.clear();
+  }
+
+  /// `\contract` 的输出，每条一行
+  pub fn render(&self) -> String {
+    self
+      .rules
+      .iter()
+      .enumerate()
+      .map(|(i, rule)| format!("{}. {}", i + 1, rule))
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  /// 请求中那条 system 消息的内容，没有规则时为 None
+  pub fn message(&self) -> Option<String> {
+    (!self.is_empty()).then(|| format!("{}\n{}", HEADER, self.render()))
+  }
+
+  /// 规则中可以在本地机械检查的部分
+  pub fn checks(&self) -> Checks {
+    let mut checks = Checks::default();
+    for rule in &self.rules {
+      let rule = rule.to_lowercase();
+      checks.emoji |= rule.contains("emoji") || rule.contains("表情");
+      checks.greeting |= ["certainly", "greeting", "寒暄", "开场白", "客套"]
+        .iter()
+        .any(|w| rule.contains(w));
+    }
+    checks
+  }
+}
+
+/// 输出过滤器要做的检查
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checks {
+  /// 去掉 emoji
+  pub emoji: bool,
+  /// 去掉回答开头的客套话
+  pub greeting: bool,
+}
+
+fn is_emoji(c: char) -> bool {
+  matches!(c,
+    '\u{1F000}'..='\u{1FAFF}'
+    | '\u{2600}'..='\u{27BF}'
+    | '\u{2B50}'
+    | '\u{2B55}'
+    | '\u{FE0F}')
+}
+
+/// 开头是否为客套话：Some(n) 表示去掉前 n 个字节（n 为 0 即不是），None 表示还需要更多内容
+fn greeting_len(text: &str) -> Option<usize> {
+  let body = text.trim_start();
+  let lead = text.len() - body.len();
+  let lower = body.to_lowercase();
+  for greeting in GREETINGS {
+    if let Some(rest) = lower.strip_prefix(greeting) {
+      let c = rest.chars().next()?;
+      if !matches!(c, '!' | '！' | ',' | '，' | '.' | '。') {
+        return Some(0);
+      }
+      // 转小写不改变这些字符的字节长度
+      let after = &body[greeting.len() + c.len_utf8()..];
+      let rest = after.trim_start();
+      return Some(lead + greeting.len() + c.len_utf8() + after.len() - rest.len());
+    }
+    if greeting.starts_with(&lower) {
+      return None;
+    }
+  }
+  Some(0)
+}
+
+/// 按输出约定过滤流式回答，显示和历史中保存的都是过滤后的内容
+#[derive(Debug)]
+pub struct OutputFilter {
+  checks: Checks,
+  /// 还在判断开头是不是客套话时缓冲的内容
+  head: Option<String>,
+  /// 刚去掉一个 emoji，且它前面是空白：紧跟的一个空格也去掉
+  skip_space: bool,
+  last_is_space: bool,
+}
+
+impl OutputFilter {
+  pub fn new(checks: Checks) -> Self {
+    Self {
+      checks,
+      head: checks.greeting.then(String::new),
+      skip_space: false,
+      last_is_space: true,
+    }
+  }
+
+  fn strip_emoji(&mut self, chunk: &str) -> String {
+    let mut out = String::with_capacity(chunk.len());
+    for c in chunk.chars() {
+      if is_emoji(c) || (c == '\u{200D}' && self.skip_space) {
+        self.skip_space |= self.last_is_space;
+        continue;
+      }
+      if c == ' ' && std::mem::take(&mut self.skip_space) {
+        continue;
+      }
+      self.skip_space = false;
+      self.last_is_space = c.is_whitespace();
+      out.push(c);
+    }
+    out
+  }
+
+  pub fn push(&mut self, chunk: &str) -> String {
+    let chunk = match self.checks.emoji {
+      true => self.strip_emoji(chunk),
+      false => chunk.to_string(),
+    };
+    let Some(head) = &mut self.head else {
+      return chunk;
+    };
+    head.push_str(&chunk);
+    match greeting_len(head) {
+      Some(n) => {
+        let out = head[n..].to_string();
+        self.head = None;
+        out
+      }
+      None => String::new(),
+    }
+  }
+
+  /// 回答结束时还缓冲着的内容（整个回答就是半句客套话）
+  pub fn finish(&mut self) -> String {
+    self.head.take().unwrap_or_default()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ALL: Checks = Checks {
+    emoji: true,
+    greeting: true,
+  };
+
+  fn filter(checks: Checks, chunks: &[&str]) -> String {
+    let mut filter = OutputFilter::new(checks);
+    let mut out: String = chunks.iter().map(|c| filter.push(c)).collect();
+    out.push_str(&filter.finish());
+    out
+  }
+
+  #[test]
+  fn test_edits_and_message() {
+    let mut contract = Contract::new(vec!["不要用 emoji".to_string(), " ".to_string()]);
+    assert_eq!(contract.rules(), ["不要用 emoji"]);
+    assert!(!contract.edited());
+    contract.add("代码注释用中文");
+    assert!(contract.edited());
+    assert_eq!(
+      contract.message().unwrap(),
+      format!("{}\n1. 不要用 emoji\n2. 代码注释用中文", HEADER)
+    );
+    assert_eq!(contract.remove(1).as_deref(), Some("不要用 emoji"));
+    assert_eq!(contract.remove(5), None);
+    contract.clear();
+    assert_eq!(contract.message(), None);
+
+    assert_eq!(parse_command(""), Ok(Command::Show));
+    assert_eq!(
+      parse_command(" add  用 tab 缩进"),
+      Ok(Command::Add("用 tab 缩进"))
+    );
+    assert_eq!(parse_command("rm 2"), Ok(Command::Remove(2)));
+    assert_eq!(parse_command("clear"), Ok(Command::Clear));
+    assert!(parse_command("rm 0").is_err());
+    assert!(parse_command("add").is_err());
+  }
+
+  #[test]
+  fn test_checks_from_rules() {
+    let contract = Contract::new(vec![
+      "No emoji".to_string(),
+      "Never start with 'Certainly!'".to_string(),
+    ]);
+    assert_eq!(contract.checks(), ALL);
+    let contract = Contract::new(vec!["use tabs".to_string()]);
+    assert_eq!(contract.checks(), Checks::default());
+  }
+
+  #[test]
+  fn test_filter_strips_greeting_and_emoji_across_chunks() {
+    assert_eq!(
+      filter(ALL, &["Cert", "ainly! ", "Here 🚀 is", " the fix ✅"]),
+      "Here is the fix "
+    );
+    assert_eq!(filter(ALL, &["好的，", "下面是答案"]), "下面是答案");
+    // 不是客套话的开头原样保留
+    assert_eq!(filter(ALL, &["Surely", " not"]), "Surely not");
+    assert_eq!(filter(ALL, &["Sure"]), "Sure");
+    assert_eq!(filter(ALL, &["Of course it works"]), "Of course it works");
+    // 只检查开头
+    assert_eq!(filter(ALL, &["Done. Certainly! 👍🏻"]), "Done. Certainly! ");
+    assert_eq!(
+      filter(Checks::default(), &["Certainly! 🚀"]),
+      "Certainly! 🚀"
+    );
+  }
+}
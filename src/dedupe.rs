@@ -0,0 +1,133 @@
+use crate::api::Message;
+use crate::history::turn_starts;
+use std::collections::HashSet;
+
+/// 词重叠达到这个比例视为重复提问
+pub const THRESHOLD: f64 = 0.8;
+/// 少于这么多词的短消息（“继续”“谢谢”之类）不检查
+pub const MIN_TOKENS: usize = 6;
+/// 提示时显示之前回答的前几行
+pub const PREVIEW_LINES: usize = 3;
+
+/// 归一化为词：英文和数字按单词、小写，中日韩字符逐字，标点与空白忽略
+pub fn tokens(text: &str) -> Vec<String> {
+  let mut tokens = vec![];
+  let mut word = String::new();
+  for c in text.chars() {
+    if c.is_alphanumeric() && c.is_ascii() {
+      word.extend(c.to_lowercase());
+      continue;
+    }
+    if !word.is_empty() {
+      tokens.push(std::mem::take(&mut word));
+    }
+    if c.is_alphanumeric() {
+      tokens.push(c.to_string());
+    }
+  }
+  if !word.is_empty() {
+    tokens.push(word);
+  }
+  tokens
+}
+
+/// 两段文本的词重叠度（Jaccard），0 到 1
+pub fn similarity(a: &str, b: &str) -> f64 {
+  let a: HashSet<_> = tokens(a).into_iter().collect();
+  let b: HashSet<_> = tokens(b).into_iter().collect();
+  if a.is_empty() && b.is_empty() {
+    return 1.0;
+  }
+  a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Duplicate {
+  /// 之前问过的轮次（从 1 开始）
+  pub turn: usize,
+  pub score: f64,
+}
+
+/// 在历史中找与 `question` 最相似且超过阈值的那一轮；短消息不检查
+pub fn find(history: &[Message], question: &str) -> Option<Duplicate> {
+  if tokens(question).len() < MIN_TOKENS {
+    return None;
+  }
+  turn_starts(history)
+    .into_iter()
+    .enumerate()
+    .filter_map(|(i, start)| match &history[start] {
+      Message::Simple { content, .. } => Some(Duplicate {
+        turn: i + 1,
+        score: similarity(content, question),
+      }),
+      Message::MultiModal { .. } | Message::Tool { .. } => None,
+    })
+    .filter(|d| d.score >= THRESHOLD)
+    .max_by(|a, b| a.score.total_cmp(&b.score))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn message(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  #[test]
+  fn test_similarity() {
+    let cases = [
+      (
+        "How do I reverse a Vec in Rust?",
+        "how do i reverse a vec in rust",
+        1.0,
+      ),
+      ("如何在 Rust 中反转 Vec？", "如何在Rust中反转Vec", 1.0),
+      ("a b c d", "a b c e", 0.6),
+      ("", "", 1.0),
+      ("alpha beta", "gamma delta", 0.0),
+    ];
+    for (a, b, expected) in cases {
+      assert!((similarity(a, b) - expected).abs() < 1e-9, "{} / {}", a, b);
+    }
+  }
+
+  #[test]
+  fn test_find_threshold() {
+    let history = vec![
+      message("user", "How do I reverse a Vec in Rust?"),
+      message("assistant", "Use v.reverse()."),
+      message("user", "请继续"),
+      message("assistant", "Or iter().rev()."),
+      message("user", "What is the difference between String and str?"),
+      message("assistant", "Ownership."),
+    ];
+    let cases = [
+      // 大小写、标点不同
+      ("how do i reverse a vec in rust", Some(1)),
+      ("what is the difference between String and str!", Some(2)),
+      // 多了几个词，重叠度低于阈值
+      (
+        "How do I reverse a Vec in Rust without allocating a new one?",
+        None,
+      ),
+      ("How do I sort a Vec in Rust?", None),
+      // 短消息从不检查
+      ("请继续", None),
+      ("reverse a Vec", None),
+    ];
+    for (question, turn) in cases {
+      assert_eq!(
+        find(&history, question).map(|d| d.turn),
+        turn,
+        "{}",
+        question
+      );
+    }
+  }
+}
@@ -0,0 +1,238 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// 从 .env 中读取的键：两个 API key 和所有 DEEPCLI_ 开头的设置
+fn is_relevant(key: &str) -> bool {
+  matches!(key, "DEEPSEEK_API_KEY" | "DASHSCOPE_API_KEY") || key.starts_with("DEEPCLI_")
+}
+
+/// 解析 .env 内容：支持 `export` 前缀、`#` 注释、单双引号和 CRLF。
+/// 单引号内原样保留；双引号内处理 \n、\t、\" 和 \\
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, String> {
+  let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+  let mut vars = vec![];
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let line = line
+      .strip_prefix("export")
+      .filter(|rest| rest.starts_with([' ', '\t']))
+      .map(str::trim_start)
+      .unwrap_or(line);
+    let Some((key, value)) = line.split_once('=') else {
+      return Err(format!("line {}: expected KEY=VALUE", i + 1));
+    };
+    let key = key.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+      return Err(format!("line {}: invalid key '{}'", i + 1, key));
+    }
+    let value =
+      parse_value(value.trim()).ok_or_else(|| format!("line {}: unterminated quote", i + 1))?;
+    vars.push((key.to_string(), value));
+  }
+  Ok(vars)
+}
+
+fn parse_value(raw: &str) -> Option<String> {
+  if let Some(rest) = raw.strip_prefix('\'') {
+    let end = rest.find('\'')?;
+    return Some(rest[..end].to_string());
+  }
+  if let Some(rest) = raw.strip_prefix('"') {
+    let mut value = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+      match c {
+        '"' => return Some(value),
+        '\\' => match chars.next()? {
+          'n' => value.push('\n'),
+          't' => value.push('\t'),
+          other => value.push(other),
+        },
+        c => value.push(c),
+      }
+    }
+    return None;
+  }
+  // 未加引号时，前面有空白的 # 开始注释
+  let end = raw
+    .char_indices()
+    .find(|&(i, c)| c == '#' && raw[..i].ends_with([' ', '\t']))
+    .map_or(raw.len(), |(i, _)| i);
+  Some(raw[..end].trim_end().to_string())
+}
+
+/// 从 `start` 向上查找 .env，到 git 仓库根目录（含）为止
+pub fn find(start: &Path) -> Option<PathBuf> {
+  for dir in start.ancestors() {
+    let candidate = dir.join(".env");
+    if candidate.is_file() {
+      return Some(candidate);
+    }
+    if dir.join(".git").exists() {
+      break;
+    }
+  }
+  None
+}
+
+/// 已加载的 .env。优先级低于真实环境变量，只作为查找的后备
+#[derive(Debug, Default)]
+pub struct DotEnv {
+  pub path: Option<PathBuf>,
+  vars: BTreeMap<String, String>,
+}
+
+impl DotEnv {
+  pub fn load(start: &Path) -> Result<Self> {
+    let Some(path) = find(start) else {
+      return Ok(Self::default());
+    };
+    let text = std::fs::read_to_string(&path).context(format!("Failed to read {:?}", path))?;
+    let vars = parse(&text)
+      .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?
+      .into_iter()
+      .filter(|(key, _)| is_relevant(key))
+      .collect();
+    Ok(Self {
+      path: Some(path),
+      vars,
+    })
+  }
+
+  /// `env` 为真实环境变量，总是优先
+  pub fn lookup(&self, key: &str, env: impl Fn(&str) -> Option<String>) -> Option<String> {
+    env(key).or_else(|| self.vars.get(key).cloned())
+  }
+
+  /// `lookup` 的值来自哪里，两处都没有时为 None
+  pub fn source(
+    &self,
+    key: &str,
+    env: impl Fn(&str) -> Option<String>,
+  ) -> Option<crate::config::Source> {
+    match env(key) {
+      Some(_) => Some(crate::config::Source::Env(key.to_string())),
+      None => self
+        .vars
+        .contains_key(key)
+        .then(|| crate::config::Source::DotEnv(key.to_string())),
+    }
+  }
+
+  /// 实际生效（没有被真实环境变量覆盖）的键
+  pub fn contributed(&self, env: impl Fn(&str) -> Option<String>) -> Vec<&str> {
+    self
+      .vars
+      .keys()
+      .filter(|key| env(key).is_none())
+      .map(String::as_str)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use std::fs;
+
+  fn parsed(text: &str) -> Vec<(String, String)> {
+    parse(text).unwrap()
+  }
+
+  fn pair(key: &str, value: &str) -> (String, String) {
+    (key.to_string(), value.to_string())
+  }
+
+  #[test]
+  fn test_parse_edge_cases() {
+    let text = "\u{feff}# 项目密钥\r\n\
+      DEEPSEEK_API_KEY=sk-123\r\n\
+      export DEEPCLI_MODEL = r1 # 注释\r\n\
+      \r\n\
+      DEEPCLI_PROMPT=\"line one\\nsay \\\"hi\\\"\"\n\
+      DEEPCLI_RAW='a \\n #b'\n\
+      DEEPCLI_HASH=abc#def\n\
+      DEEPCLI_EMPTY=\n\
+      exporter=1\n";
+    assert_eq!(
+      parsed(text),
+      [
+        pair("DEEPSEEK_API_KEY", "sk-123"),
+        pair("DEEPCLI_MODEL", "r1"),
+        pair("DEEPCLI_PROMPT", "line one\nsay \"hi\""),
+        pair("DEEPCLI_RAW", "a \\n #b"),
+        pair("DEEPCLI_HASH", "abc#def"),
+        pair("DEEPCLI_EMPTY", ""),
+        pair("exporter", "1"),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_errors_name_the_line() {
+    assert_eq!(
+      parse("A=1\nnot a pair\n").unwrap_err(),
+      "line 2: expected KEY=VALUE"
+    );
+    assert_eq!(
+      parse("A=\"open\n").unwrap_err(),
+      "line 1: unterminated quote"
+    );
+    assert!(parse("BAD KEY=1").unwrap_err().contains("invalid key"));
+  }
+
+  #[test]
+  fn test_find_stops_at_git_root() {
+    let root = temp_dir("dotenv-find");
+    let repo = root.join("repo");
+    let nested = repo.join("src").join("deep");
+    fs::create_dir_all(&nested).unwrap();
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    // 仓库外的 .env 不读取
+    fs::write(root.join(".env"), "DEEPSEEK_API_KEY=outside").unwrap();
+    assert_eq!(find(&nested), None);
+
+    fs::write(repo.join(".env"), "DEEPSEEK_API_KEY=inside\nOTHER=1").unwrap();
+    assert_eq!(find(&nested), Some(repo.join(".env")));
+    let dotenv = DotEnv::load(&nested).unwrap();
+    assert_eq!(dotenv.path, Some(repo.join(".env")));
+    // 无关的键不读取
+    assert_eq!(dotenv.lookup("OTHER", |_| None), None);
+    fs::remove_dir_all(root).unwrap();
+  }
+
+  #[test]
+  fn test_real_env_wins() {
+    let dotenv = DotEnv {
+      path: None,
+      vars: [
+        pair("DEEPSEEK_API_KEY", "from-file"),
+        pair("DEEPCLI_MODEL", "r1"),
+      ]
+      .into_iter()
+      .collect(),
+    };
+    let env = |key: &str| (key == "DEEPSEEK_API_KEY").then(|| "from-env".to_string());
+    assert_eq!(
+      dotenv.lookup("DEEPSEEK_API_KEY", env).as_deref(),
+      Some("from-env")
+    );
+    assert_eq!(dotenv.lookup("DEEPCLI_MODEL", env).as_deref(), Some("r1"));
+    assert_eq!(dotenv.contributed(env), ["DEEPCLI_MODEL"]);
+    use crate::config::Source;
+    assert_eq!(
+      dotenv.source("DEEPSEEK_API_KEY", env),
+      Some(Source::Env("DEEPSEEK_API_KEY".into()))
+    );
+    assert_eq!(
+      dotenv.source("DEEPCLI_MODEL", env),
+      Some(Source::DotEnv("DEEPCLI_MODEL".into()))
+    );
+    assert_eq!(dotenv.source("OTHER", env), None);
+  }
+}
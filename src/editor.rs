@@ -0,0 +1,716 @@
+//! 交互模式的行编辑：在 raw 模式下逐键读入，支持方向键、Ctrl-A/Ctrl-E 等 Emacs 式按键、
+//! 上下键翻阅历史输入、Tab 补全和括号粘贴。历史输入保存在数据目录的 history.txt，跨会话保留
+
+use crate::completion::Completer;
+use crate::multiline::{self, Collector};
+use crate::width::display_width;
+use crossterm::cursor::{MoveToColumn, MoveUp};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::terminal::{Clear, ClearType};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// 数据目录下保存历史输入的文件
+pub const HISTORY_FILE: &str = "history.txt";
+/// 最多保留的历史输入条数
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// 历史输入，每条一行；条目中的换行写成 `\n`，反斜杠写成 `\\`
+pub struct History {
+  entries: Vec<String>,
+  path: Option<PathBuf>,
+}
+
+fn encode(entry: &str) -> String {
+  entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn decode(line: &str) -> String {
+  let mut entry = String::new();
+  let mut chars = line.chars();
+  while let Some(c) = chars.next() {
+    match (c, c == '\\') {
+      (_, true) => match chars.next() {
+        Some('n') => entry.push('\n'),
+        Some(other) => entry.push(other),
+        None => entry.push('\\'),
+      },
+      (c, false) => entry.push(c),
+    }
+  }
+  entry
+}
+
+impl History {
+  /// 读取已有的历史；文件不存在或无法读取时从空历史开始。`path` 为 None 时不保存
+  pub fn load(path: Option<PathBuf>) -> Self {
+    let text = path
+      .as_ref()
+      .and_then(|p| fs::read_to_string(p).ok())
+      .unwrap_or_default();
+    let mut entries: Vec<String> = text.lines().map(decode).collect();
+    if entries.len() > HISTORY_LIMIT {
+      entries.drain(..entries.len() - HISTORY_LIMIT);
+      // 文件只追加，超出上限时在启动时截短
+      if let Some(path) = &path {
+        let lines: Vec<String> = entries.iter().map(|e| encode(e)).collect();
+        let _ = fs::write(path, lines.join("\n") + "\n");
+      }
+    }
+    Self { entries, path }
+  }
+
+  pub fn entries(&self) -> &[String] {
+    &self.entries
+  }
+
+  /// 记录一条输入并追加到文件；空输入和与上一条相同的输入不记录。写入失败时只保留在内存中
+  pub fn add(&mut self, entry: &str) {
+    if entry.trim().is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+      return;
+    }
+    self.entries.push(entry.to_string());
+    if self.entries.len() > HISTORY_LIMIT {
+      self.entries.remove(0);
+    }
+    if let Some(path) = &self.path {
+      let _ = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|()| {
+          let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+          writeln!(file, "{}", encode(entry))
+        });
+    }
+  }
+}
+
+/// 一次按键之后要做的事
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+  /// 继续编辑，重新显示
+  Edit,
+  /// 回车：交出这一行
+  Submit(String),
+  /// Ctrl-C：放弃这一行
+  Cancel,
+  /// 空行上按 Ctrl-C
+  Quit,
+  /// 空行上按 Ctrl-D
+  Eof,
+  /// Tab 有多个候选且没有共同的前缀可以补上：在输入下方列出
+  Candidates(Vec<String>),
+}
+
+/// 正在编辑的一行。`cursor` 是字节位置，总在字符边界上
+#[derive(Debug, Default)]
+pub struct Line {
+  text: String,
+  cursor: usize,
+  /// 正在查看的历史条目，从最新往前数
+  browsing: Option<usize>,
+  /// 开始翻阅历史前输入的内容
+  draft: String,
+}
+
+impl Line {
+  /// 预填 `text`，光标在末尾
+  fn with_text(text: &str) -> Self {
+    Self {
+      text: text.to_string(),
+      cursor: text.len(),
+      ..Self::default()
+    }
+  }
+
+  fn prev_boundary(&self) -> usize {
+    self.text[..self.cursor]
+      .char_indices()
+      .next_back()
+      .map_or(0, |(i, _)| i)
+  }
+
+  fn next_boundary(&self) -> usize {
+    self.text[self.cursor..]
+      .chars()
+      .next()
+      .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+  }
+
+  fn insert(&mut self, text: &str) {
+    self.text.insert_str(self.cursor, text);
+    self.cursor += text.len();
+  }
+
+  /// 光标前一个单词的起点，跳过单词前的空白
+  fn word_start(&self) -> usize {
+    let before = self.text[..self.cursor].trim_end();
+    before
+      .char_indices()
+      .rev()
+      .find(|(_, c)| c.is_whitespace())
+      .map_or(0, |(i, c)| i + c.len_utf8())
+  }
+
+  /// 把 `start` 到光标之间的内容换成 `text`
+  fn replace(&mut self, start: usize, text: &str) {
+    self.text.replace_range(start..self.cursor, text);
+    self.cursor = start + text.len();
+  }
+
+  /// 只有一个候选时补全并加上空格（目录除外）；多个候选时补上共同的前缀，
+  /// 没有可补的内容时返回候选列表
+  fn complete(&mut self, completer: &Completer) -> Action {
+    let (start, candidates) = completer.complete(&self.text, self.cursor);
+    match candidates.as_slice() {
+      [] => {}
+      [only] => {
+        let space = if only.ends_with('/') { "" } else { " " };
+        self.replace(start, &format!("{}{}", only, space));
+      }
+      [first, rest @ ..] => {
+        let common = rest.iter().fold(first.as_str(), |common, c| {
+          let len = common
+            .char_indices()
+            .zip(c.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(common.len().min(c.len()), |((i, _), _)| i);
+          &common[..len]
+        });
+        if common.len() <= self.cursor - start {
+          return Action::Candidates(candidates);
+        }
+        let common = common.to_string();
+        self.replace(start, &common);
+      }
+    }
+    Action::Edit
+  }
+
+  /// 翻到第 `index` 条历史（从最新往前数）；None 表示回到开始翻阅前的输入
+  fn browse(&mut self, index: Option<usize>, history: &[String]) {
+    if self.browsing.is_none() {
+      self.draft = self.text.clone();
+    }
+    self.browsing = index;
+    self.text = match index {
+      Some(i) => history[history.len() - 1 - i].clone(),
+      None => std::mem::take(&mut self.draft),
+    };
+    self.cursor = self.text.len();
+  }
+
+  pub fn handle(
+    &mut self,
+    event: &Event,
+    history: &[String],
+    completer: Option<&Completer>,
+  ) -> Action {
+    let key = match event {
+      Event::Paste(text) => {
+        self.insert(&text.replace("\r\n", "\n").replace('\r', "\n"));
+        return Action::Edit;
+      }
+      Event::Key(key) if key.kind != KeyEventKind::Release => key,
+      _ => return Action::Edit,
+    };
+    let KeyEvent {
+      code, modifiers, ..
+    } = *key;
+    let control = modifiers.contains(KeyModifiers::CONTROL);
+    match (code, control) {
+      (KeyCode::Enter, _) => {
+        let text = std::mem::take(&mut self.text);
+        *self = Self::default();
+        return Action::Submit(text);
+      }
+      (KeyCode::Char('c'), true) if self.text.is_empty() => return Action::Quit,
+      (KeyCode::Char('c'), true) => {
+        *self = Self::default();
+        return Action::Cancel;
+      }
+      (KeyCode::Char('d'), true) if self.text.is_empty() => return Action::Eof,
+      (KeyCode::Char('d'), true) | (KeyCode::Delete, _) => {
+        let end = self.next_boundary();
+        self.text.drain(self.cursor..end);
+      }
+      (KeyCode::Backspace, _) | (KeyCode::Char('h'), true) => {
+        let start = self.prev_boundary();
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+      }
+      (KeyCode::Left, _) | (KeyCode::Char('b'), true) => self.cursor = self.prev_boundary(),
+      (KeyCode::Right, _) | (KeyCode::Char('f'), true) => self.cursor = self.next_boundary(),
+      (KeyCode::Home, _) | (KeyCode::Char('a'), true) => self.cursor = 0,
+      (KeyCode::End, _) | (KeyCode::Char('e'), true) => self.cursor = self.text.len(),
+      (KeyCode::Char('k'), true) => self.text.truncate(self.cursor),
+      (KeyCode::Char('u'), true) => {
+        self.text.drain(..self.cursor);
+        self.cursor = 0;
+      }
+      (KeyCode::Char('w'), true) => {
+        let start = self.word_start();
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+      }
+      (KeyCode::Up, _) | (KeyCode::Char('p'), true) => {
+        let next = self.browsing.map_or(0, |i| i + 1);
+        if next < history.len() {
+          self.browse(Some(next), history);
+        }
+      }
+      (KeyCode::Down, _) | (KeyCode::Char('n'), true) => match self.browsing {
+        Some(0) => self.browse(None, history),
+        Some(i) => self.browse(Some(i - 1), history),
+        None => {}
+      },
+      (KeyCode::Tab, _) if let Some(completer) = completer => return self.complete(completer),
+      (KeyCode::Char(c), false) if !modifiers.contains(KeyModifiers::ALT) => {
+        self.insert(c.encode_utf8(&mut [0; 4]))
+      }
+      _ => {}
+    }
+    Action::Edit
+  }
+}
+
+/// 提示符之后显示 `text` 时某个字节位置所在的行和列（从 0 开始）。
+/// 文本中的换行另起一行并缩进到提示符的宽度；恰好写满一行时算在下一行的开头
+fn position(prompt_width: usize, text: &str, upto: usize, width: usize) -> (usize, usize) {
+  let width = width.max(prompt_width + 1);
+  let (mut row, mut col) = (0, prompt_width);
+  for c in text[..upto].chars() {
+    if c == '\n' {
+      row += 1;
+      col = prompt_width;
+      continue;
+    }
+    let w = display_width(c.encode_utf8(&mut [0; 4]));
+    if col + w > width {
+      row += 1;
+      col = 0;
+    }
+    col += w;
+  }
+  match col >= width {
+    true => (row + 1, 0),
+    false => (row, col),
+  }
+}
+
+/// 读入输入的编辑器
+pub struct Editor {
+  history: History,
+  completer: Option<Completer>,
+  /// 上次显示时光标在第几行（相对于提示符所在的行）
+  cursor_row: usize,
+  /// 空行上刚按过一次 Ctrl-C，再按一次退出
+  quit_armed: bool,
+}
+
+/// 一次读入的结果
+pub enum Input {
+  Message(String),
+  /// 空行上按了 Ctrl-D，或者在没有输入时连按两次 Ctrl-C
+  Eof,
+}
+
+impl Editor {
+  pub fn new(history: History) -> Self {
+    Self {
+      history,
+      completer: None,
+      cursor_row: 0,
+      quit_armed: false,
+    }
+  }
+
+  pub fn with_completer(mut self, completer: Completer) -> Self {
+    self.completer = Some(completer);
+    self
+  }
+
+  /// 读入一条消息，多行输入的规则与 multiline 相同。完成后记入历史
+  pub fn read(&mut self, out: &mut impl Write) -> io::Result<Input> {
+    let input = self.raw(|editor| editor.collect(out))?;
+    if let Input::Message(message) = &input {
+      self.history.add(message);
+    }
+    Ok(input)
+  }
+
+  /// 在 `prompt` 之后预填 `initial` 供修改，回车交出修改后的内容并记入历史；
+  /// 按 Ctrl-C 或在空行上按 Ctrl-D 时返回 None
+  pub fn read_with_initial(
+    &mut self,
+    prompt: &str,
+    initial: &str,
+    out: &mut impl Write,
+  ) -> io::Result<Option<String>> {
+    let action =
+      self.raw(|editor| editor.read_line(out, prompt, None, Line::with_text(initial)))?;
+    let Action::Submit(text) = action else {
+      return Ok(None);
+    };
+    self.history.add(&text);
+    Ok(Some(text))
+  }
+
+  /// 在 raw 模式和括号粘贴下运行 `f`，之后还原
+  fn raw<T>(&mut self, f: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<T> {
+    crate::terminal::with(|t| {
+      t.enable_raw_mode()?;
+      t.enable_bracketed_paste()
+    })
+    .transpose()?;
+    let result = f(self);
+    crate::terminal::with(|t| {
+      t.disable_bracketed_paste()?;
+      t.disable_raw_mode()
+    })
+    .transpose()?;
+    result
+  }
+
+  fn collect(&mut self, out: &mut impl Write) -> io::Result<Input> {
+    let mut collector = Collector::default();
+    loop {
+      let (prompt, color) = match collector.is_pending() {
+        true => (multiline::CONTINUATION, None),
+        false => (crate::transcript::PROMPT, Some(Color::Red)),
+      };
+      match self.read_line(out, prompt, color, Line::default())? {
+        Action::Submit(line) => {
+          if let Some(message) = collector.push(&line) {
+            return Ok(Input::Message(message));
+          }
+        }
+        // 多行输入中按 Ctrl-C 放弃整条消息；什么都没有输入时连按两次退出
+        Action::Cancel | Action::Quit if collector.is_pending() => {
+          self.quit_armed = false;
+          collector = Collector::default();
+        }
+        Action::Cancel => {}
+        Action::Quit if self.confirm_quit(out)? => return Ok(Input::Eof),
+        Action::Quit => {}
+        Action::Eof if collector.is_pending() => return Ok(Input::Message(collector.finish())),
+        Action::Eof => return Ok(Input::Eof),
+        Action::Edit | Action::Candidates(_) => {}
+      }
+    }
+  }
+
+  fn read_line(
+    &mut self,
+    out: &mut impl Write,
+    prompt: &str,
+    color: Option<Color>,
+    mut line: Line,
+  ) -> io::Result<Action> {
+    self.cursor_row = 0;
+    self.draw(out, prompt, color, &line)?;
+    loop {
+      let event = crossterm::event::read()?;
+      let action = line.handle(&event, self.history.entries(), self.completer.as_ref());
+      if action != Action::Quit {
+        self.quit_armed = false;
+      }
+      match action {
+        Action::Edit => self.draw(out, prompt, color, &line)?,
+        Action::Candidates(candidates) => {
+          // 在输入下方列出候选，再在下一行重新显示提示符和输入
+          let end = self.end_of(prompt, &line.text, line.text.len());
+          self.move_to(out, end)?;
+          crossterm::queue!(
+            out,
+            Print("\r\n"),
+            Print(candidates.join("  ")),
+            Print("\r\n")
+          )?;
+          self.cursor_row = 0;
+          self.draw(out, prompt, color, &line)?;
+        }
+        action => {
+          // 把光标移到输入的末尾再换行，之后的输出不会覆盖输入
+          let text = match &action {
+            Action::Submit(text) => text.as_str(),
+            _ => line.text.as_str(),
+          };
+          let end = self.end_of(prompt, text, text.len());
+          self.move_to(out, end)?;
+          if matches!(action, Action::Cancel | Action::Quit) {
+            crossterm::queue!(out, Print("^C"))?;
+          }
+          crossterm::queue!(out, Print("\r\n"))?;
+          out.flush()?;
+          return Ok(action);
+        }
+      }
+    }
+  }
+
+  /// 空行上的 Ctrl-C：第一次只提示，紧接着再按一次才退出
+  fn confirm_quit(&mut self, out: &mut impl Write) -> io::Result<bool> {
+    if std::mem::take(&mut self.quit_armed) {
+      return Ok(true);
+    }
+    self.quit_armed = true;
+    write!(out, "再按一次 Ctrl+C 退出\r\n")?;
+    Ok(false)
+  }
+
+  fn end_of(&self, prompt: &str, text: &str, upto: usize) -> (usize, usize) {
+    position(
+      display_width(prompt),
+      text,
+      upto,
+      crate::widget::terminal_width(),
+    )
+  }
+
+  /// 从上次显示的光标位置移到 `(row, col)`
+  fn move_to(&mut self, out: &mut impl Write, (row, col): (usize, usize)) -> io::Result<()> {
+    if self.cursor_row > row {
+      crossterm::queue!(out, MoveUp((self.cursor_row - row) as u16))?;
+    } else if row > self.cursor_row {
+      crossterm::queue!(out, Print("\r\n".repeat(row - self.cursor_row)))?;
+    }
+    crossterm::queue!(out, MoveToColumn(col as u16))?;
+    self.cursor_row = row;
+    Ok(())
+  }
+
+  /// 回到提示符所在的行，清除后重新显示提示符和输入，再把光标放回原处
+  fn draw(
+    &mut self,
+    out: &mut impl Write,
+    prompt: &str,
+    color: Option<Color>,
+    line: &Line,
+  ) -> io::Result<()> {
+    self.move_to(out, (0, 0))?;
+    crossterm::queue!(out, Clear(ClearType::FromCursorDown))?;
+    match color {
+      Some(color) => crossterm::queue!(out, SetForegroundColor(color), Print(prompt), ResetColor)?,
+      None => crossterm::queue!(out, Print(prompt))?,
+    }
+    for (i, part) in line.text.split('\n').enumerate() {
+      if i > 0 {
+        crossterm::queue!(out, Print("\r\n"), Print(multiline::CONTINUATION))?;
+      }
+      crossterm::queue!(out, Print(part))?;
+    }
+    let end = self.end_of(prompt, &line.text, line.text.len());
+    // 恰好写满一行时终端的光标还停在行尾，换行后才与计算的位置一致
+    if end.1 == 0 && end.0 > 0 {
+      crossterm::queue!(out, Print("\r\n"))?;
+    }
+    self.cursor_row = end.0;
+    let cursor = self.end_of(prompt, &line.text, line.cursor);
+    self.move_to(out, cursor)?;
+    out.flush()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn key(code: KeyCode) -> Event {
+    Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+  }
+
+  fn ctrl(c: char) -> Event {
+    Event::Key(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL))
+  }
+
+  fn typed(line: &mut Line, text: &str) {
+    for c in text.chars() {
+      assert_eq!(line.handle(&key(KeyCode::Char(c)), &[], None), Action::Edit);
+    }
+  }
+
+  #[test]
+  fn test_editing_keys() {
+    let mut line = Line::default();
+    typed(&mut line, "你好 world");
+    line.handle(&ctrl('a'), &[], None);
+    line.handle(&key(KeyCode::Right), &[], None);
+    line.handle(&key(KeyCode::Backspace), &[], None);
+    assert_eq!((line.text.as_str(), line.cursor), ("好 world", 0));
+    line.handle(&ctrl('e'), &[], None);
+    line.handle(&key(KeyCode::Left), &[], None);
+    typed(&mut line, "!");
+    assert_eq!(line.text, "好 worl!d");
+    line.handle(&ctrl('w'), &[], None);
+    assert_eq!(line.text, "好 d");
+    line.handle(&ctrl('k'), &[], None);
+    assert_eq!(line.text, "好 ");
+    line.handle(&ctrl('u'), &[], None);
+    assert_eq!((line.text.as_str(), line.cursor), ("", 0));
+
+    // 粘贴的换行统一为 \n，回车交出整段
+    line.handle(&Event::Paste("a\r\nb\rc".to_string()), &[], None);
+    assert_eq!(
+      line.handle(&key(KeyCode::Enter), &[], None),
+      Action::Submit("a\nb\nc".to_string())
+    );
+
+    // Ctrl-C 清空这一行；Ctrl-D 只在空行上结束输入，否则删除光标处的字符
+    typed(&mut line, "xy");
+    assert_eq!(line.handle(&ctrl('c'), &[], None), Action::Cancel);
+    assert_eq!(line.text, "");
+    // 空行上的 Ctrl-C 交给 Editor 确认是否退出
+    assert_eq!(line.handle(&ctrl('c'), &[], None), Action::Quit);
+    typed(&mut line, "xy");
+    line.handle(&key(KeyCode::Home), &[], None);
+    assert_eq!(line.handle(&ctrl('d'), &[], None), Action::Edit);
+    assert_eq!(line.text, "y");
+    line.handle(&ctrl('d'), &[], None);
+    assert_eq!(line.handle(&ctrl('d'), &[], None), Action::Eof);
+  }
+
+  #[test]
+  fn test_prefilled_line_is_edited_in_place() {
+    let mut line = Line::with_text("原来的问题");
+    assert_eq!(line.cursor, "原来的问题".len());
+    typed(&mut line, "？");
+    line.handle(&ctrl('a'), &[], None);
+    line.handle(&key(KeyCode::Delete), &[], None);
+    line.handle(&key(KeyCode::Delete), &[], None);
+    typed(&mut line, "新");
+    assert_eq!(
+      line.handle(&key(KeyCode::Enter), &[], None),
+      Action::Submit("新的问题？".to_string())
+    );
+  }
+
+  #[test]
+  fn test_quit_needs_a_second_ctrl_c() {
+    let mut editor = Editor::new(History::load(None));
+    let mut out = Vec::new();
+    assert!(!editor.confirm_quit(&mut out).unwrap());
+    assert_eq!(String::from_utf8(out).unwrap(), "再按一次 Ctrl+C 退出\r\n");
+    assert!(editor.confirm_quit(&mut Vec::new()).unwrap());
+    // 退出后重新计数
+    assert!(!editor.confirm_quit(&mut Vec::new()).unwrap());
+  }
+
+  #[test]
+  fn test_tab_completes_commands() {
+    let completer = Completer::new(None, vec!["system".to_string()]);
+    let tab = key(KeyCode::Tab);
+    let mut line = Line::default();
+    typed(&mut line, "\\rea");
+    assert_eq!(line.handle(&tab, &[], Some(&completer)), Action::Edit);
+    assert_eq!((line.text.as_str(), line.cursor), ("\\reasoning ", 11));
+    typed(&mut line, "o");
+    line.handle(&tab, &[], Some(&completer));
+    assert_eq!(line.text, "\\reasoning o");
+    typed(&mut line, "f");
+    line.handle(&tab, &[], Some(&completer));
+    assert_eq!(line.text, "\\reasoning off ");
+
+    // 多个候选：先补上共同的前缀，再按一次列出候选
+    let mut line = Line::default();
+    typed(&mut line, "\\ke");
+    line.handle(&tab, &[], Some(&completer));
+    assert_eq!(line.text, "\\keep-verification ");
+    let mut line = Line::default();
+    typed(&mut line, "\\de");
+    assert_eq!(
+      line.handle(&tab, &[], Some(&completer)),
+      Action::Candidates(vec![
+        "\\delete".to_string(),
+        "\\describe".to_string(),
+        "\\detach".to_string()
+      ])
+    );
+    assert_eq!(line.text, "\\de");
+    let mut line = Line::default();
+    typed(&mut line, "\\unse");
+    line.handle(&tab, &[], Some(&completer));
+    assert_eq!(line.text, "\\unsetvar ");
+    // 光标之后的内容保留
+    let mut line = Line::default();
+    typed(&mut line, "\\set s x");
+    line.handle(&key(KeyCode::Left), &[], None);
+    line.handle(&key(KeyCode::Left), &[], None);
+    line.handle(&tab, &[], Some(&completer));
+    assert_eq!(line.text, "\\set system  x");
+    // 没有补全来源时 Tab 什么也不做
+    let mut line = Line::default();
+    typed(&mut line, "\\rea");
+    line.handle(&tab, &[], None);
+    assert_eq!(line.text, "\\rea");
+  }
+
+  #[test]
+  fn test_history_is_browsed_and_persisted() {
+    let dir = std::env::temp_dir().join(format!("deepcli-editor-{}", std::process::id()));
+    let path = dir.join(HISTORY_FILE);
+    let mut history = History::load(Some(path.clone()));
+    assert!(history.entries().is_empty());
+    for entry in [
+      "\\stats",
+      "第一行\n第二行 C:\\dir\\new",
+      "\\stats",
+      "\\stats",
+      " ",
+    ] {
+      history.add(entry);
+    }
+    let reloaded = History::load(Some(path.clone()));
+    assert_eq!(
+      reloaded.entries(),
+      ["\\stats", "第一行\n第二行 C:\\dir\\new", "\\stats"]
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+
+    // 上键从最新的一条往前翻，下键翻回开始翻阅前的输入
+    let entries = reloaded.entries();
+    let mut line = Line::default();
+    typed(&mut line, "草稿");
+    line.handle(&key(KeyCode::Up), entries, None);
+    assert_eq!(line.text, "\\stats");
+    line.handle(&ctrl('p'), entries, None);
+    line.handle(&key(KeyCode::Up), entries, None);
+    line.handle(&key(KeyCode::Up), entries, None);
+    assert_eq!(line.text, "\\stats");
+    line.handle(&key(KeyCode::Down), entries, None);
+    assert_eq!(line.text, "第一行\n第二行 C:\\dir\\new");
+    line.handle(&key(KeyCode::Down), entries, None);
+    line.handle(&key(KeyCode::Down), entries, None);
+    assert_eq!((line.text.as_str(), line.cursor), ("草稿", "草稿".len()));
+
+    // 超出上限时只保留最新的条目
+    let lines: Vec<String> = (0..HISTORY_LIMIT + 5).map(|i| i.to_string()).collect();
+    fs::write(&path, lines.join("\n")).unwrap();
+    let trimmed = History::load(Some(path.clone()));
+    assert_eq!(trimmed.entries().len(), HISTORY_LIMIT);
+    assert_eq!(trimmed.entries()[0], "5");
+    assert_eq!(
+      fs::read_to_string(&path).unwrap().lines().count(),
+      HISTORY_LIMIT
+    );
+    fs::remove_dir_all(dir).ok();
+  }
+
+  #[test]
+  fn test_cursor_position_wraps_like_the_terminal() {
+    // 宽度 10，提示符占 2 列
+    assert_eq!(position(2, "abc", 3, 10), (0, 5));
+    assert_eq!(position(2, "abcdefgh", 8, 10), (1, 0));
+    assert_eq!(position(2, "abcdefghi", 9, 10), (1, 1));
+    // 放不下的宽字符整个移到下一行
+    assert_eq!(position(2, "abcdefg你", 10, 10), (1, 2));
+    // 换行后缩进到提示符的宽度
+    assert_eq!(position(2, "ab\ncd", 5, 10), (1, 4));
+    assert_eq!(position(2, "ab\ncd", 2, 10), (0, 4));
+  }
+}
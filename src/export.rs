@@ -0,0 +1,399 @@
+use crate::api::{Content, Message};
+use crate::history::turn_starts;
+use crate::notes::Notes;
+use crate::reasoning::Reasoning;
+use crate::session::SessionMeta;
+use crate::transcript;
+
+/// 导出页头部显示的会话信息
+#[derive(Debug, Clone, Default)]
+pub struct Meta {
+  pub title: String,
+  pub description: String,
+  pub model: String,
+  /// 已格式化的时间，例如 2026-10-14 10:00
+  pub created: String,
+}
+
+impl Meta {
+  pub fn from_session(meta: &SessionMeta) -> Self {
+    Self {
+      title: meta.title.clone(),
+      description: meta.description.clone(),
+      model: meta.model.clone(),
+      created: meta.created_at.format("%Y-%m-%d %H:%M").to_string(),
+    }
+  }
+}
+
+pub fn text_of(message: &Message) -> String {
+  match message {
+    Message::Simple { content, .. } | Message::Tool { content, .. } => content.clone(),
+    Message::MultiModal { content, .. } => content
+      .iter()
+      .filter_map(|c| match c {
+        Content::Text(t) => Some(t.text.as_str()),
+        Content::Image(_) => None,
+      })
+      .collect::<Vec<_>>()
+      .join("\n"),
+  }
+}
+
+pub fn role_of(message: &Message) -> &str {
+  match message {
+    Message::Simple { role, .. }
+    | Message::MultiModal { role, .. }
+    | Message::Tool { role, .. } => role,
+  }
+}
+
+/// 把历史按轮分组，自动续写的片段归入所在的轮
+fn turns(history: &[Message]) -> Vec<&[Message]> {
+  let starts = turn_starts(history);
+  let mut turns = vec![];
+  if let Some(&first) = starts.first()
+    && first > 0
+  {
+    // 第一轮之前的 system 消息单独成组，不计轮次
+    turns.push(&history[..first]);
+  }
+  for (i, &start) in starts.iter().enumerate() {
+    let end = starts.get(i + 1).copied().unwrap_or(history.len());
+    turns.push(&history[start..end]);
+  }
+  if starts.is_empty() && !history.is_empty() {
+    turns.push(history);
+  }
+  turns
+}
+
+/// 导出为 Markdown：每轮的推理过程以折叠块、批注以引用块形式放在该轮回答之后
+pub fn markdown(history: &[Message], notes: &Notes, reasoning: &Reasoning) -> String {
+  let mut out = String::new();
+  let starts = turn_starts(history);
+  let mut turn = 0;
+  let flush = |out: &mut String, turn: usize| {
+    if let Some(text) = reasoning.for_turn(turn) {
+      out.push_str(&format!(
+        "<details>\n<summary>推理过程</summary>\n\n{}\n\n</details>\n\n",
+        text
+      ));
+    }
+    for note in notes.for_turn(turn) {
+      let quoted: Vec<_> = note.lines().map(|l| format!("> {}", l)).collect();
+      out.push_str(&format!("> **批注**\n{}\n\n", quoted.join("\n")));
+    }
+  };
+  for (i, message) in history.iter().enumerate() {
+    if starts.contains(&i) {
+      flush(&mut out, turn);
+      turn += 1;
+      out.push_str(&format!("## 第 {} 轮\n\n", turn));
+    }
+    // 用户消息按转录形式放进代码块，保留原样的换行、空行和代码块
+    let text = match role_of(message) {
+      "user" => transcript::user_message_markdown(&text_of(message)),
+      _ => text_of(message).trim_end().to_string(),
+    };
+    out.push_str(&format!("**{}**:\n\n{}\n\n", message.speaker(), text));
+  }
+  flush(&mut out, turn);
+  out
+}
+
+const STYLE: &str = "
+body { margin: 0 auto; max-width: 860px; padding: 24px; background: #f6f7f9; color: #1f2328;
+  font: 15px/1.6 -apple-system, 'Segoe UI', 'PingFang SC', 'Microsoft YaHei', sans-serif; }
+header { border-bottom: 1px solid #d0d7de; margin-bottom: 24px; }
+header .meta { color: #656d76; font-size: 13px; }
+h2 { color: #656d76; font-size: 13px; font-weight: normal; margin: 24px 0 8px; }
+.message { border-radius: 12px; margin: 8px 0; padding: 8px 14px; max-width: 85%; }
+.message.user { background: #dbeafe; margin-left: auto; }
+.message.assistant { background: #fff; border: 1px solid #d0d7de; }
+.message.system { background: #f0f0f0; color: #656d76; font-size: 13px; max-width: 100%; }
+.speaker { color: #656d76; font-size: 12px; }
+pre { background: #0d1117; color: #e6edf3; border-radius: 6px; overflow-x: auto; padding: 10px; }
+code { font-family: ui-monospace, Menlo, Consolas, monospace; font-size: 13px; }
+p code { background: #eff1f3; border-radius: 4px; padding: 1px 4px; }
+img { max-width: 100%; border-radius: 6px; }
+blockquote.note { border-left: 3px solid #d4a72c; color: #7d4e00; margin: 8px 0; padding: 0 12px; }
+";
+
+fn escape(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '&' => out.push_str("&amp;"),
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '"' => out.push_str("&quot;"),
+      '\'' => out.push_str("&#39;"),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+/// 段落中的 `行内代码`
+fn inline(text: &str) -> String {
+  let parts: Vec<_> = text.split('`').collect();
+  // 反引号不成对时原样保留
+  if parts.len() % 2 == 0 {
+    return escape(text).replace('\n', "<br>\n");
+  }
+  parts
+    .iter()
+    .enumerate()
+    .map(|(i, part)| match i % 2 {
+      1 => format!("<code>{}</code>", escape(part)),
+      _ => escape(part).replace('\n', "<br>\n"),
+    })
+    .collect()
+}
+
+/// 消息正文：``` 代码块原样放进 <pre>，其余按空行分段
+fn body(text: &str) -> String {
+  let mut out = String::new();
+  let mut prose: Vec<&str> = vec![];
+  let mut code: Option<(String, Vec<&str>)> = None;
+  let flush_prose = |out: &mut String, prose: &mut Vec<&str>| {
+    let text = prose.join("\n");
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+      out.push_str(&format!("<p>{}</p>\n", inline(paragraph)));
+    }
+    prose.clear();
+  };
+  let code_block = |lang: &str, lines: &[&str]| {
+    let class = match lang {
+      "" => String::new(),
+      lang => format!(" class=\"language-{}\"", escape(lang)),
+    };
+    format!(
+      "<pre><code{}>{}</code></pre>\n",
+      class,
+      escape(&lines.join("\n"))
+    )
+  };
+  for line in text.lines() {
+    let fence = line.trim_start().strip_prefix("```");
+    match (&mut code, fence) {
+      (Some((lang, lines)), Some(_)) => {
+        out.push_str(&code_block(lang, lines));
+        code = None;
+      }
+      (Some((_, lines)), None) => lines.push(line),
+      (None, Some(lang)) => {
+        flush_prose(&mut out, &mut prose);
+        code = Some((lang.trim().to_string(), vec![]));
+      }
+      (None, None) => prose.push(line),
+    }
+  }
+  // 未闭合的代码块也按代码输出
+  if let Some((lang, lines)) = &code {
+    out.push_str(&code_block(lang, lines));
+  }
+  flush_prose(&mut out, &mut prose);
+  out
+}
+
+fn images(message: &Message) -> String {
+  let Message::MultiModal { content, .. } = message else {
+    return String::new();
+  };
+  content
+    .iter()
+    .filter_map(|c| match c {
+      Content::Image(image) => Some(image.image_url.url.as_str()),
+      Content::Text(_) => None,
+    })
+    .map(|url| match url.starts_with("data:") {
+      true => format!("<img src=\"{}\" alt=\"\">\n", escape(url)),
+      // 不引用外部资源，远程图片只保留链接文本
+      false => format!("<p>[图片 {}]</p>\n", escape(url)),
+    })
+    .collect()
+}
+
+/// 导出为单个 HTML 文件，样式内嵌、图片以 data URI 嵌入，不依赖外部资源。
+/// 附件引用需要先用 AttachmentStore::expand 展开，图片才会出现在页面中
+pub fn html(meta: &Meta, history: &[Message], notes: &Notes, reasoning: &Reasoning) -> String {
+  let title = match meta.title.trim() {
+    "" => "deepcli 会话",
+    title => title,
+  };
+  let mut out = format!(
+    "<!DOCTYPE html>\n<html lang=\"zh-CN\">\n<head>\n<meta charset=\"utf-8\">\n\
+     <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<header>\n<h1>{}</h1>\n",
+    escape(title),
+    STYLE,
+    escape(title)
+  );
+  let mut facts = vec![];
+  if !meta.model.is_empty() {
+    facts.push(format!("模型 {}", escape(&meta.model)));
+  }
+  if !meta.created.is_empty() {
+    facts.push(format!("创建于 {}", escape(&meta.created)));
+  }
+  let counted = turn_starts(history).len();
+  facts.push(format!("{} 轮", counted));
+  out.push_str(&format!("<p class=\"meta\">{}</p>\n", facts.join(" · ")));
+  if !meta.description.trim().is_empty() {
+    out.push_str(&format!("<p>{}</p>\n", inline(meta.description.trim())));
+  }
+  out.push_str("</header>\n");
+  let mut turn = 0;
+  for group in turns(history) {
+    let counts = group.first().is_some_and(|m| role_of(m) == "user");
+    out.push_str("<section>\n");
+    if counts {
+      turn += 1;
+      out.push_str(&format!("<h2>第 {} 轮</h2>\n", turn));
+    }
+    for message in group {
+      out.push_str(&format!(
+        "<div class=\"message {}\">\n<div class=\"speaker\">{}</div>\n{}{}</div>\n",
+        escape(role_of(message)),
+        escape(&message.speaker()),
+        body(&text_of(message)),
+        images(message)
+      ));
+    }
+    if counts {
+      if let Some(text) = reasoning.for_turn(turn) {
+        out.push_str(&format!(
+          "<details class=\"reasoning\">\n<summary>推理过程</summary>\n{}</details>\n",
+          body(text)
+        ));
+      }
+      for note in notes.for_turn(turn) {
+        out.push_str(&format!(
+          "<blockquote class=\"note\"><strong>批注</strong> {}</blockquote>\n",
+          inline(note)
+        ));
+      }
+    }
+    out.push_str("</section>\n");
+  }
+  out.push_str("</body>\n</html>\n");
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::api::{ImageContent, ImageUrl, TextContent};
+
+  fn message(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  fn history() -> Vec<Message> {
+    vec![
+      message("user", "为什么会死锁？"),
+      message("assistant", "因为持有锁时 await。"),
+      message("user", "怎么修？"),
+      message("assistant", "先释放锁。"),
+    ]
+  }
+
+  fn normalize(html: &str) -> String {
+    html.split_whitespace().collect::<Vec<_>>().join(" ")
+  }
+
+  #[test]
+  fn test_markdown_places_notes_after_their_turn() {
+    let mut notes = Notes::default();
+    notes.add(1, "这个回答错了\n见第 2 轮");
+    notes.add(2, "已验证");
+    let mut reasoning = Reasoning::default();
+    reasoning.record(1, "先看 await 点");
+    let markdown = markdown(&history(), &notes, &reasoning);
+    assert!(markdown.starts_with("## 第 1 轮\n\n**user**:\n\n```text\n> 为什么会死锁？\n```\n\n"));
+    let first = markdown.find("> 这个回答错了\n> 见第 2 轮").unwrap();
+    let thought = markdown
+      .find("<details>\n<summary>推理过程</summary>\n\n先看 await 点\n\n</details>")
+      .unwrap();
+    assert!(thought > markdown.find("持有锁时").unwrap() && thought < first);
+    assert_eq!(markdown.matches("<details>").count(), 1);
+    assert!(first < markdown.find("## 第 2 轮").unwrap());
+    assert!(markdown.ends_with("> **批注**\n> 已验证\n\n"));
+  }
+
+  #[test]
+  fn test_html_matches_golden_file() {
+    let history = vec![
+      message("system", "You are a helpful assistant."),
+      Message::MultiModal {
+        role: "user".to_string(),
+        content: vec![
+          Content::Text(TextContent {
+            content_type: "text".to_string(),
+            text: "这张图里的 <div> 为什么没有居中？".to_string(),
+          }),
+          Content::Image(ImageContent {
+            content_type: "image_url".to_string(),
+            image_url: ImageUrl {
+              url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+            },
+          }),
+        ],
+        name: Some("alice".to_string()),
+      },
+      message(
+        "assistant",
+        "父元素缺少 `display: flex`。\n\n改成：\n\n```css\n.box {\n  display: flex;\n  justify-content: center;\n}\n```\n\n这样 a < b & c 也不会被转义错。",
+      ),
+      message("user", "Rust 里呢？"),
+      message(
+        "assistant",
+        "```rust\nfn main() {\n    println!(\"居中\");\n}\n```",
+      ),
+    ];
+    let mut notes = Notes::default();
+    notes.add(1, "已在 Safari 验证");
+    let meta = Meta {
+      title: "CSS 居中".to_string(),
+      description: "排查 `flex` 布局".to_string(),
+      model: "deepseek-reasoner".to_string(),
+      created: "2026-10-14 10:00".to_string(),
+    };
+    let html = html(&meta, &history, &notes, &Reasoning::default());
+    assert_eq!(
+      normalize(&html),
+      normalize(include_str!("testdata/export.html"))
+    );
+    assert!(!html.contains("http://") && !html.contains("https://"));
+  }
+
+  #[test]
+  fn test_html_without_metadata() {
+    let html = html(
+      &Meta::default(),
+      &history(),
+      &Notes::default(),
+      &Reasoning::default(),
+    );
+    assert!(html.contains("<title>deepcli 会话</title>"));
+    assert!(html.contains("<p class=\"meta\">2 轮</p>"));
+    assert!(html.contains("<h2>第 2 轮</h2>"));
+    // 远程图片不作为外部资源引用
+    let remote = Message::MultiModal {
+      role: "user".to_string(),
+      content: vec![Content::Image(ImageContent {
+        content_type: "image_url".to_string(),
+        image_url: ImageUrl {
+          url: "https://example.com/a.png".to_string(),
+        },
+      })],
+      name: None,
+    };
+    assert!(!images(&remote).contains("<img"));
+  }
+}
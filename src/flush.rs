@@ -0,0 +1,151 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// 本地终端的合并间隔
+pub const LOCAL_INTERVAL: Duration = Duration::from_millis(10);
+/// 远程（SSH）终端的合并间隔
+pub const REMOTE_INTERVAL: Duration = Duration::from_millis(100);
+/// 至少有这么多次 flush 耗时样本后才按测量结果调整
+const MIN_SAMPLES: usize = 8;
+const MAX_SAMPLES: usize = 32;
+/// 合并间隔取 flush 耗时中位数的这个倍数
+const LATENCY_FACTOR: u32 = 20;
+
+/// 是否运行在 SSH 会话中
+pub fn is_remote() -> bool {
+  std::env::var_os("SSH_CONNECTION").is_some_and(|v| !v.is_empty())
+}
+
+/// 根据最近的 flush 耗时选择合并间隔。SSH 会话固定用 REMOTE_INTERVAL；
+/// 否则样本足够时取中位数的 LATENCY_FACTOR 倍，限制在本地与远程间隔之间
+pub fn choose_interval(samples: &[Duration], remote: bool) -> Duration {
+  if remote {
+    return REMOTE_INTERVAL;
+  }
+  if samples.len() < MIN_SAMPLES {
+    return LOCAL_INTERVAL;
+  }
+  let mut sorted = samples.to_vec();
+  sorted.sort();
+  (sorted[sorted.len() / 2] * LATENCY_FACTOR).clamp(LOCAL_INTERVAL, REMOTE_INTERVAL)
+}
+
+/// 合并流式输出的 flush：距上次真正 flush 不足合并间隔时只记下待刷新，
+/// 下一次写入后的 flush 或 drop 时再刷新
+pub struct FlushWriter<W: Write> {
+  inner: W,
+  /// --flush-interval-ms 指定的固定间隔
+  fixed: Option<Duration>,
+  remote: bool,
+  samples: Vec<Duration>,
+  last_flush: Option<Instant>,
+  pending: bool,
+  flushes: usize,
+}
+
+impl<W: Write> FlushWriter<W> {
+  pub fn new(inner: W, fixed: Option<Duration>, remote: bool) -> Self {
+    Self {
+      inner,
+      fixed,
+      remote,
+      samples: Vec::new(),
+      last_flush: None,
+      pending: false,
+      flushes: 0,
+    }
+  }
+
+  pub fn interval(&self) -> Duration {
+    self
+      .fixed
+      .unwrap_or_else(|| choose_interval(&self.samples, self.remote))
+  }
+
+  /// 实际执行的 flush 次数
+  pub fn flushes(&self) -> usize {
+    self.flushes
+  }
+
+  /// 立即刷新，并记录耗时作为终端延迟的样本
+  pub fn flush_now(&mut self) -> io::Result<()> {
+    let started = Instant::now();
+    self.inner.flush()?;
+    let elapsed = started.elapsed();
+    if self.samples.len() == MAX_SAMPLES {
+      self.samples.remove(0);
+    }
+    self.samples.push(elapsed);
+    self.last_flush = Some(Instant::now());
+    self.pending = false;
+    self.flushes += 1;
+    Ok(())
+  }
+}
+
+impl<W: Write> Write for FlushWriter<W> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.pending = true;
+    self.inner.write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    let due = self
+      .last_flush
+      .is_none_or(|last| last.elapsed() >= self.interval());
+    if due { self.flush_now() } else { Ok(()) }
+  }
+}
+
+impl<W: Write> Drop for FlushWriter<W> {
+  fn drop(&mut self) {
+    if self.pending {
+      let _ = self.flush_now();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ms(n: &[u64]) -> Vec<Duration> {
+    n.iter().map(|&n| Duration::from_millis(n)).collect()
+  }
+
+  #[test]
+  fn test_choose_interval() {
+    // 样本不足时按本地处理，SSH 会话总是远程间隔
+    assert_eq!(choose_interval(&ms(&[50, 50]), false), LOCAL_INTERVAL);
+    assert_eq!(choose_interval(&[], true), REMOTE_INTERVAL);
+    // 本地终端的 flush 几乎不耗时
+    let local = vec![Duration::from_micros(20); 10];
+    assert_eq!(choose_interval(&local, false), LOCAL_INTERVAL);
+    // 中位数 2ms → 40ms；个别很慢的样本不影响
+    let mixed = ms(&[1, 2, 2, 2, 3, 2, 900, 2, 1]);
+    assert_eq!(choose_interval(&mixed, false), Duration::from_millis(40));
+    let slow = ms(&[30; 8]);
+    assert_eq!(choose_interval(&slow, false), REMOTE_INTERVAL);
+  }
+
+  #[test]
+  fn test_flushes_are_coalesced() {
+    let mut out = FlushWriter::new(Vec::new(), Some(Duration::from_secs(60)), false);
+    for chunk in ["a", "b", "c"] {
+      write!(out, "{}", chunk).unwrap();
+      out.flush().unwrap();
+    }
+    // 第一次立即刷新，其余在间隔内被合并
+    assert_eq!(out.flushes(), 1);
+    assert!(out.pending);
+    assert_eq!(out.inner, b"abc");
+
+    let mut eager = FlushWriter::new(Vec::new(), Some(Duration::ZERO), false);
+    for _ in 0..3 {
+      write!(eager, "x").unwrap();
+      eager.flush().unwrap();
+    }
+    assert_eq!(eager.flushes(), 3);
+    assert!(!eager.pending);
+  }
+}
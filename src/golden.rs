@@ -0,0 +1,314 @@
+//! 请求组装的回归测试：每个场景经过每个内置提供方的客户端发出，请求体与
+//! `testdata/golden/<提供方>/<场景>.json` 中保存的结果比较；本地服务器同时回放该提供方的
+//! 录制响应（`response.json`、`stream.sse`、`tools.sse`），解析出的回答或增量序列也一并比较。
+//!
+//! 有意修改请求格式后用 `DEEPCLI_BLESS=1 cargo test golden` 重新生成，提交前检查差异。
+//! JSON 的键按字母排序，字段顺序的变化不算差异。
+
+use crate::api::{ApiClient, ChatBackend, FunctionSpec, Message, StreamChunk, ToolSpec};
+use crate::profile::{self, Profile};
+use anyhow::Result;
+use futures_util::StreamExt;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// 重新生成期望结果的环境变量
+const BLESS_ENV: &str = "DEEPCLI_BLESS";
+
+/// 内部的典型请求，每个提供方都要能正确组装
+#[derive(Debug, Clone, Copy)]
+enum Scenario {
+  /// 一次性问题
+  Plain,
+  /// 带历史的流式对话
+  History,
+  /// 附带图片
+  MultiModal,
+  /// --json
+  Json,
+  /// 带函数的流式对话
+  Tools,
+  /// --no-system
+  NoSystem,
+}
+
+const SCENARIOS: [Scenario; 6] = [
+  Scenario::Plain,
+  Scenario::History,
+  Scenario::MultiModal,
+  Scenario::Json,
+  Scenario::Tools,
+  Scenario::NoSystem,
+];
+
+impl Scenario {
+  fn name(self) -> &'static str {
+    match self {
+      Self::Plain => "plain",
+      Self::History => "history",
+      Self::MultiModal => "multimodal",
+      Self::Json => "json",
+      Self::Tools => "tools",
+      Self::NoSystem => "no_system",
+    }
+  }
+
+  /// 回放的录制响应
+  fn fixture(self) -> &'static str {
+    match self {
+      Self::Plain | Self::Json | Self::NoSystem => "response.json",
+      Self::History | Self::MultiModal => "stream.sse",
+      Self::Tools => "tools.sse",
+    }
+  }
+
+  /// 通过正式的请求路径发出，返回规整后的解析结果
+  async fn send(self, client: ApiClient, profile: &Profile) -> Result<Value> {
+    let model = |alias: &str| crate::cli::map_model(alias, profile).unwrap();
+    match self {
+      Self::Plain => {
+        let response = client
+          .call_api(&model("chat"), "你好", Some(0.7), Some(256), false)
+          .await?;
+        Ok(serde_json::to_value(response)?)
+      }
+      Self::Json => {
+        let response = client
+          .call_api(&model("chat"), "列出三种颜色", None, Some(256), true)
+          .await?;
+        Ok(serde_json::to_value(response)?)
+      }
+      Self::NoSystem => {
+        let response = client
+          .with_no_system(true)
+          .call_api(&model("chat"), "你好", None, None, false)
+          .await?;
+        Ok(serde_json::to_value(response)?)
+      }
+      Self::History => {
+        let history = vec![
+          message("system", "You are a helpful assistant."),
+          message("user", "1 + 1 等于几？"),
+          message("assistant", "2"),
+          message("user", "再加 3 呢？"),
+        ];
+        let stream = client
+          .stream(&model("r1"), history, None, Some(1024))
+          .await?;
+        Ok(chunks(stream).await)
+      }
+      Self::MultiModal => {
+        let image = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/testdata/dot.png");
+        let stream = client
+          .call_api_with_file_stream(
+            &model("chat"),
+            "图里是什么颜色？",
+            &image,
+            None,
+            None,
+            false,
+          )
+          .await?;
+        Ok(chunks(stream).await)
+      }
+      Self::Tools => {
+        let tool = ToolSpec {
+          kind: "function",
+          function: FunctionSpec {
+            name: "lookup_ticket".to_string(),
+            description: "查询工单状态".to_string(),
+            parameters: json!({
+              "type": "object",
+              "properties": {"id": {"type": "string"}},
+              "required": ["id"],
+            }),
+          },
+        };
+        let stream = client
+          .with_tools(vec![tool])
+          .stream(
+            &model("chat"),
+            vec![message("user", "OPS-42 现在是什么状态？")],
+            None,
+            None,
+          )
+          .await?;
+        Ok(chunks(stream).await)
+      }
+    }
+  }
+}
+
+fn message(role: &str, content: &str) -> Message {
+  Message::Simple {
+    role: role.to_string(),
+    content: content.to_string(),
+    name: None,
+  }
+}
+
+/// 增量序列，只保留有值的字段；流中的错误记为 `{"error": ...}`
+async fn chunks(stream: crate::api::ChunkStream) -> Value {
+  let items: Vec<Result<StreamChunk>> = stream.collect().await;
+  let items = items.iter().map(|item| match item {
+    Ok(chunk) => {
+      let mut value = json!({});
+      if !chunk.content.is_empty() {
+        value["content"] = json!(chunk.content);
+      }
+      if !chunk.reasoning.is_empty() {
+        value["reasoning"] = json!(chunk.reasoning);
+      }
+      if let Some(reason) = &chunk.finish_reason {
+        value["finish_reason"] = json!(reason);
+      }
+      if !chunk.tool_calls.is_empty() {
+        let calls = chunk.tool_calls.iter().map(|call| {
+          json!({
+            "index": call.index,
+            "id": call.id,
+            "name": call.name,
+            "arguments": call.arguments,
+          })
+        });
+        value["tool_calls"] = Value::Array(calls.collect());
+      }
+      if let Some(usage) = chunk.usage {
+        value["usage"] = json!(usage);
+      }
+      value
+    }
+    Err(e) => json!({"error": e.to_string()}),
+  });
+  Value::Array(items.collect())
+}
+
+/// 收到的请求：方法和路径，以及解析后的请求体
+#[derive(Debug)]
+struct Captured {
+  target: String,
+  body: Value,
+}
+
+/// 应答一个连接：记下请求，返回录制的响应
+async fn serve(
+  response: String,
+  content_type: &'static str,
+) -> (String, tokio::task::JoinHandle<Captured>) {
+  let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let addr = listener.local_addr().unwrap();
+  let handle = tokio::spawn(async move {
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut request = vec![];
+    let mut buf = [0; 4096];
+    let (head, body) = loop {
+      let n = socket.read(&mut buf).await.unwrap();
+      assert!(n > 0, "connection closed before the request was complete");
+      request.extend_from_slice(&buf[..n]);
+      let text = String::from_utf8_lossy(&request);
+      let Some(end) = text.find("\r\n\r\n") else {
+        continue;
+      };
+      let length = text[..end]
+        .lines()
+        .find_map(|l| {
+          l.to_ascii_lowercase()
+            .strip_prefix("content-length:")
+            .map(|v| v.trim().parse::<usize>().unwrap())
+        })
+        .unwrap_or(0);
+      if request.len() >= end + 4 + length {
+        break (text[..end].to_string(), request[end + 4..].to_vec());
+      }
+    };
+    let reply = format!(
+      "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+      content_type,
+      response.len(),
+      response
+    );
+    socket.write_all(reply.as_bytes()).await.unwrap();
+    let target = head.lines().next().unwrap_or_default();
+    // 去掉 HTTP 版本，只比较方法和路径
+    let target = target.rsplit_once(' ').map_or(target, |(t, _)| t);
+    Captured {
+      target: target.to_string(),
+      body: serde_json::from_slice(&body).unwrap(),
+    }
+  });
+  (format!("http://{}", addr), handle)
+}
+
+fn golden_dir(profile: &Profile) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR"))
+    .join("src/testdata/golden")
+    .join(profile.name)
+}
+
+/// 一个场景经过一个提供方的结果：请求和解析出的响应
+async fn run(scenario: Scenario, profile: &Profile) -> Value {
+  let fixture = golden_dir(profile).join(scenario.fixture());
+  let response =
+    std::fs::read_to_string(&fixture).unwrap_or_else(|e| panic!("{}: {}", fixture.display(), e));
+  let content_type = match scenario.fixture().ends_with(".sse") {
+    true => "text/event-stream",
+    false => "application/json",
+  };
+  let (addr, captured) = serve(response, content_type).await;
+  // 保留提供方地址中的路径，例如 DashScope 的 /compatible-mode/v1
+  let path = reqwest::Url::parse(profile.base_url)
+    .unwrap()
+    .path()
+    .to_string();
+  let client = ApiClient::new("test_key".to_string())
+    .with_base_url(&format!("{}{}", addr, path))
+    .with_stream_idle(None);
+  let response = match scenario.send(client, profile).await {
+    Ok(response) => response,
+    Err(e) => json!({"error": format!("{:#}", e)}),
+  };
+  let captured = captured.await.unwrap();
+  json!({
+    "request": {"target": captured.target, "body": captured.body},
+    "response": response,
+  })
+}
+
+/// 与保存的结果比较；不同时返回说明。设置了 DEEPCLI_BLESS 时改为写入
+fn check(path: &Path, actual: &Value) -> Option<String> {
+  let actual = serde_json::to_string_pretty(actual).unwrap() + "\n";
+  if std::env::var_os(BLESS_ENV).is_some() {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, actual).unwrap();
+    return None;
+  }
+  match std::fs::read_to_string(path) {
+    Ok(expected) if expected == actual => None,
+    Ok(expected) => Some(format!(
+      "{} differs:\n--- expected\n{}\n+++ actual\n{}",
+      path.display(),
+      expected,
+      actual
+    )),
+    Err(e) => Some(format!("{}: {}", path.display(), e)),
+  }
+}
+
+#[tokio::test]
+async fn golden_requests_and_responses() {
+  let mut failures = vec![];
+  for profile in &profile::BUILTIN_PROFILES {
+    for scenario in SCENARIOS {
+      let actual = run(scenario, profile).await;
+      let path = golden_dir(profile).join(format!("{}.json", scenario.name()));
+      failures.extend(check(&path, &actual));
+    }
+  }
+  assert!(
+    failures.is_empty(),
+    "{}\n\nIf the change is intended, regenerate with {}=1 cargo test golden",
+    failures.join("\n\n"),
+    BLESS_ENV
+  );
+}
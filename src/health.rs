@@ -0,0 +1,179 @@
+use crate::checkpoint::write_atomic;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// 探测结果缓存的有效期
+pub const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// 首个与最后一个数据块间隔低于此值时，认为响应被代理整体缓冲后才发出
+const MIN_STREAM_SPREAD: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+  Ok,
+  /// 能连通，但流式响应被一次性返回（常见于企业代理缓冲）
+  StreamingBroken,
+  Unreachable,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusReport {
+  pub health: Health,
+  pub base_url: String,
+  pub latency_ms: Option<u64>,
+  pub chunks: usize,
+  pub detail: String,
+  pub checked_at: DateTime<Local>,
+}
+
+impl StatusReport {
+  pub fn is_degraded(&self) -> bool {
+    self.health != Health::Ok
+  }
+
+  pub fn render(&self) -> String {
+    let latency = self
+      .latency_ms
+      .map_or_else(|| "-".to_string(), |ms| format!("{}ms", ms));
+    let health = match self.health {
+      Health::Ok => "正常",
+      Health::StreamingBroken => "可连通，但流式输出异常",
+      Health::Unreachable => "不可用",
+    };
+    let mut text = format!(
+      "{}: {}（首包延迟 {}，收到 {} 个数据块）",
+      self.base_url, health, latency, self.chunks
+    );
+    if !self.detail.is_empty() {
+      text.push_str(&format!("\n{}", self.detail));
+    }
+    text
+  }
+}
+
+/// 根据探测请求各数据块的到达时间（相对请求开始）分类
+pub fn classify(base_url: &str, probe: Result<Vec<Duration>, String>) -> StatusReport {
+  let checked_at = Local::now();
+  let arrivals = match probe {
+    Ok(arrivals) => arrivals,
+    Err(e) => {
+      return StatusReport {
+        health: Health::Unreachable,
+        base_url: base_url.to_string(),
+        latency_ms: None,
+        chunks: 0,
+        detail: e,
+        checked_at,
+      };
+    }
+  };
+  let latency_ms = arrivals.first().map(|d| d.as_millis() as u64);
+  let spread = match (arrivals.first(), arrivals.last()) {
+    (Some(first), Some(last)) => *last - *first,
+    _ => Duration::ZERO,
+  };
+  let (health, detail) = if arrivals.len() >= 2 && spread >= MIN_STREAM_SPREAD {
+    (Health::Ok, String::new())
+  } else {
+    (
+      Health::StreamingBroken,
+      "响应被一次性返回，可能有代理缓冲了流式输出".to_string(),
+    )
+  };
+  StatusReport {
+    health,
+    base_url: base_url.to_string(),
+    latency_ms,
+    chunks: arrivals.len(),
+    detail,
+    checked_at,
+  }
+}
+
+pub fn write_cache(path: &Path, report: &StatusReport) -> Result<()> {
+  let json = serde_json::to_vec_pretty(report).context("Failed to serialize status")?;
+  write_atomic(path, &json)
+}
+
+/// 读取同一地址、仍在有效期内的缓存结果
+pub fn read_cache(
+  path: &Path,
+  base_url: &str,
+  now: DateTime<Local>,
+  ttl: Duration,
+) -> Option<StatusReport> {
+  let data = fs::read(path).ok()?;
+  let report: StatusReport = serde_json::from_slice(&data).ok()?;
+  let age = now.signed_duration_since(report.checked_at).to_std().ok()?;
+  (report.base_url == base_url && age <= ttl).then_some(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+
+  const URL: &str = "https://api.deepseek.com";
+
+  fn ms(values: &[u64]) -> Vec<Duration> {
+    values.iter().map(|v| Duration::from_millis(*v)).collect()
+  }
+
+  #[test]
+  fn test_classify() {
+    let report = classify(URL, Ok(ms(&[300, 350, 420, 500])));
+    assert_eq!(report.health, Health::Ok);
+    assert_eq!(report.latency_ms, Some(300));
+    assert_eq!(report.chunks, 4);
+
+    // 代理缓冲：只有一个数据块
+    let report = classify(URL, Ok(ms(&[2300])));
+    assert_eq!(report.health, Health::StreamingBroken);
+    assert_eq!(report.latency_ms, Some(2300));
+
+    // 多个数据块但几乎同时到达，同样视为缓冲
+    let report = classify(URL, Ok(ms(&[2300, 2301, 2301])));
+    assert_eq!(report.health, Health::StreamingBroken);
+
+    let report = classify(URL, Ok(vec![]));
+    assert_eq!(report.health, Health::StreamingBroken);
+
+    let report = classify(URL, Err("connection refused".to_string()));
+    assert_eq!(report.health, Health::Unreachable);
+    assert_eq!(report.detail, "connection refused");
+    assert!(report.is_degraded());
+  }
+
+  #[test]
+  fn test_cache_round_trip() {
+    let dir = temp_dir("health-cache");
+    let path = dir.join("status.json");
+    let report = classify(URL, Ok(ms(&[2300])));
+    write_cache(&path, &report).unwrap();
+
+    let now = report.checked_at + chrono::Duration::seconds(60);
+    assert_eq!(read_cache(&path, URL, now, CACHE_TTL), Some(report.clone()));
+
+    // 过期或地址不同都不使用
+    let later = report.checked_at + chrono::Duration::seconds(301);
+    assert_eq!(read_cache(&path, URL, later, CACHE_TTL), None);
+    assert_eq!(
+      read_cache(&path, "http://localhost:8000", now, CACHE_TTL),
+      None
+    );
+
+    fs::write(&path, "garbage").unwrap();
+    assert_eq!(read_cache(&path, URL, now, CACHE_TTL), None);
+    assert_eq!(
+      read_cache(&dir.join("missing.json"), URL, now, CACHE_TTL),
+      None
+    );
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
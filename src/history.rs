@@ -0,0 +1,649 @@
+use crate::api::{MAX_NAME_CHARS, Message, valid_name};
+use crate::turn::CONTINUE_PROMPT;
+use anyhow::{Context, Result, bail};
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  /// deepcli 会话文件（带 meta）
+  Session,
+  /// OpenAI 风格的消息数组，也是旧版会话文件的格式
+  OpenAi,
+  ShareGpt,
+  ChatMl,
+}
+
+impl std::fmt::Display for Format {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str(match self {
+      Format::Session => "deepcli session",
+      Format::OpenAi => "OpenAI messages",
+      Format::ShareGpt => "ShareGPT",
+      Format::ChatMl => "ChatML",
+    })
+  }
+}
+
+#[derive(Debug)]
+pub struct Imported {
+  pub format: Format,
+  pub messages: Vec<Message>,
+  /// 无法映射的角色（tool、function 等）被跳过的条数
+  pub skipped: usize,
+}
+
+/// 映射到 user/assistant/system，其他角色返回 None
+fn map_role(role: &str) -> Option<&'static str> {
+  match role.trim().to_ascii_lowercase().as_str() {
+    "user" | "human" => Some("user"),
+    "assistant" | "gpt" | "model" | "chatgpt" | "bot" => Some("assistant"),
+    "system" => Some("system"),
+    _ => None,
+  }
+}
+
+fn simple(role: &str, content: String) -> Message {
+  Message::Simple {
+    role: role.to_string(),
+    content,
+    name: None,
+  }
+}
+
+/// 读取并自动识别格式：deepcli 会话、OpenAI 消息数组、ShareGPT 或 ChatML 文本
+pub fn import(path: &Path) -> Result<Imported> {
+  let text = crate::sandbox::read_to_string(path).context(format!("Failed to read {:?}", path))?;
+  parse(&text).context(format!("Cannot import {:?}", path))
+}
+
+pub fn parse(text: &str) -> Result<Imported> {
+  let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+  let text = text.replace("\r\n", "\n");
+  if text.contains("<|im_start|>") {
+    return parse_chatml(&text);
+  }
+  let value: Value = serde_json::from_str(&text).context("Not JSON or ChatML")?;
+  if value.get("meta").is_some()
+    && let Some(messages) = value.get("messages")
+  {
+    let messages = serde_json::from_value(messages.clone()).context("Malformed session file")?;
+    return Ok(Imported {
+      format: Format::Session,
+      messages,
+      skipped: 0,
+    });
+  }
+  // ShareGPT 数据集通常是对话的数组，只导入第一段
+  let conversation = match &value {
+    Value::Array(items) => items.first().and_then(|c| c.get("conversations")),
+    other => other.get("conversations"),
+  };
+  if let Some(turns) = conversation {
+    return from_entries(Format::ShareGpt, turns, "from", "value");
+  }
+  if value.is_array() {
+    return from_entries(Format::OpenAi, &value, "role", "content");
+  }
+  bail!("Unrecognized conversation format")
+}
+
+fn from_entries(
+  format: Format,
+  entries: &Value,
+  role_key: &str,
+  content_key: &str,
+) -> Result<Imported> {
+  let Some(entries) = entries.as_array() else {
+    bail!("Expected an array of messages");
+  };
+  let mut messages = vec![];
+  let mut skipped = 0;
+  for entry in entries {
+    let role = entry
+      .get(role_key)
+      .and_then(Value::as_str)
+      .and_then(map_role);
+    let content = entry.get(content_key).and_then(content_text);
+    // 不符合 OpenAI 限制的说话人名字直接丢弃，否则请求会被拒绝
+    let name = entry
+      .get("name")
+      .and_then(Value::as_str)
+      .filter(|name| valid_name(name))
+      .map(str::to_string);
+    match (role, content) {
+      (Some(role), Some(content)) => messages.push(Message::Simple {
+        role: role.to_string(),
+        content,
+        name,
+      }),
+      _ => skipped += 1,
+    }
+  }
+  Ok(Imported {
+    format,
+    messages,
+    skipped,
+  })
+}
+
+/// content 可以是字符串，也可以是 OpenAI 的分段数组（只取文本段）
+fn content_text(value: &Value) -> Option<String> {
+  match value {
+    Value::String(s) => Some(s.clone()),
+    Value::Array(parts) => {
+      let texts: Vec<_> = parts
+        .iter()
+        .filter_map(|p| p.get("text").and_then(Value::as_str))
+        .collect();
+      (!texts.is_empty()).then(|| texts.join("\n"))
+    }
+    _ => None,
+  }
+}
+
+fn parse_chatml(text: &str) -> Result<Imported> {
+  let mut messages = vec![];
+  let mut skipped = 0;
+  for block in text.split("<|im_start|>").skip(1) {
+    let block = block.split("<|im_end|>").next().unwrap_or(block);
+    let (role, content) = block.split_once('\n').unwrap_or((block, ""));
+    match map_role(role) {
+      Some(role) => messages.push(simple(role, content.trim_end_matches('\n').to_string())),
+      None => skipped += 1,
+    }
+  }
+  Ok(Imported {
+    format: Format::ChatMl,
+    messages,
+    skipped,
+  })
+}
+
+/// 自动续写追加的用户消息，属于上一轮而不是新的一轮
+pub fn is_continuation(message: &Message) -> bool {
+  matches!(message, Message::Simple { role, content, .. } if role == "user" && content == CONTINUE_PROMPT)
+}
+
+/// 每一轮的起始下标：用户消息，跳过自动续写的“请继续”
+pub fn turn_starts(history: &[Message]) -> Vec<usize> {
+  history
+    .iter()
+    .enumerate()
+    .filter(|(i, m)| {
+      matches!(m, Message::Simple { role, .. } | Message::MultiModal { role, .. } if role == "user")
+        && !(*i > 0 && is_continuation(m))
+    })
+    .map(|(i, _)| i)
+    .collect()
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Rollback {
+  /// 被回退的那一轮原来的用户消息
+  pub original: String,
+  /// 除这一轮外一并丢弃的轮数
+  pub dropped: usize,
+}
+
+/// 不修改历史，计算回退到第 `turn` 轮（从 1 开始）之前的结果
+pub fn plan_rollback(history: &[Message], turn: usize) -> Option<Rollback> {
+  let starts = turn_starts(history);
+  let start = *starts.get(turn.checked_sub(1)?)?;
+  let original = match &history[start] {
+    Message::Simple { content, .. } | Message::Tool { content, .. } => content.clone(),
+    Message::MultiModal { content, .. } => content
+      .iter()
+      .filter_map(|c| match c {
+        crate::api::Content::Text(t) => Some(t.text.as_str()),
+        crate::api::Content::Image(_) => None,
+      })
+      .collect::<Vec<_>>()
+      .join("\n"),
+  };
+  Some(Rollback {
+    original,
+    dropped: starts.len() - turn,
+  })
+}
+
+/// 第 `turn` 轮（从 1 开始）的完整回答，自动续写的片段拼接在一起
+pub fn turn_reply(history: &[Message], turn: usize) -> Option<String> {
+  let starts = turn_starts(history);
+  let start = *starts.get(turn.checked_sub(1)?)?;
+  let end = starts.get(turn).copied().unwrap_or(history.len());
+  let reply: String = history[start..end]
+    .iter()
+    .filter_map(|m| match m {
+      Message::Simple { role, content, .. } if role == "assistant" => Some(content.as_str()),
+      _ => None,
+    })
+    .collect();
+  (!reply.is_empty()).then_some(reply)
+}
+
+/// 删除第 `turn` 轮及之后的所有消息（包括回答和续写片段）
+pub fn rollback(history: &mut Vec<Message>, turn: usize) -> Option<Rollback> {
+  let plan = plan_rollback(history, turn)?;
+  history.truncate(turn_starts(history)[turn - 1]);
+  Some(plan)
+}
+
+/// 第 `turn` 轮（从 1 开始）在历史中的下标范围，包括回答和续写片段
+pub fn turn_range(history: &[Message], turn: usize) -> Option<std::ops::Range<usize>> {
+  let starts = turn_starts(history);
+  let start = *starts.get(turn.checked_sub(1)?)?;
+  Some(start..starts.get(turn).copied().unwrap_or(history.len()))
+}
+
+/// 只删除第 `turn` 轮，之后的轮次依次前移
+pub fn drop_turn(history: &mut Vec<Message>, turn: usize) -> bool {
+  match turn_range(history, turn) {
+    Some(range) => {
+      history.drain(range);
+      true
+    }
+    None => false,
+  }
+}
+
+/// 解析 `\as <名字> <问题>`，返回说话人和余下的输入
+pub fn parse_as(arg: &str) -> Result<(&str, &str), String> {
+  let (name, rest) = arg
+    .trim()
+    .split_once(char::is_whitespace)
+    .unwrap_or((arg.trim(), ""));
+  if !valid_name(name) {
+    return Err(format!(
+      "说话人名字 '{}' 无效：只能包含字母、数字、_ 和 -，最多 {} 个字符",
+      name, MAX_NAME_CHARS
+    ));
+  }
+  match rest.trim() {
+    "" => Err("用法: \\as <名字> <问题>".to_string()),
+    rest => Ok((name, rest)),
+  }
+}
+
+/// `\c` 的清理范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearScope {
+  /// 只清空对话轮次，保留开头的 system 消息
+  Turns,
+  /// 清空全部历史，main 中还会取消持续附加的文件、删除变量
+  All,
+  /// 只丢弃最近一轮，同 `\undo`
+  Last,
+}
+
+/// 解析 `\c [all|last]`
+pub fn parse_clear(arg: &str) -> Result<ClearScope, String> {
+  match arg.trim() {
+    "" => Ok(ClearScope::Turns),
+    "all" => Ok(ClearScope::All),
+    "last" => Ok(ClearScope::Last),
+    _ => Err("用法: \\c [all|last]".to_string()),
+  }
+}
+
+/// 按范围清理历史，返回丢弃的轮数
+pub fn clear(history: &mut Vec<Message>, scope: ClearScope) -> usize {
+  let turns = turn_starts(history).len();
+  match scope {
+    ClearScope::Turns => {
+      // 导入的会话可能以 system 消息开头，它属于设定而不是对话
+      let keep = history
+        .iter()
+        .take_while(|m| matches!(m, Message::Simple { role, .. } if role == "system"))
+        .count();
+      history.truncate(keep);
+      turns
+    }
+    ClearScope::All => {
+      history.clear();
+      turns
+    }
+    ClearScope::Last => rollback(history, turns).map_or(0, |_| 1),
+  }
+}
+
+/// `\save`：历史原样写成 JSON 消息数组（与自动快照相同的格式）
+pub fn save(path: &Path, history: &[Message]) -> Result<()> {
+  let json = serde_json::to_vec_pretty(history).context("Failed to serialize history")?;
+  crate::checkpoint::write_atomic(path, &json)
+}
+
+/// `\load`：读取 `\save` 或自动快照写出的文件，多模态消息和工具调用原样恢复。
+/// 其他格式请用 `\import`
+pub fn load(path: &Path) -> Result<Vec<Message>> {
+  let text = crate::sandbox::read_to_string(path).context(format!("Failed to read {:?}", path))?;
+  serde_json::from_str(&text).context(format!(
+    "{:?} is not a saved history (a JSON array of messages)",
+    path
+  ))
+}
+
+/// `\save` 和 `\load` 的目标：会话名存入 sessions 目录，带路径分隔符或 `.json` 后缀的是文件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target<'a> {
+  Session(&'a str),
+  File(&'a Path),
+}
+
+pub fn target(arg: &str) -> Target<'_> {
+  let is_file = arg.contains(['/', '\\'])
+    || arg.ends_with(".json")
+    || crate::session::validate_name(arg).is_err();
+  match is_file {
+    true => Target::File(Path::new(arg)),
+    false => Target::Session(arg),
+  }
+}
+
+/// 解析 `\load` 的参数：`[--append] <路径>`，返回路径和是否追加
+pub fn parse_load(arg: &str) -> Result<(&str, bool), String> {
+  let arg = arg.trim();
+  let (path, append) = match arg.strip_prefix("--append") {
+    Some(rest) if rest.is_empty() || rest.starts_with(' ') => (rest.trim(), true),
+    _ => (arg, false),
+  };
+  if path.is_empty() {
+    return Err("用法: \\load [--append] <路径>".to_string());
+  }
+  Ok((path, append))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use crate::session::SessionStore;
+  use std::fs;
+
+  fn pairs(imported: &Imported) -> Vec<(&str, &str)> {
+    imported
+      .messages
+      .iter()
+      .map(|m| match m {
+        Message::Simple { role, content, .. } | Message::Tool { role, content, .. } => {
+          (role.as_str(), content.as_str())
+        }
+        Message::MultiModal { role, .. } => (role.as_str(), ""),
+      })
+      .collect()
+  }
+
+  const EXPECTED: [(&str, &str); 3] = [
+    ("system", "Be terse."),
+    ("user", "你好"),
+    ("assistant", "Hi!\nHow can I help?"),
+  ];
+
+  #[test]
+  fn test_sharegpt() {
+    let text = r#"[{"id":"1","conversations":[
+      {"from":"system","value":"Be terse."},
+      {"from":"human","value":"你好"},
+      {"from":"observation","value":"{}"},
+      {"from":"gpt","value":"Hi!\nHow can I help?"}]},
+      {"id":"2","conversations":[]}]"#;
+    let imported = parse(text).unwrap();
+    assert_eq!(imported.format, Format::ShareGpt);
+    assert_eq!(pairs(&imported), EXPECTED);
+    assert_eq!(imported.skipped, 1);
+
+    let single = parse(r#"{"conversations":[{"from":"human","value":"q"}]}"#).unwrap();
+    assert_eq!(pairs(&single), [("user", "q")]);
+  }
+
+  #[test]
+  fn test_openai_array_with_parts_and_tools() {
+    let text = r#"[
+      {"role":"system","content":"Be terse."},
+      {"role":"user","content":[{"type":"text","text":"你好"},{"type":"image_url","image_url":{"url":"x"}}]},
+      {"role":"tool","content":"42","tool_call_id":"a"},
+      {"role":"assistant","content":"Hi!\nHow can I help?"}]"#;
+    let imported = parse(text).unwrap();
+    assert_eq!(imported.format, Format::OpenAi);
+    assert_eq!(pairs(&imported), EXPECTED);
+    assert_eq!(imported.skipped, 1);
+  }
+
+  #[test]
+  fn test_chatml_with_bom_and_crlf() {
+    let text = "\u{feff}<|im_start|>system\r\nBe terse.<|im_end|>\r\n\
+      <|im_start|>user\r\n你好<|im_end|>\r\n\
+      <|im_start|>tool\r\n{}<|im_end|>\r\n\
+      <|im_start|>assistant\r\nHi!\r\nHow can I help?<|im_end|>\r\n";
+    let imported = parse(text).unwrap();
+    assert_eq!(imported.format, Format::ChatMl);
+    assert_eq!(pairs(&imported), EXPECTED);
+    assert_eq!(imported.skipped, 1);
+  }
+
+  #[test]
+  fn test_native_session_round_trip() {
+    let dir = temp_dir("history-import");
+    let store = SessionStore::new(dir.clone());
+    let messages: Vec<_> = EXPECTED
+      .iter()
+      .map(|(r, c)| simple(r, c.to_string()))
+      .collect();
+    let path = store
+      .save("work", &messages, "deepseek-chat", None)
+      .unwrap();
+    // Windows 换行与 BOM 不影响识别
+    let raw = fs::read_to_string(&path).unwrap().replace('\n', "\r\n");
+    fs::write(&path, format!("\u{feff}{}", raw)).unwrap();
+    let imported = import(&path).unwrap();
+    assert_eq!(imported.format, Format::Session);
+    assert_eq!(pairs(&imported), EXPECTED);
+
+    // 导出为 OpenAI 数组后再导入结果相同
+    let array = dir.join("array.json");
+    fs::write(&array, serde_json::to_string(&messages).unwrap()).unwrap();
+    let imported = import(&array).unwrap();
+    assert_eq!(imported.format, Format::OpenAi);
+    assert_eq!(pairs(&imported), EXPECTED);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_speaker_names_survive_import_and_session_save() {
+    let text = r#"[
+      {"role":"user","content":"周会开始","name":"alice"},
+      {"role":"user","content":"我来汇报","name":"bob smith"},
+      {"role":"assistant","content":"好的"}]"#;
+    let imported = parse(text).unwrap();
+    let names: Vec<_> = imported
+      .messages
+      .iter()
+      .map(|m| match m {
+        Message::Simple { name, .. } => name.as_deref(),
+        _ => unreachable!(),
+      })
+      .collect();
+    // 含空格的名字不符合限制，被丢弃
+    assert_eq!(names, [Some("alice"), None, None]);
+    assert_eq!(imported.messages[0].speaker(), "user(alice)");
+
+    let dir = temp_dir("history-names");
+    let store = SessionStore::new(dir.clone());
+    let path = store
+      .save("meeting", &imported.messages, "deepseek-chat", None)
+      .unwrap();
+    let saved = fs::read_to_string(&path).unwrap();
+    assert_eq!(saved.matches("\"name\"").count(), 1);
+    let restored = import(&path).unwrap();
+    assert_eq!(restored.messages[0].speaker(), "user(alice)");
+    assert_eq!(restored.messages[2].speaker(), "assistant");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_parse_as() {
+    assert_eq!(parse_as("alice 这周进展？"), Ok(("alice", "这周进展？")));
+    assert_eq!(
+      parse_as(" bob_2  \\file a.rs"),
+      Ok(("bob_2", "\\file a.rs"))
+    );
+    assert!(parse_as("alice").is_err());
+    assert!(parse_as("张三 你好").unwrap_err().contains("无效"));
+    assert!(parse_as(&format!("{} hi", "a".repeat(65))).is_err());
+  }
+
+  #[test]
+  fn test_rollback_keeps_continuations_in_their_turn() {
+    let mut history: Vec<_> = [
+      ("user", "q1"),
+      ("assistant", "a1 part"),
+      ("user", CONTINUE_PROMPT),
+      ("assistant", "a1 rest"),
+      ("user", "q2"),
+      ("assistant", "a2"),
+      ("user", "q3"),
+      ("assistant", "a3"),
+    ]
+    .iter()
+    .map(|(r, c)| simple(r, c.to_string()))
+    .collect();
+    assert_eq!(turn_starts(&history), [0, 4, 6]);
+    assert_eq!(plan_rollback(&history, 0), None);
+    assert_eq!(plan_rollback(&history, 4), None);
+    assert_eq!(
+      plan_rollback(&history, 3),
+      Some(Rollback {
+        original: "q3".to_string(),
+        dropped: 0
+      })
+    );
+
+    assert_eq!(turn_reply(&history, 1).as_deref(), Some("a1 parta1 rest"));
+    assert_eq!(turn_reply(&history, 3).as_deref(), Some("a3"));
+    assert_eq!(turn_reply(&history, 4), None);
+
+    let mut last = history.clone();
+    rollback(&mut last, 3).unwrap();
+    assert_eq!(last.len(), 6);
+
+    // 只删除中间一轮，续写片段随第 1 轮保留
+    let mut middle = history.clone();
+    assert!(drop_turn(&mut middle, 2));
+    assert!(!drop_turn(&mut middle, 3));
+    assert_eq!(turn_starts(&middle), [0, 4]);
+    assert_eq!(turn_reply(&middle, 2).as_deref(), Some("a3"));
+
+    let rolled = rollback(&mut history, 1).unwrap();
+    assert_eq!(rolled.original, "q1");
+    assert_eq!(rolled.dropped, 2);
+    assert!(history.is_empty());
+  }
+
+  #[test]
+  fn test_unrecognized() {
+    assert!(parse("{\"foo\":1}").is_err());
+    assert!(parse("plain text").is_err());
+  }
+
+  #[test]
+  fn test_clear_scopes() {
+    let history = vec![
+      simple("system", "Be terse.".to_string()),
+      simple("user", "第一个问题".to_string()),
+      simple("assistant", "回答一".to_string()),
+      simple("user", "第二个问题".to_string()),
+      simple("assistant", "回答二的前半".to_string()),
+      simple("user", CONTINUE_PROMPT.to_string()),
+      simple("assistant", "后半".to_string()),
+    ];
+    let roles = |h: &[Message]| {
+      h.iter()
+        .map(|m| match m {
+          Message::Simple { role, .. }
+          | Message::MultiModal { role, .. }
+          | Message::Tool { role, .. } => role.clone(),
+        })
+        .collect::<Vec<_>>()
+    };
+
+    let mut h = history.clone();
+    assert_eq!(clear(&mut h, ClearScope::Turns), 2);
+    assert_eq!(roles(&h), ["system"]);
+
+    let mut h = history.clone();
+    assert_eq!(clear(&mut h, ClearScope::All), 2);
+    assert!(h.is_empty());
+
+    // 续写片段属于最后一轮，一并丢弃
+    let mut h = history.clone();
+    assert_eq!(clear(&mut h, ClearScope::Last), 1);
+    assert_eq!(roles(&h), ["system", "user", "assistant"]);
+    assert_eq!(clear(&mut h, ClearScope::Last), 1);
+    assert_eq!(roles(&h), ["system"]);
+    assert_eq!(clear(&mut h, ClearScope::Last), 0);
+    assert_eq!(clear(&mut h, ClearScope::Turns), 0);
+    assert_eq!(h.len(), 1);
+
+    assert_eq!(parse_clear(""), Ok(ClearScope::Turns));
+    assert_eq!(parse_clear(" all"), Ok(ClearScope::All));
+    assert_eq!(parse_clear("last"), Ok(ClearScope::Last));
+    assert!(parse_clear("everything").is_err());
+  }
+
+  #[test]
+  fn test_save_and_load_round_trip_multimodal() {
+    let dir = temp_dir("history-save");
+    let path = dir.join("chat.json");
+    let history = vec![
+      simple("user", "第一个问题".to_string()),
+      Message::MultiModal {
+        role: "user".to_string(),
+        content: vec![
+          crate::api::Content::Text(crate::api::TextContent {
+            content_type: "text".to_string(),
+            text: "图里是什么？".to_string(),
+          }),
+          crate::api::Content::Image(crate::api::ImageContent {
+            content_type: "image_url".to_string(),
+            image_url: crate::api::ImageUrl {
+              url: "data:image/png;base64,iVBORw0K".to_string(),
+            },
+          }),
+        ],
+        name: Some("alice".to_string()),
+      },
+      simple("assistant", "一只猫。".to_string()),
+    ];
+    save(&path, &history).unwrap();
+    let loaded = load(&path).unwrap();
+    assert_eq!(
+      serde_json::to_value(&loaded).unwrap(),
+      serde_json::to_value(&history).unwrap()
+    );
+    assert!(matches!(&loaded[1], Message::MultiModal { content, .. } if content.len() == 2));
+
+    fs::write(&path, "[{\"role\": \"user\", \"content\": ").unwrap();
+    let err = format!("{:#}", load(&path).unwrap_err());
+    assert!(err.contains("not a saved history"), "{}", err);
+    assert!(err.contains("line 1"), "{}", err);
+    assert!(load(&dir.join("missing.json")).is_err());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_parse_load() {
+    assert_eq!(parse_load(" chat.json"), Ok(("chat.json", false)));
+    assert_eq!(parse_load("--append chat.json"), Ok(("chat.json", true)));
+    assert_eq!(
+      parse_load("--appendix.json"),
+      Ok(("--appendix.json", false))
+    );
+    assert!(parse_load("--append").is_err());
+    assert!(parse_load("").is_err());
+    // 会话名进入会话目录，看起来像路径的按文件处理
+    assert_eq!(target("work"), Target::Session("work"));
+    assert_eq!(target("chat.json"), Target::File(Path::new("chat.json")));
+    assert_eq!(target("out/chat"), Target::File(Path::new("out/chat")));
+    assert_eq!(target("with space"), Target::File(Path::new("with space")));
+  }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+/// 除配置的 API 地址以外会访问网络或本机环境的功能，--isolated 时全部禁用。
+/// 每一项在调用处检查，见 Isolation::check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+  Clipboard,
+  ShellTools,
+  Telemetry,
+  WarmStart,
+}
+
+impl Feature {
+  pub const ALL: [Feature; 4] = [
+    Feature::Clipboard,
+    Feature::ShellTools,
+    Feature::Telemetry,
+    Feature::WarmStart,
+  ];
+}
+
+impl fmt::Display for Feature {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Feature::Clipboard => "clipboard access (\\copy)",
+      Feature::ShellTools => "plugins",
+      Feature::Telemetry => "OTLP telemetry (otlp_endpoint)",
+      Feature::WarmStart => "warm-up requests (warm_start)",
+    };
+    f.write_str(name)
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Isolation {
+  enabled: bool,
+}
+
+impl Isolation {
+  pub fn new(enabled: bool) -> Self {
+    Self { enabled }
+  }
+
+  /// 每个受限功能在执行前调用，隔离模式下返回指明 --isolated 的错误
+  pub fn check(&self, feature: Feature) -> anyhow::Result<()> {
+    if self.enabled {
+      anyhow::bail!("{} is disabled by --isolated", feature);
+    }
+    Ok(())
+  }
+
+  pub fn summary(&self) -> Option<String> {
+    if !self.enabled {
+      return None;
+    }
+    let names: Vec<String> = Feature::ALL.iter().map(|f| f.to_string()).collect();
+    Some(format!(
+      "[隔离模式] 仅访问配置的 API 地址，已禁用: {}",
+      names.join(", ")
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_every_feature_is_gated() {
+    let isolated = Isolation::new(true);
+    for feature in Feature::ALL {
+      let err = isolated.check(feature).unwrap_err().to_string();
+      assert!(err.contains("--isolated"), "{}", err);
+      assert!(err.contains(&feature.to_string()), "{}", err);
+    }
+    let open = Isolation::new(false);
+    for feature in Feature::ALL {
+      assert!(open.check(feature).is_ok());
+    }
+  }
+
+  #[test]
+  fn test_summary() {
+    assert!(Isolation::new(false).summary().is_none());
+    let summary = Isolation::new(true).summary().unwrap();
+    for feature in Feature::ALL {
+      assert!(summary.contains(&feature.to_string()));
+    }
+  }
+}
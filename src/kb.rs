@@ -0,0 +1,426 @@
+use crate::api::{ChatBackend, Message};
+use crate::stats::SessionStats;
+use crate::turn::{TurnEnd, TurnSettings, run_turn};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// DashScope 兼容模式的文本向量模型
+pub const EMBEDDING_MODEL: &str = "text-embedding-v3";
+/// 每个分块的最大字符数
+pub const CHUNK_CHARS: usize = 1200;
+/// 每次向量请求最多的文本条数（DashScope 的上限是 10）
+const EMBED_BATCH: usize = 10;
+const EXTENSIONS: &[&str] = &["md", "markdown", "txt"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Chunk {
+  pub text: String,
+  pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileEntry {
+  /// 建索引时文件的修改时间（毫秒）
+  pub mtime: u64,
+  pub chunks: Vec<Chunk>,
+}
+
+/// 笔记目录的本地索引，键为相对路径
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+  pub model: String,
+  pub files: BTreeMap<String, FileEntry>,
+}
+
+/// 索引文件放在缓存目录下，按笔记目录的绝对路径区分
+pub fn index_path(cache_dir: &Path, kb_dir: &Path) -> PathBuf {
+  let hash = format!("{:x}", Sha256::digest(kb_dir.to_string_lossy().as_bytes()));
+  cache_dir.join("kb").join(format!("{}.json", &hash[..16]))
+}
+
+impl Index {
+  /// 读取已有索引；不存在、损坏或向量模型不同时返回空索引
+  pub fn load(path: &Path, model: &str) -> Index {
+    fs::read_to_string(path)
+      .ok()
+      .and_then(|text| serde_json::from_str::<Index>(&text).ok())
+      .filter(|index| index.model == model)
+      .unwrap_or_else(|| Index {
+        model: model.to_string(),
+        files: BTreeMap::new(),
+      })
+  }
+
+  pub fn save(&self, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+      crate::paths::ensure(parent)?;
+    }
+    fs::write(path, serde_json::to_string(self)?).context(format!("Failed to write {:?}", path))
+  }
+}
+
+/// 按段落分块：标题开始新的块，段落合并到不超过 `max_chars`，超长的段落按字符切开
+pub fn chunk(text: &str, max_chars: usize) -> Vec<String> {
+  let text = text.replace("\r\n", "\n");
+  let mut chunks = vec![];
+  let mut current = String::new();
+  for para in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+    let len = para.chars().count();
+    if !current.is_empty()
+      && (para.starts_with('#') || current.chars().count() + 2 + len > max_chars)
+    {
+      chunks.push(std::mem::take(&mut current));
+    }
+    if len > max_chars {
+      let chars: Vec<char> = para.chars().collect();
+      chunks.extend(chars.chunks(max_chars).map(|piece| piece.iter().collect()));
+      continue;
+    }
+    if !current.is_empty() {
+      current.push_str("\n\n");
+    }
+    current.push_str(para);
+  }
+  if !current.is_empty() {
+    chunks.push(current);
+  }
+  chunks
+}
+
+pub fn cosine(a: &[f32], b: &[f32]) -> f32 {
+  let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+  let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+  let denominator = norm(a) * norm(b);
+  if denominator == 0.0 {
+    0.0
+  } else {
+    dot / denominator
+  }
+}
+
+/// 递归列出笔记文件及其修改时间，跳过隐藏文件和目录
+pub fn scan(dir: &Path) -> Result<BTreeMap<String, u64>> {
+  let mut files = BTreeMap::new();
+  let mut pending = vec![dir.to_path_buf()];
+  while let Some(current) = pending.pop() {
+    let entries = fs::read_dir(&current).context(format!("Failed to read {:?}", current))?;
+    for entry in entries {
+      let entry = entry?;
+      let path = entry.path();
+      if entry.file_name().to_string_lossy().starts_with('.') {
+        continue;
+      }
+      if path.is_dir() {
+        pending.push(path);
+        continue;
+      }
+      let is_note = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+      if !is_note {
+        continue;
+      }
+      let mtime = entry
+        .metadata()?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+      let relative = path.strip_prefix(dir).unwrap_or(&path);
+      let name = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+      files.insert(name, mtime);
+    }
+  }
+  Ok(files)
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Refresh {
+  /// 新增或修改过、需要重新计算向量的文件
+  pub changed: Vec<String>,
+  pub removed: Vec<String>,
+}
+
+pub fn plan_refresh(index: &Index, scanned: &BTreeMap<String, u64>) -> Refresh {
+  Refresh {
+    changed: scanned
+      .iter()
+      .filter(|(name, mtime)| index.files.get(*name).is_none_or(|f| f.mtime != **mtime))
+      .map(|(name, _)| name.clone())
+      .collect(),
+    removed: index
+      .files
+      .keys()
+      .filter(|name| !scanned.contains_key(*name))
+      .cloned()
+      .collect(),
+  }
+}
+
+/// 增量更新索引：只为新增或修改过的文件重新分块并计算向量
+pub async fn refresh<F, Fut>(index: &mut Index, dir: &Path, embed: F) -> Result<Refresh>
+where
+  F: Fn(Vec<String>) -> Fut,
+  Fut: std::future::Future<Output = Result<Vec<Vec<f32>>>>,
+{
+  let scanned = scan(dir)?;
+  let plan = plan_refresh(index, &scanned);
+  for name in &plan.removed {
+    index.files.remove(name);
+  }
+  for name in &plan.changed {
+    let bytes =
+      crate::sandbox::read(&dir.join(name)).context(format!("Failed to read {}", name))?;
+    let texts = chunk(&String::from_utf8_lossy(&bytes), CHUNK_CHARS);
+    let mut chunks = vec![];
+    for batch in texts.chunks(EMBED_BATCH) {
+      let vectors = embed(batch.to_vec()).await?;
+      chunks.extend(
+        batch
+          .iter()
+          .cloned()
+          .zip(vectors)
+          .map(|(text, embedding)| Chunk { text, embedding }),
+      );
+    }
+    index.files.insert(
+      name.clone(),
+      FileEntry {
+        mtime: scanned[name],
+        chunks,
+      },
+    );
+  }
+  Ok(plan)
+}
+
+#[derive(Debug)]
+pub struct Hit<'a> {
+  pub file: &'a str,
+  pub text: &'a str,
+  pub score: f32,
+}
+
+/// 按余弦相似度取最相关的 `k` 个分块
+pub fn search<'a>(index: &'a Index, query: &[f32], k: usize) -> Vec<Hit<'a>> {
+  let mut hits: Vec<_> = index
+    .files
+    .iter()
+    .flat_map(|(file, entry)| {
+      entry.chunks.iter().map(move |c| Hit {
+        file,
+        text: &c.text,
+        score: cosine(query, &c.embedding),
+      })
+    })
+    .collect();
+  hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+  hits.truncate(k);
+  hits
+}
+
+/// 只把检索到的片段作为上下文发送
+pub fn grounded_question(hits: &[Hit], question: &str) -> String {
+  let context = hits
+    .iter()
+    .map(|h| format!("[{}]\n{}", h.file, h.text))
+    .collect::<Vec<_>>()
+    .join("\n\n");
+  format!(
+    "以下是从本地笔记中检索到的片段，请基于这些内容回答；笔记中没有相关信息时请直接说明。\n\n{}\n\n问题: {}",
+    context, question
+  )
+}
+
+/// 回答末尾列出的来源文件，按相关度排序并去重
+pub fn sources<'a>(hits: &[Hit<'a>]) -> Vec<&'a str> {
+  let mut files = vec![];
+  for hit in hits {
+    if !files.contains(&hit.file) {
+      files.push(hit.file);
+    }
+  }
+  files
+}
+
+/// 基于检索到的片段回答一轮，成功时在回答后列出来源。
+/// 请求失败或被内容审核拦截时不列来源，返回 false，调用方以非零状态退出
+pub async fn answer<B: ChatBackend>(
+  backend: &B,
+  settings: &TurnSettings<'_>,
+  hits: &[Hit<'_>],
+  question: &str,
+  out: &mut impl Write,
+) -> Result<bool> {
+  let mut history = vec![Message::Simple {
+    role: "user".to_string(),
+    content: grounded_question(hits, question),
+    name: None,
+  }];
+  let mut stats = SessionStats::default();
+  let end = run_turn(backend, settings, &mut history, &mut stats, out).await?;
+  if matches!(end, TurnEnd::RolledBack | TurnEnd::Filtered) {
+    return Ok(false);
+  }
+  writeln!(out, "来源: {}", sources(hits).join(", "))?;
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  #[test]
+  fn test_chunk_by_sections_and_size() {
+    let text = "# 缓存\r\n\r\nTTL 定为 5 分钟。\r\n\r\n理由见下。\n\n# 部署\n\n蓝绿发布。";
+    assert_eq!(
+      chunk(text, 100),
+      [
+        "# 缓存\n\nTTL 定为 5 分钟。\n\n理由见下。",
+        "# 部署\n\n蓝绿发布。"
+      ]
+    );
+    let long = "字".repeat(25);
+    let pieces = chunk(&format!("短段落\n\n{}", long), 10);
+    assert_eq!(pieces[0], "短段落");
+    assert_eq!(pieces.len(), 4);
+    assert!(pieces.iter().all(|p| p.chars().count() <= 10));
+    assert!(chunk("\n\n  \n", 10).is_empty());
+  }
+
+  #[test]
+  fn test_cosine_ranking() {
+    assert!((cosine(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+    assert_eq!(cosine(&[1.0, 0.0], &[0.0, 1.0]), 0.0);
+    assert_eq!(cosine(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+
+    let entry = |vectors: &[[f32; 2]]| FileEntry {
+      mtime: 0,
+      chunks: vectors
+        .iter()
+        .enumerate()
+        .map(|(i, v)| Chunk {
+          text: format!("chunk {}", i),
+          embedding: v.to_vec(),
+        })
+        .collect(),
+    };
+    let index = Index {
+      model: EMBEDDING_MODEL.to_string(),
+      files: [
+        ("a.md".to_string(), entry(&[[1.0, 0.0], [0.6, 0.8]])),
+        ("b.md".to_string(), entry(&[[0.9, 0.1]])),
+      ]
+      .into_iter()
+      .collect(),
+    };
+    let hits = search(&index, &[1.0, 0.0], 2);
+    assert_eq!(
+      hits.iter().map(|h| (h.file, h.text)).collect::<Vec<_>>(),
+      [("a.md", "chunk 0"), ("b.md", "chunk 0")]
+    );
+    assert_eq!(sources(&search(&index, &[1.0, 0.0], 3)), ["a.md", "b.md"]);
+    assert!(grounded_question(&hits, "TTL?").contains("[b.md]\nchunk 0"));
+  }
+
+  #[tokio::test]
+  async fn test_refresh_is_incremental() {
+    let dir = temp_dir("kb-refresh");
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    fs::create_dir_all(dir.join(".git")).unwrap();
+    fs::write(dir.join("a.md"), "alpha").unwrap();
+    fs::write(dir.join("sub").join("b.txt"), "beta\n\ngamma").unwrap();
+    fs::write(dir.join("image.png"), "not a note").unwrap();
+    fs::write(dir.join(".git").join("c.md"), "hidden").unwrap();
+
+    let calls = AtomicUsize::new(0);
+    let embed = |texts: Vec<String>| {
+      calls.fetch_add(texts.len(), Ordering::SeqCst);
+      async move { Ok(texts.iter().map(|t| vec![t.len() as f32]).collect()) }
+    };
+    let mut index = Index::load(&dir.join("missing.json"), EMBEDDING_MODEL);
+    let first = refresh(&mut index, &dir, embed).await.unwrap();
+    assert_eq!(first.changed, ["a.md", "sub/b.txt"]);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // 没有变化时不再计算向量
+    let second = refresh(&mut index, &dir, embed).await.unwrap();
+    assert_eq!(second, Refresh::default());
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+    // 修改时间变化的文件重新计算，删除的文件移出索引
+    index.files.get_mut("a.md").unwrap().mtime = 1;
+    fs::remove_file(dir.join("sub").join("b.txt")).unwrap();
+    let third = refresh(&mut index, &dir, embed).await.unwrap();
+    assert_eq!(third.changed, ["a.md"]);
+    assert_eq!(third.removed, ["sub/b.txt"]);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+    // 保存后按同一模型读回，换模型则从头建立
+    let path = index_path(&dir, Path::new("/notes"));
+    index.save(&path).unwrap();
+    assert_eq!(Index::load(&path, EMBEDDING_MODEL).files, index.files);
+    assert!(Index::load(&path, "other-model").files.is_empty());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_sources_follow_only_a_successful_answer() {
+    use crate::api::StreamChunk;
+    use crate::mock::ScriptedBackend;
+    let hits = [Hit {
+      file: "cache.md",
+      text: "TTL 定为 5 分钟",
+      score: 1.0,
+    }];
+    let settings = crate::turn::tests::settings();
+    let chunk = |content: &str, finish: &str| StreamChunk {
+      content: content.to_string(),
+      finish_reason: Some(finish.to_string()),
+      ..Default::default()
+    };
+
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![chunk("5 分钟。", "stop")]);
+    let mut out = Vec::new();
+    assert!(
+      answer(&backend, &settings, &hits, "TTL?", &mut out)
+        .await
+        .unwrap()
+    );
+    let printed = String::from_utf8(out).unwrap();
+    assert!(printed.ends_with("来源: cache.md\n"), "{}", printed);
+
+    // 请求失败和内容审核拦截都不列来源
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![chunk("", crate::api::CONTENT_FILTER)]);
+    let mut out = Vec::new();
+    assert!(
+      !answer(&backend, &settings, &hits, "TTL?", &mut out)
+        .await
+        .unwrap()
+    );
+    let mut failed = Vec::new();
+    let backend = ScriptedBackend::default();
+    assert!(
+      !answer(&backend, &settings, &hits, "TTL?", &mut failed)
+        .await
+        .unwrap()
+    );
+    for out in [out, failed] {
+      assert!(!String::from_utf8(out).unwrap().contains("来源"));
+    }
+  }
+}
@@ -0,0 +1,190 @@
+//! 调用真实接口的冒烟测试，用来发现 mock 发现不了的接口格式变化。
+//!
+//! 默认跳过。运行方式：`DEEPCLI_LIVE_TESTS=1 cargo test -- --ignored live`，
+//! 密钥与 --profile 的自动选择规则相同（DASHSCOPE_API_KEY 或 DEEPSEEK_API_KEY）。
+//! 每个请求的输出上限都很小，整组测试花费不到一分钱。
+
+use crate::api::{ApiClient, ApiError, ChatBackend, Message};
+use crate::profile::{self, Profile};
+use futures_util::StreamExt;
+use std::future::Future;
+use std::time::Instant;
+
+/// 打开冒烟测试的环境变量
+const ENABLE_ENV: &str = "DEEPCLI_LIVE_TESTS";
+
+/// 未打开或没有密钥时返回 None，测试直接通过
+fn client() -> Option<(ApiClient, Profile)> {
+  if std::env::var(ENABLE_ENV).as_deref() != Ok("1") {
+    eprintln!("[live] 跳过：未设置 {}=1", ENABLE_ENV);
+    return None;
+  }
+  let resolved = match profile::resolve_profile(None, |key| std::env::var(key).ok()) {
+    Ok(resolved) => resolved,
+    Err(e) => {
+      eprintln!("[live] 跳过：{}", e);
+      return None;
+    }
+  };
+  let client = ApiClient::new(resolved.api_key).with_base_url(resolved.profile.base_url);
+  Some((client, resolved.profile))
+}
+
+/// 别名在当前提供方对应的模型名
+fn model(profile: &Profile, alias: &str) -> String {
+  crate::cli::map_model(alias, profile).unwrap()
+}
+
+/// 运行一个用例并打印耗时
+async fn timed<T>(case: &str, fut: impl Future<Output = T>) -> T {
+  let started = Instant::now();
+  let result = fut.await;
+  eprintln!("[live] {}: {} ms", case, started.elapsed().as_millis());
+  result
+}
+
+fn user(content: &str) -> Vec<Message> {
+  vec![Message::Simple {
+    role: "user".to_string(),
+    content: content.to_string(),
+    name: None,
+  }]
+}
+
+#[tokio::test]
+#[ignore = "calls the real API; set DEEPCLI_LIVE_TESTS=1"]
+async fn live_completion() {
+  let Some((client, profile)) = client() else {
+    return;
+  };
+  let model = model(&profile, "chat");
+  let reply = timed(
+    "completion",
+    client.complete(
+      &model,
+      user("Reply with the single word: ok"),
+      Some(0.0),
+      Some(8),
+    ),
+  )
+  .await
+  .unwrap();
+  assert!(!reply.trim().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "calls the real API; set DEEPCLI_LIVE_TESTS=1"]
+async fn live_streaming() {
+  let Some((client, profile)) = client() else {
+    return;
+  };
+  let model = model(&profile, "chat");
+  let chunks = timed("streaming", async {
+    let mut stream = client
+      .stream(&model, user("Count from 1 to 10."), Some(0.0), Some(32))
+      .await
+      .unwrap();
+    let mut chunks = vec![];
+    while let Some(chunk) = stream.next().await {
+      chunks.push(chunk.unwrap());
+    }
+    chunks
+  })
+  .await;
+  let content = chunks.iter().filter(|c| !c.content.is_empty()).count();
+  assert!(content >= 2, "expected at least two chunks: {:?}", chunks);
+  assert!(
+    chunks.iter().any(|c| c.finish_reason.is_some()),
+    "no finish_reason: {:?}",
+    chunks
+  );
+}
+
+#[tokio::test]
+#[ignore = "calls the real API; set DEEPCLI_LIVE_TESTS=1"]
+async fn live_json_mode() {
+  let Some((client, profile)) = client() else {
+    return;
+  };
+  let model = model(&profile, "chat");
+  let response = timed(
+    "json mode",
+    client.call_api(
+      &model,
+      r#"Return the JSON object {"ok": true} and nothing else."#,
+      Some(0.0),
+      Some(32),
+      true,
+    ),
+  )
+  .await
+  .unwrap();
+  let text = response.text();
+  let value: serde_json::Value = serde_json::from_str(&text).unwrap_or_else(|e| {
+    panic!("not JSON ({}): {}", e, text);
+  });
+  assert!(value.is_object());
+}
+
+#[tokio::test]
+#[ignore = "calls the real API; set DEEPCLI_LIVE_TESTS=1"]
+async fn live_bad_request_is_classified() {
+  let Some((client, _)) = client() else {
+    return;
+  };
+  let err = timed(
+    "bad request",
+    client.complete("deepcli-no-such-model", user("hi"), None, Some(1)),
+  )
+  .await
+  .unwrap_err();
+  let api_error = err
+    .downcast_ref::<ApiError>()
+    .unwrap_or_else(|| panic!("not an API error: {:#}", err));
+  assert!(
+    matches!(api_error, ApiError::InvalidRequest { .. }),
+    "{}",
+    api_error
+  );
+}
+
+#[tokio::test]
+#[ignore = "calls the real API; set DEEPCLI_LIVE_TESTS=1"]
+async fn live_history_after_reasoning_is_accepted() {
+  let Some((client, profile)) = client() else {
+    return;
+  };
+  let model = model(&profile, "r1");
+  let mut history = user("What is 2 + 3? Answer with the number only.");
+  let (reply, reasoning) = timed("reasoning turn", async {
+    let mut stream = client
+      .stream(&model, history.clone(), None, Some(256))
+      .await
+      .unwrap();
+    let (mut reply, mut reasoning) = (String::new(), String::new());
+    while let Some(chunk) = stream.next().await {
+      let chunk = chunk.unwrap();
+      reply.push_str(&chunk.content);
+      reasoning.push_str(&chunk.reasoning);
+    }
+    (reply, reasoning)
+  })
+  .await;
+  assert!(!reasoning.is_empty(), "{} returned no reasoning", model);
+  // 与 run_turn 相同：推理过程不进入历史，没有回答时用占位回答
+  history.push(Message::Simple {
+    role: "assistant".to_string(),
+    content: match reply.trim().is_empty() {
+      true => crate::turn::REASONING_ONLY_MARKER.to_string(),
+      false => reply,
+    },
+    name: None,
+  });
+  history.extend(user("And 3 + 4?"));
+  timed(
+    "follow-up after reasoning",
+    client.complete(&model, history, None, Some(256)),
+  )
+  .await
+  .unwrap();
+}
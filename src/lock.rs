@@ -0,0 +1,222 @@
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// 基于操作系统咨询锁的文件锁。进程崩溃时锁由系统释放，不会留下陈旧的锁；
+/// 锁文件里记录持有者的 PID，只用于提示是谁占用
+#[derive(Debug)]
+pub struct FileLock {
+  file: File,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LockError {
+  /// 另一个进程持有锁；PID 未知时为 None
+  Held {
+    path: PathBuf,
+    pid: Option<u32>,
+  },
+  Io {
+    path: PathBuf,
+    message: String,
+  },
+}
+
+impl std::fmt::Display for LockError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      LockError::Held {
+        path,
+        pid: Some(pid),
+      } => {
+        write!(
+          f,
+          "{} is in use by another deepcli (pid {})",
+          path.display(),
+          pid
+        )
+      }
+      LockError::Held { path, pid: None } => {
+        write!(f, "{} is in use by another deepcli", path.display())
+      }
+      LockError::Io { path, message } => write!(f, "{}: {}", path.display(), message),
+    }
+  }
+}
+
+impl std::error::Error for LockError {}
+
+/// `target` 对应的锁文件路径
+pub fn lock_path(target: &Path) -> PathBuf {
+  let mut name = target.file_name().unwrap_or_default().to_os_string();
+  name.push(".lock");
+  target.with_file_name(name)
+}
+
+impl FileLock {
+  /// 尝试为 `target` 加锁，不等待
+  pub fn try_acquire(target: &Path) -> Result<FileLock, LockError> {
+    let path = lock_path(target);
+    let io_error = |e: std::io::Error| LockError::Io {
+      path: path.clone(),
+      message: e.to_string(),
+    };
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).map_err(io_error)?;
+    }
+    let mut file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .open(&path)
+      .map_err(io_error)?;
+    if file.try_lock_exclusive().is_err() {
+      let mut contents = String::new();
+      let _ = file.read_to_string(&mut contents);
+      return Err(LockError::Held {
+        path: target.to_path_buf(),
+        pid: contents.trim().parse().ok(),
+      });
+    }
+    file.set_len(0).map_err(io_error)?;
+    file.seek(SeekFrom::Start(0)).map_err(io_error)?;
+    write!(file, "{}", std::process::id()).map_err(io_error)?;
+    file.flush().map_err(io_error)?;
+    Ok(FileLock { file })
+  }
+}
+
+impl Drop for FileLock {
+  fn drop(&mut self) {
+    // 不删除锁文件：删除与另一个进程的加锁之间存在竞争
+    let _ = self.file.set_len(0);
+    let _ = FileExt::unlock(&self.file);
+  }
+}
+
+/// 第二个实例遇到已被占用的自动快照目录时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockConflict {
+  /// 改用带序号后缀的目录（auto-2、auto-3……）并给出警告
+  #[default]
+  Suffix,
+  /// 不写自动快照
+  ReadOnly,
+}
+
+impl LockConflict {
+  /// 与 --lock-conflict 的取值相同
+  pub fn name(self) -> &'static str {
+    match self {
+      LockConflict::Suffix => "suffix",
+      LockConflict::ReadOnly => "read-only",
+    }
+  }
+}
+
+impl std::str::FromStr for LockConflict {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "suffix" => Ok(LockConflict::Suffix),
+      "read-only" => Ok(LockConflict::ReadOnly),
+      _ => Err("Lock conflict mode must be suffix or read-only".to_string()),
+    }
+  }
+}
+
+/// 实际使用的快照目录及其锁；None 表示只读
+pub type LockedDir = Option<(PathBuf, FileLock)>;
+
+/// 为自动快照目录 `dir` 加锁。被占用时按 `mode` 改用后缀目录，或返回 None 表示只读；
+/// 第二个值是被占用时的说明
+pub fn lock_checkpoint_dir(
+  dir: &Path,
+  mode: LockConflict,
+) -> Result<(LockedDir, Option<LockError>), LockError> {
+  let held = match FileLock::try_acquire(dir) {
+    Ok(lock) => return Ok((Some((dir.to_path_buf(), lock)), None)),
+    Err(held @ LockError::Held { .. }) => held,
+    Err(e) => return Err(e),
+  };
+  if mode == LockConflict::ReadOnly {
+    return Ok((None, Some(held)));
+  }
+  let name = dir.file_name().unwrap_or_default().to_string_lossy();
+  for n in 2.. {
+    let candidate = dir.with_file_name(format!("{}-{}", name, n));
+    match FileLock::try_acquire(&candidate) {
+      Ok(lock) => return Ok((Some((candidate, lock)), Some(held))),
+      Err(LockError::Held { .. }) => continue,
+      Err(e) => return Err(e),
+    }
+  }
+  unreachable!("ran out of checkpoint directory suffixes")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+
+  #[test]
+  fn test_second_lock_reports_holder() {
+    let dir = temp_dir("lock-holder");
+    let target = dir.join("work.json");
+    let lock = FileLock::try_acquire(&target).unwrap();
+    assert_eq!(lock_path(&target), dir.join("work.json.lock"));
+
+    let err = FileLock::try_acquire(&target).unwrap_err();
+    assert_eq!(
+      err,
+      LockError::Held {
+        path: target.clone(),
+        pid: Some(std::process::id()),
+      }
+    );
+    assert!(
+      err
+        .to_string()
+        .contains(&format!("pid {}", std::process::id()))
+    );
+
+    // 释放后可以再次加锁，残留的锁文件不影响
+    drop(lock);
+    assert!(lock_path(&target).exists());
+    FileLock::try_acquire(&target).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_stale_lock_file_is_ignored() {
+    // 崩溃进程留下的锁文件只有内容，没有系统锁
+    let dir = temp_dir("lock-stale");
+    let target = dir.join("auto");
+    fs::write(lock_path(&target), "999999").unwrap();
+    FileLock::try_acquire(&target).unwrap();
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_checkpoint_dir_conflict_modes() {
+    let dir = temp_dir("lock-checkpoint");
+    let auto = dir.join("auto");
+    let (first, conflict) = lock_checkpoint_dir(&auto, LockConflict::Suffix).unwrap();
+    assert_eq!(first.as_ref().unwrap().0, auto);
+    assert!(conflict.is_none());
+
+    let (second, conflict) = lock_checkpoint_dir(&auto, LockConflict::Suffix).unwrap();
+    assert_eq!(second.as_ref().unwrap().0, dir.join("auto-2"));
+    assert!(matches!(conflict, Some(LockError::Held { .. })));
+    let (third, _) = lock_checkpoint_dir(&auto, LockConflict::Suffix).unwrap();
+    assert_eq!(third.as_ref().unwrap().0, dir.join("auto-3"));
+
+    let (readonly, conflict) = lock_checkpoint_dir(&auto, LockConflict::ReadOnly).unwrap();
+    assert!(readonly.is_none());
+    assert!(conflict.is_some());
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -1,272 +1,2689 @@
 use anyhow::{Context, Result};
-use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor, Stylize};
 use futures_util::StreamExt;
 use std::env;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 mod api;
+mod attachment;
+mod batch;
+mod checkpoint;
+mod cite;
 mod cli;
+mod clipboard;
+mod compat;
+mod completion;
+mod config;
+mod contract;
+mod dedupe;
+mod dotenv;
+mod editor;
+mod export;
+mod flush;
+#[cfg(test)]
+mod golden;
+mod health;
+mod history;
+mod isolation;
+mod kb;
+#[cfg(test)]
+mod live;
+mod lock;
+mod markdown;
+mod math;
+#[cfg(test)]
+mod mock;
+mod models;
+mod multiline;
+mod notes;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod output;
+mod paths;
+mod persona;
+mod plugin;
+mod profile;
+mod prompt;
+mod provenance;
+mod ratelimit;
+mod reasoning;
+mod recovery;
+mod repl;
+mod replay;
+mod retry;
+mod route;
+mod run;
+mod sandbox;
+mod schema;
+mod session;
+mod sse;
+mod staging;
+mod starters;
+mod stats;
+mod steer;
+mod stop;
+mod summary;
+mod tabular;
+mod terminal;
+mod thumbnail;
+mod tokens;
+mod transcript;
+mod truncate;
+mod turn;
+mod vars;
+mod verify;
+mod walk;
+mod warm;
+mod widget;
+mod width;
 
 pub use api::{ApiClient, Message};
 pub use cli::{build_cli, map_model};
 
-fn get_model_max_tokens(model: &str) -> u32 {
-  match model {
-    "deepseek-r1" => 65536,
-    "deepseek-chat" => 8192,
-    _ => 4096,
-  }
-}
-
-fn get_model_max_input_tokens(_model: &str) -> usize {
-  65536 // 64K tokens
-}
-
-fn estimate_tokens(text: &str) -> usize {
-  // 粗略估算，1 token ≈ 4 字符
-  text.chars().count() / 4 + 1
+fn estimate_messages_tokens(messages: &[Message]) -> usize {
+  messages.iter().map(tokens::count_message).sum()
 }
 
-const MAX_AUTO_CONTINUE: usize = 5;
+const HIGH_EFFORT_MIN_TOKENS: u32 = 4096;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+  let _terminal = terminal::TerminalGuard::acquire();
   let matches = build_cli().get_matches();
-  let model_input = matches.get_one::<String>("model").unwrap();
-  let model = map_model(model_input).map_err(|e| anyhow::anyhow!(e))?;
-  let temperature = matches.get_one::<f32>("temperature").copied();
-  let max_tokens = matches
-    .get_one::<u32>("max_tokens")
+  let build = provenance::BuildInfo::current();
+  if matches.get_flag("version") && !matches.get_flag("verbose") {
+    println!("deepcli {}", build.version);
+    return Ok(());
+  }
+  let quiet = matches.get_flag("quiet");
+  // 配置文件：命令行 > 环境变量（或 .env）> 配置文件 > 默认值
+  let (file_config, file_warnings) = config::FileConfig::load(
+    matches
+      .get_one::<std::path::PathBuf>("config")
+      .map(AsRef::as_ref),
+  )?;
+  for warning in &file_warnings {
+    eprintln!("[警告] {}", warning);
+  }
+  let isolation = isolation::Isolation::new(
+    matches.get_flag("isolated") || file_config.isolated.unwrap_or(false),
+  );
+  if let Some(summary) = isolation.summary() {
+    eprintln!("{}", summary);
+  }
+  // 数据和缓存目录在读取会话之前确定
+  let dir_flag = |id: &str| {
+    matches
+      .get_one::<std::path::PathBuf>(id)
+      .map(|dir| std::path::absolute(dir).unwrap_or_else(|_| dir.clone()))
+  };
+  let dir_flags = paths::Flags {
+    data_dir: dir_flag("data_dir"),
+    cache_dir: dir_flag("cache_dir"),
+  };
+  let dirs = paths::Dirs::system().with_overrides(&dir_flags, &file_config);
+  paths::init(dirs.clone());
+  let checkpoint_keep = *matches.get_one::<usize>("checkpoint_keep").unwrap();
+  let auto_dir = paths::sessions_dir().map(|d| d.join("auto"));
+  if let Some(("sessions", sub)) = matches.subcommand() {
+    if let Some(("gc", gc)) = sub.subcommand() {
+      let keep = gc
+        .get_one::<usize>("keep")
+        .copied()
+        .unwrap_or(checkpoint_keep);
+      let dir = auto_dir.context("Cannot determine data directory (HOME not set)")?;
+      let removed = checkpoint::prune(&dir, keep)?;
+      println!("已删除 {} 个自动快照，保留最近 {} 个", removed, keep);
+    }
+    if let Some(("migrate", _)) = sub.subcommand() {
+      let store = paths::sessions_dir()
+        .map(session::SessionStore::new)
+        .context("Cannot determine data directory (HOME not set)")?;
+      let report = store.migrate_all()?;
+      for (name, version) in &report.upgraded {
+        println!("{}: 版本 {} → {}", name, version, session::FORMAT_VERSION);
+      }
+      for e in &report.failed {
+        eprintln!("[错误] {}", e);
+      }
+      println!(
+        "已升级 {} 个会话，{} 个已是最新格式，{} 个失败",
+        report.upgraded.len(),
+        report.current,
+        report.failed.len()
+      );
+      if !report.failed.is_empty() {
+        std::process::exit(1);
+      }
+    }
+    return Ok(());
+  }
+  if let Some(("export", sub)) = matches.subcommand() {
+    let name = sub.get_one::<String>("session").unwrap();
+    let out = sub.get_one::<std::path::PathBuf>("html").unwrap();
+    let store = paths::sessions_dir()
+      .map(|dir| session::SessionStore::new(dir).with_strict(matches.get_flag("strict")))
+      .context("Cannot determine data directory (HOME not set)")?;
+    let saved = store.load(name)?;
+    let html = export::html(
+      &export::Meta::from_session(&saved.meta),
+      &saved.messages,
+      &notes::Notes::default(),
+      &reasoning::Reasoning::default(),
+    );
+    std::fs::write(out, html).context(format!("Failed to write {:?}", out))?;
+    println!("已导出到 {}", out.display());
+    return Ok(());
+  }
+  let dotenv = if matches.get_flag("no_dotenv") {
+    dotenv::DotEnv::default()
+  } else {
+    dotenv::DotEnv::load(&env::current_dir()?)?
+  };
+  let real_env = |key: &str| env::var(key).ok();
+  if matches.get_flag("verbose")
+    && let Some(path) = &dotenv.path
+  {
+    let keys: Vec<_> = dotenv
+      .contributed(real_env)
+      .iter()
+      .map(|key| format!("{}=***", key))
+      .collect();
+    eprintln!("[信息] 已读取 {}: {}", path.display(), keys.join(", "));
+  }
+  tokens::init(
+    dotenv
+      .lookup(tokens::TOKENIZER_ENV, real_env)
+      .map(std::path::PathBuf::from),
+  );
+  // 环境变量（或 .env）优先于配置文件
+  let accessible_setting = dotenv
+    .lookup("DEEPCLI_ACCESSIBLE", real_env)
+    .or_else(|| file_config.accessible.map(|on| on.to_string()));
+  let term = env::var("TERM").ok();
+  let ui = widget::UiMode::detect(
+    matches.get_flag("accessible"),
+    accessible_setting.as_deref(),
+    term.as_deref(),
+  );
+  let ui_trigger = widget::UiMode::trigger(
+    matches.get_flag("accessible"),
+    accessible_setting.as_deref(),
+    term.as_deref(),
+  );
+  widget::set_mode(ui);
+  models::init(models::ModelRegistry::load(
+    paths::config_dir()
+      .map(|dir| dir.join("models.yaml"))
+      .as_deref(),
+  )?);
+  if matches.get_flag("list_models") {
+    println!("{}", models::registry().render_table());
+    return Ok(());
+  }
+  // 配置项让限制总是生效，命令行参数优先
+  let restrict_to = matches
+    .get_one::<std::path::PathBuf>("restrict_to")
+    .cloned()
+    .or_else(|| dotenv.lookup(sandbox::CONFIG_KEY, real_env).map(Into::into))
+    .or_else(|| file_config.restrict_to.clone());
+  sandbox::init(
+    restrict_to
+      .as_deref()
+      .map(sandbox::Restriction::new)
+      .transpose()?,
+  );
+  if let Some(restriction) = sandbox::current()
+    && !quiet
+  {
+    eprintln!("[信息] 只读取 {} 中的文件", restriction.root().display());
+  }
+  let resolution = profile::resolve_with_file_key(
+    matches
+      .get_one::<String>("profile")
+      .or(file_config.profile.as_ref())
+      .map(String::as_str),
+    real_env,
+    |key| dotenv.lookup(key, |_| None),
+    file_config.api_key.as_deref(),
+  )
+  .map_err(|e| anyhow::anyhow!(e))?;
+  // 升级后的第一次运行总是说明选择了哪个密钥和接口；--quiet 时留到下次
+  let first_run = !quiet
+    && paths::data_dir().is_some_and(|dir| {
+      profile::first_run_of_version(&dir.join("last-version"), env!("CARGO_PKG_VERSION"))
+    });
+  let explain = !quiet
+    && (first_run
+      || matches.get_flag("verbose")
+      || resolution.reason == profile::Reason::OnlyDeepseek);
+  if explain {
+    eprintln!("[信息] {}", resolution.summary());
+  }
+  if let Some(warning) = &resolution.warning {
+    eprintln!("[警告] {}", warning);
+  }
+  let resolved = resolution.resolved.clone();
+  let mut persona = matches
+    .get_one::<std::path::PathBuf>("persona")
+    .map(|path| persona::Persona::load(path))
+    .transpose()?;
+  let layered = config::layered(
+    &matches,
+    persona.as_ref(),
+    config::system_flag(&matches)?,
+    |key| {
+      let value = dotenv.lookup(key, real_env)?;
+      Some((value, dotenv.source(key, real_env)?))
+    },
+    &file_config,
+  );
+  for warning in &layered.warnings {
+    eprintln!("[警告] {}", warning);
+  }
+  let model = map_model(&layered.model.0, &resolved.profile).map_err(|e| anyhow::anyhow!(e))?;
+  if models::registry().lookup(&model).is_none() {
+    eprintln!(
+      "[警告] 模型表中没有 {}，按原样发送；上下文窗口和输出上限按默认的 {} / {} tokens 计算，可以在 models.yaml 中添加（--list-models 查看模型表）",
+      model,
+      models::DEFAULT_CONTEXT_WINDOW,
+      models::DEFAULT_MAX_OUTPUT
+    );
+  }
+  let temperature = layered.temperature.0;
+  let mut base_prompt = layered.system_prompt.0.clone();
+  let requested_max_tokens = layered.max_tokens.0;
+  let mut length =
+    prompt::AnswerLength::from_flags(matches.get_flag("brief"), matches.get_flag("detailed"));
+  let mut max_tokens =
+    length.max_tokens(requested_max_tokens, models::registry().max_output(&model));
+  let mut system_prompt = prompt::SystemPrompt::new(&base_prompt, length);
+  // --no-system 或 \set system none
+  let mut no_system = matches.get_flag("no_system");
+  // 输出约定在会话中一直有效，不随摘要和 system prompt 的重建丢失
+  let mut contract = contract::Contract::new(
+    matches
+      .get_many::<String>("contract")
+      .map(|rules| rules.cloned().collect())
+      .or_else(|| file_config.output_contract.clone())
+      .unwrap_or_default(),
+  );
+  // 每个模式在解析参数时已经检查过
+  let stop_patterns = matches
+    .get_many::<String>("stop_regex")
+    .map(|patterns| stop::StopPatterns::new(&patterns.cloned().collect::<Vec<_>>()))
+    .transpose()
+    .map_err(anyhow::Error::msg)?;
+  // 公式渲染只用于终端显示，管道输出保持原文
+  let render_math =
+    !matches.get_flag("no_math_render") && io::stdout().is_terminal() && !ui.is_accessible();
+  // 终端中默认渲染 Markdown，管道中原样输出
+  let render = matches
+    .get_one::<markdown::RenderMode>("render")
+    .copied()
+    .unwrap_or(match io::stdout().is_terminal() && !ui.is_accessible() {
+      true => markdown::RenderMode::Markdown,
+      false => markdown::RenderMode::Raw,
+    });
+  let mut render_markdown = render == markdown::RenderMode::Markdown;
+  // 推理过程只显示，不进入历史
+  let mut show_reasoning = !matches.get_flag("hide_reasoning");
+  let dedupe = !matches.get_flag("no_dedupe");
+  let reasoning_effort = matches
+    .get_one::<api::ReasoningEffort>("reasoning_effort")
+    .copied();
+  if reasoning_effort.is_some()
+    && !models::registry().supports(&model, models::Capability::ReasoningEffort)
+  {
+    eprintln!("[警告] 模型 {} 不支持 --reasoning-effort，已忽略", model);
+  }
+  if reasoning_effort == Some(api::ReasoningEffort::High)
+    && requested_max_tokens.is_some_and(|n| n < HIGH_EFFORT_MIN_TOKENS)
+  {
+    eprintln!(
+      "[警告] 高推理强度搭配较小的 --max_tokens ({})，推理过程可能耗尽预算导致回答被截断",
+      max_tokens
+    );
+  }
+  let truncate_mode = matches
+    .get_one::<truncate::TruncateMode>("truncate")
     .copied()
-    .unwrap_or_else(|| get_model_max_tokens(&model));
-  let api_key =
-    env::var("DASHSCOPE_API_KEY").context("DASHSCOPE_API_KEY environment variable not set")?;
-  let client = ApiClient::new(api_key);
+    .unwrap_or_default();
+  let attachment_budget = models::registry().context_window(&model) / 2;
+  let read_limits = attachment::ReadLimits {
+    max_bytes: *matches.get_one::<u64>("max_file_size").unwrap(),
+    force_text: matches.get_flag("force_text"),
+  };
+  let table_budget =
+    (!matches.get_flag("full_table")).then(|| *matches.get_one::<usize>("table_budget").unwrap());
+  // 审计用的终端用户标识：显式的 --user-id 优先，--send-user-id（或配置文件）时取 $USER
+  let send_user_id = matches.get_flag("send_user_id") || file_config.send_user_id.unwrap_or(false);
+  let user_id = matches
+    .get_one::<String>("user_id")
+    .cloned()
+    .or_else(|| send_user_id.then(|| env::var("USER").ok()).flatten());
+  let mut settings = effective_settings(
+    &matches,
+    &file_config,
+    temperature,
+    max_tokens,
+    length,
+    truncate_mode,
+  );
+  if let Some(user) = &user_id {
+    settings.push(("user_id", user.clone()));
+  }
+  // 每一项都记下来源，--show-config 和 \config 据此说明生效的配置
+  let mut config = config::startup(config::Startup {
+    matches: &matches,
+    resolution: &resolution,
+    file: &file_config,
+    layered: &layered,
+    model: &model,
+    model_default: models::registry().max_output(&model),
+    max_tokens,
+    length,
+    restrict_to: sandbox::current().map(sandbox::Restriction::root),
+    restrict_to_source: dotenv.source(sandbox::CONFIG_KEY, real_env).or_else(|| {
+      file_config
+        .restrict_to
+        .as_ref()
+        .map(|_| file_config.source())
+    }),
+    ui,
+    ui_trigger,
+    accessible_source: dotenv
+      .source("DEEPCLI_ACCESSIBLE", real_env)
+      .or_else(|| file_config.accessible.map(|_| file_config.source())),
+    dirs: &dirs,
+    render,
+  });
+  if matches.get_flag("show_config") {
+    match matches.get_flag("json") {
+      true => println!("{}", serde_json::to_string_pretty(&config.to_json())?),
+      false => println!("{}", config.to_toml()),
+    }
+    return Ok(());
+  }
+  let base_url = layered
+    .base_url
+    .0
+    .as_deref()
+    .unwrap_or(resolved.profile.base_url);
+  // 说明中的地址是提供方的默认地址，这里补充实际使用的地址
+  if let (Some(url), source) = &layered.base_url
+    && explain
+  {
+    eprintln!("[信息] 接口地址改为 {}（{}）", url, source);
+  }
+  let session_env = provenance::capture(
+    &build,
+    resolved.profile.name,
+    base_url,
+    &model,
+    &settings,
+    Some(&resolved.api_key),
+  );
+  if matches.get_flag("version") {
+    println!("deepcli {}\n{}", build.version, session_env.render());
+    return Ok(());
+  }
+  #[cfg(feature = "otlp")]
+  let tracer = otlp::Exporter::configured(
+    file_config.otlp_endpoint.as_deref(),
+    resolved.profile.name,
+    &isolation,
+  )
+  .map(std::sync::Arc::new);
+  #[cfg(not(feature = "otlp"))]
+  if file_config.otlp_endpoint.is_some() {
+    eprintln!("[警告] 编译时未启用 otlp feature，已忽略 otlp_endpoint");
+  }
+  let client = ApiClient::new(resolved.api_key)
+    .with_base_url(base_url)
+    .with_reasoning_effort(reasoning_effort)
+    .with_attachment_truncation(attachment_budget, truncate_mode)
+    .with_read_limits(read_limits)
+    .with_table_budget(table_budget)
+    .with_stream_idle(matches.get_one::<Duration>("stream_idle").copied())
+    .with_timeouts(api::Timeouts {
+      connect: *matches.get_one::<Duration>("connect_timeout").unwrap(),
+      read: *matches.get_one::<Duration>("timeout").unwrap(),
+    })
+    .with_retry(retry::RetryPolicy::new(
+      *matches.get_one::<u32>("retries").unwrap(),
+    ))
+    .with_user(user_id)
+    .with_system_prompt(&base_prompt)
+    .with_no_system(no_system);
+  #[cfg(feature = "otlp")]
+  let client = client.with_tracer(tracer.clone());
+
+  if let Some(("test-prompts", sub)) = matches.subcommand() {
+    let cases = sub.get_one::<std::path::PathBuf>("cases").unwrap();
+    let mut suite = replay::Suite::load(cases)?;
+    suite.resolve_models(|m| map_model(m, &resolved.profile))?;
+    let client = client.with_seed(Some(replay::DEFAULT_SEED));
+    let runner = replay::Runner {
+      backend: &client,
+      default_model: model,
+      snapshot_dir: replay::snapshot_dir(cases),
+      update_snapshots: sub.get_flag("update_snapshots"),
+    };
+    let results = runner.run(&suite).await;
+    println!("{}", replay::render_table(&results));
+    if results.iter().any(|r| !r.passed()) {
+      std::process::exit(1);
+    }
+    return Ok(());
+  }
 
-  let mut history: Vec<Message> = vec![];
+  if let Some(("run", sub)) = matches.subcommand() {
+    let argv: Vec<String> = sub
+      .get_many::<String>("command")
+      .unwrap()
+      .cloned()
+      .collect();
+    let output = run::run_captured(&argv, run::TAIL_LINES, &mut io::stdout(), &mut io::stderr())?;
+    let code = output.status.code().unwrap_or(1);
+    if output.status.success() && !sub.get_flag("always") {
+      return Ok(());
+    }
+    if !sub.get_flag("no_confirm") {
+      // 输出里可能有敏感信息，默认先确认
+      eprint!("将命令和最后的输出发送给模型分析？[y/N] ");
+      let mut answer = String::new();
+      io::stdin().read_line(&mut answer)?;
+      if !answer.trim().eq_ignore_ascii_case("y") {
+        std::process::exit(code);
+      }
+    }
+    match ui.is_accessible() {
+      true => println!("分析："),
+      false => println!("{} 分析 {}", "─".repeat(12), "─".repeat(12)),
+    }
+    let mut history = vec![Message::Simple {
+      role: "user".to_string(),
+      content: run::diagnosis_prompt(&argv, &output),
+      name: None,
+    }];
+    let settings = turn::TurnSettings {
+      model: &model,
+      system_prompt: &system_prompt
+        .clone()
+        .with_contract(&contract)
+        .with_no_system(no_system),
+      temperature,
+      max_tokens,
+      model_max_tokens: models::registry().max_output(&model),
+      timeout: matches.get_one::<Duration>("turn_timeout").copied(),
+      attachments: None,
+      highlight_citations: false,
+      render_math,
+      render_markdown,
+      max_words: matches.get_one::<usize>("max_words").copied(),
+      stop: stop_patterns.as_ref(),
+      ui,
+      steer: None,
+      checks: contract.checks(),
+      reasoning: None,
+      show_reasoning,
+      tools: None,
+      recovery: None,
+      keep_refusals: false,
+    };
+    let mut stats = stats::SessionStats::default();
+    turn::run_turn(
+      &client,
+      &settings,
+      &mut history,
+      &mut stats,
+      &mut io::stdout(),
+    )
+    .await?;
+    std::process::exit(code);
+  }
+
+  if let Some(("batch", sub)) = matches.subcommand() {
+    // 只做本地估算，不发送请求
+    let path = sub.get_one::<std::path::PathBuf>("input").unwrap();
+    let text = sandbox::read_to_string(path).context(format!("Failed to read {:?}", path))?;
+    let prompts = batch::parse_prompts(&text).context(format!("Invalid batch input {:?}", path))?;
+    let limits = batch::Limits {
+      concurrency: *sub.get_one::<usize>("concurrency").unwrap(),
+      rpm: sub.get_one::<u32>("rpm").copied(),
+    };
+    let estimate = batch::estimate(
+      &prompts,
+      match no_system {
+        true => "",
+        false => &system_prompt.stable,
+      },
+      *sub.get_one::<usize>("assume_output_tokens").unwrap(),
+      &model,
+      limits,
+    );
+    println!("模型: {}\n{}", model, estimate.render());
+    return Ok(());
+  }
+
+  if let Some(("ask", sub)) = matches.subcommand() {
+    let dir = sub.get_one::<std::path::PathBuf>("kb").unwrap();
+    let dir = dir
+      .canonicalize()
+      .context(format!("Cannot open notes directory {:?}", dir))?;
+    let question = sub
+      .get_many::<String>("question")
+      .unwrap()
+      .cloned()
+      .collect::<Vec<_>>()
+      .join(" ");
+    let cache = paths::cache_dir().context("Cannot determine cache directory (HOME not set)")?;
+    let index_path = kb::index_path(&cache, &dir);
+    let mut index = if sub.get_flag("kb_rebuild") {
+      kb::Index {
+        model: kb::EMBEDDING_MODEL.to_string(),
+        ..Default::default()
+      }
+    } else {
+      kb::Index::load(&index_path, kb::EMBEDDING_MODEL)
+    };
+    let embed = |texts: Vec<String>| {
+      let client = &client;
+      async move { client.embed(kb::EMBEDDING_MODEL, &texts).await }
+    };
+    let refreshed = kb::refresh(&mut index, &dir, embed).await?;
+    if !refreshed.changed.is_empty() || !refreshed.removed.is_empty() {
+      index.save(&index_path)?;
+      if !quiet {
+        eprintln!(
+          "[信息] 已更新索引：{} 个文件重新计算向量，{} 个文件已移除",
+          refreshed.changed.len(),
+          refreshed.removed.len()
+        );
+      }
+    }
+    let query = embed(vec![question.clone()]).await?;
+    let top_k = *sub.get_one::<usize>("kb_top_k").unwrap();
+    let hits = kb::search(&index, &query[0], top_k);
+    if hits.is_empty() {
+      anyhow::bail!("No notes found in {}", dir.display());
+    }
+    let settings = turn::TurnSettings {
+      model: &model,
+      system_prompt: &system_prompt
+        .clone()
+        .with_contract(&contract)
+        .with_no_system(no_system),
+      temperature,
+      max_tokens,
+      model_max_tokens: models::registry().max_output(&model),
+      timeout: matches.get_one::<Duration>("turn_timeout").copied(),
+      attachments: None,
+      highlight_citations: false,
+      render_math,
+      render_markdown,
+      max_words: matches.get_one::<usize>("max_words").copied(),
+      stop: stop_patterns.as_ref(),
+      ui,
+      steer: None,
+      checks: contract.checks(),
+      reasoning: None,
+      show_reasoning,
+      tools: None,
+      recovery: None,
+      keep_refusals: false,
+    };
+    if !kb::answer(&client, &settings, &hits, &question, &mut io::stdout()).await? {
+      std::process::exit(1);
+    }
+    return Ok(());
+  }
+
+  let status_cache = paths::cache_dir().map(|d| d.join("status.json"));
+  if matches.subcommand_name() == Some("status") {
+    let probe = client
+      .probe_stream(&model, health::PROBE_TIMEOUT)
+      .await
+      .map_err(|e| format!("{:#}", e));
+    let report = health::classify(client.base_url(), probe);
+    println!("{}", report.render());
+    if let Some(path) = &status_cache
+      && let Err(e) = health::write_cache(path, &report)
+    {
+      eprintln!("[警告] 无法写入状态缓存: {:#}", e);
+    }
+    if report.is_degraded() {
+      std::process::exit(1);
+    }
+    return Ok(());
+  }
+  if let Some(report) = status_cache.as_deref().and_then(|path| {
+    health::read_cache(
+      path,
+      client.base_url(),
+      chrono::Local::now(),
+      health::CACHE_TTL,
+    )
+  }) && report.is_degraded()
+  {
+    eprintln!(
+      "[警告] 最近一次 deepcli status 检测结果异常: {}",
+      report.render().lines().next().unwrap_or_default()
+    );
+  }
+
+  // 自定义地址在第一次对话请求前检查接口形态
+  let client = match layered.base_url.0.is_some()
+    && !compat::is_builtin(client.base_url())
+    && !matches.get_flag("no_compat_check")
+  {
+    true => check_compat(client, &model, quiet).await?,
+    false => client,
+  };
+  let run_mode = cli::run_mode(&matches, io::stdin().is_terminal());
+  if let cli::RunMode::OneShot(query) = run_mode {
+    // stdin 是管道时读完全部内容，接在命令行的问题后面
+    let piped = match io::stdin().is_terminal() {
+      true => None,
+      false => Some(io::read_to_string(io::stdin()).context("Failed to read stdin")?),
+    };
+    let query = cli::combine_stdin(query, piped.as_deref());
+    if query.trim().is_empty() {
+      anyhow::bail!("No query given: pass it as an argument or on stdin, or use -i");
+    }
+    let schema = match matches.get_one::<std::path::PathBuf>("json_schema") {
+      Some(path) => Some(schema::Schema::load(path)?),
+      None => None,
+    };
+    let json = matches.get_flag("json") || schema.is_some();
+    let mut client = client.with_json_schema(schema.as_ref().map(schema::Schema::format));
+    let file = matches.get_one::<std::path::PathBuf>("file");
+    if let Some(path) = file
+      && !path.is_file()
+    {
+      anyhow::bail!("--file {:?} does not exist or is not a file", path);
+    }
+    let output = match matches.get_one::<std::path::PathBuf>("output") {
+      Some(path) => {
+        let mode = match (matches.get_flag("output_append"), matches.get_flag("force")) {
+          (true, _) => output::Mode::Append,
+          (false, true) => output::Mode::Overwrite,
+          (false, false) => output::Mode::Create,
+        };
+        Some(output::Output::new(path, mode)?)
+      }
+      None => None,
+    };
+    // 终端中边收边显示；管道（例如交给 jq）和 --no-stream 时等完整回答
+    let streaming = !matches.get_flag("no_stream") && io::stdout().is_terminal();
+    // 图片以 data URL 发送，文本文件内联到问题之后；没有 --file 时带上 system prompt
+    let messages = |instruction: &str| {
+      let prompt = system_prompt
+        .clone()
+        .with_contract(&contract)
+        .with_no_system(no_system);
+      let prompt = match json {
+        true => prompt.with_volatile(instruction),
+        false => prompt,
+      };
+      let mut messages = prompt.messages(&[Message::Simple {
+        role: "user".to_string(),
+        content: query.trim().to_string(),
+        name: None,
+      }]);
+      let folded = prompt.no_system && prompt::fold_system(&mut messages);
+      if folded || prompt.folds() {
+        prompt::warn_folded();
+      }
+      messages
+    };
+    // 明显超出上下文窗口时在本地报错，而不是收到服务方的 400
+    let input_tokens = estimate_messages_tokens(&messages(&client.json_instruction()));
+    let context_window = models::registry().context_window(&model);
+    if input_tokens > context_window {
+      anyhow::bail!(
+        "The query is about {} tokens, more than the {} tokens {} accepts{}",
+        input_tokens,
+        context_window,
+        model,
+        match piped.is_some() {
+          true => "; pipe in a smaller part of the input",
+          false => "",
+        }
+      );
+    }
+    let tokens = Some(max_tokens);
+    // 服务方不支持 json_schema 响应格式时改用 json_object，schema 写进 system 消息再发一次
+    let answer = loop {
+      let answer = match (streaming, file) {
+        (true, Some(path)) => {
+          let stream = client
+            .call_api_with_file_stream(&model, query.trim(), path, temperature, tokens, json)
+            .await;
+          match stream {
+            Ok(stream) => {
+              turn::print_stream(
+                stream,
+                &mut io::stdout(),
+                render_markdown && !json,
+                stop_patterns.as_ref(),
+                show_reasoning.then(|| reasoning::ReasoningStream::new(!ui.is_accessible())),
+              )
+              .await
+            }
+            Err(e) => Err(e),
+          }
+        }
+        (true, None) => {
+          let stream = client
+            .call_api_with_history_stream(
+              &model,
+              messages(&client.json_instruction()),
+              temperature,
+              tokens,
+              json,
+            )
+            .await;
+          match stream {
+            Ok(stream) => {
+              turn::print_stream(
+                stream,
+                &mut io::stdout(),
+                render_markdown && !json,
+                stop_patterns.as_ref(),
+                show_reasoning.then(|| reasoning::ReasoningStream::new(!ui.is_accessible())),
+              )
+              .await
+            }
+            Err(e) => Err(e),
+          }
+        }
+        (false, Some(path)) => client
+          .call_api_with_file(&model, query.trim(), path, temperature, tokens, json)
+          .await
+          .map(|response| (response.text(), None, response.usage)),
+        (false, None) => client
+          .call_api_with_history(
+            &model,
+            messages(&client.json_instruction()),
+            temperature,
+            tokens,
+            json,
+          )
+          .await
+          .map(|response| (response.text(), None, response.usage)),
+      };
+      match answer {
+        Err(e) if client.sends_json_schema() && schema::rejected(&e) => {
+          if !quiet {
+            eprintln!("[信息] 服务方不支持 json_schema 响应格式，改为把 schema 写进提示词");
+          }
+          client = client.with_schema_in_prompt();
+        }
+        answer => break answer,
+      }
+    };
+    #[cfg(feature = "otlp")]
+    flush_traces(tracer.as_deref(), quiet).await;
+    // 流中途出错也以非零状态退出，已输出的部分回答不完整
+    let (text, finish_reason, usage) = match answer {
+      Ok(answer) => answer,
+      Err(e) => {
+        eprintln!("[API错误]: {:#}", e);
+        if let Some(hint) = api::hint(&e) {
+          eprintln!("[提示] {}", hint);
+        }
+        std::process::exit(1);
+      }
+    };
+    // 流式输出时已经在匹配处停止
+    let text = match stop_patterns.as_ref().filter(|_| !streaming) {
+      Some(stop) => stop.cut(&text).map(str::to_string).unwrap_or(text),
+      None => text,
+    };
+    if finish_reason.as_deref() == Some(api::CONTENT_FILTER) {
+      eprintln!("[内容过滤] 回答被服务商的内容审核拦截，原样重发不会成功，请换个说法");
+      std::process::exit(1);
+    }
+    if matches.get_flag("verbose") {
+      match usage {
+        Some(u) => eprintln!(
+          "[用量] 输入 {} / 输出 {} / 合计 {} tokens",
+          u.prompt_tokens, u.completion_tokens, u.total_tokens
+        ),
+        None => eprintln!("[用量] 服务方没有报告"),
+      }
+    }
+    // 文件中是回答原文，不带终端渲染；不合法的 JSON 回答不写入
+    let write_output = || -> Result<()> {
+      if let Some(output) = &output {
+        output.write(&text)?;
+        if !quiet {
+          eprintln!("[信息] 回答已写入 {}", output.path().display());
+        }
+      }
+      Ok(())
+    };
+    if !json {
+      match (streaming, render_markdown) {
+        (true, _) => {}
+        (false, true) => print!("{}", markdown::render(text.trim_end())),
+        (false, false) => println!("{}", text.trim_end()),
+      }
+      return write_output();
+    }
+    match serde_json::from_str::<serde_json::Value>(&text) {
+      Ok(value) => {
+        // 流式输出时回答已经显示过了
+        if !streaming {
+          println!("{}", serde_json::to_string_pretty(&value)?);
+        }
+        let errors = match &schema {
+          Some(schema) => schema::validate(&schema.value, &value),
+          None => vec![],
+        };
+        if !errors.is_empty() {
+          eprintln!("[错误] 回答不符合 --json-schema:");
+          for error in errors {
+            eprintln!("  {}", error);
+          }
+          std::process::exit(1);
+        }
+      }
+      Err(e) => {
+        if !streaming {
+          println!("{}", text.trim_end());
+        }
+        eprintln!("[错误] 回答不是合法的 JSON: {}", e);
+        std::process::exit(1);
+      }
+    }
+    return write_output();
+  }
+
+  let mut history: Vec<Message> = match matches.get_one::<std::path::PathBuf>("import") {
+    Some(path) => import_history(path)?,
+    None => vec![],
+  };
+  let checkpoint_every =
+    config::flag_or_file(&matches, "checkpoint_every", file_config.checkpoint_every);
+  let lock_conflict = config::flag_or_file(&matches, "lock_conflict", file_config.lock_conflict);
+  let mut checkpointer = match auto_dir.map(|dir| lock::lock_checkpoint_dir(&dir, lock_conflict)) {
+    Some(Ok((Some((dir, dir_lock)), conflict))) => {
+      if let Some(conflict) = conflict {
+        eprintln!("[警告] {}，自动快照改写到 {}", conflict, dir.display());
+      }
+      Some(
+        checkpoint::Checkpointer::new(dir, checkpoint_every, checkpoint_keep).with_lock(dir_lock),
+      )
+    }
+    Some(Ok((None, conflict))) => {
+      if let Some(conflict) = conflict {
+        eprintln!("[警告] {}，本实例不写自动快照", conflict);
+      }
+      None
+    }
+    Some(Err(e)) => {
+      eprintln!("[警告] 无法锁定自动快照目录: {}，本实例不写自动快照", e);
+      None
+    }
+    None => None,
+  };
+  // 流式输出中的回答写入快照目录，终端意外关闭后 --resume 可以找回
+  let in_progress = checkpointer.as_ref().map(|cp| {
+    std::cell::RefCell::new(recovery::InProgress::new(
+      cp.dir().join(recovery::FILE_NAME),
+    ))
+  });
+  let mut stats = stats::SessionStats::default();
+  let store = paths::sessions_dir()
+    .map(|dir| session::SessionStore::new(dir).with_strict(matches.get_flag("strict")));
+  // \save 或 \load 打开用于写入的会话，锁一直保持到换成另一个会话或退出
+  let mut open_session: Option<String> = None;
+  // 后台摘要在独立任务中运行，需要单独持有一份客户端
+  let summarizer = Arc::new(client.clone());
+  // 插件只提供给交互式对话；隔离模式下不运行外部程序
+  let path = paths::config_dir().map(|dir| dir.join("plugins.yaml"));
+  let (plugins, errors) =
+    plugin::Plugins::load_unless_isolated(path.as_deref(), &isolation).await?;
+  for error in errors {
+    eprintln!("[警告] {}", error);
+  }
+  if !quiet && !plugins.is_empty() {
+    eprintln!("[信息] 已加载插件: {}", plugins.names().join(", "));
+  }
+  let client = client.with_tools(plugins.tools());
+  // --auto-route：简单的追问交给便宜模型，@模型 指定本轮的模型
+  let route_model = map_model(
+    file_config
+      .route_model
+      .as_deref()
+      .unwrap_or(route::DEFAULT_CHEAP_MODEL),
+    &resolved.profile,
+  );
+  let mut route_threshold = match (matches.get_flag("auto_route"), &route_model) {
+    (false, _) => None,
+    (true, Ok(_)) => Some(
+      file_config
+        .route_threshold
+        .unwrap_or(route::DEFAULT_THRESHOLD),
+    ),
+    (true, Err(e)) => {
+      eprintln!("[警告] route_model 不可用: {}，不启用 --auto-route", e);
+      None
+    }
+  };
+  // --verify：每轮回答后用 route_model 复核，\verify 随时复核最后一轮
+  let verify_prompt = file_config
+    .verify_prompt
+    .clone()
+    .unwrap_or_else(|| verify::DEFAULT_PROMPT.to_string());
+  let auto_verify = match (matches.get_flag("verify"), &route_model) {
+    (true, Err(e)) => {
+      eprintln!("[警告] route_model 不可用: {}，不启用 --verify", e);
+      false
+    }
+    (verify, _) => verify,
+  };
+  // 最近一次复核的轮次和意见，供 \keep-verification 使用
+  let mut last_review: Option<(usize, String)> = None;
+  let image_protocol = match (io::stdout().is_terminal(), file_config.inline_images) {
+    (false, _) => thumbnail::Protocol::Plain,
+    (true, Some(protocol)) => protocol,
+    (true, None) => thumbnail::Protocol::detect(|key| std::env::var(key).ok()),
+  };
+  let image_rows = file_config.image_rows.unwrap_or(thumbnail::DEFAULT_ROWS);
+  let keep_refusals =
+    matches.get_flag("keep_refusals") || file_config.keep_refusals.unwrap_or(false);
+  // 复制通过终端完成，没有终端或终端不支持时给出原因
+  let clipboard = clipboard::available(&isolation, io::stdout().is_terminal(), real_env);
+  let copy_on_complete = file_config.copy_on_complete.unwrap_or(false);
+  if copy_on_complete && let Err(e) = &clipboard {
+    eprintln!("[警告] copy_on_complete 无法生效: {}", e);
+  }
+  let mut rolling = summary::RollingSummary::default();
+  // warm_start：在用户输入第一个问题的同时预热 deepseek-reasoner
+  let mut warmup = warm::Warmup::default();
+  let warm_log_path = paths::data_dir().map(|dir| dir.join(warm::LOG_FILE));
+  if file_config.warm_start.unwrap_or(false) && warm::applies(&model) {
+    warmup.start_unless_isolated(&summarizer, &model, &isolation);
+  }
+  // \file <目录> 时的排除规则，配置文件和命令行中的值都已检查过
+  let attach_rules = walk::Rules::new(
+    file_config.attach_exclude.as_deref().unwrap_or_default(),
+    &cli::attach_rules(&matches),
+  )
+  .map_err(anyhow::Error::msg)?;
+  let mut attachments = attachment::AttachmentStore::new(Some((attachment_budget, truncate_mode)))
+    .with_read_limits(read_limits)
+    .with_table_budget(table_budget);
+  let mut notes = notes::Notes::default();
+  let reasoning = std::cell::RefCell::new(reasoning::Reasoning::default());
+  let mut vars = vars::Vars::default();
   let stdin = io::stdin();
   let mut stdout = io::stdout();
+  let flush_interval = matches
+    .get_one::<u64>("flush_interval_ms")
+    .map(|&ms| Duration::from_millis(ms));
+  // 空白会话可以从对话模板开始，效果与 --persona 加上第一条消息相同
+  let mut first_message = None;
+  let resume = matches.get_flag("resume");
+  match in_progress
+    .as_ref()
+    .map(|r| recovery::load(r.borrow().path()))
+  {
+    Some(Ok(Some(recovered))) if resume => {
+      let continued;
+      (history, continued) = resume_partial(&recovered, &mut stdout)?;
+      if continued {
+        first_message = Some(turn::CONTINUE_PROMPT.to_string());
+      }
+    }
+    Some(Ok(Some(recovered))) => eprintln!(
+      "[提示] 上次退出时有未完成的回答（{} 字），可以用 --resume 找回；本次开始回答后会被覆盖",
+      recovered.partial.chars().count()
+    ),
+    Some(Ok(None)) if resume => eprintln!("[信息] 没有需要恢复的回答"),
+    Some(Ok(None)) => {}
+    Some(Err(e)) => eprintln!("[警告] 无法读取进行中的回答: {:#}", e),
+    None if resume => eprintln!("[警告] 没有可用的快照目录，无法恢复回答"),
+    None => {}
+  }
+  let launch = starters::Launch {
+    persona: persona.is_some(),
+    system: matches.contains_id("system") || matches.contains_id("system_file"),
+    query: matches.get_one::<String>("query").is_some(),
+    imported: !history.is_empty(),
+    stdin_is_terminal: stdin.is_terminal(),
+  };
+  let templates = match paths::config_dir().map(|dir| starters::load(&dir.join("starters.yaml"))) {
+    Some(Ok(templates)) => templates,
+    Some(Err(e)) => {
+      eprintln!("[警告] {:#}", e);
+      vec![]
+    }
+    None => vec![],
+  };
+  if starters::should_offer(launch, &templates) {
+    print!("{}", starters::render_menu(&templates));
+    stdout.flush()?;
+    loop {
+      let mut choice = String::new();
+      stdin.read_line(&mut choice)?;
+      match starters::parse_choice(&choice, templates.len()) {
+        Ok(Some(i)) => {
+          let starter = &templates[i];
+          base_prompt = starter.system.clone();
+          system_prompt = prompt::SystemPrompt::new(&base_prompt, length);
+          config.set(
+            "system",
+            starter.title.as_str(),
+            config::Source::Starter(starter.title.clone()),
+          );
+          first_message = starter.message.clone();
+          eprintln!("[信息] 已使用模板: {}", starter.title);
+          break;
+        }
+        Ok(None) => break,
+        Err(e) => {
+          print!("{}: ", e);
+          stdout.flush()?;
+        }
+      }
+    }
+  }
 
+  // -i 带上的问题作为第一条消息
+  if let cli::RunMode::Interactive(Some(query)) = run_mode {
+    first_message = Some(query.to_string());
+  }
+
+  // 输出中按 Ctrl-C 中断并输入纠偏说明，逐行读入问题时连按两次与 \q 相同，其他时候照常退出
+  let interrupt = stdin.is_terminal().then(steer::Interrupt::listen);
+  let ask_note = || {
+    print!("纠偏说明（直接回车结束本轮）: ");
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line).ok()?;
+    Some(line)
+  };
+  // 终端中用行编辑器读入问题；屏幕阅读器模式下逐行读入，不改写已经输出的内容
+  let mut editor =
+    (stdin.is_terminal() && stdout.is_terminal() && !ui.is_accessible()).then(|| {
+      editor::Editor::new(editor::History::load(
+        paths::data_dir().map(|dir| dir.join(editor::HISTORY_FILE)),
+      ))
+      .with_completer(completion::Completer::new(
+        paths::sessions_dir().map(session::SessionStore::new),
+        vec!["system".to_string()],
+      ))
+    });
   loop {
     print_red_prompt(&mut stdout);
     stdout.flush()?;
-    let mut input = String::new();
-    stdin.read_line(&mut input)?;
-    let input = input.trim();
+    let input = match first_message.take() {
+      // 模板的第一条消息像用户输入一样回显并发送
+      Some(message) => {
+        println!("{}", message);
+        message
+      }
+      // 空行上按 Ctrl-D 与 \q 相同
+      None if let Some(editor) = editor.as_mut() => match editor.read(&mut stdout)? {
+        editor::Input::Message(message) => message,
+        editor::Input::Eof => "\\q".to_string(),
+      },
+      None => {
+        // 只在读入问题时开启括号粘贴，多行粘贴作为一条消息；其他提示照常逐行读入
+        let paste = stdin.is_terminal() && stdout.is_terminal();
+        if paste {
+          terminal::with(|t| t.enable_bracketed_paste());
+        }
+        let input = match interrupt.as_deref() {
+          Some(interrupt) => read_prompt(interrupt, stdin.is_terminal()).await,
+          None => multiline::read(&mut stdin.lock(), &mut stdout, stdin.is_terminal()),
+        };
+        if paste {
+          terminal::with(|t| t.disable_bracketed_paste());
+        }
+        input?
+      }
+    };
+    // 多行输入保留第一行的缩进
+    let input = match input.contains('\n') {
+      true => input.trim_end(),
+      false => input.trim(),
+    };
     if input.is_empty() {
       continue;
     }
     if input == "\\q" {
+      if let (Some(store), Some(name)) = (&store, open_session.take()) {
+        store.close(&name);
+      }
+      if !quiet
+        && stdout.is_terminal()
+        && let Some(footer) = widget::stats_footer(
+          &stats.render(&model),
+          &stats.render_compact(&model),
+          widget::terminal_width(),
+          ui,
+        )
+      {
+        println!("{}", footer);
+      }
       break;
     }
-    if input == "\\c" {
-      history.clear();
+    if input == "\\stats" {
+      let mut text = stats.render(&model);
+      if warm::applies(&model)
+        && let Some(line) = warm_log_path
+          .as_deref()
+          .and_then(|p| warm::Log::load(p).render())
+      {
+        text = format!("{}\n{}", text, line);
+      }
+      if stdout.is_terminal() {
+        let footer = widget::stats_footer(
+          &text,
+          &stats.render_compact(&model),
+          widget::terminal_width(),
+          ui,
+        );
+        println!("{}", footer.unwrap_or_default());
+      } else {
+        println!("{}", text);
+      }
       continue;
     }
-    // 添加到历史
-    history.push(Message::Simple {
-      role: "user".to_string(),
-      content: input.to_string(),
-    });
-    // 构造带历史的消息
-    let mut messages = vec![Message::Simple {
-      role: "system".to_string(),
-      content: "You are a helpful assistant.".to_string(),
-    }];
-    messages.extend(history.iter().cloned());
-    // 检查token数，超限则自动摘要
-    let max_input_tokens = get_model_max_input_tokens(&model);
-    let total_tokens: usize = messages
-      .iter()
-      .map(|m| match m {
-        Message::Simple { content, .. } => estimate_tokens(content),
-        Message::MultiModal { content, .. } => content
-          .iter()
-          .map(|c| match c {
-            api::Content::Text(t) => estimate_tokens(&t.text),
-            api::Content::Image(_) => 0,
-          })
-          .sum(),
-      })
-      .sum();
-    if total_tokens > max_input_tokens {
-      // 自动摘要历史
-      let history_text = messages
-        .iter()
-        .filter_map(|m| match m {
-          Message::Simple { role, content } => {
-            if role == "user" || role == "assistant" {
-              Some(format!("{}: {}", role, content))
-            } else {
-              None
-            }
+    if input == "\\tokens" {
+      println!("{}", stats.render_usage());
+      continue;
+    }
+    if input == "\\config" {
+      println!("{}", config.to_toml());
+      continue;
+    }
+    if input == "\\headers" {
+      match client.last_headers() {
+        Some(headers) => println!("{}", headers.render()),
+        None => println!("还没有发出过请求"),
+      }
+      continue;
+    }
+    // \h 查看历史，\drop 删除某一轮，\undo 丢弃最近一轮
+    let outcome = repl::handle(
+      input,
+      &mut repl::Session {
+        history: &mut history,
+        notes: &mut notes,
+        reasoning: &mut reasoning.borrow_mut(),
+      },
+    );
+    if let Some(outcome) = outcome {
+      println!("{}", outcome.output);
+      if outcome.changed {
+        rolling.cancel();
+        if !quiet && stdout.is_terminal() {
+          print_context_bar(&attachments.expand(&history), &model, ui);
+        }
+      }
+      continue;
+    }
+    // \c 清空对话轮次，\c all 清空全部，\c last 丢弃最近一轮
+    if input == "\\c" || input.starts_with("\\c ") {
+      let scope = match history::parse_clear(&input["\\c".len()..]) {
+        Ok(scope) => scope,
+        Err(e) => {
+          println!("{}", e);
+          continue;
+        }
+      };
+      rolling.cancel();
+      let turns = notes::turn_count(&history);
+      let dropped = history::clear(&mut history, scope);
+      match scope {
+        history::ClearScope::Last => {
+          notes.truncate(turns);
+          reasoning.borrow_mut().truncate(turns);
+        }
+        _ => {
+          notes.clear();
+          reasoning.borrow_mut().clear();
+        }
+      }
+      let mut kept = vec![];
+      if scope == history::ClearScope::All {
+        let detached = attachments.clear_sticky();
+        vars.clear();
+        if detached > 0 {
+          println!("已取消 {} 个持续附加的文件，并删除了全部变量", detached);
+        }
+      } else if scope == history::ClearScope::Turns {
+        if !history.is_empty() {
+          kept.push("导入的 system 消息".to_string());
+        }
+        let sticky = attachments.sticky().len();
+        if sticky > 0 {
+          kept.push(format!("{} 个持续附加的文件", sticky));
+        }
+        if !vars.is_empty() {
+          kept.push("变量".to_string());
+        }
+      }
+      match (dropped, kept.is_empty()) {
+        (0, _) => println!("没有可以清除的对话"),
+        (n, true) => println!("已清除 {} 轮对话", n),
+        (n, false) => println!(
+          "已清除 {} 轮对话，保留{}（\\c all 一并清除）",
+          n,
+          kept.join("、")
+        ),
+      }
+      // 清理后立即显示新的上下文占用
+      if !quiet && stdout.is_terminal() {
+        print_context_bar(&attachments.expand(&history), &model, ui);
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\save ") {
+      match history::target(arg.trim()) {
+        // 指定文件时写出只有消息数组的旧格式
+        history::Target::File(path) => match history::save(path, &history) {
+          Ok(()) => println!("已保存 {} 条消息到 {}", history.len(), path.display()),
+          Err(e) => println!("[保存错误]: {:#}", e),
+        },
+        history::Target::Session(name) => {
+          let Some(store) = &store else {
+            println!("无法确定数据目录，会话管理不可用");
+            continue;
+          };
+          // 保存后会话保持锁定，另一个实例保存到同名会话时会看到持有者
+          let saved = store
+            .open_for_writing(name)
+            .and_then(|()| store.save(name, &history, &model, Some(&session_env)))
+            .and_then(|path| store.set_vars(name, vars.values()).map(|()| path))
+            .and_then(|path| store.set_contract(name, contract.rules()).map(|()| path));
+          if saved.is_ok() {
+            switch_session(store, &mut open_session, name);
           }
-          _ => None,
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
-      let summary_prompt = format!(
-        "请用中文总结以下对话内容，保留关键信息，便于后续继续对话：\n{}",
-        history_text
-      );
-      print_green_prompt(&mut stdout);
-      stdout.flush()?;
-      let mut summary = String::new();
-      match client
-        .call_api_with_history_stream(
-          &model,
-          vec![
-            Message::Simple {
-              role: "system".to_string(),
-              content: "你是一个对话历史摘要助手。".to_string(),
-            },
-            Message::Simple {
-              role: "user".to_string(),
-              content: summary_prompt,
-            },
-          ],
-          temperature,
-          Some(2048),
-          false,
-        )
-        .await
-      {
-        Ok(mut stream) => {
-          while let Some(chunk) = stream.next().await {
-            match chunk {
-              Ok((s, _)) => {
-                print!("{}", s);
-                stdout.flush()?;
-                summary.push_str(&s);
+          match saved {
+            Ok(path) => println!(
+              "已保存 {} 条消息到会话 {}（{}）",
+              history.len(),
+              name,
+              path.display()
+            ),
+            Err(e) => println!("[保存错误]: {}", e),
+          }
+        }
+      }
+      continue;
+    }
+    if input == "\\load" || input.starts_with("\\load ") {
+      let (path, append) = match history::parse_load(&input["\\load".len()..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+          println!("{}", e);
+          continue;
+        }
+      };
+      let loaded = match (history::target(path), &store) {
+        (history::Target::Session(name), Some(store))
+          if store.path_of(name).is_ok_and(|p| p.is_file()) =>
+        {
+          // 另一个实例正在写入时仍然可以读，只是之后不能保存到这个名字
+          match store.open_for_writing(name) {
+            Ok(()) => switch_session(store, &mut open_session, name),
+            Err(e) => println!("[警告] {}，以只读方式打开", e),
+          }
+          store
+            .load(name)
+            .map(|mut saved| (std::mem::take(&mut saved.messages), Some(saved)))
+            .map_err(anyhow::Error::from)
+        }
+        _ => history::load(std::path::Path::new(path)).map(|messages| (messages, None)),
+      };
+      match loaded {
+        Ok((loaded, saved)) => {
+          rolling.cancel();
+          let count = loaded.len();
+          if append {
+            history.extend(loaded);
+            println!("已追加 {} 条消息，共 {} 条", count, history.len());
+          } else {
+            history = loaded;
+            notes.clear();
+            reasoning.borrow_mut().clear();
+            println!("已恢复 {} 条消息", count);
+            // 会话中保存的变量替换当前的；文件只有消息，变量保持不变
+            if let Some(saved) = &saved {
+              vars = vars::Vars::from(saved.vars.clone());
+              // 没有保存约定的会话沿用当前的约定
+              if !saved.contract.is_empty() {
+                contract = contract::Contract::new(saved.contract.clone());
               }
-              Err(e) => {
-                eprintln!("[摘要API流错误]: {}", e);
-                break;
+            }
+          }
+          if let Some(meta) = saved.as_ref().map(|saved| &saved.meta) {
+            println!(
+              "会话 {}，{} 创建{}",
+              meta.title,
+              meta.created_at.format("%Y-%m-%d %H:%M"),
+              match meta.description.as_str() {
+                "" => String::new(),
+                description => format!("：{}", description),
               }
+            );
+          }
+        }
+        Err(e) => println!("[加载错误]: {:#}", e),
+      }
+      continue;
+    }
+    if let Some(path) = input.strip_prefix("\\import ") {
+      match import_history(std::path::Path::new(path.trim())) {
+        Ok(imported) => {
+          rolling.cancel();
+          history = imported;
+          notes.clear();
+          reasoning.borrow_mut().clear();
+        }
+        Err(e) => println!("[导入错误]: {:#}", e),
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\attach ") {
+      // \attach <路径> --sticky：之后的每个请求都带上这个文件
+      let Some(path) = arg.trim().strip_suffix("--sticky").map(str::trim) else {
+        println!("用法: \\attach <路径> --sticky（只附加一次请用 \\file）");
+        continue;
+      };
+      match attachments.attach_sticky(std::path::Path::new(path)) {
+        Ok(hash) => {
+          let tokens = attachments.get(&hash).map_or(0, |a| tokens::count(&a.data));
+          println!(
+            "已持续附加 {}（约 {} tokens），\\detach {} 取消",
+            path, tokens, path
+          );
+        }
+        Err(e) => println!("[文件错误]: {:#}", e),
+      }
+      continue;
+    }
+    if let Some(path) = input.strip_prefix("\\detach ") {
+      match attachments.detach(path.trim()) {
+        Some(path) => println!("已取消持续附加 {}", path.display()),
+        None => println!("没有持续附加 {}", path.trim()),
+      }
+      continue;
+    }
+    if input == "\\attachments" {
+      let sticky = attachments.sticky();
+      if sticky.is_empty() {
+        println!("没有持续附加的文件");
+      }
+      for (item, tokens) in sticky {
+        println!("{}  约 {} tokens", item.path.display(), tokens);
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\note ") {
+      // 批注只保存在本地，不进入 history
+      match notes::parse_note(arg, notes::turn_count(&history)) {
+        Ok((turn, text)) => {
+          notes.add(turn, text);
+          println!("已批注第 {} 轮", turn);
+        }
+        Err(e) => println!("{}", e),
+      }
+      continue;
+    }
+    if input == "\\verify" {
+      match &route_model {
+        Ok(verify_model) => match verify::last_exchange(&history) {
+          Some(exchange) => {
+            last_review = review(
+              &summarizer,
+              verify_model,
+              &verify_prompt,
+              exchange,
+              &history,
+              &mut stats,
+              ui,
+            )
+            .await;
+          }
+          None => println!("还没有可以复核的回答"),
+        },
+        Err(e) => println!("route_model 不可用: {}", e),
+      }
+      continue;
+    }
+    if input == "\\keep-verification" {
+      match last_review.take() {
+        Some((turn, text))
+          if turn == notes::turn_count(&history) && verify::keep(&mut history, &text) =>
+        {
+          println!("复核意见已附在第 {} 轮的回答后面", turn);
+        }
+        _ => println!("最后一轮还没有复核意见（\\verify 复核）"),
+      }
+      continue;
+    }
+    if input == "\\notes" {
+      if notes.is_empty() {
+        println!("暂无批注");
+      } else {
+        match ui.is_accessible() {
+          true => println!("{}", notes.render()),
+          false => println!("{}", notes.render().dim()),
+        }
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\export ") {
+      // \export <文件.md> 或 \export --html <文件.html>
+      let (path, html) = match arg.trim().strip_prefix("--html") {
+        Some(path) if path.starts_with(char::is_whitespace) => (path.trim(), true),
+        _ => (arg.trim(), false),
+      };
+      let path = std::path::Path::new(path);
+      let text = match html {
+        true => {
+          let meta = export::Meta {
+            model: model.clone(),
+            created: chrono::Local::now().format("%Y-%m-%d %H:%M").to_string(),
+            ..export::Meta::default()
+          };
+          // 展开附件引用，图片以 data URI 嵌入页面
+          export::html(
+            &meta,
+            &attachments.expand(&history),
+            &notes,
+            &reasoning.borrow(),
+          )
+        }
+        false => export::markdown(&history, &notes, &reasoning.borrow()),
+      };
+      match std::fs::write(path, text) {
+        Ok(()) => println!("已导出到 {}", path.display()),
+        Err(e) => println!("[导出错误]: {}", e),
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\setvar ") {
+      match vars::parse_setvar(arg) {
+        Ok(vars::SetVar::Literal { name, value }) => {
+          vars.set(name, value.to_string());
+          println!("已定义 {{{{{}}}}}（{} 字符）", name, value.chars().count());
+        }
+        Ok(vars::SetVar::Reply { name, turn }) => {
+          let turn = turn.unwrap_or_else(|| notes::turn_count(&history));
+          match history::turn_reply(&history, turn) {
+            Some(reply) => {
+              println!(
+                "已将第 {} 轮的回答保存为 {{{{{}}}}}（{} 字符）",
+                turn,
+                name,
+                reply.chars().count()
+              );
+              vars.set(name, reply);
             }
+            None => println!("第 {} 轮没有回答", turn),
           }
-          println!(" ");
         }
+        Err(e) => println!("{}", e),
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\copy") {
+      let turn = match arg.split_whitespace().collect::<Vec<_>>()[..] {
+        [] | ["raw"] => Some(notes::turn_count(&history)),
+        ["raw", n] => n.parse().ok(),
+        _ => None,
+      };
+      let Some(turn) = turn else {
+        println!("用法: \\copy raw [轮次]");
+        continue;
+      };
+      match history::turn_reply(&history, turn) {
+        Some(reply) => match copy_to_clipboard(&mut stdout, &clipboard, &reply) {
+          Ok(()) => println!(
+            "已复制第 {} 轮的回答原文（{} 字符）",
+            turn,
+            reply.chars().count()
+          ),
+          Err(e) => println!("[复制失败] {}", e),
+        },
+        None => println!("第 {} 轮没有回答", turn),
+      }
+      continue;
+    }
+    if let Some(name) = input.strip_prefix("\\unsetvar ") {
+      if !vars.remove(name.trim()) {
+        println!("没有变量 {}", name.trim());
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\set ") {
+      match arg.split_whitespace().collect::<Vec<_>>()[..] {
+        ["system", "none"] => {
+          no_system = true;
+          config.set(
+            "system",
+            "none",
+            config::Source::Runtime("\\set system none".to_string()),
+          );
+          println!("后续请求不再发送 system 消息");
+        }
+        ["system", "default"] => {
+          no_system = false;
+          config.set(
+            "system",
+            "default",
+            config::Source::Runtime("\\set system default".to_string()),
+          );
+          println!("后续请求恢复发送 system 消息");
+        }
+        _ => println!("用法: \\set system none|default"),
+      }
+      continue;
+    }
+    if input == "\\vars" {
+      if vars.is_empty() {
+        println!("暂无变量");
+      } else {
+        println!("{}", vars.render());
+      }
+      continue;
+    }
+    if input == "\\contract" || input.starts_with("\\contract ") {
+      match contract::parse_command(&input["\\contract".len()..]) {
+        Ok(contract::Command::Show) if contract.is_empty() => {
+          println!("暂无输出约定，\\contract add <规则> 添加")
+        }
+        Ok(contract::Command::Show) => println!("{}", contract.render()),
+        Ok(contract::Command::Add(rule)) => {
+          contract.add(rule);
+          println!("已添加第 {} 条输出约定", contract.rules().len());
+        }
+        Ok(contract::Command::Remove(n)) => match contract.remove(n) {
+          Some(rule) => println!("已删除: {}", rule),
+          None => println!("没有第 {} 条输出约定", n),
+        },
+        Ok(contract::Command::Clear) => {
+          contract.clear();
+          println!("已清空输出约定");
+        }
+        Err(e) => println!("{}", e),
+      }
+      continue;
+    }
+    if input == "\\checkpoints" {
+      let Some(cp) = &checkpointer else {
+        println!("无法确定数据目录，自动快照未启用");
+        continue;
+      };
+      let list = checkpoint::list_checkpoints(cp.dir())?;
+      if list.is_empty() {
+        println!("暂无自动快照");
+      }
+      for path in list.iter().rev() {
+        println!("\\load {}", path.display());
+      }
+      continue;
+    }
+    if let Some(command) = SessionCommand::parse(input) {
+      let Some(store) = &store else {
+        println!("无法确定数据目录，会话管理不可用");
+        continue;
+      };
+      if let Err(e) = run_session_command(store, command, &mut stdout) {
+        println!("[会话错误]: {}", e);
+      }
+      continue;
+    }
+    if input == "\\reload" {
+      let Some(current) = &persona else {
+        println!("未通过 --persona 加载人设文件");
+        continue;
+      };
+      match persona::Persona::load(&current.path) {
+        Ok(reloaded) => {
+          base_prompt = reloaded.prompt.clone();
+          system_prompt = prompt::SystemPrompt::new(&base_prompt, length);
+          println!(
+            "已重新加载 {}，后续对话使用新的 system prompt（提示缓存前缀会随之变化，历史保留）",
+            reloaded.path.display()
+          );
+          persona = Some(reloaded);
+        }
+        Err(e) => println!("[人设错误]: {:#}", e),
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\brief") {
+      length = match arg.trim() {
+        "on" => prompt::AnswerLength::Brief,
+        "off" => prompt::AnswerLength::Normal,
+        _ => {
+          println!("用法: \\brief on|off");
+          continue;
+        }
+      };
+      max_tokens = length.max_tokens(requested_max_tokens, models::registry().max_output(&model));
+      system_prompt = prompt::SystemPrompt::new(&base_prompt, length);
+      let command = config::Source::Runtime(format!("\\brief {}", arg.trim()));
+      config.set("length", length.name(), command.clone());
+      // 显式的 --max_tokens 不受 \brief 影响
+      if requested_max_tokens.is_none() {
+        config.set("max_tokens", max_tokens, command);
+      }
+      println!(
+        "简短模式: {}",
+        if length == prompt::AnswerLength::Brief {
+          "开"
+        } else {
+          "关"
+        }
+      );
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\render") {
+      render_markdown = match arg.trim() {
+        "on" => true,
+        "off" => false,
+        _ => {
+          println!("用法: \\render on|off");
+          continue;
+        }
+      };
+      let mode = match render_markdown {
+        true => markdown::RenderMode::Markdown,
+        false => markdown::RenderMode::Raw,
+      };
+      let command = config::Source::Runtime(format!("\\render {}", arg.trim()));
+      config.set("render", mode.name(), command);
+      println!(
+        "Markdown 渲染: {}",
+        if render_markdown { "开" } else { "关" }
+      );
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\reasoning") {
+      show_reasoning = match arg.trim() {
+        "on" => true,
+        "off" => false,
+        _ => {
+          println!("用法: \\reasoning on|off");
+          continue;
+        }
+      };
+      let command = config::Source::Runtime(format!("\\reasoning {}", arg.trim()));
+      config.set("show_reasoning", show_reasoning, command);
+      println!("显示推理过程: {}", if show_reasoning { "开" } else { "关" });
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\route") {
+      match (route::parse_command(arg), &route_model) {
+        (Err(e), _) => println!("{}", e),
+        (Ok(Some(_)), Err(e)) => println!("route_model 不可用: {}", e),
+        (Ok(threshold), _) => {
+          route_threshold = threshold;
+          let command = config::Source::Runtime(format!("\\route {}", arg.trim()));
+          config.set("auto_route", threshold.is_some(), command.clone());
+          match threshold {
+            Some(t) => {
+              config.set("route_threshold", t, command);
+              println!("自动路由: 开（阈值 {}）", t);
+            }
+            None => println!("自动路由: 关"),
+          }
+        }
+      }
+      continue;
+    }
+    if let Some(arg) = input.strip_prefix("\\goto ") {
+      // \goto <文件>:<行号>，从本地缓存的附件中显示上下文
+      let Some((file, line)) = cite::parse_location(arg) else {
+        println!("用法: \\goto <文件>:<行号>");
+        continue;
+      };
+      match attachments.find(file) {
+        Some(item) if item.numbered => {
+          let lines = cite::lookup(&item.data, line, cite::GOTO_CONTEXT);
+          if lines.is_empty() {
+            println!("{} 中没有第 {} 行（可能已被截断）", item.name, line);
+          }
+          for (n, text) in lines {
+            let marker = if n == line { ">" } else { " " };
+            println!("{}{:>5} | {}", marker, n, text);
+          }
+        }
+        Some(item) => println!("{} 附加时没有行号", item.name),
+        None => println!("没有附加过 {}", file),
+      }
+      continue;
+    }
+    // \as <名字> <问题>：本轮的用户消息带上说话人
+    let (speaker, input) = match input.strip_prefix("\\as ") {
+      Some(arg) => match history::parse_as(arg) {
+        Ok((name, rest)) => (Some(name.to_string()), rest),
         Err(e) => {
-          println!("[摘要API错误]: {}", e);
+          println!("{}", e);
+          continue;
+        }
+      },
+      None => (None, input),
+    };
+    // 开启自动路由时，开头的 @模型 指定本轮的模型；模型表里没有的名字连同问题原样发送
+    let (forced, input) = match route_threshold.and_then(|_| route::split_override(input)) {
+      Some((name, rest)) if models::registry().lookup(name).is_some() => {
+        match map_model(name, &resolved.profile) {
+          Ok(forced) => (Some(forced), rest),
+          Err(_) => (None, input),
         }
       }
-      // 用摘要替换历史
-      history.clear();
-      history.push(Message::Simple {
-        role: "user".to_string(),
-        content: format!("[历史摘要] {}", summary),
-      });
-      // 重新构造messages
-      messages = vec![Message::Simple {
-        role: "system".to_string(),
-        content: "You are a helpful assistant.".to_string(),
-      }];
-      messages.extend(history.iter().cloned());
+      _ => (None, input),
+    };
+    // \amend [轮次] [-e]：编辑某一轮的问题后重新发送，丢弃它之后的对话
+    let is_amend = input == "\\amend" || input.starts_with("\\amend ");
+    let amended = if is_amend {
+      match amend(
+        &mut history,
+        &mut notes,
+        &mut reasoning.borrow_mut(),
+        &input["\\amend".len()..],
+        editor.as_mut(),
+        &mut stdout,
+      ) {
+        Ok(Some(text)) => {
+          rolling.cancel();
+          Some(text)
+        }
+        Ok(None) => continue,
+        Err(e) => {
+          println!("[编辑错误]: {:#}", e);
+          continue;
+        }
+      }
+    } else {
+      None
+    };
+    let content = if let Some(text) = amended {
+      text
+    } else if let Some(arg) = input.strip_prefix("\\file ") {
+      // \file <路径> [问题]，路径为目录时附加其中所有没有被排除的文件
+      let (path, question) = arg.trim().split_once(' ').unwrap_or((arg.trim(), ""));
+      let path = std::path::Path::new(path);
+      if path.is_dir() {
+        let references =
+          match attach_dir(&mut attachments, path, &attach_rules, read_limits.max_bytes) {
+            Ok(references) => references,
+            Err(e) => {
+              println!("[文件错误]: {:#}", e);
+              continue;
+            }
+          };
+        if references.is_empty() {
+          println!("{} 中没有可以附加的文件", path.display());
+          continue;
+        }
+        let question = match question.trim() {
+          "" => "请分析这些文件",
+          question => question,
+        };
+        format!("{}\n\n{}", question, references.join("\n"))
+      } else {
+        let (hash, reused) = match attachments.attach(path) {
+          Ok(attached) => attached,
+          Err(e) => {
+            println!("[文件错误]: {:#}", e);
+            continue;
+          }
+        };
+        if reused {
+          eprintln!(
+            "[信息] {} 与之前附加的文件内容相同，请求中只保留最新的一份",
+            path.display()
+          );
+        }
+        if !reused && attachments.get(&hash).is_some_and(|a| a.summarized) {
+          eprintln!(
+            "[信息] {} 是表格文件，只发送表头、列统计和首尾几行；--full-table 发送完整内容",
+            path.display()
+          );
+        }
+        let question = if question.trim().is_empty() {
+          "请分析这个文件"
+        } else {
+          question.trim()
+        };
+        format!(
+          "{}\n\n{}",
+          question,
+          attachment::AttachmentStore::reference(&hash)
+        )
+      }
+    } else {
+      // 超长的粘贴内容（例如整份日志）先按预算截断
+      let content = truncate::truncate(input, attachment_budget, truncate_mode);
+      if content.len() != input.len() {
+        eprintln!(
+          "[信息] 输入约 {} tokens，超出预算 {}，已截断",
+          tokens::count(input),
+          attachment_budget
+        );
+      }
+      content
+    };
+    // 展开 {{变量}}；有未定义的变量时不发送
+    let expanded = match vars.expand(&content) {
+      Ok(expanded) => expanded,
+      Err(e) => {
+        println!("{}", e);
+        continue;
+      }
+    };
+    if expanded.len() != content.len() {
+      eprintln!(
+        "[信息] 已展开变量：{} → {} 字符（约 {} tokens）",
+        content.chars().count(),
+        expanded.chars().count(),
+        tokens::count(&expanded)
+      );
     }
-    // 自动续写主流程
-    let mut reply = String::new();
-    let mut auto_continue_count = 0;
-    loop {
+    let content = expanded;
+    // 与之前的问题几乎相同时先确认，不调用 API
+    let content = match dedupe && !is_amend {
+      true => match confirm_duplicate(&history, content, &vars, &mut stdout)? {
+        Some(content) => content,
+        None => continue,
+      },
+      false => content,
+    };
+    // 持续附加的文件在磁盘上改动过时重新读取
+    for notice in attachments.refresh_sticky() {
+      eprintln!("{}", notice);
+    }
+    let has_answer = history.iter().any(|m| export::role_of(m) == "assistant");
+    let route = route_threshold.map(|t| route::decide(&content, has_answer, t, forced.as_deref()));
+    let turn_model = match &route {
+      Some(route::Route::Cheap) => route_model.clone().unwrap_or_else(|_| model.clone()),
+      Some(route::Route::Forced(forced)) => forced.clone(),
+      Some(route::Route::Session) | None => model.clone(),
+    };
+    if let Some(route) = &route {
+      let why = match route {
+        route::Route::Session => "会话模型",
+        route::Route::Cheap => "简单追问",
+        route::Route::Forced(_) => "@ 指定",
+      };
+      eprintln!("[路由] 本轮由 {} 回答（{}）", turn_model, why);
+    }
+    // 显示附加的图片，确认附加的是哪一张
+    if !quiet {
+      for image in attachments.images_in(&content) {
+        let bytes = image.image_bytes().unwrap_or_default();
+        println!(
+          "{}",
+          thumbnail::render(image_protocol, &image.name, &bytes, image_rows)
+        );
+      }
+    }
+    // 添加到历史
+    history.push(Message::Simple {
+      role: "user".to_string(),
+      content,
+      name: speaker,
+    });
+    // 预算吃紧时换入后台已完成的滚动摘要
+    let max_input_tokens = models::registry().context_window(&model);
+    let used = estimate_messages_tokens(&attachments.expand(&history));
+    rolling
+      .apply_if_tight(&mut history, used, max_input_tokens)
+      .await;
+    // 构造带历史的消息
+    let request_prompt = system_prompt
+      .clone()
+      .with_contract(&contract)
+      .with_no_system(no_system);
+    let messages = request_prompt.messages(&history);
+    // 检查token数，超限则自动摘要
+    let total_tokens = estimate_messages_tokens(&attachments.expand(&messages));
+    // 只有这个问题时没有可以摘要的历史，直接交给下面的截断
+    if total_tokens > max_input_tokens && history.len() > 1 {
+      stats.summarizations += 1;
+      // 自动摘要历史；代码块和错误信息不交给模型改写，原样附在摘要之后
+      let (prose, snippets) = summary::split_verbatim(&messages);
+      let request = summary::summary_messages(&prose);
+      let prompt_tokens = estimate_messages_tokens(&request);
       print_green_prompt(&mut stdout);
       stdout.flush()?;
-      let mut last_reason = None;
-      // eprintln!("[DEBUG] max_tokens: {}", max_tokens);
-      match client
-        .call_api_with_history_stream(
-          &model,
-          messages.clone(),
-          temperature,
-          Some(max_tokens),
-          false,
-        )
-        .await
-      {
-        Ok(mut stream) => {
-          while let Some(chunk) = stream.next().await {
-            match chunk {
-              Ok((s, reason)) => {
-                print!("{}", s);
-                stdout.flush()?;
-                reply.push_str(&s);
-                // if let Some(ref r) = reason {
-                //   eprintln!("[DEBUG] finish_reason: {}", r);
-                // }
-                if reason.is_some() {
-                  last_reason = reason;
+      let mut summary = String::new();
+      let mut failed = false;
+      let started = Instant::now();
+      let mut first_token = None;
+      if prompt_tokens > max_input_tokens {
+        // 历史本身放不进一次摘要请求：按轮次分段并发摘要，再合并
+        eprintln!(
+          "[信息] 待摘要的历史约 {} tokens，超出单次请求上限，分段摘要",
+          prompt_tokens
+        );
+        match summary::summarize_history(&client, &model, &prose, max_input_tokens).await {
+          Ok(text) => {
+            println!("{}", text);
+            summary = text;
+          }
+          Err(e) => {
+            println!("[摘要API错误]: {:#}", e);
+            failed = true;
+          }
+        }
+      } else {
+        match client
+          .call_api_with_history_stream(
+            &model,
+            request,
+            temperature,
+            Some(summary::SUMMARY_MAX_TOKENS),
+            false,
+          )
+          .await
+        {
+          Ok(mut stream) => {
+            while let Some(chunk) = stream.next().await {
+              match chunk {
+                Ok(chunk) => {
+                  let s = chunk.content;
+                  if first_token.is_none() && !s.is_empty() {
+                    first_token = Some(started.elapsed());
+                  }
+                  if let Some(usage) = chunk.usage {
+                    stats.record_usage(usage);
+                  }
+                  print!("{}", s);
+                  stdout.flush()?;
+                  summary.push_str(&s);
+                }
+                Err(e) => {
+                  eprintln!("[摘要API流错误]: {}", e);
+                  failed = true;
+                  break;
                 }
-              }
-              Err(e) => {
-                eprintln!("[API流错误]: {}", e);
-                break;
               }
             }
+            println!(" ");
+          }
+          Err(e) => {
+            println!("[摘要API错误]: {}", e);
+            failed = true;
           }
-          println!(" ");
-        }
-        Err(e) => {
-          println!("[API错误]: {}", e);
-          break;
         }
       }
-      history.push(Message::Simple {
-        role: "assistant".to_string(),
-        content: reply.clone(),
-      });
+      stats.record_request(
+        prompt_tokens,
+        tokens::count(&summary),
+        first_token,
+        started.elapsed(),
+      );
+      // 用摘要替换历史；摘要失败时不丢掉对话，保留原来的历史
+      let summary = (!failed).then_some(summary.as_str());
+      if !summary::replace_history(&mut history, summary, &snippets) {
+        eprintln!("[警告] 摘要失败，保留原来的历史");
+      }
+    }
+    // 单条消息本身就超出预算（例如粘贴的大段日志）时截断它的内容，请求仍然可以发送
+    let overhead =
+      estimate_messages_tokens(&attachments.expand(&request_prompt.messages(&history)))
+        .saturating_sub(estimate_messages_tokens(&history));
+    if let Some(cut) = truncate::fit_messages(
+      &mut history,
+      max_input_tokens.saturating_sub(overhead),
+      truncate_mode,
+    ) {
+      stats.truncations += 1;
+      eprintln!(
+        "[信息] 第 {} 条消息约 {} tokens，超出上下文上限，已截断为约 {} tokens",
+        cut.index + 1,
+        cut.before,
+        cut.after
+      );
+    }
+    // 自动续写主流程
+    let settings = turn::TurnSettings {
+      model: &turn_model,
+      system_prompt: &request_prompt,
+      temperature,
+      max_tokens: max_tokens.min(models::registry().max_output(&turn_model)),
+      model_max_tokens: models::registry().max_output(&turn_model),
+      timeout: matches.get_one::<Duration>("turn_timeout").copied(),
+      attachments: Some(&attachments),
+      highlight_citations: stdout.is_terminal() && !ui.is_accessible(),
+      render_math,
+      render_markdown,
+      max_words: matches.get_one::<usize>("max_words").copied(),
+      stop: stop_patterns.as_ref(),
+      ui,
+      steer: interrupt.as_deref().map(|interrupt| steer::Steer {
+        interrupt,
+        ask: &ask_note,
+      }),
+      checks: contract.checks(),
+      reasoning: Some(&reasoning),
+      show_reasoning,
+      tools: Some(&plugins),
+      recovery: in_progress.as_ref(),
+      keep_refusals,
+    };
+    let mut out = flush::FlushWriter::new(&mut stdout, flush_interval, flush::is_remote());
+    #[cfg(feature = "otlp")]
+    let trace = tracer
+      .as_ref()
+      .map(|tracer| (tracer.start_turn(&turn_model), stats.clone()));
+    let warmed = warmup.settle(&mut stats).await == Some(warm::Outcome::Warmed);
+    let usage_before = (stats.prompt_tokens, stats.completion_tokens);
+    let end = turn::run_turn(&client, &settings, &mut history, &mut stats, &mut out).await;
+    #[cfg(feature = "otlp")]
+    if let (Some(tracer), Some((span, before))) = (&tracer, trace) {
+      tracer.finish_turn(span, otlp::TurnRecord::from_turn(&end, &before, &stats));
+    }
+    let end = end?;
+    if turn_model != model {
+      let prompt_tokens = stats.prompt_tokens - usage_before.0;
+      let completion_tokens = stats.completion_tokens - usage_before.1;
+      stats.record_routed(&turn_model, prompt_tokens, completion_tokens);
+    }
+    out.flush_now()?;
+    stats.flushes += out.flushes();
+    drop(out);
+    if end == turn::TurnEnd::RolledBack {
+      // 历史回到提问之前，不计轮数也不写快照
+      eprintln!("[信息] 本轮未完成，已撤销（历史中不保留这个问题），可以重新发送");
+      continue;
+    }
+    if end == turn::TurnEnd::Filtered && !keep_refusals {
+      continue;
+    }
+    stats.turns += 1;
+    // 第一轮的首字延迟按是否预热记录下来，\stats 中比较
+    if stats.turns == 1
+      && turn_model == model
+      && warm::applies(&model)
+      && let (Some(ttft), Some(path)) = (stats.first_ttft, &warm_log_path)
+    {
+      let mut log = warm::Log::load(path);
+      log.record(warmed, ttft);
+      if let Err(e) = log.save(path) {
+        eprintln!("[警告] {:#}", e);
+      }
+    }
+    if copy_on_complete
+      && clipboard.is_ok()
+      && let Some(reply) = history::turn_reply(&history, notes::turn_count(&history))
+      && let Err(e) = copy_to_clipboard(&mut stdout, &clipboard, &reply)
+    {
+      eprintln!("[警告] {}", e);
+    }
+    last_review = None;
+    if auto_verify
+      && let Ok(verify_model) = &route_model
+      && let Some(exchange) = verify::last_exchange(&history)
+      && verify::worth_checking(&exchange.1)
+    {
+      last_review = review(
+        &summarizer,
+        verify_model,
+        &verify_prompt,
+        exchange,
+        &history,
+        &mut stats,
+        ui,
+      )
+      .await;
+    }
+    let used = estimate_messages_tokens(&attachments.expand(&history));
+    let max_input = models::registry().context_window(&model);
+    rolling.maybe_start(&summarizer, &model, &history, used, max_input);
+    if !quiet
+      && stdout.is_terminal()
+      && let Some(bar) = widget::context_bar(used, max_input, widget::terminal_width(), ui)
+    {
+      println!("{}", bar);
+    }
+    if let Some(cp) = checkpointer.as_mut() {
+      match cp.record_turn(&history) {
+        Ok(Some(path)) => eprintln!("[快照] {}", path.display()),
+        Ok(None) => {}
+        Err(e) => eprintln!("[快照错误]: {:#}", e),
+      }
+    }
+  }
+  #[cfg(feature = "otlp")]
+  flush_traces(tracer.as_deref(), quiet).await;
+  Ok(())
+}
 
-      // 检查是否需要自动续写
-      let should_continue = if let Some(reason) = last_reason.as_deref() {
-        // eprintln!("[DEBUG] Detected finish_reason: {}", reason);
-        reason == "length"
-      } else {
-        // 如果没有finish_reason，检查回复是否看起来被截断了
-        let trimmed = reply.trim();
-        trimmed.ends_with("（")
-          || trimmed.ends_with("、")
-          || trimmed.ends_with("，")
-          || trimmed.ends_with("：")
-          || trimmed.ends_with("-")
-          || trimmed.ends_with("**")
-          || (trimmed.len() > 100
-            && !trimmed.ends_with("。")
-            && !trimmed.ends_with("！")
-            && !trimmed.ends_with("？"))
-      };
+/// 用 OSC 52 把回答原文写入剪贴板
+fn copy_to_clipboard(
+  stdout: &mut io::Stdout,
+  clipboard: &Result<clipboard::Terminal, String>,
+  text: &str,
+) -> Result<(), String> {
+  let terminal = clipboard.clone()?;
+  let sequence = clipboard::sequence(text, terminal)?;
+  stdout
+    .write_all(sequence.as_bytes())
+    .and_then(|()| stdout.flush())
+    .map_err(|e| e.to_string())
+}
 
-      if should_continue && auto_continue_count < MAX_AUTO_CONTINUE {
-        auto_continue_count += 1;
-        // eprintln!(
-        //   "[DEBUG] Auto-continuing (attempt {}/{})",
-        //   auto_continue_count, MAX_AUTO_CONTINUE
-        // );
-        history.push(Message::Simple {
-          role: "user".to_string(),
-          content: "请继续".to_string(),
-        });
-        messages = vec![Message::Simple {
-          role: "system".to_string(),
-          content: "You are a helpful assistant.".to_string(),
-        }];
-        messages.extend(history.iter().cloned());
-        reply.clear();
-        continue;
+/// 显示上次中断的回答并询问是否续写，返回开始时的历史和是否续写
+fn resume_partial(
+  recovered: &recovery::Recovered,
+  stdout: &mut io::Stdout,
+) -> Result<(Vec<Message>, bool)> {
+  let block = &recovered.in_progress;
+  println!(
+    "[恢复] 上次中断的回答（{}，开始于 {}，{} 字）",
+    block.model,
+    block.started_at,
+    recovered.partial.chars().count()
+  );
+  let ask = |stdout: &mut io::Stdout, question: &str| -> Result<String> {
+    print!("{}", question);
+    stdout.flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_lowercase())
+  };
+  if ask(stdout, "显示部分回答？[Y/n] ")? != "n" {
+    println!("{}", recovered.partial);
+  }
+  match ask(stdout, "从中断处继续生成？[y/N] ")?.as_str() {
+    "y" => Ok((recovered.continuation(), true)),
+    _ => Ok((recovered.interrupted(), false)),
+  }
+}
+
+/// 退出前发送剩余的 span，收集器不可达时最多等待 FLUSH_TIMEOUT
+#[cfg(feature = "otlp")]
+async fn flush_traces(tracer: Option<&otlp::Exporter>, quiet: bool) {
+  let Some(tracer) = tracer else {
+    return;
+  };
+  let failed = tracer.flush(otlp::FLUSH_TIMEOUT).await;
+  if failed > 0 && !quiet {
+    eprintln!("[警告] {} 批 trace span 未能发送到 OTLP 收集器", failed);
+  }
+}
+
+enum SessionCommand<'a> {
+  List,
+  Rename(&'a str, &'a str),
+  Describe(&'a str, &'a str),
+  Delete(&'a str),
+}
+
+impl<'a> SessionCommand<'a> {
+  fn parse(input: &'a str) -> Option<Self> {
+    let (command, rest) = input.split_once(' ').unwrap_or((input, ""));
+    let rest = rest.trim();
+    match command {
+      "\\sessions" => Some(SessionCommand::List),
+      "\\rename" => {
+        let (old, new) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        Some(SessionCommand::Rename(old, new.trim()))
+      }
+      "\\describe" => {
+        let (name, text) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        Some(SessionCommand::Describe(name, text.trim()))
+      }
+      "\\delete" => Some(SessionCommand::Delete(rest)),
+      _ => None,
+    }
+  }
+}
+
+/// 记下新打开的会话，释放之前打开的另一个会话的锁
+fn switch_session(store: &session::SessionStore, open: &mut Option<String>, name: &str) {
+  if let Some(previous) = open.replace(name.to_string())
+    && previous != name
+  {
+    store.close(&previous);
+  }
+}
+
+fn run_session_command(
+  store: &session::SessionStore,
+  command: SessionCommand,
+  stdout: &mut io::Stdout,
+) -> Result<()> {
+  match command {
+    SessionCommand::List => {
+      let sessions = store.list()?;
+      if sessions.is_empty() {
+        println!("暂无保存的会话");
+      }
+      for (name, meta) in sessions {
+        let model = if meta.model.is_empty() {
+          String::new()
+        } else {
+          format!(" [{}]", meta.model)
+        };
+        println!(
+          "{}  {}{}  更新于 {}",
+          name,
+          meta.title,
+          model,
+          meta.updated_at.format("%Y-%m-%d %H:%M")
+        );
+        if !meta.description.is_empty() {
+          println!("    {}", meta.description);
+        }
+      }
+    }
+    SessionCommand::Rename(old, new) => {
+      if old.is_empty() || new.is_empty() {
+        println!("用法: \\rename <旧名称> <新名称>");
+        return Ok(());
+      }
+      store.rename(old, new)?;
+      println!("已将会话 {} 重命名为 {}", old, new);
+    }
+    SessionCommand::Describe(name, text) => {
+      if name.is_empty() {
+        println!("用法: \\describe <名称> <描述>");
+        return Ok(());
+      }
+      store.describe(name, text)?;
+      println!("已更新会话 {} 的描述", name);
+    }
+    SessionCommand::Delete(name) => {
+      if name.is_empty() {
+        println!("用法: \\delete <名称>");
+        return Ok(());
+      }
+      store.load(name)?;
+      print!("确认删除会话 {}？[y/N] ", name);
+      stdout.flush()?;
+      let mut answer = String::new();
+      io::stdin().read_line(&mut answer)?;
+      if answer.trim().eq_ignore_ascii_case("y") {
+        store.delete(name)?;
+        println!("已删除会话 {}", name);
+      } else {
+        println!("已取消");
       }
-      break;
     }
   }
   Ok(())
 }
 
+/// 记录到会话环境信息中的生效设置
+fn effective_settings(
+  matches: &clap::ArgMatches,
+  file: &config::FileConfig,
+  temperature: Option<f32>,
+  max_tokens: u32,
+  length: prompt::AnswerLength,
+  truncate_mode: truncate::TruncateMode,
+) -> Vec<(&'static str, String)> {
+  let mut settings = vec![
+    (
+      "temperature",
+      temperature.map_or_else(|| "default".to_string(), |t| t.to_string()),
+    ),
+    ("max_tokens", max_tokens.to_string()),
+    ("length", format!("{:?}", length).to_lowercase()),
+    ("truncate", format!("{:?}", truncate_mode).to_lowercase()),
+    (
+      "isolated",
+      (matches.get_flag("isolated") || file.isolated.unwrap_or(false)).to_string(),
+    ),
+    (
+      "checkpoint_every",
+      config::flag_or_file(matches, "checkpoint_every", file.checkpoint_every).to_string(),
+    ),
+    (
+      "checkpoint_keep",
+      matches
+        .get_one::<usize>("checkpoint_keep")
+        .unwrap()
+        .to_string(),
+    ),
+  ];
+  if let Some(effort) = matches.get_one::<api::ReasoningEffort>("reasoning_effort") {
+    settings.push(("reasoning_effort", format!("{:?}", effort).to_lowercase()));
+  }
+  if let Some(path) = matches.get_one::<std::path::PathBuf>("persona") {
+    settings.push(("persona", path.display().to_string()));
+  }
+  if let Some(timeout) = matches.get_one::<Duration>("turn_timeout") {
+    settings.push(("turn_timeout", format!("{}s", timeout.as_secs_f64())));
+  }
+  settings
+}
+
+/// 附加目录中选出的文件，显示统计，返回各文件的引用；无法读取的文件（如二进制文件）跳过
+fn attach_dir(
+  attachments: &mut attachment::AttachmentStore,
+  dir: &std::path::Path,
+  rules: &walk::Rules,
+  max_bytes: u64,
+) -> Result<Vec<String>> {
+  let selection = walk::select(dir, rules, max_bytes)?;
+  let mut references = vec![];
+  let mut unreadable = 0;
+  for file in &selection.files {
+    let name = file.strip_prefix(dir).unwrap_or(file).display().to_string();
+    match attachments.attach_named(file, Some(name)) {
+      Ok((hash, _)) => references.push(attachment::AttachmentStore::reference(&hash)),
+      Err(_) => unreadable += 1,
+    }
+  }
+  eprintln!("[信息] {}", selection.report(dir, unreadable));
+  Ok(references)
+}
+
+/// 检查自定义地址是不是 OpenAI 兼容的对话接口；少了 `/v1` 时改正并记在缓存中，之后直接使用。
+/// 连接失败交给之后真正的请求报告
+async fn check_compat(client: ApiClient, model: &str, quiet: bool) -> Result<ApiClient> {
+  let cache_path = paths::cache_dir().map(|dir| dir.join(compat::CACHE_FILE));
+  let mut cache = cache_path
+    .as_deref()
+    .map(compat::Cache::load)
+    .unwrap_or_default();
+  let original = client.base_url().to_string();
+  let client = match cache.correction(&original) {
+    Some(corrected) => {
+      if !quiet {
+        eprintln!("[信息] 使用之前改正的地址 {}", corrected);
+      }
+      client.with_base_url(corrected)
+    }
+    None => client,
+  };
+  let Ok(response) = client.probe_shape(model).await else {
+    return Ok(client);
+  };
+  let shape = compat::classify(client.base_url(), &response);
+  if shape == compat::Shape::NeedsV1
+    && let Some(corrected) = compat::with_v1(client.base_url())
+  {
+    let retry = client.clone().with_base_url(&corrected);
+    if let Ok(response) = retry.probe_shape(model).await
+      && compat::classify(&corrected, &response) == compat::Shape::Compatible
+    {
+      cache.remember(&original, &corrected);
+      if let Some(path) = &cache_path
+        && let Err(e) = cache.save(path)
+      {
+        eprintln!("[警告] {:#}", e);
+      }
+      if !quiet {
+        eprintln!(
+          "[信息] {} 没有对话接口，已改用 {}（已记住）",
+          original, corrected
+        );
+      }
+      return Ok(retry);
+    }
+  }
+  match shape {
+    compat::Shape::Compatible => Ok(client),
+    compat::Shape::Unknown(detail) => {
+      eprintln!(
+        "[警告] 无法确认 {} 是 OpenAI 兼容的接口（{}），请求失败时请检查 --base-url",
+        client.base_url(),
+        detail
+      );
+      Ok(client)
+    }
+    shape => anyhow::bail!(compat::explain(&shape, client.base_url())),
+  }
+}
+
+/// 复核最后一轮并打印意见；返回轮次和意见，失败时打印警告
+async fn review(
+  backend: &ApiClient,
+  model: &str,
+  template: &str,
+  (question, answer): (String, String),
+  history: &[Message],
+  stats: &mut stats::SessionStats,
+  ui: widget::UiMode,
+) -> Option<(usize, String)> {
+  match verify::run(backend, model, template, (&question, &answer), stats).await {
+    Ok(text) => {
+      println!("{}", verify::render(&text, !ui.is_accessible()));
+      Some((notes::turn_count(history), text))
+    }
+    Err(e) => {
+      eprintln!("[警告] 复核失败: {:#}", e);
+      None
+    }
+  }
+}
+
+/// 修改历史后显示新的上下文占用
+fn print_context_bar(messages: &[Message], model: &str, ui: widget::UiMode) {
+  let used = estimate_messages_tokens(messages);
+  if let Some(bar) = widget::context_bar(
+    used,
+    models::registry().context_window(model),
+    widget::terminal_width(),
+    ui,
+  ) {
+    println!("{}", bar);
+  }
+}
+
+/// 在后台线程中逐行读入问题，期间的 Ctrl-C 第一次只提示，连按两次返回 `\q`。
+/// 退出时读入线程还阻塞在 stdin 上，所以用普通线程：spawn_blocking 的任务会拖住运行时的退出
+async fn read_prompt(interrupt: &steer::Interrupt, prompt: bool) -> io::Result<String> {
+  let (tx, mut rx) = tokio::sync::oneshot::channel();
+  std::thread::spawn(move || {
+    let _ = tx.send(multiline::read(
+      &mut io::stdin().lock(),
+      &mut io::stdout(),
+      prompt,
+    ));
+  });
+  interrupt.set_prompting(true);
+  let mut armed = false;
+  let input = loop {
+    tokio::select! {
+      input = &mut rx => break input.unwrap_or_else(|_| Err(io::Error::other("input thread stopped"))),
+      () = interrupt.wait_quit() => {
+        if armed {
+          break Ok("\\q".to_string());
+        }
+        armed = true;
+        let mut stdout = io::stdout();
+        println!("\n再按一次 Ctrl+C 退出");
+        print_red_prompt(&mut stdout);
+        stdout.flush()?;
+      }
+    }
+  };
+  interrupt.set_prompting(false);
+  input
+}
+
 fn print_red_prompt(stdout: &mut io::Stdout) {
   let _ = crossterm::queue!(
     stdout,
     SetForegroundColor(Color::Red),
-    Print("> "),
+    Print(transcript::PROMPT),
     ResetColor
   );
 }
 
-fn print_green_prompt(stdout: &mut io::Stdout) {
+/// 准备重新发送第 n 轮（默认最近一轮）：确认、编辑，然后回退历史。返回 None 表示取消
+fn amend(
+  history: &mut Vec<Message>,
+  notes: &mut notes::Notes,
+  reasoning: &mut reasoning::Reasoning,
+  arg: &str,
+  line_editor: Option<&mut editor::Editor>,
+  stdout: &mut io::Stdout,
+) -> Result<Option<String>> {
+  let mut use_editor = false;
+  let mut turn = None;
+  for token in arg.split_whitespace() {
+    match (token, token.parse::<usize>()) {
+      ("-e", _) => use_editor = true,
+      (_, Ok(n)) => turn = Some(n),
+      _ => {
+        println!("用法: \\amend [轮次] [-e]");
+        return Ok(None);
+      }
+    }
+  }
+  let turns = notes::turn_count(history);
+  let turn = turn.unwrap_or(turns);
+  let Some(plan) = history::plan_rollback(history, turn) else {
+    println!("没有第 {} 轮，当前共 {} 轮", turn, turns);
+    return Ok(None);
+  };
+  if plan.dropped > 0 {
+    print!(
+      "将丢弃第 {} 轮之后的 {} 轮对话，确认？[y/N] ",
+      turn, plan.dropped
+    );
+    stdout.flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if !answer.trim().eq_ignore_ascii_case("y") {
+      println!("已取消");
+      return Ok(None);
+    }
+  }
+  let edited = if use_editor {
+    edit_in_editor(&plan.original)?
+  } else if let Some(line_editor) = line_editor {
+    match line_editor.read_with_initial("新问题: ", &plan.original, stdout)? {
+      Some(edited) => edited,
+      None => {
+        println!("已取消");
+        return Ok(None);
+      }
+    }
+  } else {
+    println!("原问题: {}", plan.original);
+    print!("新问题（直接回车保持不变）: ");
+    stdout.flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    match line.trim() {
+      "" => plan.original,
+      line => line.to_string(),
+    }
+  };
+  if edited.trim().is_empty() {
+    println!("问题为空，已取消");
+    return Ok(None);
+  }
+  history::rollback(history, turn);
+  notes.truncate(turn);
+  reasoning.truncate(turn);
+  Ok(Some(edited))
+}
+
+/// 新问题与之前某一轮几乎相同时显示当时的回答并询问；返回要发送的内容，None 表示不发送
+fn confirm_duplicate(
+  history: &[Message],
+  content: String,
+  vars: &vars::Vars,
+  stdout: &mut io::Stdout,
+) -> Result<Option<String>> {
+  let Some(duplicate) = dedupe::find(history, &content) else {
+    return Ok(Some(content));
+  };
+  let answer = history::turn_reply(history, duplicate.turn).unwrap_or_default();
+  println!(
+    "[提示] 第 {} 轮问过几乎相同的问题（相似度 {:.0}%），当时的回答：",
+    duplicate.turn,
+    duplicate.score * 100.0
+  );
+  let preview: Vec<_> = answer.lines().take(dedupe::PREVIEW_LINES).collect();
+  match widget::mode().is_accessible() {
+    true => println!("{}", preview.join("\n")),
+    false => println!("{}", preview.join("\n").dim()),
+  }
+  print!("回车仍然发送，s 查看完整回答，e 修改问题，n 取消: ");
+  stdout.flush()?;
+  let mut choice = String::new();
+  io::stdin().read_line(&mut choice)?;
+  match choice.trim().to_ascii_lowercase().as_str() {
+    "s" => {
+      println!("{}", answer);
+      Ok(None)
+    }
+    "n" => Ok(None),
+    "e" => {
+      print!("新问题: ");
+      stdout.flush()?;
+      let mut line = String::new();
+      io::stdin().read_line(&mut line)?;
+      if line.trim().is_empty() {
+        println!("问题为空，已取消");
+        return Ok(None);
+      }
+      match vars.expand(line.trim()) {
+        Ok(text) => Ok(Some(text)),
+        Err(e) => {
+          println!("{}", e);
+          Ok(None)
+        }
+      }
+    }
+    _ => Ok(Some(content)),
+  }
+}
+
+/// 用 $VISUAL / $EDITOR（默认 vi）编辑文本
+fn edit_in_editor(initial: &str) -> Result<String> {
+  let path = env::temp_dir().join(format!("deepcli-amend-{}.md", std::process::id()));
+  std::fs::write(&path, initial).context(format!("Failed to write {:?}", path))?;
+  let editor = env::var("VISUAL")
+    .or_else(|_| env::var("EDITOR"))
+    .unwrap_or_else(|_| "vi".to_string());
+  // 编辑器可以带参数，例如 "code --wait"
+  let mut parts = editor.split_whitespace();
+  let status = std::process::Command::new(parts.next().unwrap_or("vi"))
+    .args(parts)
+    .arg(&path)
+    .status()
+    .context(format!("Failed to start editor {}", editor))?;
+  let text = std::fs::read_to_string(&path);
+  let _ = std::fs::remove_file(&path);
+  if !status.success() {
+    anyhow::bail!("Editor {} exited with {}", editor, status);
+  }
+  Ok(text?.trim_end().to_string())
+}
+
+/// 导入对话文件作为当前历史，并报告识别出的格式
+fn import_history(path: &std::path::Path) -> Result<Vec<Message>> {
+  let imported = history::import(path)?;
+  eprintln!(
+    "[信息] 已从 {} 导入 {} 条消息（{}）",
+    path.display(),
+    imported.messages.len(),
+    imported.format
+  );
+  if imported.skipped > 0 {
+    eprintln!("[警告] 跳过了 {} 条无法识别角色的消息", imported.skipped);
+  }
+  Ok(imported.messages)
+}
+
+fn print_green_prompt(stdout: &mut impl Write) {
   let _ = crossterm::queue!(
     stdout,
     SetForegroundColor(Color::Green),
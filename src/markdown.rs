@@ -0,0 +1,569 @@
+//! 在终端中渲染回答的 Markdown：标题加粗，列表换成圆点，引用加竖线，表格按列对齐，
+//! 行内的 **粗体**、*斜体*、~~删除线~~、`代码` 和链接换成 ANSI 样式。
+//! 流式输出时按整行渲染；表格缓冲到最后一行才输出，代码块内的行原样输出，
+//! 围栏的状态跨分片保留，代码块不会被当作正文渲染。只影响显示，历史中保存原文
+
+use crate::widget::{is_separator, table_cells};
+use crate::width::{display_width, wrap};
+use crossterm::style::{Attribute, Color, SetForegroundColor};
+
+/// 分隔线的宽度
+const RULE_WIDTH: usize = 40;
+
+/// 回答的显示方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+  Markdown,
+  /// 原样输出
+  Raw,
+}
+
+impl std::str::FromStr for RenderMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "markdown" => Ok(Self::Markdown),
+      "raw" => Ok(Self::Raw),
+      _ => Err("Render mode must be markdown or raw".to_string()),
+    }
+  }
+}
+
+impl RenderMode {
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Markdown => "markdown",
+      Self::Raw => "raw",
+    }
+  }
+}
+
+/// 完整文本的渲染结果，用于非流式输出
+pub fn render(text: &str) -> String {
+  let mut stream = MarkdownStream::default();
+  stream.push(text) + &stream.finish()
+}
+
+#[derive(Debug, Default)]
+pub struct MarkdownStream {
+  /// 还没有换行的部分
+  line: String,
+  /// 在代码块中时为开头的围栏（``` 或 ~~~，至少三个）
+  fence: Option<String>,
+  /// 还在继续的表格的原文行
+  table: Vec<String>,
+  /// 引用按这个宽度折行，每一行都带竖线；没有时不折行
+  width: Option<usize>,
+}
+
+impl MarkdownStream {
+  pub fn with_width(mut self, width: usize) -> Self {
+    self.width = Some(width);
+    self
+  }
+
+  pub fn push(&mut self, chunk: &str) -> String {
+    self.line.push_str(chunk);
+    let mut out = String::new();
+    while let Some(end) = self.line.find('\n') {
+      let line: String = self.line.drain(..=end).collect();
+      out.push_str(&self.render_line(line.trim_end_matches(['\n', '\r'])));
+    }
+    out
+  }
+
+  pub fn finish(&mut self) -> String {
+    let mut out = match self.line.is_empty() {
+      true => String::new(),
+      false => {
+        let line = std::mem::take(&mut self.line);
+        self.render_line(&line)
+      }
+    };
+    out.push_str(&self.flush_table());
+    if self.fence.take().is_some() {
+      out.push_str(&format!("{}\n", dim("└─")));
+    }
+    out
+  }
+
+  fn render_line(&mut self, line: &str) -> String {
+    let trimmed = line.trim_start();
+    if let Some(fence) = &self.fence {
+      // 结束围栏不短于开头的围栏，后面不能有其他内容
+      let run = fence_run(trimmed);
+      if run.starts_with(fence.as_str()) && trimmed[run.len()..].trim().is_empty() {
+        self.fence = None;
+        return format!("{}\n", dim("└─"));
+      }
+      return format!("{}\n", code(line));
+    }
+    let run = fence_run(trimmed);
+    if run.len() >= 3 {
+      let mut out = self.flush_table();
+      let lang = trimmed[run.len()..].trim();
+      self.fence = Some(run.to_string());
+      out.push_str(&match lang.is_empty() {
+        true => format!("{}\n", dim("┌─")),
+        false => format!("{}\n", dim(&format!("┌─ {}", lang))),
+      });
+      return out;
+    }
+    if table_cells(line).is_some() {
+      self.table.push(line.to_string());
+      return String::new();
+    }
+    let mut out = self.flush_table();
+    // 只折行引用，其余的行交给终端自己折
+    let width = self.width.filter(|_| trimmed.starts_with('>'));
+    out.push_str(&block(line, width));
+    out.push('\n');
+    out
+  }
+
+  /// 第二行是分隔行时按列对齐输出，否则逐行按正文输出
+  fn flush_table(&mut self) -> String {
+    let lines = std::mem::take(&mut self.table);
+    let rows: Vec<Vec<String>> = lines.iter().filter_map(|l| table_cells(l)).collect();
+    if rows.len() < 2 || !is_separator(&rows[1]) {
+      return lines.iter().map(|l| block(l, None) + "\n").collect();
+    }
+    let aligns: Vec<Align> = rows[1].iter().map(|c| Align::of(c)).collect();
+    let cells: Vec<Vec<String>> = rows
+      .iter()
+      .enumerate()
+      .filter(|&(i, _)| i != 1)
+      .map(|(_, row)| row.iter().map(|c| inline(c)).collect())
+      .collect();
+    let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+      .map(|i| {
+        cells
+          .iter()
+          .filter_map(|row| row.get(i))
+          .map(|c| display_width(&strip(c)))
+          .max()
+          .unwrap_or(0)
+      })
+      .collect();
+    let mut out = String::new();
+    for (n, row) in cells.iter().enumerate() {
+      let line: Vec<String> = (0..columns)
+        .map(|i| {
+          let cell = row.get(i).map_or("", String::as_str);
+          let align = aligns.get(i).copied().unwrap_or(Align::Left);
+          let padded = align.pad(cell, widths[i], i + 1 == columns);
+          match n {
+            0 => bold(&padded),
+            _ => padded,
+          }
+        })
+        .collect();
+      out.push_str(&line.join(&dim(" │ ")));
+      out.push('\n');
+      if n == 0 {
+        let rule: Vec<String> = widths.iter().map(|&w| "─".repeat(w)).collect();
+        out.push_str(&dim(&rule.join("─┼─")));
+        out.push('\n');
+      }
+    }
+    out
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+  Left,
+  Center,
+  Right,
+}
+
+impl Align {
+  fn of(separator: &str) -> Self {
+    match (separator.starts_with(':'), separator.ends_with(':')) {
+      (true, true) => Self::Center,
+      (false, true) => Self::Right,
+      _ => Self::Left,
+    }
+  }
+
+  /// 最后一列不补右侧的空格
+  fn pad(self, cell: &str, width: usize, last: bool) -> String {
+    let space = width.saturating_sub(display_width(&strip(cell)));
+    let (left, right) = match self {
+      Self::Left => (0, space),
+      Self::Right => (space, 0),
+      Self::Center => (space / 2, space - space / 2),
+    };
+    let right = if last { 0 } else { right };
+    format!("{}{}{}", " ".repeat(left), cell, " ".repeat(right))
+  }
+}
+
+/// 行首的 ``` 或 ~~~
+fn fence_run(line: &str) -> &str {
+  let Some(first) = line.chars().next().filter(|c| matches!(c, '`' | '~')) else {
+    return "";
+  };
+  let end = line.find(|c| c != first).unwrap_or(line.len());
+  &line[..end]
+}
+
+/// 块级元素：标题、分隔线、引用和列表，其余按正文渲染行内样式。
+/// 有 `width` 时正文和列表项按原文折行，结果可能含多行
+fn block(line: &str, width: Option<usize>) -> String {
+  let trimmed = line.trim_start();
+  let indent = &line[..line.len() - trimmed.len()];
+  if is_rule(trimmed) {
+    return dim(&"─".repeat(RULE_WIDTH));
+  }
+  let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+  if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+    let text = trimmed[hashes..].trim().trim_end_matches('#').trim_end();
+    let text = inline(text);
+    return match hashes {
+      1 | 2 => format!(
+        "{}{}{}{}",
+        Attribute::Bold,
+        Attribute::Underlined,
+        text,
+        Attribute::Reset
+      ),
+      _ => format!("{}{}{}", Attribute::Bold, text, Attribute::Reset),
+    };
+  }
+  if let Some(rest) = trimmed.strip_prefix('>') {
+    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+    let bar = format!("{}{}", indent, dim("│ "));
+    let width = width.map(|w| w.saturating_sub(display_width(indent) + 2));
+    let lines: Vec<String> = block(rest, width)
+      .split('\n')
+      .map(|l| format!("{}{}", bar, l))
+      .collect();
+    return lines.join("\n");
+  }
+  if let Some(rest) = ["- ", "* ", "+ "]
+    .iter()
+    .find_map(|marker| trimmed.strip_prefix(marker))
+  {
+    let bullet = match indent.len() / 2 {
+      0 => "•",
+      1 => "◦",
+      _ => "▪",
+    };
+    let (bullet, rest) = match rest {
+      _ if rest.starts_with("[ ] ") => ("☐", &rest[4..]),
+      _ if rest.starts_with("[x] ") || rest.starts_with("[X] ") => ("☑", &rest[4..]),
+      _ => (bullet, rest),
+    };
+    return wrapped(&format!("{}{} ", indent, bullet), rest, width);
+  }
+  wrapped(indent, trimmed, width)
+}
+
+/// `lead` 之后的正文，折行时后续行缩进到 `lead` 的宽度。
+/// 按原文折行，跨行的行内标记按原文显示
+fn wrapped(lead: &str, text: &str, width: Option<usize>) -> String {
+  let Some(width) = width else {
+    return format!("{}{}", lead, inline(text));
+  };
+  let hang = " ".repeat(display_width(lead));
+  let lines: Vec<String> = wrap(text, width.saturating_sub(hang.len()))
+    .iter()
+    .enumerate()
+    .map(|(i, l)| format!("{}{}", if i == 0 { lead } else { &hang }, inline(l)))
+    .collect();
+  lines.join("\n")
+}
+
+/// `---`、`***`、`___`，中间可以有空格
+fn is_rule(line: &str) -> bool {
+  let chars: Vec<char> = line.chars().filter(|c| !c.is_whitespace()).collect();
+  chars.len() >= 3 && matches!(chars[0], '-' | '*' | '_') && chars.iter().all(|&c| c == chars[0])
+}
+
+/// 行内样式。已有的 ANSI 和 OSC 序列（例如引用的超链接）原样保留
+fn inline(text: &str) -> String {
+  let chars: Vec<char> = text.chars().collect();
+  let mut out = String::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    if c == '\x1b' {
+      let end = escape_end(&chars, i);
+      out.extend(&chars[i..end]);
+      i = end;
+      continue;
+    }
+    if c == '\\' && chars.get(i + 1).is_some_and(char::is_ascii_punctuation) {
+      out.push(chars[i + 1]);
+      i += 2;
+      continue;
+    }
+    if c == '`' {
+      let ticks = chars[i..].iter().take_while(|&&c| c == '`').count();
+      let close = (i + ticks..chars.len()).find(|&j| {
+        chars[j..].iter().take_while(|&&c| c == '`').count() == ticks
+          && (j == 0 || chars[j - 1] != '`')
+      });
+      match close {
+        Some(j) => {
+          let span: String = chars[i + ticks..j].iter().collect();
+          out.push_str(&code(span.trim()));
+          i = j + ticks;
+        }
+        None => {
+          out.extend(&chars[i..i + ticks]);
+          i += ticks;
+        }
+      }
+      continue;
+    }
+    if let Some((marker, on, off)) = [
+      ("**", Attribute::Bold, Attribute::NormalIntensity),
+      ("~~", Attribute::CrossedOut, Attribute::NotCrossedOut),
+      ("*", Attribute::Italic, Attribute::NoItalic),
+    ]
+    .into_iter()
+    .find(|(marker, ..)| starts_with(&chars[i..], marker))
+    {
+      let n = marker.len();
+      if let Some(j) = closing(&chars, i + n, marker) {
+        let inner: String = chars[i + n..j].iter().collect();
+        out.push_str(&format!("{}{}{}", on, inline(&inner), off));
+        i = j + n;
+        continue;
+      }
+      out.push_str(marker);
+      i += n;
+      continue;
+    }
+    if c == '['
+      && let Some((label, url, end)) = link(&chars, i)
+    {
+      let label = inline(&label);
+      out.push_str(&format!(
+        "{}{}{}",
+        Attribute::Underlined,
+        label,
+        Attribute::NoUnderline
+      ));
+      if strip(&label) != url {
+        out.push_str(&dim(&format!(" ({})", url)));
+      }
+      i = end;
+      continue;
+    }
+    out.push(c);
+    i += 1;
+  }
+  out
+}
+
+fn starts_with(chars: &[char], marker: &str) -> bool {
+  let mut rest = chars.iter();
+  marker.chars().all(|m| rest.next() == Some(&m))
+}
+
+/// 从 `from` 开始找结束标记：内容不能为空，也不能以空白开始或结束；
+/// 单个 `*` 不与 `**` 配对
+fn closing(chars: &[char], from: usize, marker: &str) -> Option<usize> {
+  if chars.get(from).is_none_or(|c| c.is_whitespace()) {
+    return None;
+  }
+  let n = marker.len();
+  let single = marker == "*";
+  let mut j = from + 1;
+  while j + n <= chars.len() {
+    if chars[j] == '`' {
+      // 代码中的标记不算
+      let ticks = chars[j..].iter().take_while(|&&c| c == '`').count();
+      let close = (j + ticks..chars.len()).find(|&k| starts_with(&chars[k..], &"`".repeat(ticks)));
+      j = close.map_or(j + ticks, |k| k + ticks);
+      continue;
+    }
+    if single && starts_with(&chars[j..], "**") {
+      j += 2;
+      continue;
+    }
+    if starts_with(&chars[j..], marker) && !chars[j - 1].is_whitespace() {
+      return Some(j);
+    }
+    j += 1;
+  }
+  None
+}
+
+/// `[文字](地址)`，返回文字、地址和结束位置
+fn link(chars: &[char], start: usize) -> Option<(String, String, usize)> {
+  let close = start + chars[start..].iter().position(|&c| c == ']')?;
+  if chars.get(close + 1) != Some(&'(') {
+    return None;
+  }
+  let end = close + 1 + chars[close + 1..].iter().position(|&c| c == ')')?;
+  let label: String = chars[start + 1..close].iter().collect();
+  let url: String = chars[close + 2..end].iter().collect();
+  if label.is_empty() || url.is_empty() || url.contains(char::is_whitespace) {
+    return None;
+  }
+  Some((label, url, end + 1))
+}
+
+/// 转义序列的结束位置：CSI 到结束字母为止，OSC 到 ST 或 BEL 为止
+fn escape_end(chars: &[char], start: usize) -> usize {
+  match chars.get(start + 1) {
+    Some('[') => (start + 2..chars.len())
+      .find(|&j| ('\x40'..='\x7e').contains(&chars[j]))
+      .map_or(chars.len(), |j| j + 1),
+    Some(']') => (start + 2..chars.len())
+      .find_map(|j| match chars[j] {
+        '\x07' => Some(j + 1),
+        '\x1b' if chars.get(j + 1) == Some(&'\\') => Some(j + 2),
+        _ => None,
+      })
+      .unwrap_or(chars.len()),
+    Some(_) => start + 2,
+    None => start + 1,
+  }
+}
+
+/// 去掉转义序列后的文字，用于计算显示宽度
+pub fn strip(text: &str) -> String {
+  let chars: Vec<char> = text.chars().collect();
+  let mut out = String::new();
+  let mut i = 0;
+  while i < chars.len() {
+    match chars[i] {
+      '\x1b' => i = escape_end(&chars, i),
+      c => {
+        out.push(c);
+        i += 1;
+      }
+    }
+  }
+  out
+}
+
+fn dim(text: &str) -> String {
+  format!("{}{}{}", Attribute::Dim, text, Attribute::NormalIntensity)
+}
+
+fn bold(text: &str) -> String {
+  format!("{}{}{}", Attribute::Bold, text, Attribute::NormalIntensity)
+}
+
+fn code(text: &str) -> String {
+  format!(
+    "{}{}{}",
+    SetForegroundColor(Color::Cyan),
+    text,
+    SetForegroundColor(Color::Reset)
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_inline_styles() {
+    let cases = [
+      ("**粗体**和*斜体*", "粗体和斜体"),
+      ("用 `cargo build` 构建", "用 cargo build 构建"),
+      ("``a ` b``", "a ` b"),
+      ("~~旧~~", "旧"),
+      ("[文档](https://example.com)", "文档 (https://example.com)"),
+      ("[https://a.b](https://a.b)", "https://a.b"),
+      // 不成对的标记和乘号原样保留
+      ("2 * 3 * 4", "2 * 3 * 4"),
+      ("**未闭合", "**未闭合"),
+      ("\\*转义\\*", "*转义*"),
+      ("**`a*b`**", "a*b"),
+    ];
+    for (text, plain) in cases {
+      assert_eq!(strip(&inline(text)), plain, "{}", text);
+    }
+    assert_eq!(
+      inline("**a**"),
+      format!("{}a{}", Attribute::Bold, Attribute::NormalIntensity)
+    );
+    assert_eq!(
+      inline("*a **b** c*"),
+      format!(
+        "{}a {}b{} c{}",
+        Attribute::Italic,
+        Attribute::Bold,
+        Attribute::NormalIntensity,
+        Attribute::NoItalic
+      )
+    );
+    // 引用的超链接不被当作标记
+    let linked = "\x1b]8;;file:///tmp/a.rs\x1b\\a.rs:1\x1b]8;;\x1b\\ *x*";
+    assert!(inline(linked).starts_with("\x1b]8;;file:///tmp/a.rs\x1b\\a.rs:1\x1b]8;;\x1b\\ "));
+  }
+
+  #[test]
+  fn test_blocks() {
+    let text = "# 标题 #\n- 一\n  - 二\n- [x] 完成\n> 引用 **强调**\n---\n1. 第一\n";
+    let rendered = render(text);
+    assert_eq!(
+      strip(&rendered),
+      format!(
+        "标题\n• 一\n  ◦ 二\n☑ 完成\n│ 引用 强调\n{}\n1. 第一\n",
+        "─".repeat(RULE_WIDTH)
+      )
+    );
+    assert!(rendered.starts_with(&format!("{}{}标题", Attribute::Bold, Attribute::Underlined)));
+  }
+
+  #[test]
+  fn test_code_blocks_are_not_rendered() {
+    let text = "看：\n```rust\nlet x = **y** * 2;\n# not a heading\n```\n完成 *了*\n";
+    assert_eq!(
+      strip(&render(text)),
+      "看：\n┌─ rust\nlet x = **y** * 2;\n# not a heading\n└─\n完成 了\n"
+    );
+    // 较短的围栏和带内容的围栏不结束代码块；未闭合的代码块在结束时补上
+    let text = "````\n```\n``` x\n````\n";
+    assert_eq!(strip(&render(text)), "┌─\n```\n``` x\n└─\n");
+    assert_eq!(strip(&render("```\nx")), "┌─\nx\n└─\n");
+  }
+
+  #[test]
+  fn test_tables_are_aligned() {
+    let text =
+      "| 名称 | 数量 | 说明 |\n|:--|--:|:-:|\n| 苹果 | 3 | `红` |\n| kiwi | 12 | 绿 |\n之后\n";
+    let rendered = strip(&render(text));
+    assert_eq!(
+      rendered,
+      "名称 │ 数量 │ 说明\n─────┼──────┼─────\n苹果 │    3 │  红\nkiwi │   12 │  绿\n之后\n"
+    );
+    // 没有分隔行时不是表格
+    assert_eq!(strip(&render("| a |\ntext\n")), "| a |\ntext\n");
+  }
+
+  #[test]
+  fn test_streaming_matches_whole_text() {
+    let text = "## 步骤\n\n1. 运行 `make`\n\n```sh\nmake **all**\n```\n| a | b |\n|---|---|\n| 1 | 2 |\n**完成**";
+    let whole = render(text);
+    // 逐字符送入：围栏、表格和行内标记都会被拆开
+    let mut stream = MarkdownStream::default();
+    let mut streamed: String = text.chars().map(|c| stream.push(&c.to_string())).collect();
+    streamed.push_str(&stream.finish());
+    assert_eq!(streamed, whole);
+    // 代码块中的行收到换行就输出，不等整个代码块
+    let mut stream = MarkdownStream::default();
+    assert_eq!(strip(&stream.push("```\nline\n")), "┌─\nline\n");
+  }
+
+  #[test]
+  fn test_quotes_wrap_with_the_bar() {
+    let mut stream = MarkdownStream::default().with_width(10);
+    let text = "> 一二三四五六七\n> - abcdefghij\n>> 甲乙丙丁\n正文不折行的一行\n";
+    assert_eq!(
+      strip(&stream.push(text)),
+      "│ 一二三四\n│ 五六七\n│ • abcdef\n│   ghij\n│ │ 甲乙丙\n│ │ 丁\n正文不折行的一行\n"
+    );
+  }
+}
@@ -0,0 +1,661 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// 流式渲染时，未闭合的行间公式最多暂存这么多字节，超出后原样输出
+const MAX_HELD: usize = 4096;
+
+/// LaTeX 命令到 Unicode 的对照表（不含需要参数的 \frac、\sqrt 等）
+const SYMBOLS: &[(&str, &str)] = &[
+  ("alpha", "α"),
+  ("beta", "β"),
+  ("gamma", "γ"),
+  ("delta", "δ"),
+  ("epsilon", "ϵ"),
+  ("varepsilon", "ε"),
+  ("zeta", "ζ"),
+  ("eta", "η"),
+  ("theta", "θ"),
+  ("vartheta", "ϑ"),
+  ("iota", "ι"),
+  ("kappa", "κ"),
+  ("lambda", "λ"),
+  ("mu", "μ"),
+  ("nu", "ν"),
+  ("xi", "ξ"),
+  ("pi", "π"),
+  ("rho", "ρ"),
+  ("sigma", "σ"),
+  ("tau", "τ"),
+  ("upsilon", "υ"),
+  ("phi", "ϕ"),
+  ("varphi", "φ"),
+  ("chi", "χ"),
+  ("psi", "ψ"),
+  ("omega", "ω"),
+  ("Gamma", "Γ"),
+  ("Delta", "Δ"),
+  ("Theta", "Θ"),
+  ("Lambda", "Λ"),
+  ("Xi", "Ξ"),
+  ("Pi", "Π"),
+  ("Sigma", "Σ"),
+  ("Upsilon", "Υ"),
+  ("Phi", "Φ"),
+  ("Psi", "Ψ"),
+  ("Omega", "Ω"),
+  ("cdot", "·"),
+  ("times", "×"),
+  ("div", "÷"),
+  ("pm", "±"),
+  ("mp", "∓"),
+  ("le", "≤"),
+  ("leq", "≤"),
+  ("ge", "≥"),
+  ("geq", "≥"),
+  ("ne", "≠"),
+  ("neq", "≠"),
+  ("approx", "≈"),
+  ("equiv", "≡"),
+  ("sim", "∼"),
+  ("propto", "∝"),
+  ("ll", "≪"),
+  ("gg", "≫"),
+  ("infty", "∞"),
+  ("partial", "∂"),
+  ("nabla", "∇"),
+  ("sum", "∑"),
+  ("prod", "∏"),
+  ("int", "∫"),
+  ("iint", "∬"),
+  ("oint", "∮"),
+  ("in", "∈"),
+  ("notin", "∉"),
+  ("ni", "∋"),
+  ("subset", "⊂"),
+  ("subseteq", "⊆"),
+  ("supset", "⊃"),
+  ("supseteq", "⊇"),
+  ("cup", "∪"),
+  ("cap", "∩"),
+  ("setminus", "∖"),
+  ("emptyset", "∅"),
+  ("varnothing", "∅"),
+  ("forall", "∀"),
+  ("exists", "∃"),
+  ("neg", "¬"),
+  ("lnot", "¬"),
+  ("land", "∧"),
+  ("wedge", "∧"),
+  ("lor", "∨"),
+  ("vee", "∨"),
+  ("oplus", "⊕"),
+  ("otimes", "⊗"),
+  ("to", "→"),
+  ("rightarrow", "→"),
+  ("leftarrow", "←"),
+  ("gets", "←"),
+  ("Rightarrow", "⇒"),
+  ("Leftarrow", "⇐"),
+  ("implies", "⇒"),
+  ("iff", "⇔"),
+  ("Leftrightarrow", "⇔"),
+  ("leftrightarrow", "↔"),
+  ("mapsto", "↦"),
+  ("ldots", "…"),
+  ("dots", "…"),
+  ("cdots", "⋯"),
+  ("vdots", "⋮"),
+  ("circ", "∘"),
+  ("bullet", "•"),
+  ("star", "⋆"),
+  ("ast", "∗"),
+  ("angle", "∠"),
+  ("perp", "⊥"),
+  ("parallel", "∥"),
+  ("mid", "∣"),
+  ("prime", "′"),
+  ("hbar", "ℏ"),
+  ("ell", "ℓ"),
+  ("Re", "ℜ"),
+  ("Im", "ℑ"),
+  ("aleph", "ℵ"),
+  ("langle", "⟨"),
+  ("rangle", "⟩"),
+  ("lfloor", "⌊"),
+  ("rfloor", "⌋"),
+  ("lceil", "⌈"),
+  ("rceil", "⌉"),
+  ("lvert", "|"),
+  ("rvert", "|"),
+  ("vert", "|"),
+  ("Vert", "‖"),
+  ("quad", "  "),
+  ("qquad", "    "),
+  ("log", "log"),
+  ("ln", "ln"),
+  ("lg", "lg"),
+  ("exp", "exp"),
+  ("sin", "sin"),
+  ("cos", "cos"),
+  ("tan", "tan"),
+  ("cot", "cot"),
+  ("arcsin", "arcsin"),
+  ("arccos", "arccos"),
+  ("arctan", "arctan"),
+  ("sinh", "sinh"),
+  ("cosh", "cosh"),
+  ("tanh", "tanh"),
+  ("lim", "lim"),
+  ("max", "max"),
+  ("min", "min"),
+  ("sup", "sup"),
+  ("inf", "inf"),
+  ("det", "det"),
+  ("gcd", "gcd"),
+  ("mod", "mod"),
+  ("bmod", "mod"),
+];
+
+const SUPERSCRIPTS: &[(char, char)] = &[
+  ('0', '⁰'),
+  ('1', '¹'),
+  ('2', '²'),
+  ('3', '³'),
+  ('4', '⁴'),
+  ('5', '⁵'),
+  ('6', '⁶'),
+  ('7', '⁷'),
+  ('8', '⁸'),
+  ('9', '⁹'),
+  ('+', '⁺'),
+  ('-', '⁻'),
+  ('−', '⁻'),
+  ('=', '⁼'),
+  ('(', '⁽'),
+  (')', '⁾'),
+  ('a', 'ᵃ'),
+  ('b', 'ᵇ'),
+  ('c', 'ᶜ'),
+  ('d', 'ᵈ'),
+  ('e', 'ᵉ'),
+  ('f', 'ᶠ'),
+  ('g', 'ᵍ'),
+  ('h', 'ʰ'),
+  ('i', 'ⁱ'),
+  ('j', 'ʲ'),
+  ('k', 'ᵏ'),
+  ('l', 'ˡ'),
+  ('m', 'ᵐ'),
+  ('n', 'ⁿ'),
+  ('o', 'ᵒ'),
+  ('p', 'ᵖ'),
+  ('r', 'ʳ'),
+  ('s', 'ˢ'),
+  ('t', 'ᵗ'),
+  ('u', 'ᵘ'),
+  ('v', 'ᵛ'),
+  ('w', 'ʷ'),
+  ('x', 'ˣ'),
+  ('y', 'ʸ'),
+  ('z', 'ᶻ'),
+  ('T', 'ᵀ'),
+  ('′', '′'),
+  ('∘', '°'),
+  ('∗', '*'),
+];
+
+const SUBSCRIPTS: &[(char, char)] = &[
+  ('0', '₀'),
+  ('1', '₁'),
+  ('2', '₂'),
+  ('3', '₃'),
+  ('4', '₄'),
+  ('5', '₅'),
+  ('6', '₆'),
+  ('7', '₇'),
+  ('8', '₈'),
+  ('9', '₉'),
+  ('+', '₊'),
+  ('-', '₋'),
+  ('−', '₋'),
+  ('=', '₌'),
+  ('(', '₍'),
+  (')', '₎'),
+  ('a', 'ₐ'),
+  ('e', 'ₑ'),
+  ('h', 'ₕ'),
+  ('i', 'ᵢ'),
+  ('j', 'ⱼ'),
+  ('k', 'ₖ'),
+  ('l', 'ₗ'),
+  ('m', 'ₘ'),
+  ('n', 'ₙ'),
+  ('o', 'ₒ'),
+  ('p', 'ₚ'),
+  ('r', 'ᵣ'),
+  ('s', 'ₛ'),
+  ('t', 'ₜ'),
+  ('u', 'ᵤ'),
+  ('v', 'ᵥ'),
+  ('x', 'ₓ'),
+  ('β', 'ᵦ'),
+  ('γ', 'ᵧ'),
+  ('ρ', 'ᵨ'),
+  ('φ', 'ᵩ'),
+  ('χ', 'ᵪ'),
+];
+
+const VULGAR_FRACTIONS: &[(&str, &str, &str)] = &[
+  ("1", "2", "½"),
+  ("1", "3", "⅓"),
+  ("2", "3", "⅔"),
+  ("1", "4", "¼"),
+  ("3", "4", "¾"),
+  ("1", "5", "⅕"),
+  ("1", "6", "⅙"),
+  ("1", "8", "⅛"),
+];
+
+/// 逐字符映射为上标/下标，有一个字符无法映射就放弃
+fn script(text: &str, table: &[(char, char)]) -> Option<String> {
+  text
+    .chars()
+    .filter(|c| *c != ' ')
+    .map(|c| table.iter().find(|(from, _)| *from == c).map(|(_, to)| *to))
+    .collect()
+}
+
+/// 作为分数或根号的参数时是否需要加括号
+fn needs_parens(text: &str) -> bool {
+  text.chars().count() > 1 && !text.chars().all(char::is_alphanumeric)
+}
+
+fn parens(text: &str) -> String {
+  match needs_parens(text) {
+    true => format!("({})", text),
+    false => text.to_string(),
+  }
+}
+
+struct Translator<'a> {
+  chars: Peekable<Chars<'a>>,
+  /// 当前所在的 \frac 层数，嵌套的分数不翻译
+  fractions: usize,
+}
+
+impl Translator<'_> {
+  fn skip_spaces(&mut self) {
+    while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+  }
+
+  /// 翻译到 `}`（group 为 true 时）或结尾
+  fn sequence(&mut self, group: bool) -> Option<String> {
+    let mut out = String::new();
+    loop {
+      let Some(c) = self.chars.next() else {
+        // 缺少右括号
+        return (!group).then_some(out);
+      };
+      match c {
+        '}' if group => return Some(out),
+        '}' | '&' | '#' => return None,
+        '{' => out.push_str(&self.sequence(true)?),
+        '^' => out.push_str(&script(&self.argument()?, SUPERSCRIPTS)?),
+        '_' => out.push_str(&script(&self.argument()?, SUBSCRIPTS)?),
+        '\\' => out.push_str(&self.command()?),
+        c if c.is_whitespace() => {
+          self.skip_spaces();
+          if !out.ends_with(' ') {
+            out.push(' ');
+          }
+        }
+        c => out.push(c),
+      }
+    }
+  }
+
+  /// ^、_ 和命令的参数：一个括号组、一个命令或一个字符
+  fn argument(&mut self) -> Option<String> {
+    self.skip_spaces();
+    match self.chars.next()? {
+      '{' => self.sequence(true).map(|s| s.trim().to_string()),
+      '\\' => self.command(),
+      '}' | '^' | '_' | '&' => None,
+      c => Some(c.to_string()),
+    }
+  }
+
+  /// 原样取出括号组中的文本，用于 \text 等
+  fn raw_group(&mut self) -> Option<String> {
+    self.skip_spaces();
+    self.chars.next_if_eq(&'{')?;
+    let mut out = String::new();
+    let mut depth = 0;
+    loop {
+      let c = self.chars.next()?;
+      match c {
+        '{' => depth += 1,
+        '}' if depth == 0 => return Some(out),
+        '}' => depth -= 1,
+        _ => {}
+      }
+      out.push(c);
+    }
+  }
+
+  fn command(&mut self) -> Option<String> {
+    let mut name = String::new();
+    while let Some(c) = self.chars.next_if(char::is_ascii_alphabetic) {
+      name.push(c);
+    }
+    if name.is_empty() {
+      // 单个符号的命令
+      return match self.chars.next()? {
+        ',' | ';' | ':' | ' ' => Some(" ".to_string()),
+        '!' => Some(String::new()),
+        c @ ('{' | '}' | '%' | '$' | '#' | '&' | '_' | '|') => Some(c.to_string()),
+        _ => None,
+      };
+    }
+    match name.as_str() {
+      "frac" | "dfrac" | "tfrac" => {
+        if self.fractions > 0 {
+          return None;
+        }
+        self.fractions += 1;
+        let numerator = self.argument();
+        let denominator = self.argument();
+        self.fractions -= 1;
+        let (numerator, denominator) = (numerator?, denominator?);
+        if let Some((_, _, glyph)) = VULGAR_FRACTIONS
+          .iter()
+          .find(|(n, d, _)| *n == numerator && *d == denominator)
+        {
+          return Some(glyph.to_string());
+        }
+        Some(format!("{}⁄{}", parens(&numerator), parens(&denominator)))
+      }
+      "sqrt" => {
+        self.skip_spaces();
+        let root = match self.chars.next_if_eq(&'[') {
+          Some(_) => {
+            let index: String = self.chars.by_ref().take_while(|c| *c != ']').collect();
+            match index.trim() {
+              "3" => "∛",
+              "4" => "∜",
+              _ => return None,
+            }
+          }
+          None => "√",
+        };
+        Some(format!("{}{}", root, parens(&self.argument()?)))
+      }
+      "text" | "textrm" | "mathrm" | "mathit" | "mathbf" | "operatorname" | "mbox" => {
+        self.raw_group()
+      }
+      // 括号大小在终端中没有意义
+      "left" | "right" | "big" | "Big" | "bigg" | "Bigg" | "displaystyle" => {
+        // \left. 表示不显示的定界符
+        self.skip_spaces();
+        self.chars.next_if_eq(&'.');
+        Some(String::new())
+      }
+      name => SYMBOLS
+        .iter()
+        .find(|(from, _)| *from == name)
+        .map(|(_, to)| to.to_string()),
+    }
+  }
+}
+
+/// 把一段 LaTeX 公式（不含定界符）翻译为 Unicode 近似。
+/// 遇到无法映射的结构（未知命令、环境、嵌套分数、无法上标的字符等）返回 None
+pub fn translate(tex: &str) -> Option<String> {
+  let mut translator = Translator {
+    chars: tex.chars().peekable(),
+    fractions: 0,
+  };
+  let out = translator.sequence(false)?;
+  Some(out.trim().to_string())
+}
+
+#[derive(Debug, PartialEq)]
+struct Span {
+  start: usize,
+  end: usize,
+  /// 定界符内的公式
+  tex: std::ops::Range<usize>,
+}
+
+/// 行内公式 `$…$` 的右定界符：前面不是空白，后面不是数字（排除 "$5 和 $10"）
+fn inline_close(text: &str, from: usize) -> Option<usize> {
+  let line_end = text[from..].find('\n').map_or(text.len(), |i| from + i);
+  let bytes = text.as_bytes();
+  (from..line_end).find(|&i| {
+    bytes[i] == b'$'
+      && i > from
+      && !bytes[i - 1].is_ascii_whitespace()
+      && bytes[i - 1] != b'\\'
+      && !bytes.get(i + 1).is_some_and(u8::is_ascii_digit)
+  })
+}
+
+/// 查找公式片段。反引号内的代码不处理；`open` 报告是否有未闭合的行间公式
+fn spans(text: &str) -> (Vec<Span>, bool) {
+  let bytes = text.as_bytes();
+  let mut spans = vec![];
+  let mut in_code = false;
+  let mut i = 0;
+  while i < bytes.len() {
+    let rest = &text[i..];
+    if bytes[i] == b'`' {
+      in_code = !in_code;
+      i += 1;
+      continue;
+    }
+    if in_code {
+      i += 1;
+      continue;
+    }
+    let display = [("$$", "$$"), ("\\[", "\\]")]
+      .into_iter()
+      .find(|(open, _)| rest.starts_with(open));
+    if let Some((open, close)) = display {
+      let from = i + open.len();
+      let Some(len) = text[from..].find(close) else {
+        return (spans, true);
+      };
+      spans.push(Span {
+        start: i,
+        end: from + len + close.len(),
+        tex: from..from + len,
+      });
+      i = from + len + close.len();
+      continue;
+    }
+    if rest.starts_with("\\(")
+      && let Some(len) = rest.find("\\)")
+    {
+      spans.push(Span {
+        start: i,
+        end: i + len + 2,
+        tex: i + 2..i + len,
+      });
+      i += len + 2;
+      continue;
+    }
+    if rest.starts_with("\\$") {
+      i += 2;
+      continue;
+    }
+    if bytes[i] == b'$'
+      && bytes.get(i + 1).is_some_and(|b| !b.is_ascii_whitespace())
+      && let Some(close) = inline_close(text, i + 1)
+    {
+      spans.push(Span {
+        start: i,
+        end: close + 1,
+        tex: i + 1..close,
+      });
+      i = close + 1;
+      continue;
+    }
+    i += rest.chars().next().map_or(1, char::len_utf8);
+  }
+  (spans, false)
+}
+
+/// 把文本中能翻译的公式替换为 Unicode，不能翻译的保留原始 LaTeX
+pub fn render(text: &str) -> String {
+  let mut out = String::with_capacity(text.len());
+  let mut last = 0;
+  for span in spans(text).0 {
+    if let Some(rendered) = translate(&text[span.tex.clone()]) {
+      out.push_str(&text[last..span.start]);
+      out.push_str(&rendered);
+      last = span.end;
+    }
+  }
+  out.push_str(&text[last..]);
+  out
+}
+
+/// 流式输出时的公式渲染。不含 `$` 和 `\` 的内容立即输出；
+/// 否则攒到行尾（行间公式攒到闭合）再整体渲染。代码块内原样输出
+#[derive(Debug, Default)]
+pub struct MathStream {
+  pending: String,
+  /// pending 中已经原样输出的字节数
+  emitted: usize,
+  in_fence: bool,
+}
+
+impl MathStream {
+  /// 返回可以立即输出的部分
+  pub fn push(&mut self, chunk: &str) -> String {
+    self.pending.push_str(chunk);
+    let mut out = String::new();
+    while let Some(end) = self.complete_end() {
+      let rest = self.pending.split_off(end);
+      let text = std::mem::replace(&mut self.pending, rest);
+      out.push_str(&self.finish_text(&text));
+    }
+    let tail = &self.pending[self.emitted..];
+    if self.in_fence || !tail.contains(['$', '\\']) {
+      out.push_str(tail);
+      self.emitted = self.pending.len();
+    }
+    out
+  }
+
+  pub fn finish(&mut self) -> String {
+    let text = std::mem::take(&mut self.pending);
+    self.finish_text(&text)
+  }
+
+  /// 下一段可以渲染的完整文本的结尾：通常是行尾，行间公式未闭合时继续向后
+  fn complete_end(&self) -> Option<usize> {
+    let mut end = 0;
+    loop {
+      end += self.pending[end..].find('\n')? + 1;
+      if self.in_fence || end > MAX_HELD || !spans(&self.pending[..end]).1 {
+        return Some(end);
+      }
+    }
+  }
+
+  fn finish_text(&mut self, text: &str) -> String {
+    let rendered = match self.in_fence {
+      true => text.to_string(),
+      false => render(text),
+    };
+    if text.trim_start().starts_with("```") {
+      self.in_fence = !self.in_fence;
+    }
+    // 已输出的部分不含公式，渲染结果以它开头
+    let out = rendered[self.emitted.min(rendered.len())..].to_string();
+    self.emitted = 0;
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_translate_table() {
+    let cases = [
+      (r"\frac{a}{b}", "a⁄b"),
+      (r"\frac{1}{2} + \frac{x+1}{y}", "½ + (x+1)⁄y"),
+      (r"x^2 + y^{10} = z_{i+1}", "x² + y¹⁰ = zᵢ₊₁"),
+      (r"\alpha \cdot \beta \le \Omega", "α · β ≤ Ω"),
+      (r"\sqrt{2} \ne \sqrt{x+1}", "√2 ≠ √(x+1)"),
+      (r"\sqrt[3]{8}", "∛8"),
+      (r"\sum_{i=1}^{n} i", "∑ᵢ₌₁ⁿ i"),
+      (r"A^T", "Aᵀ"),
+      (r"90^\circ", "90°"),
+      (r"\left( a \right)", "( a )"),
+      (r"\text{if } x \in S", "if x ∈ S"),
+    ];
+    for (tex, expected) in cases {
+      assert_eq!(translate(tex).as_deref(), Some(expected), "{}", tex);
+    }
+  }
+
+  #[test]
+  fn test_unmappable_constructs_are_left_alone() {
+    for tex in [
+      // 嵌套的分数和上标
+      r"\frac{\frac{a}{b}}{c}",
+      r"e^{x^2}",
+      // 没有对应上标字符的 q
+      r"x^{q}",
+      r"\begin{pmatrix} a & b \end{pmatrix}",
+      r"\mathcal{L}",
+      r"\sqrt[5]{x}",
+      r"\frac{a}",
+      r"a \\ b",
+      "{x",
+    ] {
+      assert_eq!(translate(tex), None, "{}", tex);
+    }
+    let text = r"嵌套 $\frac{\frac{a}{b}}{c}$ 保持原样，$x^2$ 会渲染";
+    assert_eq!(
+      render(text),
+      r"嵌套 $\frac{\frac{a}{b}}{c}$ 保持原样，x² 会渲染"
+    );
+  }
+
+  #[test]
+  fn test_render_finds_math_spans() {
+    assert_eq!(
+      render("面积 $\\pi r^2$，\\(a_1\\) 与\n$$\n\\frac{1}{4}\n$$\n"),
+      "面积 π r²，a₁ 与\n¼\n"
+    );
+    // 美元金额、转义和代码都不是公式
+    for text in [
+      "costs $5 and $10",
+      r"price \$5 or \$6",
+      "run `echo $HOME$` first",
+      "$ cargo build",
+    ] {
+      assert_eq!(render(text), text);
+    }
+  }
+
+  #[test]
+  fn test_stream_waits_for_closing_delimiter() {
+    let mut stream = MathStream::default();
+    let mut out = stream.push("没有公式的前缀 ");
+    assert_eq!(out, "没有公式的前缀 ");
+    // 公式未闭合前不输出
+    assert_eq!(stream.push("$\\al"), "");
+    out.push_str(&stream.push("pha$ 结束\n$$\n"));
+    out.push_str(&stream.push("x^2\n"));
+    out.push_str(&stream.push("$$\n```sh\necho $HOME\n"));
+    out.push_str(&stream.push("```\n尾"));
+    out.push_str(&stream.finish());
+    assert_eq!(out, "没有公式的前缀 α 结束\nx²\n```sh\necho $HOME\n```\n尾");
+  }
+}
@@ -0,0 +1,174 @@
+use crate::api::{ChatBackend, ChunkStream, Message, StreamChunk, decode_sse};
+use anyhow::Result;
+use futures_util::{StreamExt, stream};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 预设的流式响应：先等待 `delay`，再依次给出 `chunks`，`stall` 时之后永不结束
+#[derive(Default)]
+struct ScriptedStream {
+  delay: Duration,
+  chunks: Vec<StreamChunk>,
+  stall: bool,
+  /// 给出 chunks 后以这个错误结束
+  error: Option<String>,
+  /// 经过 SSE 解码的原始字节流，设置时忽略 chunks
+  raw: Option<ChunkStream>,
+}
+
+/// 模拟 HTTP 传输：每项先等待给定时间再交出对应的字节，全部交出后关闭连接
+pub fn scripted_transport(
+  events: Vec<(Duration, String)>,
+) -> impl futures_util::Stream<Item = Result<Vec<u8>, std::io::Error>> + Send + 'static {
+  stream::unfold(VecDeque::from(events), |mut events| async move {
+    let (delay, bytes) = events.pop_front()?;
+    tokio::time::sleep(delay).await;
+    Some((Ok(bytes.into_bytes()), events))
+  })
+}
+
+/// 一行 SSE 内容增量
+pub fn sse_content(text: &str) -> String {
+  format!(
+    "data: {}\n\n",
+    serde_json::json!({"choices": [{"delta": {"content": text}}]})
+  )
+}
+
+/// 按顺序返回预设回答（非流式与流式各一个队列），并记录收到的每个请求
+#[derive(Default)]
+pub struct ScriptedBackend {
+  replies: Mutex<VecDeque<Result<String, String>>>,
+  streams: Mutex<VecDeque<ScriptedStream>>,
+  pub requests: Mutex<Vec<(String, Vec<Message>)>>,
+  pub max_tokens: Mutex<Vec<Option<u32>>>,
+}
+
+impl ScriptedBackend {
+  pub fn new<I, S>(replies: I) -> Self
+  where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+  {
+    Self {
+      replies: Mutex::new(replies.into_iter().map(|r| Ok(r.into())).collect()),
+      ..Default::default()
+    }
+  }
+
+  pub fn push_stream(&self, chunks: Vec<StreamChunk>) {
+    self.streams.lock().unwrap().push_back(ScriptedStream {
+      chunks,
+      ..Default::default()
+    });
+  }
+
+  /// 给出 `chunks` 后既不结束也不再产生数据，模拟卡住的连接
+  pub fn push_stalled_stream(&self, chunks: Vec<StreamChunk>) {
+    self.streams.lock().unwrap().push_back(ScriptedStream {
+      chunks,
+      stall: true,
+      ..Default::default()
+    });
+  }
+
+  /// 给出 `chunks` 后出错，模拟回答中途断开的连接
+  pub fn push_failing_stream(&self, chunks: Vec<StreamChunk>, error: &str) {
+    self.streams.lock().unwrap().push_back(ScriptedStream {
+      chunks,
+      error: Some(error.to_string()),
+      ..Default::default()
+    });
+  }
+
+  /// 通过 scripted_transport 和真实的 SSE 解码给出响应
+  pub fn push_transport(&self, events: Vec<(Duration, String)>, idle_warn: Option<Duration>) {
+    self.streams.lock().unwrap().push_back(ScriptedStream {
+      raw: Some(decode_sse(scripted_transport(events), idle_warn, None)),
+      ..Default::default()
+    });
+  }
+
+  /// 请求在 `delay` 之后才返回
+  pub fn push_delayed_stream(&self, delay: Duration, chunks: Vec<StreamChunk>) {
+    self.streams.lock().unwrap().push_back(ScriptedStream {
+      delay,
+      chunks,
+      ..Default::default()
+    });
+  }
+
+  fn record(&self, model: &str, messages: Vec<Message>, max_tokens: Option<u32>) {
+    self
+      .requests
+      .lock()
+      .unwrap()
+      .push((model.to_string(), messages));
+    self.max_tokens.lock().unwrap().push(max_tokens);
+  }
+
+  pub fn push_reply(&self, reply: &str) {
+    self
+      .replies
+      .lock()
+      .unwrap()
+      .push_back(Ok(reply.to_string()));
+  }
+
+  pub fn push_error(&self, message: &str) {
+    self
+      .replies
+      .lock()
+      .unwrap()
+      .push_back(Err(message.to_string()));
+  }
+
+  pub fn request_count(&self) -> usize {
+    self.requests.lock().unwrap().len()
+  }
+}
+
+impl ChatBackend for ScriptedBackend {
+  async fn complete(
+    &self,
+    model: &str,
+    messages: Vec<Message>,
+    _temperature: Option<f32>,
+    max_tokens: Option<u32>,
+  ) -> Result<String> {
+    self.record(model, messages, max_tokens);
+    match self.replies.lock().unwrap().pop_front() {
+      Some(Ok(reply)) => Ok(reply),
+      Some(Err(e)) => Err(anyhow::anyhow!(e)),
+      None => Err(anyhow::anyhow!("ScriptedBackend: no more replies")),
+    }
+  }
+
+  async fn stream(
+    &self,
+    model: &str,
+    messages: Vec<Message>,
+    _temperature: Option<f32>,
+    max_tokens: Option<u32>,
+  ) -> Result<ChunkStream> {
+    self.record(model, messages, max_tokens);
+    let Some(script) = self.streams.lock().unwrap().pop_front() else {
+      return Err(anyhow::anyhow!("ScriptedBackend: no more streams"));
+    };
+    tokio::time::sleep(script.delay).await;
+    if let Some(raw) = script.raw {
+      return Ok(raw);
+    }
+    let chunks = stream::iter(script.chunks.into_iter().map(Ok));
+    if let Some(error) = script.error {
+      return Ok(Box::pin(
+        chunks.chain(stream::once(async move { Err(anyhow::anyhow!(error)) })),
+      ));
+    }
+    if script.stall {
+      return Ok(Box::pin(chunks.chain(stream::pending())));
+    }
+    Ok(Box::pin(chunks))
+  }
+}
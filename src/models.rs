@@ -0,0 +1,537 @@
+use crate::profile::BUILTIN_PROFILES;
+use crate::width::{display_width, pad_right};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// 编译进程序的模型表
+const BUILTIN: &str = include_str!("models.yaml");
+/// 模型表里没有的模型使用的上下文窗口
+pub const DEFAULT_CONTEXT_WINDOW: usize = 65536;
+/// 模型表里没有的模型使用的输出上限
+pub const DEFAULT_MAX_OUTPUT: u32 = 4096;
+
+/// 每百万 tokens 的价格（人民币）
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pricing {
+  #[serde(rename = "input")]
+  pub input_per_million: f64,
+  #[serde(rename = "output")]
+  pub output_per_million: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+  /// 回答前输出推理过程（reasoning_content）
+  Reasoning,
+  /// 接受图片输入
+  Vision,
+  /// 接受 `reasoning_effort`（仅 OpenAI 风格的推理模型，deepseek-r1/deepseek-reasoner 不支持）
+  ReasoningEffort,
+}
+
+impl Capability {
+  pub fn name(self) -> &'static str {
+    match self {
+      Capability::Reasoning => "reasoning",
+      Capability::Vision => "vision",
+      Capability::ReasoningEffort => "reasoning_effort",
+    }
+  }
+}
+
+/// 模型表中的一项。文件中除 name 外都可以省略：覆盖已有模型时只替换写出的字段
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+  name: String,
+  aliases: Option<Vec<String>>,
+  ids: Option<BTreeMap<String, String>>,
+  id_prefixes: Option<Vec<String>>,
+  context_window: Option<usize>,
+  max_output: Option<u32>,
+  capabilities: Option<Vec<Capability>>,
+  pricing: Option<Pricing>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+  models: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+  pub name: String,
+  pub aliases: Vec<String>,
+  pub ids: BTreeMap<String, String>,
+  pub id_prefixes: Vec<String>,
+  pub context_window: usize,
+  pub max_output: u32,
+  pub capabilities: Vec<Capability>,
+  pub pricing: Option<Pricing>,
+}
+
+impl ModelInfo {
+  fn matches(&self, model: &str) -> bool {
+    self.name == model
+      || self.aliases.iter().any(|a| a == model)
+      || self.ids.values().any(|id| id == model)
+      || self
+        .id_prefixes
+        .iter()
+        .any(|p| model.starts_with(p.as_str()))
+  }
+
+  /// 用 `entry` 中写出的字段覆盖；ids 按提供方逐项合并
+  fn merge(&mut self, entry: Entry) {
+    if let Some(aliases) = entry.aliases {
+      self.aliases = aliases;
+    }
+    if let Some(ids) = entry.ids {
+      self.ids.extend(ids);
+    }
+    if let Some(prefixes) = entry.id_prefixes {
+      self.id_prefixes = prefixes;
+    }
+    if let Some(n) = entry.context_window {
+      self.context_window = n;
+    }
+    if let Some(n) = entry.max_output {
+      self.max_output = n;
+    }
+    if let Some(capabilities) = entry.capabilities {
+      self.capabilities = capabilities;
+    }
+    if entry.pricing.is_some() {
+      self.pricing = entry.pricing;
+    }
+  }
+}
+
+/// 检查一项是否合法；`existing` 为它要覆盖的模型
+fn validate(entry: &Entry, existing: Option<&ModelInfo>) -> Result<(), String> {
+  if entry.name.trim().is_empty() {
+    return Err("name must not be empty".to_string());
+  }
+  if existing.is_none() && (entry.context_window.is_none() || entry.max_output.is_none()) {
+    return Err("new models need both context_window and max_output".to_string());
+  }
+  if entry.context_window == Some(0) {
+    return Err("context_window must be greater than 0".to_string());
+  }
+  if entry.max_output == Some(0) {
+    return Err("max_output must be greater than 0".to_string());
+  }
+  let context_window = entry
+    .context_window
+    .or(existing.map(|m| m.context_window))
+    .unwrap_or_default();
+  let max_output = entry
+    .max_output
+    .or(existing.map(|m| m.max_output))
+    .unwrap_or_default() as usize;
+  if max_output > context_window {
+    return Err(format!(
+      "max_output ({}) exceeds context_window ({})",
+      max_output, context_window
+    ));
+  }
+  if let Some(alias) = entry.aliases.iter().flatten().find(|a| a.trim().is_empty()) {
+    return Err(format!("alias {:?} must not be empty", alias));
+  }
+  for (provider, id) in entry.ids.iter().flatten() {
+    if !BUILTIN_PROFILES.iter().any(|p| p.name == provider) {
+      let names: Vec<_> = BUILTIN_PROFILES.iter().map(|p| p.name).collect();
+      return Err(format!(
+        "unknown provider '{}' in ids (use {})",
+        provider,
+        names.join(" or ")
+      ));
+    }
+    if id.trim().is_empty() {
+      return Err(format!("ids.{} must not be empty", provider));
+    }
+  }
+  if let Some(p) = entry.pricing
+    && (p.input_per_million < 0.0 || p.output_per_million < 0.0)
+  {
+    return Err("pricing must not be negative".to_string());
+  }
+  Ok(())
+}
+
+/// 模型表：内置条目加上用户配置的覆盖
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelRegistry {
+  /// 查找时按顺序匹配，用户配置的条目在前
+  models: Vec<ModelInfo>,
+}
+
+impl ModelRegistry {
+  pub fn builtin() -> Self {
+    Self { models: vec![] }
+      .with_overrides(BUILTIN, "built-in models.yaml")
+      .expect("built-in model registry is valid")
+  }
+
+  /// 合并一份模型表，`source` 用于错误信息
+  pub fn with_overrides(mut self, yaml: &str, source: &str) -> Result<Self, String> {
+    let file: File = serde_yaml::from_str(yaml).map_err(|e| format!("{}: {}", source, e))?;
+    let mut front = vec![];
+    for (i, entry) in file.models.into_iter().enumerate() {
+      let index = self.models.iter().position(|m| m.name == entry.name);
+      validate(&entry, index.map(|i| &self.models[i]))
+        .map_err(|e| format!("{}: models[{}] ({}): {}", source, i, entry.name, e))?;
+      let mut model = match index {
+        Some(i) => self.models.remove(i),
+        None => ModelInfo {
+          name: entry.name.clone(),
+          aliases: vec![],
+          ids: BTreeMap::new(),
+          id_prefixes: vec![],
+          context_window: 0,
+          max_output: 0,
+          capabilities: vec![],
+          pricing: None,
+        },
+      };
+      model.merge(entry);
+      // 别名转移到后写出的条目上
+      for other in front.iter_mut().chain(self.models.iter_mut()) {
+        other.aliases.retain(|a| !model.aliases.contains(a));
+      }
+      front.push(model);
+    }
+    front.append(&mut self.models);
+    self.models = front;
+    Ok(self)
+  }
+
+  /// 内置模型表，加上 `path` 存在时其中的覆盖
+  pub fn load(path: Option<&Path>) -> Result<Self> {
+    let registry = Self::builtin();
+    let Some(path) = path.filter(|p| p.is_file()) else {
+      return Ok(registry);
+    };
+    let yaml = std::fs::read_to_string(path).context(format!("Failed to read {:?}", path))?;
+    registry
+      .with_overrides(&yaml, &path.display().to_string())
+      .map_err(|e| anyhow::anyhow!(e))
+  }
+
+  /// 按名称、别名、提供方 id 或前缀查找
+  pub fn lookup(&self, model: &str) -> Option<&ModelInfo> {
+    self.models.iter().find(|m| m.matches(model))
+  }
+
+  /// 把 -m 的输入解析为 `provider` 使用的模型名。模型表里没有的名称原样返回
+  /// （带日期的快照、兼容接口上的其他模型），限制按默认值估算
+  pub fn resolve(&self, input: &str, provider: &str) -> Result<String, String> {
+    if input.trim().is_empty() {
+      return Err("Model name must not be empty".to_string());
+    }
+    let Some(model) = self.lookup(input) else {
+      return Ok(input.to_string());
+    };
+    model.ids.get(provider).cloned().ok_or_else(|| {
+      format!(
+        "Model '{}' is not available from provider '{}'",
+        model.name, provider
+      )
+    })
+  }
+
+  /// `--list-models` 的表格，按查找顺序（用户配置的条目在前）
+  pub fn render_table(&self) -> String {
+    let join = |items: Vec<String>| match items.is_empty() {
+      true => "-".to_string(),
+      false => items.join(", "),
+    };
+    let header = [
+      "模型",
+      "别名",
+      "服务方模型名",
+      "上下文",
+      "输出上限",
+      "能力",
+      "价格（输入/输出，¥/百万 tokens）",
+    ]
+    .map(str::to_string);
+    let rows: Vec<[String; 7]> = self
+      .models
+      .iter()
+      .map(|m| {
+        let ids = m
+          .ids
+          .iter()
+          .map(|(provider, id)| format!("{}={}", provider, id))
+          .chain(m.id_prefixes.iter().map(|p| format!("{}*", p)))
+          .collect();
+        [
+          m.name.clone(),
+          join(m.aliases.clone()),
+          join(ids),
+          m.context_window.to_string(),
+          m.max_output.to_string(),
+          join(
+            m.capabilities
+              .iter()
+              .map(|c| c.name().to_string())
+              .collect(),
+          ),
+          m.pricing.map_or("-".to_string(), |p| {
+            format!("{} / {}", p.input_per_million, p.output_per_million)
+          }),
+        ]
+      })
+      .collect();
+    let mut widths = [0; 7];
+    for row in std::iter::once(&header).chain(&rows) {
+      for (width, cell) in widths.iter_mut().zip(row) {
+        *width = (*width).max(display_width(cell));
+      }
+    }
+    std::iter::once(&header)
+      .chain(&rows)
+      .map(|row| {
+        let cells: Vec<String> = row
+          .iter()
+          .zip(widths)
+          .map(|(cell, width)| pad_right(cell, width))
+          .collect();
+        cells.join("  ").trim_end().to_string()
+      })
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  pub fn max_output(&self, model: &str) -> u32 {
+    self
+      .lookup(model)
+      .map_or(DEFAULT_MAX_OUTPUT, |m| m.max_output)
+  }
+
+  pub fn context_window(&self, model: &str) -> usize {
+    self
+      .lookup(model)
+      .map_or(DEFAULT_CONTEXT_WINDOW, |m| m.context_window)
+  }
+
+  pub fn pricing(&self, model: &str) -> Option<Pricing> {
+    self.lookup(model)?.pricing
+  }
+
+  pub fn supports(&self, model: &str, capability: Capability) -> bool {
+    self
+      .lookup(model)
+      .is_some_and(|m| m.capabilities.contains(&capability))
+  }
+}
+
+static REGISTRY: OnceLock<ModelRegistry> = OnceLock::new();
+
+/// 启动时设置一次（读取用户配置之后）
+pub fn init(registry: ModelRegistry) {
+  let _ = REGISTRY.set(registry);
+}
+
+/// 当前的模型表；没有调用 init 时为内置模型表
+pub fn registry() -> &'static ModelRegistry {
+  REGISTRY.get_or_init(ModelRegistry::builtin)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::profile::{DASHSCOPE, DEEPSEEK};
+
+  #[test]
+  fn test_reasoning_effort_capability() {
+    let registry = ModelRegistry::builtin();
+    let supports = |m: &str| registry.supports(m, Capability::ReasoningEffort);
+    assert!(supports("o3-mini"));
+    assert!(supports("o1"));
+    assert!(supports("gpt-5-mini"));
+    assert!(!supports("deepseek-r1"));
+    assert!(!supports("deepseek-reasoner"));
+    assert!(!supports("deepseek-chat"));
+  }
+
+  #[test]
+  fn test_builtin_limits_and_resolution() {
+    let registry = ModelRegistry::builtin();
+    assert_eq!(
+      registry.resolve("r1", DASHSCOPE.name).unwrap(),
+      "deepseek-r1"
+    );
+    assert_eq!(
+      registry.resolve("chat", DASHSCOPE.name).unwrap(),
+      "deepseek-chat"
+    );
+    assert_eq!(
+      registry.resolve("r1", DEEPSEEK.name).unwrap(),
+      "deepseek-reasoner"
+    );
+    assert_eq!(
+      registry.resolve("qwen-max", DASHSCOPE.name).unwrap(),
+      "qwen-max"
+    );
+    let err = registry.resolve("qwen-max", DEEPSEEK.name).unwrap_err();
+    assert!(
+      err.contains("not available from provider 'deepseek'"),
+      "{}",
+      err
+    );
+    // 模型表里没有的名称原样发送，限制按默认值估算
+    assert_eq!(
+      registry
+        .resolve("deepseek-chat-0324", DEEPSEEK.name)
+        .unwrap(),
+      "deepseek-chat-0324"
+    );
+    assert!(registry.lookup("deepseek-chat-0324").is_none());
+    assert!(registry.resolve(" ", DEEPSEEK.name).is_err());
+
+    assert_eq!(registry.max_output("deepseek-r1"), 65536);
+    assert_eq!(registry.max_output("deepseek-reasoner"), 65536);
+    assert_eq!(registry.max_output("deepseek-chat"), 8192);
+    assert_eq!(registry.max_output("local-model"), DEFAULT_MAX_OUTPUT);
+    assert_eq!(registry.context_window("deepseek-chat"), 65536);
+    assert_eq!(registry.context_window("qwen-turbo"), 1_000_000);
+    assert_eq!(
+      registry.pricing("deepseek-v3").map(|p| p.input_per_million),
+      Some(2.0)
+    );
+    assert_eq!(registry.pricing("local-model"), None);
+  }
+
+  #[test]
+  fn test_every_builtin_alias_resolves() {
+    let registry = ModelRegistry::builtin();
+    for model in &registry.models {
+      for (provider, id) in &model.ids {
+        assert!(
+          BUILTIN_PROFILES.iter().any(|p| p.name == provider),
+          "{}: unknown provider {}",
+          model.name,
+          provider
+        );
+        for input in model.aliases.iter().chain([&model.name, id]) {
+          assert_eq!(
+            registry.resolve(input, provider).as_ref(),
+            Ok(id),
+            "{}",
+            input
+          );
+        }
+      }
+      // 提供方 id 查回同一个模型
+      for id in model.ids.values() {
+        assert_eq!(registry.lookup(id).unwrap().name, model.name);
+      }
+    }
+  }
+
+  #[test]
+  fn test_user_override_takes_precedence() {
+    let yaml = r#"
+models:
+  - name: deepseek-chat
+    max_output: 4096
+  - name: my-qwen
+    aliases: [chat]
+    ids: {dashscope: qwen-plus-latest}
+    context_window: 131072
+    max_output: 8192
+"#;
+    let registry = ModelRegistry::builtin()
+      .with_overrides(yaml, "models.yaml")
+      .unwrap();
+    // 只替换写出的字段
+    let chat = registry.lookup("deepseek-chat").unwrap();
+    assert_eq!(chat.max_output, 4096);
+    assert_eq!(chat.pricing.unwrap().output_per_million, 8.0);
+    // 别名转移到用户条目上
+    assert!(!chat.aliases.contains(&"chat".to_string()));
+    assert_eq!(
+      registry.resolve("chat", DASHSCOPE.name).unwrap(),
+      "qwen-plus-latest"
+    );
+    assert_eq!(
+      registry.resolve("r1", DEEPSEEK.name).unwrap(),
+      "deepseek-reasoner"
+    );
+
+    // 表格中用户配置的条目在前，各列对齐
+    let table = registry.render_table();
+    let lines: Vec<&str> = table.lines().collect();
+    assert!(lines[0].starts_with("模型"));
+    assert!(lines[1].starts_with("deepseek-chat "), "{}", table);
+    assert!(lines[2].starts_with("my-qwen "), "{}", table);
+    assert!(lines[2].contains("dashscope=qwen-plus-latest"));
+    let reasoner = lines
+      .iter()
+      .find(|l| l.starts_with("deepseek-reasoner"))
+      .unwrap();
+    assert!(
+      reasoner.contains("r1, deepseek-r1  dashscope=deepseek-r1, deepseek=deepseek-reasoner")
+    );
+    assert!(reasoner.contains(" reasoning ") && reasoner.ends_with("4 / 16"));
+    assert!(table.contains("o1*, o3*"));
+    let column = |line: &str, cell: &str| display_width(&line[..line.find(cell).unwrap()]);
+    assert_eq!(column(lines[1], "65536"), column(lines[2], "131072"));
+    assert_eq!(column(lines[0], "上下文"), column(lines[2], "131072"));
+  }
+
+  #[test]
+  fn test_malformed_overrides_are_rejected() {
+    let cases = [
+      (
+        "models:\n  - name: x\n    max_output: 10\n",
+        "models.yaml: models[0] (x): new models need both context_window and max_output",
+      ),
+      (
+        "models:\n  - name: deepseek-chat\n    max_output: 100000\n",
+        "models.yaml: models[0] (deepseek-chat): max_output (100000) exceeds context_window (65536)",
+      ),
+      (
+        "models:\n  - name: deepseek-chat\n  - name: y\n    ids: {openai: gpt}\n    context_window: 10\n    max_output: 5\n",
+        "models.yaml: models[1] (y): unknown provider 'openai' in ids (use dashscope or deepseek)",
+      ),
+      (
+        "models:\n  - name: deepseek-chat\n    pricing: {input: -1, output: 2}\n",
+        "models.yaml: models[0] (deepseek-chat): pricing must not be negative",
+      ),
+    ];
+    for (yaml, expected) in cases {
+      let err = ModelRegistry::builtin()
+        .with_overrides(yaml, "models.yaml")
+        .unwrap_err();
+      assert_eq!(err, expected);
+    }
+    // 未知字段和类型错误由 YAML 解析报告，带上位置
+    let err = ModelRegistry::builtin()
+      .with_overrides(
+        "models:\n  - name: deepseek-chat\n    max_tokens: 10\n",
+        "models.yaml",
+      )
+      .unwrap_err();
+    assert!(
+      err.starts_with("models.yaml: models[0]: unknown field `max_tokens`"),
+      "{}",
+      err
+    );
+    let err = ModelRegistry::builtin()
+      .with_overrides(
+        "models:\n  - name: deepseek-chat\n    capabilities: [tools]\n",
+        "models.yaml",
+      )
+      .unwrap_err();
+    assert!(err.contains("unknown variant `tools`"), "{}", err);
+  }
+}
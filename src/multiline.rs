@@ -0,0 +1,188 @@
+//! REPL 的多行输入：`<<<` 与只有 `>>>` 的一行之间的内容、以 `\` 结尾的续行，
+//! 以及终端括号粘贴时带标记的粘贴内容，都合成一条消息，中间的换行原样保留。
+//! 行编辑器交出的一行中已经带有换行时（粘贴或历史输入）按原样作为内容
+
+use std::io::{self, BufRead, Write};
+
+/// 开始多行输入的一行
+pub const OPEN: &str = "<<<";
+/// 结束多行输入的一行
+pub const CLOSE: &str = ">>>";
+/// 多行输入中后续行的提示符，与 `> ` 等宽
+pub const CONTINUATION: &str = "… ";
+/// 括号粘贴时终端在粘贴内容前后加的标记
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+  /// `<<<` 之后，直到 `>>>`
+  Block,
+  /// 粘贴内容，直到结束标记
+  Paste,
+  /// 上一行以 `\` 结尾
+  Continued,
+}
+
+/// 逐行收集一条输入
+#[derive(Debug, Default)]
+pub struct Collector {
+  mode: Option<Mode>,
+  lines: Vec<String>,
+}
+
+impl Collector {
+  /// 加入一行（不含换行符）；输入完整时返回整条消息
+  pub fn push(&mut self, line: &str) -> Option<String> {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    match self.mode {
+      Some(Mode::Block) => {
+        if line.trim() == CLOSE {
+          return Some(self.finish());
+        }
+        self.lines.push(line.to_string());
+        None
+      }
+      Some(Mode::Paste) => self.paste(line),
+      None if self.lines.is_empty() && line.trim() == OPEN => {
+        self.mode = Some(Mode::Block);
+        None
+      }
+      _ if line.contains('\n') => {
+        self.lines.push(line.to_string());
+        Some(self.finish())
+      }
+      _ => {
+        if let Some(start) = line.find(PASTE_START) {
+          self.mode = Some(Mode::Paste);
+          let rest = format!("{}{}", &line[..start], &line[start + PASTE_START.len()..]);
+          return self.paste(&rest);
+        }
+        match line.strip_suffix('\\') {
+          Some(head) => {
+            self.mode = Some(Mode::Continued);
+            self.lines.push(head.to_string());
+            None
+          }
+          None => {
+            self.lines.push(line.to_string());
+            Some(self.finish())
+          }
+        }
+      }
+    }
+  }
+
+  /// 是否在等待后续行
+  pub fn is_pending(&self) -> bool {
+    self.mode.is_some()
+  }
+
+  /// 粘贴内容以换行结束时，结束标记后面是用户接着输入的内容，与粘贴内容合成一条
+  fn paste(&mut self, line: &str) -> Option<String> {
+    match line.find(PASTE_END) {
+      Some(end) => {
+        // 之后按普通输入处理，可以接着续行或再次粘贴
+        self.mode = None;
+        self.push(&format!(
+          "{}{}",
+          &line[..end],
+          &line[end + PASTE_END.len()..]
+        ))
+      }
+      None => {
+        self.lines.push(line.to_string());
+        None
+      }
+    }
+  }
+
+  /// 输入结束（EOF）时返回已收集的部分
+  pub fn finish(&mut self) -> String {
+    self.mode = None;
+    let text = std::mem::take(&mut self.lines).join("\n");
+    // 以换行结束的粘贴内容最后多出一个空行
+    text.trim_end_matches('\n').to_string()
+  }
+}
+
+/// 读入一条输入。等待后续行时在 `prompt` 为 true 时显示 CONTINUATION；EOF 时返回空字符串
+pub fn read(input: &mut impl BufRead, out: &mut impl Write, prompt: bool) -> io::Result<String> {
+  let mut collector = Collector::default();
+  loop {
+    if collector.is_pending() && prompt {
+      write!(out, "{}", CONTINUATION)?;
+      out.flush()?;
+    }
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+      return Ok(collector.finish());
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    if let Some(message) = collector.push(line) {
+      return Ok(message);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read_all(text: &str) -> Vec<String> {
+    let mut input = io::Cursor::new(text.as_bytes().to_vec());
+    let mut out = Vec::new();
+    let mut messages = vec![];
+    loop {
+      let message = read(&mut input, &mut out, true).unwrap();
+      if message.is_empty() && input.position() as usize == text.len() {
+        break;
+      }
+      messages.push(message);
+    }
+    messages
+  }
+
+  #[test]
+  fn test_block_and_backslash_continue_input() {
+    let code = "fn main() {\n\n    println!(\"hi\");\n}";
+    let typed = format!("<<<\n{}\n>>>\n第一行\\\n  第二行\\\n第三行\n\\q\n", code);
+    assert_eq!(read_all(&typed), [code, "第一行\n  第二行\n第三行", "\\q"]);
+
+    // 只有 `<<<` 的一行才开始多行输入，结束行可以有首尾空白
+    assert_eq!(read_all("a <<<\n"), ["a <<<"]);
+    assert_eq!(read_all("<<<\r\nx\r\n>>> \r\n"), ["x"]);
+    // 多行输入中的 `\` 和 `<<<` 原样保留
+    assert_eq!(read_all("<<<\nC:\\\n<<<\n>>>\n"), ["C:\\\n<<<"]);
+
+    // 后续行前显示续行提示符
+    let mut out = Vec::new();
+    let mut input = io::Cursor::new(b"<<<\na\n>>>\n".to_vec());
+    read(&mut input, &mut out, true).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), CONTINUATION.repeat(2));
+
+    // EOF 时返回已输入的部分
+    assert_eq!(read_all("<<<\n未完\n"), ["未完"]);
+  }
+
+  #[test]
+  fn test_bracketed_paste_is_one_message() {
+    // 粘贴内容没有以换行结束：用户接着输入再回车
+    let pasted = format!("{}line 1\nline 2{} 怎么改？\n", PASTE_START, PASTE_END);
+    assert_eq!(read_all(&pasted), ["line 1\nline 2 怎么改？"]);
+
+    // 以换行结束：结束标记之后直接回车
+    let pasted = format!("看看\n{}a\\\nb\n{}\n下一条\n", PASTE_START, PASTE_END);
+    assert_eq!(read_all(&pasted), ["看看", "a\\\nb", "下一条"]);
+
+    // 行编辑器交出的多行内容不再检查 `<<<` 和行尾的 `\`
+    let mut collector = Collector::default();
+    assert_eq!(collector.push("<<<\na\\"), Some("<<<\na\\".to_string()));
+    assert_eq!(collector.push("x\\"), None);
+    assert_eq!(collector.push("<<<\ny"), Some("x\n<<<\ny".to_string()));
+
+    // 粘贴前已经输入的内容和粘贴后的续行都在同一条消息中
+    let pasted = format!("解释：{}x\ny{}\\\n谢谢\n", PASTE_START, PASTE_END);
+    assert_eq!(read_all(&pasted), ["解释：x\ny\n谢谢"]);
+  }
+}
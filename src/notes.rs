@@ -0,0 +1,136 @@
+use crate::api::Message;
+use crate::history::turn_starts;
+use std::collections::BTreeMap;
+
+/// 轮次批注。与 history 分开保存，从不写入消息，
+/// 因此不会出现在任何发给模型的请求或摘要输入中
+#[derive(Debug, Default)]
+pub struct Notes {
+  /// 轮次（从 1 开始）到批注
+  by_turn: BTreeMap<usize, Vec<String>>,
+}
+
+/// 历史中的轮数，自动续写的片段不单独计数
+pub fn turn_count(history: &[Message]) -> usize {
+  turn_starts(history).len()
+}
+
+/// 解析 `\note` 的参数：`<文本>` 批注最近一轮，`<n> <文本>` 批注第 n 轮
+pub fn parse_note(arg: &str, turns: usize) -> Result<(usize, &str), String> {
+  let arg = arg.trim();
+  let (turn, text) = match arg.split_once(char::is_whitespace) {
+    Some((n, rest)) if n.parse::<usize>().is_ok() => (n.parse().unwrap(), rest.trim()),
+    _ => (turns, arg),
+  };
+  if text.is_empty() {
+    return Err("用法: \\note [轮次] <文本>".to_string());
+  }
+  if turns == 0 {
+    return Err("还没有可以批注的对话".to_string());
+  }
+  if turn == 0 || turn > turns {
+    return Err(format!("没有第 {} 轮，当前共 {} 轮", turn, turns));
+  }
+  Ok((turn, text))
+}
+
+impl Notes {
+  pub fn add(&mut self, turn: usize, text: &str) {
+    self.by_turn.entry(turn).or_default().push(text.to_string());
+  }
+
+  pub fn for_turn(&self, turn: usize) -> &[String] {
+    self.by_turn.get(&turn).map_or(&[], Vec::as_slice)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.by_turn.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.by_turn.clear();
+  }
+
+  /// 回退历史后，丢弃第 `turn` 轮及之后的批注
+  pub fn truncate(&mut self, turn: usize) {
+    self.by_turn.split_off(&turn);
+  }
+
+  /// 删除第 `turn` 轮后，丢弃它的批注，之后各轮的批注前移一轮
+  pub fn remove(&mut self, turn: usize) {
+    let later = self.by_turn.split_off(&turn);
+    self.by_turn.extend(
+      later
+        .into_iter()
+        .filter(|(t, _)| *t != turn)
+        .map(|(t, v)| (t - 1, v)),
+    );
+  }
+
+  /// `\notes` 的输出，每条一行
+  pub fn render(&self) -> String {
+    self
+      .by_turn
+      .iter()
+      .flat_map(|(turn, notes)| notes.iter().map(move |n| format!("第 {} 轮: {}", turn, n)))
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn message(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  fn history() -> Vec<Message> {
+    vec![
+      message("user", "为什么会死锁？"),
+      message("assistant", "因为持有锁时 await。"),
+      message("user", "怎么修？"),
+      message("assistant", "先释放锁。"),
+    ]
+  }
+
+  #[test]
+  fn test_parse_note() {
+    assert_eq!(parse_note("这个回答错了", 2), Ok((2, "这个回答错了")));
+    assert_eq!(parse_note("1  见第 2 轮", 2), Ok((1, "见第 2 轮")));
+    // 数字后没有文本时整体作为批注
+    assert_eq!(parse_note("42", 2), Ok((2, "42")));
+    assert!(parse_note("3 太远", 2).unwrap_err().contains("第 3 轮"));
+    assert!(parse_note("0 无效", 2).is_err());
+    assert!(parse_note("  ", 2).is_err());
+    assert!(parse_note("没有对话", 0).is_err());
+  }
+
+  #[test]
+  fn test_render_and_truncate() {
+    let mut notes = Notes::default();
+    notes.add(1, "这个回答错了\n见第 2 轮");
+    notes.add(2, "已验证");
+    assert_eq!(
+      notes.render(),
+      "第 1 轮: 这个回答错了\n见第 2 轮\n第 2 轮: 已验证"
+    );
+    assert_eq!(turn_count(&history()), 2);
+
+    let mut dropped = Notes::default();
+    dropped.add(1, "一");
+    dropped.add(2, "二");
+    dropped.add(3, "三");
+    dropped.remove(2);
+    assert_eq!(dropped.render(), "第 1 轮: 一\n第 2 轮: 三");
+
+    notes.truncate(2);
+    assert_eq!(notes.for_turn(1).len(), 1);
+    assert!(notes.for_turn(2).is_empty());
+  }
+}
@@ -0,0 +1,578 @@
+//! 把每一轮对话和每个 HTTP 请求作为 trace span 发送到 OTLP 收集器（OTLP/HTTP JSON）。
+//!
+//! 只在启用 `otlp` feature 并配置了 `otlp_endpoint` 时生效。属性名遵循 OpenTelemetry
+//! GenAI 语义约定（`gen_ai.*`），没有对应约定的放在 `deepcli.*` 下。
+//! 发送在后台进行，收集器不可达时静默丢弃；退出时最多等待 FLUSH_TIMEOUT。
+
+use serde_json::{Value, json};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// 退出前等待未发送的 span 的上限
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+/// 单次发送的超时，收集器卡住时后台任务也不会堆积
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// OTLP 的 SpanKind
+const KIND_INTERNAL: u8 = 1;
+const KIND_CLIENT: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attr {
+  Str(String),
+  Int(i64),
+  Strs(Vec<String>),
+}
+
+impl Attr {
+  fn to_json(&self) -> Value {
+    match self {
+      Attr::Str(s) => json!({ "stringValue": s }),
+      // OTLP JSON 中的 64 位整数写成字符串
+      Attr::Int(n) => json!({ "intValue": n.to_string() }),
+      Attr::Strs(items) => json!({
+        "arrayValue": { "values": items.iter().map(|s| json!({ "stringValue": s })).collect::<Vec<_>>() }
+      }),
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+  name: String,
+  kind: u8,
+  trace_id: u128,
+  span_id: u64,
+  parent: Option<u64>,
+  start: SystemTime,
+  end: SystemTime,
+  attributes: Vec<(&'static str, Attr)>,
+  /// 失败时的说明，span 状态记为 ERROR
+  error: Option<String>,
+}
+
+fn nanos(t: SystemTime) -> String {
+  t.duration_since(UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_nanos()
+    .to_string()
+}
+
+impl Span {
+  fn to_json(&self) -> Value {
+    let mut span = json!({
+      "traceId": format!("{:032x}", self.trace_id),
+      "spanId": format!("{:016x}", self.span_id),
+      "name": self.name,
+      "kind": self.kind,
+      "startTimeUnixNano": nanos(self.start),
+      "endTimeUnixNano": nanos(self.end),
+      "attributes": self.attributes.iter().map(|(key, value)| json!({ "key": key, "value": value.to_json() })).collect::<Vec<_>>(),
+      "status": match &self.error {
+        Some(message) => json!({ "code": 2, "message": message }),
+        None => json!({ "code": 1 }),
+      },
+    });
+    if let Some(parent) = self.parent {
+      span["parentSpanId"] = json!(format!("{:016x}", parent));
+    }
+    span
+  }
+}
+
+/// 进行中的一轮对话，HTTP 请求的 span 挂在它下面
+#[derive(Debug, Clone, Copy)]
+struct Current {
+  trace_id: u128,
+  span_id: u64,
+}
+
+/// 一轮对话结束时记录的数据
+#[derive(Debug, Clone, Default)]
+pub struct TurnRecord {
+  pub input_tokens: usize,
+  pub output_tokens: usize,
+  pub ttft: Option<Duration>,
+  /// 本轮如何结束：stop、timeout、max_words、interrupted
+  pub finish_reason: Option<&'static str>,
+  /// 失败的原因；error.type 取本轮最后一个失败的 HTTP 状态码，没有时为 transport
+  pub error: Option<String>,
+}
+
+impl TurnRecord {
+  /// 由 run_turn 的结果和前后的统计得出
+  pub fn from_turn(
+    end: &anyhow::Result<crate::turn::TurnEnd>,
+    before: &crate::stats::SessionStats,
+    after: &crate::stats::SessionStats,
+  ) -> Self {
+    use crate::turn::TurnEnd;
+    let (ttft_before, samples_before) = before.ttft_sum();
+    let (ttft_after, samples_after) = after.ttft_sum();
+    let ttft = (samples_after > samples_before)
+      .then(|| (ttft_after - ttft_before) / (samples_after - samples_before));
+    let (finish_reason, error) = match end {
+      Ok(TurnEnd::Done) => (Some("stop"), None),
+      Ok(TurnEnd::TimedOut) => (Some("timeout"), None),
+      Ok(TurnEnd::WordLimited) => (Some("max_words"), None),
+      Ok(TurnEnd::Stopped) => (Some("stop_regex"), None),
+      Ok(TurnEnd::Interrupted) => (Some("interrupted"), None),
+      Ok(TurnEnd::StreamFailed) => (None, Some("stream failed, partial answer kept".to_string())),
+      Ok(TurnEnd::Filtered) => (Some("content_filter"), None),
+      Ok(TurnEnd::RolledBack) => (None, Some("request failed, turn rolled back".to_string())),
+      Err(e) => (None, Some(format!("{:#}", e))),
+    };
+    Self {
+      input_tokens: after.prompt_tokens - before.prompt_tokens,
+      output_tokens: after.completion_tokens - before.completion_tokens,
+      ttft,
+      finish_reason,
+      error,
+    }
+  }
+}
+
+/// start_turn 返回，交给 finish_turn
+#[derive(Debug)]
+pub struct TurnSpan {
+  current: Current,
+  model: String,
+  start: SystemTime,
+}
+
+pub struct Exporter {
+  url: String,
+  /// 提供方，记为 gen_ai.system
+  system: String,
+  client: reqwest::Client,
+  random: RandomState,
+  counter: AtomicU64,
+  pending: Mutex<Vec<Span>>,
+  current: Mutex<Option<Current>>,
+  /// 本轮最近一次失败的 HTTP 状态码
+  last_error_status: Mutex<Option<u16>>,
+  tasks: Mutex<Vec<JoinHandle<()>>>,
+  /// 没能发送的批数，后台任务共享
+  failed: Arc<AtomicUsize>,
+}
+
+impl Exporter {
+  /// 配置了 `otlp_endpoint` 时创建导出器；隔离模式下不发送遥测，提示后返回 None
+  pub fn configured(
+    endpoint: Option<&str>,
+    system: &str,
+    isolation: &crate::isolation::Isolation,
+  ) -> Option<Self> {
+    let endpoint = endpoint?;
+    match isolation.check(crate::isolation::Feature::Telemetry) {
+      Ok(()) => Some(Self::new(endpoint, system)),
+      Err(e) => {
+        eprintln!("[隔离] {}", e);
+        None
+      }
+    }
+  }
+
+  /// `endpoint` 为收集器地址（如 http://localhost:4318），span 发往其下的 /v1/traces
+  pub fn new(endpoint: &str, system: &str) -> Self {
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = match endpoint.ends_with("/v1/traces") {
+      true => endpoint.to_string(),
+      false => format!("{}/v1/traces", endpoint),
+    };
+    Self {
+      url,
+      system: system.to_string(),
+      client: reqwest::Client::new(),
+      random: RandomState::new(),
+      counter: AtomicU64::new(0),
+      pending: Mutex::default(),
+      current: Mutex::default(),
+      last_error_status: Mutex::default(),
+      tasks: Mutex::default(),
+      failed: Arc::default(),
+    }
+  }
+
+  fn next_id(&self) -> u64 {
+    let mut hasher = self.random.build_hasher();
+    hasher.write_u64(self.counter.fetch_add(1, Ordering::Relaxed));
+    hasher.finish().max(1)
+  }
+
+  fn trace_id(&self) -> u128 {
+    (u128::from(self.next_id()) << 64) | u128::from(self.next_id())
+  }
+
+  pub fn start_turn(&self, model: &str) -> TurnSpan {
+    let current = Current {
+      trace_id: self.trace_id(),
+      span_id: self.next_id(),
+    };
+    *self.current.lock().unwrap() = Some(current);
+    *self.last_error_status.lock().unwrap() = None;
+    TurnSpan {
+      current,
+      model: model.to_string(),
+      start: SystemTime::now(),
+    }
+  }
+
+  /// 记录一轮对话的 span，并在后台发送本轮积累的 span
+  pub fn finish_turn(&self, turn: TurnSpan, record: TurnRecord) {
+    *self.current.lock().unwrap() = None;
+    let end = SystemTime::now();
+    let latency = end.duration_since(turn.start).unwrap_or_default();
+    let mut attributes = vec![
+      ("gen_ai.operation.name", Attr::Str("chat".into())),
+      ("gen_ai.system", Attr::Str(self.system.clone())),
+      ("gen_ai.request.model", Attr::Str(turn.model.clone())),
+      (
+        "gen_ai.usage.input_tokens",
+        Attr::Int(record.input_tokens as i64),
+      ),
+      (
+        "gen_ai.usage.output_tokens",
+        Attr::Int(record.output_tokens as i64),
+      ),
+      (
+        "deepcli.turn.latency_ms",
+        Attr::Int(latency.as_millis() as i64),
+      ),
+    ];
+    if let Some(ttft) = record.ttft {
+      attributes.push(("deepcli.turn.ttft_ms", Attr::Int(ttft.as_millis() as i64)));
+    }
+    if let Some(reason) = record.finish_reason {
+      attributes.push((
+        "gen_ai.response.finish_reasons",
+        Attr::Strs(vec![reason.to_string()]),
+      ));
+    }
+    if record.error.is_some() {
+      let class = match *self.last_error_status.lock().unwrap() {
+        Some(status) => status.to_string(),
+        None => "transport".to_string(),
+      };
+      attributes.push(("error.type", Attr::Str(class)));
+    }
+    self.pending.lock().unwrap().push(Span {
+      name: format!("chat {}", turn.model),
+      kind: KIND_INTERNAL,
+      trace_id: turn.current.trace_id,
+      span_id: turn.current.span_id,
+      parent: None,
+      start: turn.start,
+      end,
+      attributes,
+      error: record.error,
+    });
+    self.export_pending();
+  }
+
+  /// 一个 HTTP 请求；`status` 为 None 表示没有收到响应
+  pub fn record_http(
+    &self,
+    url: &str,
+    start: SystemTime,
+    status: Option<u16>,
+    error: Option<String>,
+  ) {
+    let current = *self.current.lock().unwrap();
+    let (trace_id, parent) = match current {
+      Some(c) => (c.trace_id, Some(c.span_id)),
+      None => (self.trace_id(), None),
+    };
+    let mut attributes = vec![
+      ("http.request.method", Attr::Str("POST".into())),
+      ("url.full", Attr::Str(url.to_string())),
+    ];
+    let failed = match status {
+      Some(status) => {
+        attributes.push(("http.response.status_code", Attr::Int(status.into())));
+        status >= 400
+      }
+      None => true,
+    };
+    if failed {
+      let class = status.map_or("transport".to_string(), |s| s.to_string());
+      attributes.push(("error.type", Attr::Str(class)));
+      if let Some(status) = status {
+        *self.last_error_status.lock().unwrap() = Some(status);
+      }
+    }
+    self.pending.lock().unwrap().push(Span {
+      name: "POST".to_string(),
+      kind: KIND_CLIENT,
+      trace_id,
+      span_id: self.next_id(),
+      parent,
+      start,
+      end: SystemTime::now(),
+      attributes,
+      error: failed.then(|| error.unwrap_or_else(|| format!("HTTP {}", status.unwrap_or(0)))),
+    });
+    // 不在对话中的请求（如一次性查询）直接发送
+    if current.is_none() {
+      self.export_pending();
+    }
+  }
+
+  fn payload(&self, spans: &[Span]) -> Value {
+    json!({
+      "resourceSpans": [{
+        "resource": { "attributes": [
+          { "key": "service.name", "value": { "stringValue": "deepcli" } },
+          { "key": "service.version", "value": { "stringValue": env!("CARGO_PKG_VERSION") } },
+        ] },
+        "scopeSpans": [{
+          "scope": { "name": "deepcli", "version": env!("CARGO_PKG_VERSION") },
+          "spans": spans.iter().map(Span::to_json).collect::<Vec<_>>(),
+        }],
+      }],
+    })
+  }
+
+  fn export_pending(&self) {
+    let spans = std::mem::take(&mut *self.pending.lock().unwrap());
+    if spans.is_empty() {
+      return;
+    }
+    let request = self
+      .client
+      .post(&self.url)
+      .timeout(EXPORT_TIMEOUT)
+      .json(&self.payload(&spans));
+    let failed = self.failed.clone();
+    let task = tokio::spawn(async move {
+      let sent = match request.send().await {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+      };
+      if !sent {
+        failed.fetch_add(1, Ordering::Relaxed);
+      }
+    });
+    let mut tasks = self.tasks.lock().unwrap();
+    tasks.retain(|t| !t.is_finished());
+    tasks.push(task);
+  }
+
+  /// 发送剩余的 span 并等待后台发送完成，最多等待 `timeout`。返回未能发送的批数
+  pub async fn flush(&self, timeout: Duration) -> usize {
+    self.export_pending();
+    let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+    let pending = tasks.len();
+    match tokio::time::timeout(timeout, futures_util::future::join_all(tasks)).await {
+      Ok(_) => self.failed.load(Ordering::Relaxed),
+      Err(_) => pending,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::api::{ApiClient, ChatBackend};
+  use crate::stats::SessionStats;
+  use crate::turn::TurnEnd;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio::sync::mpsc;
+
+  /// 对每个连接都回应 `status` 和 `body` 的 HTTP 服务，收到的请求体从通道取出
+  async fn serve(
+    status: &'static str,
+    body: &'static str,
+  ) -> (String, mpsc::UnboundedReceiver<String>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+      while let Ok((mut socket, _)) = listener.accept().await {
+        let mut request = vec![];
+        let mut buf = [0; 4096];
+        let body_start = loop {
+          let n = socket.read(&mut buf).await.unwrap();
+          request.extend_from_slice(&buf[..n]);
+          let text = String::from_utf8_lossy(&request).to_string();
+          if let Some(end) = text.find("\r\n\r\n") {
+            let length = text
+              .lines()
+              .find_map(|l| {
+                l.to_ascii_lowercase()
+                  .strip_prefix("content-length:")
+                  .map(|v| v.trim().parse::<usize>().unwrap())
+              })
+              .unwrap_or(0);
+            if request.len() >= end + 4 + length || n == 0 {
+              break end + 4;
+            }
+          }
+          if n == 0 {
+            break request.len();
+          }
+        };
+        tx.send(String::from_utf8_lossy(&request[body_start..]).to_string())
+          .ok();
+        let response = format!(
+          "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+          status,
+          body.len(),
+          body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+      }
+    });
+    (format!("http://{}", addr), rx)
+  }
+
+  /// 收集器收到的所有 span
+  fn spans(rx: &mut mpsc::UnboundedReceiver<String>) -> Vec<Value> {
+    let mut spans = vec![];
+    while let Ok(body) = rx.try_recv() {
+      let payload: Value = serde_json::from_str(&body).unwrap();
+      let scope = &payload["resourceSpans"][0]["scopeSpans"][0];
+      assert_eq!(scope["scope"]["name"], "deepcli");
+      spans.extend(scope["spans"].as_array().unwrap().iter().cloned());
+    }
+    spans
+  }
+
+  fn attr(span: &Value, key: &str) -> Value {
+    let attributes = span["attributes"].as_array().unwrap();
+    let found = attributes.iter().find(|a| a["key"] == key);
+    found.map_or(Value::Null, |a| a["value"].clone())
+  }
+
+  async fn turn(exporter: &Arc<Exporter>, api: &str) -> anyhow::Result<TurnEnd> {
+    let client = ApiClient::new("test_key".to_string())
+      .with_base_url(api)
+      .with_tracer(Some(exporter.clone()));
+    let before = SessionStats::default();
+    let span = exporter.start_turn("deepseek-chat");
+    let end = client
+      .complete("deepseek-chat", vec![], None, Some(16))
+      .await
+      .map(|_| TurnEnd::Done);
+    let mut after = before.clone();
+    if end.is_ok() {
+      after.record_request(
+        120,
+        30,
+        Some(Duration::from_millis(250)),
+        Duration::from_secs(1),
+      );
+    }
+    let end = end.or(Ok(TurnEnd::RolledBack));
+    exporter.finish_turn(span, TurnRecord::from_turn(&end, &before, &after));
+    end
+  }
+
+  #[test]
+  fn test_isolated_mode_creates_no_exporter() {
+    use crate::isolation::Isolation;
+    let endpoint = Some("http://127.0.0.1:4318");
+    assert!(Exporter::configured(endpoint, "deepseek", &Isolation::new(true)).is_none());
+    assert!(Exporter::configured(endpoint, "deepseek", &Isolation::new(false)).is_some());
+    assert!(Exporter::configured(None, "deepseek", &Isolation::new(false)).is_none());
+  }
+
+  #[tokio::test]
+  async fn test_successful_turn_spans() {
+    let (collector, mut received) = serve("200 OK", "{}").await;
+    let (api, _) = serve(
+      "200 OK",
+      r#"{"choices":[{"message":{"role":"assistant","content":"hi"}}]}"#,
+    )
+    .await;
+    let exporter = Arc::new(Exporter::new(&collector, "deepseek"));
+    turn(&exporter, &api).await.unwrap();
+    assert_eq!(exporter.flush(FLUSH_TIMEOUT).await, 0);
+
+    let spans = spans(&mut received);
+    assert_eq!(spans.len(), 2, "{:#?}", spans);
+    let http = spans.iter().find(|s| s["name"] == "POST").unwrap();
+    let chat = spans
+      .iter()
+      .find(|s| s["name"] == "chat deepseek-chat")
+      .unwrap();
+    // HTTP 请求挂在本轮下面
+    assert_eq!(http["traceId"], chat["traceId"]);
+    assert_eq!(http["parentSpanId"], chat["spanId"]);
+    assert!(chat.get("parentSpanId").is_none());
+    assert_eq!(http["kind"], KIND_CLIENT);
+    assert_eq!(attr(http, "http.response.status_code")["intValue"], "200");
+    assert_eq!(attr(http, "error.type"), Value::Null);
+
+    assert_eq!(attr(chat, "gen_ai.operation.name")["stringValue"], "chat");
+    assert_eq!(attr(chat, "gen_ai.system")["stringValue"], "deepseek");
+    assert_eq!(
+      attr(chat, "gen_ai.request.model")["stringValue"],
+      "deepseek-chat"
+    );
+    assert_eq!(attr(chat, "gen_ai.usage.input_tokens")["intValue"], "120");
+    assert_eq!(attr(chat, "gen_ai.usage.output_tokens")["intValue"], "30");
+    assert_eq!(attr(chat, "deepcli.turn.ttft_ms")["intValue"], "250");
+    assert_eq!(
+      attr(chat, "gen_ai.response.finish_reasons")["arrayValue"]["values"][0]["stringValue"],
+      "stop"
+    );
+    assert!(attr(chat, "deepcli.turn.latency_ms")["intValue"].is_string());
+    assert_eq!(chat["status"]["code"], 1);
+    assert_eq!(chat["traceId"].as_str().unwrap().len(), 32);
+    assert_eq!(chat["spanId"].as_str().unwrap().len(), 16);
+  }
+
+  #[tokio::test]
+  async fn test_failed_turn_spans() {
+    let (collector, mut received) = serve("200 OK", "{}").await;
+    let (api, _) = serve(
+      "401 Unauthorized",
+      r#"{"error":{"message":"Authentication Fails","type":"authentication_error"}}"#,
+    )
+    .await;
+    let exporter = Arc::new(Exporter::new(
+      &format!("{}/v1/traces", collector),
+      "deepseek",
+    ));
+    assert_eq!(turn(&exporter, &api).await.unwrap(), TurnEnd::RolledBack);
+    assert_eq!(exporter.flush(FLUSH_TIMEOUT).await, 0);
+
+    let spans = spans(&mut received);
+    assert_eq!(spans.len(), 2, "{:#?}", spans);
+    let http = spans.iter().find(|s| s["name"] == "POST").unwrap();
+    let chat = spans
+      .iter()
+      .find(|s| s["name"] == "chat deepseek-chat")
+      .unwrap();
+    assert_eq!(http["parentSpanId"], chat["spanId"]);
+    assert_eq!(attr(http, "http.response.status_code")["intValue"], "401");
+    assert_eq!(attr(http, "error.type")["stringValue"], "401");
+    assert_eq!(http["status"]["code"], 2);
+    // 本轮的错误类别取失败请求的状态码
+    assert_eq!(attr(chat, "error.type")["stringValue"], "401");
+    assert_eq!(attr(chat, "gen_ai.usage.output_tokens")["intValue"], "0");
+    assert_eq!(attr(chat, "gen_ai.response.finish_reasons"), Value::Null);
+    assert_eq!(chat["status"]["code"], 2);
+  }
+
+  #[tokio::test]
+  async fn test_unreachable_collector_is_bounded() {
+    // 绑定后立即释放的端口，连接会被拒绝
+    let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+      .await
+      .unwrap()
+      .local_addr()
+      .unwrap();
+    let exporter = Exporter::new(&format!("http://{}", addr), "deepseek");
+    let span = exporter.start_turn("deepseek-chat");
+    exporter.finish_turn(span, TurnRecord::default());
+    let started = std::time::Instant::now();
+    assert_eq!(exporter.flush(Duration::from_millis(500)).await, 1);
+    assert!(started.elapsed() < Duration::from_secs(1));
+  }
+}
@@ -0,0 +1,125 @@
+//! `--output`：单次提问的完整回答同时写入文件。回答完整收到后才写，
+//! 流式输出中途出错或被内容审核拦截时不会留下半份回答；覆盖时先写临时文件再改名
+
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+  /// 文件不能已经存在
+  Create,
+  /// --force
+  Overwrite,
+  /// --output-append：接在已有内容之后
+  Append,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+  path: PathBuf,
+  mode: Mode,
+}
+
+impl Output {
+  /// 在发出请求之前检查，文件已存在且不能覆盖时不浪费一次请求
+  pub fn new(path: &Path, mode: Mode) -> Result<Self> {
+    if path.is_dir() {
+      anyhow::bail!("--output {:?} is a directory", path);
+    }
+    if mode == Mode::Create && path.exists() {
+      anyhow::bail!(
+        "--output {:?} already exists; use --force to overwrite it or --output-append to add to it",
+        path
+      );
+    }
+    Ok(Self {
+      path: path.to_path_buf(),
+      mode,
+    })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// 写入完整回答，末尾保留一个换行
+  pub fn write(&self, reply: &str) -> Result<()> {
+    let text = format!("{}\n", reply.trim_end());
+    let failed = || format!("Failed to write --output {:?}", self.path);
+    match self.mode {
+      Mode::Create => OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&self.path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .with_context(failed),
+      Mode::Overwrite => {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".partial");
+        let tmp = PathBuf::from(tmp);
+        fs::write(&tmp, &text).with_context(failed)?;
+        fs::rename(&tmp, &self.path).with_context(failed)
+      }
+      Mode::Append => {
+        // 上一次的内容没有以换行结束时先补上，两次回答不会连在一行
+        let separator = match fs::read(&self.path) {
+          Ok(bytes) if bytes.last().is_some_and(|&b| b != b'\n') => "\n",
+          _ => "",
+        };
+        OpenOptions::new()
+          .append(true)
+          .create(true)
+          .open(&self.path)
+          .and_then(|mut file| file.write_all(format!("{}{}", separator, text).as_bytes()))
+          .with_context(failed)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+
+  #[test]
+  fn test_create_refuses_existing_file_and_force_overwrites() {
+    let dir = temp_dir("output-create");
+    let path = dir.join("answer.md");
+    Output::new(&path, Mode::Create)
+      .unwrap()
+      .write("# 标题\n\n正文\n\n")
+      .unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "# 标题\n\n正文\n");
+
+    let error = Output::new(&path, Mode::Create).unwrap_err().to_string();
+    assert!(error.contains("--force"), "{}", error);
+    assert!(Output::new(&dir, Mode::Overwrite).is_err());
+
+    Output::new(&path, Mode::Overwrite)
+      .unwrap()
+      .write("新的")
+      .unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "新的\n");
+    // 临时文件已经改名
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_append_accumulates_runs() {
+    let dir = temp_dir("output-append");
+    let path = dir.join("log.md");
+    let output = Output::new(&path, Mode::Append).unwrap();
+    output.write("第一次").unwrap();
+    output.write("第二次").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "第一次\n第二次\n");
+
+    fs::write(&path, "手写的内容").unwrap();
+    output.write("第三次").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "手写的内容\n第三次\n");
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
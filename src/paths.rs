@@ -0,0 +1,377 @@
+//! deepcli 使用的目录：配置（config.toml、models.yaml 等）、数据（会话、版本记录）和缓存
+//! （知识库索引、状态栏）。数据和缓存目录按 --data-dir/--cache-dir > 配置文件的
+//! data_dir/cache_dir > XDG_DATA_HOME/XDG_CACHE_HOME > 平台默认位置 的顺序决定，
+//! 配置目录只看 XDG_CONFIG_HOME 和平台默认位置。启动时用 [`init`] 解析一次。
+
+use crate::config::Source;
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+const APP: &str = "deepcli";
+
+static DIRS: OnceLock<Dirs> = OnceLock::new();
+
+/// 决定默认位置的平台
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+  /// Linux 和其他类 Unix 系统：~/.config、~/.local/share、~/.cache
+  Unix,
+  /// ~/Library/Application Support、~/Library/Caches
+  MacOs,
+  /// %APPDATA%、%LOCALAPPDATA%
+  Windows,
+}
+
+impl Platform {
+  pub fn current() -> Self {
+    match () {
+      _ if cfg!(target_os = "macos") => Self::MacOs,
+      _ if cfg!(windows) => Self::Windows,
+      _ => Self::Unix,
+    }
+  }
+}
+
+/// 一个目录和决定它的那一层
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+  pub path: PathBuf,
+  pub source: Source,
+}
+
+/// 命令行指定的目录，已按当前目录转成绝对路径
+#[derive(Debug, Clone, Default)]
+pub struct Flags {
+  pub data_dir: Option<PathBuf>,
+  pub cache_dir: Option<PathBuf>,
+}
+
+/// 找不到主目录（HOME、APPDATA 等都没有设置）时对应的目录为 None
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dirs {
+  pub config: Option<Location>,
+  pub data: Option<Location>,
+  pub cache: Option<Location>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+  Config,
+  Data,
+  Cache,
+}
+
+impl Kind {
+  fn xdg(self) -> &'static str {
+    match self {
+      Self::Config => "XDG_CONFIG_HOME",
+      Self::Data => "XDG_DATA_HOME",
+      Self::Cache => "XDG_CACHE_HOME",
+    }
+  }
+
+  /// 以前各平台都使用的 XDG 默认位置
+  fn unix(self, env: &impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    let home = PathBuf::from(var(env, "HOME")?);
+    Some(match self {
+      Self::Config => home.join(".config").join(APP),
+      Self::Data => home.join(".local").join("share").join(APP),
+      Self::Cache => home.join(".cache").join(APP),
+    })
+  }
+
+  fn platform(self, platform: Platform, env: &impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+    match platform {
+      Platform::Unix => self.unix(env),
+      Platform::MacOs => {
+        let library = PathBuf::from(var(env, "HOME")?).join("Library");
+        Some(match self {
+          Self::Config | Self::Data => library.join("Application Support").join(APP),
+          Self::Cache => library.join("Caches").join(APP),
+        })
+      }
+      Platform::Windows => {
+        let profile = || var(env, "USERPROFILE").map(|p| PathBuf::from(p).join("AppData"));
+        let (base, sub) = match self {
+          Self::Config => (var(env, "APPDATA"), "config"),
+          Self::Data => (var(env, "APPDATA"), "data"),
+          Self::Cache => (var(env, "LOCALAPPDATA"), "cache"),
+        };
+        let base = match (base, self) {
+          (Some(base), _) => PathBuf::from(base),
+          (None, Self::Cache) => profile()?.join("Local"),
+          (None, _) => profile()?.join("Roaming"),
+        };
+        Some(base.join(APP).join(sub))
+      }
+    }
+  }
+
+  /// XDG 变量（相对路径按规范忽略），否则平台默认位置。macOS 和 Windows 上新位置还不存在
+  /// 而旧的 XDG 默认位置存在时继续使用旧位置，升级前的会话和配置不会丢失
+  fn resolve(
+    self,
+    platform: Platform,
+    env: &impl Fn(&str) -> Option<String>,
+    exists: &impl Fn(&Path) -> bool,
+  ) -> Option<Location> {
+    if let Some(dir) = var(env, self.xdg()).map(PathBuf::from)
+      && dir.is_absolute()
+    {
+      return Some(Location {
+        path: dir.join(APP),
+        source: Source::Env(self.xdg().to_string()),
+      });
+    }
+    let default = self.platform(platform, env)?;
+    let path = match self.unix(env) {
+      Some(legacy) if platform != Platform::Unix && !exists(&default) && exists(&legacy) => legacy,
+      _ => default,
+    };
+    Some(Location {
+      path,
+      source: Source::Default,
+    })
+  }
+}
+
+fn var(env: &impl Fn(&str) -> Option<String>, key: &str) -> Option<String> {
+  env(key).filter(|v| !v.is_empty())
+}
+
+impl Dirs {
+  /// 只由环境决定的目录；`exists` 用于判断旧位置是否还在使用
+  pub fn resolve(
+    platform: Platform,
+    env: impl Fn(&str) -> Option<String>,
+    exists: impl Fn(&Path) -> bool,
+  ) -> Self {
+    Self {
+      config: Kind::Config.resolve(platform, &env, &exists),
+      data: Kind::Data.resolve(platform, &env, &exists),
+      cache: Kind::Cache.resolve(platform, &env, &exists),
+    }
+  }
+
+  /// 按命令行和配置文件覆盖数据和缓存目录
+  pub fn with_overrides(mut self, flags: &Flags, file: &crate::config::FileConfig) -> Self {
+    let pick = |flag: &Option<PathBuf>, name, key: &Option<PathBuf>, current| match (flag, key) {
+      (Some(path), _) => Some(Location {
+        path: path.clone(),
+        source: Source::Flag(name),
+      }),
+      (None, Some(path)) => Some(Location {
+        path: path.clone(),
+        source: file.source(),
+      }),
+      (None, None) => current,
+    };
+    self.data = pick(&flags.data_dir, "--data-dir", &file.data_dir, self.data);
+    self.cache = pick(&flags.cache_dir, "--cache-dir", &file.cache_dir, self.cache);
+    self
+  }
+
+  /// 当前进程的环境
+  pub fn system() -> Self {
+    Self::resolve(
+      Platform::current(),
+      |key| std::env::var(key).ok(),
+      Path::exists,
+    )
+  }
+}
+
+/// 启动时解析出的目录，之后的 data_dir() 等都使用它
+pub fn init(dirs: Dirs) {
+  let _ = DIRS.set(dirs);
+}
+
+/// init 之前（以及测试中）按当前环境解析
+fn current<T>(f: impl FnOnce(&Dirs) -> T) -> T {
+  match DIRS.get() {
+    Some(dirs) => f(dirs),
+    None => f(&Dirs::system()),
+  }
+}
+
+/// 数据目录：会话、自动快照和版本记录
+pub fn data_dir() -> Option<PathBuf> {
+  current(|d| d.data.as_ref().map(|l| l.path.clone()))
+}
+
+pub fn sessions_dir() -> Option<PathBuf> {
+  data_dir().map(|d| d.join("sessions"))
+}
+
+/// 缓存目录：知识库索引和状态栏缓存，可以随时删除
+pub fn cache_dir() -> Option<PathBuf> {
+  current(|d| d.cache.as_ref().map(|l| l.path.clone()))
+}
+
+/// 配置目录：config.toml、models.yaml、plugins.yaml、starters.yaml
+pub fn config_dir() -> Option<PathBuf> {
+  current(|d| d.config.as_ref().map(|l| l.path.clone()))
+}
+
+/// 需要写入时才创建目录；失败时的错误给出绝对路径
+pub fn ensure(dir: &Path) -> anyhow::Result<()> {
+  std::fs::create_dir_all(dir).with_context(|| {
+    let shown = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+    format!("Cannot create directory {}", shown.display())
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  fn resolve(platform: Platform, vars: &[(&str, &str)], existing: &[&str]) -> Dirs {
+    let env: HashMap<&str, &str> = vars.iter().copied().collect();
+    Dirs::resolve(
+      platform,
+      |key| env.get(key).map(|v| v.to_string()),
+      |path| existing.iter().any(|e| Path::new(e) == path),
+    )
+  }
+
+  fn path(location: &Option<Location>) -> PathBuf {
+    location.as_ref().unwrap().path.clone()
+  }
+
+  fn join(base: &str, parts: &[&str]) -> PathBuf {
+    parts
+      .iter()
+      .fold(PathBuf::from(base), |p, part| p.join(part))
+  }
+
+  #[test]
+  fn test_platform_defaults() {
+    let unix = resolve(Platform::Unix, &[("HOME", "/home/a")], &[]);
+    assert_eq!(path(&unix.config), join("/home/a", &[".config", APP]));
+    assert_eq!(path(&unix.data), join("/home/a", &[".local", "share", APP]));
+    assert_eq!(path(&unix.cache), join("/home/a", &[".cache", APP]));
+    assert_eq!(unix.data.unwrap().source, Source::Default);
+
+    let mac = resolve(Platform::MacOs, &[("HOME", "/Users/a")], &[]);
+    let support = join("/Users/a", &["Library", "Application Support", APP]);
+    assert_eq!(path(&mac.config), support);
+    assert_eq!(path(&mac.data), support);
+    assert_eq!(
+      path(&mac.cache),
+      join("/Users/a", &["Library", "Caches", APP])
+    );
+
+    let roaming = r"C:\Users\a\AppData\Roaming";
+    let local = r"C:\Users\a\AppData\Local";
+    let vars = [("APPDATA", roaming), ("LOCALAPPDATA", local)];
+    let windows = resolve(Platform::Windows, &vars, &[]);
+    assert_eq!(path(&windows.config), join(roaming, &[APP, "config"]));
+    assert_eq!(path(&windows.data), join(roaming, &[APP, "data"]));
+    assert_eq!(path(&windows.cache), join(local, &[APP, "cache"]));
+    // 没有 APPDATA 时从 USERPROFILE 推出
+    let windows = resolve(Platform::Windows, &[("USERPROFILE", r"C:\Users\a")], &[]);
+    assert_eq!(
+      path(&windows.cache),
+      join(r"C:\Users\a", &["AppData", "Local", APP, "cache"])
+    );
+
+    let nothing = resolve(Platform::Unix, &[("HOME", "")], &[]);
+    assert_eq!(
+      (nothing.config, nothing.data, nothing.cache),
+      (None, None, None)
+    );
+  }
+
+  #[test]
+  fn test_xdg_variables_win_on_every_platform() {
+    let vars = [
+      ("HOME", "/home/a"),
+      ("USERPROFILE", r"C:\Users\a"),
+      ("XDG_CONFIG_HOME", "/cfg"),
+      ("XDG_DATA_HOME", "/data"),
+      // 相对路径按规范忽略
+      ("XDG_CACHE_HOME", "cache"),
+    ];
+    for platform in [Platform::Unix, Platform::MacOs, Platform::Windows] {
+      let dirs = resolve(platform, &vars, &[]);
+      assert_eq!(path(&dirs.config), join("/cfg", &[APP]), "{:?}", platform);
+      assert_eq!(path(&dirs.data), join("/data", &[APP]), "{:?}", platform);
+      let data = dirs.data.unwrap();
+      assert_eq!(data.source.to_string(), "from env XDG_DATA_HOME");
+      assert_eq!(
+        dirs.cache.unwrap().source,
+        Source::Default,
+        "{:?}",
+        platform
+      );
+    }
+  }
+
+  #[test]
+  fn test_legacy_location_kept_after_upgrade() {
+    let legacy = "/Users/a/.local/share/deepcli";
+    let mac = resolve(Platform::MacOs, &[("HOME", "/Users/a")], &[legacy]);
+    assert_eq!(path(&mac.data), PathBuf::from(legacy));
+    // 其他目录不受影响；新位置已存在时不再看旧位置
+    assert_eq!(
+      path(&mac.config),
+      join("/Users/a", &["Library", "Application Support", APP])
+    );
+    let support = "/Users/a/Library/Application Support/deepcli";
+    let mac = resolve(Platform::MacOs, &[("HOME", "/Users/a")], &[legacy, support]);
+    assert_eq!(path(&mac.data), PathBuf::from(support));
+  }
+
+  #[test]
+  fn test_flags_override_config_file_and_env() {
+    let file = crate::config::FileConfig {
+      path: PathBuf::from("/etc/deepcli.toml"),
+      data_dir: Some(PathBuf::from("/project/data")),
+      cache_dir: Some(PathBuf::from("/project/cache")),
+      ..Default::default()
+    };
+    let flags = Flags {
+      data_dir: Some(PathBuf::from("/tmp/data")),
+      cache_dir: None,
+    };
+    let vars = [
+      ("HOME", "/home/a"),
+      ("USERPROFILE", r"C:\Users\a"),
+      ("XDG_DATA_HOME", "/xdg"),
+    ];
+    for platform in [Platform::Unix, Platform::MacOs, Platform::Windows] {
+      let dirs = resolve(platform, &vars, &[]).with_overrides(&flags, &file);
+      let data = dirs.data.unwrap();
+      assert_eq!(data.path, PathBuf::from("/tmp/data"));
+      assert_eq!(data.source.to_string(), "from --data-dir");
+      let cache = dirs.cache.unwrap();
+      assert_eq!(cache.path, PathBuf::from("/project/cache"));
+      assert_eq!(cache.source.to_string(), "from /etc/deepcli.toml");
+      // 配置目录不能覆盖
+      assert_eq!(dirs.config.unwrap().source, Source::Default);
+    }
+    let dirs = resolve(Platform::Unix, &vars, &[])
+      .with_overrides(&Flags::default(), &crate::config::FileConfig::default());
+    assert_eq!(
+      dirs.data.unwrap().source.to_string(),
+      "from env XDG_DATA_HOME"
+    );
+  }
+
+  #[test]
+  fn test_ensure_names_the_directory() {
+    let dir = std::env::temp_dir().join(format!("deepcli-paths-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let blocker = dir.join("file");
+    std::fs::write(&blocker, "").unwrap();
+    ensure(&dir.join("a/b")).unwrap();
+    let error = ensure(&blocker.join("sub")).unwrap_err();
+    assert_eq!(
+      error.to_string(),
+      format!("Cannot create directory {}", blocker.join("sub").display())
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Markdown 人设文件：可选的 front-matter（model、temperature）加上作为 system prompt 的正文
+#[derive(Debug, Clone, PartialEq)]
+pub struct Persona {
+  pub path: PathBuf,
+  pub prompt: String,
+  pub model: Option<String>,
+  pub temperature: Option<f32>,
+}
+
+impl Persona {
+  /// 文件名（不含扩展名），用于说明设置来自哪个人设
+  pub fn name(&self) -> String {
+    self
+      .path
+      .file_stem()
+      .map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+  }
+
+  pub fn load(path: &Path) -> Result<Self> {
+    let text = crate::sandbox::read_to_string(path)
+      .context(format!("Failed to read persona file: {:?}", path))?;
+    let mut persona = parse(&text).context(format!("Invalid persona file: {:?}", path))?;
+    persona.path = path.to_path_buf();
+    Ok(persona)
+  }
+}
+
+/// 解析 `---` 包围的 front-matter，只支持 `key: value` 形式
+pub fn parse(text: &str) -> Result<Persona> {
+  let text = text.trim_start_matches('\u{feff}');
+  let mut persona = Persona {
+    path: PathBuf::new(),
+    prompt: text.trim().to_string(),
+    model: None,
+    temperature: None,
+  };
+  let mut lines = text.lines();
+  if lines.next().map(str::trim_end) != Some("---") {
+    return Ok(persona);
+  }
+  let mut header = vec![];
+  let mut closed = false;
+  for line in lines.by_ref() {
+    if line.trim_end() == "---" {
+      closed = true;
+      break;
+    }
+    header.push(line);
+  }
+  if !closed {
+    anyhow::bail!("front-matter is not closed with '---'");
+  }
+  for line in header {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    let (key, value) = line
+      .split_once(':')
+      .context(format!("expected 'key: value', got '{}'", line))?;
+    let value = value.trim().trim_matches('"').trim_matches('\'');
+    match key.trim() {
+      "model" => persona.model = Some(value.to_string()),
+      "temperature" => {
+        let temp: f32 = value
+          .parse()
+          .context(format!("invalid temperature '{}'", value))?;
+        if !(0.0..=2.0).contains(&temp) {
+          anyhow::bail!("temperature must be between 0.0 and 2.0, got {}", temp);
+        }
+        persona.temperature = Some(temp);
+      }
+      other => eprintln!("[警告] 人设文件中未知的键: {}", other),
+    }
+  }
+  persona.prompt = lines.collect::<Vec<_>>().join("\n").trim().to_string();
+  Ok(persona)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use std::fs;
+
+  #[test]
+  fn test_parse_plain_markdown() {
+    let persona = parse("# SRE\n\nYou are an on-call helper.\n").unwrap();
+    assert_eq!(persona.prompt, "# SRE\n\nYou are an on-call helper.");
+    assert_eq!(persona.model, None);
+    assert_eq!(persona.temperature, None);
+  }
+
+  #[test]
+  fn test_parse_front_matter() {
+    let text = "---\nmodel: chat\ntemperature: 0.3\n# comment\n---\n你是一名法律审阅助手。\n";
+    let persona = parse(text).unwrap();
+    assert_eq!(persona.model.as_deref(), Some("chat"));
+    assert_eq!(persona.temperature, Some(0.3));
+    assert_eq!(persona.prompt, "你是一名法律审阅助手。");
+
+    let persona = parse("\u{feff}---\r\nmodel: \"r1\"\r\n---\r\nBody\r\n").unwrap();
+    assert_eq!(persona.model.as_deref(), Some("r1"));
+    assert_eq!(persona.prompt, "Body");
+  }
+
+  #[test]
+  fn test_parse_errors() {
+    assert!(parse("---\nmodel: chat\nno closing").is_err());
+    assert!(parse("---\ntemperature: hot\n---\nx").is_err());
+    assert!(parse("---\ntemperature: 3.5\n---\nx").is_err());
+    assert!(parse("---\njust text\n---\nx").is_err());
+  }
+
+  #[test]
+  fn test_reload_picks_up_edits() {
+    let dir = temp_dir("persona");
+    let path = dir.join("sre.md");
+    fs::write(&path, "You are an SRE.").unwrap();
+    let first = Persona::load(&path).unwrap();
+    assert_eq!(first.prompt, "You are an SRE.");
+    assert_eq!(first.path, path);
+
+    fs::write(&path, "---\ntemperature: 0.1\n---\nYou are a careful SRE.").unwrap();
+    let second = Persona::load(&path).unwrap();
+    assert_eq!(second.prompt, "You are a careful SRE.");
+    assert_eq!(second.temperature, Some(0.1));
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
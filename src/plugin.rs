@@ -0,0 +1,489 @@
+//! 自定义工具插件。plugins.yaml 列出可执行文件，每个插件：
+//!
+//! - 以 `--manifest` 运行时在 stdout 输出 JSON 清单：
+//!   `{"name": ..., "description": ..., "parameters": <JSON Schema>}`
+//! - 被调用时从 stdin 读入参数 JSON，stdout 即为返回给模型的结果
+//!
+//! 插件出错（超时、非零退出、输出过大）时把错误说明作为结果交给模型，不中断对话。
+
+use crate::api::{FunctionSpec, ToolSpec};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+/// 调用的默认时长上限
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 结果的默认字节上限，超出部分截断
+pub const DEFAULT_MAX_OUTPUT: usize = 16 * 1024;
+
+/// 读取 `--manifest` 输出的时长与字节上限
+const MANIFEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MANIFEST_MAX_BYTES: usize = 64 * 1024;
+
+/// 出错时随错误说明附带的 stderr 字节数
+const STDERR_BYTES: usize = 2048;
+
+/// 插件以这个前缀开头的结果表示调用失败
+pub const ERROR_PREFIX: &str = "[插件错误]";
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+  #[serde(default)]
+  plugins: Vec<Entry>,
+}
+
+/// plugins.yaml 中的一项
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+  path: PathBuf,
+  #[serde(default = "enabled_by_default")]
+  enabled: bool,
+  /// 每次调用的时长上限（秒）
+  timeout: Option<u64>,
+  /// 结果的字节上限
+  max_output: Option<usize>,
+}
+
+fn enabled_by_default() -> bool {
+  true
+}
+
+/// `--manifest` 的输出
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Manifest {
+  pub name: String,
+  pub description: String,
+  pub parameters: serde_json::Value,
+}
+
+/// 检查清单是否能作为 tools 数组中的函数
+pub fn validate(manifest: &Manifest) -> Result<(), String> {
+  if !crate::api::valid_name(&manifest.name) {
+    return Err(format!(
+      "name {:?} must be 1-64 letters, digits, _ or -",
+      manifest.name
+    ));
+  }
+  if manifest.description.trim().is_empty() {
+    return Err("description must not be empty".to_string());
+  }
+  let Some(schema) = manifest.parameters.as_object() else {
+    return Err("parameters must be a JSON Schema object".to_string());
+  };
+  if schema.get("type").and_then(|t| t.as_str()) != Some("object") {
+    return Err(r#"parameters must have "type": "object""#.to_string());
+  }
+  Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct Plugin {
+  pub path: PathBuf,
+  pub manifest: Manifest,
+  timeout: Duration,
+  max_output: usize,
+}
+
+/// 已加载的插件
+#[derive(Debug, Default)]
+pub struct Plugins {
+  plugins: Vec<Plugin>,
+}
+
+impl Plugins {
+  /// 隔离模式下不运行外部程序：有配置文件时提示后返回空的插件列表，否则同 load
+  pub async fn load_unless_isolated(
+    path: Option<&Path>,
+    isolation: &crate::isolation::Isolation,
+  ) -> Result<(Self, Vec<String>)> {
+    if let Err(e) = isolation.check(crate::isolation::Feature::ShellTools) {
+      if path.is_some_and(Path::is_file) {
+        eprintln!("[隔离] {}", e);
+      }
+      return Ok((Self::default(), vec![]));
+    }
+    Self::load(path).await
+  }
+
+  /// 读取 `path` 中的配置并询问每个启用的插件的清单。配置文件格式错误时返回错误；
+  /// 单个插件无法加载时跳过它，说明放在第二个返回值中
+  pub async fn load(path: Option<&Path>) -> Result<(Self, Vec<String>)> {
+    let Some(path) = path.filter(|p| p.is_file()) else {
+      return Ok((Self::default(), vec![]));
+    };
+    let yaml = std::fs::read_to_string(path).context(format!("Failed to read {:?}", path))?;
+    let file: File =
+      serde_yaml::from_str(&yaml).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    let mut plugins = Self::default();
+    let mut errors = vec![];
+    for entry in file.plugins.into_iter().filter(|e| e.enabled) {
+      match plugins.add(entry.clone()).await {
+        Ok(()) => {}
+        Err(e) => errors.push(format!("插件 {} 未加载: {}", entry.path.display(), e)),
+      }
+    }
+    Ok((plugins, errors))
+  }
+
+  async fn add(&mut self, entry: Entry) -> Result<(), String> {
+    let stdout = run(
+      &entry.path,
+      Some("--manifest"),
+      b"",
+      MANIFEST_TIMEOUT,
+      MANIFEST_MAX_BYTES,
+    )
+    .await?;
+    if stdout.truncated {
+      return Err(format!("manifest exceeds {} bytes", MANIFEST_MAX_BYTES));
+    }
+    let manifest: Manifest =
+      serde_json::from_slice(&stdout.bytes).map_err(|e| format!("invalid manifest: {}", e))?;
+    validate(&manifest).map_err(|e| format!("invalid manifest: {}", e))?;
+    if let Some(other) = self.get(&manifest.name) {
+      return Err(format!(
+        "tool name '{}' is already provided by {}",
+        manifest.name,
+        other.path.display()
+      ));
+    }
+    self.plugins.push(Plugin {
+      path: entry.path,
+      manifest,
+      timeout: entry.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs),
+      max_output: entry.max_output.unwrap_or(DEFAULT_MAX_OUTPUT),
+    });
+    Ok(())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.plugins.is_empty()
+  }
+
+  pub fn names(&self) -> Vec<&str> {
+    self
+      .plugins
+      .iter()
+      .map(|p| p.manifest.name.as_str())
+      .collect()
+  }
+
+  fn get(&self, name: &str) -> Option<&Plugin> {
+    self.plugins.iter().find(|p| p.manifest.name == name)
+  }
+
+  /// 请求 tools 数组中的函数
+  pub fn tools(&self) -> Vec<ToolSpec> {
+    self
+      .plugins
+      .iter()
+      .map(|p| ToolSpec {
+        kind: "function",
+        function: FunctionSpec {
+          name: p.manifest.name.clone(),
+          description: p.manifest.description.clone(),
+          parameters: p.manifest.parameters.clone(),
+        },
+      })
+      .collect()
+  }
+
+  /// 执行模型的一次调用，返回交给模型的结果
+  pub async fn call(&self, name: &str, arguments: &str) -> String {
+    let Some(plugin) = self.get(name) else {
+      return format!("{} unknown tool '{}'", ERROR_PREFIX, name);
+    };
+    // 模型生成的参数不一定合法，不合法时不启动插件，让模型自己改正
+    let arguments = match arguments.trim() {
+      "" => "{}",
+      trimmed => trimmed,
+    };
+    if !serde_json::from_str::<serde_json::Value>(arguments).is_ok_and(|v| v.is_object()) {
+      return format!("{} arguments must be a JSON object", ERROR_PREFIX);
+    }
+    match run(
+      &plugin.path,
+      None,
+      arguments.as_bytes(),
+      plugin.timeout,
+      plugin.max_output,
+    )
+    .await
+    {
+      Ok(output) => {
+        let mut text = String::from_utf8_lossy(&output.bytes).into_owned();
+        if output.truncated {
+          text.push_str(&format!("\n[输出超过 {} 字节，已截断]", plugin.max_output));
+        }
+        text
+      }
+      Err(e) => format!("{} {}", ERROR_PREFIX, e),
+    }
+  }
+}
+
+/// 插件的 stdout，最多 `max_bytes` 字节
+#[derive(Debug)]
+struct Output {
+  bytes: Vec<u8>,
+  truncated: bool,
+}
+
+/// 运行插件：写入 stdin 后关闭，读取 stdout。超时或输出超过上限时结束进程
+async fn run(
+  path: &Path,
+  arg: Option<&str>,
+  input: &[u8],
+  timeout: Duration,
+  max_bytes: usize,
+) -> Result<Output, String> {
+  let mut command = Command::new(path);
+  command.args(arg);
+  let mut child = command
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .kill_on_drop(true)
+    .spawn()
+    .map_err(|e| format!("failed to start {}: {}", path.display(), e))?;
+  let mut stdin = child.stdin.take().expect("stdin is piped");
+  let mut stdout = child.stdout.take().expect("stdout is piped");
+  let mut stderr = child.stderr.take().expect("stderr is piped");
+  let input = input.to_vec();
+  // 插件可能不读 stdin，写入失败不算错误
+  let write = async move {
+    let _ = stdin.write_all(&input).await;
+  };
+  let mut bytes = vec![];
+  let mut errors = vec![];
+  let read = async {
+    let out = async {
+      let read = (&mut stdout)
+        .take(max_bytes as u64 + 1)
+        .read_to_end(&mut bytes)
+        .await;
+      if bytes.len() > max_bytes {
+        // 剩下的输出不再读取，结束进程，否则它会阻塞在写入上
+        let _ = child.start_kill();
+      }
+      read
+    };
+    let err = async {
+      (&mut stderr)
+        .take(STDERR_BYTES as u64)
+        .read_to_end(&mut errors)
+        .await
+    };
+    let (_, out, _) = tokio::join!(write, out, err);
+    out
+  };
+  match tokio::time::timeout(timeout, read).await {
+    Err(_) => return Err(format!("timed out after {}s", timeout.as_secs_f32())),
+    Ok(Err(e)) => return Err(format!("failed to read output: {}", e)),
+    Ok(Ok(_)) => {}
+  }
+  if bytes.len() > max_bytes {
+    let _ = child.wait().await;
+    bytes.truncate(max_bytes);
+    return Ok(Output {
+      bytes,
+      truncated: true,
+    });
+  }
+  let status = match tokio::time::timeout(timeout, child.wait()).await {
+    Err(_) => return Err(format!("timed out after {}s", timeout.as_secs_f32())),
+    Ok(status) => status.map_err(|e| e.to_string())?,
+  };
+  if !status.success() {
+    let stderr = String::from_utf8_lossy(&errors);
+    return Err(format!("{} ({})", status, stderr.trim()));
+  }
+  Ok(Output {
+    bytes,
+    truncated: false,
+  })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use std::os::unix::fs::PermissionsExt;
+
+  /// 示例插件，也用在 turn 的测试中
+  pub(crate) fn example() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/plugins/ticket.sh")
+  }
+
+  /// 在 `dir` 中写一个可执行的 shell 脚本
+  fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+  }
+
+  fn manifest(name: &str) -> String {
+    format!(
+      r#"[ "$1" = --manifest ] && echo '{{"name":"{}","description":"test tool","parameters":{{"type":"object"}}}}' && exit 0"#,
+      name
+    )
+  }
+
+  pub(crate) fn config(dir: &Path, entries: &[(&Path, &str)]) -> PathBuf {
+    let mut yaml = "plugins:\n".to_string();
+    for (path, extra) in entries {
+      yaml.push_str(&format!("  - path: {}\n{}", path.display(), extra));
+    }
+    let path = dir.join("plugins.yaml");
+    std::fs::write(&path, yaml).unwrap();
+    path
+  }
+
+  #[test]
+  fn test_validate_manifest() {
+    let ok = Manifest {
+      name: "lookup_ticket".to_string(),
+      description: "Look up a ticket".to_string(),
+      parameters: serde_json::json!({"type": "object", "properties": {}}),
+    };
+    assert_eq!(validate(&ok), Ok(()));
+    let bad = [
+      Manifest {
+        name: "lookup ticket".to_string(),
+        ..ok.clone()
+      },
+      Manifest {
+        description: " ".to_string(),
+        ..ok.clone()
+      },
+      Manifest {
+        parameters: serde_json::json!({"type": "string"}),
+        ..ok.clone()
+      },
+      Manifest {
+        parameters: serde_json::json!([]),
+        ..ok.clone()
+      },
+    ];
+    for manifest in bad {
+      assert!(validate(&manifest).is_err(), "{:?}", manifest);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_load_skips_disabled_and_invalid_plugins() {
+    let dir = temp_dir("plugin-load");
+    let good = script(&dir, "good.sh", &manifest("good"));
+    let twin = script(&dir, "twin.sh", &manifest("good"));
+    let broken = script(&dir, "broken.sh", "echo 'not json'");
+    let invalid = script(&dir, "invalid.sh", &manifest("has space"));
+    let off = script(&dir, "off.sh", "exit 1");
+    let path = config(
+      &dir,
+      &[
+        (&good, ""),
+        (&twin, ""),
+        (&broken, ""),
+        (&invalid, ""),
+        (&off, "    enabled: false\n"),
+        (&dir.join("missing.sh"), ""),
+      ],
+    );
+    let (plugins, errors) = Plugins::load(Some(&path)).await.unwrap();
+    assert_eq!(plugins.names(), ["good"]);
+    assert_eq!(errors.len(), 4, "{:?}", errors);
+    assert!(errors[0].contains("already provided"), "{}", errors[0]);
+    assert!(errors[1].contains("invalid manifest"), "{}", errors[1]);
+    assert!(errors[2].contains("must be 1-64"), "{}", errors[2]);
+    assert!(errors[3].contains("failed to start"), "{}", errors[3]);
+
+    std::fs::write(&path, "plugins:\n  - path: x\n    enable: true\n").unwrap();
+    assert!(Plugins::load(Some(&path)).await.is_err());
+    let (plugins, _) = Plugins::load(Some(&dir.join("none.yaml"))).await.unwrap();
+    assert!(plugins.is_empty());
+
+    // 隔离模式下不询问插件，也就不运行任何外部程序
+    let path = config(&dir, &[(&good, "")]);
+    let isolated = crate::isolation::Isolation::new(true);
+    let (plugins, errors) = Plugins::load_unless_isolated(Some(&path), &isolated)
+      .await
+      .unwrap();
+    assert!(plugins.is_empty() && errors.is_empty());
+    let open = crate::isolation::Isolation::new(false);
+    let (plugins, _) = Plugins::load_unless_isolated(Some(&path), &open)
+      .await
+      .unwrap();
+    assert_eq!(plugins.names(), ["good"]);
+  }
+
+  #[tokio::test]
+  async fn test_call_enforces_timeout_size_and_exit_status() {
+    let dir = temp_dir("plugin-call");
+    let slow = script(&dir, "slow.sh", &format!("{}\nsleep 5", manifest("slow")));
+    let big = script(
+      &dir,
+      "big.sh",
+      &format!(
+        "{}\nwhile :; do echo xxxxxxxxxxxxxxx; done",
+        manifest("big")
+      ),
+    );
+    let fail = script(
+      &dir,
+      "fail.sh",
+      &format!("{}\necho 'no such ticket' >&2; exit 3", manifest("fail")),
+    );
+    let path = config(
+      &dir,
+      &[
+        (&slow, "    timeout: 1\n"),
+        (&big, "    max_output: 64\n"),
+        (&fail, ""),
+      ],
+    );
+    let (plugins, errors) = Plugins::load(Some(&path)).await.unwrap();
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    let started = std::time::Instant::now();
+    let result = plugins.call("slow", "{}").await;
+    assert!(result.starts_with(ERROR_PREFIX) && result.contains("timed out"));
+    assert!(started.elapsed() < Duration::from_secs(3));
+
+    let result = plugins.call("big", "{}").await;
+    assert!(result.starts_with(&"xxxxxxxxxxxxxxx\n".repeat(4)));
+    assert!(result.ends_with("[输出超过 64 字节，已截断]"), "{}", result);
+
+    let result = plugins.call("fail", "{}").await;
+    assert!(result.starts_with(ERROR_PREFIX), "{}", result);
+    assert!(result.contains("no such ticket"), "{}", result);
+
+    assert!(plugins.call("fail", "[1]").await.contains("JSON object"));
+    assert!(plugins.call("nope", "{}").await.contains("unknown tool"));
+  }
+
+  #[tokio::test]
+  async fn test_example_plugin() {
+    let dir = temp_dir("plugin-example");
+    let path = config(&dir, &[(&example(), "")]);
+    let (plugins, errors) = Plugins::load(Some(&path)).await.unwrap();
+    assert!(errors.is_empty(), "{:?}", errors);
+    let tools = serde_json::to_value(plugins.tools()).unwrap();
+    assert_eq!(tools[0]["type"], "function");
+    assert_eq!(tools[0]["function"]["name"], "lookup_ticket");
+    assert_eq!(
+      tools[0]["function"]["parameters"]["required"][0],
+      serde_json::json!("id")
+    );
+    let result = plugins.call("lookup_ticket", r#"{"id": "OPS-42"}"#).await;
+    let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+    assert_eq!(result["id"], "OPS-42");
+  }
+}
@@ -0,0 +1,436 @@
+/// 内置的服务提供方配置：接口地址和读取密钥的环境变量。各提供方的模型名见 models.yaml
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+  pub name: &'static str,
+  pub base_url: &'static str,
+  pub api_key_env: &'static str,
+}
+
+pub const DASHSCOPE: Profile = Profile {
+  name: "dashscope",
+  base_url: "https://dashscope.aliyuncs.com/compatible-mode/v1",
+  api_key_env: "DASHSCOPE_API_KEY",
+};
+
+pub const DEEPSEEK: Profile = Profile {
+  name: "deepseek",
+  base_url: "https://api.deepseek.com",
+  api_key_env: "DEEPSEEK_API_KEY",
+};
+
+pub const BUILTIN_PROFILES: [Profile; 2] = [DASHSCOPE, DEEPSEEK];
+
+pub fn find_profile(name: &str) -> Option<Profile> {
+  BUILTIN_PROFILES.iter().copied().find(|p| p.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedProfile {
+  pub profile: Profile,
+  pub api_key: String,
+  /// 未指定 --profile 且回退到了非默认的 deepseek 配置
+  pub auto_selected: bool,
+}
+
+/// 根据 --profile 和环境变量选择配置。`env` 用于读取环境变量，便于测试时传入快照。
+///
+/// 未指定 --profile 时，优先使用 DASHSCOPE_API_KEY（保持原有行为），
+/// 仅设置了 DEEPSEEK_API_KEY 时自动选择官方 deepseek 配置。
+pub fn resolve_profile(
+  requested: Option<&str>,
+  env: impl Fn(&str) -> Option<String>,
+) -> Result<ResolvedProfile, String> {
+  let lookup = |p: &Profile| env(p.api_key_env).filter(|v| !v.trim().is_empty());
+
+  if let Some(name) = requested {
+    let profile = find_profile(name).ok_or_else(|| {
+      let names: Vec<_> = BUILTIN_PROFILES.iter().map(|p| p.name).collect();
+      format!(
+        "Unknown profile '{}'. Use one of: {}",
+        name,
+        names.join(", ")
+      )
+    })?;
+    let api_key = lookup(&profile).ok_or_else(|| {
+      format!(
+        "{} environment variable not set (required by profile '{}')",
+        profile.api_key_env, profile.name
+      )
+    })?;
+    return Ok(ResolvedProfile {
+      profile,
+      api_key,
+      auto_selected: false,
+    });
+  }
+
+  if let Some(api_key) = lookup(&DASHSCOPE) {
+    return Ok(ResolvedProfile {
+      profile: DASHSCOPE,
+      api_key,
+      auto_selected: false,
+    });
+  }
+  if let Some(api_key) = lookup(&DEEPSEEK) {
+    return Ok(ResolvedProfile {
+      profile: DEEPSEEK,
+      api_key,
+      auto_selected: true,
+    });
+  }
+  Err(format!(
+    "{} or {} environment variable not set",
+    DASHSCOPE.api_key_env, DEEPSEEK.api_key_env
+  ))
+}
+
+/// 密钥从哪里读到
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource {
+  Env,
+  DotEnv,
+  /// 配置文件中的 api_key
+  ConfigFile,
+}
+
+/// 为什么选择了这个配置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+  /// --profile
+  Requested,
+  /// 未指定 --profile，DASHSCOPE_API_KEY 优先（原有行为）
+  DashscopeFirst,
+  /// 未指定 --profile，只有 DEEPSEEK_API_KEY
+  OnlyDeepseek,
+}
+
+/// 带来源和原因的选择结果，启动时据此报告用了哪个密钥、发往哪里
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+  pub resolved: ResolvedProfile,
+  pub source: KeySource,
+  pub reason: Reason,
+  /// 多个来源同时存在，且结果与只读取环境变量时不同
+  pub warning: Option<String>,
+}
+
+impl Resolution {
+  /// 启动时显示的一行说明
+  pub fn summary(&self) -> String {
+    let profile = self.resolved.profile;
+    let key = match self.source {
+      KeySource::Env => format!("{}（环境变量）", profile.api_key_env),
+      KeySource::DotEnv => format!("{}（.env）", profile.api_key_env),
+      KeySource::ConfigFile => "配置文件中的 api_key ".to_string(),
+    };
+    let reason = match self.reason {
+      Reason::Requested => "由 --profile 指定".to_string(),
+      Reason::DashscopeFirst => format!("未指定 --profile，{} 优先", DASHSCOPE.api_key_env),
+      Reason::OnlyDeepseek => format!("未指定 --profile，且未设置 {}", DASHSCOPE.api_key_env),
+    };
+    format!(
+      "使用 {}→ {} 配置 ({})：{}",
+      key, profile.name, profile.base_url, reason
+    )
+  }
+}
+
+/// 与 resolve_profile 相同的规则，另外区分真实环境变量 `env` 和 .env 文件 `dotenv`（前者优先）。
+/// 两者都是快照，没有副作用
+pub fn resolve_explained(
+  requested: Option<&str>,
+  env: impl Fn(&str) -> Option<String>,
+  dotenv: impl Fn(&str) -> Option<String>,
+) -> Result<Resolution, String> {
+  let present = |value: Option<String>| value.filter(|v| !v.trim().is_empty());
+  let resolved = resolve_profile(requested, |key| env(key).or_else(|| dotenv(key)))?;
+  let source = match present(env(resolved.profile.api_key_env)) {
+    Some(_) => KeySource::Env,
+    None => KeySource::DotEnv,
+  };
+  let reason = match (requested, resolved.auto_selected) {
+    (Some(_), _) => Reason::Requested,
+    (None, false) => Reason::DashscopeFirst,
+    (None, true) => Reason::OnlyDeepseek,
+  };
+  // 以前只读取环境变量：.env 中的密钥让隐式选择换了服务方时提醒，避免把请求发到意料之外的地方
+  let warning = match resolve_profile(requested, &env) {
+    Ok(before) if requested.is_none() && before.profile != resolved.profile => Some(format!(
+      "环境变量中有 {}（{} 配置），但 .env 中的 {} 优先，请求将发往 {}；用 --profile {} 明确指定可消除此提示",
+      before.profile.api_key_env,
+      before.profile.name,
+      resolved.profile.api_key_env,
+      resolved.profile.base_url,
+      before.profile.name
+    )),
+    _ => None,
+  };
+  Ok(Resolution {
+    resolved,
+    source,
+    reason,
+    warning,
+  })
+}
+
+/// 环境变量和 .env 都没有可用的密钥时，改用配置文件中的 `api_key`：
+/// 发往指定的配置，未指定时与自动选择一样优先 DASHSCOPE
+pub fn resolve_with_file_key(
+  requested: Option<&str>,
+  env: impl Fn(&str) -> Option<String>,
+  dotenv: impl Fn(&str) -> Option<String>,
+  file_key: Option<&str>,
+) -> Result<Resolution, String> {
+  let err = match resolve_explained(requested, env, dotenv) {
+    Ok(resolution) => return Ok(resolution),
+    Err(e) => e,
+  };
+  let Some(api_key) = file_key else {
+    return Err(err);
+  };
+  let (profile, reason) = match requested {
+    Some(name) => (find_profile(name).ok_or(err)?, Reason::Requested),
+    None => (DASHSCOPE, Reason::DashscopeFirst),
+  };
+  Ok(Resolution {
+    resolved: ResolvedProfile {
+      profile,
+      api_key: api_key.to_string(),
+      auto_selected: false,
+    },
+    source: KeySource::ConfigFile,
+    reason,
+    warning: None,
+  })
+}
+
+/// 记录本次运行的版本；返回 true 表示首次运行或刚升级了版本
+pub fn first_run_of_version(state: &std::path::Path, version: &str) -> bool {
+  if std::fs::read_to_string(state).is_ok_and(|v| v.trim() == version) {
+    return false;
+  }
+  if let Some(dir) = state.parent() {
+    let _ = std::fs::create_dir_all(dir);
+  }
+  let _ = std::fs::write(state, version);
+  true
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  fn env_of(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let map: HashMap<String, String> = vars
+      .iter()
+      .map(|(k, v)| (k.to_string(), v.to_string()))
+      .collect();
+    move |key| map.get(key).cloned()
+  }
+
+  #[test]
+  fn test_auto_selection_matrix() {
+    // 只有 DASHSCOPE_API_KEY
+    let r = resolve_profile(None, env_of(&[("DASHSCOPE_API_KEY", "ds")])).unwrap();
+    assert_eq!(r.profile, DASHSCOPE);
+    assert_eq!(r.api_key, "ds");
+    assert!(!r.auto_selected);
+
+    // 只有 DEEPSEEK_API_KEY
+    let r = resolve_profile(None, env_of(&[("DEEPSEEK_API_KEY", "dk")])).unwrap();
+    assert_eq!(r.profile, DEEPSEEK);
+    assert_eq!(r.api_key, "dk");
+    assert!(r.auto_selected);
+
+    // 两者都有时保持原有的 dashscope 行为
+    let r = resolve_profile(
+      None,
+      env_of(&[("DASHSCOPE_API_KEY", "ds"), ("DEEPSEEK_API_KEY", "dk")]),
+    )
+    .unwrap();
+    assert_eq!(r.profile, DASHSCOPE);
+    assert!(!r.auto_selected);
+
+    // 都没有
+    assert!(resolve_profile(None, env_of(&[])).is_err());
+
+    // 空值视为未设置
+    let r = resolve_profile(
+      None,
+      env_of(&[("DASHSCOPE_API_KEY", ""), ("DEEPSEEK_API_KEY", "dk")]),
+    )
+    .unwrap();
+    assert_eq!(r.profile, DEEPSEEK);
+  }
+
+  #[test]
+  fn test_explicit_profile() {
+    let env = env_of(&[("DASHSCOPE_API_KEY", "ds"), ("DEEPSEEK_API_KEY", "dk")]);
+    let r = resolve_profile(Some("deepseek"), &env).unwrap();
+    assert_eq!(r.profile, DEEPSEEK);
+    assert_eq!(r.api_key, "dk");
+    assert!(!r.auto_selected);
+
+    let err = resolve_profile(Some("deepseek"), env_of(&[("DASHSCOPE_API_KEY", "ds")]));
+    assert!(err.unwrap_err().contains("DEEPSEEK_API_KEY"));
+
+    assert!(resolve_profile(Some("unknown"), &env).is_err());
+  }
+
+  #[test]
+  fn test_resolution_matrix() {
+    use KeySource::{DotEnv, Env};
+    use Reason::*;
+    type Vars = &'static [(&'static str, &'static str)];
+    type Expected = Option<(Profile, KeySource, Reason, bool)>;
+    // (--profile, 环境变量, .env, 期望的配置/来源/原因/是否警告；None 为出错)
+    let cases: &[(Option<&str>, Vars, Vars, Expected)] = &[
+      // 只有 DASHSCOPE_API_KEY：原有行为
+      (
+        None,
+        &[("DASHSCOPE_API_KEY", "ds")],
+        &[],
+        Some((DASHSCOPE, Env, DashscopeFirst, false)),
+      ),
+      (
+        None,
+        &[],
+        &[("DASHSCOPE_API_KEY", "ds")],
+        Some((DASHSCOPE, DotEnv, DashscopeFirst, false)),
+      ),
+      // 只有 DEEPSEEK_API_KEY
+      (
+        None,
+        &[("DEEPSEEK_API_KEY", "dk")],
+        &[],
+        Some((DEEPSEEK, Env, OnlyDeepseek, false)),
+      ),
+      (
+        None,
+        &[],
+        &[("DEEPSEEK_API_KEY", "dk")],
+        Some((DEEPSEEK, DotEnv, OnlyDeepseek, false)),
+      ),
+      // 两个密钥都在环境变量中：与以前相同
+      (
+        None,
+        &[("DASHSCOPE_API_KEY", "ds"), ("DEEPSEEK_API_KEY", "dk")],
+        &[],
+        Some((DASHSCOPE, Env, DashscopeFirst, false)),
+      ),
+      // 冲突：环境变量的 DEEPSEEK 被 .env 的 DASHSCOPE 取代
+      (
+        None,
+        &[("DEEPSEEK_API_KEY", "dk")],
+        &[("DASHSCOPE_API_KEY", "ds")],
+        Some((DASHSCOPE, DotEnv, DashscopeFirst, true)),
+      ),
+      // 环境变量的 DASHSCOPE 仍然优先于 .env 的 DEEPSEEK
+      (
+        None,
+        &[("DASHSCOPE_API_KEY", "ds")],
+        &[("DEEPSEEK_API_KEY", "dk")],
+        Some((DASHSCOPE, Env, DashscopeFirst, false)),
+      ),
+      // 空的环境变量不算设置
+      (
+        None,
+        &[("DASHSCOPE_API_KEY", " ")],
+        &[("DEEPSEEK_API_KEY", "dk")],
+        Some((DEEPSEEK, DotEnv, OnlyDeepseek, false)),
+      ),
+      // 明确指定时不再提醒
+      (
+        Some("deepseek"),
+        &[("DASHSCOPE_API_KEY", "ds")],
+        &[("DEEPSEEK_API_KEY", "dk")],
+        Some((DEEPSEEK, DotEnv, Requested, false)),
+      ),
+      (
+        Some("dashscope"),
+        &[("DEEPSEEK_API_KEY", "dk")],
+        &[("DASHSCOPE_API_KEY", "ds")],
+        Some((DASHSCOPE, DotEnv, Requested, false)),
+      ),
+      (Some("deepseek"), &[("DASHSCOPE_API_KEY", "ds")], &[], None),
+      (None, &[], &[], None),
+    ];
+    for (requested, env, dotenv, expected) in cases {
+      let result = resolve_explained(*requested, env_of(env), env_of(dotenv));
+      let actual = result
+        .as_ref()
+        .ok()
+        .map(|r| (r.resolved.profile, r.source, r.reason, r.warning.is_some()));
+      assert_eq!(actual, *expected, "{:?} {:?} {:?}", requested, env, dotenv);
+    }
+
+    let r = resolve_explained(
+      None,
+      env_of(&[("DEEPSEEK_API_KEY", "dk")]),
+      env_of(&[("DASHSCOPE_API_KEY", "ds")]),
+    )
+    .unwrap();
+    assert_eq!(r.resolved.api_key, "ds");
+    assert!(r.warning.as_ref().unwrap().contains("--profile deepseek"));
+    assert_eq!(
+      r.summary(),
+      format!(
+        "使用 DASHSCOPE_API_KEY（.env）→ dashscope 配置 ({})：未指定 --profile，DASHSCOPE_API_KEY 优先",
+        DASHSCOPE.base_url
+      )
+    );
+  }
+
+  #[test]
+  fn test_file_key_is_the_last_resort() {
+    // 环境变量中的密钥优先于配置文件
+    let r = resolve_with_file_key(
+      None,
+      env_of(&[("DEEPSEEK_API_KEY", "dk")]),
+      env_of(&[]),
+      Some("file"),
+    )
+    .unwrap();
+    assert_eq!(
+      (r.resolved.api_key.as_str(), r.source),
+      ("dk", KeySource::Env)
+    );
+
+    let r = resolve_with_file_key(None, env_of(&[]), env_of(&[]), Some("file")).unwrap();
+    assert_eq!(r.resolved.profile, DASHSCOPE);
+    assert_eq!(
+      (r.resolved.api_key.as_str(), r.source),
+      ("file", KeySource::ConfigFile)
+    );
+    assert!(
+      r.summary().contains("配置文件中的 api_key"),
+      "{}",
+      r.summary()
+    );
+
+    // 指定的配置没有密钥时也用配置文件中的
+    let r = resolve_with_file_key(
+      Some("deepseek"),
+      env_of(&[("DASHSCOPE_API_KEY", "ds")]),
+      env_of(&[]),
+      Some("file"),
+    )
+    .unwrap();
+    assert_eq!(r.resolved.profile, DEEPSEEK);
+    assert_eq!(r.resolved.api_key, "file");
+
+    assert!(resolve_with_file_key(None, env_of(&[]), env_of(&[]), None).is_err());
+    assert!(resolve_with_file_key(Some("unknown"), env_of(&[]), env_of(&[]), Some("k")).is_err());
+  }
+
+  #[test]
+  fn test_first_run_of_version() {
+    let dir = crate::checkpoint::temp_dir("first-run");
+    let state = dir.join("state/last-version");
+    assert!(first_run_of_version(&state, "0.1.0"));
+    assert!(!first_run_of_version(&state, "0.1.0"));
+    assert!(first_run_of_version(&state, "0.2.0"));
+    std::fs::remove_dir_all(dir).ok();
+  }
+}
@@ -0,0 +1,390 @@
+use crate::api::{Content, Message, TextContent};
+use crate::contract::Contract;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful assistant.";
+
+/// 简短模式下默认的 max_tokens 上限，显式的 -l 仍然优先
+pub const BRIEF_MAX_TOKENS: u32 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnswerLength {
+  Brief,
+  #[default]
+  Normal,
+  Detailed,
+}
+
+impl AnswerLength {
+  pub fn from_flags(brief: bool, detailed: bool) -> Self {
+    match (brief, detailed) {
+      (true, _) => AnswerLength::Brief,
+      (_, true) => AnswerLength::Detailed,
+      _ => AnswerLength::Normal,
+    }
+  }
+
+  pub fn instruction(self) -> Option<&'static str> {
+    match self {
+      AnswerLength::Brief => Some("Answer in at most 3 sentences."),
+      AnswerLength::Normal => None,
+      AnswerLength::Detailed => Some("Give a thorough answer with examples."),
+    }
+  }
+
+  /// `requested` 为显式指定的上限（-l、环境变量或配置文件），`model_default` 为模型默认输出上限
+  pub fn max_tokens(self, requested: Option<u32>, model_default: u32) -> u32 {
+    match (self, requested) {
+      (_, Some(n)) => n,
+      (AnswerLength::Brief, None) => model_default.min(BRIEF_MAX_TOKENS),
+      (_, None) => model_default,
+    }
+  }
+
+  /// `max_tokens` 的结果由哪一层决定，`requested` 为显式上限的来源，`model` 为取默认上限的模型
+  pub fn max_tokens_source(
+    self,
+    requested: Option<&crate::config::Source>,
+    model_default: u32,
+    model: &str,
+  ) -> crate::config::Source {
+    use crate::config::Source;
+    match (self, requested) {
+      (_, Some(source)) => source.clone(),
+      (AnswerLength::Brief, None) if model_default > BRIEF_MAX_TOKENS => Source::Preset("brief"),
+      (_, None) => Source::Model(model.to_string()),
+    }
+  }
+
+  /// `length` 设置项的值
+  pub fn name(self) -> &'static str {
+    match self {
+      AnswerLength::Brief => "brief",
+      AnswerLength::Normal => "normal",
+      AnswerLength::Detailed => "detailed",
+    }
+  }
+}
+
+/// 请求中的 system 部分，按固定顺序组装：
+///
+/// 1. 第一条 system 消息：`stable`（人设，以后的项目上下文与记忆也放在这里），
+///    会话内不变，保证请求前缀可以命中服务端的前缀缓存；
+/// 2. 对话历史；
+/// 3. 最后一条 system 消息：`volatile` 按加入顺序用空格连接（长度要求、引用格式等），
+///    为空时省略。
+///
+/// 输出约定单独占一条 system 消息：会话开始时就有的紧跟在 stable 之后，
+/// 会话中改动过的移到历史之后、volatile 之前。
+///
+/// 只改变 volatile 或改动约定时，前两部分序列化后逐字节相同
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemPrompt {
+  pub stable: String,
+  pub volatile: Vec<String>,
+  pub contract: Option<String>,
+  /// 约定在会话中改动过，不再放进可缓存的前缀
+  pub contract_edited: bool,
+  /// --no-system：不发送默认的 stable，其余 system 内容（人设、约定等）附加到第一条用户消息
+  pub no_system: bool,
+}
+
+/// 把所有 system 消息移出，内容依次附加到第一条用户消息开头；返回是否移动了内容
+pub fn fold_system(messages: &mut Vec<Message>) -> bool {
+  let mut folded = vec![];
+  messages.retain(|m| match m {
+    Message::Simple { role, content, .. } if role == "system" => {
+      folded.push(content.clone());
+      false
+    }
+    Message::MultiModal { role, .. } => role != "system",
+    _ => true,
+  });
+  folded.retain(|c| !c.trim().is_empty());
+  if folded.is_empty() {
+    return false;
+  }
+  let prefix = folded.join("\n\n");
+  let first_user = messages.iter_mut().find(
+    |m| matches!(m, Message::Simple { role, .. } | Message::MultiModal { role, .. } if role == "user"),
+  );
+  match first_user {
+    Some(Message::Simple { content, .. } | Message::Tool { content, .. }) => {
+      *content = format!("{}\n\n{}", prefix, content)
+    }
+    Some(Message::MultiModal { content, .. }) => content.insert(
+      0,
+      Content::Text(TextContent {
+        content_type: "text".to_string(),
+        text: prefix,
+      }),
+    ),
+    None => messages.insert(
+      0,
+      Message::Simple {
+        role: "user".to_string(),
+        content: prefix,
+        name: None,
+      },
+    ),
+  }
+  true
+}
+
+static FOLD_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// 第一次把 system 内容附加到用户消息时提示一次
+pub fn warn_folded() {
+  if !FOLD_WARNED.swap(true, Ordering::Relaxed) {
+    eprintln!(
+      "[警告] 未发送 system 消息：输出约定、长度要求、附件等 system 内容已附加到第一条用户消息"
+    );
+  }
+}
+
+impl SystemPrompt {
+  pub fn new(base: &str, length: AnswerLength) -> Self {
+    Self {
+      stable: base.trim_end().to_string(),
+      volatile: length
+        .instruction()
+        .map(str::to_string)
+        .into_iter()
+        .collect(),
+      contract: None,
+      contract_edited: false,
+      no_system: false,
+    }
+  }
+
+  pub fn with_no_system(mut self, no_system: bool) -> Self {
+    self.no_system = no_system;
+    self
+  }
+
+  /// 是否有 system 内容要附加到用户消息（--no-system 且有人设、约定或 volatile）
+  pub fn folds(&self) -> bool {
+    self.no_system
+      && (self.stable != DEFAULT_SYSTEM_PROMPT
+        || self.contract.is_some()
+        || !self.volatile.is_empty())
+  }
+
+  pub fn with_contract(mut self, contract: &Contract) -> Self {
+    self.contract = contract.message();
+    self.contract_edited = contract.edited();
+    self
+  }
+
+  pub fn with_volatile(mut self, instruction: &str) -> Self {
+    self.volatile.push(instruction.to_string());
+    self
+  }
+
+  pub fn messages(&self, history: &[Message]) -> Vec<Message> {
+    let system = |content: String| Message::Simple {
+      role: "system".to_string(),
+      content,
+      name: None,
+    };
+    let mut messages = match self.no_system && self.stable == DEFAULT_SYSTEM_PROMPT {
+      true => vec![],
+      false => vec![system(self.stable.clone())],
+    };
+    let contract = self.contract.clone().map(system);
+    let (pinned, trailing) = match self.contract_edited {
+      false => (contract, None),
+      true => (None, contract),
+    };
+    messages.extend(pinned);
+    messages.extend(history.iter().cloned());
+    messages.extend(trailing);
+    if !self.volatile.is_empty() {
+      messages.push(system(self.volatile.join(" ")));
+    }
+    if self.no_system {
+      fold_system(&mut messages);
+    }
+    messages
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_from_flags() {
+    assert_eq!(AnswerLength::from_flags(false, false), AnswerLength::Normal);
+    assert_eq!(AnswerLength::from_flags(true, false), AnswerLength::Brief);
+    assert_eq!(
+      AnswerLength::from_flags(false, true),
+      AnswerLength::Detailed
+    );
+  }
+
+  fn user(content: &str) -> Message {
+    Message::Simple {
+      role: "user".to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  fn text(message: &Message) -> &str {
+    match message {
+      Message::Simple { content, .. } | Message::Tool { content, .. } => content,
+      Message::MultiModal { .. } => "",
+    }
+  }
+
+  fn json(messages: &[Message]) -> String {
+    serde_json::to_string(messages).unwrap()
+  }
+
+  #[test]
+  fn test_system_prompt_order() {
+    let history = [user("hi")];
+    let normal = SystemPrompt::new(DEFAULT_SYSTEM_PROMPT, AnswerLength::Normal);
+    assert_eq!(
+      json(&normal.messages(&history)),
+      r#"[{"role":"system","content":"You are a helpful assistant."},{"role":"user","content":"hi"}]"#
+    );
+    let brief = SystemPrompt::new(DEFAULT_SYSTEM_PROMPT, AnswerLength::Brief)
+      .with_volatile("Cite file:line.");
+    let messages = brief.messages(&history);
+    assert_eq!(messages.len(), 3);
+    assert!(json(&messages).ends_with(
+      r#"{"role":"system","content":"Answer in at most 3 sentences. Cite file:line."}]"#
+    ));
+  }
+
+  #[test]
+  fn test_prefix_stable_across_volatile_changes() {
+    let assistant = Message::Simple {
+      role: "assistant".to_string(),
+      content: "a1".to_string(),
+      name: None,
+    };
+    let history = [user("q1"), assistant, user("q2")];
+    let prompts = [
+      SystemPrompt::new("Persona.", AnswerLength::Normal),
+      SystemPrompt::new("Persona.", AnswerLength::Brief),
+      SystemPrompt::new("Persona.", AnswerLength::Detailed).with_volatile("Cite file:line."),
+    ];
+    // 不含 volatile 的部分逐字节相同
+    let stable = json(&prompts[0].messages(&history));
+    let prefix = stable.trim_end_matches(']');
+    for prompt in &prompts {
+      assert!(json(&prompt.messages(&history)).starts_with(prefix));
+    }
+    // 上一轮的请求去掉末尾的 volatile 后，是下一轮请求的前缀
+    let first = json(&prompts[1].messages(&history[..1]));
+    let first_stable = json(&prompts[0].messages(&history[..1]));
+    assert_ne!(first, first_stable);
+    assert!(stable.starts_with(first_stable.trim_end_matches(']')));
+  }
+
+  #[test]
+  fn test_contract_placement() {
+    let history = [user("q1")];
+    let base = SystemPrompt::new("Persona.", AnswerLength::Brief);
+    let mut contract = Contract::new(vec!["No emoji.".to_string()]);
+    let messages = base.clone().with_contract(&contract).messages(&history);
+    // 会话开始时的约定属于前缀：紧跟 stable
+    let texts: Vec<_> = messages.iter().map(text).collect();
+    assert_eq!(texts.len(), 4);
+    assert!(texts[1].ends_with("1. No emoji."));
+    assert_eq!(texts[2], "q1");
+
+    // 改动后移到历史之后，前缀与没有约定时相同
+    contract.add("Use tabs.");
+    let messages = base.clone().with_contract(&contract).messages(&history);
+    let plain = json(&base.messages(&history));
+    let edited = json(&messages);
+    assert!(
+      edited.starts_with(
+        plain
+          .split(r#",{"role":"system","content":"Answer"#)
+          .next()
+          .unwrap()
+      )
+    );
+    let texts: Vec<_> = messages.iter().map(text).collect();
+    assert!(texts[2].ends_with("2. Use tabs."));
+    assert_eq!(texts[3], "Answer in at most 3 sentences.");
+  }
+
+  #[test]
+  fn test_no_system_folds_into_first_user_message() {
+    let assistant = Message::Simple {
+      role: "assistant".to_string(),
+      content: "a1".to_string(),
+      name: None,
+    };
+    let history = [user("q1"), assistant, user("q2")];
+    // 默认的 system prompt 直接省略
+    let plain = SystemPrompt::new(DEFAULT_SYSTEM_PROMPT, AnswerLength::Normal).with_no_system(true);
+    assert_eq!(json(&plain.messages(&history)), json(&history));
+    assert!(!plain.folds());
+
+    let contract = Contract::new(vec!["No emoji.".to_string()]);
+    let prompt = SystemPrompt::new("Persona.", AnswerLength::Brief)
+      .with_contract(&contract)
+      .with_no_system(true);
+    let messages = prompt.messages(&history);
+    assert!(!json(&messages).contains(r#""role":"system""#));
+    let texts: Vec<_> = messages.iter().map(text).collect();
+    assert_eq!(texts.len(), 3);
+    assert_eq!(
+      texts[0],
+      format!(
+        "Persona.\n\n{}\n1. No emoji.\n\nAnswer in at most 3 sentences.\n\nq1",
+        crate::contract::HEADER
+      )
+    );
+    assert!(prompt.folds());
+    assert_eq!(texts[2], "q2");
+
+    // 没有用户消息时作为一条用户消息发送
+    let mut messages = vec![Message::Simple {
+      role: "system".to_string(),
+      content: "Be terse.".to_string(),
+      name: None,
+    }];
+    assert!(fold_system(&mut messages));
+    assert_eq!(json(&messages), json(&[user("Be terse.")]));
+    assert!(!fold_system(&mut messages));
+  }
+
+  #[test]
+  fn test_max_tokens_interaction() {
+    // 简短模式只收紧默认值
+    assert_eq!(AnswerLength::Brief.max_tokens(None, 8192), BRIEF_MAX_TOKENS);
+    assert_eq!(AnswerLength::Brief.max_tokens(None, 512), 512);
+    // 显式 -l 优先
+    assert_eq!(AnswerLength::Brief.max_tokens(Some(4000), 8192), 4000);
+    assert_eq!(AnswerLength::Normal.max_tokens(None, 8192), 8192);
+    assert_eq!(AnswerLength::Normal.max_tokens(Some(100), 8192), 100);
+    assert_eq!(AnswerLength::Detailed.max_tokens(None, 65536), 65536);
+    assert_eq!(AnswerLength::Detailed.max_tokens(Some(300), 65536), 300);
+    use crate::config::Source;
+    assert_eq!(
+      AnswerLength::Brief.max_tokens_source(None, 8192, "deepseek-chat"),
+      Source::Preset("brief")
+    );
+    // 模型上限本来就更小时，简短模式没有起作用
+    assert_eq!(
+      AnswerLength::Brief.max_tokens_source(None, 512, "tiny"),
+      Source::Model("tiny".into())
+    );
+    assert_eq!(
+      AnswerLength::Brief.max_tokens_source(
+        Some(&Source::Flag("--max_tokens")),
+        8192,
+        "deepseek-chat"
+      ),
+      Source::Flag("--max_tokens")
+    );
+  }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub(crate) const REDACTED: &str = "[redacted]";
+
+/// 构建时确定的信息
+#[derive(Debug, Clone, Copy)]
+pub struct BuildInfo {
+  pub version: &'static str,
+  pub git_describe: Option<&'static str>,
+}
+
+impl BuildInfo {
+  pub fn current() -> Self {
+    Self {
+      version: env!("CARGO_PKG_VERSION"),
+      git_describe: option_env!("DEEPCLI_GIT_DESCRIBE"),
+    }
+  }
+}
+
+/// 会话开始时的运行环境，保存到会话文件中，便于事后知道是哪个版本和设置产生的结果
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+  pub version: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub git_describe: Option<String>,
+  pub profile: String,
+  pub base_url: String,
+  pub model: String,
+  #[serde(default)]
+  pub settings: BTreeMap<String, String>,
+}
+
+/// 纯函数：由构建信息和生效的设置生成环境信息。`secret` 出现在任何字段中都会被替换
+pub fn capture(
+  build: &BuildInfo,
+  profile: &str,
+  base_url: &str,
+  model: &str,
+  settings: &[(&str, String)],
+  secret: Option<&str>,
+) -> Provenance {
+  let redact = |value: &str| match secret.filter(|s| !s.is_empty()) {
+    Some(secret) => value.replace(secret, REDACTED),
+    None => value.to_string(),
+  };
+  Provenance {
+    version: build.version.to_string(),
+    git_describe: build.git_describe.map(|d| d.to_string()),
+    profile: redact(profile),
+    base_url: redact(base_url),
+    model: redact(model),
+    settings: settings
+      .iter()
+      .map(|(key, value)| {
+        let value = if is_secret_key(key) {
+          REDACTED.to_string()
+        } else {
+          redact(value)
+        };
+        (key.to_string(), value)
+      })
+      .collect(),
+  }
+}
+
+pub(crate) fn is_secret_key(key: &str) -> bool {
+  let key = key.to_ascii_lowercase();
+  ["key", "token", "secret", "password"]
+    .iter()
+    .any(|s| key.contains(s) && key != "max_tokens")
+}
+
+impl Provenance {
+  /// `key: value` 形式，用于 --version --verbose，也可作为导出文件的 front-matter
+  pub fn render(&self) -> String {
+    let mut lines = vec![format!("version: {}", self.version)];
+    if let Some(describe) = &self.git_describe {
+      lines.push(format!("git: {}", describe));
+    }
+    lines.push(format!("profile: {}", self.profile));
+    lines.push(format!("base_url: {}", self.base_url));
+    lines.push(format!("model: {}", self.model));
+    for (key, value) in &self.settings {
+      lines.push(format!("{}: {}", key, value));
+    }
+    lines.join("\n")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const BUILD: BuildInfo = BuildInfo {
+    version: "0.1.0",
+    git_describe: Some("v0.1.0-3-gabc1234"),
+  };
+
+  #[test]
+  fn test_capture_and_render() {
+    let env = capture(
+      &BUILD,
+      "deepseek",
+      "https://api.deepseek.com",
+      "deepseek-reasoner",
+      &[
+        ("temperature", "0.7".to_string()),
+        ("max_tokens", "8192".to_string()),
+      ],
+      Some("sk-live"),
+    );
+    assert_eq!(env.settings["max_tokens"], "8192");
+    let rendered = env.render();
+    assert!(rendered.starts_with("version: 0.1.0\ngit: v0.1.0-3-gabc1234\nprofile: deepseek"));
+    assert!(rendered.contains("max_tokens: 8192\ntemperature: 0.7"));
+
+    let no_git = BuildInfo {
+      git_describe: None,
+      ..BUILD
+    };
+    let env = capture(&no_git, "dashscope", "u", "m", &[], None);
+    assert!(!env.render().contains("git:"));
+    assert!(
+      serde_json::to_string(&env)
+        .unwrap()
+        .find("git_describe")
+        .is_none()
+    );
+  }
+
+  #[test]
+  fn test_no_secret_material_in_output() {
+    let secret = "sk-0123456789abcdef";
+    let env = capture(
+      &BUILD,
+      "dashscope",
+      &format!("https://proxy.example.com/v1?key={}", secret),
+      "deepseek-r1",
+      &[
+        ("api_key", secret.to_string()),
+        ("auth_token", "other-token".to_string()),
+        ("system_prompt", format!("use {} to log in", secret)),
+        ("user_id", "alice@corp".to_string()),
+      ],
+      Some(secret),
+    );
+    let rendered = env.render();
+    let json = serde_json::to_string(&env).unwrap();
+    for output in [&rendered, &json] {
+      assert!(!output.contains(secret), "{}", output);
+      assert!(!output.contains("other-token"), "{}", output);
+    }
+    assert_eq!(env.settings["api_key"], REDACTED);
+    // 用户标识用于审计，不是密钥
+    assert_eq!(env.settings["user_id"], "alice@corp");
+  }
+}
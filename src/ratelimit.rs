@@ -0,0 +1,226 @@
+use reqwest::header::HeaderMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 不保存的响应头：有些代理会把请求里的 Authorization 原样回显
+const HIDDEN: &[&str] = &[
+  "authorization",
+  "proxy-authorization",
+  "cookie",
+  "set-cookie",
+];
+/// 剩余请求数不超过这个值时开始放慢，把剩余额度平摊到重置前
+const LOW_REMAINING: u64 = 3;
+/// 没有给出重置时间时，额度用完后等待的时长
+const DEFAULT_RESET: Duration = Duration::from_secs(1);
+
+/// 一次响应的状态码和响应头，供 `\headers` 查看
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Headers {
+  pub status: u16,
+  pub entries: Vec<(String, String)>,
+}
+
+impl Headers {
+  pub fn capture(status: u16, headers: &HeaderMap) -> Self {
+    let entries = headers
+      .iter()
+      .filter(|(name, _)| !HIDDEN.contains(&name.as_str()))
+      .map(|(name, value)| {
+        (
+          name.as_str().to_string(),
+          String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        )
+      })
+      .filter(|(_, value)| !value.starts_with("Bearer "))
+      .collect();
+    Self { status, entries }
+  }
+
+  /// 名字不区分大小写；同名的头取第一个
+  pub fn get(&self, name: &str) -> Option<&str> {
+    self
+      .entries
+      .iter()
+      .find(|(n, _)| n.eq_ignore_ascii_case(name))
+      .map(|(_, v)| v.as_str())
+  }
+
+  pub fn limits(&self) -> RateLimits {
+    let number = |name: &str| self.get(name).and_then(|v| v.trim().parse().ok());
+    let duration = |name: &str| self.get(name).and_then(parse_reset);
+    RateLimits {
+      remaining_requests: number("x-ratelimit-remaining-requests"),
+      remaining_tokens: number("x-ratelimit-remaining-tokens"),
+      reset_requests: duration("x-ratelimit-reset-requests"),
+      reset_tokens: duration("x-ratelimit-reset-tokens"),
+      retry_after: duration("retry-after"),
+    }
+  }
+
+  pub fn render(&self) -> String {
+    let mut lines = vec![format!("HTTP {}", self.status)];
+    lines.extend(self.entries.iter().map(|(n, v)| format!("{}: {}", n, v)));
+    lines.join("\n")
+  }
+}
+
+/// 解析 `30`、`1.5s`、`20ms`、`6m0s` 这样的时长
+pub fn parse_reset(value: &str) -> Option<Duration> {
+  let value = value.trim();
+  if let Ok(secs) = value.parse::<f64>() {
+    return Duration::try_from_secs_f64(secs).ok();
+  }
+  let mut total = 0.0;
+  let mut rest = value;
+  while !rest.is_empty() {
+    let split = rest
+      .find(|c: char| !c.is_ascii_digit() && c != '.')
+      .filter(|&i| i > 0)?;
+    let number: f64 = rest[..split].parse().ok()?;
+    rest = &rest[split..];
+    let unit = rest
+      .find(|c: char| c.is_ascii_digit())
+      .unwrap_or(rest.len());
+    total += number
+      * match &rest[..unit] {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return None,
+      };
+    rest = &rest[unit..];
+  }
+  Duration::try_from_secs_f64(total).ok()
+}
+
+/// 响应头中与限流有关的部分
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits {
+  pub remaining_requests: Option<u64>,
+  pub remaining_tokens: Option<u64>,
+  pub reset_requests: Option<Duration>,
+  pub reset_tokens: Option<Duration>,
+  pub retry_after: Option<Duration>,
+}
+
+/// 客户端限流：根据最近一次响应的限流头，在额度用完之前就推迟下一个请求
+#[derive(Debug, Default)]
+pub struct Limiter {
+  /// 下一个请求不早于这个时刻发出
+  not_before: Mutex<Option<Instant>>,
+}
+
+impl Limiter {
+  pub fn observe(&self, limits: &RateLimits, now: Instant) {
+    let mut wait = limits.retry_after;
+    match limits.remaining_requests {
+      Some(0) => wait = wait.max(Some(limits.reset_requests.unwrap_or(DEFAULT_RESET))),
+      // 剩余不多时把重置前的时间平摊给剩下的请求
+      Some(n) if n <= LOW_REMAINING => {
+        wait = wait.max(limits.reset_requests.map(|reset| reset / (n as u32 + 1)));
+      }
+      _ => {}
+    }
+    if limits.remaining_tokens == Some(0) {
+      wait = wait.max(Some(limits.reset_tokens.unwrap_or(DEFAULT_RESET)));
+    }
+    *self.not_before.lock().unwrap() = wait.map(|wait| now + wait);
+  }
+
+  /// 发出下一个请求前需要等待的时间
+  pub fn delay(&self, now: Instant) -> Option<Duration> {
+    self
+      .not_before
+      .lock()
+      .unwrap()
+      .and_then(|at| at.checked_duration_since(now))
+      .filter(|d| !d.is_zero())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use reqwest::header::HeaderValue;
+
+  fn limits(pairs: &[(&'static str, &'static str)]) -> RateLimits {
+    let mut map = HeaderMap::new();
+    for (name, value) in pairs {
+      map.insert(*name, HeaderValue::from_static(value));
+    }
+    Headers::capture(200, &map).limits()
+  }
+
+  #[test]
+  fn test_parse_reset() {
+    let cases = [
+      ("30", Some(30_000)),
+      ("1.5s", Some(1_500)),
+      ("20ms", Some(20)),
+      ("6m0s", Some(360_000)),
+      ("1h2m", Some(3_720_000)),
+      ("soon", None),
+      ("5x", None),
+    ];
+    for (value, millis) in cases {
+      assert_eq!(
+        parse_reset(value),
+        millis.map(Duration::from_millis),
+        "{}",
+        value
+      );
+    }
+  }
+
+  #[test]
+  fn test_limiter_slows_down_before_429() {
+    let now = Instant::now();
+    let limiter = Limiter::default();
+    let case = |pairs: &[(&'static str, &'static str)]| {
+      limiter.observe(&limits(pairs), now);
+      limiter.delay(now)
+    };
+    assert_eq!(case(&[("x-ratelimit-remaining-requests", "50")]), None);
+    // 还剩 3 次、10 秒后重置：每次间隔 2.5 秒
+    assert_eq!(
+      case(&[
+        ("x-ratelimit-remaining-requests", "3"),
+        ("x-ratelimit-reset-requests", "10s"),
+      ]),
+      Some(Duration::from_millis(2_500))
+    );
+    assert_eq!(
+      case(&[
+        ("x-ratelimit-remaining-requests", "0"),
+        ("x-ratelimit-reset-requests", "6s"),
+      ]),
+      Some(Duration::from_secs(6))
+    );
+    assert_eq!(
+      case(&[
+        ("x-ratelimit-remaining-tokens", "0"),
+        ("x-ratelimit-reset-tokens", "1m"),
+        ("retry-after", "2"),
+      ]),
+      Some(Duration::from_secs(60))
+    );
+    assert_eq!(case(&[("retry-after", "2")]), Some(Duration::from_secs(2)));
+    assert_eq!(limiter.delay(now + Duration::from_secs(3)), None);
+  }
+
+  #[test]
+  fn test_capture_drops_credentials() {
+    let mut map = HeaderMap::new();
+    map.insert(
+      "authorization",
+      HeaderValue::from_static("Bearer sk-secret"),
+    );
+    map.insert("x-echo", HeaderValue::from_static("Bearer sk-secret"));
+    map.insert("x-request-id", HeaderValue::from_static("abc"));
+    let headers = Headers::capture(200, &map);
+    assert_eq!(headers.render(), "HTTP 200\nx-request-id: abc");
+    assert_eq!(headers.get("X-Request-Id"), Some("abc"));
+  }
+}
@@ -0,0 +1,141 @@
+use crossterm::style::Stylize;
+use std::collections::BTreeMap;
+
+/// 各轮的推理过程（reasoning_content）。与 history 分开保存，从不写入消息：
+/// 接口不接受历史中带回推理过程，因此它不会出现在任何请求中，只用于导出
+#[derive(Debug, Default)]
+pub struct Reasoning {
+  /// 轮次（从 1 开始）到推理过程，自动续写的片段依次追加
+  by_turn: BTreeMap<usize, String>,
+}
+
+impl Reasoning {
+  pub fn record(&mut self, turn: usize, text: &str) {
+    let text = text.trim();
+    if text.is_empty() {
+      return;
+    }
+    let entry = self.by_turn.entry(turn).or_default();
+    if !entry.is_empty() {
+      entry.push_str("\n\n");
+    }
+    entry.push_str(text);
+  }
+
+  pub fn for_turn(&self, turn: usize) -> Option<&str> {
+    self.by_turn.get(&turn).map(String::as_str)
+  }
+
+  pub fn clear(&mut self) {
+    self.by_turn.clear();
+  }
+
+  /// 回退历史后，丢弃第 `turn` 轮及之后的推理过程
+  pub fn truncate(&mut self, turn: usize) {
+    self.by_turn.split_off(&turn);
+  }
+
+  /// 删除第 `turn` 轮后，丢弃它的推理过程，之后各轮的推理过程前移一轮
+  pub fn remove(&mut self, turn: usize) {
+    let later = self.by_turn.split_off(&turn);
+    self.by_turn.extend(
+      later
+        .into_iter()
+        .filter(|(t, _)| *t != turn)
+        .map(|(t, v)| (t - 1, v)),
+    );
+  }
+}
+
+/// 流式显示推理过程（deepseek-r1）：灰色显示在回答之前，回答开始时空一行分开。
+/// 只影响显示，推理过程仍然只由 [`Reasoning`] 保存；`styled` 为 false（无障碍模式）时不着色，改为加上标题
+#[derive(Debug)]
+pub struct ReasoningStream {
+  styled: bool,
+  /// 正在显示推理过程，回答还没有开始
+  open: bool,
+}
+
+impl ReasoningStream {
+  pub fn new(styled: bool) -> Self {
+    Self {
+      styled,
+      open: false,
+    }
+  }
+
+  /// 一个分片的推理过程和回答，返回要在回答之前显示的部分
+  pub fn push(&mut self, reasoning: &str, content: &str) -> String {
+    let mut shown = String::new();
+    if !reasoning.is_empty() {
+      if !self.open && !self.styled {
+        shown.push_str("推理过程：\n");
+      }
+      self.open = true;
+      match self.styled {
+        true => shown.push_str(&reasoning.dark_grey().to_string()),
+        false => shown.push_str(reasoning),
+      }
+    }
+    if self.open && !content.is_empty() {
+      self.open = false;
+      shown.push_str(match self.styled {
+        true => "\n\n",
+        false => "\n\n回答：\n",
+      });
+    }
+    shown
+  }
+
+  /// 流结束时只有推理过程（例如推理用完了预算）时补上换行
+  pub fn finish(&mut self) -> &'static str {
+    match std::mem::take(&mut self.open) {
+      true => "\n",
+      false => "",
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_record_appends_continuations() {
+    let mut reasoning = Reasoning::default();
+    reasoning.record(1, "先看锁的持有范围");
+    reasoning.record(1, " 再看 await 点\n");
+    reasoning.record(2, "  ");
+    reasoning.record(3, "第三轮");
+    assert_eq!(
+      reasoning.for_turn(1),
+      Some("先看锁的持有范围\n\n再看 await 点")
+    );
+    assert_eq!(reasoning.for_turn(2), None);
+    reasoning.truncate(2);
+    assert_eq!(reasoning.for_turn(3), None);
+    assert!(reasoning.for_turn(1).is_some());
+  }
+
+  #[test]
+  fn test_stream_shows_reasoning_before_answer() {
+    let mut stream = ReasoningStream::new(false);
+    assert_eq!(stream.push("", "直接回答"), "");
+    let mut stream = ReasoningStream::new(false);
+    let shown: String = [("先想", ""), ("一想", ""), ("", "答"), ("", "案")]
+      .iter()
+      .map(|(reasoning, content)| stream.push(reasoning, content))
+      .collect();
+    assert_eq!(shown, "推理过程：\n先想一想\n\n回答：\n");
+    assert_eq!(stream.finish(), "");
+
+    let mut stream = ReasoningStream::new(true);
+    let shown = stream.push("思考", "");
+    assert!(
+      shown.contains("思考") && shown.contains('\x1b'),
+      "{:?}",
+      shown
+    );
+    assert_eq!(stream.finish(), "\n");
+  }
+}
@@ -0,0 +1,298 @@
+use crate::api::Message;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// 快照目录中保存进行中回答的文件
+pub const FILE_NAME: &str = "in-progress.jsonl";
+/// 缓冲的文本至少这么多 token 时追加一次
+pub const MIN_TOKENS: usize = 64;
+/// 距上次追加超过这么久时，不足 MIN_TOKENS 也追加
+pub const INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InProgressBlock {
+  pub model: String,
+  pub started_at: String,
+}
+
+/// 文件的第一行：请求开始时的历史（末尾是问题）
+#[derive(Serialize, Deserialize)]
+struct Header {
+  history: Vec<Message>,
+  in_progress: InProgressBlock,
+}
+
+/// 之后每行是一段新增的回答
+#[derive(Serialize, Deserialize)]
+struct Delta {
+  text: String,
+}
+
+/// 流式输出时把收到的回答分批追加到 in-progress.jsonl，终端意外关闭后可以用 --resume 找回。
+/// 每个请求开始时重写第一行，整轮结束时删除文件；进程在写入中途被杀死时最后一行可能不完整，
+/// 读取时忽略这一行，之前的文本（包括多字节字符）完整保留
+pub struct InProgress {
+  path: PathBuf,
+  file: Option<File>,
+  pending: String,
+  last_write: Instant,
+  interval: Duration,
+  min_tokens: usize,
+}
+
+impl InProgress {
+  pub fn new(path: PathBuf) -> Self {
+    Self {
+      path,
+      file: None,
+      pending: String::new(),
+      last_write: Instant::now(),
+      interval: INTERVAL,
+      min_tokens: MIN_TOKENS,
+    }
+  }
+
+  #[cfg(test)]
+  pub(crate) fn with_thresholds(mut self, interval: Duration, min_tokens: usize) -> Self {
+    self.interval = interval;
+    self.min_tokens = min_tokens;
+    self
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// 新的请求：写入历史并从空回答开始。写入失败时本轮不再记录
+  pub fn begin(&mut self, history: &[Message], model: &str) {
+    self.file = None;
+    self.pending.clear();
+    if let Err(e) = self.write_header(history, model) {
+      eprintln!("[警告] 无法记录进行中的回答: {:#}", e);
+    }
+  }
+
+  fn write_header(&mut self, history: &[Message], model: &str) -> Result<()> {
+    let header = Header {
+      history: history.to_vec(),
+      in_progress: InProgressBlock {
+        model: model.to_string(),
+        started_at: chrono::Local::now().to_rfc3339(),
+      },
+    };
+    let mut line = serde_json::to_vec(&header).context("Failed to serialize history")?;
+    line.push(b'\n');
+    crate::checkpoint::write_atomic(&self.path, &line)?;
+    let file = fs::OpenOptions::new()
+      .append(true)
+      .open(&self.path)
+      .context(format!("Failed to open {:?}", self.path))?;
+    self.file = Some(file);
+    self.last_write = Instant::now();
+    Ok(())
+  }
+
+  /// 收到一段回答；缓冲达到 token 数或时间间隔时追加一行
+  pub fn push(&mut self, text: &str) {
+    if self.file.is_none() || text.is_empty() {
+      return;
+    }
+    self.pending.push_str(text);
+    if crate::tokens::count(&self.pending) >= self.min_tokens
+      || self.last_write.elapsed() >= self.interval
+    {
+      self.write_pending();
+    }
+  }
+
+  /// 一行一次 write，整行要么写完要么被读取时忽略
+  fn write_pending(&mut self) {
+    let Some(file) = self.file.as_mut() else {
+      return;
+    };
+    if self.pending.is_empty() {
+      return;
+    }
+    let delta = Delta {
+      text: std::mem::take(&mut self.pending),
+    };
+    let mut line = serde_json::to_vec(&delta).unwrap_or_default();
+    line.push(b'\n');
+    if let Err(e) = file.write_all(&line) {
+      eprintln!("[警告] 无法记录进行中的回答: {}", e);
+      self.file = None;
+    }
+    self.last_write = Instant::now();
+  }
+
+  /// 本轮正常结束（包括撤销），回答已经进入历史或被丢弃
+  pub fn clear(&mut self) {
+    self.file = None;
+    self.pending.clear();
+    match fs::remove_file(&self.path) {
+      Ok(()) => {}
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+      Err(e) => eprintln!("[警告] 无法删除 {}: {}", self.path.display(), e),
+    }
+  }
+}
+
+/// 上次被中断的回答
+pub struct Recovered {
+  pub history: Vec<Message>,
+  pub in_progress: InProgressBlock,
+  pub partial: String,
+}
+
+impl Recovered {
+  /// 按前缀续写：部分回答作为 assistant 消息，下一条问题用续写提示
+  pub fn continuation(&self) -> Vec<Message> {
+    let mut history = self.history.clone();
+    history.push(Message::Simple {
+      role: "assistant".to_string(),
+      content: self.partial.clone(),
+      name: None,
+    });
+    history
+  }
+
+  /// 不续写时，部分回答带上中断标记留在历史中
+  pub fn interrupted(&self) -> Vec<Message> {
+    let mut history = self.history.clone();
+    history.push(Message::Simple {
+      role: "assistant".to_string(),
+      content: match self.partial.is_empty() {
+        true => crate::turn::INTERRUPT_MARKER.to_string(),
+        false => format!("{}\n{}", self.partial, crate::turn::INTERRUPT_MARKER),
+      },
+      name: None,
+    });
+    history
+  }
+}
+
+/// 读取 in-progress.jsonl；文件不存在或第一行不完整时返回 None
+pub fn load(path: &Path) -> Result<Option<Recovered>> {
+  let bytes = match fs::read(path) {
+    Ok(bytes) => bytes,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => return Err(e).context(format!("Failed to read {:?}", path)),
+  };
+  // 只使用以换行结尾的完整行
+  let mut lines = bytes.split_inclusive(|&b| b == b'\n');
+  let Some(first) = lines.next().filter(|line| line.ends_with(b"\n")) else {
+    return Ok(None);
+  };
+  let header: Header = serde_json::from_slice(first).context(format!("Invalid {:?}", path))?;
+  let mut partial = String::new();
+  for line in lines {
+    if !line.ends_with(b"\n") {
+      break;
+    }
+    match serde_json::from_slice::<Delta>(line) {
+      Ok(delta) => partial.push_str(&delta.text),
+      Err(_) => break,
+    }
+  }
+  Ok(Some(Recovered {
+    history: header.history,
+    in_progress: header.in_progress,
+    partial,
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+
+  fn question() -> Vec<Message> {
+    vec![Message::Simple {
+      role: "user".to_string(),
+      content: "讲讲 Rust 的所有权".to_string(),
+      name: None,
+    }]
+  }
+
+  /// 按流式输出的节奏写入，丢弃时不调用 clear，相当于进程被杀死
+  fn crash(path: PathBuf, chunks: &[&str], min_tokens: usize) {
+    let mut writer = InProgress::new(path).with_thresholds(Duration::from_secs(3600), min_tokens);
+    writer.begin(&question(), "deepseek-chat");
+    for chunk in chunks {
+      writer.push(chunk);
+    }
+  }
+
+  #[test]
+  fn test_partial_answer_survives_crash() {
+    let dir = temp_dir("recovery-crash");
+    let path = dir.join(FILE_NAME);
+    let chunks = ["所有权", "是 Rust ", "最独特的🦀", "特性，", "每个值都有"];
+    crash(path.clone(), &chunks, 6);
+
+    let recovered = load(&path).unwrap().unwrap();
+    assert_eq!(recovered.in_progress.model, "deepseek-chat");
+    assert_eq!(recovered.history.len(), 1);
+    // 最后一段还在缓冲里，没有写入
+    assert_eq!(recovered.partial, "所有权是 Rust 最独特的🦀特性，");
+    let continued = serde_json::to_string(&recovered.continuation()).unwrap();
+    assert!(continued.contains("特性，"), "{}", continued);
+
+    // 缓冲阈值很高时只有问题，没有回答
+    crash(path.clone(), &chunks, 10_000);
+    assert_eq!(load(&path).unwrap().unwrap().partial, "");
+  }
+
+  #[test]
+  fn test_torn_last_line_is_ignored() {
+    let dir = temp_dir("recovery-torn");
+    let path = dir.join(FILE_NAME);
+    crash(path.clone(), &["第一段，", "第二段，", "第三段"], 1);
+
+    // 写入最后一行时被杀死：截在多字节字符中间
+    let bytes = fs::read(&path).unwrap();
+    let cut = bytes.len() - 5;
+    fs::write(&path, &bytes[..cut]).unwrap();
+    assert!(std::str::from_utf8(&bytes[..cut]).is_err());
+    assert_eq!(load(&path).unwrap().unwrap().partial, "第一段，第二段，");
+
+    // 第一行都没写完时没有可恢复的回答
+    fs::write(&path, &bytes[..10]).unwrap();
+    assert!(load(&path).unwrap().is_none());
+    fs::remove_file(&path).unwrap();
+    assert!(load(&path).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_clear_and_new_request_reset_the_file() {
+    let dir = temp_dir("recovery-clear");
+    let path = dir.join(FILE_NAME);
+    let mut writer = InProgress::new(path.clone()).with_thresholds(Duration::ZERO, 1);
+    writer.begin(&question(), "deepseek-chat");
+    writer.push("旧的回答");
+    // 自动续写等新的请求从空回答开始
+    let mut history = question();
+    history.push(Message::Simple {
+      role: "assistant".to_string(),
+      content: "旧的回答".to_string(),
+      name: None,
+    });
+    writer.begin(&history, "deepseek-chat");
+    writer.push("新的");
+    let recovered = load(&path).unwrap().unwrap();
+    assert_eq!(recovered.history.len(), 2);
+    assert_eq!(recovered.partial, "新的");
+    let interrupted = serde_json::to_string(&recovered.interrupted()).unwrap();
+    assert!(interrupted.contains(crate::turn::INTERRUPT_MARKER));
+
+    writer.clear();
+    assert!(!path.exists());
+    // 清除之后不再写入
+    writer.push("更多");
+    assert!(!path.exists());
+  }
+}
@@ -0,0 +1,244 @@
+//! 交互模式中查看和编辑历史的命令：`\h [full]`、`\drop <轮次>|last` 和 `\undo`。
+//! 只操作传入的历史、批注和推理过程，输出以字符串返回，由 main 打印
+
+use crate::api::Message;
+use crate::export::{role_of, text_of};
+use crate::history;
+use crate::notes::{Notes, turn_count};
+use crate::reasoning::Reasoning;
+use crossterm::style::Stylize;
+
+/// `\h` 中每条消息预览的最大显示宽度
+pub const PREVIEW_WIDTH: usize = 100;
+
+/// 按轮次对齐的状态；删除一轮时三者一起调整
+pub struct Session<'a> {
+  pub history: &'a mut Vec<Message>,
+  pub notes: &'a mut Notes,
+  pub reasoning: &'a mut Reasoning,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outcome {
+  pub output: String,
+  /// 历史被修改，进行中的滚动摘要需要取消
+  pub changed: bool,
+}
+
+impl Outcome {
+  fn show(output: impl Into<String>) -> Self {
+    Self {
+      output: output.into(),
+      changed: false,
+    }
+  }
+}
+
+/// 处理一条输入；不是这里的命令时返回 None
+pub fn handle(input: &str, session: &mut Session) -> Option<Outcome> {
+  let (command, arg) = input.split_once(' ').unwrap_or((input, ""));
+  match command {
+    "\\h" => Some(match arg.trim() {
+      "" => Outcome::show(render(session.history, false)),
+      "full" => Outcome::show(render(session.history, true)),
+      _ => Outcome::show("用法: \\h [full]"),
+    }),
+    "\\drop" => Some(drop(session, arg)),
+    "\\undo" if arg.trim().is_empty() => Some(match turn_count(session.history) {
+      0 => Outcome::show("没有可以清除的对话"),
+      turns => {
+        remove(session, turns);
+        Outcome {
+          output: "已清除 1 轮对话".to_string(),
+          changed: true,
+        }
+      }
+    }),
+    _ => None,
+  }
+}
+
+fn drop(session: &mut Session, arg: &str) -> Outcome {
+  let turns = turn_count(session.history);
+  let turn = match arg.trim() {
+    "last" => turns,
+    arg => match arg.parse::<usize>() {
+      Ok(n) => n,
+      Err(_) => return Outcome::show("用法: \\drop <轮次>|last"),
+    },
+  };
+  if turns == 0 {
+    return Outcome::show("没有可以删除的对话");
+  }
+  if turn == 0 || turn > turns {
+    return Outcome::show(format!("没有第 {} 轮，当前共 {} 轮", turn, turns));
+  }
+  remove(session, turn);
+  let output = match turn == turns {
+    true => format!("已删除第 {} 轮", turn),
+    false => format!("已删除第 {} 轮，之后的轮次依次前移（\\h 查看）", turn),
+  };
+  Outcome {
+    output,
+    changed: true,
+  }
+}
+
+fn remove(session: &mut Session, turn: usize) {
+  history::drop_turn(session.history, turn);
+  session.notes.remove(turn);
+  session.reasoning.remove(turn);
+}
+
+/// `\h` 的输出：第一轮之前的 system 消息不编号，每轮显示问题和拼接后的回答
+pub fn render(history: &[Message], full: bool) -> String {
+  let starts = history::turn_starts(history);
+  let first = starts.first().copied().unwrap_or(history.len());
+  let mut lines = vec![];
+  for message in &history[..first] {
+    lines.push(entry(
+      &message.speaker(),
+      role_of(message),
+      &text_of(message),
+      full,
+    ));
+  }
+  for (i, &start) in starts.iter().enumerate() {
+    let question = &history[start];
+    lines.push(format!("第 {} 轮", i + 1).bold().to_string());
+    lines.push(entry(&question.speaker(), "user", &text_of(question), full));
+    if let Some(reply) = history::turn_reply(history, i + 1) {
+      lines.push(entry("assistant", "assistant", &reply, full));
+    }
+  }
+  match lines.is_empty() {
+    true => "还没有对话".to_string(),
+    false => lines.join("\n"),
+  }
+}
+
+/// 一条消息：角色着色，`full` 为 false 时内容压成一行并截断
+fn entry(speaker: &str, role: &str, text: &str, full: bool) -> String {
+  let label = format!("{}:", speaker);
+  let label = match role {
+    "user" => label.red().to_string(),
+    "assistant" => label.green().to_string(),
+    _ => label,
+  };
+  match full {
+    true => format!("  {}\n{}", label, indent(text)),
+    false => {
+      let flat = text.split_whitespace().collect::<Vec<_>>().join(" ");
+      format!(
+        "  {} {}",
+        label,
+        crate::width::truncate(&flat, PREVIEW_WIDTH)
+      )
+    }
+  }
+}
+
+fn indent(text: &str) -> String {
+  text
+    .lines()
+    .map(|line| format!("    {}", line))
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::turn::CONTINUE_PROMPT;
+
+  fn message(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  fn conversation() -> Vec<Message> {
+    [
+      ("system", "你是助手"),
+      ("user", "q1"),
+      ("assistant", "a1 "),
+      ("user", CONTINUE_PROMPT),
+      ("assistant", "rest"),
+      ("user", "q2"),
+      ("assistant", "a2"),
+      ("user", "q3"),
+      ("assistant", "a3"),
+    ]
+    .iter()
+    .map(|(r, c)| message(r, c))
+    .collect()
+  }
+
+  fn run(input: &str, history: &mut Vec<Message>, notes: &mut Notes) -> Option<Outcome> {
+    let mut reasoning = Reasoning::default();
+    handle(
+      input,
+      &mut Session {
+        history,
+        notes,
+        reasoning: &mut reasoning,
+      },
+    )
+  }
+
+  #[test]
+  fn test_render_numbers_turns_and_truncates() {
+    let mut history = conversation();
+    history.push(message("user", &format!("长问题\n{}", "字".repeat(200))));
+    let preview = render(&history, false);
+    let lines: Vec<&str> = preview.lines().collect();
+    assert_eq!(lines.len(), 1 + 3 * 3 + 2);
+    assert_eq!(lines[0], "  system: 你是助手");
+    assert_eq!(lines[1], "第 1 轮".bold().to_string());
+    assert_eq!(lines[2], format!("  {} q1", "user:".red()));
+    // 续写片段拼进同一个回答，不单独显示“请继续”
+    assert_eq!(lines[3], format!("  {} a1 rest", "assistant:".green()));
+    assert!(lines[11].ends_with('…'));
+    assert!(!preview.contains(CONTINUE_PROMPT));
+
+    // full 保留换行和全部内容
+    let full = render(&history, true);
+    assert!(full.contains(&format!("    长问题\n    {}", "字".repeat(200))));
+    assert_eq!(render(&[], false), "还没有对话");
+  }
+
+  #[test]
+  fn test_drop_shifts_later_turns_and_notes() {
+    let mut history = conversation();
+    let mut notes = Notes::default();
+    notes.add(2, "错误的回答");
+    notes.add(3, "第三轮");
+
+    let outcome = run("\\drop 2", &mut history, &mut notes).unwrap();
+    assert!(outcome.changed);
+    assert_eq!(turn_count(&history), 2);
+    assert_eq!(history::turn_reply(&history, 2).as_deref(), Some("a3"));
+    assert_eq!(notes.render(), "第 2 轮: 第三轮");
+    assert!(render(&history, false).contains("第 2 轮"));
+
+    let outcome = run("\\drop last", &mut history, &mut notes).unwrap();
+    assert_eq!(outcome.output, "已删除第 2 轮");
+    assert!(notes.is_empty());
+    let outcome = run("\\drop 5", &mut history, &mut notes).unwrap();
+    assert_eq!(
+      (outcome.output.as_str(), outcome.changed),
+      ("没有第 5 轮，当前共 1 轮", false)
+    );
+    assert!(!run("\\drop x", &mut history, &mut notes).unwrap().changed);
+
+    assert!(run("\\undo", &mut history, &mut notes).unwrap().changed);
+    assert_eq!(history.len(), 1);
+    let outcome = run("\\undo", &mut history, &mut notes).unwrap();
+    assert_eq!(outcome.output, "没有可以清除的对话");
+    // 其他命令和普通输入交给 main
+    assert_eq!(run("\\headers", &mut history, &mut notes), None);
+    assert_eq!(run("\\hello", &mut history, &mut notes), None);
+  }
+}
@@ -0,0 +1,443 @@
+use crate::api::{ChatBackend, Message};
+use crate::session::validate_name;
+use crate::tokens;
+use crate::transcript;
+use crate::width::{display_width, pad_right};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 回放时默认的采样设置：低温度加固定种子，尽量让结果可复现
+pub const DEFAULT_TEMPERATURE: f32 = 0.0;
+pub const DEFAULT_SEED: u64 = 42;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaseSettings {
+  pub model: Option<String>,
+  pub temperature: Option<f32>,
+  pub max_tokens: Option<u32>,
+  pub system: Option<String>,
+}
+
+impl CaseSettings {
+  /// 用例自身的设置优先，缺省项取文件级 defaults
+  fn or(&self, defaults: &CaseSettings) -> CaseSettings {
+    CaseSettings {
+      model: self.model.clone().or_else(|| defaults.model.clone()),
+      temperature: self.temperature.or(defaults.temperature),
+      max_tokens: self.max_tokens.or(defaults.max_tokens),
+      system: self.system.clone().or_else(|| defaults.system.clone()),
+    }
+  }
+}
+
+/// 针对最后一轮回答的断言
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Assertions {
+  #[serde(default)]
+  pub contains: Vec<String>,
+  #[serde(default)]
+  pub not_contains: Vec<String>,
+  #[serde(default)]
+  pub json: bool,
+  pub max_tokens: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Case {
+  pub name: String,
+  #[serde(default)]
+  pub settings: CaseSettings,
+  pub turns: Vec<String>,
+  #[serde(default, rename = "assert")]
+  pub assertions: Assertions,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suite {
+  #[serde(default)]
+  pub defaults: CaseSettings,
+  pub cases: Vec<Case>,
+}
+
+impl Suite {
+  pub fn parse(text: &str) -> Result<Suite> {
+    let suite: Suite = serde_yaml::from_str(text).context("Invalid test case file")?;
+    for case in &suite.cases {
+      validate_name(&case.name).map_err(|e| anyhow::anyhow!("{}", e))?;
+      if case.turns.is_empty() {
+        anyhow::bail!("Case '{}' has no turns", case.name);
+      }
+      let all = case.assertions.contains.iter();
+      for pattern in all.chain(&case.assertions.not_contains) {
+        Regex::new(pattern)
+          .with_context(|| format!("Case '{}': invalid regex '{}'", case.name, pattern))?;
+      }
+    }
+    Ok(suite)
+  }
+
+  pub fn load(path: &Path) -> Result<Suite> {
+    let text = crate::sandbox::read_to_string(path)
+      .context(format!("Failed to read test cases: {:?}", path))?;
+    Suite::parse(&text)
+  }
+
+  /// 把 r1/chat 之类的别名换成实际模型名
+  pub fn resolve_models(&mut self, resolve: impl Fn(&str) -> Result<String, String>) -> Result<()> {
+    let settings =
+      std::iter::once(&mut self.defaults).chain(self.cases.iter_mut().map(|c| &mut c.settings));
+    for s in settings {
+      if let Some(model) = &s.model {
+        s.model = Some(resolve(model).map_err(|e| anyhow::anyhow!(e))?);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// `cases.yaml` 的快照目录为同级的 `cases.snapshots/`
+pub fn snapshot_dir(cases: &Path) -> PathBuf {
+  let stem = cases
+    .file_stem()
+    .and_then(|s| s.to_str())
+    .unwrap_or("cases");
+  cases.with_file_name(format!("{}.snapshots", stem))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotStatus {
+  Matched,
+  Missing,
+  Updated,
+  Changed,
+  Skipped,
+}
+
+#[derive(Debug)]
+pub struct CaseResult {
+  pub name: String,
+  pub failures: Vec<String>,
+  pub snapshot: SnapshotStatus,
+}
+
+impl CaseResult {
+  pub fn passed(&self) -> bool {
+    self.failures.is_empty()
+  }
+}
+
+pub struct Runner<'a, B: ChatBackend> {
+  pub backend: &'a B,
+  pub default_model: String,
+  pub snapshot_dir: PathBuf,
+  pub update_snapshots: bool,
+}
+
+impl<B: ChatBackend> Runner<'_, B> {
+  pub async fn run(&self, suite: &Suite) -> Vec<CaseResult> {
+    let mut results = vec![];
+    for case in &suite.cases {
+      results.push(self.run_case(case, &suite.defaults).await);
+    }
+    results
+  }
+
+  async fn run_case(&self, case: &Case, defaults: &CaseSettings) -> CaseResult {
+    let settings = case.settings.or(defaults);
+    let model = settings.model.as_deref().unwrap_or(&self.default_model);
+    let temperature = settings.temperature.unwrap_or(DEFAULT_TEMPERATURE);
+    let mut history = vec![];
+    if let Some(system) = &settings.system {
+      history.push(Message::Simple {
+        role: "system".to_string(),
+        content: system.clone(),
+        name: None,
+      });
+    }
+    let mut transcript = String::new();
+    let mut last = String::new();
+    for turn in &case.turns {
+      history.push(Message::Simple {
+        role: "user".to_string(),
+        content: turn.clone(),
+        name: None,
+      });
+      let reply = match self
+        .backend
+        .complete(
+          model,
+          history.clone(),
+          Some(temperature),
+          settings.max_tokens,
+        )
+        .await
+      {
+        Ok(reply) => reply,
+        Err(e) => {
+          return CaseResult {
+            name: case.name.clone(),
+            failures: vec![format!("request failed: {:#}", e)],
+            snapshot: SnapshotStatus::Skipped,
+          };
+        }
+      };
+      transcript.push_str(&format!(
+        "## user\n\n{}\n\n## assistant\n\n{}\n\n",
+        transcript::user_message(turn),
+        reply
+      ));
+      history.push(Message::Simple {
+        role: "assistant".to_string(),
+        content: reply.clone(),
+        name: None,
+      });
+      last = reply;
+    }
+
+    let mut failures = check(&case.assertions, &last);
+    let snapshot = match self.check_snapshot(&case.name, &transcript) {
+      Ok(status) => status,
+      Err(e) => {
+        failures.push(format!("{:#}", e));
+        SnapshotStatus::Skipped
+      }
+    };
+    if snapshot == SnapshotStatus::Changed {
+      failures.push("transcript differs from snapshot".to_string());
+    }
+    CaseResult {
+      name: case.name.clone(),
+      failures,
+      snapshot,
+    }
+  }
+
+  fn check_snapshot(&self, name: &str, transcript: &str) -> Result<SnapshotStatus> {
+    let path = self.snapshot_dir.join(format!("{}.md", name));
+    if self.update_snapshots {
+      fs::create_dir_all(&self.snapshot_dir).context(format!(
+        "Failed to create directory: {:?}",
+        self.snapshot_dir
+      ))?;
+      fs::write(&path, transcript).context(format!("Failed to write snapshot: {:?}", path))?;
+      return Ok(SnapshotStatus::Updated);
+    }
+    match fs::read_to_string(&path) {
+      Ok(saved) if saved == transcript => Ok(SnapshotStatus::Matched),
+      Ok(_) => Ok(SnapshotStatus::Changed),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SnapshotStatus::Missing),
+      Err(e) => Err(e).context(format!("Failed to read snapshot: {:?}", path)),
+    }
+  }
+}
+
+/// 返回所有未通过的断言说明，空列表表示通过
+pub fn check(assertions: &Assertions, reply: &str) -> Vec<String> {
+  let mut failures = vec![];
+  for pattern in &assertions.contains {
+    // 正则已在 Suite::parse 中校验
+    if !Regex::new(pattern).is_ok_and(|re| re.is_match(reply)) {
+      failures.push(format!("expected match for /{}/", pattern));
+    }
+  }
+  for pattern in &assertions.not_contains {
+    if Regex::new(pattern).is_ok_and(|re| re.is_match(reply)) {
+      failures.push(format!("unexpected match for /{}/", pattern));
+    }
+  }
+  if assertions.json
+    && let Err(e) = serde_json::from_str::<serde_json::Value>(strip_code_fence(reply))
+  {
+    failures.push(format!("not valid JSON: {}", e));
+  }
+  if let Some(limit) = assertions.max_tokens {
+    let used = tokens::count(reply);
+    if used > limit {
+      failures.push(format!("~{} tokens exceeds limit {}", used, limit));
+    }
+  }
+  failures
+}
+
+/// 模型常把 JSON 包在 ```json 代码块里
+fn strip_code_fence(text: &str) -> &str {
+  let trimmed = text.trim();
+  let Some(inner) = trimmed.strip_prefix("```") else {
+    return trimmed;
+  };
+  let inner = inner.strip_suffix("```").unwrap_or(inner);
+  match inner.split_once('\n') {
+    Some((_lang, body)) => body.trim(),
+    None => inner.trim(),
+  }
+}
+
+pub fn render_table(results: &[CaseResult]) -> String {
+  let width = results
+    .iter()
+    .map(|r| display_width(&r.name))
+    .max()
+    .unwrap_or(0)
+    .max(4);
+  let mut out = format!("{:<8}{}  {}\n", "STATUS", pad_right("CASE", width), "NOTES");
+  for r in results {
+    let status = if r.passed() { "PASS" } else { "FAIL" };
+    let mut notes = r.failures.clone();
+    match r.snapshot {
+      SnapshotStatus::Missing => notes.push("no snapshot".to_string()),
+      SnapshotStatus::Updated => notes.push("snapshot updated".to_string()),
+      _ => {}
+    }
+    out.push_str(&format!(
+      "{:<8}{}  {}\n",
+      status,
+      pad_right(&r.name, width),
+      notes.join("; ")
+    ));
+  }
+  let passed = results.iter().filter(|r| r.passed()).count();
+  out.push_str(&format!("\n{}/{} 通过", passed, results.len()));
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use crate::mock::ScriptedBackend;
+
+  const CASES: &str = r#"
+defaults:
+  model: chat
+  system: You are terse.
+cases:
+  - name: greeting
+    turns: ["Say hello"]
+    assert:
+      contains: ["(?i)hello"]
+      not_contains: ["goodbye"]
+      max_tokens: 20
+  - name: json-output
+    settings:
+      model: r1
+      temperature: 0.2
+    turns: ["Hi", "Reply with a JSON object"]
+    assert:
+      json: true
+"#;
+
+  fn runner<'a>(
+    backend: &'a ScriptedBackend,
+    dir: &Path,
+    update: bool,
+  ) -> Runner<'a, ScriptedBackend> {
+    Runner {
+      backend,
+      default_model: "deepseek-chat".to_string(),
+      snapshot_dir: dir.join("cases.snapshots"),
+      update_snapshots: update,
+    }
+  }
+
+  fn suite() -> Suite {
+    let mut suite = Suite::parse(CASES).unwrap();
+    suite
+      .resolve_models(|m| match m {
+        "r1" => Ok("deepseek-r1".to_string()),
+        "chat" => Ok("deepseek-chat".to_string()),
+        other => Err(format!("unknown model {}", other)),
+      })
+      .unwrap();
+    suite
+  }
+
+  #[test]
+  fn test_parse_rejects_bad_cases() {
+    assert!(Suite::parse("cases:\n  - name: x\n    turns: []\n").is_err());
+    assert!(Suite::parse("cases:\n  - name: ../x\n    turns: [hi]\n").is_err());
+    assert!(
+      Suite::parse("cases:\n  - name: x\n    turns: [hi]\n    assert:\n      contains: ['(']\n")
+        .is_err()
+    );
+    assert!(Suite::parse("cases:\n  - name: x\n    turns: [hi]\n    unknown: 1\n").is_err());
+  }
+
+  #[test]
+  fn test_assertions() {
+    let a = Assertions {
+      contains: vec!["\\d+".to_string()],
+      not_contains: vec!["(?i)sorry".to_string()],
+      json: true,
+      max_tokens: Some(14),
+    };
+    assert!(check(&a, "```json\n{\"n\": 42}\n```").is_empty());
+    let failures = check(&a, "Sorry, I can't produce a number here, it is too long.");
+    assert_eq!(failures.len(), 4, "{:?}", failures);
+  }
+
+  #[tokio::test]
+  async fn test_replay_against_mock() {
+    let dir = temp_dir("replay");
+    let backend = ScriptedBackend::new(["Hello there!", "Sure.", "{\"ok\": true}"]);
+    let results = runner(&backend, &dir, true).run(&suite()).await;
+    assert!(results.iter().all(|r| r.passed()), "{:?}", results);
+    assert!(
+      results
+        .iter()
+        .all(|r| r.snapshot == SnapshotStatus::Updated)
+    );
+
+    let saved = fs::read_to_string(dir.join("cases.snapshots/greeting.md")).unwrap();
+    assert!(saved.starts_with("## user\n\n> Say hello\n\n## assistant\n\nHello there!"));
+
+    // 多轮用例带上了之前的回答，模型别名和 system prompt 都已生效
+    let requests = backend.requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[0].0, "deepseek-chat");
+    assert_eq!(requests[2].0, "deepseek-r1");
+    assert_eq!(requests[2].1.len(), 4);
+    assert!(matches!(&requests[2].1[0], Message::Simple { role, .. } if role == "system"));
+
+    // 相同回答与快照一致；回答变化时失败
+    let backend = ScriptedBackend::new(["Hello there!", "Sure.", "not json"]);
+    let results = runner(&backend, &dir, false).run(&suite()).await;
+    assert_eq!(results[0].snapshot, SnapshotStatus::Matched);
+    assert!(results[0].passed());
+    assert_eq!(results[1].snapshot, SnapshotStatus::Changed);
+    assert_eq!(results[1].failures.len(), 2, "{:?}", results[1].failures);
+
+    let table = render_table(&results);
+    assert!(table.contains("PASS    greeting"));
+    assert!(table.contains("FAIL    json-output"));
+    assert!(table.ends_with("1/2 通过"));
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_request_failure_fails_case() {
+    let dir = temp_dir("replay-error");
+    let backend = ScriptedBackend::new(Vec::<String>::new());
+    backend.push_error("connection refused");
+    let results = runner(&backend, &dir, false).run(&suite()).await;
+    assert!(results[0].failures[0].contains("connection refused"));
+    // 回答用完后后续用例同样失败，而不是 panic
+    assert!(!results[1].passed());
+    assert_eq!(backend.request_count(), 2);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_snapshot_dir() {
+    assert_eq!(
+      snapshot_dir(Path::new("tests/cases.yaml")),
+      Path::new("tests/cases.snapshots")
+    );
+  }
+}
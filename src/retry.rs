@@ -0,0 +1,92 @@
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// 第一次重试前的等待时长，之后每次加倍
+const BASE_DELAY: Duration = Duration::from_millis(500);
+/// 单次等待的上限
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// 暂时性失败（连接错误、超时、500/502/503）的重试策略：指数退避加随机抖动。
+/// 流式请求只在读取响应体之前重试，已经输出的内容不会重复
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+  /// 失败后最多再试几次；0 表示不重试
+  pub retries: u32,
+  pub base: Duration,
+  pub max: Duration,
+}
+
+impl Default for RetryPolicy {
+  fn default() -> Self {
+    Self::new(0)
+  }
+}
+
+impl RetryPolicy {
+  pub fn new(retries: u32) -> Self {
+    Self {
+      retries,
+      base: BASE_DELAY,
+      max: MAX_DELAY,
+    }
+  }
+
+  /// 第 `attempt` 次重试（从 0 开始）前的等待时长。`jitter` 在 [0, 1) 之间，
+  /// 实际等待为退避时长的 50%～100%，避免多个客户端同时重试
+  pub fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+    let backoff = self
+      .base
+      .saturating_mul(2u32.saturating_pow(attempt))
+      .min(self.max);
+    backoff.mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+  }
+}
+
+/// 重试这些状态码；400/401 等请求本身的问题直接失败
+pub fn retryable_status(status: u16) -> bool {
+  matches!(status, 500 | 502 | 503)
+}
+
+pub fn retryable_error(error: &reqwest::Error) -> bool {
+  error.is_connect() || error.is_timeout()
+}
+
+/// [0, 1) 之间的随机数，只用于抖动
+pub fn jitter() -> f64 {
+  let bits = std::collections::hash_map::RandomState::new()
+    .build_hasher()
+    .finish();
+  (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_exponential_backoff_with_jitter() {
+    let policy = RetryPolicy::new(5);
+    assert_eq!(policy.delay(0, 1.0), Duration::from_millis(500));
+    assert_eq!(policy.delay(1, 1.0), Duration::from_secs(1));
+    assert_eq!(policy.delay(2, 1.0), Duration::from_secs(2));
+    // 抖动最多减半
+    assert_eq!(policy.delay(2, 0.0), Duration::from_secs(1));
+    // 不超过上限，次数很大时也不溢出
+    assert_eq!(policy.delay(10, 1.0), MAX_DELAY);
+    assert_eq!(policy.delay(u32::MAX, 1.0), MAX_DELAY);
+    for _ in 0..100 {
+      let j = jitter();
+      assert!((0.0..1.0).contains(&j), "{}", j);
+    }
+  }
+
+  #[test]
+  fn test_retryable_statuses() {
+    for status in [500, 502, 503] {
+      assert!(retryable_status(status));
+    }
+    for status in [400, 401, 403, 404, 422, 429, 504] {
+      assert!(!retryable_status(status), "{}", status);
+    }
+  }
+}
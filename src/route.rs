@@ -0,0 +1,208 @@
+/// --auto-route 把简单的追问交给便宜模型时的默认模型
+pub const DEFAULT_CHEAP_MODEL: &str = "chat";
+/// 得分达到这个值才改用便宜模型；越高越保守
+pub const DEFAULT_THRESHOLD: f32 = 0.6;
+/// 超过这么多字的追问总是交给会话模型
+const MAX_TRIVIAL_CHARS: usize = 120;
+
+/// 指代上一条回答的说法
+const REFERENTIAL: &[&str] = &[
+  "上面", "上述", "刚才", "那个", "这个", "这段", "那段", "它", "above", "that", "this", "it ",
+  "again",
+];
+/// 只需要改写上一条回答的要求
+const REWRITE: &[&str] = &[
+  "简短",
+  "短一点",
+  "精简",
+  "翻译",
+  "译成",
+  "错别字",
+  "拼写",
+  "格式",
+  "改成",
+  "换成",
+  "列表",
+  "表格",
+  "总结",
+  "shorter",
+  "concise",
+  "translate",
+  "typo",
+  "format",
+  "rephrase",
+  "reword",
+  "bullet",
+  "summarize",
+];
+/// 需要推理的问题
+const COMPLEX: &[&str] = &[
+  "为什么",
+  "如何",
+  "怎么",
+  "证明",
+  "推导",
+  "分析",
+  "比较",
+  "设计",
+  "优化",
+  "权衡",
+  "why",
+  "how ",
+  "prove",
+  "derive",
+  "explain",
+  "analy",
+  "compare",
+  "design",
+  "optimi",
+  "trade-off",
+  "step by step",
+];
+
+/// 本轮由哪个模型回答
+#[derive(Debug, Clone, PartialEq)]
+pub enum Route {
+  /// 会话模型
+  Session,
+  /// 得分达到阈值的简单追问
+  Cheap,
+  /// 用 @模型 指定
+  Forced(String),
+}
+
+/// 追问有多“简单”，0 到 1：短、指代上一条回答、只要求改写时得分高，
+/// 带推理类词语或代码时得分低
+pub fn triviality(message: &str) -> f32 {
+  let chars = message.chars().count();
+  if chars > MAX_TRIVIAL_CHARS || message.contains("```") {
+    return 0.0;
+  }
+  let lower = message.to_lowercase();
+  let has = |words: &[&str]| words.iter().any(|w| lower.contains(w));
+  let mut score: f32 = match chars {
+    0..=30 => 0.4,
+    31..=60 => 0.25,
+    _ => 0.1,
+  };
+  if has(REFERENTIAL) {
+    score += 0.3;
+  }
+  if has(REWRITE) {
+    score += 0.3;
+  }
+  if has(COMPLEX) {
+    score -= 0.5;
+  }
+  // 一次问好几个问题
+  if lower.matches(['?', '？']).count() > 1 {
+    score -= 0.3;
+  }
+  score.clamp(0.0, 1.0)
+}
+
+/// 决定本轮的模型。没有上一条回答时不算追问；`forced` 为 @模型 指定的模型
+pub fn decide(message: &str, has_answer: bool, threshold: f32, forced: Option<&str>) -> Route {
+  if let Some(model) = forced {
+    return Route::Forced(model.to_string());
+  }
+  match has_answer && triviality(message) >= threshold {
+    true => Route::Cheap,
+    false => Route::Session,
+  }
+}
+
+/// 拆出开头的 `@模型 `，例如 `@r1 再想想`；没有时返回 None
+pub fn split_override(input: &str) -> Option<(&str, &str)> {
+  let rest = input.strip_prefix('@')?;
+  let (name, message) = rest.split_once(char::is_whitespace)?;
+  let valid = !name.is_empty()
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'));
+  valid.then(|| (name, message.trim_start()))
+}
+
+/// `\route`：on、off 或新的阈值
+pub fn parse_command(arg: &str) -> Result<Option<f32>, String> {
+  match arg.trim() {
+    "on" => Ok(Some(DEFAULT_THRESHOLD)),
+    "off" => Ok(None),
+    value => match value.parse::<f32>() {
+      Ok(t) if (0.0..=1.0).contains(&t) => Ok(Some(t)),
+      _ => Err("用法: \\route on|off|<0 到 1 之间的阈值>".to_string()),
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_routing_decisions() {
+    let t = DEFAULT_THRESHOLD;
+    let cases: &[(&str, bool, Route)] = &[
+      ("shorter please", true, Route::Cheap),
+      ("translate that to English", true, Route::Cheap),
+      ("fix the typo in that code", true, Route::Cheap),
+      ("把上面的回答翻译成英文", true, Route::Cheap),
+      ("简短一点", true, Route::Cheap),
+      // 会话的第一个问题不是追问
+      ("shorter please", false, Route::Session),
+      ("为什么这里会死锁？", true, Route::Session),
+      ("explain why that is O(n log n)", true, Route::Session),
+      (
+        "那个函数怎么改才能支持并发？还要考虑哪些边界情况？",
+        true,
+        Route::Session,
+      ),
+      (
+        "fix this:\n```rust\nfn main() {}\n```",
+        true,
+        Route::Session,
+      ),
+      ("好的", true, Route::Session),
+    ];
+    for (message, has_answer, expected) in cases {
+      assert_eq!(
+        &decide(message, *has_answer, t, None),
+        expected,
+        "{:?} scored {}",
+        message,
+        triviality(message)
+      );
+    }
+    let long = "把上面的内容翻译成英文。".repeat(20);
+    assert_eq!(decide(&long, true, t, None), Route::Session);
+    // 阈值调低后更激进，调到 1 以上不再路由
+    assert_eq!(decide("好的", true, 0.3, None), Route::Cheap);
+    assert_eq!(decide("shorter please", true, 1.1, None), Route::Session);
+    assert_eq!(
+      decide("shorter please", true, t, Some("deepseek-reasoner")),
+      Route::Forced("deepseek-reasoner".to_string())
+    );
+  }
+
+  #[test]
+  fn test_split_override() {
+    assert_eq!(split_override("@r1 再想想"), Some(("r1", "再想想")));
+    assert_eq!(
+      split_override("@deepseek-chat  shorter"),
+      Some(("deepseek-chat", "shorter"))
+    );
+    assert_eq!(split_override("@r1"), None);
+    assert_eq!(split_override("@ r1 hi"), None);
+    assert_eq!(split_override("email me@example.com"), None);
+    assert_eq!(split_override("@张三 你好"), None);
+  }
+
+  #[test]
+  fn test_route_command() {
+    assert_eq!(parse_command("on"), Ok(Some(DEFAULT_THRESHOLD)));
+    assert_eq!(parse_command(" off "), Ok(None));
+    assert_eq!(parse_command("0.8"), Ok(Some(0.8)));
+    assert!(parse_command("2").is_err());
+    assert!(parse_command("").is_err());
+  }
+}
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+
+/// 发送给模型的输出行数上限
+pub const TAIL_LINES: usize = 200;
+
+/// 只保留最近 `capacity` 行的合并输出
+pub struct RingBuffer {
+  lines: VecDeque<String>,
+  capacity: usize,
+  dropped: usize,
+}
+
+impl RingBuffer {
+  pub fn new(capacity: usize) -> Self {
+    Self {
+      lines: VecDeque::with_capacity(capacity),
+      capacity,
+      dropped: 0,
+    }
+  }
+
+  pub fn push(&mut self, line: String) {
+    if self.lines.len() == self.capacity {
+      self.lines.pop_front();
+      self.dropped += 1;
+    }
+    self.lines.push_back(line);
+  }
+
+  pub fn lines(&self) -> impl Iterator<Item = &str> {
+    self.lines.iter().map(String::as_str)
+  }
+
+  /// 被挤出缓冲区的行数
+  pub fn dropped(&self) -> usize {
+    self.dropped
+  }
+}
+
+pub struct RunOutput {
+  pub status: ExitStatus,
+  pub tail: RingBuffer,
+}
+
+/// 运行命令，stdout/stderr 原样转发到 `out`/`err`，同时把两路输出按到达顺序记入缓冲区
+pub fn run_captured(
+  argv: &[String],
+  capacity: usize,
+  out: &mut (impl Write + Send),
+  err: &mut (impl Write + Send),
+) -> Result<RunOutput> {
+  let (program, args) = argv.split_first().context("No command given")?;
+  let mut child = Command::new(program)
+    .args(args)
+    .stdin(Stdio::inherit())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .context(format!("Failed to run {}", program))?;
+  let stdout = child.stdout.take().expect("stdout is piped");
+  let stderr = child.stderr.take().expect("stderr is piped");
+  let tail = Mutex::new(RingBuffer::new(capacity));
+
+  std::thread::scope(|scope| {
+    scope.spawn(|| forward(stdout, out, &tail));
+    scope.spawn(|| forward(stderr, err, &tail));
+  });
+  let status = child.wait().context("Failed to wait for command")?;
+  Ok(RunOutput {
+    status,
+    tail: tail.into_inner().unwrap(),
+  })
+}
+
+/// 按行转发；非 UTF-8 内容原样写出，缓冲区中做有损转换
+fn forward(source: impl Read, sink: &mut impl Write, tail: &Mutex<RingBuffer>) {
+  let mut reader = BufReader::new(source);
+  let mut line = Vec::new();
+  while reader.read_until(b'\n', &mut line).unwrap_or(0) > 0 {
+    let _ = sink.write_all(&line);
+    let _ = sink.flush();
+    let text = String::from_utf8_lossy(&line);
+    tail
+      .lock()
+      .unwrap()
+      .push(text.trim_end_matches(['\n', '\r']).to_string());
+    line.clear();
+  }
+}
+
+fn describe_status(status: &ExitStatus) -> String {
+  match status.code() {
+    Some(code) => format!("退出码 {}", code),
+    None => "被信号终止".to_string(),
+  }
+}
+
+/// 构造请模型诊断的提示：命令行、退出状态和最后的输出
+pub fn diagnosis_prompt(argv: &[String], output: &RunOutput) -> String {
+  let mut prompt = format!(
+    "我运行了下面的命令，{}。请分析输出，说明可能的原因和修复方法。\n\n命令: {}\n\n",
+    describe_status(&output.status),
+    argv.join(" ")
+  );
+  if output.tail.dropped() > 0 {
+    prompt.push_str(&format!(
+      "输出（省略了前 {} 行，以下为最后 {} 行）:\n",
+      output.tail.dropped(),
+      output.tail.lines.len()
+    ));
+  } else {
+    prompt.push_str("输出:\n");
+  }
+  prompt.push_str("```\n");
+  for line in output.tail.lines() {
+    prompt.push_str(line);
+    prompt.push('\n');
+  }
+  prompt.push_str("```");
+  prompt
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sh(script: &str) -> Vec<String> {
+    vec!["sh".to_string(), "-c".to_string(), script.to_string()]
+  }
+
+  #[test]
+  fn test_ring_buffer_keeps_last_lines() {
+    let mut buffer = RingBuffer::new(3);
+    for i in 0..5 {
+      buffer.push(i.to_string());
+    }
+    assert_eq!(buffer.lines().collect::<Vec<_>>(), ["2", "3", "4"]);
+    assert_eq!(buffer.dropped(), 2);
+  }
+
+  #[test]
+  fn test_failing_command_is_captured() {
+    let argv = sh("echo building; echo 'error: missing ;' >&2; exit 3");
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let output = run_captured(&argv, TAIL_LINES, &mut out, &mut err).unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    // 输出原样转发到各自的流
+    assert_eq!(out, b"building\n");
+    assert_eq!(err, b"error: missing ;\n");
+    let mut lines: Vec<_> = output.tail.lines().collect();
+    lines.sort();
+    assert_eq!(lines, ["building", "error: missing ;"]);
+
+    let prompt = diagnosis_prompt(&argv, &output);
+    assert!(prompt.contains("退出码 3"));
+    assert!(prompt.contains("命令: sh -c echo building"));
+    assert!(prompt.contains("error: missing ;"));
+  }
+
+  #[test]
+  fn test_long_output_is_tailed() {
+    let argv = sh("seq 1 500; exit 1");
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let output = run_captured(&argv, TAIL_LINES, &mut out, &mut err).unwrap();
+    assert_eq!(out.iter().filter(|&&b| b == b'\n').count(), 500);
+    assert_eq!(output.tail.dropped(), 300);
+    assert_eq!(output.tail.lines().next(), Some("301"));
+    let prompt = diagnosis_prompt(&argv, &output);
+    assert!(prompt.contains("省略了前 300 行"));
+    assert!(!prompt.contains("\n300\n"));
+  }
+
+  #[test]
+  fn test_successful_and_missing_commands() {
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let output = run_captured(&sh("true"), TAIL_LINES, &mut out, &mut err).unwrap();
+    assert!(output.status.success());
+    assert!(
+      run_captured(
+        &["deepcli-no-such-command".to_string()],
+        TAIL_LINES,
+        &mut out,
+        &mut err
+      )
+      .is_err()
+    );
+  }
+}
@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// 总是启用限制的配置项（环境变量或 .env），值为目录
+pub const CONFIG_KEY: &str = "DEEPCLI_RESTRICT_TO";
+
+/// 路径解析到了允许的目录之外
+#[derive(Debug)]
+pub struct OutsideRoot {
+  pub requested: PathBuf,
+  pub resolved: PathBuf,
+  pub root: PathBuf,
+}
+
+impl std::fmt::Display for OutsideRoot {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{:?} resolves to {}, which is outside --restrict-to {}",
+      self.requested,
+      self.resolved.display(),
+      self.root.display()
+    )
+  }
+}
+
+impl std::error::Error for OutsideRoot {}
+
+/// --restrict-to：只允许读取这个目录（解析符号链接之后）中的文件
+#[derive(Debug, Clone, PartialEq)]
+pub struct Restriction {
+  root: PathBuf,
+}
+
+impl Restriction {
+  pub fn new(root: &Path) -> Result<Self> {
+    let root = root
+      .canonicalize()
+      .context(format!("Cannot resolve --restrict-to {:?}", root))?;
+    if !root.is_dir() {
+      anyhow::bail!("--restrict-to {} is not a directory", root.display());
+    }
+    Ok(Self { root })
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// 解析 `..` 和所有符号链接，结果不在根目录下时返回 OutsideRoot
+  pub fn resolve(&self, path: &Path) -> Result<PathBuf> {
+    let resolved = path
+      .canonicalize()
+      .context(format!("Failed to read {:?}", path))?;
+    if !resolved.starts_with(&self.root) {
+      return Err(
+        OutsideRoot {
+          requested: path.to_path_buf(),
+          resolved,
+          root: self.root.clone(),
+        }
+        .into(),
+      );
+    }
+    Ok(resolved)
+  }
+}
+
+static RESTRICTION: OnceLock<Option<Restriction>> = OnceLock::new();
+
+/// 启动时设置一次
+pub fn init(restriction: Option<Restriction>) {
+  let _ = RESTRICTION.set(restriction);
+}
+
+pub fn current() -> Option<&'static Restriction> {
+  RESTRICTION.get().and_then(Option::as_ref)
+}
+
+/// 打开用户指定的文件（附件、人设、导入、批量输入、笔记等）。所有这类读取都经过这里；
+/// 设置了 --restrict-to 时打开的是检查过的解析结果，而不是原路径
+pub fn open(path: &Path) -> Result<File> {
+  open_with(current(), path)
+}
+
+fn open_with(restriction: Option<&Restriction>, path: &Path) -> Result<File> {
+  let path = match restriction {
+    Some(restriction) => restriction.resolve(path)?,
+    None => path.to_path_buf(),
+  };
+  Ok(File::open(path)?)
+}
+
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+  use std::io::Read;
+  let mut bytes = vec![];
+  open(path)?.read_to_end(&mut bytes)?;
+  Ok(bytes)
+}
+
+pub fn read_to_string(path: &Path) -> Result<String> {
+  let bytes = read(path)?;
+  String::from_utf8(bytes).context(format!("File is not valid UTF-8: {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use std::fs;
+  use std::os::unix::fs::symlink;
+
+  /// root/ 中有 inside.txt 和 sub/，旁边的 secret.txt 在根目录之外
+  fn tree(name: &str) -> (PathBuf, Restriction) {
+    let dir = temp_dir(name);
+    let root = dir.join("root");
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("inside.txt"), "ok").unwrap();
+    fs::write(root.join("sub/nested.txt"), "ok").unwrap();
+    fs::create_dir_all(dir.join("outside")).unwrap();
+    fs::write(dir.join("outside/secret.txt"), "secret").unwrap();
+    fs::write(dir.join("secret.txt"), "secret").unwrap();
+    let restriction = Restriction::new(&root).unwrap();
+    (dir, restriction)
+  }
+
+  fn outside(restriction: &Restriction, path: &Path) -> OutsideRoot {
+    let err = open_with(Some(restriction), path).unwrap_err();
+    match err.downcast::<OutsideRoot>() {
+      Ok(outside) => outside,
+      Err(e) => panic!("{:?} was not refused as outside: {:#}", path, e),
+    }
+  }
+
+  #[test]
+  fn test_paths_inside_root_are_allowed() {
+    let (dir, restriction) = tree("sandbox-inside");
+    let root = dir.join("root");
+    assert!(open_with(Some(&restriction), &root.join("inside.txt")).is_ok());
+    assert!(open_with(Some(&restriction), &root.join("sub/../sub/nested.txt")).is_ok());
+    // 指向根目录内部的符号链接可以读取
+    symlink(root.join("sub/nested.txt"), root.join("link.txt")).unwrap();
+    assert!(open_with(Some(&restriction), &root.join("link.txt")).is_ok());
+    // 没有限制时原样打开
+    assert!(open_with(None, &dir.join("secret.txt")).is_ok());
+  }
+
+  #[test]
+  fn test_dot_dot_traversal_is_refused() {
+    let (dir, restriction) = tree("sandbox-dotdot");
+    let err = outside(&restriction, &dir.join("root/sub/../../secret.txt"));
+    assert_eq!(err.resolved, dir.join("secret.txt").canonicalize().unwrap());
+    let message = err.to_string();
+    assert!(message.contains("secret.txt"), "{}", message);
+    assert!(message.contains("--restrict-to"), "{}", message);
+  }
+
+  #[test]
+  fn test_file_symlink_escape_is_refused() {
+    let (dir, restriction) = tree("sandbox-file-link");
+    let link = dir.join("root/notes.txt");
+    symlink(dir.join("secret.txt"), &link).unwrap();
+    let err = outside(&restriction, &link);
+    assert_eq!(err.requested, link);
+    assert_eq!(err.resolved, dir.join("secret.txt").canonicalize().unwrap());
+  }
+
+  #[test]
+  fn test_dir_symlink_escape_is_refused() {
+    let (dir, restriction) = tree("sandbox-dir-link");
+    symlink(dir.join("outside"), dir.join("root/vendor")).unwrap();
+    let err = outside(&restriction, &dir.join("root/vendor/secret.txt"));
+    assert_eq!(
+      err.resolved,
+      dir.join("outside/secret.txt").canonicalize().unwrap()
+    );
+    // 根目录名的前缀相同也不算在内
+    fs::create_dir_all(dir.join("root2")).unwrap();
+    fs::write(dir.join("root2/x.txt"), "x").unwrap();
+    outside(&restriction, &dir.join("root2/x.txt"));
+  }
+
+  #[test]
+  fn test_root_must_be_an_existing_directory() {
+    let (dir, _) = tree("sandbox-root");
+    assert!(Restriction::new(&dir.join("missing")).is_err());
+    assert!(Restriction::new(&dir.join("secret.txt")).is_err());
+    // 根目录本身经过符号链接时按解析结果比较
+    symlink(dir.join("root"), dir.join("alias")).unwrap();
+    let restriction = Restriction::new(&dir.join("alias")).unwrap();
+    assert_eq!(restriction.root(), dir.join("root").canonicalize().unwrap());
+    assert!(open_with(Some(&restriction), &dir.join("alias/inside.txt")).is_ok());
+  }
+}
@@ -0,0 +1,422 @@
+//! `--json-schema <FILE>`：用 `json_schema` 响应格式约束输出，并在本地按 schema 检查最终回答。
+//! 服务方不支持 `json_schema` 时改用 `json_object`，schema 写进 system 消息。
+//! 本地检查支持常用的关键字：type、enum、const、properties、required、additionalProperties、
+//! items、数值和长度范围、pattern、allOf/anyOf/oneOf 和指向 `#/$defs/…` 的 $ref；其余关键字忽略
+
+use crate::api::{ApiError, JsonSchemaFormat};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// $ref 嵌套超过这个深度时停止检查，避免循环引用
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+  pub name: String,
+  pub value: Value,
+}
+
+impl Schema {
+  /// 名称取 schema 的 title，没有时用文件名；只保留 OpenAI 允许的字符
+  pub fn load(path: &Path) -> Result<Self> {
+    let text = std::fs::read_to_string(path)
+      .with_context(|| format!("Cannot read --json-schema {:?}", path))?;
+    let value: Value = serde_json::from_str(&text)
+      .with_context(|| format!("--json-schema {:?} is not valid JSON", path))?;
+    if !value.is_object() {
+      anyhow::bail!("--json-schema {:?} must contain a JSON object", path);
+    }
+    let title = value["title"].as_str().map(str::to_string);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned());
+    let name: String = title
+      .or(stem)
+      .unwrap_or_default()
+      .chars()
+      .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+        true => c,
+        false => '_',
+      })
+      .take(64)
+      .collect();
+    Ok(Self {
+      name: match name.is_empty() {
+        true => "response".to_string(),
+        false => name,
+      },
+      value,
+    })
+  }
+
+  pub fn format(&self) -> JsonSchemaFormat {
+    JsonSchemaFormat {
+      name: self.name.clone(),
+      schema: self.value.clone(),
+      strict: true,
+    }
+  }
+}
+
+/// 服务方是否因为不支持 json_schema 响应格式而拒绝了请求
+pub fn rejected(error: &anyhow::Error) -> bool {
+  match error.downcast_ref::<ApiError>() {
+    Some(ApiError::InvalidRequest {
+      status, message, ..
+    }) if matches!(status.as_u16(), 400 | 422) => {
+      let message = message.to_ascii_lowercase();
+      message.contains("json_schema") || message.contains("response_format")
+    }
+    _ => false,
+  }
+}
+
+/// 按 schema 检查 `value`，返回每个不符合的位置和原因；符合时为空
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+  let mut validator = Validator {
+    root: schema,
+    errors: vec![],
+  };
+  validator.check(schema, value, "$", 0);
+  validator.errors
+}
+
+struct Validator<'a> {
+  root: &'a Value,
+  errors: Vec<String>,
+}
+
+fn kind(value: &Value) -> &'static str {
+  match value {
+    Value::Null => "null",
+    Value::Bool(_) => "boolean",
+    Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+    Value::Number(_) => "number",
+    Value::String(_) => "string",
+    Value::Array(_) => "array",
+    Value::Object(_) => "object",
+  }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+  match (name, value) {
+    ("integer", Value::Number(n)) => n.as_f64().is_some_and(|f| f.fract() == 0.0),
+    ("number", Value::Number(_)) => true,
+    _ => kind(value) == name,
+  }
+}
+
+impl<'a> Validator<'a> {
+  fn fail(&mut self, path: &str, reason: String) {
+    self.errors.push(format!("{}: {}", path, reason));
+  }
+
+  /// `#/$defs/name` 形式的引用
+  fn resolve(&self, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    self.root.pointer(pointer)
+  }
+
+  /// 子 schema 是否接受 `value`，不记录错误
+  fn accepts(&self, schema: &Value, value: &Value, depth: usize) -> bool {
+    let mut probe = Validator {
+      root: self.root,
+      errors: vec![],
+    };
+    probe.check(schema, value, "$", depth);
+    probe.errors.is_empty()
+  }
+
+  fn check(&mut self, schema: &Value, value: &Value, path: &str, depth: usize) {
+    let schema = match schema {
+      Value::Bool(false) => return self.fail(path, "not allowed here".to_string()),
+      Value::Object(schema) if depth < MAX_DEPTH => schema,
+      _ => return,
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+      match self.resolve(reference) {
+        Some(target) => self.check(target, value, path, depth + 1),
+        None => self.fail(path, format!("cannot resolve $ref {:?}", reference)),
+      }
+    }
+    if let Some(types) = schema.get("type") {
+      let names: Vec<&str> = match types {
+        Value::String(name) => vec![name],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+      };
+      if !names.is_empty() && !names.iter().any(|name| has_type(value, name)) {
+        // 类型不对时其余关键字没有意义
+        return self.fail(
+          path,
+          format!("expected {}, got {}", names.join(" or "), kind(value)),
+        );
+      }
+    }
+    if let Some(Value::Array(options)) = schema.get("enum")
+      && !options.contains(value)
+    {
+      let options: Vec<String> = options.iter().map(Value::to_string).collect();
+      self.fail(path, format!("must be one of {}", options.join(", ")));
+    }
+    if let Some(expected) = schema.get("const")
+      && expected != value
+    {
+      self.fail(path, format!("must be {}", expected));
+    }
+    match value {
+      Value::Number(n) => self.check_number(schema, n.as_f64().unwrap_or_default(), path),
+      Value::String(s) => self.check_string(schema, s, path),
+      Value::Array(items) => self.check_array(schema, items, path, depth),
+      Value::Object(fields) => self.check_object(schema, fields, path, depth),
+      _ => {}
+    }
+    self.check_combinators(schema, value, path, depth);
+  }
+
+  fn check_number(&mut self, schema: &Map<String, Value>, n: f64, path: &str) {
+    let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+    if let Some(min) = bound("minimum")
+      && n < min
+    {
+      self.fail(path, format!("must be at least {}", min));
+    }
+    if let Some(max) = bound("maximum")
+      && n > max
+    {
+      self.fail(path, format!("must be at most {}", max));
+    }
+    if let Some(min) = bound("exclusiveMinimum")
+      && n <= min
+    {
+      self.fail(path, format!("must be greater than {}", min));
+    }
+    if let Some(max) = bound("exclusiveMaximum")
+      && n >= max
+    {
+      self.fail(path, format!("must be less than {}", max));
+    }
+  }
+
+  fn check_string(&mut self, schema: &Map<String, Value>, s: &str, path: &str) {
+    let len = s.chars().count() as u64;
+    if let Some(min) = schema.get("minLength").and_then(Value::as_u64)
+      && len < min
+    {
+      self.fail(path, format!("must be at least {} characters", min));
+    }
+    if let Some(max) = schema.get("maxLength").and_then(Value::as_u64)
+      && len > max
+    {
+      self.fail(path, format!("must be at most {} characters", max));
+    }
+    if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+      match Regex::new(pattern) {
+        Ok(regex) if !regex.is_match(s) => {
+          self.fail(path, format!("does not match pattern {:?}", pattern))
+        }
+        Ok(_) => {}
+        Err(_) => self.fail(path, format!("invalid pattern {:?} in schema", pattern)),
+      }
+    }
+  }
+
+  fn check_array(
+    &mut self,
+    schema: &Map<String, Value>,
+    items: &[Value],
+    path: &str,
+    depth: usize,
+  ) {
+    let len = items.len() as u64;
+    if let Some(min) = schema.get("minItems").and_then(Value::as_u64)
+      && len < min
+    {
+      self.fail(path, format!("must have at least {} items", min));
+    }
+    if let Some(max) = schema.get("maxItems").and_then(Value::as_u64)
+      && len > max
+    {
+      self.fail(path, format!("must have at most {} items", max));
+    }
+    if let Some(item_schema) = schema.get("items") {
+      for (i, item) in items.iter().enumerate() {
+        self.check(item_schema, item, &format!("{}[{}]", path, i), depth + 1);
+      }
+    }
+  }
+
+  fn check_object(
+    &mut self,
+    schema: &Map<String, Value>,
+    fields: &Map<String, Value>,
+    path: &str,
+    depth: usize,
+  ) {
+    let field_path = |name: &str| format!("{}.{}", path, name);
+    if let Some(Value::Array(required)) = schema.get("required") {
+      for name in required.iter().filter_map(Value::as_str) {
+        if !fields.contains_key(name) {
+          self.fail(&field_path(name), "required field is missing".to_string());
+        }
+      }
+    }
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (name, field) in fields {
+      match properties.and_then(|p| p.get(name)) {
+        Some(field_schema) => self.check(field_schema, field, &field_path(name), depth + 1),
+        None => match schema.get("additionalProperties") {
+          Some(Value::Bool(false)) => self.fail(&field_path(name), "unexpected field".to_string()),
+          Some(extra) => self.check(extra, field, &field_path(name), depth + 1),
+          None => {}
+        },
+      }
+    }
+  }
+
+  fn check_combinators(
+    &mut self,
+    schema: &Map<String, Value>,
+    value: &Value,
+    path: &str,
+    depth: usize,
+  ) {
+    if let Some(Value::Array(all)) = schema.get("allOf") {
+      for sub in all {
+        self.check(sub, value, path, depth + 1);
+      }
+    }
+    if let Some(Value::Array(any)) = schema.get("anyOf")
+      && !any.iter().any(|sub| self.accepts(sub, value, depth + 1))
+    {
+      self.fail(path, "does not match any schema in anyOf".to_string());
+    }
+    if let Some(Value::Array(one)) = schema.get("oneOf") {
+      let matched = one
+        .iter()
+        .filter(|sub| self.accepts(sub, value, depth + 1))
+        .count();
+      if matched != 1 {
+        self.fail(
+          path,
+          format!(
+            "must match exactly one schema in oneOf, matched {}",
+            matched
+          ),
+        );
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use serde_json::json;
+
+  fn schema() -> Value {
+    json!({
+      "title": "Bug report",
+      "type": "object",
+      "properties": {
+        "title": {"type": "string", "minLength": 3},
+        "severity": {"enum": ["low", "high"]},
+        "count": {"type": "integer", "minimum": 1},
+        "tags": {"type": "array", "items": {"type": "string"}, "maxItems": 2},
+        "owner": {"$ref": "#/$defs/person"}
+      },
+      "required": ["title", "severity"],
+      "additionalProperties": false,
+      "$defs": {
+        "person": {
+          "type": "object",
+          "properties": {"email": {"type": "string", "pattern": "^[^@]+@[^@]+$"}},
+          "required": ["email"]
+        }
+      }
+    })
+  }
+
+  #[test]
+  fn test_validate_reports_each_failed_field() {
+    let schema = schema();
+    let ok = json!({"title": "崩溃了", "severity": "high", "count": 2.0, "tags": ["ui"]});
+    assert_eq!(validate(&schema, &ok), Vec::<String>::new());
+
+    let bad = json!({
+      "title": "x",
+      "severity": "urgent",
+      "count": 0,
+      "tags": ["a", 3, "c"],
+      "owner": {"email": "nobody"},
+      "extra": true
+    });
+    assert_eq!(
+      validate(&schema, &bad),
+      vec![
+        "$.count: must be at least 1",
+        "$.extra: unexpected field",
+        "$.owner.email: does not match pattern \"^[^@]+@[^@]+$\"",
+        "$.severity: must be one of \"low\", \"high\"",
+        "$.tags: must have at most 2 items",
+        "$.tags[1]: expected string, got integer",
+        "$.title: must be at least 3 characters",
+      ]
+    );
+    assert_eq!(
+      validate(&schema, &json!({"severity": "low"})),
+      vec!["$.title: required field is missing"]
+    );
+    assert_eq!(
+      validate(&schema, &json!([1])),
+      vec!["$: expected object, got array"]
+    );
+
+    let either = json!({"oneOf": [{"type": "string"}, {"type": "integer", "maximum": 5}]});
+    assert!(validate(&either, &json!(3)).is_empty());
+    assert_eq!(
+      validate(&either, &json!(9)),
+      vec!["$: must match exactly one schema in oneOf, matched 0"]
+    );
+    // 循环引用不会无限递归
+    let cycle = json!({"$ref": "#"});
+    assert!(validate(&cycle, &json!(1)).is_empty());
+  }
+
+  #[test]
+  fn test_load_names_the_schema_and_detects_rejection() {
+    let dir = temp_dir("json-schema");
+    let path = dir.join("report.schema.json");
+    std::fs::write(&path, schema().to_string()).unwrap();
+    let loaded = Schema::load(&path).unwrap();
+    assert_eq!(loaded.name, "Bug_report");
+    std::fs::write(&path, r#"{"type": "object"}"#).unwrap();
+    assert_eq!(Schema::load(&path).unwrap().name, "report_schema");
+    assert_eq!(
+      (loaded.format().name.as_str(), loaded.format().strict),
+      ("Bug_report", true)
+    );
+    std::fs::write(&path, "[]").unwrap();
+    assert!(Schema::load(&path).is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+
+    let error = |status: u16, body: &str| {
+      anyhow::Error::new(ApiError::from_response(
+        reqwest::StatusCode::from_u16(status).unwrap(),
+        body.to_string(),
+        None,
+      ))
+    };
+    assert!(rejected(&error(
+      400,
+      r#"{"error":{"message":"This response_format type is unavailable now"}}"#
+    )));
+    assert!(!rejected(&error(
+      400,
+      r#"{"error":{"message":"Model Not Exist"}}"#
+    )));
+    assert!(!rejected(&error(500, "response_format")));
+    assert!(!rejected(&anyhow::anyhow!("json_schema")));
+  }
+}
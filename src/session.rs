@@ -0,0 +1,851 @@
+use crate::api::Message;
+use crate::checkpoint::write_atomic;
+use crate::lock::{FileLock, LockError};
+use crate::provenance::Provenance;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionMeta {
+  pub title: String,
+  #[serde(default)]
+  pub description: String,
+  pub created_at: DateTime<Local>,
+  pub updated_at: DateTime<Local>,
+  #[serde(default)]
+  pub model: String,
+  /// 创建会话时的版本与设置
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub provenance: Option<Provenance>,
+}
+
+/// 会话文件格式版本。0：只有消息数组；1：meta、messages 和 vars；2：加上 format_version
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedSession {
+  pub format_version: u32,
+  pub meta: SessionMeta,
+  pub messages: Vec<Message>,
+  /// \setvar 定义的变量
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub vars: BTreeMap<String, String>,
+  /// \contract 的输出约定
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub contract: Vec<String>,
+}
+
+/// 由文件内容判断格式版本
+pub fn detect_version(value: &Value) -> Result<u32, String> {
+  match value {
+    Value::Array(_) => Ok(0),
+    Value::Object(map) => match map.get("format_version") {
+      None => Ok(1),
+      Some(v) => match v.as_u64() {
+        Some(n) if n <= FORMAT_VERSION as u64 => Ok(n as u32),
+        Some(n) => Err(format!(
+          "format version {} is newer than this deepcli supports ({})",
+          n, FORMAT_VERSION
+        )),
+        None => Err(format!("format_version must be a number, got {}", v)),
+      },
+    },
+    _ => Err("expected a JSON object or array".to_string()),
+  }
+}
+
+/// 版本 0 → 1：补上 meta，标题用会话名，时间用文件的修改时间
+fn migrate_v0(messages: Value, title: &str, modified: DateTime<Local>) -> Value {
+  serde_json::json!({
+    "meta": {
+      "title": title,
+      "created_at": modified,
+      "updated_at": modified,
+    },
+    "messages": messages,
+  })
+}
+
+/// 版本 1 → 2：只加上版本号
+fn migrate_v1(mut session: Value) -> Value {
+  session["format_version"] = 2.into();
+  session
+}
+
+/// 逐版本升级到 FORMAT_VERSION，返回升级后的内容和原来的版本
+pub fn migrate(
+  mut value: Value,
+  title: &str,
+  modified: DateTime<Local>,
+) -> Result<(Value, u32), String> {
+  let detected = detect_version(&value)?;
+  let mut version = detected;
+  while version < FORMAT_VERSION {
+    value = match version {
+      0 => migrate_v0(value, title, modified),
+      1 => migrate_v1(value),
+      _ => unreachable!("no migration from version {}", version),
+    };
+    version += 1;
+  }
+  Ok((value, detected))
+}
+
+/// 输入中有、重新序列化后却没有的字段，即反序列化时被忽略的字段。
+/// 空值可能只是被 skip_serializing_if 省略的默认值，不算
+fn unknown_fields(input: &Value, output: &Value, prefix: &str, found: &mut Vec<String>) {
+  match (input, output) {
+    (Value::Object(input), Value::Object(output)) => {
+      for (key, value) in input {
+        let path = match prefix {
+          "" => key.clone(),
+          _ => format!("{}.{}", prefix, key),
+        };
+        match output.get(key) {
+          Some(out) => unknown_fields(value, out, &path, found),
+          None if is_empty(value) => {}
+          None => found.push(path),
+        }
+      }
+    }
+    (Value::Array(input), Value::Array(output)) => {
+      for (i, (value, out)) in input.iter().zip(output).enumerate() {
+        unknown_fields(value, out, &format!("{}[{}]", prefix, i), found);
+      }
+    }
+    _ => {}
+  }
+}
+
+fn is_empty(value: &Value) -> bool {
+  match value {
+    Value::Null => true,
+    Value::Object(map) => map.is_empty(),
+    Value::Array(items) => items.is_empty(),
+    _ => false,
+  }
+}
+
+/// 解析会话文件内容。失败时返回检测到的版本和原因；`strict` 时未知字段也算失败
+pub fn parse_session(
+  data: &[u8],
+  title: &str,
+  modified: DateTime<Local>,
+  strict: bool,
+) -> Result<(SavedSession, u32), (Option<u32>, String)> {
+  let value: Value = serde_json::from_slice(data).map_err(|e| (None, e.to_string()))?;
+  let (value, version) = migrate(value, title, modified).map_err(|e| (None, e))?;
+  let session: SavedSession =
+    serde_json::from_value(value.clone()).map_err(|e| (Some(version), e.to_string()))?;
+  if strict {
+    let mut found = vec![];
+    let output = serde_json::to_value(&session).expect("session serialization cannot fail");
+    unknown_fields(&value, &output, "", &mut found);
+    if !found.is_empty() {
+      return Err((
+        Some(version),
+        format!("unknown fields: {}", found.join(", ")),
+      ));
+    }
+  }
+  Ok((session, version))
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+  InvalidName(String),
+  NotFound(String),
+  AlreadyExists(String),
+  /// 另一个实例打开了这个会话用于写入
+  Locked {
+    name: String,
+    pid: Option<u32>,
+  },
+  Malformed {
+    path: PathBuf,
+    /// 能识别出格式版本时的版本号
+    version: Option<u32>,
+    reason: String,
+  },
+  Io {
+    path: PathBuf,
+    source: std::io::Error,
+  },
+}
+
+impl fmt::Display for SessionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SessionError::InvalidName(name) => write!(
+        f,
+        "Invalid session name '{}': use letters, digits, '-', '_' or '.', not starting with '.'",
+        name
+      ),
+      SessionError::NotFound(name) => write!(f, "Session '{}' not found", name),
+      SessionError::AlreadyExists(name) => write!(f, "Session '{}' already exists", name),
+      SessionError::Locked {
+        name,
+        pid: Some(pid),
+      } => write!(
+        f,
+        "Session '{}' is open in another deepcli (pid {})",
+        name, pid
+      ),
+      SessionError::Locked { name, pid: None } => {
+        write!(f, "Session '{}' is open in another deepcli", name)
+      }
+      SessionError::Malformed {
+        path,
+        version: Some(version),
+        reason,
+      } => write!(
+        f,
+        "{} is not a valid session file (format version {}): {}",
+        path.display(),
+        version,
+        reason
+      ),
+      SessionError::Malformed {
+        path,
+        version: None,
+        reason,
+      } => write!(
+        f,
+        "{} is not a valid session file: {}",
+        path.display(),
+        reason
+      ),
+      SessionError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+    }
+  }
+}
+
+impl std::error::Error for SessionError {}
+
+pub type SessionResult<T> = Result<T, SessionError>;
+
+/// 会话名就是文件名（不含 .json），禁止路径分隔符和隐藏文件
+pub fn validate_name(name: &str) -> SessionResult<()> {
+  let ok = !name.is_empty()
+    && name.len() <= 128
+    && !name.starts_with('.')
+    && name
+      .chars()
+      .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+  if ok {
+    Ok(())
+  } else {
+    Err(SessionError::InvalidName(name.to_string()))
+  }
+}
+
+/// 保存在 sessions 目录下的命名会话
+pub struct SessionStore {
+  dir: PathBuf,
+  /// 本实例打开用于写入的会话，锁保持到关闭或退出
+  held: Mutex<HashMap<String, FileLock>>,
+  /// 拒绝含有未知字段的文件
+  strict: bool,
+}
+
+/// `sessions migrate` 的结果
+#[derive(Debug, Default)]
+pub struct MigrateReport {
+  /// 会话名和原来的版本
+  pub upgraded: Vec<(String, u32)>,
+  pub current: usize,
+  pub failed: Vec<SessionError>,
+}
+
+impl SessionStore {
+  pub fn new(dir: PathBuf) -> Self {
+    Self {
+      dir,
+      held: Mutex::new(HashMap::new()),
+      strict: false,
+    }
+  }
+
+  pub fn with_strict(mut self, strict: bool) -> Self {
+    self.strict = strict;
+    self
+  }
+
+  /// 打开会话用于写入：加锁并保持，其他实例对它的修改会得到 Locked
+  pub fn open_for_writing(&self, name: &str) -> SessionResult<()> {
+    if let Some(lock) = self.guard(name)? {
+      self.held.lock().unwrap().insert(name.to_string(), lock);
+    }
+    Ok(())
+  }
+
+  /// 释放 open_for_writing 持有的锁
+  pub fn close(&self, name: &str) {
+    self.held.lock().unwrap().remove(name);
+  }
+
+  /// 修改会话前调用。本实例已持有锁时返回 None，否则在修改期间临时加锁
+  fn guard(&self, name: &str) -> SessionResult<Option<FileLock>> {
+    let path = self.path_of(name)?;
+    if self.held.lock().unwrap().contains_key(name) {
+      return Ok(None);
+    }
+    match FileLock::try_acquire(&path) {
+      Ok(lock) => Ok(Some(lock)),
+      Err(LockError::Held { pid, .. }) => Err(SessionError::Locked {
+        name: name.to_string(),
+        pid,
+      }),
+      Err(LockError::Io { path, message }) => Err(SessionError::Io {
+        path,
+        source: std::io::Error::other(message),
+      }),
+    }
+  }
+
+  pub fn path_of(&self, name: &str) -> SessionResult<PathBuf> {
+    validate_name(name)?;
+    Ok(self.dir.join(format!("{}.json", name)))
+  }
+
+  fn existing_path(&self, name: &str) -> SessionResult<PathBuf> {
+    let path = self.path_of(name)?;
+    if !path.is_file() {
+      return Err(SessionError::NotFound(name.to_string()));
+    }
+    Ok(path)
+  }
+
+  /// 总是以当前版本写入
+  fn write(&self, path: &Path, session: &SavedSession) -> SessionResult<()> {
+    let session = SavedSession {
+      format_version: FORMAT_VERSION,
+      ..session.clone()
+    };
+    let json = serde_json::to_vec_pretty(&session).expect("session serialization cannot fail");
+    write_atomic(path, &json).map_err(|e| SessionError::Io {
+      path: path.to_path_buf(),
+      source: std::io::Error::other(format!("{:#}", e)),
+    })
+  }
+
+  /// `\save <名称>`：meta 和环境信息保留第一次保存时的
+  pub fn save(
+    &self,
+    name: &str,
+    messages: &[Message],
+    model: &str,
+    provenance: Option<&Provenance>,
+  ) -> SessionResult<PathBuf> {
+    let path = self.path_of(name)?;
+    let _lock = self.guard(name)?;
+    let now = Local::now();
+    let (meta, vars, contract) = match self.load(name) {
+      // 保留会话最初的环境信息，旧文件没有时补上
+      Ok(existing) => (
+        SessionMeta {
+          updated_at: now,
+          model: model.to_string(),
+          provenance: existing.meta.provenance.or(provenance.cloned()),
+          ..existing.meta
+        },
+        existing.vars,
+        existing.contract,
+      ),
+      Err(_) => (
+        SessionMeta {
+          title: name.to_string(),
+          description: String::new(),
+          created_at: now,
+          updated_at: now,
+          model: model.to_string(),
+          provenance: provenance.cloned(),
+        },
+        BTreeMap::new(),
+        vec![],
+      ),
+    };
+    let session = SavedSession {
+      format_version: FORMAT_VERSION,
+      meta,
+      messages: messages.to_vec(),
+      vars,
+      contract,
+    };
+    self.write(&path, &session)?;
+    Ok(path)
+  }
+
+  /// 读取会话；旧版本的文件在内存中升级，写回要等下一次修改或 `sessions migrate`
+  pub fn load(&self, name: &str) -> SessionResult<SavedSession> {
+    self.load_versioned(name).map(|(session, _)| session)
+  }
+
+  fn load_versioned(&self, name: &str) -> SessionResult<(SavedSession, u32)> {
+    let path = self.existing_path(name)?;
+    let data = fs::read(&path).map_err(|source| SessionError::Io {
+      path: path.clone(),
+      source,
+    })?;
+    let modified = fs::metadata(&path)
+      .and_then(|m| m.modified())
+      .map(DateTime::<Local>::from)
+      .unwrap_or_else(|_| Local::now());
+    parse_session(&data, name, modified, self.strict).map_err(|(version, reason)| {
+      SessionError::Malformed {
+        path,
+        version,
+        reason,
+      }
+    })
+  }
+
+  /// 把所有旧版本的会话文件升级为当前版本，原文件先复制为 `<名称>.json.v<版本>.bak`
+  pub fn migrate_all(&self) -> SessionResult<MigrateReport> {
+    let mut report = MigrateReport::default();
+    for name in self.names()? {
+      let (session, version) = match self.load_versioned(&name) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+          report.failed.push(e);
+          continue;
+        }
+      };
+      if version == FORMAT_VERSION {
+        report.current += 1;
+        continue;
+      }
+      let path = self.path_of(&name)?;
+      let result = self.guard(&name).and_then(|_lock| {
+        let backup = self.dir.join(format!("{}.json.v{}.bak", name, version));
+        fs::copy(&path, &backup).map_err(|source| SessionError::Io {
+          path: backup,
+          source,
+        })?;
+        self.write(&path, &session)
+      });
+      match result {
+        Ok(()) => report.upgraded.push((name, version)),
+        Err(e) => report.failed.push(e),
+      }
+    }
+    Ok(report)
+  }
+
+  /// sessions 目录下所有 .json 文件的会话名
+  fn names(&self) -> SessionResult<Vec<String>> {
+    let entries = match fs::read_dir(&self.dir) {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+      Err(source) => {
+        return Err(SessionError::Io {
+          path: self.dir.clone(),
+          source,
+        });
+      }
+    };
+    let mut names: Vec<_> = entries
+      .filter_map(|e| e.ok())
+      .filter_map(|e| {
+        let path = e.path();
+        if path.extension()? != "json" {
+          return None;
+        }
+        Some(path.file_stem()?.to_str()?.to_string())
+      })
+      .collect();
+    names.sort();
+    Ok(names)
+  }
+
+  pub fn list(&self) -> SessionResult<Vec<(String, SessionMeta)>> {
+    let mut sessions: Vec<_> = self
+      .names()?
+      .into_iter()
+      .filter_map(|name| {
+        let session = self.load(&name).ok()?;
+        Some((name, session.meta))
+      })
+      .collect();
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.1.updated_at));
+    Ok(sessions)
+  }
+
+  pub fn rename(&self, old: &str, new: &str) -> SessionResult<PathBuf> {
+    let old_path = self.existing_path(old)?;
+    let new_path = self.path_of(new)?;
+    let _locks = (self.guard(old)?, self.guard(new)?);
+    if new_path.exists() {
+      return Err(SessionError::AlreadyExists(new.to_string()));
+    }
+    let mut session = self.load(old)?;
+    if session.meta.title == old {
+      session.meta.title = new.to_string();
+    }
+    session.meta.updated_at = Local::now();
+    self.write(&new_path, &session)?;
+    fs::remove_file(&old_path).map_err(|source| SessionError::Io {
+      path: old_path,
+      source,
+    })?;
+    Ok(new_path)
+  }
+
+  pub fn describe(&self, name: &str, description: &str) -> SessionResult<()> {
+    let path = self.existing_path(name)?;
+    let _lock = self.guard(name)?;
+    let mut session = self.load(name)?;
+    session.meta.description = description.to_string();
+    session.meta.updated_at = Local::now();
+    self.write(&path, &session)
+  }
+
+  /// 替换会话中保存的变量
+  pub fn set_vars(&self, name: &str, vars: &BTreeMap<String, String>) -> SessionResult<()> {
+    let path = self.existing_path(name)?;
+    let _lock = self.guard(name)?;
+    let mut session = self.load(name)?;
+    session.vars = vars.clone();
+    session.meta.updated_at = Local::now();
+    self.write(&path, &session)
+  }
+
+  /// 替换会话中保存的输出约定
+  pub fn set_contract(&self, name: &str, rules: &[String]) -> SessionResult<()> {
+    let path = self.existing_path(name)?;
+    let _lock = self.guard(name)?;
+    let mut session = self.load(name)?;
+    session.contract = rules.to_vec();
+    session.meta.updated_at = Local::now();
+    self.write(&path, &session)
+  }
+
+  pub fn delete(&self, name: &str) -> SessionResult<()> {
+    let path = self.existing_path(name)?;
+    let _lock = self.guard(name)?;
+    fs::remove_file(&path).map_err(|source| SessionError::Io { path, source })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+
+  fn messages() -> Vec<Message> {
+    vec![
+      Message::Simple {
+        role: "user".to_string(),
+        content: "你好".to_string(),
+        name: None,
+      },
+      Message::Simple {
+        role: "assistant".to_string(),
+        content: "你好！".to_string(),
+        name: None,
+      },
+    ]
+  }
+
+  #[test]
+  fn test_validate_name() {
+    for ok in ["work", "bug-123", "2026_10.notes", "调试"] {
+      assert!(validate_name(ok).is_ok(), "{}", ok);
+    }
+    for bad in ["", ".hidden", "a/b", "..", "a\\b", "with space", "tab\t"] {
+      assert!(
+        matches!(validate_name(bad), Err(SessionError::InvalidName(_))),
+        "{}",
+        bad
+      );
+    }
+  }
+
+  #[test]
+  fn test_save_and_list() {
+    let dir = temp_dir("session-list");
+    let store = SessionStore::new(dir.clone());
+    store
+      .save("first", &messages(), "deepseek-chat", None)
+      .unwrap();
+    let loaded = store.load("first").unwrap();
+    assert_eq!(loaded.meta.title, "first");
+    assert_eq!(loaded.meta.model, "deepseek-chat");
+    assert_eq!(loaded.messages.len(), 2);
+
+    // 再次保存保留 created_at、描述和变量
+    store.describe("first", "调试会话").unwrap();
+    let vars = BTreeMap::from([("schema".to_string(), "CREATE TABLE t;".to_string())]);
+    store.set_vars("first", &vars).unwrap();
+    store
+      .set_contract("first", &["不要用 emoji".to_string()])
+      .unwrap();
+    let before = store.load("first").unwrap().meta;
+    store
+      .save("first", &messages()[..1], "deepseek-r1", None)
+      .unwrap();
+    let after = store.load("first").unwrap();
+    assert_eq!(after.meta.created_at, before.created_at);
+    assert_eq!(after.meta.description, "调试会话");
+    assert_eq!(after.meta.model, "deepseek-r1");
+    assert_eq!(after.messages.len(), 1);
+    assert_eq!(after.vars, vars);
+    assert_eq!(after.contract, ["不要用 emoji"]);
+
+    let listed = store.list().unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].0, "first");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_provenance_kept_from_first_save() {
+    use crate::provenance::{BuildInfo, capture};
+    let dir = temp_dir("session-provenance");
+    let store = SessionStore::new(dir.clone());
+    let build = BuildInfo {
+      version: "0.1.0",
+      git_describe: None,
+    };
+    let first = capture(&build, "deepseek", "u", "deepseek-chat", &[], None);
+    let later = capture(&build, "deepseek", "u", "deepseek-reasoner", &[], None);
+    store
+      .save("p", &messages(), "deepseek-chat", Some(&first))
+      .unwrap();
+    store
+      .save("p", &messages(), "deepseek-reasoner", Some(&later))
+      .unwrap();
+    let meta = store.load("p").unwrap().meta;
+    assert_eq!(meta.provenance, Some(first));
+    assert_eq!(meta.model, "deepseek-reasoner");
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_locked_session_reports_holder() {
+    let dir = temp_dir("session-locked");
+    // 两个 store 模拟两个实例：锁属于各自打开的文件，同一进程内也会冲突
+    let first = SessionStore::new(dir.clone());
+    let second = SessionStore::new(dir.clone());
+    first.open_for_writing("work").unwrap();
+    first
+      .save("work", &messages(), "deepseek-chat", None)
+      .unwrap();
+
+    let err = second
+      .save("work", &messages(), "deepseek-chat", None)
+      .unwrap_err();
+    assert!(matches!(&err, SessionError::Locked { name, pid: Some(pid) }
+      if name == "work" && *pid == std::process::id()));
+    assert!(err.to_string().contains("pid"));
+    assert!(matches!(
+      second.delete("work"),
+      Err(SessionError::Locked { .. })
+    ));
+    // 只读操作不受影响
+    assert_eq!(second.load("work").unwrap().messages.len(), 2);
+    assert_eq!(second.list().unwrap().len(), 1);
+
+    first.close("work");
+    second
+      .save("work", &messages()[..1], "deepseek-chat", None)
+      .unwrap();
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_rename() {
+    let dir = temp_dir("session-rename");
+    let store = SessionStore::new(dir.clone());
+    store
+      .save("old", &messages(), "deepseek-chat", None)
+      .unwrap();
+    store
+      .save("taken", &messages(), "deepseek-chat", None)
+      .unwrap();
+
+    assert!(matches!(
+      store.rename("old", "taken"),
+      Err(SessionError::AlreadyExists(_))
+    ));
+    assert!(matches!(
+      store.rename("missing", "x"),
+      Err(SessionError::NotFound(_))
+    ));
+    assert!(matches!(
+      store.rename("old", "../escape"),
+      Err(SessionError::InvalidName(_))
+    ));
+
+    store.rename("old", "new").unwrap();
+    assert!(!dir.join("old.json").exists());
+    let renamed = store.load("new").unwrap();
+    assert_eq!(renamed.meta.title, "new");
+    assert_eq!(renamed.messages.len(), 2);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_legacy_file_migrated_on_touch() {
+    let dir = temp_dir("session-legacy");
+    let store = SessionStore::new(dir.clone());
+    fs::write(
+      dir.join("legacy.json"),
+      serde_json::to_vec(&messages()).unwrap(),
+    )
+    .unwrap();
+
+    let loaded = store.load("legacy").unwrap();
+    assert_eq!(loaded.meta.title, "legacy");
+    assert_eq!(loaded.messages.len(), 2);
+
+    store.describe("legacy", "迁移后的描述").unwrap();
+    let raw: serde_json::Value =
+      serde_json::from_slice(&fs::read(dir.join("legacy.json")).unwrap()).unwrap();
+    assert_eq!(raw["meta"]["description"], "迁移后的描述");
+    assert_eq!(raw["messages"].as_array().unwrap().len(), 2);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_delete_and_errors() {
+    let dir = temp_dir("session-delete");
+    let store = SessionStore::new(dir.clone());
+    store
+      .save("gone", &messages(), "deepseek-chat", None)
+      .unwrap();
+    store.delete("gone").unwrap();
+    assert!(matches!(
+      store.delete("gone"),
+      Err(SessionError::NotFound(_))
+    ));
+    assert!(matches!(
+      store.describe("gone", "x"),
+      Err(SessionError::NotFound(_))
+    ));
+
+    fs::write(dir.join("broken.json"), "{not json").unwrap();
+    assert!(matches!(
+      store.load("broken"),
+      Err(SessionError::Malformed { .. })
+    ));
+    // 损坏的文件不影响列表
+    assert!(store.list().unwrap().is_empty());
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  const FIXTURES: [(&str, &[u8]); 3] = [
+    ("v0", include_bytes!("testdata/sessions/v0.json")),
+    ("v1", include_bytes!("testdata/sessions/v1.json")),
+    ("v2", include_bytes!("testdata/sessions/v2.json")),
+  ];
+
+  fn modified() -> DateTime<Local> {
+    DateTime::parse_from_rfc3339("2026-08-01T12:00:00+08:00")
+      .unwrap()
+      .with_timezone(&Local)
+  }
+
+  #[test]
+  fn test_fixtures_of_each_version_upgrade() {
+    let mut upgraded = vec![];
+    for (version, (name, data)) in FIXTURES.iter().enumerate() {
+      let (session, detected) = parse_session(data, name, modified(), true).unwrap();
+      assert_eq!(detected, version as u32);
+      assert_eq!(session.format_version, FORMAT_VERSION);
+      upgraded.push(session);
+    }
+    // v0：meta 由会话名和文件修改时间补全
+    assert_eq!(upgraded[0].meta.title, "v0");
+    assert_eq!(upgraded[0].meta.created_at, modified());
+    assert_eq!(upgraded[0].meta.model, "");
+    assert_eq!(upgraded[0].messages.len(), 2);
+    // v1：原有的字段全部保留
+    let v1 = &upgraded[1];
+    assert_eq!(v1.meta.title, "调试死锁");
+    assert_eq!(v1.meta.provenance.as_ref().unwrap().profile, "deepseek");
+    assert_eq!(v1.vars["lock"], "std::sync::Mutex");
+    assert_eq!(v1.messages[1].speaker(), "user(alice)");
+    assert_eq!(upgraded[2].meta.model, "deepseek-reasoner");
+
+    let (value, version) = migrate(serde_json::json!([]), "x", modified()).unwrap();
+    assert_eq!(version, 0);
+    assert_eq!(value["format_version"], FORMAT_VERSION);
+    assert_eq!(value["meta"]["title"], "x");
+  }
+
+  #[test]
+  fn test_unknown_fields_only_rejected_when_strict() {
+    let mut value: Value = serde_json::from_slice(FIXTURES[1].1).unwrap();
+    value["branches"] = serde_json::json!([{"at": 2}]);
+    value["meta"]["color"] = "red".into();
+    value["messages"][0]["pinned"] = true.into();
+    // 空值可能是被省略的默认值，不算未知字段
+    value["meta"]["provenance"]["git_describe"] = Value::Null;
+    let data = serde_json::to_vec(&value).unwrap();
+    assert!(parse_session(&data, "x", modified(), false).is_ok());
+    let (version, reason) = parse_session(&data, "x", modified(), true).unwrap_err();
+    assert_eq!(version, Some(1));
+    assert_eq!(
+      reason,
+      "unknown fields: branches, messages[0].pinned, meta.color"
+    );
+
+    let (version, reason) =
+      parse_session(br#"{"format_version": 9}"#, "x", modified(), false).unwrap_err();
+    assert_eq!(version, None);
+    assert!(reason.contains("newer"), "{}", reason);
+
+    // 错误中带上文件路径、检测到的版本和缺少的字段
+    let dir = temp_dir("session-strict");
+    fs::write(
+      dir.join("bad.json"),
+      br#"{"meta": {"title": "t"}, "messages": []}"#,
+    )
+    .unwrap();
+    let err = SessionStore::new(dir.clone()).load("bad").unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("bad.json"), "{}", message);
+    assert!(message.contains("format version 1"), "{}", message);
+    assert!(
+      message.contains("missing field `created_at`"),
+      "{}",
+      message
+    );
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_migrate_all_in_place_with_backups() {
+    let dir = temp_dir("session-migrate");
+    for (name, data) in FIXTURES {
+      fs::write(dir.join(format!("{}.json", name)), data).unwrap();
+    }
+    fs::write(dir.join("broken.json"), "{not json").unwrap();
+    let store = SessionStore::new(dir.clone());
+    let report = store.migrate_all().unwrap();
+    assert_eq!(
+      report.upgraded,
+      [("v0".to_string(), 0), ("v1".to_string(), 1)]
+    );
+    assert_eq!(report.current, 1);
+    assert_eq!(report.failed.len(), 1);
+
+    assert_eq!(fs::read(dir.join("v1.json.v1.bak")).unwrap(), FIXTURES[1].1);
+    assert!(dir.join("v0.json.v0.bak").exists());
+    let raw: Value = serde_json::from_slice(&fs::read(dir.join("v1.json")).unwrap()).unwrap();
+    assert_eq!(raw["format_version"], FORMAT_VERSION);
+    assert_eq!(raw["vars"]["lock"], "std::sync::Mutex");
+    // 已经是最新格式时不再改动
+    let again = store.migrate_all().unwrap();
+    assert!(again.upgraded.is_empty());
+    assert_eq!(again.current, 3);
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
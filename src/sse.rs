@@ -0,0 +1,160 @@
+//! 按 Server-Sent Events 规范切分流式响应：行尾可以是 `\r\n`、`\n` 或 `\r`，
+//! `data:` 后的空格可有可无，`:` 开头的注释行（代理和 DeepSeek 的 keep-alive）跳过，
+//! 一个事件的多行 data 用换行连接，空行结束一个事件。
+//! 连接关闭时还没有以换行结束的最后一个事件同样交出。
+
+/// 只保留事件的 data 字段；event、id、retry 对聊天接口没有意义
+#[derive(Debug, Default)]
+pub struct SseParser {
+  buffer: Vec<u8>,
+  /// 当前事件已收到的 data 行
+  data: Vec<String>,
+}
+
+impl SseParser {
+  /// 加入一块字节，返回其中完整的事件。被拆开的行（包括多字节字符）留到下一块
+  pub fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+    self.buffer.extend_from_slice(bytes);
+    let mut events = vec![];
+    let mut start = 0;
+    while let Some(offset) = self.buffer[start..]
+      .iter()
+      .position(|&b| b == b'\n' || b == b'\r')
+    {
+      let end = start + offset;
+      let next = match self.buffer[end] {
+        // 行尾的 \r 可能是 \r\n 的前一半
+        b'\r' if end + 1 == self.buffer.len() => break,
+        b'\r' if self.buffer[end + 1] == b'\n' => end + 2,
+        _ => end + 1,
+      };
+      let line = String::from_utf8_lossy(&self.buffer[start..end]).into_owned();
+      events.extend(self.line(&line));
+      start = next;
+    }
+    self.buffer.drain(..start);
+    events
+  }
+
+  /// 连接已关闭：交出没有换行结尾的最后一行和还没有被空行结束的事件
+  pub fn finish(&mut self) -> Option<String> {
+    let rest = std::mem::take(&mut self.buffer);
+    let rest = String::from_utf8_lossy(&rest);
+    let rest = rest.strip_suffix('\r').unwrap_or(&rest);
+    if !rest.is_empty() {
+      self.line(rest);
+    }
+    self.dispatch()
+  }
+
+  fn line(&mut self, line: &str) -> Option<String> {
+    if line.is_empty() {
+      return self.dispatch();
+    }
+    if line.starts_with(':') {
+      return None;
+    }
+    let (field, value) = match line.split_once(':') {
+      Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+      None => (line, ""),
+    };
+    if field == "data" {
+      self.data.push(value.to_string());
+    }
+    None
+  }
+
+  fn dispatch(&mut self) -> Option<String> {
+    if self.data.is_empty() {
+      return None;
+    }
+    Some(std::mem::take(&mut self.data).join("\n"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// 抓取的 DashScope 兼容模式响应（qwen-plus，截短了 id）
+  const DASHSCOPE: &str = concat!(
+    "data: {\"choices\":[{\"delta\":{\"content\":\"\",\"role\":\"assistant\"},\"index\":0,\"logprobs\":null,\"finish_reason\":null}],\"object\":\"chat.completion.chunk\",\"usage\":null,\"created\":1715931028,\"system_fingerprint\":null,\"model\":\"qwen-plus\",\"id\":\"chatcmpl-3bb0\"}\n\n",
+    "data: {\"choices\":[{\"finish_reason\":null,\"delta\":{\"content\":\"你好\"},\"index\":0,\"logprobs\":null}],\"object\":\"chat.completion.chunk\",\"usage\":null,\"created\":1715931028,\"system_fingerprint\":null,\"model\":\"qwen-plus\",\"id\":\"chatcmpl-3bb0\"}\n\n",
+    "data: {\"choices\":[{\"delta\":{\"content\":\"！\"},\"finish_reason\":\"stop\",\"index\":0,\"logprobs\":null}],\"object\":\"chat.completion.chunk\",\"usage\":null,\"created\":1715931028,\"system_fingerprint\":null,\"model\":\"qwen-plus\",\"id\":\"chatcmpl-3bb0\"}\n\n",
+    "data: [DONE]\n\n",
+  );
+
+  /// 抓取的 DeepSeek 响应：排队时发送 keep-alive 注释，CRLF 行尾
+  const DEEPSEEK: &str = concat!(
+    ": keep-alive\r\n\r\n",
+    ": keep-alive\r\n\r\n",
+    "data: {\"id\":\"a1\",\"object\":\"chat.completion.chunk\",\"created\":1737000000,\"model\":\"deepseek-reasoner\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":null,\"reasoning_content\":\"嗯\"},\"finish_reason\":null}]}\r\n\r\n",
+    "data: {\"id\":\"a1\",\"object\":\"chat.completion.chunk\",\"created\":1737000000,\"model\":\"deepseek-reasoner\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"好\"},\"finish_reason\":\"stop\"}]}\r\n\r\n",
+    "data: [DONE]\r\n\r\n",
+  );
+
+  /// 压缩过的 OpenAI 风格响应：data 后没有空格，最后一个事件没有换行就关闭了连接
+  const COMPACT: &str = concat!(
+    "data:{\"choices\":[{\"delta\":{\"content\":\"a\"}}]}\n\n",
+    "event: message\nid: 2\nretry: 1000\ndata:{\"choices\":[{\"delta\":{\"content\":\"b\"}}]}\n\n",
+    "data:{\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}",
+  );
+
+  fn events(chunks: &[&[u8]]) -> Vec<String> {
+    let mut parser = SseParser::default();
+    let mut events: Vec<String> = chunks.iter().flat_map(|c| parser.push(c)).collect();
+    events.extend(parser.finish());
+    events
+  }
+
+  #[test]
+  fn test_events_are_split_per_spec() {
+    let cases: &[(&str, &str, &[&str])] = &[
+      ("lf", "data: a\n\ndata: b\n\n", &["a", "b"]),
+      ("crlf", "data: a\r\n\r\ndata: b\r\n\r\n", &["a", "b"]),
+      ("cr", "data: a\r\rdata: b\r\r", &["a", "b"]),
+      ("no space", "data:a\n\n", &["a"]),
+      // 只去掉一个空格
+      ("two spaces", "data:  a\n\n", &[" a"]),
+      ("comments", ": ping\n\n:\ndata: a\n: mid-event\n\n", &["a"]),
+      (
+        "multi-line",
+        "data: {\"a\":\ndata: 1}\n\n",
+        &["{\"a\":\n1}"],
+      ),
+      ("empty data", "data\n\ndata:\n\n", &["", ""]),
+      ("other fields", "event: done\nid: 7\n\n", &[]),
+      ("no trailing newline", "data: a\n\ndata: b", &["a", "b"]),
+      ("no blank line", "data: a\n", &["a"]),
+      ("trailing cr", "data: a\r", &["a"]),
+    ];
+    for (name, text, expected) in cases {
+      assert_eq!(events(&[text.as_bytes()]), *expected, "{}", name);
+    }
+  }
+
+  #[test]
+  fn test_captured_fixtures_in_any_chunking() {
+    let cases: &[(&str, &str, &[&str])] = &[
+      // 只有 role 的第一个增量被跳过
+      ("dashscope", DASHSCOPE, &["你好", "！"]),
+      ("deepseek", DEEPSEEK, &["", "好"]),
+      ("compact", COMPACT, &["a", "b", ""]),
+    ];
+    for (name, fixture, contents) in cases {
+      let whole = events(&[fixture.as_bytes()]);
+      // 逐字节送入：CRLF 和多字节字符都会被拆开
+      let bytes: Vec<&[u8]> = fixture.as_bytes().chunks(1).collect();
+      assert_eq!(events(&bytes), whole, "{}", name);
+      let decoded: Vec<String> = whole
+        .iter()
+        .filter(|data| *data != "[DONE]")
+        .filter_map(|data| crate::api::parse_stream_data(data))
+        .map(|chunk| chunk.content)
+        .collect();
+      assert_eq!(decoded, *contents, "{}", name);
+    }
+    let reasoning = crate::api::parse_stream_data(&events(&[DEEPSEEK.as_bytes()])[0]).unwrap();
+    assert_eq!(reasoning.reasoning, "嗯");
+  }
+}
@@ -0,0 +1,132 @@
+use crate::api::Message;
+use crate::reasoning::Reasoning;
+use std::cell::RefCell;
+
+/// 一轮对话对历史的改动。一轮可能包含多次请求（自动续写、工具调用、纠偏），
+/// 其间产生的消息都写在工作副本上，整轮结束时一次提交；遇到无法恢复的失败时整体回滚，
+/// 连同本轮的问题一起撤销，历史回到提问之前，问题可以直接重新发送。
+///
+/// 提交之前 `history` 保持原样，快照只会写入已提交的历史。
+/// 没有提交也没有回滚就被丢弃时（例如输出失败），`history` 同样保持原样
+pub struct Staged<'a> {
+  history: &'a mut Vec<Message>,
+  work: Vec<Message>,
+  /// 本轮问题在 history 中的位置
+  start: usize,
+  /// 待提交的推理过程：(轮次, 文本)
+  reasoning: Vec<(usize, String)>,
+}
+
+impl<'a> Staged<'a> {
+  /// 本轮从历史末尾的用户消息（问题）开始
+  pub fn begin(history: &'a mut Vec<Message>) -> Self {
+    let start = match history.last() {
+      Some(
+        Message::Simple { role, .. }
+        | Message::MultiModal { role, .. }
+        | Message::Tool { role, .. },
+      ) if role == "user" => history.len() - 1,
+      _ => history.len(),
+    };
+    Self {
+      work: history.clone(),
+      history,
+      start,
+      reasoning: vec![],
+    }
+  }
+
+  /// 已提交的历史加上本轮暂存的消息，用于构造请求
+  pub fn messages(&self) -> &[Message] {
+    &self.work
+  }
+
+  pub fn push(&mut self, message: Message) {
+    self.work.push(message);
+  }
+
+  /// 纠偏说明并入本轮的问题
+  pub fn merge_note(&mut self, note: &str) {
+    crate::steer::merge_note(&mut self.work, note);
+  }
+
+  /// 记在当前轮次名下，提交时才写入
+  pub fn keep_reasoning(&mut self, text: &str) {
+    let turn = crate::notes::turn_count(&self.work);
+    self.reasoning.push((turn, text.to_string()));
+  }
+
+  pub fn commit(self, reasoning: Option<&RefCell<Reasoning>>) {
+    if let Some(log) = reasoning {
+      let mut log = log.borrow_mut();
+      for (turn, text) in &self.reasoning {
+        log.record(*turn, text);
+      }
+    }
+    *self.history = self.work;
+  }
+
+  /// 丢弃本轮的所有改动并撤销问题
+  pub fn rollback(self) {
+    self.history.truncate(self.start);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn simple(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  fn json(history: &[Message]) -> String {
+    serde_json::to_string(history).unwrap()
+  }
+
+  #[test]
+  fn test_commit_applies_staged_messages_and_reasoning() {
+    let mut history = vec![simple("user", "q1"), simple("assistant", "a1")];
+    history.push(simple("user", "q2"));
+    let reasoning = RefCell::new(Reasoning::default());
+
+    let mut staged = Staged::begin(&mut history);
+    staged.merge_note("简短一些");
+    staged.push(simple("assistant", "第一部分"));
+    staged.keep_reasoning("先想一想");
+    staged.push(simple("user", crate::turn::CONTINUE_PROMPT));
+    staged.push(simple("assistant", "第二部分"));
+    assert_eq!(staged.messages().len(), 6);
+    staged.commit(Some(&reasoning));
+
+    assert_eq!(history.len(), 6);
+    assert!(json(&history[2..3]).contains("简短一些"));
+    assert_eq!(reasoning.borrow().for_turn(2), Some("先想一想"));
+  }
+
+  #[test]
+  fn test_rollback_removes_question_and_staged_messages() {
+    let mut history = vec![simple("user", "q1"), simple("assistant", "a1")];
+    let committed = json(&history);
+    history.push(simple("user", "q2"));
+
+    let mut staged = Staged::begin(&mut history);
+    staged.push(simple("assistant", "部分"));
+    staged.merge_note("改一下");
+    staged.keep_reasoning("推理");
+    staged.rollback();
+    assert_eq!(json(&history), committed);
+
+    // 丢弃而不提交时原样保留，问题还在
+    history.push(simple("user", "q2"));
+    let before = json(&history);
+    let mut staged = Staged::begin(&mut history);
+    staged.push(simple("assistant", "部分"));
+    drop(staged);
+    assert_eq!(json(&history), before);
+  }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// 空白交互会话启动时可选的对话模板
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Starter {
+  pub title: String,
+  /// 作为 system prompt，与 --persona 的正文相同
+  pub system: String,
+  /// 选择后立即发送的第一条消息
+  #[serde(default)]
+  pub message: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+  #[serde(default)]
+  starters: Vec<Starter>,
+}
+
+pub fn parse(text: &str) -> Result<Vec<Starter>> {
+  let config: Config = serde_yaml::from_str(text).context("Invalid starters file")?;
+  if let Some(starter) = config.starters.iter().find(|s| s.title.trim().is_empty()) {
+    anyhow::bail!(
+      "Starter with system prompt '{}' has no title",
+      starter.system
+    );
+  }
+  Ok(config.starters)
+}
+
+/// 读取 `starters.yaml`，文件不存在时没有模板
+pub fn load(path: &Path) -> Result<Vec<Starter>> {
+  match fs::read_to_string(path) {
+    Ok(text) => parse(&text).context(format!("Cannot load {:?}", path)),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+    Err(e) => Err(e).context(format!("Failed to read {:?}", path)),
+  }
+}
+
+/// 启动时的情况，决定是否显示模板菜单
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Launch {
+  /// 通过 --persona 指定了 system prompt
+  pub persona: bool,
+  /// 通过 --system 或 --system-file 指定了 system prompt
+  pub system: bool,
+  /// 命令行给出了问题
+  pub query: bool,
+  /// 通过 --import 带入了历史
+  pub imported: bool,
+  pub stdin_is_terminal: bool,
+}
+
+/// 只有在没有其他安排、且能交互输入的空白会话中才显示菜单
+pub fn should_offer(launch: Launch, starters: &[Starter]) -> bool {
+  !starters.is_empty()
+    && launch.stdin_is_terminal
+    && !launch.persona
+    && !launch.system
+    && !launch.query
+    && !launch.imported
+}
+
+pub fn render_menu(starters: &[Starter]) -> String {
+  let mut menu = String::from("对话模板：\n");
+  for (i, starter) in starters.iter().enumerate() {
+    menu.push_str(&format!("  {:>2}. {}\n", i + 1, starter.title));
+  }
+  menu.push_str("输入编号选择，直接回车开始空白会话: ");
+  menu
+}
+
+/// 解析菜单输入：空输入为不选择，否则为从 1 开始的编号
+pub fn parse_choice(input: &str, count: usize) -> Result<Option<usize>, String> {
+  let input = input.trim();
+  if input.is_empty() {
+    return Ok(None);
+  }
+  match input.parse::<usize>() {
+    Ok(n) if (1..=count).contains(&n) => Ok(Some(n - 1)),
+    _ => Err(format!("请输入 1 到 {} 之间的编号", count)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const STARTERS: &str = r#"
+starters:
+  - title: 代码审查
+    system: 你是一名严格的代码审查者。
+  - title: 翻译助手
+    system: 你是一名中英翻译，只输出译文。
+    message: 接下来我发的每段话都请翻译成英文
+"#;
+
+  #[test]
+  fn test_parse_and_menu() {
+    let starters = parse(STARTERS).unwrap();
+    assert_eq!(starters.len(), 2);
+    assert_eq!(starters[0].message, None);
+    assert_eq!(
+      starters[1].message.as_deref(),
+      Some("接下来我发的每段话都请翻译成英文")
+    );
+    assert_eq!(
+      render_menu(&starters),
+      "对话模板：\n   1. 代码审查\n   2. 翻译助手\n输入编号选择，直接回车开始空白会话: "
+    );
+    assert!(parse("starters:\n  - title: ''\n    system: x\n").is_err());
+    assert!(parse("starters:\n  - title: x\n").is_err());
+    assert!(parse("").unwrap().is_empty());
+
+    let missing = std::env::temp_dir().join("deepcli-no-such-starters.yaml");
+    assert!(load(&missing).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_parse_choice() {
+    assert_eq!(parse_choice("", 3), Ok(None));
+    assert_eq!(parse_choice("  \n", 3), Ok(None));
+    assert_eq!(parse_choice("3", 3), Ok(Some(2)));
+    assert_eq!(parse_choice(" 1 ", 3), Ok(Some(0)));
+    assert!(parse_choice("0", 3).is_err());
+    assert!(parse_choice("4", 3).is_err());
+    assert!(parse_choice("翻译", 3).is_err());
+  }
+
+  #[test]
+  fn test_menu_precedence() {
+    let starters = parse(STARTERS).unwrap();
+    let blank = Launch {
+      stdin_is_terminal: true,
+      ..Launch::default()
+    };
+    assert!(should_offer(blank, &starters));
+    assert!(!should_offer(blank, &[]));
+    for launch in [
+      Launch {
+        persona: true,
+        ..blank
+      },
+      Launch {
+        system: true,
+        ..blank
+      },
+      Launch {
+        query: true,
+        ..blank
+      },
+      Launch {
+        imported: true,
+        ..blank
+      },
+      Launch {
+        stdin_is_terminal: false,
+        ..blank
+      },
+    ] {
+      assert!(!should_offer(launch, &starters), "{:?}", launch);
+    }
+  }
+}
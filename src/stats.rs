@@ -0,0 +1,398 @@
+use crate::api::Usage;
+use crate::models;
+use std::time::Duration;
+
+/// 一个模型在自动路由下回答的轮次和用量（也计入会话的总量）
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RoutedUsage {
+  pub model: String,
+  pub turns: usize,
+  pub prompt_tokens: usize,
+  pub completion_tokens: usize,
+}
+
+/// 会话级计数器，\stats 与退出时的摘要都从这里读取
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SessionStats {
+  pub turns: usize,
+  pub requests: usize,
+  pub prompt_tokens: usize,
+  pub completion_tokens: usize,
+  pub wait_time: Duration,
+  pub auto_continues: usize,
+  pub summarizations: usize,
+  /// 单条消息超出预算而被截断的次数
+  pub truncations: usize,
+  /// 流式输出时实际刷新终端的次数
+  pub flushes: usize,
+  /// --auto-route 交给其他模型回答的轮次，按各自的价格估算费用
+  pub routed: Vec<RoutedUsage>,
+  /// 服务方报告的用量累计，没有报告的请求不计入
+  pub reported: Usage,
+  /// 最近一轮问答（包括自动续写）报告的用量
+  pub last_exchange: Option<Usage>,
+  /// --verify 和 \verify 的复核请求，按复核模型计价，turns 为复核次数
+  pub verification: Option<RoutedUsage>,
+  /// warm_start 发出的预热请求，用量计入总量但不算请求次数
+  pub warmups: usize,
+  /// 会话中第一个收到内容的请求的首字延迟，warm_start 用它比较预热的效果
+  pub first_ttft: Option<Duration>,
+  ttft_total: Duration,
+  ttft_samples: u32,
+}
+
+impl SessionStats {
+  /// 记录一次 API 请求：`ttft` 为收到首个内容片段的耗时，`elapsed` 为整个流的耗时
+  pub fn record_request(
+    &mut self,
+    prompt_tokens: usize,
+    completion_tokens: usize,
+    ttft: Option<Duration>,
+    elapsed: Duration,
+  ) {
+    self.requests += 1;
+    self.prompt_tokens += prompt_tokens;
+    self.completion_tokens += completion_tokens;
+    self.wait_time += elapsed;
+    if let Some(ttft) = ttft {
+      self.first_ttft.get_or_insert(ttft);
+      self.ttft_total += ttft;
+      self.ttft_samples += 1;
+    }
+  }
+
+  /// 记录一次预热请求的用量（估算）
+  pub fn record_warmup(&mut self, prompt_tokens: usize, completion_tokens: usize) {
+    self.warmups += 1;
+    self.prompt_tokens += prompt_tokens;
+    self.completion_tokens += completion_tokens;
+  }
+
+  /// 新的一轮问答开始，\tokens 的“上一轮”从这里算起
+  pub fn start_exchange(&mut self) {
+    self.last_exchange = None;
+  }
+
+  /// 记录响应中的 usage 对象
+  pub fn record_usage(&mut self, usage: Usage) {
+    self.reported += usage;
+    *self.last_exchange.get_or_insert_default() += usage;
+  }
+
+  /// \tokens：服务方报告的上一轮和整个会话的用量；从未报告时给出估算值
+  pub fn render_usage(&self) -> String {
+    let line = |u: &Usage| {
+      format!(
+        "输入 {} / 输出 {} / 合计 {}",
+        u.prompt_tokens, u.completion_tokens, u.total_tokens
+      )
+    };
+    let last = match &self.last_exchange {
+      Some(usage) => line(usage),
+      None => "服务方没有报告".to_string(),
+    };
+    let total = match self.reported == Usage::default() {
+      true => format!(
+        "服务方没有报告（估算: 输入 {} / 输出 {}）",
+        self.prompt_tokens, self.completion_tokens
+      ),
+      false => line(&self.reported),
+    };
+    format!("上一轮: {}\n本次会话: {}", last, total)
+  }
+
+  /// 首字延迟的累计值和样本数，用于算出单轮的首字延迟
+  #[cfg(feature = "otlp")]
+  pub fn ttft_sum(&self) -> (Duration, u32) {
+    (self.ttft_total, self.ttft_samples)
+  }
+
+  pub fn avg_ttft(&self) -> Option<Duration> {
+    (self.ttft_samples > 0).then(|| self.ttft_total / self.ttft_samples)
+  }
+
+  /// 记录一轮由会话模型以外的模型回答的用量
+  pub fn record_routed(&mut self, model: &str, prompt_tokens: usize, completion_tokens: usize) {
+    let index = match self.routed.iter().position(|r| r.model == model) {
+      Some(index) => index,
+      None => {
+        self.routed.push(RoutedUsage {
+          model: model.to_string(),
+          ..Default::default()
+        });
+        self.routed.len() - 1
+      }
+    };
+    let usage = &mut self.routed[index];
+    usage.turns += 1;
+    usage.prompt_tokens += prompt_tokens;
+    usage.completion_tokens += completion_tokens;
+  }
+
+  /// 记录一次复核请求的用量（估算）
+  pub fn record_verification(
+    &mut self,
+    model: &str,
+    prompt_tokens: usize,
+    completion_tokens: usize,
+  ) {
+    self.prompt_tokens += prompt_tokens;
+    self.completion_tokens += completion_tokens;
+    let usage = self.verification.get_or_insert_with(|| RoutedUsage {
+      model: model.to_string(),
+      ..Default::default()
+    });
+    usage.model = model.to_string();
+    usage.turns += 1;
+    usage.prompt_tokens += prompt_tokens;
+    usage.completion_tokens += completion_tokens;
+  }
+
+  /// 路由和复核的用量按各自的模型计价，其余按会话模型
+  pub fn estimated_cost(&self, model: &str) -> Option<f64> {
+    let (mut prompt, mut completion) = (self.prompt_tokens, self.completion_tokens);
+    let mut cost = 0.0;
+    for usage in self.routed.iter().chain(&self.verification) {
+      prompt = prompt.saturating_sub(usage.prompt_tokens);
+      completion = completion.saturating_sub(usage.completion_tokens);
+      cost += price_of(&usage.model, usage.prompt_tokens, usage.completion_tokens)?;
+    }
+    Some(cost + price_of(model, prompt, completion)?)
+  }
+
+  /// 路由的轮次如果都由会话模型回答要多花的钱
+  pub fn routing_savings(&self, model: &str) -> Option<f64> {
+    self.routed.iter().try_fold(0.0, |saved, usage| {
+      let session = price_of(model, usage.prompt_tokens, usage.completion_tokens)?;
+      let routed = price_of(&usage.model, usage.prompt_tokens, usage.completion_tokens)?;
+      Some(saved + session - routed)
+    })
+  }
+
+  pub fn render(&self, model: &str) -> String {
+    let cost = match self.estimated_cost(model) {
+      Some(cost) => format!("¥{:.4}", cost),
+      None => "未知".to_string(),
+    };
+    let ttft = match self.avg_ttft() {
+      Some(d) => format_duration(d),
+      None => "-".to_string(),
+    };
+    [
+      format!("轮次: {}（请求 {} 次）", self.turns, self.requests),
+      format!(
+        "Tokens（估算）: 输入 {} / 输出 {}",
+        format_count(self.prompt_tokens),
+        format_count(self.completion_tokens)
+      ),
+      format!("预估费用: {}", cost),
+      format!(
+        "等待模型: {}，平均首字延迟 {}",
+        format_duration(self.wait_time),
+        ttft
+      ),
+      format!(
+        "自动续写: {} 次，历史摘要: {} 次，消息截断: {} 次",
+        self.auto_continues, self.summarizations, self.truncations
+      ),
+      format!("终端刷新: {} 次", self.flushes),
+    ]
+    .into_iter()
+    .chain((self.warmups > 0).then(|| format!("预热请求: {} 次", self.warmups)))
+    .chain(self.render_routing(model))
+    .chain(self.verification.as_ref().map(render_verification))
+    .collect::<Vec<_>>()
+    .join("\n")
+  }
+
+  fn render_routing(&self, model: &str) -> Option<String> {
+    if self.routed.is_empty() {
+      return None;
+    }
+    let models: Vec<_> = self
+      .routed
+      .iter()
+      .map(|r| format!("{} {} 轮", r.model, r.turns))
+      .collect();
+    let saved = match self.routing_savings(model) {
+      Some(saved) => format!("，节省约 ¥{:.4}", saved),
+      None => String::new(),
+    };
+    Some(format!("自动路由: {}{}", models.join("、"), saved))
+  }
+
+  /// 窄终端下的单行形式
+  pub fn render_compact(&self, model: &str) -> String {
+    let cost = match self.estimated_cost(model) {
+      Some(cost) => format!("¥{:.4}", cost),
+      None => "¥?".to_string(),
+    };
+    format!(
+      "轮次 {} · 输入 {} / 输出 {} · {}",
+      self.turns,
+      format_count(self.prompt_tokens),
+      format_count(self.completion_tokens),
+      cost
+    )
+  }
+}
+
+fn render_verification(usage: &RoutedUsage) -> String {
+  let cost = match price_of(&usage.model, usage.prompt_tokens, usage.completion_tokens) {
+    Some(cost) => format!("，约 ¥{:.4}", cost),
+    None => String::new(),
+  };
+  format!("答案复核: {} 次（{}）{}", usage.turns, usage.model, cost)
+}
+
+fn price_of(model: &str, prompt_tokens: usize, completion_tokens: usize) -> Option<f64> {
+  let price = models::registry().pricing(model)?;
+  Some(
+    prompt_tokens as f64 / 1e6 * price.input_per_million
+      + completion_tokens as f64 / 1e6 * price.output_per_million,
+  )
+}
+
+pub fn format_count(n: usize) -> String {
+  if n >= 1000 {
+    format!("{:.1}K", n as f64 / 1000.0)
+  } else {
+    n.to_string()
+  }
+}
+
+pub fn format_duration(d: Duration) -> String {
+  let secs = d.as_secs_f64();
+  if secs >= 60.0 {
+    format!("{}m{:02}s", d.as_secs() / 60, d.as_secs() % 60)
+  } else {
+    format!("{:.1}s", secs)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_render_usage() {
+    let mut stats = SessionStats::default();
+    stats.record_request(1200, 300, None, Duration::from_secs(1));
+    assert_eq!(
+      stats.render_usage(),
+      "上一轮: 服务方没有报告\n本次会话: 服务方没有报告（估算: 输入 1200 / 输出 300）"
+    );
+    let usage = Usage {
+      prompt_tokens: 1180,
+      completion_tokens: 310,
+      total_tokens: 1490,
+    };
+    stats.record_usage(usage);
+    stats.start_exchange();
+    stats.record_usage(usage);
+    assert_eq!(
+      stats.render_usage(),
+      "上一轮: 输入 1180 / 输出 310 / 合计 1490\n本次会话: 输入 2360 / 输出 620 / 合计 2980"
+    );
+  }
+
+  #[test]
+  fn test_record_request() {
+    let mut stats = SessionStats::default();
+    stats.record_request(
+      100,
+      50,
+      Some(Duration::from_secs(2)),
+      Duration::from_secs(5),
+    );
+    stats.record_request(200, 0, None, Duration::from_secs(1));
+    stats.record_request(
+      300,
+      70,
+      Some(Duration::from_secs(4)),
+      Duration::from_secs(6),
+    );
+    assert_eq!(stats.requests, 3);
+    assert_eq!(stats.prompt_tokens, 600);
+    assert_eq!(stats.completion_tokens, 120);
+    assert_eq!(stats.wait_time, Duration::from_secs(12));
+    // 没有首字的请求不计入平均值
+    assert_eq!(stats.avg_ttft(), Some(Duration::from_secs(3)));
+    assert_eq!(stats.first_ttft, Some(Duration::from_secs(2)));
+    assert_eq!(SessionStats::default().avg_ttft(), None);
+  }
+
+  #[test]
+  fn test_estimated_cost() {
+    let stats = SessionStats {
+      prompt_tokens: 1_000_000,
+      completion_tokens: 500_000,
+      ..Default::default()
+    };
+    assert_eq!(stats.estimated_cost("deepseek-r1"), Some(4.0 + 8.0));
+    assert_eq!(stats.estimated_cost("deepseek-chat"), Some(2.0 + 4.0));
+    assert_eq!(stats.estimated_cost("unknown-model"), None);
+  }
+
+  #[test]
+  fn test_routed_turns_are_priced_by_their_model() {
+    let mut stats = SessionStats {
+      prompt_tokens: 1_000_000,
+      completion_tokens: 500_000,
+      ..Default::default()
+    };
+    // 一半的用量由 deepseek-chat 回答
+    stats.record_routed("deepseek-chat", 300_000, 100_000);
+    stats.record_routed("deepseek-chat", 200_000, 150_000);
+    assert_eq!(stats.routed.len(), 1);
+    assert_eq!(stats.routed[0].turns, 2);
+    let cost = stats.estimated_cost("deepseek-r1").unwrap();
+    assert!((cost - (6.0 + 3.0)).abs() < 1e-9, "{}", cost);
+    let saved = stats.routing_savings("deepseek-r1").unwrap();
+    assert!((saved - 3.0).abs() < 1e-9, "{}", saved);
+    assert!(
+      stats
+        .render("deepseek-r1")
+        .contains("自动路由: deepseek-chat 2 轮，节省约 ¥3.0000")
+    );
+    assert!(
+      !SessionStats::default()
+        .render("deepseek-r1")
+        .contains("自动路由")
+    );
+  }
+
+  #[test]
+  fn test_render() {
+    let mut stats = SessionStats {
+      turns: 2,
+      auto_continues: 1,
+      summarizations: 1,
+      truncations: 1,
+      flushes: 12,
+      ..Default::default()
+    };
+    stats.record_request(
+      2100,
+      800,
+      Some(Duration::from_millis(1500)),
+      Duration::from_secs(75),
+    );
+    let text = stats.render("deepseek-chat");
+    assert!(text.contains("轮次: 2（请求 1 次）"));
+    assert!(text.contains("输入 2.1K / 输出 800"));
+    assert!(text.contains("等待模型: 1m15s，平均首字延迟 1.5s"));
+    assert!(text.contains("自动续写: 1 次，历史摘要: 1 次，消息截断: 1 次"));
+    assert!(text.contains("终端刷新: 12 次"));
+    assert!(!text.contains("预热请求"));
+    assert!(text.contains("¥"));
+    assert!(stats.render("unknown").contains("预估费用: 未知"));
+    assert_eq!(
+      stats.render_compact("unknown"),
+      "轮次 2 · 输入 2.1K / 输出 800 · ¥?"
+    );
+    stats.record_warmup(5, 1);
+    assert_eq!((stats.requests, stats.prompt_tokens), (1, 2105));
+    assert!(stats.render("deepseek-chat").contains("预热请求: 1 次"));
+  }
+}
@@ -0,0 +1,201 @@
+use crate::api::{Content, Message, TextContent};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// 纠偏说明追加到本轮问题时的前缀
+pub const NOTE_PREFIX: &str = "[补充]";
+
+/// 流式输出期间的软中断。接管 Ctrl-C：输出中按下时中断生成，逐行读入问题时交给读入的一方，
+/// 其他时候照常退出
+#[derive(Debug, Default)]
+pub struct Interrupt {
+  streaming: AtomicBool,
+  requested: AtomicBool,
+  notify: Notify,
+  prompting: AtomicBool,
+  quit: AtomicBool,
+  prompt_notify: Notify,
+}
+
+impl Interrupt {
+  /// 在后台监听 Ctrl-C
+  pub fn listen() -> Arc<Self> {
+    let interrupt = Arc::new(Self::default());
+    let listener = interrupt.clone();
+    tokio::spawn(async move {
+      while tokio::signal::ctrl_c().await.is_ok() {
+        if !listener.trigger() && !listener.trigger_quit() {
+          crate::terminal::restore();
+          std::process::exit(130);
+        }
+      }
+    });
+    interrupt
+  }
+
+  /// 正在输出时请求中断并返回 true；否则不做任何事
+  pub fn trigger(&self) -> bool {
+    if !self.streaming.load(Ordering::SeqCst) {
+      return false;
+    }
+    self.requested.store(true, Ordering::SeqCst);
+    self.notify.notify_waiters();
+    true
+  }
+
+  pub fn set_streaming(&self, streaming: bool) {
+    self.streaming.store(streaming, Ordering::SeqCst);
+    if !streaming {
+      self.requested.store(false, Ordering::SeqCst);
+    }
+  }
+
+  /// 正在等待输入问题时记下退出请求并返回 true；否则不做任何事
+  pub fn trigger_quit(&self) -> bool {
+    if !self.prompting.load(Ordering::SeqCst) {
+      return false;
+    }
+    self.quit.store(true, Ordering::SeqCst);
+    self.prompt_notify.notify_waiters();
+    true
+  }
+
+  pub fn set_prompting(&self, prompting: bool) {
+    self.prompting.store(prompting, Ordering::SeqCst);
+    if !prompting {
+      self.quit.store(false, Ordering::SeqCst);
+    }
+  }
+
+  /// 等到等待输入时的下一次 Ctrl-C
+  pub async fn wait_quit(&self) {
+    loop {
+      let notified = self.prompt_notify.notified();
+      if self.quit.swap(false, Ordering::SeqCst) {
+        return;
+      }
+      notified.await;
+      if self.quit.swap(false, Ordering::SeqCst) {
+        return;
+      }
+    }
+  }
+
+  /// 取走已有的中断请求
+  pub fn take(&self) -> bool {
+    self.requested.swap(false, Ordering::SeqCst)
+  }
+
+  /// 等到下一次中断请求
+  pub async fn wait(&self) {
+    loop {
+      let notified = self.notify.notified();
+      if self.take() {
+        return;
+      }
+      notified.await;
+      if self.take() {
+        return;
+      }
+    }
+  }
+}
+
+/// 本轮的纠偏设置：中断来源，以及向用户询问纠偏说明的方式（None 或空表示不纠偏、结束本轮）
+#[derive(Clone, Copy)]
+pub struct Steer<'a> {
+  pub interrupt: &'a Interrupt,
+  pub ask: &'a dyn Fn() -> Option<String>,
+}
+
+/// 纠偏请求的末尾：被中断的部分回答作为 assistant 消息，纠偏说明作为新的 user 消息
+pub fn follow_up(partial: &str, note: &str) -> [Message; 2] {
+  [
+    Message::Simple {
+      role: "assistant".to_string(),
+      content: partial.to_string(),
+      name: None,
+    },
+    Message::Simple {
+      role: "user".to_string(),
+      content: note.to_string(),
+      name: None,
+    },
+  ]
+}
+
+/// 纠偏后的回答完成时，把说明并入本轮最后一条用户消息，
+/// 历史中不出现部分回答，也不会有连续两条用户消息
+pub fn merge_note(history: &mut [Message], note: &str) {
+  let addition = format!("\n\n{} {}", NOTE_PREFIX, note);
+  let last_user = history.iter_mut().rev().find(
+    |m| matches!(m, Message::Simple { role, .. } | Message::MultiModal { role, .. } if role == "user"),
+  );
+  match last_user {
+    Some(Message::Simple { content, .. } | Message::Tool { content, .. }) => {
+      content.push_str(&addition)
+    }
+    Some(Message::MultiModal { content, .. }) => content.push(Content::Text(TextContent {
+      content_type: "text".to_string(),
+      text: addition.trim_start().to_string(),
+    })),
+    None => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_trigger_only_while_streaming() {
+    let interrupt = Interrupt::default();
+    assert!(!interrupt.trigger());
+    assert!(!interrupt.take());
+    interrupt.set_streaming(true);
+    assert!(interrupt.trigger());
+    // 请求在等待开始前到达也不会丢
+    interrupt.wait().await;
+    assert!(!interrupt.take());
+    assert!(interrupt.trigger());
+    interrupt.set_streaming(false);
+    assert!(!interrupt.take());
+  }
+
+  #[tokio::test]
+  async fn test_quit_only_while_prompting() {
+    let interrupt = Interrupt::default();
+    assert!(!interrupt.trigger_quit());
+    interrupt.set_prompting(true);
+    assert!(!interrupt.trigger());
+    assert!(interrupt.trigger_quit());
+    interrupt.wait_quit().await;
+    // 读入结束后遗留的请求不会带到下一次
+    assert!(interrupt.trigger_quit());
+    interrupt.set_prompting(false);
+    interrupt.set_prompting(true);
+    assert!(!interrupt.quit.load(Ordering::SeqCst));
+  }
+
+  #[test]
+  fn test_merge_note() {
+    let mut history = vec![
+      Message::Simple {
+        role: "user".to_string(),
+        content: "讲讲所有权".to_string(),
+        name: None,
+      },
+      Message::Simple {
+        role: "assistant".to_string(),
+        content: "…".to_string(),
+        name: None,
+      },
+    ];
+    merge_note(&mut history, "只讲借用");
+    let Message::Simple { content, .. } = &history[0] else {
+      panic!("expected a simple message");
+    };
+    assert_eq!(content, "讲讲所有权\n\n[补充] 只讲借用");
+  }
+}
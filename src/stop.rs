@@ -0,0 +1,254 @@
+//! `--stop-regex`：回答一旦匹配其中任何一个模式就在本地停止接收，并去掉匹配的部分。
+//! 流式匹配只保留还没有交出的末尾：用 DFA 判断哪些位置不可能再开始一个匹配，
+//! 这些位置之前的内容立即交出，可能成为匹配开头的部分先留着，所以被去掉的内容不会显示出来
+
+use regex::{Regex, RegexBuilder};
+use regex_automata::Anchored;
+use regex_automata::hybrid::dfa::{Cache, DFA};
+use regex_automata::util::{start, syntax};
+
+/// 最多留着这么多字节等待匹配；比这更长的匹配在流式输出中可能识别不到
+pub const WINDOW: usize = 1024;
+
+/// 检查 `--stop-regex` 的值；`^` 和 `$` 按行匹配
+pub fn validate(pattern: &str) -> Result<String, String> {
+  compile(pattern).map(|_| pattern.to_string())
+}
+
+fn compile(pattern: &str) -> Result<Regex, String> {
+  RegexBuilder::new(pattern)
+    .multi_line(true)
+    .build()
+    .map_err(|e| format!("Invalid pattern '{}': {}", pattern, e))
+}
+
+/// 编译好的一组停止模式
+#[derive(Debug, Clone)]
+pub struct StopPatterns {
+  regexes: Vec<Regex>,
+  /// 所有模式的锚定 DFA，用来判断某个位置是否还可能开始一个匹配
+  dfa: DFA,
+}
+
+impl StopPatterns {
+  pub fn new(patterns: &[String]) -> Result<Self, String> {
+    let regexes = patterns
+      .iter()
+      .map(|p| compile(p))
+      .collect::<Result<Vec<_>, _>>()?;
+    let dfa = DFA::builder()
+      .syntax(syntax::Config::new().multi_line(true))
+      .configure(DFA::config().unicode_word_boundary(true))
+      .build_many(patterns)
+      .map_err(|e| format!("Invalid stop patterns: {}", e))?;
+    Ok(Self { regexes, dfa })
+  }
+
+  /// `text` 中从 `start` 开始最早的匹配位置；`start` 之前的内容只用于判断 `^` 和 `\b`
+  pub fn find_at(&self, text: &str, start: usize) -> Option<usize> {
+    self
+      .regexes
+      .iter()
+      .filter_map(|r| r.find_at(text, start))
+      .map(|m| m.start())
+      .min()
+  }
+
+  /// 完整回答应保留的部分：第一个匹配之前的内容
+  pub fn cut<'t>(&self, text: &'t str) -> Option<&'t str> {
+    self.find_at(text, 0).map(|start| &text[..start])
+  }
+}
+
+/// 跨分片的流式匹配
+pub struct StopMatcher<'a> {
+  patterns: &'a StopPatterns,
+  cache: Cache,
+  /// 已交出内容的最后一个字符（供 `^` 和 `\b` 判断）加上还没有交出的内容
+  tail: String,
+  /// tail 中上下文字符的长度
+  context: usize,
+  window: usize,
+  stopped: bool,
+}
+
+impl<'a> StopMatcher<'a> {
+  pub fn new(patterns: &'a StopPatterns) -> Self {
+    Self::with_window(patterns, WINDOW)
+  }
+
+  fn with_window(patterns: &'a StopPatterns, window: usize) -> Self {
+    Self {
+      cache: patterns.dfa.create_cache(),
+      patterns,
+      tail: String::new(),
+      context: 0,
+      window,
+      stopped: false,
+    }
+  }
+
+  /// 从 `at` 开始是否不可能再有匹配，不管之后还会收到什么。缓存用尽等无法判断的情况按可能处理
+  fn is_dead_from(&mut self, at: usize) -> bool {
+    let dfa = &self.patterns.dfa;
+    let config = start::Config::new()
+      .anchored(Anchored::Yes)
+      .look_behind(self.tail.as_bytes()[..at].last().copied());
+    let Ok(mut state) = dfa.start_state(&mut self.cache, &config) else {
+      return false;
+    };
+    for &byte in &self.tail.as_bytes()[at..] {
+      match dfa.next_state(&mut self.cache, state, byte) {
+        Ok(next) if next.is_dead() => return true,
+        Ok(next) if !next.is_quit() => state = next,
+        _ => return false,
+      }
+    }
+    false
+  }
+
+  /// 计入一个分片，返回可以显示的内容和是否已经匹配。匹配后不再交出任何内容
+  pub fn push(&mut self, chunk: &str) -> (String, bool) {
+    if self.stopped {
+      return (String::new(), true);
+    }
+    self.tail.push_str(chunk);
+    if let Some(start) = self.patterns.find_at(&self.tail, self.context) {
+      self.stopped = true;
+      let kept = self.tail[self.context..start].to_string();
+      self.tail.clear();
+      return (kept, true);
+    }
+    // 跳过不可能开始匹配的位置；超出窗口的部分不再等待
+    let mut at = self.context;
+    while let Some(c) = self.tail[at..].chars().next()
+      && (self.tail.len() - at > self.window || self.is_dead_from(at))
+    {
+      at += c.len_utf8();
+    }
+    let ready = self.tail[self.context..at].to_string();
+    if let Some(last) = ready.chars().next_back() {
+      let keep_from = at - last.len_utf8();
+      self.tail.drain(..keep_from);
+      self.context = last.len_utf8();
+    }
+    (ready, false)
+  }
+
+  /// 回答结束时交出留着的内容
+  pub fn finish(&mut self) -> String {
+    let rest = match self.stopped {
+      true => String::new(),
+      false => self.tail[self.context..].to_string(),
+    };
+    self.tail.clear();
+    self.context = 0;
+    rest
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn patterns(patterns: &[&str]) -> StopPatterns {
+    StopPatterns::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+  }
+
+  /// 每收到一个分片就重新扫描整个回答
+  fn oracle(patterns: &StopPatterns, chunks: &[&str]) -> (String, bool) {
+    let mut text = String::new();
+    for chunk in chunks {
+      text.push_str(chunk);
+      if let Some(kept) = patterns.cut(&text) {
+        return (kept.to_string(), true);
+      }
+    }
+    (text, false)
+  }
+
+  /// 返回交出的全部内容、是否匹配，以及每一步交出后的累计内容
+  fn stream(matcher: &mut StopMatcher, chunks: &[&str]) -> (String, bool, Vec<String>) {
+    let mut shown = String::new();
+    let mut steps = vec![];
+    for chunk in chunks {
+      let (ready, stopped) = matcher.push(chunk);
+      shown.push_str(&ready);
+      steps.push(shown.clone());
+      if stopped {
+        return (shown, true, steps);
+      }
+    }
+    shown.push_str(&matcher.finish());
+    (shown, false, steps)
+  }
+
+  #[test]
+  fn test_stops_at_pattern_across_chunks() {
+    let stop = patterns(&["^## References"]);
+    let chunks = ["答案见上。\n\n## Ref", "eren", "ces\n- [1] 某论文", "\n"];
+    let mut matcher = StopMatcher::new(&stop);
+    let (shown, stopped, steps) = stream(&mut matcher, &chunks);
+    assert_eq!((shown.as_str(), stopped), ("答案见上。\n\n", true));
+    // 可能是匹配开头的 "## Ref" 没有先显示出来
+    assert_eq!(steps[0], "答案见上。\n\n");
+    assert_eq!(matcher.push("more"), (String::new(), true));
+
+    // 不在行首时不匹配，不可能匹配的内容立即显示
+    let mut matcher = StopMatcher::new(&stop);
+    let (shown, stopped, steps) = stream(&mut matcher, &["see ## References", " below"]);
+    assert_eq!(
+      (shown.as_str(), stopped),
+      ("see ## References below", false)
+    );
+    assert_eq!(steps[0], "see ## References");
+    assert_eq!(stop.cut("x\n## References"), Some("x\n"));
+    assert!(validate("(unclosed").is_err());
+  }
+
+  #[test]
+  fn test_incremental_matching_agrees_with_full_rescan() {
+    let stop = patterns(&[
+      "^#{1,3} ?b",
+      r"ab\b",
+      "a\nb",
+      "参{2}",
+      "b{3}$",
+      r"\Ba ",
+      "(?i)BA#",
+    ]);
+    let alphabet = ['a', 'b', ' ', '\n', '#', '参', 'x', 'A'];
+    // xorshift，结果可以复现
+    let mut seed: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = |n: usize| {
+      seed ^= seed << 13;
+      seed ^= seed >> 7;
+      seed ^= seed << 17;
+      (seed % n as u64) as usize
+    };
+    let mut stopped_cases = 0;
+    for case in 0..3000 {
+      let len = next(60);
+      let text: String = (0..len).map(|_| alphabet[next(alphabet.len())]).collect();
+      // 在随机的字符边界处切开，包括空分片
+      let mut cuts: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+      cuts.push(text.len());
+      let mut bounds: Vec<usize> = (0..next(8)).map(|_| cuts[next(cuts.len())]).collect();
+      bounds.extend([0, text.len()]);
+      bounds.sort();
+      let chunks: Vec<&str> = bounds.windows(2).map(|w| &text[w[0]..w[1]]).collect();
+
+      let expected = oracle(&stop, &chunks);
+      let mut matcher = StopMatcher::with_window(&stop, 16);
+      let (shown, stopped, steps) = stream(&mut matcher, &chunks);
+      assert_eq!((shown, stopped), expected, "case {}: {:?}", case, chunks);
+      // 每一步显示的都是最终结果的开头，被去掉的内容从未显示
+      for step in steps {
+        assert!(expected.0.starts_with(&step), "case {}: {:?}", case, chunks);
+      }
+      stopped_cases += expected.1 as usize;
+    }
+    // 两种结果都覆盖到
+    assert!((300..2700).contains(&stopped_cases), "{}", stopped_cases);
+  }
+}
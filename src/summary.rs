@@ -0,0 +1,818 @@
+use crate::api::{ChatBackend, Message};
+use crate::history::turn_starts;
+use crate::truncate::{self, TruncateMode};
+use crate::{estimate_messages_tokens, tokens};
+use anyhow::Result;
+use futures_util::{StreamExt, stream};
+use regex::Regex;
+use std::ops::Range;
+use std::sync::{Arc, LazyLock};
+use tokio::task::JoinHandle;
+
+/// 上下文超过预算的这个比例后，在后台开始摘要最早的对话
+pub const START_PERCENT: usize = 50;
+/// 超过这个比例时，用已完成的摘要替换对应的历史
+pub const APPLY_PERCENT: usize = 75;
+/// 最近的这几条消息始终原样保留
+pub const KEEP_RECENT: usize = 4;
+pub const SUMMARY_MAX_TOKENS: u32 = 2048;
+pub const SUMMARY_PREFIX: &str = "[历史摘要]";
+/// 摘要后面原样保留的代码块和错误信息的标题
+pub const APPENDIX_HEADING: &str = "保留的代码片段：";
+/// 原样保留的片段最多占这么多 tokens，超出时先丢弃最早的
+pub const APPENDIX_MAX_TOKENS: usize = 2048;
+
+/// 分段摘要时同时进行的请求数
+pub const CHUNK_CONCURRENCY: usize = 4;
+/// 某一段摘要失败时，把这一段原文截断到这么多 tokens 代替它的摘要
+const FALLBACK_TOKENS: usize = SUMMARY_MAX_TOKENS as usize;
+const SUMMARY_INSTRUCTION: &str =
+  "请用中文总结以下对话内容，保留关键信息和 [代码片段 N] 形式的引用，便于后续继续对话：";
+const MERGE_INSTRUCTION: &str = "以下是同一段对话按时间顺序分段写成的摘要，请用中文合并为一份摘要，保留关键信息和 [代码片段 N] 形式的引用，便于后续继续对话：";
+
+/// 摘要输入的对话文本，只包含用户和助手的消息
+fn transcript(messages: &[Message]) -> String {
+  messages
+    .iter()
+    .filter_map(|m| match m {
+      Message::Simple { role, content, .. } if role == "user" || role == "assistant" => {
+        Some(format!("{}: {}", m.speaker(), content))
+      }
+      _ => None,
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// 像错误信息的一行：编译器和运行时的错误、panic、异常和 Traceback
+static ERROR_LINE: LazyLock<Regex> = LazyLock::new(|| {
+  Regex::new(
+    r"^\s*(?i:error(?:\[\w+\])?:|fatal:|panic:|caused by:|traceback \(most recent call last\)|exception in thread)|thread '[^']*' panicked|^\s*(?:\w+\.)*\w+(?:Error|Exception):",
+  )
+  .unwrap()
+});
+
+/// 摘要时原样保留的一段原文，正文中用 `[代码片段 N]` 代替
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+  pub id: usize,
+  pub text: String,
+}
+
+fn placeholder(id: usize) -> String {
+  format!("[代码片段 {}]", id)
+}
+
+/// 行首的 ``` 或 ~~~（至少三个）
+fn fence_of(line: &str) -> Option<&str> {
+  let line = line.trim_start();
+  let first = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+  let run = &line[..line.find(|c| c != first).unwrap_or(line.len())];
+  (run.len() >= 3).then_some(run)
+}
+
+/// 收集原文片段；同样的内容再次出现时沿用原来的编号，并算作最新的一段
+#[derive(Default)]
+struct Collector {
+  snippets: Vec<Snippet>,
+  next_id: usize,
+}
+
+impl Collector {
+  fn add(&mut self, text: String) -> String {
+    let id = match self.snippets.iter().position(|s| s.text == text) {
+      Some(i) => self.snippets.remove(i).id,
+      None => {
+        self.next_id += 1;
+        self.next_id
+      }
+    };
+    self.snippets.push(Snippet { id, text });
+    placeholder(id)
+  }
+
+  /// 上一次摘要保留的片段，编号不变
+  fn keep(&mut self, snippet: Snippet) {
+    self.next_id = self.next_id.max(snippet.id);
+    self.snippets.retain(|s| s.text != snippet.text);
+    self.snippets.push(snippet);
+  }
+
+  /// 把代码块和连续的错误行换成占位，返回剩下的正文
+  fn split(&mut self, text: &str) -> String {
+    let mut prose: Vec<String> = vec![];
+    let mut lines = text.split('\n').peekable();
+    while let Some(line) = lines.next() {
+      if let Some(fence) = fence_of(line) {
+        let mut block = vec![line];
+        for next in lines.by_ref() {
+          block.push(next);
+          if fence_of(next).is_some_and(|f| f.starts_with(fence))
+            && next.trim_start()[fence.len()..].trim().is_empty()
+          {
+            break;
+          }
+        }
+        prose.push(self.add(block.join("\n")));
+        continue;
+      }
+      if ERROR_LINE.is_match(line) {
+        let mut run = vec![line.trim_end()];
+        while let Some(next) = lines.next_if(|l| ERROR_LINE.is_match(l)) {
+          run.push(next.trim_end());
+        }
+        prose.push(self.add(run.join("\n")));
+        continue;
+      }
+      prose.push(line.to_string());
+    }
+    prose.join("\n")
+  }
+
+  /// 从最新的一段往前保留，放不下时丢弃更早的；单独一段就超出上限的直接跳过
+  fn finish(self, budget: usize) -> Vec<Snippet> {
+    let mut kept = vec![];
+    let mut used = 0;
+    for snippet in self.snippets.into_iter().rev() {
+      let tokens = tokens::count(&snippet.text);
+      if tokens > budget {
+        continue;
+      }
+      if used + tokens > budget {
+        break;
+      }
+      used += tokens;
+      kept.push(snippet);
+    }
+    kept.reverse();
+    kept
+  }
+}
+
+/// 已有摘要中保留的片段：标题之后每段以单独一行的 `[代码片段 N]` 开始，代码块里的同样一行不算
+fn parse_appendix(appendix: &str) -> Vec<Snippet> {
+  let header = |line: &str| {
+    line
+      .strip_prefix("[代码片段 ")?
+      .strip_suffix(']')?
+      .parse::<usize>()
+      .ok()
+  };
+  let mut snippets: Vec<Snippet> = vec![];
+  let mut fence: Option<String> = None;
+  for line in appendix.split('\n') {
+    match &fence {
+      Some(open) => {
+        if fence_of(line).is_some_and(|f| f.starts_with(open.as_str())) {
+          fence = None;
+        }
+      }
+      None => {
+        if let Some(id) = header(line) {
+          snippets.push(Snippet {
+            id,
+            text: String::new(),
+          });
+          continue;
+        }
+        fence = fence_of(line).map(str::to_string);
+      }
+    }
+    if let Some(last) = snippets.last_mut()
+      && (!last.text.is_empty() || !line.is_empty())
+    {
+      last.text.push_str(line);
+      last.text.push('\n');
+    }
+  }
+  // 片段之间的空行不属于片段
+  for snippet in &mut snippets {
+    let trimmed = snippet.text.trim_end_matches('\n').len();
+    snippet.text.truncate(trimmed);
+  }
+  snippets
+}
+
+/// 摘要前把历史中的代码块和错误信息换成占位，返回只剩正文的历史和要原样保留的片段。
+/// 已有摘要附带的片段排在最前面，总量不超过 APPENDIX_MAX_TOKENS
+pub fn split_verbatim(history: &[Message]) -> (Vec<Message>, Vec<Snippet>) {
+  let mut collector = Collector::default();
+  let prose = history
+    .iter()
+    .map(|m| match m {
+      Message::Simple {
+        role,
+        content,
+        name,
+      } => {
+        let content = match content.split_once(&format!("\n\n{}\n", APPENDIX_HEADING)) {
+          Some((summary, appendix)) if content.starts_with(SUMMARY_PREFIX) => {
+            for snippet in parse_appendix(appendix) {
+              collector.keep(snippet);
+            }
+            summary.to_string()
+          }
+          _ => collector.split(content),
+        };
+        Message::Simple {
+          role: role.clone(),
+          content,
+          name: name.clone(),
+        }
+      }
+      other => other.clone(),
+    })
+    .collect();
+  (prose, collector.finish(APPENDIX_MAX_TOKENS))
+}
+
+/// 替换历史的摘要消息：正文摘要，后面跟着原样保留的片段
+pub fn assemble(summary: &str, snippets: &[Snippet]) -> String {
+  let mut content = format!("{} {}", SUMMARY_PREFIX, summary);
+  if !snippets.is_empty() {
+    let appendix: Vec<String> = snippets
+      .iter()
+      .map(|s| format!("{}\n{}", placeholder(s.id), s.text))
+      .collect();
+    content.push_str(&format!(
+      "\n\n{}\n{}",
+      APPENDIX_HEADING,
+      appendix.join("\n\n")
+    ));
+  }
+  content
+}
+
+/// 用摘要替换整段历史。摘要失败（None）或为空时历史保持不变，返回 false
+pub fn replace_history(
+  history: &mut Vec<Message>,
+  summary: Option<&str>,
+  snippets: &[Snippet],
+) -> bool {
+  let Some(summary) = summary.filter(|s| !s.trim().is_empty()) else {
+    return false;
+  };
+  *history = vec![Message::Simple {
+    role: "user".to_string(),
+    content: assemble(summary, snippets),
+    name: None,
+  }];
+  true
+}
+
+fn summary_request(instruction: &str, text: &str) -> Vec<Message> {
+  vec![
+    Message::Simple {
+      role: "system".to_string(),
+      content: "你是一个对话历史摘要助手。".to_string(),
+      name: None,
+    },
+    Message::Simple {
+      role: "user".to_string(),
+      content: format!("{}\n{}", instruction, text),
+      name: None,
+    },
+  ]
+}
+
+/// 请模型摘要 `messages` 的请求，应急摘要与后台摘要共用
+pub fn summary_messages(messages: &[Message]) -> Vec<Message> {
+  summary_request(SUMMARY_INSTRUCTION, &transcript(messages))
+}
+
+/// 请求中除对话文本以外的部分
+fn request_overhead(instruction: &str) -> usize {
+  estimate_messages_tokens(&summary_request(instruction, ""))
+}
+
+/// 把 `text` 截断到放进摘要请求后不超过 `budget`
+fn fit(instruction: &str, text: &str, budget: usize) -> String {
+  let room = budget.saturating_sub(request_overhead(instruction)).max(1);
+  truncate::truncate(text, room, TruncateMode::Head)
+}
+
+/// 按轮次把历史切成若干段，每段的摘要请求不超过 `budget`；
+/// 一轮不会被拆开，单独一轮就超出预算时自成一段，请求前再截断
+pub fn chunk_turns(history: &[Message], budget: usize) -> Vec<Range<usize>> {
+  let mut starts = turn_starts(history);
+  // 第一轮之前的消息（导入的 system 消息、已有的摘要）并入第一段
+  if !history.is_empty() && starts.first() != Some(&0) {
+    starts.insert(0, 0);
+  }
+  let room = budget.saturating_sub(request_overhead(SUMMARY_INSTRUCTION));
+  let mut chunks: Vec<Range<usize>> = vec![];
+  let mut used = 0;
+  for (i, &start) in starts.iter().enumerate() {
+    let end = starts.get(i + 1).copied().unwrap_or(history.len());
+    // 各轮分开估算之和不小于整段的估算
+    let tokens = tokens::count(&transcript(&history[start..end]));
+    match chunks.last_mut() {
+      Some(chunk) if used + tokens <= room => {
+        chunk.end = end;
+        used += tokens;
+      }
+      _ => {
+        chunks.push(start..end);
+        used = tokens;
+      }
+    }
+  }
+  chunks
+}
+
+/// 摘要整段历史。放得进一次请求时直接摘要；否则按轮次分段、并发摘要每一段，
+/// 再把各段摘要合并为最终摘要。某一段失败时用截断的原文代替，不影响其他段；
+/// 所有段都失败时返回第一段的错误
+pub async fn summarize_history<B: ChatBackend>(
+  backend: &B,
+  model: &str,
+  history: &[Message],
+  budget: usize,
+) -> Result<String> {
+  let chunks = chunk_turns(history, budget);
+  if chunks.len() <= 1 {
+    let text = fit(SUMMARY_INSTRUCTION, &transcript(history), budget);
+    let request = summary_request(SUMMARY_INSTRUCTION, &text);
+    return backend
+      .complete(model, request, None, Some(SUMMARY_MAX_TOKENS))
+      .await;
+  }
+  let mut partials: Vec<Result<String, (String, anyhow::Error)>> =
+    stream::iter(chunks.into_iter().map(|range| {
+      let text = transcript(&history[range]);
+      async move {
+        let request = summary_request(
+          SUMMARY_INSTRUCTION,
+          &fit(SUMMARY_INSTRUCTION, &text, budget),
+        );
+        let fallback = || truncate::truncate(&text, FALLBACK_TOKENS, TruncateMode::Head);
+        match backend
+          .complete(model, request, None, Some(SUMMARY_MAX_TOKENS))
+          .await
+        {
+          Ok(summary) if !summary.trim().is_empty() => Ok(summary.trim().to_string()),
+          Ok(_) => Err((fallback(), anyhow::anyhow!("empty summary"))),
+          Err(e) => Err((fallback(), e)),
+        }
+      }
+    }))
+    .buffered(CHUNK_CONCURRENCY)
+    .collect()
+    .await;
+  if partials.iter().all(Result::is_err)
+    && let Some(Err((_, e))) = partials.drain(..).next()
+  {
+    return Err(e.context("every chunk of the summary failed"));
+  }
+  let combined = partials
+    .iter()
+    .map(|partial| match partial {
+      Ok(summary) | Err((summary, _)) => summary,
+    })
+    .enumerate()
+    .map(|(i, partial)| format!("第 {} 段：{}", i + 1, partial))
+    .collect::<Vec<_>>()
+    .join("\n\n");
+  let request = summary_request(
+    MERGE_INSTRUCTION,
+    &fit(MERGE_INSTRUCTION, &combined, budget),
+  );
+  backend
+    .complete(model, request, None, Some(SUMMARY_MAX_TOKENS))
+    .await
+}
+
+fn is_user(message: &Message) -> bool {
+  matches!(message, Message::Simple { role, .. } | Message::MultiModal { role, .. } if role == "user")
+}
+
+/// 可以摘要的前缀长度：保留最近 KEEP_RECENT 条，并在用户消息处切分，不拆开一轮对话
+pub fn split_point(history: &[Message]) -> usize {
+  let limit = history.len().saturating_sub(KEEP_RECENT);
+  (1..=limit)
+    .rev()
+    .find(|&k| history.get(k).is_some_and(is_user))
+    .unwrap_or(0)
+}
+
+struct Pending {
+  /// 摘要覆盖 history[..upto]
+  upto: usize,
+  /// 开始时的历史前缀，应用前用来确认历史没有被清空或改写
+  prefix: Vec<String>,
+  /// 摘要之外原样保留的片段
+  snippets: Vec<Snippet>,
+  handle: JoinHandle<Result<String>>,
+}
+
+fn fingerprint(messages: &[Message]) -> Vec<String> {
+  messages
+    .iter()
+    .map(|m| serde_json::to_string(m).unwrap_or_default())
+    .collect()
+}
+
+/// 增量维护的滚动摘要：后台静默生成，预算吃紧时替换最早的对话，
+/// 避免等到溢出时才做一次长时间的完整摘要
+#[derive(Default)]
+pub struct RollingSummary {
+  pending: Option<Pending>,
+}
+
+impl RollingSummary {
+  /// 一轮对话结束后调用；超过 START_PERCENT 且没有进行中的摘要时在后台开始，返回是否开始
+  pub fn maybe_start<B>(
+    &mut self,
+    backend: &Arc<B>,
+    model: &str,
+    history: &[Message],
+    used: usize,
+    budget: usize,
+  ) -> bool
+  where
+    B: ChatBackend + Send + Sync + 'static,
+  {
+    if self.pending.is_some() || used * 100 <= budget * START_PERCENT {
+      return false;
+    }
+    let upto = split_point(history);
+    // 只有一条已有的摘要时没有可合并的内容
+    if upto == 0 || (upto == 1 && is_summary(&history[0])) {
+      return false;
+    }
+    let (prose, snippets) = split_verbatim(&history[..upto]);
+    let backend = Arc::clone(backend);
+    let model = model.to_string();
+    let handle =
+      tokio::spawn(async move { summarize_history(&*backend, &model, &prose, budget).await });
+    self.pending = Some(Pending {
+      upto,
+      prefix: fingerprint(&history[..upto]),
+      snippets,
+      handle,
+    });
+    true
+  }
+
+  /// 发送请求前调用。超过 APPLY_PERCENT 时用已完成的摘要替换对应的历史；
+  /// 超过预算时等待进行中的摘要。返回是否替换了历史。
+  pub async fn apply_if_tight(
+    &mut self,
+    history: &mut Vec<Message>,
+    used: usize,
+    budget: usize,
+  ) -> bool {
+    if used * 100 <= budget * APPLY_PERCENT {
+      return false;
+    }
+    let Some(pending) = &self.pending else {
+      return false;
+    };
+    if !pending.handle.is_finished() && used <= budget {
+      return false;
+    }
+    let pending = self.pending.take().unwrap();
+    let summary = match pending.handle.await {
+      Ok(Ok(summary)) if !summary.trim().is_empty() => summary,
+      // 后台摘要失败时静默放弃，超限时由应急摘要兜底
+      _ => return false,
+    };
+    if history.len() < pending.upto || fingerprint(&history[..pending.upto]) != pending.prefix {
+      return false;
+    }
+    history.splice(
+      ..pending.upto,
+      [Message::Simple {
+        role: "user".to_string(),
+        content: assemble(summary.trim(), &pending.snippets),
+        name: None,
+      }],
+    );
+    true
+  }
+
+  /// 清空历史或退出时取消进行中的摘要
+  pub fn cancel(&mut self) {
+    if let Some(pending) = self.pending.take() {
+      pending.handle.abort();
+    }
+  }
+}
+
+impl Drop for RollingSummary {
+  fn drop(&mut self) {
+    self.cancel();
+  }
+}
+
+fn is_summary(message: &Message) -> bool {
+  matches!(message, Message::Simple { content, .. } if content.starts_with(SUMMARY_PREFIX))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::contract::Contract;
+  use crate::mock::ScriptedBackend;
+  use crate::prompt::{AnswerLength, SystemPrompt};
+
+  const BUDGET: usize = 1000;
+
+  fn message(role: &str, tokens: usize) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      // tokens::count 把每个 " x" 算作 1 token
+      content: format!("x{}", " x".repeat(tokens.saturating_sub(1))),
+      name: None,
+    }
+  }
+
+  fn content(message: &Message) -> &str {
+    match message {
+      Message::Simple { content, .. } | Message::Tool { content, .. } => content,
+      Message::MultiModal { .. } => "",
+    }
+  }
+
+  #[test]
+  fn test_split_point_keeps_recent_turns() {
+    let history: Vec<_> = (0..8)
+      .map(|i| message(if i % 2 == 0 { "user" } else { "assistant" }, 1))
+      .collect();
+    assert_eq!(split_point(&history), 4);
+    assert_eq!(split_point(&history[..5]), 0);
+    assert_eq!(split_point(&history[..7]), 2);
+    assert_eq!(split_point(&[]), 0);
+  }
+
+  #[test]
+  fn test_contract_is_never_summarized() {
+    let contract = Contract::new(vec!["代码注释用中文".to_string()]);
+    let prompt = SystemPrompt::new("Persona.", AnswerLength::Normal).with_contract(&contract);
+    let history = vec![message("user", 10), message("assistant", 10)];
+    // 摘要的输入只有对话，约定不会被改写进摘要
+    let request = summary_messages(&prompt.messages(&history));
+    assert!(!content(&request[1]).contains("代码注释用中文"));
+    // 历史换成摘要后，约定仍然原样出现在请求中
+    let summarized = vec![Message::Simple {
+      role: "user".to_string(),
+      content: format!("{} 摘要", SUMMARY_PREFIX),
+      name: None,
+    }];
+    let messages = prompt.messages(&summarized);
+    assert_eq!(content(&messages[1]), contract.message().unwrap());
+  }
+
+  fn turn(question: usize, answer: usize) -> [Message; 2] {
+    [message("user", question), message("assistant", answer)]
+  }
+
+  #[test]
+  fn test_chunks_end_at_turn_boundaries() {
+    let mut history = vec![];
+    history.extend(turn(100, 100));
+    // 自动续写的片段属于同一轮
+    history.extend([
+      message("user", 100),
+      message("assistant", 50),
+      Message::Simple {
+        role: "user".to_string(),
+        content: crate::turn::CONTINUE_PROMPT.to_string(),
+        name: None,
+      },
+      message("assistant", 50),
+    ]);
+    history.extend(turn(100, 100));
+    // 单独一轮就超出预算
+    history.extend(turn(1000, 10));
+    history.extend(turn(100, 100));
+    // 每轮约 205 tokens，600 的预算放得下两轮
+    assert_eq!(chunk_turns(&history, 600), [0..6, 6..8, 8..10, 10..12]);
+    assert_eq!(chunk_turns(&history, 100_000).len(), 1);
+    assert_eq!(chunk_turns(&[], 600), Vec::<Range<usize>>::new());
+  }
+
+  #[tokio::test]
+  async fn test_long_history_is_summarized_in_chunks() {
+    let history: Vec<_> = (0..6).flat_map(|_| turn(100, 100)).collect();
+    let backend = ScriptedBackend::new(["第一段摘要"]);
+    // 第二段失败时用截断的原文代替
+    backend.push_error("502 Bad Gateway");
+    backend.push_reply("第三段摘要");
+    backend.push_reply("最终摘要");
+    let summary = summarize_history(&backend, "deepseek-chat", &history, 600)
+      .await
+      .unwrap();
+    assert_eq!(summary, "最终摘要");
+
+    let requests = backend.requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 4);
+    for (_, request) in &requests {
+      assert!(estimate_messages_tokens(request) <= 600);
+    }
+    let merge = content(&requests[3].1[1]);
+    assert!(merge.starts_with(MERGE_INSTRUCTION));
+    assert!(merge.contains("第 1 段：第一段摘要\n\n第 2 段：user: x x"));
+    assert!(merge.ends_with("第 3 段：第三段摘要"));
+
+    // 放得进一次请求时不分段
+    let backend = ScriptedBackend::new(["摘要"]);
+    summarize_history(&backend, "deepseek-chat", &history[..4], 600)
+      .await
+      .unwrap();
+    let (_, request) = backend.requests.lock().unwrap()[0].clone();
+    assert_eq!(
+      serde_json::to_string(&request).unwrap(),
+      serde_json::to_string(&summary_messages(&history[..4])).unwrap()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_failed_summary_keeps_the_history() {
+    let mut history: Vec<_> = (0..6).flat_map(|_| turn(100, 100)).collect();
+    let original = serde_json::to_string(&history).unwrap();
+    let backend = ScriptedBackend::default();
+    for _ in 0..3 {
+      backend.push_error("502 Bad Gateway");
+    }
+    let result = summarize_history(&backend, "deepseek-chat", &history, 600).await;
+    let error = format!("{:#}", result.as_ref().unwrap_err());
+    assert!(error.contains("502 Bad Gateway"), "{}", error);
+    // 三段都失败后不再发合并请求
+    assert_eq!(backend.request_count(), 3);
+    let summary = result.ok();
+    assert!(!replace_history(&mut history, summary.as_deref(), &[]));
+    assert_eq!(serde_json::to_string(&history).unwrap(), original);
+
+    assert!(replace_history(&mut history, Some("摘要"), &[]));
+    assert_eq!(history.len(), 1);
+    assert!(content(&history[0]).ends_with("摘要"));
+  }
+
+  #[tokio::test]
+  async fn test_background_summary_fires_and_replaces_oldest_turns() {
+    let backend = Arc::new(ScriptedBackend::new(["用户在讨论 x 的重复"]));
+    let mut rolling = RollingSummary::default();
+    let mut history = vec![];
+    let mut started_at = None;
+    // 每轮 102 token，超过 50% 预算之后才开始
+    for turn in 0..6 {
+      history.push(message("user", 51));
+      history.push(message("assistant", 51));
+      let used = estimate_messages_tokens(&history);
+      if rolling.maybe_start(&backend, "deepseek-chat", &history, used, BUDGET) {
+        started_at.get_or_insert(turn);
+      }
+    }
+    assert_eq!(started_at, Some(4));
+    assert!(rolling.pending.is_some());
+
+    // 未到 APPLY_PERCENT 时不替换
+    history.push(message("user", 50));
+    let used = estimate_messages_tokens(&history);
+    assert!(!rolling.apply_if_tight(&mut history, used, BUDGET).await);
+    history.push(message("assistant", 50));
+    history.push(message("user", 100));
+    // 等后台任务完成（测试运行时是单线程的）
+    while !rolling.pending.as_ref().unwrap().handle.is_finished() {
+      tokio::task::yield_now().await;
+    }
+    let used = estimate_messages_tokens(&history);
+    assert!(rolling.apply_if_tight(&mut history, used, BUDGET).await);
+
+    assert!(content(&history[0]).starts_with("[历史摘要] 用户在讨论"));
+    // 摘要覆盖开始时的前 3 轮，之后的消息原样保留
+    assert_eq!(backend.request_count(), 1);
+    let (_, request) = backend.requests.lock().unwrap()[0].clone();
+    assert_eq!(content(&request[1]).matches("user: ").count(), 3);
+    assert_eq!(history.len(), 1 + 9);
+    assert!(estimate_messages_tokens(&history) < used);
+    assert!(rolling.pending.is_none());
+  }
+
+  fn simple(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  #[test]
+  fn test_code_and_errors_are_split_from_prose() {
+    let rust = "```rust\nfn main() {\n  println!(\"hi\");\n}\n```";
+    let nested = "````markdown\n```\ninner\n```\n````";
+    let error =
+      "error[E0382]: borrow of moved value: `v`\nthread 'main' panicked at src/main.rs:4:5";
+    let history = vec![
+      simple(
+        "user",
+        &format!("这段代码报错：\n{}\n编译输出：\n{}", rust, error),
+      ),
+      simple(
+        "assistant",
+        &format!("v 被移动了。示例：\n{}\n改好了", nested),
+      ),
+      // 同样的代码再次出现时沿用编号
+      simple("user", &format!("还是\n{}", rust)),
+      simple("assistant", "好的"),
+    ];
+    let (prose, snippets) = split_verbatim(&history);
+    assert_eq!(
+      content(&prose[0]),
+      "这段代码报错：\n[代码片段 1]\n编译输出：\n[代码片段 2]"
+    );
+    assert_eq!(
+      content(&prose[1]),
+      "v 被移动了。示例：\n[代码片段 3]\n改好了"
+    );
+    assert_eq!(content(&prose[2]), "还是\n[代码片段 1]");
+    assert_eq!(content(&prose[3]), "好的");
+    let texts: Vec<(usize, &str)> = snippets.iter().map(|s| (s.id, s.text.as_str())).collect();
+    assert_eq!(texts, [(2, error), (3, nested), (1, rust)]);
+
+    // 重新摘要时沿用上次的片段：原样解析出来，新片段的编号接在后面
+    let summary = assemble("用户在修复借用错误", &snippets);
+    assert!(
+      summary.starts_with("[历史摘要] 用户在修复借用错误\n\n保留的代码片段：\n[代码片段 2]\n")
+    );
+    let unclosed = "~~~\n[代码片段 9]\nstill open";
+    let (prose, again) = split_verbatim(&[
+      simple("user", &summary),
+      simple(
+        "user",
+        &format!(
+          "Traceback (most recent call last):\nValueError: bad\n{}",
+          unclosed
+        ),
+      ),
+    ]);
+    assert_eq!(content(&prose[0]), "[历史摘要] 用户在修复借用错误");
+    assert_eq!(content(&prose[1]), "[代码片段 4]\n[代码片段 5]");
+    assert_eq!(&again[..3], &snippets[..]);
+    assert_eq!(
+      again[3].text,
+      "Traceback (most recent call last):\nValueError: bad"
+    );
+    assert_eq!(again[4].text, unclosed);
+    // 代码块中像编号的行不会把片段拆开
+    let (_, parsed) = split_verbatim(&[simple("user", &assemble("摘要", &again))]);
+    assert_eq!(parsed, again);
+    assert_eq!(assemble("摘要", &[]), "[历史摘要] 摘要");
+  }
+
+  #[test]
+  fn test_appendix_drops_oldest_snippets_over_cap() {
+    let block = |word: &str, tokens: usize| {
+      format!("```\n{}{}\n```", word, format!(" {}", word).repeat(tokens))
+    };
+    let history = vec![
+      simple("user", &block("a", APPENDIX_MAX_TOKENS / 2)),
+      // 单独一段就超出上限，直接跳过，不影响更早的片段
+      simple("assistant", &block("b", APPENDIX_MAX_TOKENS * 2)),
+      simple("user", &block("c", APPENDIX_MAX_TOKENS / 2)),
+      simple("assistant", &block("d", 10)),
+    ];
+    let (prose, snippets) = split_verbatim(&history);
+    assert_eq!(content(&prose[1]), "[代码片段 2]");
+    let ids: Vec<usize> = snippets.iter().map(|s| s.id).collect();
+    assert_eq!(ids, [3, 4]);
+    let used: usize = snippets.iter().map(|s| tokens::count(&s.text)).sum();
+    assert!(used <= APPENDIX_MAX_TOKENS);
+  }
+
+  #[tokio::test]
+  async fn test_cleared_history_discards_summary() {
+    let backend = Arc::new(ScriptedBackend::new(["摘要"]));
+    let mut rolling = RollingSummary::default();
+    let mut history: Vec<_> = (0..8)
+      .map(|i| message(if i % 2 == 0 { "user" } else { "assistant" }, 100))
+      .collect();
+    assert!(rolling.maybe_start(&backend, "deepseek-chat", &history, 800, BUDGET));
+
+    // \c 之后的新历史与摘要覆盖的前缀不同，不能替换
+    history = (0..8)
+      .map(|i| message(if i % 2 == 0 { "user" } else { "assistant" }, 120))
+      .collect();
+    assert!(!rolling.apply_if_tight(&mut history, 1100, BUDGET).await);
+    assert_eq!(history.len(), 8);
+  }
+
+  #[tokio::test]
+  async fn test_failed_summary_is_silent_and_cancel_aborts() {
+    let backend = Arc::new(ScriptedBackend::default());
+    backend.push_error("timeout");
+    let mut rolling = RollingSummary::default();
+    let mut history: Vec<_> = (0..8)
+      .map(|i| message(if i % 2 == 0 { "user" } else { "assistant" }, 100))
+      .collect();
+    assert!(rolling.maybe_start(&backend, "deepseek-chat", &history, 800, BUDGET));
+    assert!(!rolling.apply_if_tight(&mut history, 1100, BUDGET).await);
+    assert_eq!(history.len(), 8);
+
+    let backend = Arc::new(ScriptedBackend::new(["摘要"]));
+    assert!(rolling.maybe_start(&backend, "deepseek-chat", &history, 800, BUDGET));
+    rolling.cancel();
+    assert!(rolling.pending.is_none());
+  }
+}
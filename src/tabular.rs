@@ -0,0 +1,404 @@
+use crate::tokens;
+use std::path::Path;
+
+/// 表格概要默认的 token 预算
+pub const DEFAULT_BUDGET: usize = 2000;
+/// 推断列类型时只看前这么多行
+const TYPE_SAMPLE_ROWS: usize = 1000;
+/// 概要中首尾各显示的行数，超出预算时逐步减少
+const PREVIEW_ROWS: [usize; 5] = [10, 5, 3, 1, 0];
+/// 判断是否为表格时检查开头的行数
+const SNIFF_LINES: usize = 10;
+/// 单元格在概要中最多显示的字符数
+const MAX_CELL_CHARS: usize = 40;
+
+/// 按扩展名确定分隔符
+fn delimiter_for(path: &Path) -> Option<char> {
+  match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+    "csv" => Some(','),
+    "tsv" | "tab" => Some('\t'),
+    _ => None,
+  }
+}
+
+/// 解析为记录：支持双引号包围的字段、`""` 转义，以及引号内的分隔符和换行
+pub fn records(text: &str, delimiter: char) -> Vec<Vec<String>> {
+  let mut records = vec![];
+  let mut record = vec![];
+  let mut field = String::new();
+  let mut quoted = false;
+  let mut chars = text.chars().peekable();
+  while let Some(c) = chars.next() {
+    match (quoted, c) {
+      (true, '"') if chars.peek() == Some(&'"') => {
+        chars.next();
+        field.push('"');
+      }
+      (true, '"') => quoted = false,
+      (true, c) => field.push(c),
+      (false, '"') if field.is_empty() => quoted = true,
+      (false, c) if c == delimiter => record.push(std::mem::take(&mut field)),
+      (false, '\r') if chars.peek() == Some(&'\n') => {}
+      (false, '\n') => {
+        record.push(std::mem::take(&mut field));
+        records.push(std::mem::take(&mut record));
+      }
+      (false, c) => field.push(c),
+    }
+  }
+  if !field.is_empty() || !record.is_empty() {
+    record.push(field);
+    records.push(record);
+  }
+  // 空行不算记录
+  records.retain(|r| !(r.len() == 1 && r[0].is_empty()));
+  records
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+  Integer,
+  Float,
+  Bool,
+  Date,
+  Text,
+  /// 样本中全为空
+  Empty,
+}
+
+impl ColumnType {
+  fn label(self) -> &'static str {
+    match self {
+      ColumnType::Integer => "整数",
+      ColumnType::Float => "小数",
+      ColumnType::Bool => "布尔",
+      ColumnType::Date => "日期",
+      ColumnType::Text => "文本",
+      ColumnType::Empty => "空",
+    }
+  }
+}
+
+fn is_date(value: &str) -> bool {
+  let bytes = value.as_bytes();
+  bytes.len() == 10
+    && bytes[4] == b'-'
+    && bytes[7] == b'-'
+    && bytes
+      .iter()
+      .enumerate()
+      .all(|(i, b)| i == 4 || i == 7 || b.is_ascii_digit())
+}
+
+/// 由非空值推断类型：所有值都满足的最窄类型
+pub fn infer_type<'a>(values: impl Iterator<Item = &'a str>) -> ColumnType {
+  let mut kind = ColumnType::Empty;
+  for value in values.map(str::trim).filter(|v| !v.is_empty()) {
+    let this = if value.parse::<i64>().is_ok() {
+      ColumnType::Integer
+    } else if value.parse::<f64>().is_ok() {
+      ColumnType::Float
+    } else if matches!(value.to_ascii_lowercase().as_str(), "true" | "false") {
+      ColumnType::Bool
+    } else if is_date(value) {
+      ColumnType::Date
+    } else {
+      ColumnType::Text
+    };
+    kind = match (kind, this) {
+      (ColumnType::Empty, this) => this,
+      (a, b) if a == b => a,
+      (ColumnType::Integer, ColumnType::Float) | (ColumnType::Float, ColumnType::Integer) => {
+        ColumnType::Float
+      }
+      _ => return ColumnType::Text,
+    };
+  }
+  kind
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumericStats {
+  pub min: f64,
+  pub max: f64,
+  pub mean: f64,
+}
+
+/// 可以解析为数字的值的统计，其他值忽略
+pub fn numeric_stats<'a>(values: impl Iterator<Item = &'a str>) -> Option<NumericStats> {
+  let (mut min, mut max, mut sum, mut n) = (f64::INFINITY, f64::NEG_INFINITY, 0.0, 0usize);
+  for v in values.filter_map(|v| v.trim().parse::<f64>().ok()) {
+    min = min.min(v);
+    max = max.max(v);
+    sum += v;
+    n += 1;
+  }
+  (n > 0).then(|| NumericStats {
+    min,
+    max,
+    mean: sum / n as f64,
+  })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+  pub name: String,
+  pub kind: ColumnType,
+  pub stats: Option<NumericStats>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+  pub columns: Vec<Column>,
+  /// 数据行，不含表头
+  pub rows: Vec<Vec<String>>,
+  /// 字段数与表头不一致的行数
+  pub ragged: usize,
+}
+
+pub fn profile(records: Vec<Vec<String>>) -> Option<Profile> {
+  let mut records = records.into_iter();
+  let header = records.next()?;
+  let rows: Vec<_> = records.collect();
+  let ragged = rows.iter().filter(|r| r.len() != header.len()).count();
+  let columns = header
+    .iter()
+    .enumerate()
+    .map(|(i, name)| {
+      let kind = infer_type(rows.iter().take(TYPE_SAMPLE_ROWS).map(|r| cell(r, i)));
+      let stats = matches!(kind, ColumnType::Integer | ColumnType::Float)
+        .then(|| numeric_stats(rows.iter().map(|r| cell(r, i))))
+        .flatten();
+      Column {
+        name: name.trim().to_string(),
+        kind,
+        stats,
+      }
+    })
+    .collect();
+  Some(Profile {
+    columns,
+    rows,
+    ragged,
+  })
+}
+
+/// 扩展名是表格、并且开头几行的字段数与表头一致时返回分隔符
+pub fn detect(path: &Path, text: &str) -> Option<char> {
+  let delimiter = delimiter_for(path)?;
+  let head: String = text
+    .lines()
+    .take(SNIFF_LINES)
+    .collect::<Vec<_>>()
+    .join("\n");
+  let sample = records(&head, delimiter);
+  let width = sample.first()?.len();
+  let consistent = sample.iter().filter(|r| r.len() == width).count();
+  (width >= 2 && consistent * 5 >= sample.len() * 4).then_some(delimiter)
+}
+
+fn cell_text(value: &str) -> String {
+  let value = value.replace(['\n', '\r'], " ").replace('|', "\\|");
+  match value.chars().count() > MAX_CELL_CHARS {
+    true => format!(
+      "{}…",
+      value.chars().take(MAX_CELL_CHARS).collect::<String>()
+    ),
+    false => value,
+  }
+}
+
+fn number(v: f64, kind: ColumnType) -> String {
+  match kind {
+    ColumnType::Integer => format!("{}", v as i64),
+    _ => format!("{}", (v * 1e4).round() / 1e4),
+  }
+}
+
+fn cell(row: &[String], i: usize) -> &str {
+  row.get(i).map_or("", String::as_str)
+}
+
+fn table(header: &[String], rows: &[Vec<String>]) -> String {
+  let width = header.len();
+  let line = |cells: Vec<String>| format!("| {} |", cells.join(" | "));
+  let mut lines = vec![
+    line(header.iter().map(|h| cell_text(h)).collect()),
+    line(vec!["---".to_string(); width]),
+  ];
+  for row in rows {
+    lines.push(line((0..width).map(|i| cell_text(cell(row, i))).collect()));
+  }
+  lines.join("\n")
+}
+
+/// 概要：规模、每列的类型与数值统计、首尾 `preview` 行
+pub fn render(name: &str, profile: &Profile, preview: usize) -> String {
+  let mut out = format!(
+    "[表格 {}：{} 行 × {} 列，以下为概要，未附加全部内容]",
+    name,
+    profile.rows.len(),
+    profile.columns.len()
+  );
+  if profile.ragged > 0 {
+    out.push_str(&format!("\n（{} 行的字段数与表头不一致）", profile.ragged));
+  }
+  let header: Vec<String> = ["列", "类型", "最小值", "最大值", "平均值"]
+    .map(String::from)
+    .to_vec();
+  let columns: Vec<Vec<String>> = profile
+    .columns
+    .iter()
+    .map(|c| {
+      let (min, max, mean) = match c.stats {
+        Some(s) => (
+          number(s.min, c.kind),
+          number(s.max, c.kind),
+          number(s.mean, ColumnType::Float),
+        ),
+        None => Default::default(),
+      };
+      vec![c.name.clone(), c.kind.label().to_string(), min, max, mean]
+    })
+    .collect();
+  out.push_str(&format!("\n\n{}", table(&header, &columns)));
+  let names: Vec<String> = profile.columns.iter().map(|c| c.name.clone()).collect();
+  let rows = &profile.rows;
+  if rows.len() <= preview * 2 {
+    if !rows.is_empty() {
+      out.push_str(&format!(
+        "\n\n全部 {} 行：\n\n{}",
+        rows.len(),
+        table(&names, rows)
+      ));
+    }
+  } else if preview > 0 {
+    out.push_str(&format!(
+      "\n\n前 {} 行：\n\n{}\n\n后 {} 行：\n\n{}",
+      preview,
+      table(&names, &rows[..preview]),
+      preview,
+      table(&names, &rows[rows.len() - preview..])
+    ));
+  }
+  out
+}
+
+/// 表格附件的概要，在 `budget` tokens 内尽量多显示首尾行；不是表格时返回 None
+pub fn summarize(path: &Path, bytes: &[u8], budget: usize) -> Option<String> {
+  let text = std::str::from_utf8(bytes).ok()?;
+  let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+  let delimiter = detect(path, text)?;
+  let profile = profile(records(text, delimiter))?;
+  let name = path.file_name().map_or_else(
+    || path.display().to_string(),
+    |n| n.to_string_lossy().into_owned(),
+  );
+  let mut summary = String::new();
+  for preview in PREVIEW_ROWS {
+    summary = render(&name, &profile, preview);
+    if tokens::count(&summary) <= budget {
+      break;
+    }
+  }
+  Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn row(cells: &[&str]) -> Vec<String> {
+    cells.iter().map(|c| c.to_string()).collect()
+  }
+
+  #[test]
+  fn test_records_quoting() {
+    let text =
+      "id,name,note\r\n1,\"Smith, John\",\"said \"\"hi\"\"\"\n2,Ann,\"two\nlines\"\n\n3,,\n";
+    assert_eq!(
+      records(text, ','),
+      [
+        row(&["id", "name", "note"]),
+        row(&["1", "Smith, John", "said \"hi\""]),
+        row(&["2", "Ann", "two\nlines"]),
+        row(&["3", "", ""]),
+      ]
+    );
+    // 没有结尾换行、引号出现在字段中间
+    assert_eq!(
+      records("a\tb\n5\" tall\tx", '\t'),
+      [row(&["a", "b"]), row(&["5\" tall", "x"])]
+    );
+  }
+
+  #[test]
+  fn test_infer_type_and_stats() {
+    let cases = [
+      (vec!["1", "2", " 3 "], ColumnType::Integer),
+      (vec!["1", "2.5", ""], ColumnType::Float),
+      (vec!["true", "False"], ColumnType::Bool),
+      (vec!["2026-10-14", "2026-01-01"], ColumnType::Date),
+      (vec!["1", "n/a"], ColumnType::Text),
+      (vec!["", " "], ColumnType::Empty),
+    ];
+    for (values, kind) in cases {
+      assert_eq!(infer_type(values.iter().copied()), kind, "{:?}", values);
+    }
+    let stats = numeric_stats(["4", "x", "-2", "10"].into_iter()).unwrap();
+    assert_eq!((stats.min, stats.max, stats.mean), (-2.0, 10.0, 4.0));
+    assert_eq!(numeric_stats(["x"].into_iter()), None);
+  }
+
+  #[test]
+  fn test_ragged_rows() {
+    let records = vec![
+      row(&["a", "b", "c"]),
+      row(&["1", "2"]),
+      row(&["3", "4", "5", "extra"]),
+      row(&["6", "7", "8"]),
+    ];
+    let profile = profile(records).unwrap();
+    assert_eq!(profile.ragged, 2);
+    assert_eq!(profile.columns[2].stats.unwrap().mean, 6.5);
+    let rendered = render("t.csv", &profile, 10);
+    assert!(rendered.contains("（2 行的字段数与表头不一致）"));
+    // 缺少的单元格留空，多出的舍弃
+    assert!(
+      rendered
+        .contains("全部 3 行：\n\n| a | b | c |\n| --- | --- | --- |\n| 1 | 2 |  |\n| 3 | 4 | 5 |")
+    );
+  }
+
+  #[test]
+  fn test_detect() {
+    let csv = "id,value\n1,2\n3,4\n";
+    assert_eq!(detect(Path::new("data.CSV"), csv), Some(','));
+    assert_eq!(detect(Path::new("data.tsv"), "a\tb\n1\t2\n"), Some('\t'));
+    assert_eq!(detect(Path::new("data.txt"), csv), None);
+    // 扩展名是 csv，但内容只有一列
+    assert_eq!(detect(Path::new("notes.csv"), "hello\nworld\n"), None);
+  }
+
+  #[test]
+  fn test_summarize_within_budget() {
+    let mut csv = "id,price,city\n".to_string();
+    for i in 1..=50_000 {
+      csv.push_str(&format!("{},{}.5,\"City {}, CN\"\n", i, i % 100, i));
+    }
+    let summary = summarize(Path::new("sales.csv"), csv.as_bytes(), DEFAULT_BUDGET).unwrap();
+    assert!(tokens::count(&summary) <= DEFAULT_BUDGET);
+    assert!(summary.starts_with("[表格 sales.csv：50000 行 × 3 列"));
+    assert!(summary.contains("| id | 整数 | 1 | 50000 | 25000.5 |"));
+    assert!(summary.contains("| price | 小数 | 0.5 | 99.5 | 50 |"));
+    assert!(summary.contains("| city | 文本 |  |  |  |"));
+    assert!(summary.contains("前 10 行："));
+    assert!(summary.contains("| 50000 | 0.5 | City 50000, CN |"));
+
+    // 预算很小时减少预览行数
+    let small = summarize(Path::new("sales.csv"), csv.as_bytes(), 320).unwrap();
+    assert!(tokens::count(&small) <= 320);
+    assert!(small.contains("前 5 行："));
+    assert!(summarize(Path::new("sales.txt"), csv.as_bytes(), 200).is_none());
+  }
+}
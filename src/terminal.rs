@@ -0,0 +1,270 @@
+//! 对终端状态的改动（raw 模式、括号粘贴、颜色）都经过这里记录，
+//! 正常退出、panic、SIGTERM/SIGHUP 和 Ctrl-C 退出时统一恢复，不会把用户的 shell 留在异常状态
+
+use std::io::{self, IsTerminal};
+use std::sync::{Mutex, TryLockError};
+
+/// 实际改动终端的操作。测试中用记录调用顺序的实现代替 crossterm
+pub trait Backend: Send {
+  fn enable_raw_mode(&mut self) -> io::Result<()>;
+  fn disable_raw_mode(&mut self) -> io::Result<()>;
+  /// 括号粘贴：粘贴的内容前后带上标记，多行粘贴可以作为一条消息读入
+  fn enable_bracketed_paste(&mut self) -> io::Result<()>;
+  fn disable_bracketed_paste(&mut self) -> io::Result<()>;
+  fn reset_colors(&mut self) -> io::Result<()>;
+}
+
+/// 通过 crossterm 改动标准输出所在的终端
+pub struct Crossterm;
+
+impl Backend for Crossterm {
+  fn enable_raw_mode(&mut self) -> io::Result<()> {
+    crossterm::terminal::enable_raw_mode()
+  }
+
+  fn disable_raw_mode(&mut self) -> io::Result<()> {
+    crossterm::terminal::disable_raw_mode()
+  }
+
+  fn enable_bracketed_paste(&mut self) -> io::Result<()> {
+    crossterm::execute!(io::stdout(), crossterm::event::EnableBracketedPaste)
+  }
+
+  fn disable_bracketed_paste(&mut self) -> io::Result<()> {
+    crossterm::execute!(io::stdout(), crossterm::event::DisableBracketedPaste)
+  }
+
+  fn reset_colors(&mut self) -> io::Result<()> {
+    crossterm::execute!(io::stdout(), crossterm::style::ResetColor)
+  }
+}
+
+/// 记录改动过的状态。多个功能可以叠加 raw 模式，
+/// 最后一个释放时才真正还原；restore 不管层数，全部还原，重复调用不再有输出
+pub struct Terminal<B> {
+  backend: B,
+  raw: usize,
+  paste: bool,
+  colored: bool,
+}
+
+impl<B: Backend> Terminal<B> {
+  /// `colored`：是否已经或将要输出颜色
+  pub fn new(backend: B, colored: bool) -> Self {
+    Self {
+      backend,
+      raw: 0,
+      paste: false,
+      colored,
+    }
+  }
+
+  pub fn enable_raw_mode(&mut self) -> io::Result<()> {
+    if self.raw == 0 {
+      self.backend.enable_raw_mode()?;
+    }
+    self.raw += 1;
+    Ok(())
+  }
+
+  pub fn disable_raw_mode(&mut self) -> io::Result<()> {
+    if self.raw == 1 {
+      self.backend.disable_raw_mode()?;
+    }
+    self.raw = self.raw.saturating_sub(1);
+    Ok(())
+  }
+
+  /// 重复开启或关闭没有输出
+  pub fn enable_bracketed_paste(&mut self) -> io::Result<()> {
+    if !self.paste {
+      self.backend.enable_bracketed_paste()?;
+      self.paste = true;
+    }
+    Ok(())
+  }
+
+  pub fn disable_bracketed_paste(&mut self) -> io::Result<()> {
+    if self.paste {
+      self.backend.disable_bracketed_paste()?;
+      self.paste = false;
+    }
+    Ok(())
+  }
+
+  /// 按改动的相反顺序还原；出错时继续还原其余的状态
+  pub fn restore(&mut self) {
+    if self.raw > 0 {
+      let _ = self.backend.disable_raw_mode();
+      self.raw = 0;
+    }
+    if self.paste {
+      let _ = self.backend.disable_bracketed_paste();
+      self.paste = false;
+    }
+    if self.colored {
+      let _ = self.backend.reset_colors();
+      self.colored = false;
+    }
+  }
+}
+
+/// 无法取得记录时（例如 panic 发生在持有锁的代码里）不管记录，全部还原
+fn restore_everything(backend: &mut impl Backend) {
+  let _ = backend.disable_raw_mode();
+  let _ = backend.disable_bracketed_paste();
+  let _ = backend.reset_colors();
+}
+
+static TERMINAL: Mutex<Option<Terminal<Crossterm>>> = Mutex::new(None);
+
+/// 启动时获取一次，丢弃时还原终端。同时安装 panic hook 和 SIGTERM/SIGHUP 处理
+pub struct TerminalGuard;
+
+impl TerminalGuard {
+  pub fn acquire() -> Self {
+    let colored = io::stdout().is_terminal();
+    *TERMINAL.lock().unwrap_or_else(|e| e.into_inner()) = Some(Terminal::new(Crossterm, colored));
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+      restore();
+      previous(info);
+    }));
+    #[cfg(unix)]
+    tokio::spawn(async {
+      use tokio::signal::unix::{SignalKind, signal};
+      let (Ok(mut term), Ok(mut hangup)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+      ) else {
+        return;
+      };
+      let code = tokio::select! {
+        _ = term.recv() => 128 + 15,
+        _ = hangup.recv() => 128 + 1,
+      };
+      restore();
+      std::process::exit(code);
+    });
+    Self
+  }
+}
+
+impl Drop for TerminalGuard {
+  fn drop(&mut self) {
+    restore();
+  }
+}
+
+/// 在记录下改动终端，例如 `terminal::with(|t| t.enable_raw_mode())`；没有获取 TerminalGuard 时返回 None
+pub fn with<R>(f: impl FnOnce(&mut Terminal<Crossterm>) -> R) -> Option<R> {
+  let mut terminal = TERMINAL.lock().unwrap_or_else(|e| e.into_inner());
+  terminal.as_mut().map(f)
+}
+
+/// 还原终端。可以重复调用，也可以在 panic hook 和信号处理中调用
+pub fn restore() {
+  match TERMINAL.try_lock() {
+    Ok(mut terminal) => {
+      if let Some(terminal) = terminal.as_mut() {
+        terminal.restore();
+      }
+    }
+    Err(TryLockError::Poisoned(poisoned)) => {
+      if let Some(terminal) = poisoned.into_inner().as_mut() {
+        terminal.restore();
+      }
+    }
+    Err(TryLockError::WouldBlock) => {
+      if io::stdout().is_terminal() {
+        restore_everything(&mut Crossterm);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::Arc;
+
+  /// 只记录调用顺序
+  #[derive(Clone, Default)]
+  struct Recorder(Arc<Mutex<Vec<String>>>);
+
+  impl Recorder {
+    fn take(&self) -> Vec<String> {
+      std::mem::take(&mut self.0.lock().unwrap())
+    }
+
+    fn push(&self, call: &str) -> io::Result<()> {
+      self.0.lock().unwrap().push(call.to_string());
+      Ok(())
+    }
+  }
+
+  impl Backend for Recorder {
+    fn enable_raw_mode(&mut self) -> io::Result<()> {
+      self.push("raw on")
+    }
+    fn disable_raw_mode(&mut self) -> io::Result<()> {
+      self.push("raw off")
+    }
+    fn enable_bracketed_paste(&mut self) -> io::Result<()> {
+      self.push("paste on")
+    }
+    fn disable_bracketed_paste(&mut self) -> io::Result<()> {
+      self.push("paste off")
+    }
+    fn reset_colors(&mut self) -> io::Result<()> {
+      self.push("reset colors")
+    }
+  }
+
+  #[test]
+  fn test_restore_undoes_every_change_once() {
+    let recorder = Recorder::default();
+    let mut terminal = Terminal::new(recorder.clone(), true);
+    terminal.enable_raw_mode().unwrap();
+    terminal.enable_bracketed_paste().unwrap();
+    terminal.enable_bracketed_paste().unwrap();
+    assert_eq!(recorder.take(), ["raw on", "paste on"]);
+    terminal.restore();
+    assert_eq!(recorder.take(), ["raw off", "paste off", "reset colors"]);
+    // 重复还原（例如 panic hook 之后又 Drop）没有输出
+    terminal.restore();
+    assert!(recorder.take().is_empty());
+  }
+
+  #[test]
+  fn test_layered_changes_are_released_by_the_last_owner() {
+    let recorder = Recorder::default();
+    let mut terminal = Terminal::new(recorder.clone(), false);
+    // 编辑器和另一个读按键的功能都开启了 raw 模式
+    terminal.enable_raw_mode().unwrap();
+    terminal.enable_raw_mode().unwrap();
+    assert_eq!(recorder.take(), ["raw on"]);
+    terminal.disable_raw_mode().unwrap();
+    assert!(recorder.take().is_empty());
+    terminal.disable_raw_mode().unwrap();
+    assert_eq!(recorder.take(), ["raw off"]);
+    // 多余的释放不会再改动终端
+    terminal.disable_raw_mode().unwrap();
+    terminal.restore();
+    assert!(recorder.take().is_empty());
+
+    // 中途还原后，之后的改动重新计数
+    terminal.enable_raw_mode().unwrap();
+    terminal.enable_raw_mode().unwrap();
+    terminal.restore();
+    terminal.enable_raw_mode().unwrap();
+    assert_eq!(recorder.take(), ["raw on", "raw off", "raw on"]);
+  }
+
+  #[test]
+  fn test_restore_without_the_record_resets_everything() {
+    let mut recorder = Recorder::default();
+    restore_everything(&mut recorder);
+    assert_eq!(recorder.take(), ["raw off", "paste off", "reset colors"]);
+  }
+}
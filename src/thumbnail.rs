@@ -0,0 +1,253 @@
+use base64::Engine;
+use std::io::Cursor;
+
+/// 缩略图默认最多占这么多行
+pub const DEFAULT_ROWS: u16 = 8;
+/// 估算的单元格高度（像素），发送前先把图片缩小到显示所需的大小
+const CELL_PIXELS: u32 = 20;
+/// kitty 图形协议每段最多 4096 个 base64 字符
+const KITTY_CHUNK: usize = 4096;
+
+/// 在终端中显示图片的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+  /// kitty 图形协议（kitty、Ghostty）
+  Kitty,
+  /// iTerm2 内联图片（iTerm2、WezTerm）
+  Iterm2,
+  /// 只显示文件名、尺寸和大小
+  Plain,
+}
+
+impl Protocol {
+  /// 配置文件中的 inline_images：auto 返回 None，由 detect 判断
+  pub fn parse(value: &str) -> Result<Option<Self>, String> {
+    match value {
+      "auto" => Ok(None),
+      "kitty" => Ok(Some(Self::Kitty)),
+      "iterm2" => Ok(Some(Self::Iterm2)),
+      "plain" => Ok(Some(Self::Plain)),
+      _ => Err("expected auto, kitty, iterm2 or plain".to_string()),
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      Self::Kitty => "kitty",
+      Self::Iterm2 => "iterm2",
+      Self::Plain => "plain",
+    }
+  }
+
+  /// 按环境变量判断终端支持的协议。tmux 和 screen 默认不转发图形序列，按纯文本处理
+  pub fn detect(env: impl Fn(&str) -> Option<String>) -> Self {
+    let is = |key: &str, value: &str| env(key).is_some_and(|v| v == value);
+    if env("TMUX").is_some() || env("TERM").is_some_and(|t| t.starts_with("screen")) {
+      return Self::Plain;
+    }
+    if env("KITTY_WINDOW_ID").is_some()
+      || is("TERM", "xterm-kitty")
+      || is("TERM", "xterm-ghostty")
+      || is("TERM_PROGRAM", "ghostty")
+    {
+      return Self::Kitty;
+    }
+    if is("TERM_PROGRAM", "iTerm.app")
+      || is("LC_TERMINAL", "iTerm2")
+      || is("TERM_PROGRAM", "WezTerm")
+    {
+      return Self::Iterm2;
+    }
+    Self::Plain
+  }
+}
+
+/// 1536 → "1KB"，215040 → "210KB"
+fn format_size(bytes: usize) -> String {
+  match bytes {
+    0..1024 => format!("{}B", bytes),
+    1024..1_048_576 => format!("{}KB", (bytes + 512) / 1024),
+    _ => format!("{:.1}MB", bytes as f64 / 1_048_576.0),
+  }
+}
+
+/// `[图片: screenshot.png, 1280x800, 210KB]`；无法读取尺寸时省略尺寸
+pub fn label(name: &str, bytes: &[u8]) -> String {
+  let dimensions = image::io::Reader::new(Cursor::new(bytes))
+    .with_guessed_format()
+    .ok()
+    .and_then(|reader| reader.into_dimensions().ok());
+  match dimensions {
+    Some((width, height)) => format!(
+      "[图片: {}, {}x{}, {}]",
+      name,
+      width,
+      height,
+      format_size(bytes.len())
+    ),
+    None => format!("[图片: {}, {}]", name, format_size(bytes.len())),
+  }
+}
+
+/// kitty 图形协议：PNG 数据（f=100）分段发送，`r` 为显示的行数，宽度按比例计算
+pub fn kitty(png: &[u8], rows: u16) -> String {
+  let data = base64::engine::general_purpose::STANDARD.encode(png);
+  let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+  let mut out = String::new();
+  for (i, chunk) in chunks.iter().enumerate() {
+    let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+    let more = i + 1 < chunks.len();
+    match (i, more) {
+      (0, false) => out.push_str(&format!("\x1b_Gf=100,a=T,r={},q=2;", rows)),
+      (0, true) => out.push_str(&format!("\x1b_Gf=100,a=T,r={},q=2,m=1;", rows)),
+      (_, more) => out.push_str(&format!("\x1b_Gm={};", more as u8)),
+    }
+    out.push_str(chunk);
+    out.push_str("\x1b\\");
+  }
+  out
+}
+
+/// iTerm2 内联图片：文件名和数据都是 base64，`height` 为显示的行数
+pub fn iterm2(name: &str, data: &[u8], rows: u16) -> String {
+  let engine = base64::engine::general_purpose::STANDARD;
+  format!(
+    "\x1b]1337;File=name={};size={};inline=1;height={};preserveAspectRatio=1:{}\x07",
+    engine.encode(name),
+    data.len(),
+    rows,
+    engine.encode(data)
+  )
+}
+
+/// 附加图片后显示的缩略图和说明。图片缩小到最多 `max_rows` 行的高度后转成 PNG 发送；
+/// 无法解码或不支持图形协议时只有说明
+pub fn render(protocol: Protocol, name: &str, bytes: &[u8], max_rows: u16) -> String {
+  let label = label(name, bytes);
+  if protocol == Protocol::Plain {
+    return label;
+  }
+  let Ok(image) = image::load_from_memory(bytes) else {
+    return label;
+  };
+  let max_rows = max_rows.max(1);
+  let max_height = max_rows as u32 * CELL_PIXELS;
+  let image = match image.height() > max_height {
+    true => image.resize(u32::MAX, max_height, image::imageops::FilterType::Triangle),
+    false => image,
+  };
+  let mut png = Cursor::new(vec![]);
+  if image
+    .write_to(&mut png, image::ImageOutputFormat::Png)
+    .is_err()
+  {
+    return label;
+  }
+  let png = png.into_inner();
+  let rows = image
+    .height()
+    .div_ceil(CELL_PIXELS)
+    .clamp(1, max_rows as u32) as u16;
+  let inline = match protocol {
+    Protocol::Kitty => kitty(&png, rows),
+    _ => iterm2(name, &png, rows),
+  };
+  format!("{}\n{}", inline, label)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::HashMap;
+
+  /// 1x1 的红色 PNG
+  const DOT: &[u8] = include_bytes!("testdata/dot.png");
+
+  #[test]
+  fn test_encoders_match_fixtures() {
+    assert_eq!(kitty(DOT, 1), include_str!("testdata/dot.kitty"));
+    assert_eq!(
+      iterm2("dot.png", DOT, 1),
+      include_str!("testdata/dot.iterm2")
+    );
+
+    // 超过一段时分段发送，只有第一段带参数，最后一段 m=0
+    let large = vec![0u8; KITTY_CHUNK * 3 / 4 * 2 + 3];
+    let encoded = kitty(&large, 4);
+    let parts: Vec<&str> = encoded.split("\x1b\\").filter(|p| !p.is_empty()).collect();
+    assert_eq!(parts.len(), 3);
+    assert!(parts[0].starts_with("\x1b_Gf=100,a=T,r=4,q=2,m=1;"));
+    assert!(parts[1].starts_with("\x1b_Gm=1;"));
+    assert_eq!(parts[2], "\x1b_Gm=0;AAAA");
+    assert_eq!(parts[1].len(), "\x1b_Gm=1;".len() + KITTY_CHUNK);
+  }
+
+  #[test]
+  fn test_render_downscales_and_labels() {
+    let image = image::RgbImage::from_pixel(640, 400, image::Rgb([0, 128, 255]));
+    let mut png = Cursor::new(vec![]);
+    image::DynamicImage::ImageRgb8(image)
+      .write_to(&mut png, image::ImageOutputFormat::Png)
+      .unwrap();
+    let png = png.into_inner();
+
+    let plain = render(Protocol::Plain, "shot.png", &png, 8);
+    assert_eq!(plain, label("shot.png", &png));
+    assert!(plain.starts_with("[图片: shot.png, 640x400, "), "{}", plain);
+
+    // 最多 4 行：缩小到 80 像素高后发送
+    let shown = render(Protocol::Iterm2, "shot.png", &png, 4);
+    let (inline, text) = shown.split_once('\n').unwrap();
+    assert_eq!(text, plain);
+    assert!(inline.contains(";height=4;"), "{}", inline);
+    let data = inline.rsplit_once(':').unwrap().1.trim_end_matches('\x07');
+    let sent = base64::engine::general_purpose::STANDARD
+      .decode(data)
+      .unwrap();
+    let sent = image::load_from_memory(&sent).unwrap();
+    assert_eq!((sent.width(), sent.height()), (128, 80));
+
+    // 小图不放大，行数按高度计算
+    let dot = render(Protocol::Kitty, "dot.png", DOT, 8);
+    assert!(dot.starts_with("\x1b_Gf=100,a=T,r=1,q=2;"));
+    assert!(dot.ends_with("[图片: dot.png, 1x1, 69B]"));
+
+    // 不是图片时只有说明
+    assert_eq!(
+      render(Protocol::Kitty, "fake.png", &[0x89, b'P', b'N', b'G'], 8),
+      "[图片: fake.png, 4B]"
+    );
+    assert_eq!(format_size(215_040), "210KB");
+    assert_eq!(format_size(3 * 1_048_576 / 2), "1.5MB");
+  }
+
+  #[test]
+  fn test_detect_protocol() {
+    let cases: &[(&[(&str, &str)], Protocol)] = &[
+      (&[("TERM", "xterm-kitty")], Protocol::Kitty),
+      (&[("KITTY_WINDOW_ID", "1")], Protocol::Kitty),
+      (&[("TERM_PROGRAM", "ghostty")], Protocol::Kitty),
+      (&[("TERM_PROGRAM", "iTerm.app")], Protocol::Iterm2),
+      (&[("LC_TERMINAL", "iTerm2")], Protocol::Iterm2),
+      (&[("TERM_PROGRAM", "WezTerm")], Protocol::Iterm2),
+      (&[("TERM", "xterm-256color")], Protocol::Plain),
+      (&[], Protocol::Plain),
+      (
+        &[("TERM", "xterm-kitty"), ("TMUX", "/tmp/tmux-1000/default")],
+        Protocol::Plain,
+      ),
+      (
+        &[("TERM", "screen-256color"), ("LC_TERMINAL", "iTerm2")],
+        Protocol::Plain,
+      ),
+    ];
+    for (vars, expected) in cases {
+      let env: HashMap<&str, &str> = vars.iter().copied().collect();
+      let detected = Protocol::detect(|key| env.get(key).map(|v| v.to_string()));
+      assert_eq!(detected, *expected, "{:?}", vars);
+    }
+    assert_eq!(Protocol::parse("auto"), Ok(None));
+    assert_eq!(Protocol::parse("iterm2"), Ok(Some(Protocol::Iterm2)));
+    assert!(Protocol::parse("sixel").is_err());
+  }
+}
@@ -0,0 +1,323 @@
+//! token 计数。加载了 tiktoken 格式的 BPE 词表（例如 cl100k_base.tiktoken，
+//! 放在 `~/.config/deepcli/tokenizer.tiktoken` 或由 DEEPCLI_TOKENIZER 指定）时按词表切分；
+//! 否则按同样的预切分规则估算：中日韩字符每个约一个 token，英文单词大多一个。
+
+use crate::api::{Content, Message};
+use base64::Engine;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// 指定词表文件的环境变量
+pub const TOKENIZER_ENV: &str = "DEEPCLI_TOKENIZER";
+
+pub fn is_cjk(c: char) -> bool {
+  matches!(c,
+    '\u{3040}'..='\u{30FF}'
+    | '\u{3400}'..='\u{4DBF}'
+    | '\u{4E00}'..='\u{9FFF}'
+    | '\u{AC00}'..='\u{D7AF}'
+    | '\u{F900}'..='\u{FAFF}'
+    | '\u{20000}'..='\u{2FFFF}')
+}
+
+/// 字节对编码：tiktoken 词表中每行是 base64 编码的字节串和它的合并优先级
+#[derive(Debug)]
+pub struct Bpe {
+  ranks: HashMap<Vec<u8>, u32>,
+}
+
+impl Bpe {
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let engine = base64::engine::general_purpose::STANDARD;
+    let mut ranks = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+      if line.trim().is_empty() {
+        continue;
+      }
+      let parsed = line.split_once(' ').and_then(|(token, rank)| {
+        Some((engine.decode(token).ok()?, rank.trim().parse::<u32>().ok()?))
+      });
+      let Some((token, rank)) = parsed else {
+        return Err(format!("line {}: expected `<base64> <rank>`", i + 1));
+      };
+      ranks.insert(token, rank);
+    }
+    if ranks.is_empty() {
+      return Err("empty vocabulary".to_string());
+    }
+    Ok(Self { ranks })
+  }
+
+  /// 词表文件：`path` 为 None 时读取配置目录中的 tokenizer.tiktoken，不存在时返回 Ok(None)
+  pub fn load(path: Option<&Path>) -> Result<Option<Self>, String> {
+    let (path, explicit) = match path {
+      Some(path) => (path.to_path_buf(), true),
+      None => match crate::paths::config_dir().map(|dir| dir.join("tokenizer.tiktoken")) {
+        Some(path) => (path, false),
+        None => return Ok(None),
+      },
+    };
+    if !explicit && !path.exists() {
+      return Ok(None);
+    }
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Self::parse(&text)
+      .map(Some)
+      .map_err(|e| format!("{}: {}", path.display(), e))
+  }
+
+  pub fn count(&self, text: &str) -> usize {
+    pieces(text).map(|piece| self.merge(piece.as_bytes())).sum()
+  }
+
+  /// 一个预切分片段的 token 数：反复合并优先级最高（rank 最小）的相邻两段。
+  /// `bounds` 为各段的起点加上末尾
+  fn merge(&self, piece: &[u8]) -> usize {
+    if self.ranks.contains_key(piece) {
+      return 1;
+    }
+    let mut bounds: Vec<usize> = (0..=piece.len()).collect();
+    loop {
+      let best = (0..bounds.len().saturating_sub(2))
+        .filter_map(|i| {
+          let rank = self.ranks.get(&piece[bounds[i]..bounds[i + 2]])?;
+          Some((*rank, i))
+        })
+        .min();
+      let Some((_, i)) = best else {
+        return bounds.len() - 1;
+      };
+      bounds.remove(i + 1);
+    }
+  }
+}
+
+static TOKENIZER: OnceLock<Bpe> = OnceLock::new();
+
+/// 启动时加载一次词表；加载失败时给出原因，之后按估算计数
+pub fn init(path: Option<PathBuf>) {
+  match Bpe::load(path.as_deref()) {
+    Ok(Some(bpe)) => {
+      let _ = TOKENIZER.set(bpe);
+    }
+    Ok(None) => {}
+    Err(e) => eprintln!("[警告] 无法加载分词词表（{}），token 数按估算计算", e),
+  }
+}
+
+/// 文本的 token 数：有词表时精确切分，否则估算
+pub fn count(text: &str) -> usize {
+  match TOKENIZER.get() {
+    Some(bpe) => bpe.count(text),
+    None => heuristic(text),
+  }
+}
+
+/// 没有词表时的估算：按预切分的片段计数。中日韩字符无论是否连写都按一个字一个 token，
+/// 英文单词 7 个字母以内算一个，数字每 3 位一个，标点每 2 个一个
+pub fn heuristic(text: &str) -> usize {
+  pieces(text)
+    .map(|piece| {
+      let cjk = piece.chars().filter(|c| is_cjk(*c)).count();
+      let letters = piece
+        .chars()
+        .filter(|c| c.is_alphabetic() && !is_cjk(*c))
+        .count();
+      let chars = piece.chars().count();
+      if cjk + letters > 0 {
+        // 非 ASCII 的字母（西里尔、阿拉伯字母等）切得更碎
+        let ascii = piece.chars().filter(|c| c.is_ascii_alphabetic()).count();
+        let other = letters - ascii;
+        let words = match ascii {
+          0 => 0,
+          n => 1 + (n - 1) / 7,
+        };
+        // 字母串前的符号（不是空格）单独成一个 token
+        let prefix = piece.starts_with(|c: char| !c.is_alphabetic() && !c.is_whitespace());
+        cjk + words + other.div_ceil(2) + prefix as usize
+      } else if piece.chars().all(char::is_numeric) {
+        1
+      } else if piece.chars().all(char::is_whitespace) {
+        chars.div_ceil(8)
+      } else {
+        // 标点片段可能带一个前导空格和结尾的换行
+        piece
+          .chars()
+          .filter(|c| !c.is_whitespace())
+          .count()
+          .div_ceil(2)
+          .max(1)
+      }
+    })
+    .sum()
+}
+
+/// 近似 cl100k_base 的预切分：英文缩写、（可带一个前导符号的）字母串、最多 3 位的数字、
+/// （可带一个前导空格的）标点串、换行和其他空白。空白串后面紧跟内容时，最后一个空白留给后面的片段
+pub fn pieces(text: &str) -> impl Iterator<Item = &str> {
+  let mut rest = text;
+  std::iter::from_fn(move || {
+    if rest.is_empty() {
+      return None;
+    }
+    let len = next_piece(rest);
+    let (piece, tail) = rest.split_at(len);
+    rest = tail;
+    Some(piece)
+  })
+}
+
+fn next_piece(text: &str) -> usize {
+  const CONTRACTIONS: &[&str] = &["'s", "'t", "'re", "'ve", "'m", "'ll", "'d"];
+  let is_letter = |c: char| c.is_alphabetic();
+  let is_symbol = |c: char| !c.is_whitespace() && !c.is_alphanumeric();
+  let chars: Vec<(usize, char)> = text.char_indices().take(2).collect();
+  let (first, second) = (chars[0].1, chars.get(1).map(|(_, c)| *c));
+  let end_of = |start: usize, keep: &dyn Fn(char) -> bool| {
+    text[start..]
+      .char_indices()
+      .find(|(_, c)| !keep(*c))
+      .map_or(text.len(), |(i, _)| start + i)
+  };
+  if first == '\''
+    && let Some(c) = CONTRACTIONS.iter().find(|c| {
+      text
+        .get(..c.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(c))
+    })
+  {
+    return c.len();
+  }
+  if is_letter(first) {
+    return end_of(0, &is_letter);
+  }
+  if first != '\r'
+    && first != '\n'
+    && !first.is_numeric()
+    && let Some(second) = second.filter(|c| is_letter(*c))
+  {
+    let start = first.len_utf8() + second.len_utf8();
+    return end_of(start, &is_letter);
+  }
+  if first.is_numeric() {
+    return text
+      .char_indices()
+      .take_while(|(_, c)| c.is_numeric())
+      .take(3)
+      .last()
+      .map_or(0, |(i, c)| i + c.len_utf8());
+  }
+  if is_symbol(first) || (first == ' ' && second.is_some_and(is_symbol)) {
+    let start = match first {
+      ' ' => 1,
+      _ => 0,
+    };
+    let end = end_of(start, &is_symbol);
+    return end_of(end, &|c| c == '\r' || c == '\n');
+  }
+  // 空白：到最后一个换行为止；没有换行且后面还有内容时留下最后一个空白
+  let end = end_of(0, &char::is_whitespace);
+  if let Some(newline) = text[..end].rfind(['\r', '\n']) {
+    return newline + 1;
+  }
+  match text[..end].char_indices().last() {
+    Some((last, _)) if end < text.len() && last > 0 => last,
+    _ => end,
+  }
+}
+
+/// 一条消息的文本部分，图片不计
+pub fn count_message(message: &Message) -> usize {
+  match message {
+    Message::Simple { content, .. } | Message::Tool { content, .. } => count(content),
+    Message::MultiModal { content, .. } => content
+      .iter()
+      .map(|c| match c {
+        Content::Text(t) => count(&t.text),
+        Content::Image(_) => 0,
+      })
+      .sum(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_pieces_follow_cl100k_split() {
+    let cases: &[(&str, &[&str])] = &[
+      ("Hello, world!", &["Hello", ",", " world", "!"]),
+      ("I'm here", &["I", "'m", " here"]),
+      ("12345 apples", &["123", "45", " apples"]),
+      ("a  b", &["a", " ", " b"]),
+      ("fn main() {\n}", &["fn", " main", "()", " {\n", "}"]),
+      ("x\n\n  y", &["x", "\n\n", " ", " y"]),
+      ("你好，世界", &["你好", "，世界"]),
+      ("end  ", &["end", "  "]),
+    ];
+    for (text, expected) in cases {
+      assert_eq!(pieces(text).collect::<Vec<_>>(), *expected, "{:?}", text);
+    }
+  }
+
+  #[test]
+  fn test_heuristic_is_close_to_cl100k() {
+    // cl100k_base 的计数
+    let cases: &[(&str, usize)] = &[
+      ("Hello, world!", 4),
+      ("The quick brown fox jumps over the lazy dog.", 10),
+      ("fn main() {}", 4),
+    ];
+    for (text, expected) in cases {
+      assert_eq!(heuristic(text), *expected, "{:?}", text);
+    }
+    // 常用汉字在 cl100k_base 中是一到两个 token；旧的 字符数/4 把中文低估到四分之一
+    for text in [
+      "今天天气很好，我们去公园散步吧。",
+      "请把上面的代码改成异步的版本，并解释为什么需要这样做。",
+    ] {
+      let chars = text.chars().count();
+      let counted = heuristic(text);
+      assert!(
+        (chars..=chars * 2).contains(&counted),
+        "{:?}: {}",
+        text,
+        counted
+      );
+    }
+    // 中英混排和代码：每个词、每组标点都至少一个 token
+    let mixed = "用 tokio 写一个 TCP echo server";
+    assert_eq!(heuristic(mixed), 1 + 1 + 3 + 1 + 1 + 1);
+    let code = "fn main() {\n    let x = vec![1, 2, 3];\n    println!(\"{:?}\", x);\n}\n";
+    let counted = heuristic(code);
+    assert!((22..=32).contains(&counted), "{}", counted);
+    assert_eq!(heuristic(""), 0);
+  }
+
+  #[test]
+  fn test_bpe_merges_by_rank() {
+    let engine = base64::engine::general_purpose::STANDARD;
+    // 所有单字节，加上 "ab"、"abc" 和 " ab" 三个合并
+    let mut vocab: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+    vocab.extend([b"ab".to_vec(), b"abc".to_vec(), b" ab".to_vec()]);
+    let text: String = vocab
+      .iter()
+      .enumerate()
+      .map(|(rank, token)| format!("{} {}\n", engine.encode(token), rank))
+      .collect();
+    let bpe = Bpe::parse(&text).unwrap();
+    assert_eq!(bpe.count("abc"), 1);
+    assert_eq!(bpe.count("abd"), 2);
+    // 没有 "abab" 时停在两个 "ab"；"x abc" 切成 "x" 和 " abc" 两段，后者合并为 " " 和 "abc"
+    assert_eq!(bpe.count("abab"), 2);
+    assert_eq!(bpe.count("x abc"), 3);
+    assert_eq!(bpe.count("你"), 3);
+
+    assert!(Bpe::parse("").is_err());
+    assert!(Bpe::parse("YWI=\n").unwrap_err().starts_with("line 1"));
+    let missing = Path::new("/nonexistent/tokenizer.tiktoken");
+    assert!(Bpe::load(Some(missing)).is_err());
+  }
+}
@@ -0,0 +1,75 @@
+use crate::width::display_width;
+
+/// REPL 的输入提示符，转录中用户消息的第一行以它开头
+pub const PROMPT: &str = "> ";
+
+/// 独占一行时会被误认为转录结构的内容：提示符、`## user` 之类的标题、`**user**:` 说话人行
+fn looks_like_marker(line: &str) -> bool {
+  line.starts_with('>')
+    || line.starts_with("## ")
+    || (line.starts_with("**") && line.ends_with("**:"))
+}
+
+/// 行首加 `\`；本来就以 `\` 开头、去掉后像标记的行再加一个，保证可以逐行还原
+fn escape(line: &str) -> String {
+  if looks_like_marker(line.trim_start_matches('\\')) {
+    format!("\\{}", line)
+  } else {
+    line.to_string()
+  }
+}
+
+/// 用户消息的规范转录形式：第一行加提示符，后续行加与提示符等宽的缩进，
+/// 中间的空行原样保留，像转录标记的行先转义
+pub fn user_message(text: &str) -> String {
+  let gutter = " ".repeat(display_width(PROMPT));
+  text
+    .trim_end_matches(['\n', '\r'])
+    .lines()
+    .enumerate()
+    .map(|(i, line)| {
+      let prefix = if i == 0 { PROMPT } else { gutter.as_str() };
+      match escape(line) {
+        line if line.is_empty() => prefix.trim_end().to_string(),
+        line => format!("{}{}", prefix, line),
+      }
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+/// Markdown 中用代码块包住转录形式，块内的 ``` 不会提前结束它
+pub fn user_message_markdown(text: &str) -> String {
+  let longest = text
+    .lines()
+    .map(|l| l.trim_start().chars().take_while(|&c| c == '`').count())
+    .max()
+    .unwrap_or(0);
+  let fence = "`".repeat(longest.max(2) + 1);
+  format!("{}text\n{}\n{}", fence, user_message(text), fence)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const INPUT: &str = "这段代码为什么不编译？\n\n```rust\nfn main() {\n    let x: i32 = \"1\";\n}\n```\n\n> 引用的报错\n## assistant\n\\> 已经转义的\n最后一行\n";
+
+  #[test]
+  fn test_user_message_matches_golden_file() {
+    assert_eq!(
+      user_message(INPUT),
+      include_str!("testdata/transcript_user.txt").trim_end()
+    );
+    assert_eq!(user_message("单行"), "> 单行");
+    assert_eq!(user_message(""), "");
+  }
+
+  #[test]
+  fn test_markdown_fence_outlasts_content() {
+    let markdown = user_message_markdown(INPUT);
+    assert!(markdown.starts_with("````text\n> 这段代码为什么不编译？\n"));
+    assert!(markdown.ends_with("\n  最后一行\n````"));
+    assert_eq!(user_message_markdown("hi"), "```text\n> hi\n```");
+  }
+}
@@ -0,0 +1,545 @@
+use crate::api::{Content, Message};
+use crate::tokens;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TruncateMode {
+  /// 看起来像日志时保留首尾，否则只保留开头
+  #[default]
+  Auto,
+  Head,
+  Log,
+}
+
+impl std::str::FromStr for TruncateMode {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(TruncateMode::Auto),
+      "head" => Ok(TruncateMode::Head),
+      "log" => Ok(TruncateMode::Log),
+      _ => Err("Truncate mode must be auto, head or log".to_string()),
+    }
+  }
+}
+
+/// 日志模式下开头和结尾各占预算的比例，剩余部分留给省略标记和误差
+const LOG_HEAD_PERCENT: usize = 15;
+const LOG_TAIL_PERCENT: usize = 60;
+const MIN_LOG_LINES: usize = 20;
+
+fn omitted_marker(lines: usize) -> String {
+  format!("[... 省略 {} 行 ...]", lines)
+}
+
+fn has_timestamp(line: &str) -> bool {
+  let prefix: Vec<char> = line.chars().take(40).collect();
+  let digit = |i: usize| prefix.get(i).is_some_and(|c| c.is_ascii_digit());
+  (0..prefix.len()).any(|i| {
+    // HH:MM:SS
+    let time = digit(i)
+      && digit(i + 1)
+      && prefix.get(i + 2) == Some(&':')
+      && digit(i + 3)
+      && digit(i + 4)
+      && prefix.get(i + 5) == Some(&':')
+      && digit(i + 6)
+      && digit(i + 7);
+    // YYYY-MM-DD
+    let date = (0..4).all(|k| digit(i + k))
+      && prefix.get(i + 4) == Some(&'-')
+      && digit(i + 5)
+      && digit(i + 6)
+      && prefix.get(i + 7) == Some(&'-');
+    time || date
+  })
+}
+
+fn has_level(line: &str) -> bool {
+  let prefix: String = line.chars().take(48).collect();
+  ["INFO", "WARN", "ERROR", "DEBUG", "TRACE", "FATAL"]
+    .iter()
+    .any(|level| prefix.contains(level))
+}
+
+/// 把数字统一替换，只取前三个词，用来判断行是否“长得一样”
+fn line_shape(line: &str) -> String {
+  line
+    .split_whitespace()
+    .take(3)
+    .map(|w| {
+      w.chars()
+        .map(|c| if c.is_ascii_digit() { '#' } else { c })
+        .collect::<String>()
+    })
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// 启发式判断：多数行带时间戳/日志级别，或大量行的形状相同
+pub fn looks_like_log(text: &str) -> bool {
+  let lines: Vec<&str> = text
+    .lines()
+    .filter(|l| !l.trim().is_empty())
+    .take(500)
+    .collect();
+  if lines.len() < MIN_LOG_LINES {
+    return false;
+  }
+  let stamped = lines
+    .iter()
+    .filter(|l| has_timestamp(l) || has_level(l))
+    .count();
+  if stamped * 2 >= lines.len() {
+    return true;
+  }
+  let mut firsts: Vec<String> = lines.iter().map(|l| line_shape(l)).collect();
+  firsts.sort_unstable();
+  let mut best = 0;
+  let mut run = 0;
+  for (i, word) in firsts.iter().enumerate() {
+    run = if i > 0 && firsts[i - 1] == *word {
+      run + 1
+    } else {
+      1
+    };
+    best = best.max(run);
+  }
+  best * 10 >= lines.len() * 6
+}
+
+/// 第 `chars` 个字符的字节位置
+fn byte_of(text: &str, chars: usize) -> usize {
+  text
+    .char_indices()
+    .nth(chars)
+    .map_or(text.len(), |(i, _)| i)
+}
+
+/// 开头（`from_end` 时为结尾）最多多少个字符在 `budget` tokens 以内。
+/// 计数随长度大致单调，二分查找得到的长度一定放得下
+fn chars_within(text: &str, budget: usize, from_end: bool) -> usize {
+  let total = text.chars().count();
+  let fits = |n: usize| {
+    let part = match from_end {
+      true => &text[byte_of(text, total - n)..],
+      false => &text[..byte_of(text, n)],
+    };
+    tokens::count(part) <= budget
+  };
+  let (mut lo, mut hi) = (0, total);
+  while lo < hi {
+    let mid = (lo + hi).div_ceil(2);
+    if fits(mid) {
+      lo = mid;
+    } else {
+      hi = mid - 1;
+    }
+  }
+  lo
+}
+
+fn take_tail_chars(line: &str, budget: usize) -> &str {
+  // 单行超出预算时退而按字符截取结尾
+  let keep = chars_within(line, budget, true);
+  &line[byte_of(line, line.chars().count() - keep)..]
+}
+
+fn truncate_head(lines: &[&str], budget: usize) -> String {
+  let mut used = 0;
+  let mut kept = 0;
+  for line in lines {
+    let cost = tokens::count(line);
+    if used + cost > budget {
+      break;
+    }
+    used += cost;
+    kept += 1;
+  }
+  let mut out = lines[..kept].join("\n");
+  if !out.is_empty() {
+    out.push('\n');
+  }
+  out.push_str(&omitted_marker(lines.len() - kept));
+  out
+}
+
+fn truncate_log(lines: &[&str], budget: usize) -> String {
+  let head_budget = budget * LOG_HEAD_PERCENT / 100;
+  let tail_budget = budget * LOG_TAIL_PERCENT / 100;
+
+  let mut head = 0;
+  let mut used = 0;
+  for line in lines {
+    let cost = tokens::count(line);
+    if used + cost > head_budget {
+      break;
+    }
+    used += cost;
+    head += 1;
+  }
+
+  let mut tail = 0;
+  used = 0;
+  for line in lines[head..].iter().rev() {
+    let cost = tokens::count(line);
+    if used + cost > tail_budget {
+      break;
+    }
+    used += cost;
+    tail += 1;
+  }
+
+  let tail_start = lines.len() - tail;
+  let mut parts: Vec<String> = lines[..head].iter().map(|l| l.to_string()).collect();
+  if tail == 0 && tail_start > head {
+    // 最后一行本身就超出预算：保留它的结尾，错误信息通常在那里
+    let last = lines[lines.len() - 1];
+    parts.push(omitted_marker(lines.len() - head - 1));
+    parts.push(take_tail_chars(last, tail_budget).to_string());
+    return parts.join("\n");
+  }
+  parts.push(omitted_marker(tail_start - head));
+  parts.extend(lines[tail_start..].iter().map(|l| l.to_string()));
+  parts.join("\n")
+}
+
+fn is_log(text: &str, mode: TruncateMode) -> bool {
+  match mode {
+    TruncateMode::Log => true,
+    TruncateMode::Head => false,
+    TruncateMode::Auto => looks_like_log(text),
+  }
+}
+
+/// 在预算内返回原文；超出时按模式截断并插入省略标记
+pub fn truncate(text: &str, budget: usize, mode: TruncateMode) -> String {
+  if tokens::count(text) <= budget {
+    return text.to_string();
+  }
+  let lines: Vec<&str> = text.lines().collect();
+  if is_log(text, mode) {
+    truncate_log(&lines, budget)
+  } else {
+    truncate_head(&lines, budget)
+  }
+}
+
+fn omitted_chars_marker(chars: usize) -> String {
+  format!("[... 省略 {} 字符 ...]", chars)
+}
+
+/// 按 token 数在字符边界上截断，结果一定在预算内：日志保留首尾（比例同按行截断），
+/// 其他内容只保留开头
+fn cut_chars(text: &str, budget: usize, log: bool) -> String {
+  let total = text.chars().count();
+  // 扣掉省略标记和两个换行
+  let marker = tokens::count(&format!("\n{}\n", omitted_chars_marker(total)));
+  let mut keep = budget.saturating_sub(marker);
+  loop {
+    let (head_budget, tail_budget) = match log {
+      true => {
+        let head = keep * LOG_HEAD_PERCENT / (LOG_HEAD_PERCENT + LOG_TAIL_PERCENT);
+        (head, keep - head)
+      }
+      false => (keep, 0),
+    };
+    let head = chars_within(text, head_budget, false);
+    let rest = &text[byte_of(text, head)..];
+    let tail = match tail_budget {
+      0 => 0,
+      budget => chars_within(rest, budget, true),
+    };
+    let mut out = text[..byte_of(text, head)].to_string();
+    out.push('\n');
+    out.push_str(&omitted_chars_marker(total - head - tail));
+    if tail > 0 {
+      out.push('\n');
+      out.push_str(&rest[byte_of(rest, rest.chars().count() - tail)..]);
+    }
+    // 拼接处的切分可能与分开计数略有不同
+    if tokens::count(&out) <= budget || keep == 0 {
+      return out;
+    }
+    keep -= 1;
+  }
+}
+
+/// 与 truncate 相同，但保证结果在预算内：按行截断放不下（例如只有一行）时按字符截断
+pub fn fit_text(text: &str, budget: usize, mode: TruncateMode) -> String {
+  if tokens::count(text) <= budget {
+    return text.to_string();
+  }
+  if text.lines().nth(1).is_some() {
+    let by_lines = truncate(text, budget, mode);
+    if tokens::count(&by_lines) <= budget && by_lines.lines().count() > 1 {
+      return by_lines;
+    }
+  }
+  cut_chars(text, budget, is_log(text, mode))
+}
+
+/// fit_messages 截断了哪条消息，前后的 token 估算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncation {
+  pub index: usize,
+  pub before: usize,
+  pub after: usize,
+}
+
+/// 省略标记本身也要占用预算，剩余预算比这更少时截断没有意义
+const MIN_FIT_BUDGET: usize = 16;
+
+/// 丢弃整条消息（摘要历史）之后仍然超出预算时的最后手段：最长的那条消息单独就超出了
+/// 其余消息剩下的预算（例如粘贴的大段日志），就截断它的文本使请求还能发送。
+/// 其余消息和图片保持不变；多模态消息截断其中最长的文本部分
+pub fn fit_messages(
+  messages: &mut [Message],
+  budget: usize,
+  mode: TruncateMode,
+) -> Option<Truncation> {
+  let sizes: Vec<usize> = messages.iter().map(tokens::count_message).collect();
+  let total: usize = sizes.iter().sum();
+  if total <= budget {
+    return None;
+  }
+  let (index, &before) = sizes
+    .iter()
+    .enumerate()
+    .max_by_key(|&(i, size)| (size, i))?;
+  let remaining = budget.checked_sub(total - before)?;
+  // 要截断的文本，以及同一条消息中其余文本的估算
+  let (text, others) = match &mut messages[index] {
+    Message::Simple { content, .. } | Message::Tool { content, .. } => (content, 0),
+    Message::MultiModal { content, .. } => {
+      let text = content
+        .iter_mut()
+        .filter_map(|c| match c {
+          Content::Text(t) => Some(&mut t.text),
+          Content::Image(_) => None,
+        })
+        .max_by_key(|text| tokens::count(text))?;
+      let others = before - tokens::count(text);
+      (text, others)
+    }
+  };
+  let remaining = remaining.checked_sub(others)?;
+  if remaining < MIN_FIT_BUDGET {
+    return None;
+  }
+  *text = fit_text(text, remaining, mode);
+  Some(Truncation {
+    index,
+    before,
+    after: others + tokens::count(text),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn synthetic_log(lines: usize) -> String {
+    (0..lines)
+      .map(|i| {
+        if i == lines - 1 {
+          "2026-10-14 12:00:59 ERROR panicked at src/main.rs:42: index out of bounds".to_string()
+        } else {
+          format!(
+            "2026-10-14 12:00:{:02} INFO worker {} processed batch {}",
+            i % 60,
+            i % 4,
+            i
+          )
+        }
+      })
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  #[test]
+  fn test_looks_like_log() {
+    let cases: &[(&str, bool)] = &[
+      (&synthetic_log(50), true),
+      (&synthetic_log(5), false),
+      (&"GET /index.html 200\n".repeat(30), true),
+      (
+        &[
+          "所有权决定了值何时被释放。",
+          "借用检查器在编译期工作。",
+          "生命周期标注描述引用的有效范围。",
+          "迭代器是惰性的。",
+          "模式匹配必须穷尽所有情况。",
+        ]
+        .repeat(8)
+        .join("\n"),
+        false,
+      ),
+      (
+        &(0..40)
+          .map(|i| format!("[{}] WARN retrying connection", i))
+          .collect::<Vec<_>>()
+          .join("\n"),
+        true,
+      ),
+    ];
+    for (text, expected) in cases {
+      let preview: String = text.chars().take(40).collect();
+      assert_eq!(looks_like_log(text), *expected, "{}", preview);
+    }
+  }
+
+  #[test]
+  fn test_within_budget_is_untouched() {
+    let log = synthetic_log(10);
+    assert_eq!(truncate(&log, 10_000, TruncateMode::Log), log);
+    assert_eq!(truncate(&log, 10_000, TruncateMode::Head), log);
+  }
+
+  #[test]
+  fn test_log_keeps_head_and_tail() {
+    let log = synthetic_log(1000);
+    let budget = 1000;
+    let out = truncate(&log, budget, TruncateMode::Auto);
+    assert!(tokens::count(&out) <= budget);
+    // 结尾的错误和开头的启动信息都在
+    assert!(out.ends_with("index out of bounds"));
+    assert!(out.starts_with("2026-10-14 12:00:00 INFO worker 0 processed batch 0"));
+    let marker = out.lines().find(|l| l.starts_with("[... 省略")).unwrap();
+    let kept = out.lines().count() - 1;
+    assert_eq!(marker, omitted_marker(1000 - kept));
+    // 尾部保留的行比头部多
+    let pos = out.lines().position(|l| l == marker).unwrap();
+    assert!(kept - pos > pos);
+    // 只在行边界截断
+    for line in out.lines().filter(|l| *l != marker) {
+      assert!(log.lines().any(|orig| orig == line), "{}", line);
+    }
+  }
+
+  #[test]
+  fn test_head_mode_for_non_logs() {
+    let sentences = [
+      "The borrow checker enforces aliasing rules at compile time.",
+      "Lifetimes describe how long references stay valid.",
+      "Ownership moves by default when values are assigned.",
+      "Traits define shared behaviour across types.",
+      "Iterators are lazy until consumed.",
+      "Pattern matching must be exhaustive.",
+      "Macros operate on token trees.",
+    ];
+    let text = (0..400)
+      .map(|i| sentences[i % sentences.len()])
+      .collect::<Vec<_>>()
+      .join("\n");
+    let out = truncate(&text, 200, TruncateMode::Auto);
+    assert!(out.starts_with("The borrow checker"));
+    assert!(out.lines().last().unwrap().starts_with("[... 省略"));
+    assert!(tokens::count(&out) <= 200 + 10);
+
+    // 即使看起来像日志，head 模式也只保留开头
+    let out = truncate(&synthetic_log(500), 200, TruncateMode::Head);
+    assert!(!out.contains("ERROR"));
+  }
+
+  #[test]
+  fn test_forced_log_mode_with_giant_last_line() {
+    let text = format!(
+      "start\n{}\n{}END",
+      "middle\n".repeat(10),
+      "x".repeat(10_000)
+    );
+    let out = truncate(&text, 100, TruncateMode::Log);
+    assert!(out.ends_with("END"));
+    assert!(out.starts_with("start"));
+    assert!(tokens::count(&out) <= 100);
+  }
+
+  #[test]
+  fn test_parse_mode() {
+    assert_eq!("log".parse::<TruncateMode>(), Ok(TruncateMode::Log));
+    assert_eq!("head".parse::<TruncateMode>(), Ok(TruncateMode::Head));
+    assert_eq!("auto".parse::<TruncateMode>(), Ok(TruncateMode::Auto));
+    assert!("tail".parse::<TruncateMode>().is_err());
+  }
+
+  fn simple(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  fn text_of(message: &Message) -> &str {
+    match message {
+      Message::Simple { content, .. } => content,
+      _ => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn test_fit_text_at_the_budget_boundary() {
+    let budget = 100;
+    // 每个 " a" 是一个 token：正好 100 tokens
+    let exact = format!("a{}", " a".repeat(99));
+    assert_eq!(tokens::count(&exact), budget);
+    assert_eq!(fit_text(&exact, budget, TruncateMode::Auto), exact);
+    // 多一个 token 就要截断，且结果在预算内
+    let over = format!("{} a", exact);
+    assert_eq!(tokens::count(&over), budget + 1);
+    let out = fit_text(&over, budget, TruncateMode::Auto);
+    assert!(tokens::count(&out) <= budget, "{}", out);
+    assert!(
+      out.starts_with("a a a") && out.contains("[... 省略 "),
+      "{}",
+      out
+    );
+  }
+
+  #[test]
+  fn test_fit_text_cuts_on_char_boundaries() {
+    // 单行的多字节文本：按行截断放不下，按字符截断不会切开字符
+    let text = "日志🚀é".repeat(500);
+    for mode in [TruncateMode::Head, TruncateMode::Log] {
+      let out = fit_text(&text, 50, mode);
+      assert!(tokens::count(&out) <= 50, "{}", out);
+      let head = out.lines().next().unwrap();
+      assert!(!head.is_empty() && text.starts_with(head), "{}", out);
+      if mode == TruncateMode::Log {
+        let tail = out.lines().last().unwrap();
+        assert!(!tail.is_empty() && text.ends_with(tail), "{}", out);
+      }
+    }
+    // 多行日志仍然按行截断，保留结尾的错误
+    let log = synthetic_log(1000);
+    let out = fit_text(&log, 300, TruncateMode::Auto);
+    assert!(out.ends_with("index out of bounds"));
+    assert!(tokens::count(&out) <= 300);
+  }
+
+  #[test]
+  fn test_fit_messages_cuts_the_oversized_message() {
+    let log = synthetic_log(2000);
+    let mut messages = vec![
+      simple("user", "之前的问题"),
+      simple("assistant", "之前的回答"),
+      simple("user", &log),
+    ];
+    let cut = fit_messages(&mut messages, 500, TruncateMode::Auto).unwrap();
+    assert_eq!(cut.index, 2);
+    assert_eq!(cut.before, tokens::count(&log));
+    let total: usize = messages.iter().map(tokens::count_message).sum();
+    assert!(total <= 500 && cut.after <= 500, "{} {:?}", total, cut);
+    // 其余消息不变，被截断的消息保留首尾
+    assert_eq!(text_of(&messages[0]), "之前的问题");
+    assert!(text_of(&messages[2]).ends_with("index out of bounds"));
+
+    // 在预算内时什么都不做
+    let mut messages = vec![simple("user", "hi")];
+    assert_eq!(fit_messages(&mut messages, 500, TruncateMode::Auto), None);
+    // 其余消息已经占满预算时无法截断
+    let mut messages = vec![simple("user", &" x".repeat(400)), simple("user", &log)];
+    assert_eq!(fit_messages(&mut messages, 400, TruncateMode::Auto), None);
+  }
+}
@@ -0,0 +1,2223 @@
+use crate::api::{
+  ApiError, CONTENT_FILTER, ChatBackend, ChunkStream, IdleDisconnect, Message, ToolCallAccumulator,
+  Usage,
+};
+use crate::attachment::AttachmentStore;
+use crate::cite::{self, StreamHighlighter};
+use crate::contract::{Checks, OutputFilter};
+use crate::markdown::MarkdownStream;
+use crate::math::MathStream;
+use crate::plugin::Plugins;
+use crate::prompt::{self, SystemPrompt};
+use crate::reasoning::{Reasoning, ReasoningStream};
+use crate::recovery::InProgress;
+use crate::staging::Staged;
+use crate::stats::SessionStats;
+use crate::steer::{self, Steer};
+use crate::stop::{StopMatcher, StopPatterns};
+use crate::widget::{AccessibleStream, UiMode};
+use crate::{estimate_messages_tokens, print_green_prompt, tokens};
+use anyhow::Result;
+use futures_util::StreamExt;
+use std::cell::RefCell;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+pub const MAX_AUTO_CONTINUE: usize = 5;
+
+/// 可见回答少于这么多字符时视为“几乎没有回答”
+const NEAR_EMPTY_ANSWER: usize = 16;
+
+/// 自动续写时追加的用户消息
+pub const CONTINUE_PROMPT: &str = "请继续";
+
+/// 整轮超时后保留的部分回答末尾追加的标记
+pub const TIMEOUT_MARKER: &str = "[超时截断]";
+
+/// 用户中断且没有给出纠偏说明时，部分回答末尾追加的标记
+pub const INTERRUPT_MARKER: &str = "[已中断]";
+
+/// 流式输出中途出错时，部分回答末尾追加的标记
+fn stream_error_marker(error: &str) -> String {
+  format!("[连接中断: {}]", error)
+}
+
+/// 超出 --max-words 后停止接收并追加的标记
+pub const WORD_LIMIT_MARKER: &str = "……[字数截断]";
+
+/// 推理耗尽上限、没有任何回答时保存的 assistant 消息。
+/// 历史中每个问题后都要有回答：推理模型不接受连续两条用户消息
+pub const REASONING_ONLY_MARKER: &str = "[只有推理过程，没有回答]";
+
+/// 用 keep_refusals 保留被内容审核拦截的一轮时，回答末尾追加的标记
+pub const REFUSAL_MARKER: &str = "[内容审核拦截]";
+
+/// 一轮中最多执行这么多次工具调用往返，防止模型反复调用
+pub const MAX_TOOL_ROUNDS: usize = 8;
+
+pub struct TurnSettings<'a> {
+  pub model: &'a str,
+  pub system_prompt: &'a SystemPrompt,
+  pub temperature: Option<f32>,
+  pub max_tokens: u32,
+  /// 模型允许的输出上限，重试时提高 max_tokens 不会超过它
+  pub model_max_tokens: u32,
+  /// 整轮（含重试与自动续写）的总时长上限，不含中断后等待纠偏说明的时间
+  pub timeout: Option<Duration>,
+  /// 用于展开历史中的附件引用
+  pub attachments: Option<&'a AttachmentStore>,
+  /// 高亮回答中的 file:line 引用（仅在终端输出时开启）
+  pub highlight_citations: bool,
+  /// 把回答中的 LaTeX 公式渲染为 Unicode（仅在终端输出时开启）
+  pub render_math: bool,
+  /// 把回答的 Markdown 渲染为终端样式（--render，可用 \render 切换）
+  pub render_markdown: bool,
+  /// 可见回答的字数上限（中日韩字符逐字计数），超出宽限后截断
+  pub max_words: Option<usize>,
+  /// --stop-regex：回答匹配后在本地停止，去掉匹配的部分
+  pub stop: Option<&'a StopPatterns>,
+  /// 输出中途中断并注入纠偏说明
+  pub steer: Option<Steer<'a>>,
+  /// 输出约定中可以在本地执行的检查
+  pub checks: Checks,
+  /// 无障碍模式下按整行显示，并改写代码块和表格
+  pub ui: UiMode,
+  /// 保存回答的推理过程；推理过程不写入 history
+  pub reasoning: Option<&'a RefCell<Reasoning>>,
+  /// 流式显示 deepseek-r1 的推理过程（--hide-reasoning 或 \reasoning off 时关闭）
+  pub show_reasoning: bool,
+  /// 执行模型调用的插件；请求中的 tools 由客户端附加
+  pub tools: Option<&'a Plugins>,
+  /// 把进行中的回答写入快照目录，终端意外关闭后可以找回
+  pub recovery: Option<&'a RefCell<InProgress>>,
+  /// 被内容审核拦截的一轮带上 REFUSAL_MARKER 保留在历史中；默认连同问题一起撤销
+  pub keep_refusals: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnEnd {
+  Done,
+  TimedOut,
+  /// 达到 --max-words 后在本地截断
+  WordLimited,
+  /// 回答匹配了 --stop-regex，在本地停止
+  Stopped,
+  /// 用户中断了输出且没有纠偏
+  Interrupted,
+  /// 回答中途出错，部分回答带着标记保存，不自动续写
+  StreamFailed,
+  /// 请求失败，本轮的所有改动连同问题都已撤销，可以重新发送
+  RolledBack,
+  /// 服务商的内容审核拦截了问题或回答；没有 keep_refusals 时本轮已撤销
+  Filtered,
+}
+
+/// 流式字数统计。连续的字母数字算一个词，中日韩字符每个算一个；
+/// 状态跨分片保留，被拆开的词只计一次
+#[derive(Debug)]
+pub struct WordCounter {
+  count: usize,
+  in_word: bool,
+  /// 超过这个数时截断：上限加上一点宽限，避免在最后一句中间硬切
+  threshold: usize,
+}
+
+impl WordCounter {
+  pub fn new(limit: usize) -> Self {
+    Self {
+      count: 0,
+      in_word: false,
+      threshold: limit + (limit / 10).max(5),
+    }
+  }
+
+  /// 计入一个分片；超出阈值时返回截断位置（超出的那个词开始处的字节偏移）
+  pub fn push(&mut self, chunk: &str) -> Option<usize> {
+    for (i, c) in chunk.char_indices() {
+      let starts_word = if tokens::is_cjk(c) {
+        self.in_word = false;
+        true
+      } else if c.is_alphanumeric() || (self.in_word && matches!(c, '\'' | '_')) {
+        !std::mem::replace(&mut self.in_word, true)
+      } else {
+        self.in_word = false;
+        false
+      };
+      if starts_word {
+        self.count += 1;
+        if self.count > self.threshold {
+          return Some(i);
+        }
+      }
+    }
+    None
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Next {
+  Stop,
+  Continue,
+  /// 丢弃本次结果，用更大的 max_tokens 重发同一请求
+  Retry {
+    max_tokens: u32,
+  },
+  GiveUp,
+  /// 被内容审核拦截，续写只会再次触发拦截
+  Refused,
+}
+
+/// r1 在 finish_reason 为 length 时常常是推理耗尽了预算、回答为空；
+/// 这时“请继续”只会开始新一轮推理，还是没有回答
+pub fn reasoning_starved(finish_reason: Option<&str>, content: &str, reasoning: &str) -> bool {
+  finish_reason == Some("length")
+    && content.trim().chars().count() < NEAR_EMPTY_ANSWER
+    && !reasoning.trim().is_empty()
+}
+
+/// 完成 token 数达到 max_tokens 的这个比例时视为被截断
+const TRUNCATED_PERCENT: usize = 98;
+
+/// 回复结束在未闭合的代码块里，一定是被截断了
+pub fn in_open_fence(reply: &str) -> bool {
+  reply
+    .lines()
+    .filter(|l| {
+      let l = l.trim_start();
+      l.starts_with("```") || l.starts_with("~~~")
+    })
+    .count()
+    % 2
+    == 1
+}
+
+/// 没有 finish_reason 时判断回复是否被截断。主要依据完成 token 数是否接近 max_tokens：
+/// `usage` 为接口返回的完成 token 数，没有时用本地估算（含推理），
+/// 并且只在这种情况下退回保守的标点检查——只认明显停在句中的逗号、顿号和冒号。
+pub fn looks_truncated(
+  reply: &str,
+  reasoning: &str,
+  usage: Option<usize>,
+  max_tokens: u32,
+) -> bool {
+  if in_open_fence(reply) {
+    return true;
+  }
+  let used = usage.unwrap_or_else(|| tokens::count(reply) + tokens::count(reasoning));
+  if used * 100 >= max_tokens as usize * TRUNCATED_PERCENT {
+    return true;
+  }
+  if usage.is_some() {
+    return false;
+  }
+  let trimmed = reply.trim_end();
+  ["，", "、", "：", "（", ","]
+    .iter()
+    .any(|p| trimmed.ends_with(p))
+}
+
+/// 一轮对话内的续写决策：普通截断最多自动续写 MAX_AUTO_CONTINUE 次，
+/// 推理耗尽预算只重试一次。`completion` 为本次请求报告的完成 token 数
+#[derive(Debug, Default)]
+pub struct ContinuePolicy {
+  continues: usize,
+  retried: bool,
+}
+
+impl ContinuePolicy {
+  pub fn next(
+    &mut self,
+    finish_reason: Option<&str>,
+    content: &str,
+    reasoning: &str,
+    completion: Option<usize>,
+    settings: &TurnSettings,
+    max_tokens: u32,
+  ) -> Next {
+    if finish_reason == Some(CONTENT_FILTER) {
+      return Next::Refused;
+    }
+    if reasoning_starved(finish_reason, content, reasoning) {
+      let raised = max_tokens.saturating_mul(2).min(settings.model_max_tokens);
+      if self.retried || raised <= max_tokens {
+        return Next::GiveUp;
+      }
+      self.retried = true;
+      return Next::Retry { max_tokens: raised };
+    }
+    let truncated = match finish_reason {
+      Some(reason) => reason == "length",
+      None => looks_truncated(content, reasoning, completion, max_tokens),
+    };
+    if truncated && self.continues < MAX_AUTO_CONTINUE {
+      self.continues += 1;
+      return Next::Continue;
+    }
+    Next::Stop
+  }
+}
+
+/// 发送一轮对话（含自动续写与重试），把回答写入 `out` 并追加到 `history`。
+/// 整轮完成（或保留部分回答的超时、中断、字数截断）时才改动 `history`，
+/// 请求失败时撤销本轮，见 Staged。
+/// 推理过程不显示，只用于判断是否耗尽了预算。
+///
+/// 超时计时只覆盖网络等待，从进入本函数开始跨越所有内部请求；
+/// 超时后保留已收到的回答并标记 TIMEOUT_MARKER。
+pub async fn run_turn<B: ChatBackend>(
+  backend: &B,
+  settings: &TurnSettings<'_>,
+  history: &mut Vec<Message>,
+  stats: &mut SessionStats,
+  out: &mut impl Write,
+) -> Result<TurnEnd> {
+  let end = stream_turn(backend, settings, history, stats, out).await;
+  // 输出失败时保留进行中的回答，与进程被杀死相同
+  if end.is_ok()
+    && let Some(recovery) = settings.recovery
+  {
+    recovery.borrow_mut().clear();
+  }
+  end
+}
+
+async fn stream_turn<B: ChatBackend>(
+  backend: &B,
+  settings: &TurnSettings<'_>,
+  history: &mut Vec<Message>,
+  stats: &mut SessionStats,
+  out: &mut impl Write,
+) -> Result<TurnEnd> {
+  let mut policy = ContinuePolicy::default();
+  let mut max_tokens = settings.max_tokens;
+  stats.start_exchange();
+  let mut deadline = settings.timeout.map(|t| tokio::time::Instant::now() + t);
+  // 自动续写的内容也计入同一个上限
+  let mut words = settings.max_words.map(WordCounter::new);
+  let mut idle_retried = false;
+  // 中断后的下一个请求末尾附加部分回答和纠偏说明；回答完成后说明并入历史
+  let mut steering: Option<([Message; 2], String)> = None;
+  // 本轮的工具调用与结果，附加到后续请求；历史中只保存最终回答
+  let mut tool_exchange: Vec<Message> = vec![];
+  let mut tool_rounds = 0;
+  let mut turn = Staged::begin(history);
+  loop {
+    let numbered = settings
+      .attachments
+      .is_some_and(AttachmentStore::has_numbered);
+    let prompt = match numbered {
+      true => settings
+        .system_prompt
+        .clone()
+        .with_volatile(cite::CITE_INSTRUCTION),
+      false => settings.system_prompt.clone(),
+    };
+    let mut messages = prompt.messages(turn.messages());
+    messages.extend(tool_exchange.iter().cloned());
+    if let Some((follow_up, _)) = &steering {
+      messages.extend(follow_up.iter().cloned());
+    }
+    if let Some(store) = settings.attachments {
+      messages = store.expand(&messages);
+    }
+    // 置顶附件的引用也是 system 消息
+    let folded = prompt.no_system && prompt::fold_system(&mut messages);
+    if folded || prompt.folds() {
+      prompt::warn_folded();
+    }
+
+    print_green_prompt(out);
+    out.flush()?;
+    let mut reply = String::new();
+    let mut reasoning = String::new();
+    let mut highlighter =
+      (settings.highlight_citations && numbered).then(StreamHighlighter::default);
+    let mut math = settings.render_math.then(MathStream::default);
+    let mut filter = OutputFilter::new(settings.checks);
+    let mut stop = settings.stop.map(StopMatcher::new);
+    let mut accessible = settings.ui.is_accessible().then(AccessibleStream::default);
+    // 无障碍模式自己改写代码块和表格
+    let mut markdown = (settings.render_markdown && accessible.is_none())
+      .then(|| MarkdownStream::default().with_width(crate::widget::terminal_width()));
+    let mut thinking = settings
+      .show_reasoning
+      .then(|| ReasoningStream::new(!settings.ui.is_accessible()));
+    let resolve = |file: &str| {
+      let store = settings.attachments?;
+      Some(store.find(file)?.path.display().to_string())
+    };
+    let mut last_reason = None;
+    let mut completion = None;
+    let mut calls = ToolCallAccumulator::default();
+    let started = Instant::now();
+    let mut first_token = None;
+    let mut timed_out = false;
+    let mut word_limited = false;
+    let mut stop_matched = false;
+    let mut idle_disconnect = false;
+    // 这次请求什么也没有收到就出错了
+    let mut failed = false;
+    // 已经有输出后出错：保留部分回答，不当作正常结束
+    let mut stream_error = None;
+    let mut interrupted = false;
+    if let Some(steer) = settings.steer {
+      steer.interrupt.set_streaming(true);
+    }
+    if let Some(recovery) = settings.recovery {
+      recovery.borrow_mut().begin(turn.messages(), settings.model);
+    }
+    match until(
+      deadline,
+      backend.stream(
+        settings.model,
+        messages.clone(),
+        settings.temperature,
+        Some(max_tokens),
+      ),
+    )
+    .await
+    {
+      None => timed_out = true,
+      Some(Ok(mut stream)) => loop {
+        let next = match settings.steer {
+          Some(steer) => tokio::select! {
+            next = until(deadline, stream.next()) => next,
+            _ = steer.interrupt.wait() => {
+              // 丢弃流即取消请求
+              interrupted = true;
+              break;
+            }
+          },
+          None => until(deadline, stream.next()).await,
+        };
+        let Some(next) = next else {
+          timed_out = true;
+          break;
+        };
+        let Some(chunk) = next else {
+          break;
+        };
+        match chunk {
+          Ok(chunk) => {
+            if first_token.is_none() && !chunk.content.is_empty() {
+              first_token = Some(started.elapsed());
+            }
+            if let Some(t) = &mut thinking {
+              write!(out, "{}", t.push(&chunk.reasoning, &chunk.content))?;
+            }
+            let content = filter.push(&chunk.content);
+            let (content, matched) = match &mut stop {
+              Some(s) => s.push(&content),
+              None => (content, false),
+            };
+            let cut = words.as_mut().and_then(|w| w.push(&content));
+            let visible = &content[..cut.unwrap_or(content.len())];
+            // 公式渲染只影响显示，历史中保存原文
+            let shown = match &mut math {
+              Some(m) => m.push(visible),
+              None => visible.to_string(),
+            };
+            let shown = match &mut accessible {
+              Some(a) => a.push(&shown),
+              None => shown,
+            };
+            let shown = match &mut highlighter {
+              Some(h) => h.push(&shown, resolve),
+              None => shown,
+            };
+            match &mut markdown {
+              Some(m) => write!(out, "{}", m.push(&shown))?,
+              None => write!(out, "{}", shown)?,
+            }
+            out.flush()?;
+            reply.push_str(visible);
+            if let Some(recovery) = settings.recovery {
+              recovery.borrow_mut().push(visible);
+            }
+            reasoning.push_str(&chunk.reasoning);
+            if let Some(usage) = chunk.usage {
+              completion = Some(usage.completion_tokens);
+              stats.record_usage(usage);
+            }
+            for delta in &chunk.tool_calls {
+              calls.push(delta);
+            }
+            if cut.is_some() {
+              // 丢弃流即取消请求
+              word_limited = true;
+              break;
+            }
+            if matched {
+              stop_matched = true;
+              break;
+            }
+            if chunk.finish_reason.is_some() {
+              last_reason = chunk.finish_reason;
+            }
+          }
+          Err(e) => {
+            // 还没有任何输出时被空闲断开，没有丢失内容，从头重试一次
+            idle_disconnect = e.is::<IdleDisconnect>() && reply.is_empty() && !idle_retried;
+            failed = reply.is_empty() && calls.is_empty();
+            if !failed {
+              stream_error = Some(e.to_string());
+            }
+            eprintln!("[API流错误]: {:#}", e);
+            break;
+          }
+        }
+      },
+      Some(Err(e)) => {
+        if let Some(ApiError::ContentFilter { .. }) = e.downcast_ref::<ApiError>() {
+          return refuse(turn, "", "", settings, out);
+        }
+        writeln!(out, "[API错误]: {}", e)?;
+        if let Some(hint) = crate::api::hint(&e) {
+          writeln!(out, "[提示] {}", hint)?;
+        }
+        turn.rollback();
+        return Ok(TurnEnd::RolledBack);
+      }
+    }
+    if let Some(steer) = settings.steer {
+      steer.interrupt.set_streaming(false);
+    }
+    // 过滤器还缓冲着的开头（整个回答只有几个字时），以及等待判断是否匹配停止模式的末尾
+    let held = filter.finish();
+    let held = match &mut stop {
+      Some(s) if !word_limited => {
+        let (held, matched) = s.push(&held);
+        stop_matched |= matched;
+        held + &s.finish()
+      }
+      _ => held,
+    };
+    reply.push_str(&held);
+    let rest = match &mut math {
+      Some(m) => m.push(&held) + &m.finish(),
+      None => held,
+    };
+    let rest = match &mut accessible {
+      Some(a) => a.push(&rest) + &a.finish(),
+      None => rest,
+    };
+    let rest = match &mut highlighter {
+      Some(h) => h.push(&rest, resolve) + &h.finish(resolve),
+      None => rest,
+    };
+    match &mut markdown {
+      Some(m) => write!(out, "{}{}", m.push(&rest), m.finish())?,
+      None => write!(out, "{}", rest)?,
+    }
+    if idle_disconnect {
+      idle_retried = true;
+      eprintln!("[信息] 连接在模型输出前被断开，可能是代理的空闲超时或缓冲设置，正在重试");
+      continue;
+    }
+    if failed {
+      writeln!(out)?;
+      turn.rollback();
+      return Ok(TurnEnd::RolledBack);
+    }
+    if timed_out {
+      writeln!(out, "\n{}", TIMEOUT_MARKER)?;
+    } else if let Some(error) = &stream_error {
+      writeln!(out, "\n{}", stream_error_marker(error))?;
+    } else if interrupted {
+      writeln!(out, "\n{}", INTERRUPT_MARKER)?;
+    } else if word_limited {
+      writeln!(out, "{}", WORD_LIMIT_MARKER)?;
+    } else {
+      writeln!(out, " ")?;
+    }
+    stats.record_request(
+      estimate_messages_tokens(&messages),
+      tokens::count(&reply) + tokens::count(&reasoning),
+      first_token,
+      started.elapsed(),
+    );
+    if let Some(steer) = settings.steer.filter(|_| interrupted) {
+      // 部分回答只留在屏幕上，连同说明一起附加到下一个请求
+      // 等待用户输入纠偏说明的时间不计入本轮的时限
+      let asked = tokio::time::Instant::now();
+      let note = (steer.ask)().filter(|note| !note.trim().is_empty());
+      deadline = deadline.map(|d| d + asked.elapsed());
+      match note {
+        Some(note) => {
+          let note = note.trim().to_string();
+          steering = Some((steer::follow_up(&reply, &note), note));
+          continue;
+        }
+        None => {
+          turn.push(Message::Simple {
+            role: "assistant".to_string(),
+            content: if reply.is_empty() {
+              INTERRUPT_MARKER.to_string()
+            } else {
+              format!("{}\n{}", reply, INTERRUPT_MARKER)
+            },
+            name: None,
+          });
+          turn.keep_reasoning(&reasoning);
+          turn.commit(settings.reasoning);
+          return Ok(TurnEnd::Interrupted);
+        }
+      }
+    }
+    if let Some((_, note)) = steering.take() {
+      turn.merge_note(&note);
+    }
+    if timed_out {
+      turn.push(Message::Simple {
+        role: "assistant".to_string(),
+        content: if reply.is_empty() {
+          TIMEOUT_MARKER.to_string()
+        } else {
+          format!("{}\n{}", reply, TIMEOUT_MARKER)
+        },
+        name: None,
+      });
+      turn.keep_reasoning(&reasoning);
+      turn.commit(settings.reasoning);
+      return Ok(TurnEnd::TimedOut);
+    }
+    if let Some(error) = stream_error {
+      // 不知道回答是否完整，不自动续写
+      turn.push(Message::Simple {
+        role: "assistant".to_string(),
+        content: format!("{}\n{}", reply, stream_error_marker(&error)),
+        name: None,
+      });
+      turn.keep_reasoning(&reasoning);
+      turn.commit(settings.reasoning);
+      return Ok(TurnEnd::StreamFailed);
+    }
+    if word_limited {
+      // 本地截断，不触发自动续写
+      turn.push(Message::Simple {
+        role: "assistant".to_string(),
+        content: format!("{}{}", reply.trim_end(), WORD_LIMIT_MARKER),
+        name: None,
+      });
+      turn.keep_reasoning(&reasoning);
+      turn.commit(settings.reasoning);
+      return Ok(TurnEnd::WordLimited);
+    }
+    if stop_matched {
+      // 匹配的部分是不想要的内容，去掉后不留标记，也不续写
+      turn.push(Message::Simple {
+        role: "assistant".to_string(),
+        content: reply.trim_end().to_string(),
+        name: None,
+      });
+      turn.keep_reasoning(&reasoning);
+      turn.commit(settings.reasoning);
+      return Ok(TurnEnd::Stopped);
+    }
+    if let Some(tools) = settings.tools.filter(|_| !calls.is_empty()) {
+      if tool_rounds < MAX_TOOL_ROUNDS {
+        tool_rounds += 1;
+        let calls = calls.finish();
+        tool_exchange.push(Message::Tool {
+          role: "assistant".to_string(),
+          content: reply,
+          tool_calls: calls.clone(),
+          tool_call_id: None,
+        });
+        for call in calls {
+          writeln!(
+            out,
+            "[工具] {}({})",
+            call.function.name, call.function.arguments
+          )?;
+          out.flush()?;
+          let result = tools
+            .call(&call.function.name, &call.function.arguments)
+            .await;
+          tool_exchange.push(Message::Tool {
+            role: "tool".to_string(),
+            content: result,
+            tool_calls: vec![],
+            tool_call_id: Some(call.id),
+          });
+        }
+        continue;
+      }
+      eprintln!("[提示] 本轮工具调用已达 {} 次，不再执行", MAX_TOOL_ROUNDS);
+    }
+
+    match policy.next(
+      last_reason.as_deref(),
+      &reply,
+      &reasoning,
+      completion,
+      settings,
+      max_tokens,
+    ) {
+      Next::Retry { max_tokens: raised } => {
+        eprintln!(
+          "[信息] 推理耗尽了 max_tokens（{}），没有产生回答，提高到 {} 重试",
+          max_tokens, raised
+        );
+        max_tokens = raised;
+      }
+      Next::Refused => return refuse(turn, &reply, &reasoning, settings, out),
+      Next::GiveUp => {
+        turn.push(Message::Simple {
+          role: "assistant".to_string(),
+          content: match reply.trim().is_empty() {
+            true => REASONING_ONLY_MARKER.to_string(),
+            false => reply,
+          },
+          name: None,
+        });
+        turn.keep_reasoning(&reasoning);
+        eprintln!(
+          "[提示] 推理耗尽了 max_tokens（{}），仍然没有回答。可以用 -l 提高上限，或换用 -m chat",
+          max_tokens
+        );
+        turn.commit(settings.reasoning);
+        return Ok(TurnEnd::Done);
+      }
+      Next::Continue => {
+        stats.auto_continues += 1;
+        // 续写请求中工具结果会排在续写提示之后，顺序不再成立；被截断的回答已经用过它们
+        tool_exchange.clear();
+        turn.push(Message::Simple {
+          role: "assistant".to_string(),
+          content: reply,
+          name: None,
+        });
+        turn.keep_reasoning(&reasoning);
+        turn.push(Message::Simple {
+          role: "user".to_string(),
+          content: CONTINUE_PROMPT.to_string(),
+          name: None,
+        });
+      }
+      Next::Stop => {
+        turn.push(Message::Simple {
+          role: "assistant".to_string(),
+          content: reply,
+          name: None,
+        });
+        turn.keep_reasoning(&reasoning);
+        turn.commit(settings.reasoning);
+        return Ok(TurnEnd::Done);
+      }
+    }
+  }
+}
+
+/// 内容审核拦截了这一轮：显示提示，不再续写。默认连同问题撤销本轮，
+/// 免得之后的每个请求都带着被拦截的内容
+fn refuse(
+  mut turn: Staged,
+  reply: &str,
+  reasoning: &str,
+  settings: &TurnSettings,
+  out: &mut impl Write,
+) -> Result<TurnEnd> {
+  if !settings.keep_refusals {
+    writeln!(
+      out,
+      "[内容过滤] 服务商的内容审核拦截了这一轮，已撤销（历史中不保留这个问题），原样重发不会成功，请换个说法"
+    )?;
+    turn.rollback();
+    return Ok(TurnEnd::Filtered);
+  }
+  writeln!(
+    out,
+    "[内容过滤] 服务商的内容审核拦截了这一轮，已标记为 {} 保留在历史中",
+    REFUSAL_MARKER
+  )?;
+  turn.push(Message::Simple {
+    role: "assistant".to_string(),
+    content: match reply.is_empty() {
+      true => REFUSAL_MARKER.to_string(),
+      false => format!("{}\n{}", reply, REFUSAL_MARKER),
+    },
+    name: None,
+  });
+  turn.keep_reasoning(reasoning);
+  turn.commit(settings.reasoning);
+  Ok(TurnEnd::Filtered)
+}
+
+/// 单次查询的流式输出：收到的回答直接写入 `out`（`markdown` 时按行渲染），结束时补一个换行。
+/// 匹配 `stop` 后丢弃流，不再接收。
+/// 返回完整回答、finish_reason 和报告的用量；流中途出错时返回错误，已输出的部分保留
+pub async fn print_stream(
+  mut stream: ChunkStream,
+  out: &mut impl Write,
+  markdown: bool,
+  stop: Option<&StopPatterns>,
+  mut thinking: Option<ReasoningStream>,
+) -> Result<(String, Option<String>, Option<Usage>)> {
+  let mut renderer = markdown.then(MarkdownStream::default);
+  let mut stop = stop.map(StopMatcher::new);
+  let mut text = String::new();
+  let mut finish_reason = None;
+  let mut usage = None;
+  let result = loop {
+    // 流结束或出错时交出停止模式留着的末尾
+    let (content, end) = match stream.next().await {
+      Some(Ok(chunk)) => {
+        if chunk.finish_reason.is_some() {
+          finish_reason = chunk.finish_reason;
+        }
+        usage = chunk.usage.or(usage);
+        if let Some(t) = &mut thinking {
+          write!(out, "{}", t.push(&chunk.reasoning, &chunk.content))?;
+        }
+        match &mut stop {
+          Some(s) => match s.push(&chunk.content) {
+            (content, true) => (content, Some(Ok(()))),
+            (content, false) => (content, None),
+          },
+          None => (chunk.content, None),
+        }
+      }
+      Some(Err(e)) => (
+        stop.as_mut().map(StopMatcher::finish).unwrap_or_default(),
+        Some(Err(e)),
+      ),
+      None => (
+        stop.as_mut().map(StopMatcher::finish).unwrap_or_default(),
+        Some(Ok(())),
+      ),
+    };
+    match &mut renderer {
+      Some(m) => write!(out, "{}", m.push(&content))?,
+      None => write!(out, "{}", content)?,
+    }
+    out.flush()?;
+    text.push_str(&content);
+    if let Some(end) = end {
+      break end;
+    }
+  };
+  if let Some(t) = &mut thinking {
+    write!(out, "{}", t.finish())?;
+  }
+  match &mut renderer {
+    // 渲染后的每一行都以换行结束
+    Some(m) => write!(out, "{}", m.finish())?,
+    None if !text.is_empty() && !text.ends_with('\n') => writeln!(out)?,
+    None => {}
+  }
+  result.map(|()| (text, finish_reason, usage))
+}
+
+/// 在截止时间前等待 `future`，超时返回 None；没有截止时间时一直等待
+async fn until<F: std::future::Future>(
+  deadline: Option<tokio::time::Instant>,
+  future: F,
+) -> Option<F::Output> {
+  match deadline {
+    Some(deadline) => tokio::time::timeout_at(deadline, future).await.ok(),
+    None => Some(future.await),
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use crate::api::StreamChunk;
+  use crate::contract::Contract;
+  use crate::mock::ScriptedBackend;
+  use std::sync::LazyLock;
+
+  static PROMPT: LazyLock<SystemPrompt> =
+    LazyLock::new(|| SystemPrompt::new("You are a helpful assistant.", Default::default()));
+
+  pub(crate) fn settings() -> TurnSettings<'static> {
+    TurnSettings {
+      model: "deepseek-reasoner",
+      system_prompt: &PROMPT,
+      temperature: None,
+      max_tokens: 8192,
+      model_max_tokens: 65536,
+      timeout: None,
+      attachments: None,
+      highlight_citations: false,
+      render_math: false,
+      render_markdown: false,
+      max_words: None,
+      stop: None,
+      steer: None,
+      checks: Checks::default(),
+      ui: UiMode::Standard,
+      reasoning: None,
+      show_reasoning: false,
+      tools: None,
+      recovery: None,
+      keep_refusals: false,
+    }
+  }
+
+  fn chunk(content: &str, reasoning: &str, finish: Option<&str>) -> StreamChunk {
+    StreamChunk {
+      content: content.to_string(),
+      reasoning: reasoning.to_string(),
+      finish_reason: finish.map(|s| s.to_string()),
+      ..Default::default()
+    }
+  }
+
+  /// 推理写满预算、回答为空的响应
+  fn starved() -> Vec<StreamChunk> {
+    vec![
+      chunk("", "让我想想……", None),
+      chunk("", "还需要考虑更多情况", Some("length")),
+    ]
+  }
+
+  fn user(text: &str) -> Vec<Message> {
+    vec![Message::Simple {
+      role: "user".to_string(),
+      content: text.to_string(),
+      name: None,
+    }]
+  }
+
+  fn roles(history: &[Message]) -> Vec<&str> {
+    history
+      .iter()
+      .map(|m| match m {
+        Message::Simple { role, .. }
+        | Message::MultiModal { role, .. }
+        | Message::Tool { role, .. } => role.as_str(),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_reasoning_starved() {
+    assert!(reasoning_starved(Some("length"), "", "思考"));
+    assert!(reasoning_starved(Some("length"), " 好的 ", "思考"));
+    assert!(!reasoning_starved(Some("length"), "", ""));
+    assert!(!reasoning_starved(Some("stop"), "", "思考"));
+    assert!(!reasoning_starved(
+      Some("length"),
+      "这是一段已经足够长的可见回答内容，应该正常续写",
+      "思考"
+    ));
+  }
+
+  #[test]
+  fn test_policy_retries_once_then_gives_up() {
+    let s = settings();
+    let mut policy = ContinuePolicy::default();
+    assert_eq!(
+      policy.next(Some("length"), "", "思考", None, &s, 8192),
+      Next::Retry { max_tokens: 16384 }
+    );
+    assert_eq!(
+      policy.next(Some("length"), "", "思考", None, &s, 16384),
+      Next::GiveUp
+    );
+
+    // 已经是模型上限时不重试
+    let mut policy = ContinuePolicy::default();
+    assert_eq!(
+      policy.next(Some("length"), "", "思考", None, &s, 65536),
+      Next::GiveUp
+    );
+
+    // 普通截断照常续写，次数有上限
+    let mut policy = ContinuePolicy::default();
+    for _ in 0..MAX_AUTO_CONTINUE {
+      assert_eq!(
+        policy.next(Some("length"), "很长的回答，", "", None, &s, 8192),
+        Next::Continue
+      );
+    }
+    assert_eq!(
+      policy.next(Some("length"), "很长的回答，", "", None, &s, 8192),
+      Next::Stop
+    );
+    assert_eq!(
+      policy.next(None, "完整的回答。", "", None, &s, 8192),
+      Next::Stop
+    );
+    assert_eq!(policy.next(None, "未完，", "", None, &s, 8192), Next::Stop);
+
+    // 没有 finish_reason 时相信报告的完成 token 数，不看标点
+    let mut policy = ContinuePolicy::default();
+    assert_eq!(
+      policy.next(None, "未完，", "", Some(20), &s, 8192),
+      Next::Stop
+    );
+    assert_eq!(
+      policy.next(None, "未完，", "", None, &s, 8192),
+      Next::Continue
+    );
+    assert_eq!(
+      policy.next(None, "未完，", "", Some(8100), &s, 8192),
+      Next::Continue
+    );
+  }
+
+  #[test]
+  fn test_truncation_old_heuristic_false_positives() {
+    // 旧规则：超过 100 字符且不以 。！？ 结尾就算截断
+    let long_english = format!(
+      "{}This is the end of a normal English answer.",
+      "Some words. ".repeat(20)
+    );
+    let code_block = format!(
+      "示例如下：\n\n```rust\n{}fn main() {{}}\n```",
+      "// x\n".repeat(30)
+    );
+    let list = format!("步骤：\n{}", "- 做一件事情，然后检查结果\n".repeat(10));
+    for reply in [&long_english, &code_block, &list] {
+      assert!(reply.len() > 100);
+      assert!(!looks_truncated(reply, "", None, 8192), "{}", reply);
+      assert!(!looks_truncated(reply, "", Some(300), 8192), "{}", reply);
+    }
+  }
+
+  #[test]
+  fn test_truncation_old_heuristic_false_negatives() {
+    // 旧规则会放过：结尾恰好是句号，但 token 已经用满
+    let full = "这是一个完整的句子。".repeat(10);
+    assert!(looks_truncated(&full, "", Some(1000), 1000));
+    assert!(looks_truncated(&full, "", Some(985), 1000));
+    assert!(!looks_truncated(&full, "", Some(900), 1000));
+    // 本地估算也能发现：推理加回答用满了预算
+    assert!(looks_truncated(&full, &" x".repeat(1000), None, 1000));
+    // 停在未闭合的代码块里，即使最后一行以句号结尾
+    let open = "代码：\n```python\nprint('done')\n# 结束。";
+    assert!(in_open_fence(open));
+    assert!(looks_truncated(open, "", Some(10), 8192));
+    assert!(!in_open_fence("```\na\n```\n"));
+    assert!(in_open_fence("  ~~~\nb"));
+  }
+
+  #[test]
+  fn test_punctuation_fallback_only_without_usage() {
+    assert!(looks_truncated("第一点，", "", None, 8192));
+    assert!(looks_truncated("包括以下几项：", "", None, 8192));
+    // 有 usage 时相信 token 数
+    assert!(!looks_truncated("第一点，", "", Some(20), 8192));
+    // 不再把 - 和 ** 结尾当作截断
+    assert!(!looks_truncated("总结 -", "", None, 8192));
+    assert!(!looks_truncated("**重点**", "", None, 8192));
+  }
+
+  #[tokio::test]
+  async fn test_no_system_folds_sticky_attachments() {
+    let dir = crate::checkpoint::temp_dir("turn-no-system");
+    let path = dir.join("schema.sql");
+    std::fs::write(&path, "CREATE TABLE users (id INT);\n").unwrap();
+    let mut store = AttachmentStore::default();
+    store.attach_sticky(&path).unwrap();
+    let prompt = SystemPrompt::new(crate::prompt::DEFAULT_SYSTEM_PROMPT, Default::default())
+      .with_no_system(true);
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![chunk("好", "", Some("stop"))]);
+    let settings = TurnSettings {
+      system_prompt: &prompt,
+      attachments: Some(&store),
+      ..settings()
+    };
+    let mut history = user("这张表有几列？");
+    let mut stats = SessionStats::default();
+    run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut stats,
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+    let requests = backend.requests.lock().unwrap();
+    let sent = &requests[0].1;
+    assert_eq!(roles(sent), ["user"]);
+    assert!(contents(sent)[0].contains("CREATE TABLE users"));
+    assert!(contents(sent)[0].ends_with("这张表有几列？"));
+    std::fs::remove_dir_all(dir).ok();
+  }
+
+  #[tokio::test]
+  async fn test_reasoning_is_kept_out_of_history() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![
+      chunk("", "先想想所有权", None),
+      chunk("借用检查器", "", Some("stop")),
+    ]);
+    backend.push_stream(vec![chunk("好的", "", Some("stop"))]);
+    let reasoning = RefCell::new(Reasoning::default());
+    let settings = TurnSettings {
+      reasoning: Some(&reasoning),
+      show_reasoning: true,
+      ui: UiMode::Accessible,
+      ..settings()
+    };
+    let mut history = user("讲讲 Rust");
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    run_turn(&backend, &settings, &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+    history.extend(user("再讲讲生命周期"));
+    run_turn(&backend, &settings, &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    assert_eq!(contents(&history)[1], "借用检查器");
+    assert_eq!(reasoning.borrow().for_turn(1), Some("先想想所有权"));
+    assert_eq!(reasoning.borrow().for_turn(2), None);
+    // 第二个请求带着第一轮的回答，但没有它的推理过程
+    let requests = backend.requests.lock().unwrap();
+    let sent = serde_json::to_string(&requests[1]).unwrap();
+    assert!(sent.contains("借用检查器"));
+    assert!(!sent.contains("先想想所有权"), "{}", sent);
+    // 推理过程只在回答之前显示
+    let shown = String::from_utf8(out).unwrap();
+    assert!(
+      shown.contains("推理过程：\n先想想所有权\n\n回答：\n借用检查器"),
+      "{}",
+      shown
+    );
+  }
+
+  #[tokio::test]
+  async fn test_summary_keeps_code_blocks_verbatim_in_next_request() {
+    use crate::summary::RollingSummary;
+    let code = "```rust\nlet v = vec![1];\nlet w = v;\nprintln!(\"{:?}\", v);\n```";
+    let error = "error[E0382]: borrow of moved value: `v`";
+    let backend = std::sync::Arc::new(ScriptedBackend::new(["用户在排查 [代码片段 2] 的借用错误"]));
+    backend.push_stream(vec![chunk("用 clone", "", Some("stop"))]);
+    let mut history = vec![];
+    for i in 0..4 {
+      history.extend(user(&format!("第 {} 个问题\n{}\n{}", i, code, error)));
+      history.push(Message::Simple {
+        role: "assistant".to_string(),
+        content: format!("第 {} 个回答", i),
+        name: None,
+      });
+    }
+    let mut rolling = RollingSummary::default();
+    assert!(rolling.maybe_start(&backend, "deepseek-chat", &history, 800, 1000));
+    // 超过预算时等待后台摘要完成
+    assert!(rolling.apply_if_tight(&mut history, 1100, 1000).await);
+    history.extend(user("怎么修？"));
+    run_turn(
+      &*backend,
+      &settings(),
+      &mut history,
+      &mut SessionStats::default(),
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+
+    // 摘要请求里只有占位，之后的对话请求里片段逐字节保留
+    let requests = backend.requests.lock().unwrap();
+    let summarized = serde_json::to_string(&requests[0].1).unwrap();
+    assert!(!summarized.contains("let w = v;"), "{}", summarized);
+    let sent = serde_json::to_string(&requests[1].1).unwrap();
+    for snippet in [code, error] {
+      let escaped = serde_json::to_string(snippet).unwrap();
+      assert!(sent.contains(escaped.trim_matches('"')), "{}", sent);
+    }
+    assert!(contents(&history)[0].contains("保留的代码片段："));
+  }
+
+  #[tokio::test]
+  async fn test_starved_reasoning_gives_up_instead_of_looping() {
+    let backend = ScriptedBackend::default();
+    for _ in 0..6 {
+      backend.push_stream(starved());
+    }
+    let mut history = user("证明黎曼猜想");
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    run_turn(&backend, &settings(), &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    // 原样重试一次（不追加“请继续”），之后放弃
+    assert_eq!(backend.request_count(), 2);
+    assert_eq!(stats.auto_continues, 0);
+    assert_eq!(stats.requests, 2);
+    // 只有推理没有回答时也保存一条回答，下一个问题不会紧跟在这个问题之后
+    assert_eq!(roles(&history), ["user", "assistant"]);
+    assert_eq!(contents(&history)[1], REASONING_ONLY_MARKER);
+    let max_tokens = backend.max_tokens.lock().unwrap().clone();
+    assert_eq!(max_tokens, [Some(8192), Some(16384)]);
+  }
+
+  #[tokio::test]
+  async fn test_retry_with_larger_budget_recovers_answer() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(starved());
+    backend.push_stream(vec![
+      chunk("", "想清楚了", None),
+      chunk("答案是 42。", "", Some("stop")),
+    ]);
+    let mut history = user("问题");
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    run_turn(&backend, &settings(), &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    assert_eq!(roles(&history), ["user", "assistant"]);
+    let printed = String::from_utf8(out).unwrap();
+    assert!(printed.contains("答案是 42。"));
+    // 推理内容不显示
+    assert!(!printed.contains("想清楚了"));
+  }
+
+  #[tokio::test]
+  async fn test_citations_requested_and_linked() {
+    let dir = crate::checkpoint::temp_dir("turn-cite");
+    let path = dir.join("api.rs");
+    std::fs::write(&path, "fn main() {}\n").unwrap();
+    let mut store = AttachmentStore::default();
+    let (hash, _) = store.attach(&path).unwrap();
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![
+      chunk("入口在 api", "", None),
+      chunk(".rs:1 。", "", Some("stop")),
+    ]);
+    let mut history = user(&AttachmentStore::reference(&hash));
+    let settings = TurnSettings {
+      attachments: Some(&store),
+      highlight_citations: true,
+      ..settings()
+    };
+    let mut out = Vec::new();
+    run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    let (_, request) = backend.requests.lock().unwrap()[0].clone();
+    assert!(
+      matches!(request.last().unwrap(), Message::Simple { role, content, .. } if role == "system" && content == cite::CITE_INSTRUCTION)
+    );
+    let printed = String::from_utf8(out).unwrap();
+    assert!(printed.contains(&format!("\x1b]8;;file://{}", path.display())));
+    // 历史中保存的是原始回答
+    assert!(
+      matches!(&history[1], Message::Simple { content, .. } if content == "入口在 api.rs:1 。")
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+
+  fn counted(chunks: &[&str], limit: usize) -> (usize, Option<(usize, usize)>) {
+    let mut counter = WordCounter::new(limit);
+    for (i, chunk) in chunks.iter().enumerate() {
+      if let Some(cut) = counter.push(chunk) {
+        return (counter.count, Some((i, cut)));
+      }
+    }
+    (counter.count, None)
+  }
+
+  #[test]
+  fn test_word_counter_across_chunks() {
+    // 被拆开的词只计一次
+    assert_eq!(counted(&["hel", "lo wor", "ld, it's ", "fine"], 100).0, 4);
+    assert_eq!(counted(&["hello", " ", "world"], 100).0, 2);
+    // 中文逐字计数，与英文混排
+    assert_eq!(counted(&["你好", "，世界", "！"], 100).0, 4);
+    assert_eq!(counted(&["用 Rust", "写", "的 CLI 工具"], 100).0, 7);
+    assert_eq!(counted(&["", "  ", "—"], 100).0, 0);
+  }
+
+  #[test]
+  fn test_word_counter_cuts_after_grace() {
+    // 上限 10，宽限 5：第 16 个词开始处截断
+    let text: Vec<String> = (0..40).map(|i| format!("w{} ", i)).collect();
+    let chunks: Vec<&str> = text.iter().map(String::as_str).collect();
+    assert_eq!(counted(&chunks, 10), (16, Some((15, 0))));
+    let chinese = "一二三四五六七八九十".repeat(3);
+    let (count, cut) = counted(&[&chinese[..15], &chinese[15..]], 10);
+    assert_eq!(count, 16);
+    // 每个汉字 3 字节，第 16 个字在第二个分片的第 10 个字节
+    assert_eq!(cut, Some((1, 30)));
+  }
+
+  #[tokio::test]
+  async fn test_max_words_cuts_stream_without_continuing() {
+    let backend = ScriptedBackend::default();
+    let words: Vec<String> = (0..20).map(|i| format!("word{} ", i)).collect();
+    backend.push_stream(
+      words
+        .iter()
+        .map(|w| chunk(w, "", None))
+        .chain([chunk("", "", Some("length"))])
+        .collect(),
+    );
+    let mut history = user("长话短说");
+    let mut out = Vec::new();
+    let settings = TurnSettings {
+      max_words: Some(5),
+      ..settings()
+    };
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(end, TurnEnd::WordLimited);
+    assert_eq!(backend.request_count(), 1);
+    assert_eq!(roles(&history), ["user", "assistant"]);
+    let Message::Simple { content, .. } = &history[1] else {
+      panic!("expected a text reply");
+    };
+    assert!(content.starts_with("word0 word1"));
+    assert!(content.ends_with("word9……[字数截断]"));
+    assert!(!String::from_utf8(out).unwrap().contains("word10"));
+  }
+
+  #[tokio::test]
+  async fn test_math_is_rendered_on_screen_but_kept_raw_in_history() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![
+      chunk("面积是 $\\pi r", "", None),
+      chunk("^2$。", "", Some("stop")),
+    ]);
+    let mut history = user("圆的面积？");
+    let mut out = Vec::new();
+    let settings = TurnSettings {
+      render_math: true,
+      ..settings()
+    };
+    run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    assert!(String::from_utf8(out).unwrap().contains("面积是 π r²。"));
+    assert!(
+      matches!(&history[1], Message::Simple { content, .. } if content == "面积是 $\\pi r^2$。")
+    );
+  }
+
+  #[tokio::test]
+  async fn test_markdown_is_rendered_by_line_but_kept_raw_in_history() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![
+      chunk("## 结论\n用 **`cargo", "", None),
+      chunk(" test`** 验证", "", Some("stop")),
+    ]);
+    let mut history = user("怎么验证？");
+    let mut out = Vec::new();
+    let settings = TurnSettings {
+      render_markdown: true,
+      ..settings()
+    };
+    run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    let printed = crate::markdown::strip(&String::from_utf8(out).unwrap());
+    assert!(
+      printed.contains("结论\n用 cargo test 验证\n"),
+      "{:?}",
+      printed
+    );
+    assert!(
+      matches!(&history[1], Message::Simple { content, .. } if content == "## 结论\n用 **`cargo test`** 验证")
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_idle_disconnect_retries_once_from_scratch() {
+    use crate::mock::sse_content;
+    let backend = ScriptedBackend::default();
+    let stop = "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n";
+    backend.push_transport(vec![(Duration::from_secs(65), String::new())], None);
+    backend.push_transport(
+      vec![
+        (Duration::from_secs(5), sse_content("答案是 42。")),
+        (Duration::ZERO, stop.to_string()),
+      ],
+      None,
+    );
+    // 第二次仍然空闲断开时不再重试
+    backend.push_transport(vec![(Duration::from_secs(65), String::new())], None);
+    backend.push_transport(vec![(Duration::from_secs(65), String::new())], None);
+
+    let mut history = user("问题");
+    let mut out = Vec::new();
+    let mut stats = SessionStats::default();
+    run_turn(&backend, &settings(), &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+    assert_eq!(backend.request_count(), 2);
+    assert!(matches!(&history[1], Message::Simple { content, .. } if content == "答案是 42。"));
+
+    let mut history = user("问题");
+    run_turn(&backend, &settings(), &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+    assert_eq!(backend.request_count(), 4);
+  }
+
+  #[tokio::test]
+  async fn test_plain_truncation_still_auto_continues() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![chunk("第一部分，", "", Some("length"))]);
+    backend.push_stream(vec![chunk("第二部分。", "", Some("stop"))]);
+    let mut history = user("写一篇长文");
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    run_turn(&backend, &settings(), &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    assert_eq!(stats.auto_continues, 1);
+    assert_eq!(roles(&history), ["user", "assistant", "user", "assistant"]);
+  }
+
+  #[tokio::test]
+  async fn test_custom_system_prompt_in_every_continue_request() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![chunk("第一部分，", "", Some("length"))]);
+    backend.push_stream(vec![chunk("第二部分。", "", Some("stop"))]);
+    let prompt = SystemPrompt::new("You translate to French.", Default::default());
+    let settings = TurnSettings {
+      system_prompt: &prompt,
+      ..settings()
+    };
+    let mut history = user("翻译这篇长文");
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    run_turn(&backend, &settings, &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    let requests = backend.requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    for (_, messages) in requests.iter() {
+      let first = serde_json::to_value(&messages[0]).unwrap();
+      assert_eq!(first["role"], "system");
+      assert_eq!(first["content"], "You translate to French.");
+    }
+  }
+
+  /// 显示到某段文字时终端关闭
+  struct DyingTerminal;
+
+  impl Write for DyingTerminal {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      match String::from_utf8_lossy(buf).contains("第三段") {
+        true => Err(std::io::ErrorKind::BrokenPipe.into()),
+        false => Ok(buf.len()),
+      }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[tokio::test]
+  async fn test_in_progress_answer_survives_dead_terminal() {
+    let dir = crate::checkpoint::temp_dir("turn-recovery");
+    let path = dir.join(crate::recovery::FILE_NAME);
+    let recovery =
+      RefCell::new(InProgress::new(path.clone()).with_thresholds(Duration::from_secs(3600), 1));
+    let settings = TurnSettings {
+      recovery: Some(&recovery),
+      ..settings()
+    };
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![
+      chunk("第一段，", "", None),
+      chunk("第二段，🦀", "", None),
+      chunk("第三段", "", Some("stop")),
+    ]);
+    let mut history = user("问题");
+    let mut stats = SessionStats::default();
+    let result = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut stats,
+      &mut DyingTerminal,
+    )
+    .await;
+    assert!(result.is_err());
+    let recovered = crate::recovery::load(&path).unwrap().unwrap();
+    assert_eq!(recovered.partial, "第一段，第二段，🦀");
+    assert_eq!(
+      serde_json::to_value(&recovered.history[0]).unwrap()["content"],
+      "问题"
+    );
+
+    // 正常结束的一轮删除文件
+    backend.push_stream(vec![chunk("完整回答", "", Some("stop"))]);
+    let mut out = Vec::new();
+    run_turn(&backend, &settings, &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+    assert!(!path.exists());
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_turn_timeout_salvages_partial_answer() {
+    let backend = ScriptedBackend::default();
+    backend.push_stalled_stream(vec![chunk("部分回答", "", None)]);
+    let settings = TurnSettings {
+      timeout: Some(Duration::from_secs(180)),
+      ..settings()
+    };
+    let mut history = user("问题");
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    let started = tokio::time::Instant::now();
+    let end = run_turn(&backend, &settings, &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    assert_eq!(end, TurnEnd::TimedOut);
+    assert_eq!(started.elapsed(), Duration::from_secs(180));
+    let Message::Simple { content, .. } = &history[1] else {
+      panic!("expected a simple message");
+    };
+    assert_eq!(content, "部分回答\n[超时截断]");
+    assert!(String::from_utf8(out).unwrap().contains(TIMEOUT_MARKER));
+  }
+
+  #[tokio::test]
+  async fn test_stream_error_after_output_keeps_marked_partial_answer() {
+    let backend = ScriptedBackend::default();
+    // 没有 finish_reason 的部分回答原本会被当作截断而续写
+    backend.push_failing_stream(vec![chunk("部分回答", "", None)], "connection reset");
+    let mut history = user("问题");
+    let mut out = Vec::new();
+    let end = run_turn(
+      &backend,
+      &settings(),
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(end, TurnEnd::StreamFailed);
+    assert_eq!(backend.request_count(), 1);
+    assert_eq!(
+      contents(&history),
+      ["问题", "部分回答\n[连接中断: connection reset]"]
+    );
+    assert!(
+      String::from_utf8(out)
+        .unwrap()
+        .contains("[连接中断: connection reset]")
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_turn_timeout_spans_auto_continues() {
+    let backend = ScriptedBackend::default();
+    // 每次请求 100 秒，第二次续写期间到达 150 秒的总时限
+    for part in ["第一部分，", "第二部分，", "第三部分。"] {
+      backend.push_delayed_stream(
+        Duration::from_secs(100),
+        vec![chunk(part, "", Some("length"))],
+      );
+    }
+    let settings = TurnSettings {
+      timeout: Some(Duration::from_secs(150)),
+      ..settings()
+    };
+    let mut history = user("写长文");
+    let mut stats = SessionStats::default();
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut stats,
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(end, TurnEnd::TimedOut);
+    assert_eq!(backend.request_count(), 2);
+    assert_eq!(roles(&history), ["user", "assistant", "user", "assistant"]);
+    let Message::Simple { content, .. } = &history[3] else {
+      panic!("expected a simple message");
+    };
+    assert_eq!(content, TIMEOUT_MARKER);
+  }
+
+  fn contents(messages: &[Message]) -> Vec<&str> {
+    messages
+      .iter()
+      .map(|m| match m {
+        Message::Simple { content, .. } | Message::Tool { content, .. } => content.as_str(),
+        Message::MultiModal { .. } => "",
+      })
+      .collect()
+  }
+
+  /// 10 秒后（流式输出中途）按下 Ctrl-C
+  fn interrupt_later() -> std::sync::Arc<steer::Interrupt> {
+    let interrupt = std::sync::Arc::new(steer::Interrupt::default());
+    let trigger = interrupt.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_secs(10)).await;
+      assert!(trigger.trigger());
+    });
+    interrupt
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_steering_resends_partial_answer_and_note() {
+    let backend = ScriptedBackend::default();
+    backend.push_stalled_stream(vec![chunk("可以先用 unsafe 绕过", "", None)]);
+    backend.push_stream(vec![chunk("改用 Rc<RefCell<T>>。", "", Some("stop"))]);
+    let interrupt = interrupt_later();
+    let ask = || Some("不要用 unsafe\n".to_string());
+    let settings = TurnSettings {
+      steer: Some(Steer {
+        interrupt: &interrupt,
+        ask: &ask,
+      }),
+      ..settings()
+    };
+    let mut history = user("两个结构体怎么共享可变数据？");
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    let end = run_turn(&backend, &settings, &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    assert_eq!(end, TurnEnd::Done);
+    // 第二个请求：原问题、作为 assistant 的部分回答、作为 user 的纠偏说明
+    let requests = backend.requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(
+      contents(&requests[1].1)[1..],
+      [
+        "两个结构体怎么共享可变数据？",
+        "可以先用 unsafe 绕过",
+        "不要用 unsafe"
+      ]
+    );
+    assert_eq!(roles(&requests[1].1[1..]), ["user", "assistant", "user"]);
+    // 部分回答只留在屏幕上，说明并入本轮的问题
+    assert_eq!(
+      contents(&history),
+      [
+        "两个结构体怎么共享可变数据？\n\n[补充] 不要用 unsafe",
+        "改用 Rc<RefCell<T>>。"
+      ]
+    );
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("可以先用 unsafe 绕过\n[已中断]"));
+    assert_eq!(stats.requests, 2);
+    assert!(!interrupt.take());
+  }
+
+  #[tokio::test]
+  async fn test_turn_timeout_pauses_while_asking_for_a_note() {
+    let backend = ScriptedBackend::default();
+    backend.push_stalled_stream(vec![chunk("部分回答", "", None)]);
+    backend.push_stream(vec![chunk("按说明重答。", "", Some("stop"))]);
+    let interrupt = std::sync::Arc::new(steer::Interrupt::default());
+    let trigger = interrupt.clone();
+    tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(50)).await;
+      assert!(trigger.trigger());
+    });
+    // 输入说明用的时间超过了整轮的时限
+    let ask = || {
+      std::thread::sleep(Duration::from_millis(400));
+      Some("简短些".to_string())
+    };
+    let settings = TurnSettings {
+      timeout: Some(Duration::from_millis(300)),
+      steer: Some(Steer {
+        interrupt: &interrupt,
+        ask: &ask,
+      }),
+      ..settings()
+    };
+    let mut history = user("问题");
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(end, TurnEnd::Done);
+    assert_eq!(
+      contents(&history),
+      ["问题\n\n[补充] 简短些", "按说明重答。"]
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_interrupt_without_note_ends_turn() {
+    let backend = ScriptedBackend::default();
+    backend.push_stalled_stream(vec![chunk("部分回答", "", None)]);
+    let interrupt = interrupt_later();
+    let ask = || None;
+    let settings = TurnSettings {
+      steer: Some(Steer {
+        interrupt: &interrupt,
+        ask: &ask,
+      }),
+      ..settings()
+    };
+    let mut history = user("问题");
+    let mut stats = SessionStats::default();
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut stats,
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(end, TurnEnd::Interrupted);
+    assert_eq!(backend.request_count(), 1);
+    assert_eq!(contents(&history), ["问题", "部分回答\n[已中断]"]);
+  }
+
+  #[tokio::test]
+  async fn test_contract_checks_filter_output_and_history() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![
+      chunk("Certainly! ", "", None),
+      chunk("Use 🚀 a Mutex", "", None),
+      chunk(".", "", Some("stop")),
+    ]);
+    let contract = Contract::new(vec!["No emoji, no 'Certainly!'".to_string()]);
+    let prompt = PROMPT.clone().with_contract(&contract);
+    let settings = TurnSettings {
+      system_prompt: &prompt,
+      checks: contract.checks(),
+      ..settings()
+    };
+    let mut history = user("问题");
+    let mut out = Vec::new();
+    run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(contents(&history), ["问题", "Use a Mutex."]);
+    assert!(String::from_utf8(out).unwrap().contains("Use a Mutex."));
+    // 约定作为单独的 system 消息紧跟 system prompt
+    let (_, request) = backend.requests.lock().unwrap()[0].clone();
+    assert_eq!(roles(&request), ["system", "system", "user"]);
+  }
+
+  /// 一行 SSE 函数调用片段
+  fn sse_tool_call(id: Option<&str>, name: Option<&str>, arguments: &str) -> String {
+    let mut function = serde_json::json!({ "arguments": arguments });
+    if let Some(name) = name {
+      function["name"] = name.into();
+    }
+    let mut call = serde_json::json!({ "index": 0, "function": function });
+    if let Some(id) = id {
+      call["id"] = id.into();
+      call["type"] = "function".into();
+    }
+    format!(
+      "data: {}\n\n",
+      serde_json::json!({"choices": [{"delta": {"tool_calls": [call]}}]})
+    )
+  }
+
+  fn sse_finish(reason: &str) -> String {
+    format!(
+      "data: {}\n\n",
+      serde_json::json!({"choices": [{"delta": {}, "finish_reason": reason}]})
+    )
+  }
+
+  #[tokio::test]
+  async fn test_plugin_tool_call_round_trip() {
+    let dir = crate::checkpoint::temp_dir("turn-plugin");
+    let config = crate::plugin::tests::config(&dir, &[(&crate::plugin::tests::example(), "")]);
+    let (plugins, errors) = Plugins::load(Some(&config)).await.unwrap();
+    assert!(errors.is_empty(), "{:?}", errors);
+
+    let backend = ScriptedBackend::default();
+    let at = |s: String| (Duration::ZERO, s);
+    // 参数分两段到达
+    backend.push_transport(
+      vec![
+        at(sse_tool_call(
+          Some("call_1"),
+          Some("lookup_ticket"),
+          "{\"id\": ",
+        )),
+        at(sse_tool_call(None, None, "\"OPS-42\"}")),
+        at(sse_finish("tool_calls")),
+      ],
+      None,
+    );
+    backend.push_transport(
+      vec![
+        at(crate::mock::sse_content("OPS-42 仍未解决。")),
+        at(sse_finish("stop")),
+      ],
+      None,
+    );
+    let settings = TurnSettings {
+      tools: Some(&plugins),
+      ..settings()
+    };
+    let mut history = user("OPS-42 现在什么状态？");
+    let mut out = Vec::new();
+    run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+      contents(&history),
+      ["OPS-42 现在什么状态？", "OPS-42 仍未解决。"]
+    );
+    let out = String::from_utf8(out).unwrap();
+    assert!(
+      out.contains(r#"[工具] lookup_ticket({"id": "OPS-42"})"#),
+      "{}",
+      out
+    );
+    let requests = backend.requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    let (_, second) = &requests[1];
+    assert_eq!(roles(second), ["system", "user", "assistant", "tool"]);
+    let second = serde_json::to_value(second).unwrap();
+    assert_eq!(second[2]["tool_calls"][0]["id"], "call_1");
+    assert_eq!(
+      second[2]["tool_calls"][0]["function"]["name"],
+      "lookup_ticket"
+    );
+    assert_eq!(second[3]["tool_call_id"], "call_1");
+    let result: serde_json::Value =
+      serde_json::from_str(second[3]["content"].as_str().unwrap()).unwrap();
+    assert_eq!(result["id"], "OPS-42");
+    assert_eq!(result["status"], "open");
+  }
+
+  #[tokio::test]
+  async fn test_tool_calls_stop_after_round_limit() {
+    let dir = crate::checkpoint::temp_dir("turn-plugin-limit");
+    let config = crate::plugin::tests::config(&dir, &[(&crate::plugin::tests::example(), "")]);
+    let (plugins, _) = Plugins::load(Some(&config)).await.unwrap();
+    let backend = ScriptedBackend::default();
+    for _ in 0..=MAX_TOOL_ROUNDS {
+      backend.push_stream(vec![StreamChunk {
+        tool_calls: vec![crate::api::ToolCallDelta {
+          index: 0,
+          id: Some("call".to_string()),
+          name: Some("lookup_ticket".to_string()),
+          arguments: r#"{"id": "OPS-1"}"#.to_string(),
+        }],
+        finish_reason: Some("tool_calls".to_string()),
+        ..Default::default()
+      }]);
+    }
+    let settings = TurnSettings {
+      tools: Some(&plugins),
+      ..settings()
+    };
+    let mut history = user("问题");
+    run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(backend.request_count(), MAX_TOOL_ROUNDS + 1);
+    assert_eq!(roles(&history), ["user", "assistant"]);
+  }
+
+  #[tokio::test]
+  async fn test_reported_usage_covers_the_whole_exchange() {
+    let usage = |prompt, completion| StreamChunk {
+      usage: Some(Usage {
+        prompt_tokens: prompt,
+        completion_tokens: completion,
+        total_tokens: prompt + completion,
+      }),
+      ..Default::default()
+    };
+    let backend = ScriptedBackend::default();
+    // 被截断后自动续写一次：两个请求的用量都算在这一轮
+    backend.push_stream(vec![chunk("前半", "", Some("length")), usage(100, 50)]);
+    backend.push_stream(vec![chunk("后半", "", Some("stop")), usage(160, 20)]);
+    backend.push_stream(vec![chunk("好", "", Some("stop"))]);
+    let mut stats = SessionStats::default();
+    let mut history = user("问题");
+    run_turn(
+      &backend,
+      &settings(),
+      &mut history,
+      &mut stats,
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(backend.request_count(), 2);
+    let exchange = Usage {
+      prompt_tokens: 260,
+      completion_tokens: 70,
+      total_tokens: 330,
+    };
+    assert_eq!(stats.last_exchange, Some(exchange));
+    assert_eq!(stats.reported, exchange);
+
+    // 没有报告用量的一轮：上一轮为空，会话累计不变
+    history.extend(user("再问一个"));
+    run_turn(
+      &backend,
+      &settings(),
+      &mut history,
+      &mut stats,
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(stats.last_exchange, None);
+    assert_eq!(stats.reported, exchange);
+  }
+
+  /// 已经完成一轮、正在问第二个问题的历史
+  fn second_question() -> Vec<Message> {
+    let mut history = user("第一个问题");
+    history.push(Message::Simple {
+      role: "assistant".to_string(),
+      content: "第一个回答".to_string(),
+      name: None,
+    });
+    history.extend(user("写一篇长文"));
+    history
+  }
+
+  #[tokio::test]
+  async fn test_failure_after_auto_continue_rolls_back_turn() {
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![chunk("第一部分，", "先列提纲", Some("length"))]);
+    // 续写请求失败（没有更多的响应）
+    let reasoning = RefCell::new(Reasoning::default());
+    let settings = TurnSettings {
+      reasoning: Some(&reasoning),
+      ..settings()
+    };
+    let mut history = second_question();
+    let mut stats = SessionStats::default();
+    let mut out = Vec::new();
+    let end = run_turn(&backend, &settings, &mut history, &mut stats, &mut out)
+      .await
+      .unwrap();
+
+    assert_eq!(end, TurnEnd::RolledBack);
+    assert_eq!(backend.request_count(), 2);
+    // 部分回答、续写提示和问题都不留在历史中，推理过程也不记录
+    assert_eq!(contents(&history), ["第一个问题", "第一个回答"]);
+    assert_eq!(reasoning.borrow().for_turn(2), None);
+    assert!(String::from_utf8(out).unwrap().contains("[API错误]"));
+
+    // 重新发送时请求中没有上次的残留
+    backend.push_stream(vec![chunk("完整的长文。", "", Some("stop"))]);
+    history.extend(user("写一篇长文"));
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut stats,
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(end, TurnEnd::Done);
+    let requests = backend.requests.lock().unwrap();
+    let (_, retried) = requests.last().unwrap();
+    assert_eq!(
+      contents(retried),
+      [
+        PROMPT.stable.as_str(),
+        "第一个问题",
+        "第一个回答",
+        "写一篇长文"
+      ]
+    );
+    assert_eq!(
+      contents(&history),
+      ["第一个问题", "第一个回答", "写一篇长文", "完整的长文。"]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_failure_inside_tool_loop_rolls_back_turn() {
+    let dir = crate::checkpoint::temp_dir("turn-plugin-rollback");
+    let config = crate::plugin::tests::config(&dir, &[(&crate::plugin::tests::example(), "")]);
+    let (plugins, _) = Plugins::load(Some(&config)).await.unwrap();
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![StreamChunk {
+      tool_calls: vec![crate::api::ToolCallDelta {
+        index: 0,
+        id: Some("call_1".to_string()),
+        name: Some("lookup_ticket".to_string()),
+        arguments: r#"{"id": "OPS-1"}"#.to_string(),
+      }],
+      finish_reason: Some("tool_calls".to_string()),
+      ..Default::default()
+    }]);
+    // 带着工具结果的第二次请求失败
+    let settings = TurnSettings {
+      tools: Some(&plugins),
+      ..settings()
+    };
+    let mut history = second_question();
+    let mut out = Vec::new();
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(end, TurnEnd::RolledBack);
+    assert_eq!(backend.request_count(), 2);
+    assert!(
+      String::from_utf8(out)
+        .unwrap()
+        .contains("[工具] lookup_ticket")
+    );
+    assert_eq!(contents(&history), ["第一个问题", "第一个回答"]);
+  }
+
+  #[tokio::test]
+  async fn test_empty_stream_error_rolls_back_turn() {
+    let backend = ScriptedBackend::default();
+    // 连接建立后、任何内容之前就断开（不是空闲断开）
+    backend.push_transport(
+      vec![(Duration::ZERO, "data: {not json\n\n".to_string())],
+      None,
+    );
+    let mut history = second_question();
+    let end = run_turn(
+      &backend,
+      &settings(),
+      &mut history,
+      &mut SessionStats::default(),
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(end, TurnEnd::RolledBack);
+    assert_eq!(contents(&history), ["第一个问题", "第一个回答"]);
+  }
+
+  #[tokio::test]
+  async fn test_content_filter_is_shown_and_rolled_back() {
+    // OpenAI 风格：回答到一半时 finish_reason 为 content_filter，停在逗号上也不续写
+    let backend = ScriptedBackend::default();
+    backend.push_stream(vec![
+      chunk("这篇长文的开头，", "", None),
+      chunk("", "", Some(CONTENT_FILTER)),
+    ]);
+    let mut history = second_question();
+    let mut out = Vec::new();
+    let end = run_turn(
+      &backend,
+      &settings(),
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+    assert_eq!(end, TurnEnd::Filtered);
+    assert_eq!(backend.request_count(), 1);
+    assert_eq!(contents(&history), ["第一个问题", "第一个回答"]);
+    assert!(String::from_utf8(out).unwrap().contains("[内容过滤]"));
+  }
+
+  #[tokio::test]
+  async fn test_dashscope_refusal_can_be_kept() {
+    // DashScope：流中的错误事件
+    let backend = ScriptedBackend::default();
+    let refusal = r#"data: {"error":{"code":"data_inspection_failed","message":"Output data may contain inappropriate content."}}"#;
+    backend.push_transport(
+      vec![
+        (Duration::ZERO, crate::mock::sse_content("这篇长文的开头，")),
+        (Duration::ZERO, format!("{}\n\n", refusal)),
+      ],
+      None,
+    );
+    let settings = TurnSettings {
+      keep_refusals: true,
+      ..settings()
+    };
+    let mut history = second_question();
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut Vec::new(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(end, TurnEnd::Filtered);
+    assert_eq!(backend.request_count(), 1);
+    assert_eq!(
+      contents(&history),
+      [
+        "第一个问题",
+        "第一个回答",
+        "写一篇长文",
+        "这篇长文的开头，\n[内容审核拦截]"
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn test_stop_pattern_ends_turn_without_continuing() {
+    let stop = StopPatterns::new(&["^#+ References".to_string()]).unwrap();
+    let backend = ScriptedBackend::default();
+    // 截断的回答本来会自动续写
+    backend.push_stream(vec![
+      chunk("借用规则很简单。\n\n## Refer", "", None),
+      chunk("ences\n1. 某本书", "", Some("length")),
+    ]);
+    let settings = TurnSettings {
+      stop: Some(&stop),
+      ..settings()
+    };
+    let mut history = user("讲讲借用");
+    let mut out = Vec::new();
+    let end = run_turn(
+      &backend,
+      &settings,
+      &mut history,
+      &mut SessionStats::default(),
+      &mut out,
+    )
+    .await
+    .unwrap();
+    assert_eq!(end, TurnEnd::Stopped);
+    assert_eq!(backend.request_count(), 1);
+    assert_eq!(contents(&history)[1], "借用规则很简单。");
+    let shown = String::from_utf8(out).unwrap();
+    assert!(!shown.contains("Refer"), "{}", shown);
+
+    // 单次查询同样在匹配处停止
+    use crate::mock::{scripted_transport, sse_content};
+    let events = vec![
+      (Duration::ZERO, sse_content("答案\n#")),
+      (Duration::ZERO, sse_content("# References\n")),
+      (Duration::ZERO, sse_content("更多")),
+    ];
+    let mut out = Vec::new();
+    let (text, _, _) = print_stream(
+      crate::api::decode_sse(scripted_transport(events), None, None),
+      &mut out,
+      false,
+      Some(&stop),
+      None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(text, "答案\n");
+    assert_eq!(String::from_utf8(out).unwrap(), "答案\n");
+  }
+
+  #[tokio::test]
+  async fn test_print_stream_reports_mid_stream_errors() {
+    use crate::api::decode_sse;
+    use crate::mock::{scripted_transport, sse_content};
+    let stop =
+      "data: {\"choices\":[{\"delta\":{\"content\":\"。\"},\"finish_reason\":\"stop\"}]}\n\n";
+    let usage = "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":5,\"total_tokens\":17}}\n\n";
+    let events = vec![
+      (Duration::ZERO, sse_content("答案是 42")),
+      (Duration::ZERO, stop.to_string()),
+      (Duration::ZERO, usage.to_string()),
+    ];
+    let mut out = Vec::new();
+    let (text, reason, usage) = print_stream(
+      decode_sse(scripted_transport(events), None, None),
+      &mut out,
+      false,
+      None,
+      None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(text, "答案是 42。");
+    assert_eq!(reason.as_deref(), Some("stop"));
+    assert_eq!(usage.map(|u| u.total_tokens), Some(17));
+    assert_eq!(String::from_utf8(out).unwrap(), "答案是 42。\n");
+
+    // 回答中途断开：已输出的部分保留，结果是错误
+    let events = vec![(Duration::ZERO, sse_content("答案是"))];
+    let mut out = Vec::new();
+    let err = print_stream(
+      decode_sse(scripted_transport(events), None, None),
+      &mut out,
+      false,
+      None,
+      None,
+    )
+    .await
+    .unwrap_err();
+    assert!(err.to_string().contains("before the answer finished"));
+    assert_eq!(String::from_utf8(out).unwrap(), "答案是\n");
+  }
+}
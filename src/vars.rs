@@ -0,0 +1,234 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+/// `{{name}}` 形式的引用，名字两侧允许空白
+static REFERENCE: LazyLock<Regex> =
+  LazyLock::new(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_-]*)\s*\}\}").unwrap());
+
+/// 与 REFERENCE 中的名字规则一致
+pub fn valid_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars
+    .next()
+    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// 替换 `text` 中的 `{{name}}`。只替换一层，值里的 `{{x}}` 原样保留；
+/// 有未定义的名字时不做任何替换，错误中列出全部未定义的名字
+pub fn substitute<'a>(
+  text: &str,
+  lookup: impl Fn(&str) -> Option<&'a str>,
+) -> Result<String, String> {
+  let mut unknown: Vec<&str> = vec![];
+  for caps in REFERENCE.captures_iter(text) {
+    let name = caps.get(1).unwrap().as_str();
+    if lookup(name).is_none() && !unknown.contains(&name) {
+      unknown.push(name);
+    }
+  }
+  if !unknown.is_empty() {
+    return Err(format!("未定义的变量: {}", unknown.join(", ")));
+  }
+  Ok(
+    REFERENCE
+      .replace_all(text, |caps: &regex::Captures| {
+        lookup(&caps[1]).unwrap_or_default().to_string()
+      })
+      .into_owned(),
+  )
+}
+
+/// 会话中定义的变量
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Vars {
+  values: BTreeMap<String, String>,
+}
+
+/// 从会话文件中恢复
+impl From<BTreeMap<String, String>> for Vars {
+  fn from(values: BTreeMap<String, String>) -> Self {
+    Self { values }
+  }
+}
+
+impl Vars {
+  /// 保存到会话文件中的内容
+  pub fn values(&self) -> &BTreeMap<String, String> {
+    &self.values
+  }
+
+  pub fn set(&mut self, name: &str, value: String) {
+    self.values.insert(name.to_string(), value);
+  }
+
+  pub fn remove(&mut self, name: &str) -> bool {
+    self.values.remove(name).is_some()
+  }
+
+  pub fn clear(&mut self) {
+    self.values.clear();
+  }
+
+  pub fn expand(&self, text: &str) -> Result<String, String> {
+    substitute(text, |name| self.values.get(name).map(String::as_str))
+  }
+
+  /// `\vars` 的输出：名字、字符数和开头的内容
+  pub fn render(&self) -> String {
+    self
+      .values
+      .iter()
+      .map(|(name, value)| {
+        let preview: String = value.chars().take(40).collect();
+        let preview = preview.replace('\n', " ");
+        format!(
+          "{}  {} 字符  {}{}",
+          name,
+          value.chars().count(),
+          preview,
+          if value.chars().count() > 40 {
+            "…"
+          } else {
+            ""
+          }
+        )
+      })
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty()
+  }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SetVar<'a> {
+  /// 第 n 轮（默认最近一轮）的回答
+  Reply {
+    name: &'a str,
+    turn: Option<usize>,
+  },
+  Literal {
+    name: &'a str,
+    value: &'a str,
+  },
+}
+
+/// 解析 `\setvar name`、`\setvar name <n>` 和 `\setvar name = 文本`
+pub fn parse_setvar(arg: &str) -> Result<SetVar<'_>, String> {
+  let arg = arg.trim();
+  let usage = "用法: \\setvar <名字> [轮次] 或 \\setvar <名字> = <文本>".to_string();
+  let (name, rest) = match arg.find(|c: char| c.is_whitespace() || c == '=') {
+    Some(i) => (&arg[..i], arg[i..].trim_start()),
+    None => (arg, ""),
+  };
+  if name.is_empty() {
+    return Err(usage);
+  }
+  if !valid_name(name) {
+    return Err(format!(
+      "变量名 '{}' 无效：只能包含字母、数字、_ 和 -，且不能以数字开头",
+      name
+    ));
+  }
+  if let Some(value) = rest.strip_prefix('=') {
+    return Ok(SetVar::Literal {
+      name,
+      value: value.trim(),
+    });
+  }
+  match rest {
+    "" => Ok(SetVar::Reply { name, turn: None }),
+    n => match n.parse() {
+      Ok(turn) => Ok(SetVar::Reply {
+        name,
+        turn: Some(turn),
+      }),
+      Err(_) => Err(usage),
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars(pairs: &[(&str, &str)]) -> Vars {
+    let mut vars = Vars::default();
+    for (name, value) in pairs {
+      vars.set(name, value.to_string());
+    }
+    vars
+  }
+
+  #[test]
+  fn test_expand_does_not_recurse() {
+    let vars = vars(&[
+      ("schema", "CREATE TABLE t;"),
+      ("loop", "see {{schema}} and {{loop}}"),
+    ]);
+    assert_eq!(
+      vars.expand("用 {{schema}} 与 {{ schema }}").unwrap(),
+      "用 CREATE TABLE t; 与 CREATE TABLE t;"
+    );
+    // 值里的引用不再展开
+    assert_eq!(
+      vars.expand("{{loop}}").unwrap(),
+      "see {{schema}} and {{loop}}"
+    );
+    assert_eq!(
+      vars.expand("没有引用 {x} {{ 1x }}").unwrap(),
+      "没有引用 {x} {{ 1x }}"
+    );
+  }
+
+  #[test]
+  fn test_unknown_names_fail_before_substitution() {
+    let vars = vars(&[("a", "1")]);
+    assert_eq!(
+      vars.expand("{{a}} {{b}} {{c}} {{b}}").unwrap_err(),
+      "未定义的变量: b, c"
+    );
+    // 随会话保存和恢复
+    assert_eq!(Vars::from(vars.values().clone()), vars);
+  }
+
+  #[test]
+  fn test_parse_setvar() {
+    assert_eq!(
+      parse_setvar("schema"),
+      Ok(SetVar::Reply {
+        name: "schema",
+        turn: None
+      })
+    );
+    assert_eq!(
+      parse_setvar("schema 3"),
+      Ok(SetVar::Reply {
+        name: "schema",
+        turn: Some(3)
+      })
+    );
+    assert_eq!(
+      parse_setvar("greeting = hello {{name}}"),
+      Ok(SetVar::Literal {
+        name: "greeting",
+        value: "hello {{name}}"
+      })
+    );
+    assert_eq!(
+      parse_setvar("x=1"),
+      Ok(SetVar::Literal {
+        name: "x",
+        value: "1"
+      })
+    );
+    assert!(parse_setvar("").is_err());
+    assert!(parse_setvar("9lives").unwrap_err().contains("无效"));
+    assert!(parse_setvar("a}}{{b").is_err());
+    assert!(parse_setvar("schema three").is_err());
+  }
+}
@@ -0,0 +1,151 @@
+//! `--verify` 和 `\verify`：回答完成后让便宜模型复核一遍，检查前后矛盾、明显的事实问题
+//! 和不可能编译通过的代码。复核意见单独显示，不进入历史；`\keep-verification` 把最近一次的意见附在回答后面
+
+use crate::api::{ChatBackend, Message};
+use crate::export::text_of;
+use crate::history;
+use crate::stats::SessionStats;
+use crate::{estimate_messages_tokens, tokens};
+use anyhow::Result;
+use crossterm::style::Stylize;
+
+pub const HEADING: &str = "复核意见";
+/// --verify 自动复核时，回答少于这么多 tokens 就跳过
+pub const MIN_TOKENS: usize = 80;
+pub const MAX_TOKENS: u32 = 512;
+/// 默认的复核提示词；`verify_prompt` 可以替换，`{question}` 和 `{answer}` 换成本轮的问题和回答
+pub const DEFAULT_PROMPT: &str = "请复核下面这个问题的回答。只指出回答中前后矛盾的地方、明显可疑的事实和不可能编译或运行的代码，\
+每条一行，尽量简短；没有发现问题时只回答“未发现明显问题”。\n\n问题：\n{question}\n\n回答：\n{answer}";
+
+/// `verify_prompt` 的值必须包含 `{answer}`
+pub fn validate(template: &str) -> Result<(), String> {
+  match template.contains("{answer}") {
+    true => Ok(()),
+    false => Err("expected a template containing {answer}".to_string()),
+  }
+}
+
+/// 最后一轮的问题和完整回答（续写拼接在一起）
+pub fn last_exchange(history: &[Message]) -> Option<(String, String)> {
+  let starts = history::turn_starts(history);
+  let question = text_of(&history[*starts.last()?]);
+  let answer = history::turn_reply(history, starts.len())?;
+  Some((question, answer))
+}
+
+/// 回答太短时不值得自动复核
+pub fn worth_checking(answer: &str) -> bool {
+  tokens::count(answer) >= MIN_TOKENS
+}
+
+fn request(template: &str, question: &str, answer: &str) -> Vec<Message> {
+  vec![Message::Simple {
+    role: "user".to_string(),
+    content: template
+      .replace("{question}", question)
+      .replace("{answer}", answer),
+    name: None,
+  }]
+}
+
+/// 发出复核请求，用量按复核模型的价格计入统计
+pub async fn run<B: ChatBackend>(
+  backend: &B,
+  model: &str,
+  template: &str,
+  (question, answer): (&str, &str),
+  stats: &mut SessionStats,
+) -> Result<String> {
+  let messages = request(template, question, answer);
+  let prompt_tokens = estimate_messages_tokens(&messages);
+  let reply = backend
+    .complete(model, messages, None, Some(MAX_TOKENS))
+    .await?;
+  stats.record_verification(model, prompt_tokens, tokens::count(&reply));
+  Ok(reply.trim().to_string())
+}
+
+/// 显示在回答下方；`styled` 为 false（无障碍模式）时不着色
+pub fn render(review: &str, styled: bool) -> String {
+  match styled {
+    true => format!("{}\n{}", HEADING.cyan().bold(), review.cyan()),
+    false => format!("{}：\n{}", HEADING, review),
+  }
+}
+
+/// `\keep-verification`：把复核意见附在最后一条回答后面，之后的对话可以看到它
+pub fn keep(history: &mut [Message], review: &str) -> bool {
+  match history.last_mut() {
+    Some(Message::Simple { role, content, .. }) if role == "assistant" => {
+      content.push_str(&format!("\n\n[{}]\n{}", HEADING, review));
+      true
+    }
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::mock::ScriptedBackend;
+
+  fn message(role: &str, content: &str) -> Message {
+    Message::Simple {
+      role: role.to_string(),
+      content: content.to_string(),
+      name: None,
+    }
+  }
+
+  #[tokio::test]
+  async fn test_verification_is_a_separate_cheap_request() {
+    let mut history = vec![
+      message("user", "Rust 里怎么反转字符串？"),
+      message("assistant", "用 `s.reverse()`。"),
+    ];
+    let backend = ScriptedBackend::new(["  `str` 没有 reverse 方法，应为 s.chars().rev()  \n"]);
+    let mut stats = SessionStats::default();
+    let (question, answer) = last_exchange(&history).unwrap();
+    let review = run(
+      &backend,
+      "deepseek-chat",
+      DEFAULT_PROMPT,
+      (&question, &answer),
+      &mut stats,
+    )
+    .await
+    .unwrap();
+    assert_eq!(review, "`str` 没有 reverse 方法，应为 s.chars().rev()");
+
+    let requests = backend.requests.lock().unwrap();
+    assert_eq!(requests[0].0, "deepseek-chat");
+    let prompt = text_of(&requests[0].1[0]);
+    assert!(prompt.contains("问题：\nRust 里怎么反转字符串？\n\n回答：\n用 `s.reverse()`。"));
+    // 复核不进入历史，用量单独计价
+    assert_eq!(history.len(), 2);
+    let usage = stats.verification.as_ref().unwrap();
+    assert_eq!((usage.model.as_str(), usage.turns), ("deepseek-chat", 1));
+    assert_eq!(stats.requests, 0);
+    assert!(stats.render("deepseek-r1").contains("答案复核: 1 次"));
+
+    assert!(keep(&mut history, &review));
+    assert!(
+      text_of(&history[1]).ends_with("[复核意见]\n`str` 没有 reverse 方法，应为 s.chars().rev()")
+    );
+    assert_eq!(
+      render("未发现明显问题", false),
+      "复核意见：\n未发现明显问题"
+    );
+  }
+
+  #[test]
+  fn test_short_answers_and_templates() {
+    assert!(!worth_checking("好的。"));
+    assert!(worth_checking(&"这是一个很长的回答。".repeat(40)));
+    assert!(validate(DEFAULT_PROMPT).is_ok());
+    assert!(validate("检查 {question}").is_err());
+    let messages = request("Q={question} A={answer}", "1+1", "3");
+    assert_eq!(text_of(&messages[0]), "Q=1+1 A=3");
+    assert_eq!(last_exchange(&[message("system", "s")]), None);
+  }
+}
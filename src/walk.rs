@@ -0,0 +1,520 @@
+//! 附加目录时选出要发送的文件。规则按顺序排列，与 .gitignore 一样最后匹配的一条生效：
+//! 先是目录中各级 .gitignore（子目录的排在后面），再是配置文件的 `[attach] exclude`，
+//! 最后是命令行的 `--exclude` / `--include`，按出现的顺序。.gitignore 中的 `!` 与 git 一样
+//! 不能重新包含被排除目录中的文件，之后的 `--include` 可以。超过单个文件大小上限的文件跳过
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 总是跳过、不计入统计的目录
+const ALWAYS_SKIPPED: &[&str] = &[".git"];
+
+/// 命令行规则的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  Exclude,
+  Include,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+  /// 规则所在的目录，相对于附加的目录、以 `/` 分隔，根目录为空
+  base: String,
+  regex: Regex,
+  /// 以 `/` 结尾的模式只匹配目录
+  dir_only: bool,
+  /// 匹配时包含：`!` 开头的规则或 --include
+  include: bool,
+  /// 模式中不含通配符的开头目录（已拼上 base），规则只能匹配它之下的路径
+  prefix: String,
+}
+
+/// 把一行 gitignore 语法的模式编译成规则；空行和注释返回 None
+fn parse(line: &str, base: &str) -> Result<Option<Rule>, String> {
+  let line = line.trim_end();
+  if line.is_empty() || line.starts_with('#') {
+    return Ok(None);
+  }
+  let (include, pattern) = match line.strip_prefix('!') {
+    Some(rest) => (true, rest),
+    // `\#` 和 `\!` 开头的模式以这两个字符开头
+    None => match line.strip_prefix('\\') {
+      Some(rest) if rest.starts_with(['#', '!']) => (false, rest),
+      _ => (false, line),
+    },
+  };
+  let (dir_only, pattern) = match pattern.strip_suffix('/') {
+    Some(rest) => (true, rest),
+    None => (false, pattern),
+  };
+  // 中间或开头有 `/` 时相对于所在目录，否则匹配任意一级
+  let anchored = pattern.contains('/');
+  let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+  if pattern.is_empty() {
+    return Err(format!("empty pattern '{}'", line));
+  }
+  let body = glob_regex(pattern);
+  let regex = match anchored {
+    true => format!("^{}$", body),
+    false => format!("^(?:.*/)?{}$", body),
+  };
+  let regex = Regex::new(&regex).map_err(|e| format!("Invalid pattern '{}': {}", line, e))?;
+  let literal: Vec<&str> = match anchored {
+    true => pattern
+      .split('/')
+      .take_while(|c| !c.contains(['*', '?', '[', '\\']))
+      .collect(),
+    false => vec![],
+  };
+  // 整个模式都是字面路径时最后一段是匹配的文件本身
+  let literal = match literal.len() == pattern.split('/').count() {
+    true => &literal[..literal.len() - 1],
+    false => &literal[..],
+  };
+  let prefix = join(base, &literal.join("/"));
+  Ok(Some(Rule {
+    base: base.to_string(),
+    regex,
+    dir_only,
+    include,
+    prefix,
+  }))
+}
+
+/// gitignore 通配符转正则：`*` 和 `?` 不跨目录，`**` 跨任意层，`[...]` 为字符集
+fn glob_regex(pattern: &str) -> String {
+  let chars: Vec<char> = pattern.chars().collect();
+  let mut out = String::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let at_start = i == 0 || chars[i - 1] == '/';
+    match chars[i] {
+      '*' if chars.get(i + 1) == Some(&'*') && at_start => match chars.get(i + 2) {
+        // `**/` 匹配零或多层目录
+        Some('/') => {
+          out.push_str("(?:.*/)?");
+          i += 3;
+          continue;
+        }
+        // 末尾的 `**` 匹配其下的一切
+        None => {
+          out.push_str(".*");
+          i += 2;
+          continue;
+        }
+        _ => {
+          out.push_str("[^/]*");
+          i += 2;
+          continue;
+        }
+      },
+      '*' => out.push_str("[^/]*"),
+      '?' => out.push_str("[^/]"),
+      '\\' if i + 1 < chars.len() => {
+        i += 1;
+        out.push_str(&regex::escape(&chars[i].to_string()));
+      }
+      '[' => {
+        // 开头的 `]` 是字符集的一员；没有闭合时按字面处理
+        let start = i + 1;
+        let mut end = start;
+        if matches!(chars.get(end), Some('!' | '^')) {
+          end += 1;
+        }
+        if chars.get(end) == Some(&']') {
+          end += 1;
+        }
+        while end < chars.len() && chars[end] != ']' {
+          end += 1;
+        }
+        if end == chars.len() {
+          out.push_str(r"\[");
+        } else {
+          out.push('[');
+          let mut members = &chars[start..end];
+          if let ['!' | '^', rest @ ..] = members {
+            out.push('^');
+            members = rest;
+          }
+          for &c in members {
+            match c {
+              '-' => out.push('-'),
+              c => out.push_str(&regex::escape(&c.to_string())),
+            }
+          }
+          out.push(']');
+          i = end;
+        }
+      }
+      c => out.push_str(&regex::escape(&c.to_string())),
+    }
+    i += 1;
+  }
+  out
+}
+
+fn join(base: &str, name: &str) -> String {
+  match (base.is_empty(), name.is_empty()) {
+    (true, _) => name.to_string(),
+    (_, true) => base.to_string(),
+    _ => format!("{}/{}", base, name),
+  }
+}
+
+/// `b` 是 `a` 本身或在 `a` 之下
+fn is_within(b: &str, a: &str) -> bool {
+  a.is_empty() || b == a || b.strip_prefix(a).is_some_and(|rest| rest.starts_with('/'))
+}
+
+impl Rule {
+  fn matches(&self, path: &str, is_dir: bool) -> bool {
+    if self.dir_only && !is_dir {
+      return false;
+    }
+    let relative = match self.base.is_empty() {
+      true => path,
+      false => match path
+        .strip_prefix(&self.base)
+        .and_then(|p| p.strip_prefix('/'))
+      {
+        Some(relative) => relative,
+        None => return false,
+      },
+    };
+    self.regex.is_match(relative)
+  }
+
+  /// 这条规则是否可能匹配目录 `dir` 之下的路径
+  fn reaches_into(&self, dir: &str) -> bool {
+    is_within(dir, &self.prefix) || is_within(&self.prefix, dir)
+  }
+}
+
+/// 检查 `--exclude` 和 `--include` 的值
+pub fn validate(pattern: &str) -> Result<String, String> {
+  match parse(pattern, "")? {
+    Some(_) => Ok(pattern.to_string()),
+    None => Err(format!("empty pattern '{}'", pattern)),
+  }
+}
+
+/// 配置文件和命令行给出的规则，排在目录中的 .gitignore 之后
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+  user: Vec<Rule>,
+}
+
+impl Rules {
+  /// `excludes` 为配置文件的 `[attach] exclude`，`cli` 为命令行中按顺序出现的规则
+  pub fn new(excludes: &[String], cli: &[(Kind, String)]) -> Result<Self, String> {
+    let mut user = vec![];
+    let config = excludes.iter().map(|p| (Kind::Exclude, p.as_str()));
+    for (kind, pattern) in config.chain(cli.iter().map(|(k, p)| (*k, p.as_str()))) {
+      if let Some(mut rule) = parse(pattern, "")? {
+        rule.include = rule.include != (kind == Kind::Include);
+        user.push(rule);
+      }
+    }
+    Ok(Self { user })
+  }
+}
+
+/// 一次目录附加的结果
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+  /// 要附加的文件，按路径排序
+  pub files: Vec<PathBuf>,
+  /// 被规则排除的文件
+  pub excluded_files: usize,
+  /// 被规则整个排除、没有进入的目录
+  pub excluded_dirs: usize,
+  /// 超过大小上限的文件
+  pub oversized: usize,
+}
+
+impl Selection {
+  /// 发送前显示的统计
+  pub fn report(&self, dir: &Path, unreadable: usize) -> String {
+    let mut skipped = vec![];
+    match (self.excluded_files, self.excluded_dirs) {
+      (0, 0) => {}
+      (files, 0) => skipped.push(format!("{} 个文件被规则排除", files)),
+      (0, dirs) => skipped.push(format!("{} 个目录被规则排除", dirs)),
+      (files, dirs) => skipped.push(format!("{} 个文件和 {} 个目录被规则排除", files, dirs)),
+    }
+    if self.oversized > 0 {
+      skipped.push(format!(
+        "{} 个文件超过大小上限（--max-file-size）",
+        self.oversized
+      ));
+    }
+    if unreadable > 0 {
+      skipped.push(format!("{} 个文件无法读取", unreadable));
+    }
+    let included = self.files.len() - unreadable;
+    match skipped.is_empty() {
+      true => format!("附加 {} 中的 {} 个文件", dir.display(), included),
+      false => format!(
+        "附加 {} 中的 {} 个文件；{}",
+        dir.display(),
+        included,
+        skipped.join("，")
+      ),
+    }
+  }
+}
+
+struct Walker<'a> {
+  root: &'a Path,
+  user: &'a [Rule],
+  /// 已经读到的 .gitignore 规则，父目录的在前
+  git: Vec<Rule>,
+  max_bytes: u64,
+  selection: Selection,
+}
+
+impl Walker<'_> {
+  /// 最后一条匹配 `path` 本身或其任一上级目录的规则，返回它的位置和是否包含
+  fn decide(&self, path: &str, is_dir: bool) -> Option<(usize, bool)> {
+    let ancestors: Vec<&str> = path.match_indices('/').map(|(i, _)| &path[..i]).collect();
+    self
+      .git
+      .iter()
+      .chain(self.user)
+      .enumerate()
+      .filter(|(_, rule)| {
+        rule.matches(path, is_dir) || ancestors.iter().any(|dir| rule.matches(dir, true))
+      })
+      .map(|(i, rule)| (i, rule.include))
+      .last()
+  }
+
+  fn excluded(&self, path: &str, is_dir: bool) -> (bool, usize) {
+    match self.decide(path, is_dir) {
+      Some((i, include)) => (!include, i),
+      None => (false, 0),
+    }
+  }
+
+  fn walk(&mut self, relative: &str) -> Result<()> {
+    let dir = self.root.join(relative);
+    let gitignore = dir.join(".gitignore");
+    if gitignore.is_file() {
+      let text = crate::sandbox::read_to_string(&gitignore)?;
+      for line in text.lines() {
+        // 无法解析的行与 git 一样忽略
+        if let Ok(Some(rule)) = parse(line, relative) {
+          self.git.push(rule);
+        }
+      }
+    }
+    let mut entries = fs::read_dir(&dir)
+      .context(format!("Failed to read {:?}", dir))?
+      .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+      let name = entry.file_name().to_string_lossy().into_owned();
+      let path = join(relative, &name);
+      // 不进入符号链接指向的目录
+      let file_type = entry.file_type()?;
+      let is_dir = file_type.is_dir();
+      if is_dir && ALWAYS_SKIPPED.contains(&name.as_str()) {
+        continue;
+      }
+      let (excluded, by) = self.excluded(&path, is_dir);
+      if is_dir {
+        // 与 git 相同，.gitignore 中的 `!` 不能重新包含被排除目录中的文件；
+        // 之后的 --include 可能包含其中的文件时仍然进入
+        let rescued = self
+          .user
+          .iter()
+          .skip((by + 1).saturating_sub(self.git.len()))
+          .any(|rule| rule.include && rule.reaches_into(&path));
+        if excluded && !rescued {
+          self.selection.excluded_dirs += 1;
+        } else {
+          self.walk(&path)?;
+        }
+        continue;
+      }
+      let Ok(metadata) = fs::metadata(entry.path()) else {
+        continue;
+      };
+      if !metadata.is_file() {
+        continue;
+      }
+      if excluded {
+        self.selection.excluded_files += 1;
+      } else if metadata.len() > self.max_bytes {
+        self.selection.oversized += 1;
+      } else {
+        self.selection.files.push(entry.path());
+      }
+    }
+    Ok(())
+  }
+}
+
+/// 列出目录 `root` 中要附加的文件
+pub fn select(root: &Path, rules: &Rules, max_bytes: u64) -> Result<Selection> {
+  if let Some(restriction) = crate::sandbox::current() {
+    restriction.resolve(root)?;
+  }
+  let mut walker = Walker {
+    root,
+    user: &rules.user,
+    git: vec![],
+    max_bytes,
+    selection: Selection::default(),
+  };
+  walker.walk("")?;
+  Ok(walker.selection)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+
+  fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = temp_dir(name);
+    for (path, content) in files {
+      let path = dir.join(path);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, content).unwrap();
+    }
+    dir
+  }
+
+  fn names(dir: &Path, selection: &Selection) -> Vec<String> {
+    selection
+      .files
+      .iter()
+      .map(|p| {
+        p.strip_prefix(dir)
+          .unwrap()
+          .to_string_lossy()
+          .replace('\\', "/")
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_glob_syntax() {
+    let cases = [
+      ("*.lock", "Cargo.lock", true),
+      ("*.lock", "a/b/Cargo.lock", true),
+      ("/*.lock", "a/Cargo.lock", false),
+      ("target/**", "target/debug/x", true),
+      ("target/**", "target", false),
+      ("src/**/mod.rs", "src/mod.rs", true),
+      ("src/**/mod.rs", "src/a/b/mod.rs", true),
+      ("docs/*.md", "docs/a/b.md", false),
+      ("file?.[ch]", "x/file1.c", true),
+      ("file?.[!ch]", "file1.c", false),
+      (r"\#notes", "#notes", true),
+      ("a[", "a[", true),
+      (r"\*.c", "x.c", false),
+    ];
+    for (pattern, path, expected) in cases {
+      let rule = parse(pattern, "").unwrap().unwrap();
+      assert_eq!(rule.matches(path, false), expected, "{} {}", pattern, path);
+    }
+    assert!(
+      !parse("build/", "")
+        .unwrap()
+        .unwrap()
+        .matches("build", false)
+    );
+    assert!(parse("# 注释", "").unwrap().is_none());
+    assert!(validate("").is_err());
+    assert!(validate("!").is_err());
+  }
+
+  #[test]
+  fn test_layered_rules_and_report() {
+    let dir = fixture(
+      "walk-layers",
+      &[
+        (".gitignore", "*.log\ntarget/\n!keep.log\n"),
+        ("keep.log", "k"),
+        ("debug.log", "d"),
+        ("Cargo.lock", "lock"),
+        ("src/main.rs", "fn main() {}"),
+        ("src/gen/.gitignore", "*.rs\n!api.rs\n"),
+        ("src/gen/api.rs", "api"),
+        ("src/gen/big.rs", "generated"),
+        ("target/debug/out", "bin"),
+        ("target/doc/index.html", "doc"),
+        ("node_modules/pkg/index.js", "js"),
+        ("assets/huge.txt", &"x".repeat(100)),
+        (".git/HEAD", "ref"),
+      ],
+    );
+    // 只有 .gitignore：子目录的 !api.rs 覆盖自己的 *.rs，根目录的 !keep.log 覆盖 *.log
+    let selection = select(&dir, &Rules::default(), 64).unwrap();
+    assert_eq!(
+      names(&dir, &selection),
+      [
+        ".gitignore",
+        "Cargo.lock",
+        "keep.log",
+        "node_modules/pkg/index.js",
+        "src/gen/.gitignore",
+        "src/gen/api.rs",
+        "src/main.rs",
+      ]
+    );
+    assert_eq!(
+      (
+        selection.excluded_files,
+        selection.excluded_dirs,
+        selection.oversized
+      ),
+      (2, 1, 1)
+    );
+
+    // 配置排除之后，命令行的 --include 重新包含被 .gitignore 排除的目录中的文件
+    let rules = Rules::new(
+      &["*.lock".to_string(), "node_modules/**".to_string()],
+      &[
+        (Kind::Exclude, ".gitignore".to_string()),
+        (Kind::Include, "target/doc/**".to_string()),
+        (Kind::Exclude, "keep.log".to_string()),
+      ],
+    )
+    .unwrap();
+    let selection = select(&dir, &rules, 1024).unwrap();
+    assert_eq!(
+      names(&dir, &selection),
+      [
+        "assets/huge.txt",
+        "src/gen/api.rs",
+        "src/main.rs",
+        "target/doc/index.html",
+      ]
+    );
+    assert_eq!(
+      selection.report(&dir, 1),
+      format!(
+        "附加 {} 中的 3 个文件；6 个文件和 2 个目录被规则排除，1 个文件无法读取",
+        dir.display()
+      )
+    );
+    // 最后匹配的规则生效：之后的 --exclude 又排除了刚包含的文件
+    let rules = Rules::new(
+      &[],
+      &[
+        (Kind::Include, "target/**".to_string()),
+        (Kind::Exclude, "*.html".to_string()),
+      ],
+    )
+    .unwrap();
+    let selection = select(&dir, &rules, 64).unwrap();
+    assert!(names(&dir, &selection).contains(&"target/debug/out".to_string()));
+    assert!(!names(&dir, &selection).contains(&"target/doc/index.html".to_string()));
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -0,0 +1,246 @@
+//! `warm_start`：deepseek-reasoner 的第一个请求首字延迟很长。交互模式启动后在后台发一个
+//! 只要 1 个 token 的请求预热服务方，回答丢弃、不进入历史，用量计入统计。
+//! 第一轮真正的请求开始时预热还没完成就取消它；第一轮的首字延迟按是否预热分别记录，用于比较效果
+
+use crate::api::{ChatBackend, Message};
+use crate::stats::{SessionStats, format_duration};
+use crate::{estimate_messages_tokens, models, tokens};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// 只有这个模型需要预热
+pub const REASONER: &str = "deepseek-reasoner";
+/// 数据目录中的首轮延迟记录
+pub const LOG_FILE: &str = "warm_start.json";
+/// 每种情况最多保留这么多次记录
+pub const KEEP_SAMPLES: usize = 20;
+const PROMPT: &str = "hi";
+
+/// `model`（可以是别名）是否需要预热
+pub fn applies(model: &str) -> bool {
+  models::registry()
+    .lookup(model)
+    .is_some_and(|m| m.name == REASONER)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+  /// 第一轮开始前预热已经完成
+  Warmed,
+  /// 第一轮开始时预热还没完成，已取消
+  Cancelled,
+  Failed,
+}
+
+fn prompt() -> Vec<Message> {
+  vec![Message::Simple {
+    role: "user".to_string(),
+    content: PROMPT.to_string(),
+    name: None,
+  }]
+}
+
+#[derive(Default)]
+pub struct Warmup {
+  handle: Option<JoinHandle<Result<String>>>,
+  outcome: Option<Outcome>,
+}
+
+impl Warmup {
+  /// 在后台发出预热请求；已经发过时什么也不做
+  pub fn start<B>(&mut self, backend: &Arc<B>, model: &str)
+  where
+    B: ChatBackend + Send + Sync + 'static,
+  {
+    if self.handle.is_some() || self.outcome.is_some() {
+      return;
+    }
+    let backend = Arc::clone(backend);
+    let model = model.to_string();
+    self.handle = Some(tokio::spawn(async move {
+      backend.complete(&model, prompt(), None, Some(1)).await
+    }));
+  }
+
+  /// 同 start，隔离模式下提示后不预热
+  pub fn start_unless_isolated<B>(
+    &mut self,
+    backend: &Arc<B>,
+    model: &str,
+    isolation: &crate::isolation::Isolation,
+  ) where
+    B: ChatBackend + Send + Sync + 'static,
+  {
+    match isolation.check(crate::isolation::Feature::WarmStart) {
+      Ok(()) => self.start(backend, model),
+      Err(e) => eprintln!("[隔离] {}", e),
+    }
+  }
+
+  /// 真正的请求发出前调用：已完成的预热计入用量，还没完成的取消。返回第一轮时预热的结果
+  pub async fn settle(&mut self, stats: &mut SessionStats) -> Option<Outcome> {
+    let Some(handle) = self.handle.take() else {
+      return self.outcome;
+    };
+    let prompt_tokens = estimate_messages_tokens(&prompt());
+    let outcome = match handle.is_finished() {
+      true => match handle.await {
+        Ok(Ok(reply)) => {
+          stats.record_warmup(prompt_tokens, tokens::count(&reply));
+          Outcome::Warmed
+        }
+        _ => Outcome::Failed,
+      },
+      false => {
+        handle.abort();
+        // 请求可能已经到达服务方，输入按已计费处理
+        stats.record_warmup(prompt_tokens, 0);
+        Outcome::Cancelled
+      }
+    };
+    self.outcome = Some(outcome);
+    self.outcome
+  }
+
+  pub fn cancel(&mut self) {
+    if let Some(handle) = self.handle.take() {
+      handle.abort();
+    }
+  }
+}
+
+impl Drop for Warmup {
+  fn drop(&mut self) {
+    self.cancel();
+  }
+}
+
+/// 各次会话第一轮的首字延迟（毫秒），按是否已预热分开
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Log {
+  #[serde(default)]
+  warm: Vec<u64>,
+  #[serde(default)]
+  cold: Vec<u64>,
+}
+
+impl Log {
+  /// 文件不存在或无法解析时从空记录开始
+  pub fn load(path: &Path) -> Self {
+    std::fs::read(path)
+      .ok()
+      .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+      .unwrap_or_default()
+  }
+
+  pub fn save(&self, path: &Path) -> Result<()> {
+    let json = serde_json::to_vec_pretty(self).context("Failed to serialize warm start log")?;
+    crate::checkpoint::write_atomic(path, &json)
+  }
+
+  pub fn record(&mut self, warmed: bool, ttft: Duration) {
+    let samples = match warmed {
+      true => &mut self.warm,
+      false => &mut self.cold,
+    };
+    samples.push(ttft.as_millis() as u64);
+    if samples.len() > KEEP_SAMPLES {
+      samples.remove(0);
+    }
+  }
+
+  /// \stats 中的一行；还没有记录时返回 None
+  pub fn render(&self) -> Option<String> {
+    let average = |samples: &[u64]| match samples.len() {
+      0 => "-".to_string(),
+      n => format!(
+        "{}（{} 次）",
+        format_duration(Duration::from_millis(
+          samples.iter().sum::<u64>() / n as u64
+        )),
+        n
+      ),
+    };
+    (!self.warm.is_empty() || !self.cold.is_empty()).then(|| {
+      format!(
+        "首轮首字延迟: 预热 {} / 未预热 {}",
+        average(&self.warm),
+        average(&self.cold)
+      )
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::checkpoint::temp_dir;
+  use crate::mock::ScriptedBackend;
+
+  #[tokio::test]
+  async fn test_finished_warmup_is_counted_and_late_one_cancelled() {
+    assert!(applies("r1"));
+    assert!(!applies("deepseek-chat"));
+
+    let backend = Arc::new(ScriptedBackend::new(["好"]));
+    let mut warmup = Warmup::default();
+    warmup.start(&backend, "deepseek-reasoner");
+    while !warmup.handle.as_ref().unwrap().is_finished() {
+      tokio::task::yield_now().await;
+    }
+    let mut stats = SessionStats::default();
+    assert_eq!(warmup.settle(&mut stats).await, Some(Outcome::Warmed));
+    assert_eq!((stats.warmups, stats.requests), (1, 0));
+    assert_eq!(*backend.max_tokens.lock().unwrap(), vec![Some(1)]);
+    // 之后的轮次沿用第一轮的结果，也不会再预热
+    warmup.start(&backend, "deepseek-reasoner");
+    assert_eq!(warmup.settle(&mut stats).await, Some(Outcome::Warmed));
+    assert_eq!((stats.warmups, backend.request_count()), (1, 1));
+
+    // 单线程运行时中任务还没有机会运行，真正的请求先开始
+    let mut warmup = Warmup::default();
+    warmup.start(&backend, "deepseek-reasoner");
+    assert_eq!(warmup.settle(&mut stats).await, Some(Outcome::Cancelled));
+    assert_eq!(backend.request_count(), 1);
+    assert_eq!(Warmup::default().settle(&mut stats).await, None);
+
+    // 隔离模式下不发出预热请求
+    let backend = Arc::new(ScriptedBackend::new(["好"]));
+    let mut warmup = Warmup::default();
+    let isolated = crate::isolation::Isolation::new(true);
+    warmup.start_unless_isolated(&backend, "deepseek-reasoner", &isolated);
+    assert_eq!(warmup.settle(&mut stats).await, None);
+    assert_eq!(backend.request_count(), 0);
+  }
+
+  #[test]
+  fn test_log_keeps_recent_samples_and_persists() {
+    let dir = temp_dir("warm-log");
+    let path = dir.join(LOG_FILE);
+    let mut log = Log::load(&path);
+    assert_eq!(log.render(), None);
+    log.record(false, Duration::from_millis(4000));
+    log.record(false, Duration::from_millis(3000));
+    log.record(true, Duration::from_millis(800));
+    log.save(&path).unwrap();
+    let log = Log::load(&path);
+    assert_eq!(
+      log.render().as_deref(),
+      Some("首轮首字延迟: 预热 0.8s（1 次） / 未预热 3.5s（2 次）")
+    );
+
+    let mut log = Log::default();
+    for ms in 0..KEEP_SAMPLES as u64 + 5 {
+      log.record(true, Duration::from_millis(ms));
+    }
+    assert_eq!(log.warm.len(), KEEP_SAMPLES);
+    assert_eq!(log.warm[0], 5);
+    std::fs::write(&path, "not json").unwrap();
+    assert_eq!(Log::load(&path), Log::default());
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
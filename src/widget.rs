@@ -0,0 +1,539 @@
+use crate::stats::format_count;
+use crate::width::{display_width, truncate};
+use std::io::Write;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// 低于这个宽度时使用紧凑形式
+pub const FULL_MIN_WIDTH: usize = 60;
+/// 低于这个宽度时整个组件不显示
+pub const COMPACT_MIN_WIDTH: usize = 12;
+
+const BAR_MAX: usize = 30;
+
+/// 无障碍模式下状态行的最短间隔
+pub const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 界面模式。无障碍模式下不使用原地刷新、颜色和 Unicode 装饰，
+/// 所有组件都按顺序输出完整的文字行，便于屏幕阅读器朗读
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UiMode {
+  #[default]
+  Standard,
+  Accessible,
+}
+
+impl UiMode {
+  /// `--accessible`、`DEEPCLI_ACCESSIBLE`（可以写在 .env 中）为真，或 `TERM=dumb` 时启用
+  pub fn detect(flag: bool, setting: Option<&str>, term: Option<&str>) -> Self {
+    let setting = setting.map(|v| v.trim().to_ascii_lowercase());
+    let enabled = match setting.as_deref() {
+      Some("1" | "true" | "yes" | "on") => true,
+      Some("0" | "false" | "no" | "off") => flag,
+      _ => flag || term == Some("dumb"),
+    };
+    match enabled {
+      true => UiMode::Accessible,
+      false => UiMode::Standard,
+    }
+  }
+
+  /// `detect` 的结果由哪一项决定
+  pub fn trigger(flag: bool, setting: Option<&str>, term: Option<&str>) -> Trigger {
+    let setting = setting.map(|v| v.trim().to_ascii_lowercase());
+    match setting.as_deref() {
+      Some("1" | "true" | "yes" | "on") => Trigger::Setting,
+      Some("0" | "false" | "no" | "off") if !flag => Trigger::Setting,
+      _ if flag => Trigger::Flag,
+      _ if term == Some("dumb") => Trigger::Term,
+      _ => Trigger::Default,
+    }
+  }
+
+  pub fn is_accessible(self) -> bool {
+    self == UiMode::Accessible
+  }
+}
+
+/// 界面模式由哪一项决定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+  Flag,
+  Setting,
+  Term,
+  Default,
+}
+
+static MODE: OnceLock<UiMode> = OnceLock::new();
+
+/// 启动时设置一次；无障碍模式同时关闭所有颜色输出
+pub fn set_mode(mode: UiMode) {
+  if MODE.set(mode).is_ok() && mode.is_accessible() {
+    crossterm::style::force_color_output(false);
+  }
+}
+
+/// 当前的界面模式，没有设置时为标准模式
+pub fn mode() -> UiMode {
+  MODE.get().copied().unwrap_or_default()
+}
+
+/// 每次渲染时重新查询终端宽度，不订阅 resize 事件；查询失败时按 80 列
+pub fn terminal_width() -> usize {
+  crossterm::terminal::size()
+    .map(|(w, _)| w as usize)
+    .unwrap_or(80)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+  Full,
+  Compact,
+  Hidden,
+}
+
+pub fn form_for(width: usize) -> Form {
+  match width {
+    w if w >= FULL_MIN_WIDTH => Form::Full,
+    w if w >= COMPACT_MIN_WIDTH => Form::Compact,
+    _ => Form::Hidden,
+  }
+}
+
+/// 上下文预算条：`[上下文 ███░░░ 64% 41.0K/64.0K]`，窄终端下为 `[ctx 64%]`，
+/// 无障碍模式下为一句话
+pub fn context_bar(used: usize, budget: usize, width: usize, mode: UiMode) -> Option<String> {
+  let percent = (used * 100).checked_div(budget).unwrap_or(0);
+  if mode.is_accessible() {
+    return Some(format!(
+      "上下文已使用 {}%，{} / {} tokens。",
+      percent,
+      format_count(used),
+      format_count(budget)
+    ));
+  }
+  match form_for(width) {
+    Form::Hidden => None,
+    Form::Compact => Some(format!("[ctx {}%]", percent)),
+    Form::Full => {
+      let suffix = format!(
+        " {}% {}/{}]",
+        percent,
+        format_count(used),
+        format_count(budget)
+      );
+      let prefix = "[上下文 ";
+      let room = width - 1 - display_width(prefix) - display_width(&suffix);
+      let len = room.min(BAR_MAX);
+      let filled = (used.min(budget) * len).checked_div(budget).unwrap_or(0);
+      Some(format!(
+        "{}{}{}{}",
+        prefix,
+        "█".repeat(filled),
+        "░".repeat(len - filled),
+        suffix
+      ))
+    }
+  }
+}
+
+/// 统计信息：宽终端逐行显示并截断超长的行，窄终端合成一行；无障碍模式下总是完整显示
+pub fn stats_footer(full: &str, compact: &str, width: usize, mode: UiMode) -> Option<String> {
+  if mode.is_accessible() {
+    return Some(full.to_string());
+  }
+  match form_for(width) {
+    Form::Hidden => None,
+    Form::Compact => Some(truncate(compact, width - 1)),
+    Form::Full => Some(
+      full
+        .lines()
+        .map(|l| truncate(l, width - 1))
+        .collect::<Vec<_>>()
+        .join("\n"),
+    ),
+  }
+}
+
+/// 原地刷新的单行组件（倒计时等）。重写前先用空格覆盖上一次的内容，
+/// 并保证不超过终端宽度，否则折行后 \r 只能回到最后一行。
+/// 无障碍模式下改为逐行输出，最多每 STATUS_INTERVAL 一行
+#[derive(Default)]
+pub struct InPlaceLine {
+  mode: UiMode,
+  last_width: usize,
+  last_status: Option<Instant>,
+}
+
+impl InPlaceLine {
+  pub fn new(mode: UiMode) -> Self {
+    Self {
+      mode,
+      ..Self::default()
+    }
+  }
+
+  pub fn render(&mut self, out: &mut impl Write, text: &str, width: usize) -> std::io::Result<()> {
+    self.render_at(out, text, width, Instant::now())
+  }
+
+  fn render_at(
+    &mut self,
+    out: &mut impl Write,
+    text: &str,
+    width: usize,
+    now: Instant,
+  ) -> std::io::Result<()> {
+    if self.mode.is_accessible() {
+      if self
+        .last_status
+        .is_some_and(|last| now.duration_since(last) < STATUS_INTERVAL)
+      {
+        return Ok(());
+      }
+      self.last_status = Some(now);
+      writeln!(out, "{}", text)?;
+      return out.flush();
+    }
+    let text = truncate(text, width.saturating_sub(1));
+    write!(out, "\r{}\r{}", " ".repeat(self.last_width), text)?;
+    self.last_width = display_width(&text);
+    out.flush()
+  }
+
+  /// 清除当前内容，光标回到行首
+  pub fn clear(&mut self, out: &mut impl Write) -> std::io::Result<()> {
+    if self.mode.is_accessible() {
+      return Ok(());
+    }
+    write!(out, "\r{}\r", " ".repeat(self.last_width))?;
+    self.last_width = 0;
+    out.flush()
+  }
+}
+
+/// 在 stderr 上原地倒计时 `delay`，每秒刷新一次 `label(剩余时长)`，结束后清除。
+/// stderr 不是终端时与无障碍模式一样逐行输出
+pub async fn countdown(delay: Duration, label: impl Fn(Duration) -> String) {
+  use std::io::IsTerminal;
+  let mode = match std::io::stderr().is_terminal() {
+    true => mode(),
+    false => UiMode::Accessible,
+  };
+  let line = InPlaceLine::new(mode);
+  countdown_on(&mut std::io::stderr(), line, delay, label, terminal_width()).await;
+}
+
+async fn countdown_on(
+  out: &mut impl Write,
+  mut line: InPlaceLine,
+  delay: Duration,
+  label: impl Fn(Duration) -> String,
+  width: usize,
+) {
+  let end = Instant::now() + delay;
+  loop {
+    let left = end.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+      break;
+    }
+    // 输出失败不影响等待
+    let _ = line.render(out, &label(left), width);
+    tokio::time::sleep(left.min(Duration::from_secs(1))).await;
+  }
+  let _ = line.clear(out);
+}
+
+/// Markdown 表格的一行，不是表格行时为 None
+pub fn table_cells(line: &str) -> Option<Vec<String>> {
+  let line = line.trim();
+  let inner = line.strip_prefix('|')?;
+  let inner = inner.strip_suffix('|').unwrap_or(inner);
+  Some(inner.split('|').map(|c| c.trim().to_string()).collect())
+}
+
+pub fn is_separator(cells: &[String]) -> bool {
+  cells.iter().all(|c| {
+    let c = c.trim_matches(':');
+    !c.is_empty() && c.chars().all(|ch| ch == '-')
+  })
+}
+
+/// 无障碍模式下改写流式回答的显示：按整行输出，代码块前后加上“代码开始”“代码结束”，
+/// 表格的每一行改为“列名: 值”的逐行形式。只影响显示，历史中保存原文
+#[derive(Debug, Default)]
+pub struct AccessibleStream {
+  /// 还没有换行的部分
+  line: String,
+  in_code: bool,
+  /// 已确认的表头
+  header: Option<Vec<String>>,
+  /// 可能是表头的一行，要等到下一行是分隔行才能确定
+  pending: Option<(String, Vec<String>)>,
+}
+
+impl AccessibleStream {
+  pub fn push(&mut self, chunk: &str) -> String {
+    self.line.push_str(chunk);
+    let mut out = String::new();
+    while let Some(end) = self.line.find('\n') {
+      let line: String = self.line.drain(..=end).collect();
+      out.push_str(&self.render_line(line.trim_end_matches(['\n', '\r'])));
+    }
+    out
+  }
+
+  pub fn finish(&mut self) -> String {
+    let mut out = match self.line.is_empty() {
+      true => String::new(),
+      false => {
+        let line = std::mem::take(&mut self.line);
+        self.render_line(&line)
+      }
+    };
+    out.push_str(&self.flush_pending());
+    if std::mem::take(&mut self.in_code) {
+      out.push_str("代码结束。\n");
+    }
+    out
+  }
+
+  fn flush_pending(&mut self) -> String {
+    self
+      .pending
+      .take()
+      .map(|(line, _)| format!("{}\n", line))
+      .unwrap_or_default()
+  }
+
+  fn render_line(&mut self, line: &str) -> String {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+      let mut out = self.flush_pending();
+      self.header = None;
+      if std::mem::take(&mut self.in_code) {
+        out.push_str("代码结束。\n");
+      } else {
+        self.in_code = true;
+        let lang = trimmed.trim_start_matches(['`', '~']).trim();
+        out.push_str(&match lang.is_empty() {
+          true => "代码开始：\n".to_string(),
+          false => format!("代码开始（{}）：\n", lang),
+        });
+      }
+      return out;
+    }
+    if self.in_code {
+      return format!("{}\n", line);
+    }
+    let Some(cells) = table_cells(line) else {
+      self.header = None;
+      return format!("{}{}\n", self.flush_pending(), line);
+    };
+    if let Some(header) = &self.header {
+      let mut out: String = header
+        .iter()
+        .zip(cells.iter().chain(std::iter::repeat(&String::new())))
+        .map(|(name, value)| format!("{}: {}\n", name, value))
+        .collect();
+      out.push('\n');
+      return out;
+    }
+    if is_separator(&cells)
+      && let Some((_, header)) = self.pending.take()
+    {
+      let out = format!("表格，{} 列：{}。\n", header.len(), header.join("、"));
+      self.header = Some(header);
+      return out;
+    }
+    let out = self.flush_pending();
+    self.pending = Some((line.to_string(), cells));
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WIDTHS: [usize; 3] = [40, 80, 200];
+
+  #[test]
+  fn test_context_bar_forms() {
+    let forms: Vec<_> = WIDTHS
+      .iter()
+      .map(|&w| context_bar(41_000, 64_000, w, UiMode::Standard).unwrap())
+      .collect();
+    assert_eq!(forms[0], "[ctx 64%]");
+    assert!(forms[1].starts_with("[上下文 █"));
+    assert!(forms[1].ends_with(" 64% 41.0K/64.0K]"));
+    // 宽终端下条的长度有上限
+    assert_eq!(forms[2].matches(['█', '░']).count(), BAR_MAX);
+    for (form, width) in forms.iter().zip(WIDTHS) {
+      assert!(display_width(form) < width, "{} > {}", form, width);
+    }
+    assert!(context_bar(1, 100, COMPACT_MIN_WIDTH - 1, UiMode::Standard).is_none());
+    // 刚好达到完整形式的宽度时也不超出
+    let narrowest = context_bar(64_000, 64_000, FULL_MIN_WIDTH, UiMode::Standard).unwrap();
+    assert!(display_width(&narrowest) < FULL_MIN_WIDTH);
+    assert!(!narrowest.contains('░'));
+  }
+
+  #[test]
+  fn test_stats_footer_fits() {
+    let full = "轮次: 3（请求 4 次）\n等待模型: 12.0s，平均首字延迟 1.2s，为了测试而写得很长很长很长很长的一行";
+    let compact = "轮次 3 · 输入 1.2K / 输出 300 · ¥0.0100";
+    for width in WIDTHS {
+      let footer = stats_footer(full, compact, width, UiMode::Standard).unwrap();
+      assert_eq!(footer.lines().count(), if width >= 60 { 2 } else { 1 });
+      for line in footer.lines() {
+        assert!(display_width(line) < width, "{:?} at {}", line, width);
+      }
+    }
+    assert!(stats_footer(full, compact, 8, UiMode::Standard).is_none());
+    // 无障碍模式下不截断，也不因为终端窄而隐藏
+    assert_eq!(
+      stats_footer(full, compact, 8, UiMode::Accessible).as_deref(),
+      Some(full)
+    );
+  }
+
+  #[test]
+  fn test_in_place_line_clears_previous_render() {
+    let mut line = InPlaceLine::default();
+    let mut out = Vec::new();
+    line.render(&mut out, "剩余 10s", 80).unwrap();
+    line.render(&mut out, "9s", 80).unwrap();
+    let printed = String::from_utf8(out).unwrap();
+    // 第二次先覆盖上一次的 8 列
+    assert!(printed.ends_with(&format!("\r{}\r9s", " ".repeat(8))));
+
+    let mut out = Vec::new();
+    line.render(&mut out, &"x".repeat(100), 40).unwrap();
+    assert_eq!(line.last_width, 39);
+    line.clear(&mut out).unwrap();
+    assert_eq!(line.last_width, 0);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_countdown_redraws_every_second() {
+    let mut out = Vec::new();
+    let label = |left: Duration| format!("{}s 后发送", left.as_secs_f64().ceil());
+    let started = Instant::now();
+    countdown_on(
+      &mut out,
+      InPlaceLine::default(),
+      Duration::from_millis(2500),
+      label,
+      80,
+    )
+    .await;
+    assert_eq!(started.elapsed(), Duration::from_millis(2500));
+    // 每次先用空格覆盖上一次的 9 列，结束后清除
+    let blank = format!("\r{}\r", " ".repeat(9));
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      format!("\r\r3s 后发送{0}2s 后发送{0}1s 后发送{0}", blank)
+    );
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn test_accessible_countdown_prints_a_line_every_interval() {
+    let mut out = Vec::new();
+    let label = |left: Duration| format!("{}s 后发送", left.as_secs_f64().ceil());
+    countdown_on(
+      &mut out,
+      InPlaceLine::new(UiMode::Accessible),
+      Duration::from_secs(25),
+      label,
+      80,
+    )
+    .await;
+    let printed = String::from_utf8(out).unwrap();
+    assert_eq!(printed, "25s 后发送\n15s 后发送\n5s 后发送\n");
+  }
+
+  #[test]
+  fn test_detect_mode() {
+    assert_eq!(UiMode::detect(false, None, Some("xterm")), UiMode::Standard);
+    assert_eq!(UiMode::detect(true, None, None), UiMode::Accessible);
+    assert_eq!(
+      UiMode::detect(false, None, Some("dumb")),
+      UiMode::Accessible
+    );
+    assert_eq!(
+      UiMode::detect(false, Some("yes"), Some("xterm")),
+      UiMode::Accessible
+    );
+    // 显式关闭时 TERM=dumb 也不启用，命令行参数仍然优先
+    assert_eq!(
+      UiMode::detect(false, Some("0"), Some("dumb")),
+      UiMode::Standard
+    );
+    assert_eq!(UiMode::detect(true, Some("0"), None), UiMode::Accessible);
+    assert_eq!(
+      UiMode::trigger(false, Some("0"), Some("dumb")),
+      Trigger::Setting
+    );
+    assert_eq!(UiMode::trigger(true, Some("0"), None), Trigger::Flag);
+    assert_eq!(UiMode::trigger(false, None, Some("dumb")), Trigger::Term);
+    assert_eq!(
+      UiMode::trigger(false, None, Some("xterm")),
+      Trigger::Default
+    );
+  }
+
+  #[test]
+  fn test_accessible_context_bar() {
+    for width in [8, 40, 200] {
+      let bar = context_bar(41_000, 64_000, width, UiMode::Accessible).unwrap();
+      assert_eq!(bar, "上下文已使用 64%，41.0K / 64.0K tokens。");
+    }
+  }
+
+  #[test]
+  fn test_accessible_in_place_line_prints_sequential_lines() {
+    let mut line = InPlaceLine::new(UiMode::Accessible);
+    let mut out = Vec::new();
+    let start = Instant::now();
+    for secs in [0, 3, 9, 10, 15, 21] {
+      let text = format!("剩余 {}s", 30 - secs);
+      line
+        .render_at(&mut out, &text, 80, start + Duration::from_secs(secs))
+        .unwrap();
+    }
+    line.clear(&mut out).unwrap();
+    let printed = String::from_utf8(out).unwrap();
+    assert_eq!(printed, "剩余 30s\n剩余 20s\n剩余 9s\n");
+    assert!(!printed.contains('\r'));
+  }
+
+  fn accessible(chunks: &[&str]) -> String {
+    let mut stream = AccessibleStream::default();
+    let mut out: String = chunks.iter().map(|c| stream.push(c)).collect();
+    out.push_str(&stream.finish());
+    out
+  }
+
+  #[test]
+  fn test_accessible_stream_announces_code_blocks() {
+    assert_eq!(
+      accessible(&["看这里：\n```ru", "st\nlet x = 1;\n| a | b |\n``", "`\n完"]),
+      "看这里：\n代码开始（rust）：\nlet x = 1;\n| a | b |\n代码结束。\n完\n"
+    );
+    // 没有闭合的代码块在结束时补上标记
+    assert_eq!(accessible(&["```\nx"]), "代码开始：\nx\n代码结束。\n");
+  }
+
+  #[test]
+  fn test_accessible_stream_reads_tables_as_lines() {
+    let table = "| 名称 | 大小 |\n|---|:---:|\n| a.txt | 1K |\n| b.txt |\n\n后文";
+    assert_eq!(
+      accessible(&[&table[..10], &table[10..]]),
+      "表格，2 列：名称、大小。\n名称: a.txt\n大小: 1K\n\n名称: b.txt\n大小: \n\n\n后文\n"
+    );
+    // 没有分隔行的竖线行原样保留
+    assert_eq!(accessible(&["| 只是一行 |\n正文"]), "| 只是一行 |\n正文\n");
+  }
+}
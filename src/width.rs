@@ -0,0 +1,182 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+const ZWJ: char = '\u{200D}';
+const EMOJI_PRESENTATION: char = '\u{FE0F}';
+
+fn is_regional_indicator(c: char) -> bool {
+  ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+fn is_skin_tone(c: char) -> bool {
+  ('\u{1F3FB}'..='\u{1F3FF}').contains(&c)
+}
+
+/// 一个字素簇在终端中占用的列数。ZWJ 组合、旗帜、肤色修饰和 VS16 序列
+/// 按整体计为 2 列，而不是逐个码点相加
+pub fn grapheme_width(grapheme: &str) -> usize {
+  let mut chars = grapheme.chars();
+  let Some(first) = chars.next() else {
+    return 0;
+  };
+  if grapheme.contains(ZWJ)
+    || grapheme.contains(EMOJI_PRESENTATION)
+    || grapheme.chars().any(is_skin_tone)
+    || is_regional_indicator(first)
+  {
+    return 2;
+  }
+  // 组合附加符号宽度为 0；控制字符没有宽度
+  grapheme
+    .chars()
+    .map(|c| UnicodeWidthChar::width(c).unwrap_or(0))
+    .sum::<usize>()
+    .min(2)
+}
+
+/// 字符串的显示宽度，所有按列对齐、折行的地方都应使用它
+pub fn display_width(text: &str) -> usize {
+  text.graphemes(true).map(grapheme_width).sum()
+}
+
+/// 用空格右侧补齐到 `width` 列，已超过时原样返回
+pub fn pad_right(text: &str, width: usize) -> String {
+  let pad = width.saturating_sub(display_width(text));
+  format!("{}{}", text, " ".repeat(pad))
+}
+
+/// 截断到最多 `width` 列，被截掉时以 `…` 结尾
+pub fn truncate(text: &str, width: usize) -> String {
+  if display_width(text) <= width {
+    return text.to_string();
+  }
+  let mut out = String::new();
+  let mut used = 0;
+  for g in text.graphemes(true) {
+    let w = grapheme_width(g);
+    if used + w + 1 > width {
+      break;
+    }
+    out.push_str(g);
+    used += w;
+  }
+  if width > 0 {
+    out.push('…');
+  }
+  out
+}
+
+/// 按显示宽度硬折行，不拆开字素簇；单个字素比 `width` 宽时独占一行
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+  let width = width.max(1);
+  let mut lines = vec![];
+  for source in text.split('\n') {
+    let mut line = String::new();
+    let mut used = 0;
+    for g in source.graphemes(true) {
+      let w = grapheme_width(g);
+      if used + w > width && !line.is_empty() {
+        lines.push(std::mem::take(&mut line));
+        used = 0;
+      }
+      line.push_str(g);
+      used += w;
+    }
+    lines.push(line);
+  }
+  lines
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_truncate() {
+    assert_eq!(truncate("abc", 3), "abc");
+    assert_eq!(truncate("abcdef", 4), "abc…");
+    // 宽字符不会被拆开，也不会超出
+    assert_eq!(truncate("中文字符", 5), "中文…");
+    assert_eq!(truncate("中文字符", 4), "中…");
+    assert_eq!(truncate("abc", 0), "");
+  }
+
+  #[test]
+  fn test_tricky_corpus() {
+    let cases = [
+      ("abc", 3),
+      ("中文", 4),
+      ("（注）", 6),
+      ("a（b）c", 7),
+      ("🇨🇳", 2),
+      ("🇨🇳🇺🇸", 4),
+      ("👨‍👩‍👧‍👦", 2),
+      ("🏳️‍🌈", 2),
+      ("👍🏽", 2),
+      ("❤️", 2),
+      ("1️⃣", 2),
+      ("e\u{301}", 1),
+      ("über", 4),
+      ("ＡＢＣ", 6),
+      ("日本語 text", 11),
+      ("\t", 0),
+      ("", 0),
+    ];
+    for (text, expected) in cases {
+      assert_eq!(display_width(text), expected, "{:?}", text);
+    }
+  }
+
+  #[test]
+  fn test_pad_right() {
+    assert_eq!(pad_right("用例", 6), "用例  ");
+    assert_eq!(pad_right("case", 6), "case  ");
+    assert_eq!(pad_right("toolong", 3), "toolong");
+  }
+
+  #[test]
+  fn test_wrap_keeps_clusters_together() {
+    assert_eq!(wrap("中文中文", 5), ["中文", "中文"]);
+    assert_eq!(wrap("ab👨‍👩‍👧‍👦cd", 3), ["ab", "👨‍👩‍👧‍👦c", "d"]);
+    assert_eq!(wrap("a\nbc", 1), ["a", "b", "c"]);
+    // 宽度为 1 时宽字符独占一行
+    assert_eq!(wrap("中a", 1), ["中", "a"]);
+  }
+
+  #[test]
+  fn test_wrap_never_exceeds_width() {
+    let pieces = [
+      "a",
+      "b",
+      " ",
+      "中",
+      "（",
+      "）",
+      "👍🏽",
+      "🇯🇵",
+      "👨‍👩‍👧",
+      "e\u{301}",
+      "Ｚ",
+      "❤️",
+      "\u{200B}",
+    ];
+    // 固定种子的线性同余生成器，保证失败可复现
+    let mut state: u64 = 0x5eed;
+    let mut next = |n: usize| {
+      state = state
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+      (state >> 33) as usize % n
+    };
+    for _ in 0..500 {
+      let len = next(40);
+      let text: String = (0..len).map(|_| pieces[next(pieces.len())]).collect();
+      let width = 2 + next(20);
+      let lines = wrap(&text, width);
+      for line in &lines {
+        assert!(display_width(line) <= width, "{:?} at {}", line, width);
+      }
+      assert_eq!(lines.concat(), text);
+    }
+  }
+}